@@ -13,6 +13,7 @@ use floresta_rpc::jsonrpc_client::Client;
 use floresta_rpc::rpc::FlorestaRPC;
 use floresta_rpc::rpc_types::AddNodeCommand;
 use floresta_rpc::rpc_types::RescanConfidence;
+use floresta_rpc::rpc_types::SetBanCommand;
 
 // Main function that runs the CLI application
 fn main() -> anyhow::Result<()> {
@@ -82,9 +83,14 @@ fn do_request(cmd: &Cli, client: Client) -> anyhow::Result<String> {
             use_timestamp,
             confidence,
         )?)?,
+        Methods::GetRescanInfo => serde_json::to_string_pretty(&client.get_rescan_info()?)?,
+        Methods::AbortRescan => serde_json::to_string_pretty(&client.abort_rescan()?)?,
         Methods::SendRawTransaction { tx } => {
             serde_json::to_string_pretty(&client.send_raw_transaction(tx)?)?
         }
+        Methods::SubmitPackage { package } => {
+            serde_json::to_string_pretty(&client.submit_package(package)?)?
+        }
         Methods::GetBlockHeader { hash } => {
             serde_json::to_string_pretty(&client.get_block_header(hash)?)?
         }
@@ -92,6 +98,12 @@ fn do_request(cmd: &Cli, client: Client) -> anyhow::Result<String> {
             serde_json::to_string_pretty(&client.load_descriptor(desc)?)?
         }
         Methods::GetRoots => serde_json::to_string_pretty(&client.get_roots()?)?,
+        Methods::DumpUtreexoState { path } => {
+            serde_json::to_string_pretty(&client.dump_utreexo_state(path)?)?
+        }
+        Methods::LoadUtreexoState { path } => {
+            serde_json::to_string_pretty(&client.load_utreexo_state(path)?)?
+        }
         Methods::GetBlock { hash, verbosity } => {
             serde_json::to_string_pretty(&client.get_block(hash, verbosity)?)?
         }
@@ -112,6 +124,20 @@ fn do_request(cmd: &Cli, client: Client) -> anyhow::Result<String> {
             node_address,
             node_id,
         } => serde_json::to_string_pretty(&client.disconnect_node(node_address, node_id)?)?,
+        Methods::SetBan {
+            subnet,
+            command,
+            bantime,
+            absolute,
+        } => serde_json::to_string_pretty(&client.set_ban(
+            subnet,
+            command,
+            bantime,
+            absolute.unwrap_or(false),
+        )?)?,
+        Methods::ListBanned => serde_json::to_string_pretty(&client.list_banned()?)?,
+        Methods::ClearBanned => serde_json::to_string_pretty(&client.clear_banned()?)?,
+        Methods::GetNetTotals => serde_json::to_string_pretty(&client.get_net_totals()?)?,
         Methods::FindTxOut {
             txid,
             vout,
@@ -251,6 +277,24 @@ pub enum Methods {
         confidence: RescanConfidence,
     },
 
+    #[doc = include_str!("../../../doc/rpc/getrescaninfo.md")]
+    #[command(
+        name = "getrescaninfo",
+        about = "Returns the progress of every rescan the node is running, or that got interrupted.",
+        long_about = Some(include_str!("../../../doc/rpc/getrescaninfo.md")),
+        disable_help_subcommand = true
+    )]
+    GetRescanInfo,
+
+    #[doc = include_str!("../../../doc/rpc/abortrescan.md")]
+    #[command(
+        name = "abortrescan",
+        about = "Stops every rescan, including the ones that got interrupted.",
+        long_about = Some(include_str!("../../../doc/rpc/abortrescan.md")),
+        disable_help_subcommand = true
+    )]
+    AbortRescan,
+
     /// Submits a raw transaction to the network
     #[doc = include_str!("../../../doc/rpc/sendrawtransaction.md")]
     #[command(
@@ -261,6 +305,19 @@ pub enum Methods {
     )]
     SendRawTransaction { tx: String },
 
+    #[doc = include_str!("../../../doc/rpc/submitpackage.md")]
+    #[command(
+        name = "submitpackage",
+        about = "Submit a child with its unconfirmed parents to the mempool and the P2P network",
+        long_about = Some(include_str!("../../../doc/rpc/submitpackage.md")),
+        disable_help_subcommand = true
+    )]
+    SubmitPackage {
+        /// The serialized transactions, parents first and the child last
+        #[arg(required = true, value_parser = crate::parsers::parse_json_array::<String>)]
+        package: std::vec::Vec<String>,
+    },
+
     /// Returns the block header for the given block hash
     #[command(name = "getblockheader")]
     GetBlockHeader { hash: BlockHash },
@@ -279,6 +336,24 @@ pub enum Methods {
     #[command(name = "getroots")]
     GetRoots,
 
+    #[doc = include_str!("../../../doc/rpc/dumputreexostate.md")]
+    #[command(
+        name = "dumputreexostate",
+        about = "Writes our Utreexo accumulator to a snapshot file",
+        long_about = Some(include_str!("../../../doc/rpc/dumputreexostate.md")),
+        disable_help_subcommand = true
+    )]
+    DumpUtreexoState { path: String },
+
+    #[doc = include_str!("../../../doc/rpc/loadutreexostate.md")]
+    #[command(
+        name = "loadutreexostate",
+        about = "Starts from the Utreexo accumulator in a snapshot file",
+        long_about = Some(include_str!("../../../doc/rpc/loadutreexostate.md")),
+        disable_help_subcommand = true
+    )]
+    LoadUtreexoState { path: String },
+
     /// Returns a block
     #[doc = include_str!("../../../doc/rpc/getblock.md")]
     #[command(
@@ -343,6 +418,47 @@ pub enum Methods {
         node_id: Option<usize>,
     },
 
+    #[doc = include_str!("../../../doc/rpc/setban.md")]
+    #[command(
+        name = "setban",
+        about = "Attempts to add or remove an IP/Subnet from the banned list",
+        long_about = Some(include_str!("../../../doc/rpc/setban.md")),
+        disable_help_subcommand = true
+    )]
+    SetBan {
+        subnet: String,
+        command: SetBanCommand,
+        bantime: Option<u64>,
+        absolute: Option<bool>,
+    },
+
+    #[doc = include_str!("../../../doc/rpc/listbanned.md")]
+    #[command(
+        name = "listbanned",
+        about = "List all manually and automatically banned IPs/Subnets",
+        long_about = Some(include_str!("../../../doc/rpc/listbanned.md")),
+        disable_help_subcommand = true
+    )]
+    ListBanned,
+
+    #[doc = include_str!("../../../doc/rpc/clearbanned.md")]
+    #[command(
+        name = "clearbanned",
+        about = "Clear all banned IPs",
+        long_about = Some(include_str!("../../../doc/rpc/clearbanned.md")),
+        disable_help_subcommand = true
+    )]
+    ClearBanned,
+
+    #[doc = include_str!("../../../doc/rpc/getnettotals.md")]
+    #[command(
+        name = "getnettotals",
+        about = "Returns how many bytes we exchanged with our peers, and the state of our upload target",
+        long_about = Some(include_str!("../../../doc/rpc/getnettotals.md")),
+        disable_help_subcommand = true
+    )]
+    GetNetTotals,
+
     #[command(name = "findtxout")]
     FindTxOut {
         txid: Txid,
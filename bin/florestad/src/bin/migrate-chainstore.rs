@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Converts the chain data of a florestad data directory between the `flat-chainstore` and
+//! `kv-chainstore` backends, in place. florestad must not be running while we do it.
+//!
+//! Afterwards, the old chain data is kept in `chaindata.old`, and may be removed once florestad
+//! starts fine with the new backend.
+
+#![deny(missing_docs)]
+
+use std::process::exit;
+
+use clap::Parser;
+use clap::ValueEnum;
+use floresta_node::migrate_chainstore;
+use floresta_node::ChainStoreBackend;
+use tracing::Level;
+
+#[derive(Clone, Copy, ValueEnum)]
+/// The backend we should convert to
+enum Backend {
+    /// The mmap-based flat chainstore, florestad's default
+    Flat,
+
+    /// The chainstore on top of an embedded key-value database
+    Kv,
+}
+
+#[derive(Parser)]
+#[command(
+    version = env!("GIT_DESCRIBE"),
+    about = "migrate-chainstore - converts florestad's chain data to another chainstore backend"
+)]
+struct Cli {
+    /// The data directory of the network we're migrating, like `~/.floresta/signet`
+    data_dir: String,
+
+    #[arg(long, value_enum)]
+    /// Which backend we should convert to
+    to: Backend,
+}
+
+fn main() {
+    let params = Cli::parse();
+    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+
+    let to = match params.to {
+        Backend::Flat => ChainStoreBackend::Flat,
+        Backend::Kv => ChainStoreBackend::Kv,
+    };
+
+    if let Err(e) = migrate_chainstore(&params.data_dir, to) {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
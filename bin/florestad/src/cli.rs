@@ -27,6 +27,29 @@ pub struct Cli {
     /// Which network should we use
     pub network: Network,
 
+    #[arg(long, value_name = "HEX")]
+    /// The challenge of a custom signet, as a hex-encoded script
+    ///
+    /// Only valid with `--network signet`. Every block must carry a solution for this challenge,
+    /// and we'll only talk to peers on the same signet. Its data is kept in a subdirectory of the
+    /// signet data directory, named after the challenge's hash. Can also be set in the `[signet]`
+    /// section of the config file, but then use a different data directory for each signet.
+    pub signet_challenge: Option<String>,
+
+    #[arg(long, value_name = "HOST")]
+    /// A DNS seed for our custom signet
+    ///
+    /// This option can be passed many times, and only has effect with `--signet-challenge`.
+    pub signet_seed: Option<Vec<String>>,
+
+    #[arg(long, value_name = "FILE")]
+    /// A chain spec file, to run on a custom chain
+    ///
+    /// The file is TOML, or JSON if it ends with `.json`, and may override the genesis header,
+    /// network magic, default port, PoW rules, activation heights, assume-valid and
+    /// assume-utreexo values of `--network`. Use a different data directory for each chain.
+    pub chain_spec: Option<String>,
+
     #[arg(short, long, default_value_t = false)]
     /// Turn debugging information on
     pub debug: bool,
@@ -55,6 +78,47 @@ pub struct Cli {
     /// The url of a proxy we should open p2p connections through (e.g. 127.0.0.1:9050)
     pub proxy: Option<String>,
 
+    #[arg(long, default_value = None, value_name = "address[:<port>]")]
+    /// The SAM bridge of an I2P router, used to connect with I2P peers (e.g. 127.0.0.1:7656)
+    pub i2psam: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// Keep the same I2P address across restarts, saving its private key in our data directory
+    pub i2p_persistent: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Broadcast our own transactions through short-lived connections made over our proxy or
+    /// I2P, instead of announcing them to our regular peers. Requires --proxy or --i2psam
+    pub privatebroadcast: bool,
+
+    #[arg(long, default_value = None, value_name = "MiB")]
+    /// How many MiB we may upload to our peers every 24 hours
+    ///
+    /// Close to this target, we stop answering bandwidth-heavy requests, and once we reach it,
+    /// we also stop serving mempool transactions. Blocks are never limited, and manual peers
+    /// are exempt. Useful on metered connections.
+    pub maxuploadtarget: Option<u64>,
+
+    #[arg(long, default_value = None, value_name = "address[:<port>]")]
+    /// Tor's control port, used to publish an onion service other nodes can connect to
+    /// (e.g. 127.0.0.1:9051). Our Electrum and RPC servers may be published there too
+    pub torcontrol: Option<String>,
+
+    #[arg(long, default_value = None, value_name = "PASSWORD")]
+    /// The password for Tor's control port. If not set, we'll use Tor's cookie authentication
+    pub torpassword: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// Publish our Electrum server in our onion service. Requires --torcontrol
+    pub onion_electrum: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Publish our JSON-RPC server in our onion service. Requires --torcontrol
+    ///
+    /// Anyone who knows our onion address will be able to use it, so only enable this if you
+    /// know what you are doing.
+    pub onion_rpc: bool,
+
     #[arg(long, value_name = "XPUB")]
     /// Add an xpub to our wallet
     ///
@@ -121,6 +185,24 @@ pub struct Cli {
     /// we reach the assumed tip. If you want to stop this behavior, use the --no-backfill flag.
     pub no_assume_utreexo: bool,
 
+    #[arg(long, default_value_t = false)]
+    /// Use PoW fraud proofs to skip the validation of historical blocks
+    ///
+    /// Instead of the hardcoded assume utreexo value, we ask our peers for the accumulator of
+    /// the most-work chain. If some peers announce another tip, we validate the first block
+    /// where the chains diverge, and follow the valid one. Requires peers with the
+    /// UTREEXO_ARCHIVE service. The tips we've checked are shown by `getblockchaininfo`.
+    pub pow_fraud_proofs: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Start from the Utreexo accumulator in this snapshot file
+    ///
+    /// Snapshots are made with the `dumputreexostate` RPC. Once we've downloaded the headers, we
+    /// check the snapshot's block is in our best chain, and skip the validation of all blocks up
+    /// to it, like with assume utreexo. If backfill is enabled, those blocks are validated in the
+    /// background. Only use snapshots you trust.
+    pub utreexo_snapshot: Option<String>,
+
     #[arg(long, value_name = "address[:<port>]")]
     /// The address where the Electrum Server should listen to, in the format `<address>[:<port>]`
     pub electrum_address: Option<String>,
@@ -133,6 +215,69 @@ pub struct Cli {
     /// The address where the Electrum TLS Server should listen to, in the format `<address>[:<port>]`
     pub electrum_address_tls: Option<String>,
 
+    #[arg(long, value_name = "COUNT")]
+    /// Maximum number of clients connected to the Electrum Server at the same time (default: 256)
+    pub electrum_max_clients: Option<usize>,
+
+    #[arg(long, value_name = "COUNT")]
+    /// Maximum number of addresses a single Electrum client may subscribe to (default: 5000)
+    pub electrum_max_subscriptions: Option<usize>,
+
+    #[arg(long, value_name = "BYTES")]
+    /// Maximum size of a single Electrum request, in bytes (default: 1000000)
+    pub electrum_max_request_size: Option<usize>,
+
+    #[arg(long, value_name = "COST")]
+    /// How much request cost an Electrum client may spend per second (default: 100)
+    ///
+    /// Most requests cost one unit, but requests returning a lot of data, like the history of
+    /// an address with many transactions, cost more. Clients may spend up to 100 seconds worth
+    /// of cost at once, and after that their requests are refused until the budget refills.
+    pub electrum_request_rate: Option<u32>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// Disconnect Electrum clients that stay silent for this long (default: 600)
+    pub electrum_idle_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECONDS")]
+    /// For how long misbehaving Electrum clients are banned (default: 3600)
+    ///
+    /// Clients connecting from localhost, like the ones coming through a Tor onion service,
+    /// are only disconnected, never banned.
+    pub electrum_ban_time: Option<u64>,
+
+    #[arg(long, value_name = "HOST")]
+    /// A public host where our Electrum Server can be reached, in the format
+    /// `<hostname>[:t<tcp_port>][:s<ssl_port>]` (e.g. `example.com:t50001:s50002`)
+    ///
+    /// This option can be passed many times, e.g. once for a clearnet hostname and once for an
+    /// onion address. Those hosts are reported to wallets and announced to other Electrum servers.
+    pub electrum_public_host: Option<Vec<String>>,
+
+    #[arg(long, value_name = "HOST")]
+    /// An Electrum server to check and announce ourselves to, in the same format as
+    /// `--electrum-public-host`
+    ///
+    /// This option can be passed many times. Servers that pass our checks are returned to
+    /// wallets asking for `server.peers.subscribe`.
+    pub electrum_peer: Option<Vec<String>>,
+
+    #[arg(long, value_name = "NAME:TOKEN")]
+    /// A tenant of our Electrum Server, and the token its clients authenticate with
+    ///
+    /// This option can be passed many times. With any tenant, the Electrum Server runs in
+    /// multi-tenant mode: each tenant gets its own wallet, and clients must call
+    /// `server.authenticate` with their token before querying any address.
+    pub electrum_tenant: Option<Vec<String>>,
+
+    #[arg(long, value_name = "NAME:FINGERPRINT")]
+    /// A tenant of our Electrum Server, authenticated by the TLS client certificate with this
+    /// sha256 fingerprint
+    ///
+    /// This option can be passed many times. Clients presenting this certificate to the Electrum
+    /// TLS Server are bound to the tenant without calling `server.authenticate`.
+    pub electrum_tenant_cert: Option<Vec<String>>,
+
     #[arg(long, default_value_t = false)]
     /// Whether to generate a self-signed TLS certificate on start.
     ///
@@ -162,6 +307,14 @@ pub struct Cli {
     /// if we can't make a V2 connection.
     pub allow_v1_fallback: bool,
 
+    #[arg(long, value_name = "FILE")]
+    /// An ASmap file, in Bitcoin Core's format, mapping IP addresses to the Autonomous System
+    /// announcing them
+    ///
+    /// If set, we'll try to connect to peers in different Autonomous Systems, instead of just
+    /// different IP ranges, making it harder for a single entity to control all our connections.
+    pub asmap: Option<String>,
+
     #[cfg(unix)]
     #[arg(long, default_value = "false")]
     /// Run florestad as a daemon.
@@ -188,6 +341,25 @@ pub struct Cli {
     /// This will run in the background and wont't affect node's operation. However,
     /// to disable backfilling, run floresta using this flag.
     pub no_backfill: bool,
+
+    #[arg(long, value_name = "FILE")]
+    /// Backfill in parallel, starting one more range from the Utreexo snapshot in this file
+    ///
+    /// Snapshots are made with the `dumputreexostate` RPC. Each one splits the backfill into one
+    /// more range, validated at the same time as the others, and checked against the snapshot
+    /// of the next range once it's done. So a wrong snapshot only costs us time, it can't make
+    /// us accept an invalid chain. This option can be passed many times.
+    pub backfill_checkpoints: Option<Vec<String>>,
+
+    #[arg(long, default_value_t = false)]
+    /// Only keep the accumulator roots of recent blocks, and a few old ones
+    ///
+    /// We keep the Utreexo accumulator of every block, in case we need to reorg back to it. On
+    /// mainnet, this is a few hundreds of MiB. With this flag, we only keep the accumulators for
+    /// the last day of blocks and one for every difficulty period, so florestad fits on small
+    /// disks. If a reorg ever goes deeper than that, we validate the blocks after the last
+    /// accumulator we have again.
+    pub prune_roots: bool,
 }
 
 impl Cli {
@@ -28,10 +28,13 @@ use std::process::exit;
 use std::sync::Arc;
 use std::time::Duration;
 
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
 use bitcoin::Network;
 use clap::Parser;
 use cli::Cli;
 use floresta_node::Config;
+use floresta_node::ElectrumLimits;
 use floresta_node::Florestad;
 use tokio::sync::RwLock;
 use tokio::time::sleep;
@@ -47,8 +50,14 @@ fn main() {
     let params = Cli::parse();
     params.validate();
 
+    let electrum_limits = electrum_limits(&params);
+
     // If not provided defaults to `$HOME/.floresta`. Uses a subdirectory for non-mainnet networks.
-    let data_dir = data_dir_path(params.data_dir, params.network);
+    let data_dir = data_dir_path(
+        params.data_dir,
+        params.network,
+        params.signet_challenge.as_deref(),
+    );
 
     // Create the data directory if it doesn't exist
     fs::create_dir_all(&data_dir).unwrap_or_else(|e| {
@@ -60,10 +69,22 @@ fn main() {
         data_dir,
         disable_dns_seeds: params.connect.is_some() || params.disable_dns_seeds,
         network: params.network,
+        signet_challenge: params.signet_challenge,
+        signet_seeds: params.signet_seed.unwrap_or_default(),
+        chain_spec: params.chain_spec,
         debug: params.debug,
         cfilters: !params.no_cfilters,
         proxy: params.proxy,
+        i2psam: params.i2psam,
+        i2p_persistent: params.i2p_persistent,
+        private_broadcast: params.privatebroadcast,
+        max_upload_target: params.maxuploadtarget,
+        tor_control: params.torcontrol,
+        tor_password: params.torpassword,
+        onion_electrum: params.onion_electrum,
+        onion_rpc: params.onion_rpc,
         assume_utreexo: !params.no_assume_utreexo,
+        pow_fraud_proofs: params.pow_fraud_proofs,
         connect: params.connect,
         wallet_xpub: params.wallet_xpub,
         config_file: params.config_file,
@@ -85,13 +106,23 @@ fn main() {
         filters_start_height: params.filters_start_height,
         user_agent: env!("USER_AGENT").to_owned(),
         assumeutreexo_value: None,
+        utreexo_snapshot: params.utreexo_snapshot,
         electrum_address: params.electrum_address,
         enable_electrum_tls: params.enable_electrum_tls,
         electrum_address_tls: params.electrum_address_tls,
+        electrum_limits,
+        electrum_public_hosts: params.electrum_public_host.unwrap_or_default(),
+        electrum_seed_peers: params.electrum_peer.unwrap_or_default(),
+        electrum_tenants: params.electrum_tenant.unwrap_or_default(),
+        electrum_tenant_certs: params.electrum_tenant_cert.unwrap_or_default(),
         tls_cert_path: params.tls_cert_path,
         tls_key_path: params.tls_key_path,
         allow_v1_fallback: params.allow_v1_fallback,
+        asmap: params.asmap,
         backfill: !params.no_backfill,
+        backfill_checkpoints: Vec::new(),
+        backfill_checkpoint_snapshots: params.backfill_checkpoints.unwrap_or_default(),
+        prune_roots: params.prune_roots,
     };
 
     #[cfg(unix)]
@@ -166,7 +197,40 @@ fn main() {
     drop(_logger_guard);
 }
 
-fn data_dir_path(dir: Option<String>, network: Network) -> String {
+/// Builds the Electrum Server limits, using the defaults for anything not set in the cli.
+fn electrum_limits(params: &Cli) -> ElectrumLimits {
+    let mut limits = ElectrumLimits::default();
+
+    if let Some(max_clients) = params.electrum_max_clients {
+        limits.max_clients = max_clients;
+    }
+    if let Some(max_subscriptions) = params.electrum_max_subscriptions {
+        limits.max_subscriptions_per_client = max_subscriptions;
+    }
+    if let Some(max_request_size) = params.electrum_max_request_size {
+        limits.max_request_bytes = max_request_size;
+    }
+    if let Some(request_rate) = params.electrum_request_rate {
+        // Keep the burst at the same number of seconds worth of requests as the defaults
+        let burst_seconds = limits.request_cost_burst / limits.request_cost_per_second;
+        limits.request_cost_per_second = request_rate;
+        limits.request_cost_burst = request_rate.saturating_mul(burst_seconds);
+    }
+    if let Some(idle_timeout) = params.electrum_idle_timeout {
+        limits.idle_timeout = Duration::from_secs(idle_timeout);
+    }
+    if let Some(ban_time) = params.electrum_ban_time {
+        limits.ban_duration = Duration::from_secs(ban_time);
+    }
+
+    // Clients must be able to afford subscribing to all the addresses they are allowed to
+    let max_subscriptions = u32::try_from(limits.max_subscriptions_per_client).unwrap_or(u32::MAX);
+    limits.request_cost_burst = limits.request_cost_burst.max(max_subscriptions);
+
+    limits
+}
+
+fn data_dir_path(dir: Option<String>, network: Network, signet_challenge: Option<&str>) -> String {
     // base dir: provided `dir` or $HOME/.floresta or "./.floresta"
     let mut base: PathBuf = dir
         .as_ref()
@@ -186,6 +250,13 @@ fn data_dir_path(dir: Option<String>, network: Network) -> String {
         Network::Regtest => base.push("regtest"),
     }
 
+    // A custom signet has its own genesis chain, peers and wallet transactions, so each one
+    // gets a subdir named after the hash of its challenge
+    if let (Network::Signet, Some(challenge)) = (network, signet_challenge) {
+        let hash = sha256::Hash::hash(challenge.to_ascii_lowercase().as_bytes());
+        base.push(&hash.to_string()[..16]);
+    }
+
     base.to_string_lossy().into_owned()
 }
 
@@ -201,24 +272,24 @@ mod tests {
             .join(".floresta");
 
         assert_eq!(
-            data_dir_path(None, net),
+            data_dir_path(None, net, None),
             default_expected.display().to_string(),
         );
 
         // Using other made-up directories
         let mut path = Some("path/to/dir".into());
-        assert_eq!(data_dir_path(path, net), "path/to/dir");
+        assert_eq!(data_dir_path(path, net, None), "path/to/dir");
 
         path = Some("path/to/dir/".into());
-        assert_eq!(data_dir_path(path, net), "path/to/dir");
+        assert_eq!(data_dir_path(path, net, None), "path/to/dir");
 
         // Test removing the '\' separator
         path = Some(format!("path{}", '\\'));
-        assert_eq!(data_dir_path(path, net), "path");
+        assert_eq!(data_dir_path(path, net, None), "path");
 
         // Test removing many separators
         path = Some("path///".into());
-        assert_eq!(data_dir_path(path, net), "path");
+        assert_eq!(data_dir_path(path, net, None), "path");
 
         // Using other networks
         for &(net, suffix) in &[
@@ -230,9 +301,34 @@ mod tests {
             let expected = PathBuf::from("path").join(suffix);
 
             assert_eq!(
-                data_dir_path(Some("path///".into()), net),
+                data_dir_path(Some("path///".into()), net, None),
                 expected.display().to_string(),
             );
         }
+
+        // Custom signets get their own subdir, the challenge is ignored on other networks
+        let challenge =
+            "512102f7561d208dd9ae99bf497273e16f389bdbd6c4742ddb8e6b216e64fa2928ad8f51ae";
+        let custom = data_dir_path(Some("path".into()), Network::Signet, Some(challenge));
+        assert_eq!(
+            custom,
+            PathBuf::from("path")
+                .join("signet")
+                .join(&sha256::Hash::hash(challenge.as_bytes()).to_string()[..16])
+                .display()
+                .to_string(),
+        );
+
+        let other = data_dir_path(Some("path".into()), Network::Signet, Some("51"));
+        assert_ne!(custom, other);
+
+        let upper = challenge.to_ascii_uppercase();
+        let same = data_dir_path(Some("path".into()), Network::Signet, Some(&upper));
+        assert_eq!(custom, same);
+
+        assert_eq!(
+            data_dir_path(Some("path".into()), Network::Regtest, Some(challenge)),
+            PathBuf::from("path").join("regtest").display().to_string(),
+        );
     }
 }
@@ -12,7 +12,6 @@ use bitcoin::Block;
 use bitcoin::Network;
 use bitcoin::OutPoint;
 use criterion::criterion_group;
-use criterion::criterion_main;
 use criterion::BatchSize;
 use criterion::Criterion;
 use criterion::SamplingMode;
@@ -21,8 +20,10 @@ use floresta_chain::pruned_utreexo::utxo_data::UtxoData;
 use floresta_chain::pruned_utreexo::UpdatableChainstate;
 use floresta_chain::AssumeValidArg;
 use floresta_chain::ChainState;
+use floresta_chain::ChainStore;
 use floresta_chain::FlatChainStore;
 use floresta_chain::FlatChainStoreConfig;
+use floresta_chain::MemoryChainStore;
 use rustreexo::proof::Proof;
 
 /// Reads the first 151 blocks (or 150 blocks on top of genesis) from `regtest_blocks.txt`
@@ -61,22 +62,40 @@ fn read_mainnet_headers() -> Vec<BlockHeader> {
     headers
 }
 
-fn setup_test_chain(
+/// A chainstore we can run our benchmarks on
+trait BenchStore: ChainStore + Sized {
+    /// Creates a new, empty store
+    fn new_bench_store() -> Self;
+}
+
+impl BenchStore for FlatChainStore {
+    fn new_bench_store() -> Self {
+        let test_id = rand::random::<u64>();
+        let config = FlatChainStoreConfig {
+            block_index_size: Some(32_768),
+            headers_file_size: Some(32_768),
+            fork_file_size: Some(10_000), // Will be rounded up to 16,384
+            cache_size: Some(10),
+            file_permission: Some(0o660),
+            roots_retention: None,
+            path: format!("./tmp-db/{test_id}/").into(),
+        };
+
+        FlatChainStore::new(config).unwrap()
+    }
+}
+
+impl BenchStore for MemoryChainStore {
+    fn new_bench_store() -> Self {
+        MemoryChainStore::new()
+    }
+}
+
+fn setup_test_chain<S: BenchStore>(
     network: Network,
     assume_valid_arg: AssumeValidArg,
-) -> ChainState<FlatChainStore> {
-    let test_id = rand::random::<u64>();
-    let config = FlatChainStoreConfig {
-        block_index_size: Some(32_768),
-        headers_file_size: Some(32_768),
-        fork_file_size: Some(10_000), // Will be rounded up to 16,384
-        cache_size: Some(10),
-        file_permission: Some(0o660),
-        path: format!("./tmp-db/{test_id}/").into(),
-    };
-
-    let chainstore = FlatChainStore::new(config).unwrap();
-    ChainState::open(chainstore, network, assume_valid_arg).unwrap()
+) -> ChainState<S> {
+    ChainState::open(S::new_bench_store(), network, assume_valid_arg).unwrap()
 }
 
 fn decode_block_and_inputs(
@@ -135,12 +154,12 @@ fn check_merkle_root_benchmark(c: &mut Criterion) {
     });
 }
 
-fn accept_mainnet_headers_benchmark(c: &mut Criterion) {
+fn accept_mainnet_headers_benchmark<S: BenchStore>(c: &mut Criterion) {
     let headers = read_mainnet_headers();
 
     c.bench_function("accept_10k_mainnet_headers", |b| {
         b.iter_batched(
-            || setup_test_chain(Network::Bitcoin, AssumeValidArg::Hardcoded),
+            || setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Hardcoded),
             |chain| {
                 headers
                     .iter()
@@ -151,12 +170,12 @@ fn accept_mainnet_headers_benchmark(c: &mut Criterion) {
     });
 }
 
-fn accept_headers_benchmark(c: &mut Criterion) {
+fn accept_headers_benchmark<S: BenchStore>(c: &mut Criterion) {
     let blocks = read_blocks_txt();
 
     c.bench_function("accept_150_headers", |b| {
         b.iter_batched(
-            || setup_test_chain(Network::Regtest, AssumeValidArg::Disabled),
+            || setup_test_chain::<S>(Network::Regtest, AssumeValidArg::Disabled),
             |chain| {
                 blocks
                     .iter()
@@ -167,11 +186,11 @@ fn accept_headers_benchmark(c: &mut Criterion) {
     });
 }
 
-fn connect_blocks_benchmark(c: &mut Criterion) {
+fn connect_blocks_benchmark<S: BenchStore>(c: &mut Criterion) {
     let blocks = read_blocks_txt();
 
     let setup_chain = || {
-        let chain = setup_test_chain(Network::Regtest, AssumeValidArg::Disabled);
+        let chain = setup_test_chain::<S>(Network::Regtest, AssumeValidArg::Disabled);
         // We need to accept the headers before connecting blocks
         blocks
             .iter()
@@ -195,12 +214,12 @@ fn connect_blocks_benchmark(c: &mut Criterion) {
     });
 }
 
-fn validate_full_block_benchmark(c: &mut Criterion) {
+fn validate_full_block_benchmark<S: BenchStore>(c: &mut Criterion) {
     let block_file = File::open("./testdata/block_866342/raw.zst").unwrap();
     let stxos_file = File::open("./testdata/block_866342/spent_utxos.zst").unwrap();
     let (block, inputs) = decode_block_and_inputs(block_file, stxos_file);
 
-    let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
+    let chain = setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Disabled);
 
     c.bench_function("validate_block_866342", |b| {
         b.iter_batched(
@@ -211,7 +230,7 @@ fn validate_full_block_benchmark(c: &mut Criterion) {
     });
 }
 
-fn validate_many_inputs_block_benchmark(c: &mut Criterion) {
+fn validate_many_inputs_block_benchmark<S: BenchStore>(c: &mut Criterion) {
     if std::env::var("EXPENSIVE_BENCHES").is_err() {
         println!(
             "validate_many_inputs_block_benchmark ... \x1b[33mskipped\x1b[0m\n\
@@ -225,7 +244,7 @@ fn validate_many_inputs_block_benchmark(c: &mut Criterion) {
     let stxos_file = File::open("./testdata/block_367891/spent_utxos.zst").unwrap();
     let (block, inputs) = decode_block_and_inputs(block_file, stxos_file);
 
-    let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
+    let chain = setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Disabled);
 
     // Create a group with the lowest possible sample size, as validating this block is very slow
     let mut group = c.benchmark_group("validate_block_367891");
@@ -243,7 +262,6 @@ fn validate_many_inputs_block_benchmark(c: &mut Criterion) {
 }
 
 fn chainstore_checksum_benchmark(c: &mut Criterion) {
-    use floresta_chain::ChainStore;
     use floresta_chain::DiskBlockHeader;
 
     let headers = read_mainnet_headers();
@@ -277,14 +295,34 @@ fn chainstore_checksum_benchmark(c: &mut Criterion) {
 }
 
 criterion_group!(
-    benches,
+    flat_benches,
     initialize_chainstore_benchmark,
     check_merkle_root_benchmark,
-    accept_mainnet_headers_benchmark,
-    accept_headers_benchmark,
-    connect_blocks_benchmark,
-    validate_full_block_benchmark,
-    validate_many_inputs_block_benchmark,
+    accept_mainnet_headers_benchmark::<FlatChainStore>,
+    accept_headers_benchmark::<FlatChainStore>,
+    connect_blocks_benchmark::<FlatChainStore>,
+    validate_full_block_benchmark::<FlatChainStore>,
+    validate_many_inputs_block_benchmark::<FlatChainStore>,
     chainstore_checksum_benchmark
 );
-criterion_main!(benches);
+
+criterion_group!(
+    memory_benches,
+    check_merkle_root_benchmark,
+    accept_mainnet_headers_benchmark::<MemoryChainStore>,
+    accept_headers_benchmark::<MemoryChainStore>,
+    connect_blocks_benchmark::<MemoryChainStore>,
+    validate_full_block_benchmark::<MemoryChainStore>,
+    validate_many_inputs_block_benchmark::<MemoryChainStore>
+);
+
+/// Runs our benchmarks on the `FlatChainStore`, or on the `MemoryChainStore` if
+/// `BENCH_CHAINSTORE=memory` is set
+fn main() {
+    match std::env::var("BENCH_CHAINSTORE").as_deref() {
+        Ok("memory") => memory_benches(),
+        _ => flat_benches(),
+    }
+
+    Criterion::default().configure_from_args().final_summary();
+}
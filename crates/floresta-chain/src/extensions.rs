@@ -281,6 +281,7 @@ mod tests {
     use bitcoin::consensus::encode::deserialize_hex;
     use bitcoin::hashes::sha256::Hash as Sha256Hash;
     use bitcoin::params::Params;
+    use bitcoin::Amount;
     use bitcoin::Block;
     use bitcoin::BlockHash;
     use bitcoin::OutPoint;
@@ -429,6 +430,16 @@ mod tests {
             unimplemented!()
         }
 
+        fn validate_transaction(
+            &self,
+            _: &Transaction,
+            _: Proof,
+            _: HashMap<OutPoint, UtxoData>,
+            _: Vec<Sha256Hash>,
+        ) -> Result<Amount, Self::Error> {
+            unimplemented!()
+        }
+
         fn get_fork_point(&self, _: BlockHash) -> Result<BlockHash, Self::Error> {
             unimplemented!()
         }
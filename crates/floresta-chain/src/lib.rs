@@ -9,7 +9,10 @@
 //!
 //! All data is stored in a `ChainStore` implementation, which is generic over the
 //! underlying database. See the ChainStore trait for more information. For a
-//! ready-to-use implementation, see the [`FlatChainStore`] struct.
+//! ready-to-use implementation, see the [`FlatChainStore`] struct, or the `KvChainStore`
+//! struct (behind the `kv-chainstore` feature) where memory maps aren't available. For tests and
+//! throwaway chains, the `MemoryChainStore` (behind the `memory-chainstore` feature) keeps
+//! everything in memory.
 
 // cargo docs customization
 #![cfg_attr(docsrs, feature(doc_cfg))]
@@ -32,7 +35,12 @@ pub use pruned_utreexo::consensus::swift_sync_agg;
 pub use pruned_utreexo::error::*;
 #[cfg(feature = "flat-chainstore")]
 pub use pruned_utreexo::flat_chain_store::*;
+#[cfg(feature = "kv-chainstore")]
+pub use pruned_utreexo::kv_chain_store::*;
+#[cfg(feature = "memory-chainstore")]
+pub use pruned_utreexo::memory_chain_store::*;
 pub use pruned_utreexo::udata::*;
+pub use pruned_utreexo::utreexo_snapshot::UtreexoSnapshotError;
 pub use pruned_utreexo::utxo_data::*;
 pub use pruned_utreexo::BlockchainInterface;
 pub use pruned_utreexo::ChainBackend;
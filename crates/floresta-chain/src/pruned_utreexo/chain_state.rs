@@ -29,9 +29,9 @@ use core::cmp::min;
 use core::ops::Add;
 
 use bitcoin::block::Header as BlockHeader;
-use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::hashes::sha256;
 use bitcoin::hashes::Hash;
+use bitcoin::Amount;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Network;
@@ -69,6 +69,7 @@ use crate::read_lock;
 use crate::write_lock;
 use crate::BestChain;
 use crate::ChainStore;
+use crate::TransactionError;
 
 /// Trait for components that need to receive notifications about new blocks.
 pub trait BlockConsumer: Sync + Send + 'static {
@@ -276,8 +277,8 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
     /// Returns the cumulative work in this branch.
     ///
-    /// This function is more optimized CPU-wise. It will take (n/2016) header lookups to compute
-    /// the work of a branch starting a `header`.
+    /// This function is more optimized CPU-wise. It will take one header lookup per retarget
+    /// interval (n/2016 on mainnet) to compute the work of a branch starting a `header`.
     ///
     /// It does not work with fork headers, see `get_fork_work` for that.
     fn get_branch_work(&self, header: BlockHeader) -> Result<Work, BlockchainError> {
@@ -286,9 +287,10 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             .ok_or(BlockchainError::BlockNotPresent)?;
 
         let mut total_chainwork = Work::from_be_bytes([0u8; 32]);
-        for epoch_start_height in (0..=block_height).step_by(2016) {
+        let interval = self.chain_params().params.difficulty_adjustment_interval() as u32;
+        for epoch_start_height in (0..=block_height).step_by(interval as usize) {
             // Calculate the number of blocks in this epoch
-            let epoch_end_height = min(epoch_start_height + 2015, block_height);
+            let epoch_end_height = min(epoch_start_height + interval - 1, block_height);
             let blocks_in_epoch = epoch_end_height - epoch_start_height + 1;
 
             // Get the block hash and header at the start of the epoch
@@ -424,18 +426,49 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
     }
 
     /// Changes the acc we are using to validate blocks.
+    ///
+    /// If our chainstore doesn't have the roots for the fork point anymore, because they were
+    /// pruned, we go back to the last block we still have roots for, and validate all blocks
+    /// after it again.
     fn reorg_acc(&self, fork_point: &BlockHeader) -> Result<(), BlockchainError> {
         let height = self
             .get_block_height(&fork_point.block_hash())?
             .ok_or(BlockchainError::BlockNotPresent)?;
 
-        let acc = self.get_roots_for_block(height)?.unwrap_or_default();
+        let (acc_height, acc) = self.get_last_roots(height)?;
+        if acc_height != height {
+            warn!(
+                "We don't have the accumulator for block {height}, going back to block {acc_height}"
+            );
+
+            for height in (acc_height + 1)..=height {
+                let header = self.get_header_by_height(height)?;
+                self.update_header(&DiskBlockHeader::HeadersOnly(*header, height))?;
+            }
+
+            let last_acc_header = self.get_header_by_height(acc_height)?;
+            write_lock!(self).best_block.validation_index = last_acc_header.block_hash();
+        }
+
         let mut inner = write_lock!(self);
         inner.acc = acc;
 
         Ok(())
     }
 
+    /// Returns the last block at or before `height` that we have roots for, and its accumulator
+    ///
+    /// The genesis block has an empty accumulator, so we always find one.
+    fn get_last_roots(&self, height: u32) -> Result<(u32, Stump), BlockchainError> {
+        for height in (1..=height).rev() {
+            if let Some(acc) = self.get_roots_for_block(height)? {
+                return Ok((height, acc));
+            }
+        }
+
+        Ok((0, Stump::default()))
+    }
+
     // This method should only be called after we validate the new branch
     fn reorg(&self, new_tip: BlockHeader) -> Result<(), BlockchainError> {
         let current_best_block = self.get_block_header(&self.get_best_block()?.1)?;
@@ -448,6 +481,7 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         let depth = self.get_chain_depth(&new_tip)?;
 
         self.change_active_chain(&new_tip, validation_index, depth);
+        self.update_alt_tips(&new_tip, &current_best_block, &fork_point);
         self.reorg_acc(&fork_point)?;
 
         Ok(())
@@ -461,6 +495,27 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         inner.best_block.depth = depth;
     }
 
+    /// After a reorg, the branch we left becomes an alternative tip, and the branch we moved to
+    /// is not one anymore
+    fn update_alt_tips(
+        &self,
+        new_tip: &BlockHeader,
+        old_tip: &BlockHeader,
+        fork_point: &BlockHeader,
+    ) {
+        let mut inner = self.inner.write();
+        let alternative_tips = &mut inner.best_block.alternative_tips;
+
+        // The new tip may extend a branch we already knew about
+        alternative_tips
+            .retain(|hash| *hash != new_tip.block_hash() && *hash != new_tip.prev_blockhash);
+
+        // If the old tip is the fork point, there's no branch left behind
+        if old_tip.block_hash() != fork_point.block_hash() {
+            alternative_tips.push(old_tip.block_hash());
+        }
+    }
+
     /// Grabs the last block we validated in this branch. We don't validate a fork, unless it
     /// becomes the best chain. This function technically finds out what is the last common block
     /// between two branches.
@@ -575,11 +630,10 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
     fn new(
         mut chainstore: PersistedState,
-        network: Network,
+        parameters: ChainParams,
         assume_valid: AssumeValidArg,
     ) -> ChainState<PersistedState> {
-        let parameters = network.into();
-        let genesis = genesis_block(&parameters);
+        let genesis = parameters.genesis.clone();
 
         chainstore
             .save_header(&DiskBlockHeader::FullyValid(genesis.header, 0))
@@ -589,7 +643,7 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             .update_block_index(0, genesis.block_hash())
             .expect("Error updating index");
 
-        let assume_valid = ChainParams::get_assume_valid(network, assume_valid);
+        let assume_valid = parameters.assume_valid(assume_valid);
 
         ChainState {
             inner: RwLock::new(ChainStateInner {
@@ -660,16 +714,9 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
         // This will be the height of the last accumulator we have
         // Find the last height <= validation_index_height that has an accumulator
-        let mut found = None;
+        let (last_acc_height, acc) = self.get_last_roots(validation_index_height)?;
 
-        for h in (1..=validation_index_height).rev() {
-            if let Some(acc) = self.get_roots_for_block(h)? {
-                found = Some((h, acc));
-                break;
-            }
-        }
-
-        if let Some((last_acc_height, acc)) = found {
+        if last_acc_height != 0 {
             // If the last acc height is lower than the validation index height, roll back our
             // database state
             for height in (last_acc_height + 1)..=validation_index_height {
@@ -742,7 +789,7 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
     fn load_chain_state(
         mut chainstore: PersistedState,
-        network: Network,
+        parameters: ChainParams,
         assume_valid: AssumeValidArg,
     ) -> Result<ChainState<PersistedState>, BlockchainError> {
         let best_block = chainstore
@@ -766,10 +813,8 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             fee_estimation: (1_f64, 1_f64, 1_f64),
             subscribers: Vec::new(),
             ibd: true,
-            consensus: Consensus {
-                parameters: network.into(),
-            },
-            assume_valid: ChainParams::get_assume_valid(network, assume_valid),
+            assume_valid: parameters.assume_valid(assume_valid),
+            consensus: Consensus { parameters },
         };
 
         info!(
@@ -799,11 +844,25 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         chainstore: PersistedState,
         network: Network,
         assume_valid: AssumeValidArg,
+    ) -> Result<Self, BlockchainError> {
+        Self::open_with_params(chainstore, network.into(), assume_valid)
+    }
+
+    /// Same as [`ChainState::open`], but for a chain with custom parameters, like a custom
+    /// signet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read or if the persisted state is corrupted.
+    pub fn open_with_params(
+        chainstore: PersistedState,
+        parameters: ChainParams,
+        assume_valid: AssumeValidArg,
     ) -> Result<Self, BlockchainError> {
         if chainstore.load_height()?.is_some() {
-            return Self::load_chain_state(chainstore, network, assume_valid);
+            return Self::load_chain_state(chainstore, parameters, assume_valid);
         }
-        Ok(Self::new(chainstore, network, assume_valid))
+        Ok(Self::new(chainstore, parameters, assume_valid))
     }
 
     /// Checks whether our database got a file-level corruption, and if so, reindex.
@@ -937,6 +996,21 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             None => Ok(true),
         }
     }
+
+    /// Returns the median time past of the block at `height` in our best chain, as used by
+    /// BIP 113 and BIP 68
+    fn get_median_time_past(&self, height: u32) -> Result<u32, BlockchainError> {
+        let mut times = (height.saturating_sub(10)..=height)
+            .map(|height| {
+                let hash = self.get_block_hash(height)?;
+                Ok(self.get_block_header(&hash)?.time)
+            })
+            .collect::<Result<Vec<_>, BlockchainError>>()?;
+
+        times.sort_unstable();
+        Ok(times[times.len() / 2])
+    }
+
     pub fn acc(&self) -> Stump {
         read_lock!(self).acc.to_owned()
     }
@@ -958,9 +1032,10 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         }
 
         // Regtest don't have retarget
-        if !params.params.no_pow_retargeting && (next_height) % 2016 == 0 {
+        let interval = params.params.difficulty_adjustment_interval() as u32;
+        if !params.params.no_pow_retargeting && next_height % interval == 0 {
             // First block in this epoch
-            let first_block = self.get_header_by_height(next_height - 2016)?;
+            let first_block = self.get_header_by_height(next_height - interval)?;
             let last_block = self.get_header_by_height(next_height - 1)?;
 
             let target =
@@ -1093,6 +1168,81 @@ impl<PersistedState: ChainStore> BlockchainInterface for ChainState<PersistedSta
         self.validate_block_no_acc(block, height, inputs)
     }
 
+    fn validate_transaction(
+        &self,
+        transaction: &Transaction,
+        proof: Proof,
+        mut inputs: HashMap<OutPoint, UtxoData>,
+        del_hashes: Vec<sha256::Hash>,
+    ) -> Result<Amount, Self::Error> {
+        // Unlike old blocks, nothing vouches for an unconfirmed transaction's signatures,
+        // so we refuse it instead of skipping its scripts
+        if !Consensus::VERIFIES_SCRIPTS {
+            return Err(BlockchainError::ScriptValidationUnavailable);
+        }
+
+        // Convert to BitcoinNodeHashes, from rustreexo
+        let del_hashes: Vec<_> = del_hashes
+            .into_iter()
+            .map(|hash| BitcoinNodeHash::Some(hash.to_byte_array()))
+            .collect();
+
+        if !self.acc().verify(&proof, &del_hashes)? {
+            return Err(BlockValidationErrors::InvalidUtreexoProof)?;
+        }
+
+        // Coinbase transactions are only valid as the first transaction of a block
+        if transaction.is_coinbase() {
+            return Err(TransactionError {
+                txid: transaction.compute_txid(),
+                error: BlockValidationErrors::InvalidCoinbase(
+                    "coinbase transactions can't be in the mempool".into(),
+                ),
+            })?;
+        }
+
+        // If this transaction gets mined, the earliest it can be is in the block after our tip
+        let tip = self.get_validation_index()?;
+        let height = tip + 1;
+
+        // Like Core, we always use the median time past of our tip for locktimes, regardless
+        // of whether BIP 113 is active
+        let median_time_past = self.get_median_time_past(tip)?;
+        Consensus::check_final(transaction, height, median_time_past)?;
+
+        if height >= self.chain_params().csv_activation_height {
+            for input in transaction.input.iter() {
+                if !input.sequence.is_time_locked() {
+                    continue;
+                }
+
+                // BIP 68 defines a UTXO's creation time as the median time past of the block
+                // before the one confirming it
+                if let Some(utxo) = inputs.get_mut(&input.previous_output) {
+                    let before_creation = utxo.creation_height.saturating_sub(1);
+                    utxo.creation_time = self.get_median_time_past(before_creation)?;
+                }
+            }
+
+            Consensus::check_sequence_locks(transaction, &inputs, height, median_time_past)?;
+        }
+
+        // There's no block yet, so no exception can apply to it
+        #[cfg(feature = "bitcoinkernel")]
+        let flags = self
+            .chain_params()
+            .get_validation_flags(height, BlockHash::all_zeros());
+        #[cfg(not(feature = "bitcoinkernel"))]
+        let flags = 0;
+
+        // Assume-valid only covers blocks buried under the assumed one, never the mempool
+        let (in_value, out_value) =
+            Consensus::verify_transaction(transaction, &mut inputs, height, true, flags)?;
+
+        // `verify_transaction` makes sure we don't spend more than we have
+        Ok(in_value - out_value)
+    }
+
     fn get_block_locator_for_tip(&self, tip: BlockHash) -> Result<Vec<BlockHash>, BlockchainError> {
         let mut hashes = Vec::new();
         let height = self.get_disk_block_header(&tip)?.try_height()?;
@@ -1461,7 +1611,7 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
             .collect();
 
         let inner = PartialChainStateInner {
-            error: None,
+            invalid_block: None,
             blocks,
             consensus: Consensus {
                 parameters: self.chain_params(),
@@ -1514,7 +1664,7 @@ macro_rules! write_lock {
     };
 }
 
-#[cfg(all(test, feature = "flat-chainstore"))]
+#[cfg(test)]
 mod test {
     use std::format;
     use std::fs::File;
@@ -1545,29 +1695,90 @@ mod test {
     use crate::extensions::WorkExt;
     use crate::prelude::HashMap;
     use crate::pruned_utreexo::consensus::Consensus;
+    use crate::pruned_utreexo::memory_chain_store::MemoryChainStore;
     use crate::pruned_utreexo::utxo_data::UtxoData;
     use crate::AssumeValidArg;
     use crate::BlockchainError;
     use crate::ChainStore;
+    #[cfg(feature = "flat-chainstore")]
     use crate::FlatChainStore;
-    use crate::FlatChainStoreConfig;
 
-    fn setup_test_chain(
-        network: Network,
-        assume_valid_arg: AssumeValidArg,
-    ) -> ChainState<FlatChainStore> {
-        let test_id = rand::random::<u64>();
-        let config = crate::FlatChainStoreConfig {
-            block_index_size: Some(32_768),
-            headers_file_size: Some(32_768),
-            fork_file_size: Some(10_000), // Will be rounded up to 16,384
-            cache_size: Some(10),
-            file_permission: Some(0o660),
-            path: format!("./tmp-db/{test_id}/").into(),
+    /// A chainstore we can run these tests on
+    trait TestStore: ChainStore + Sized {
+        /// Creates a new, empty store
+        fn new_test_store() -> Self;
+    }
+
+    #[cfg(feature = "flat-chainstore")]
+    impl TestStore for FlatChainStore {
+        fn new_test_store() -> Self {
+            let test_id = rand::random::<u64>();
+            let config = crate::FlatChainStoreConfig {
+                block_index_size: Some(32_768),
+                headers_file_size: Some(32_768),
+                fork_file_size: Some(10_000), // Will be rounded up to 16,384
+                cache_size: Some(10),
+                file_permission: Some(0o660),
+                roots_retention: None,
+                path: format!("./tmp-db/{test_id}/").into(),
+            };
+
+            FlatChainStore::new(config).unwrap()
+        }
+    }
+
+    impl TestStore for MemoryChainStore {
+        fn new_test_store() -> Self {
+            MemoryChainStore::new()
+        }
+    }
+
+    /// Runs each of these generic tests against every store we have
+    macro_rules! test_with_each_store {
+        ($($(#[$attr:meta])* $test:ident),* $(,)?) => {
+            mod memory {
+                use super::MemoryChainStore;
+
+                $(
+                    #[test]
+                    $(#[$attr])*
+                    fn $test() {
+                        super::$test::<MemoryChainStore>()
+                    }
+                )*
+            }
+
+            #[cfg(feature = "flat-chainstore")]
+            mod flat {
+                use super::FlatChainStore;
+
+                $(
+                    #[test]
+                    $(#[$attr])*
+                    fn $test() {
+                        super::$test::<FlatChainStore>()
+                    }
+                )*
+            }
         };
+    }
 
-        let chainstore = FlatChainStore::new(config).unwrap();
-        ChainState::open(chainstore, network, assume_valid_arg).unwrap()
+    test_with_each_store!(
+        #[cfg_attr(debug_assertions, ignore = "this test is very slow in debug mode")]
+        test_validate_many_inputs_block,
+        test_validate_full_block,
+        accept_mainnet_headers,
+        accept_first_signet_headers,
+        test_reorg,
+        test_chainstate_functions,
+        test_calculate_chain_work,
+    );
+
+    fn setup_test_chain<S: TestStore>(
+        network: Network,
+        assume_valid_arg: AssumeValidArg,
+    ) -> ChainState<S> {
+        ChainState::open(S::new_test_store(), network, assume_valid_arg).unwrap()
     }
 
     fn decode_block_and_inputs(
@@ -1595,9 +1806,7 @@ mod test {
         (block, inputs)
     }
 
-    #[test]
-    #[cfg_attr(debug_assertions, ignore = "this test is very slow in debug mode")]
-    fn test_validate_many_inputs_block() {
+    fn test_validate_many_inputs_block<S: TestStore>() {
         let block_file = File::open("./testdata/block_367891/raw.zst").unwrap();
         let stxos_file = File::open("./testdata/block_367891/spent_utxos.zst").unwrap();
         let (block, inputs) = decode_block_and_inputs(block_file, stxos_file);
@@ -1608,14 +1817,13 @@ mod test {
         );
 
         // Check whether the block validation passes or not
-        let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
+        let chain = setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Disabled);
         chain
             .validate_block_no_acc(&block, 367891, inputs)
             .expect("Block must be valid");
     }
 
-    #[test]
-    fn test_validate_full_block() {
+    fn test_validate_full_block<S: TestStore>() {
         let block_file = File::open("./testdata/block_866342/raw.zst").unwrap();
         let stxos_file = File::open("./testdata/block_866342/spent_utxos.zst").unwrap();
         let (block, inputs) = decode_block_and_inputs(block_file, stxos_file);
@@ -1626,21 +1834,20 @@ mod test {
         );
 
         // Check whether the block validation passes or not
-        let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
+        let chain = setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Disabled);
         chain
             .validate_block_no_acc(&block, 866342, inputs)
             .expect("Block must be valid");
     }
 
-    #[test]
-    fn accept_mainnet_headers() {
+    fn accept_mainnet_headers<S: TestStore>() {
         // Accepts the first 10,237 mainnet headers
         let file = include_bytes!("../../testdata/headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
         let mut buffer = uncompressed.as_slice();
 
         let mut headers = Vec::new();
-        let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Hardcoded);
+        let chain = setup_test_chain::<S>(Network::Bitcoin, AssumeValidArg::Hardcoded);
 
         while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
             chain.accept_header(header).unwrap();
@@ -1695,14 +1902,13 @@ mod test {
         }
     }
 
-    #[test]
-    fn accept_first_signet_headers() {
+    fn accept_first_signet_headers<S: TestStore>() {
         // Accepts the first 2016 signet headers
         let file = include_bytes!("../../testdata/signet_headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
         let mut buffer = uncompressed.as_slice();
 
-        let chain = setup_test_chain(Network::Signet, AssumeValidArg::Hardcoded);
+        let chain = setup_test_chain::<S>(Network::Signet, AssumeValidArg::Hardcoded);
         while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
             chain.accept_header(header).unwrap();
         }
@@ -1722,9 +1928,8 @@ mod test {
         assert_eq!(0x1e012fa7, next_target.to_compact_lossy().to_consensus());
     }
 
-    #[test]
-    fn test_reorg() {
-        let chain = setup_test_chain(Network::Regtest, AssumeValidArg::Hardcoded);
+    fn test_reorg<S: TestStore>() {
+        let chain = setup_test_chain::<S>(Network::Regtest, AssumeValidArg::Hardcoded);
         let json_blocks = include_str!("../../testdata/test_reorg.json");
         let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
         let mut fork_acc = Stump::default();
@@ -1784,6 +1989,14 @@ mod test {
         );
 
         assert_eq!(chain.get_best_block().unwrap(), expected);
+        assert_eq!(
+            chain.get_chain_tips().unwrap(),
+            vec![
+                expected.1,
+                bhash!("6e9c49a19038f7db8d13f6c2e70566385536ea11975528b557799e08a014e784"),
+            ],
+            "The chain we left is kept as the only alternative tip",
+        );
         assert_eq!(
             chain.acc(),
             fork_acc,
@@ -1814,6 +2027,81 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "flat-chainstore")]
+    fn test_reorg_with_pruned_roots() {
+        let test_id = rand::random::<u64>();
+        let config = crate::FlatChainStoreConfig {
+            block_index_size: Some(32_768),
+            headers_file_size: Some(32_768),
+            fork_file_size: Some(10_000),
+            cache_size: Some(10),
+            file_permission: Some(0o660),
+            // We'll only have the roots for heights 4, 8, 9 and 10 when the reorg happens
+            roots_retention: Some(crate::RootsRetention {
+                keep_recent: 2,
+                checkpoint_interval: 4,
+            }),
+            path: format!("./tmp-db/{test_id}/").into(),
+        };
+
+        let chainstore = FlatChainStore::new(config).unwrap();
+        let chain =
+            ChainState::open(chainstore, Network::Regtest, AssumeValidArg::Hardcoded).unwrap();
+
+        let json_blocks = include_str!("../../testdata/test_reorg.json");
+        let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
+        let parse_blocks = |blocks: &[&str]| {
+            blocks
+                .iter()
+                .map(|s| deserialize_hex(s).unwrap())
+                .collect::<Vec<Block>>()
+        };
+
+        let short_chain = parse_blocks(&blocks[0]);
+        let long_chain = parse_blocks(&blocks[1]);
+        let mut checkpoint_acc = Stump::default();
+
+        for (i, block) in short_chain.iter().enumerate() {
+            chain.accept_header(block.header).unwrap();
+            chain
+                .connect_block(block, Proof::default(), HashMap::new(), Vec::new())
+                .unwrap();
+
+            if i + 1 == 4 {
+                checkpoint_acc = chain.acc();
+            }
+        }
+
+        // The fork point is at height 5, but we've pruned its roots, so we go back to height 4
+        for fork_block in long_chain.iter() {
+            chain.accept_header(fork_block.header).unwrap();
+        }
+
+        assert_eq!(chain.get_height().unwrap(), 16);
+        assert_eq!(chain.get_validation_index().unwrap(), 4);
+        assert_eq!(chain.acc(), checkpoint_acc);
+
+        // Block 5 must be validated again before the fork chain
+        chain
+            .connect_block(
+                &short_chain[4],
+                Proof::default(),
+                HashMap::new(),
+                Vec::new(),
+            )
+            .unwrap();
+
+        for fork in long_chain {
+            chain
+                .connect_block(&fork, Proof::default(), HashMap::new(), Vec::new())
+                .unwrap();
+        }
+
+        assert_eq!(chain.get_validation_index().unwrap(), 16);
+    }
+
+    #[test]
+    #[cfg(feature = "flat-chainstore")]
     fn open_resumes_existing_chain_state() {
         let file = include_bytes!("../../testdata/signet_headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
@@ -1828,6 +2116,7 @@ mod test {
             fork_file_size: Some(10_000),
             cache_size: Some(10),
             file_permission: Some(0o660),
+            roots_retention: None,
             path: path.clone().into(),
         };
         let chain = ChainState::open(
@@ -1859,6 +2148,7 @@ mod test {
             fork_file_size: Some(10_000),
             cache_size: Some(10),
             file_permission: Some(0o660),
+            roots_retention: None,
             path: path.into(),
         };
         let chain2 = ChainState::open(
@@ -1870,13 +2160,12 @@ mod test {
         assert_eq!(chain2.get_height().unwrap(), height);
     }
 
-    #[test]
-    fn test_chainstate_functions() {
+    fn test_chainstate_functions<S: TestStore>() {
         let file = include_bytes!("../../testdata/signet_headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
         let mut buffer = uncompressed.as_slice();
 
-        let chain = setup_test_chain(Network::Signet, AssumeValidArg::Hardcoded);
+        let chain = setup_test_chain::<S>(Network::Signet, AssumeValidArg::Hardcoded);
         let mut headers: Vec<BlockHeader> = Vec::new();
         while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
             headers.push(header);
@@ -1919,12 +2208,8 @@ mod test {
         );
     }
 
-    #[test]
-    fn test_calculate_chain_work() {
-        let mut chainstore = FlatChainStore::new(FlatChainStoreConfig::new(
-            "../../testdata/signet_headers.zst",
-        ))
-        .unwrap();
+    fn test_calculate_chain_work<S: TestStore>() {
+        let mut chainstore = S::new_test_store();
 
         let mut headers = vec![];
         let genesis_header: BlockHeader = deserialize_hex("0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c").unwrap();
@@ -1964,7 +2249,11 @@ mod test {
             })
             .unwrap();
 
-        let chainstate = ChainState::new(chainstore, Network::Bitcoin, AssumeValidArg::Disabled);
+        let chainstate = ChainState::new(
+            chainstore,
+            Network::Bitcoin.into(),
+            AssumeValidArg::Disabled,
+        );
         let header = headers[headers.len() - 1];
         let fork = headers[headers.len() / 2];
 
@@ -1984,4 +2273,30 @@ mod test {
         assert_eq!(fork_work, work);
         assert_eq!(work, expected_work);
     }
+
+    #[test]
+    #[cfg(not(feature = "bitcoinkernel"))]
+    fn test_validate_transaction_needs_bitcoinkernel() {
+        let chain =
+            setup_test_chain::<MemoryChainStore>(Network::Regtest, AssumeValidArg::Hardcoded);
+        let coinbase = &genesis_block(Network::Regtest).txdata[0];
+
+        // Spend the genesis output, we should refuse it before even looking at the proof
+        let transaction = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: OutPoint::new(coinbase.compute_txid(), 0),
+                ..Default::default()
+            }],
+            output: coinbase.output.clone(),
+        };
+
+        let result =
+            chain.validate_transaction(&transaction, Proof::default(), HashMap::new(), vec![]);
+        assert!(matches!(
+            result,
+            Err(BlockchainError::ScriptValidationUnavailable)
+        ));
+    }
 }
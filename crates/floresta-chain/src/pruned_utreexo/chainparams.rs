@@ -6,6 +6,7 @@
 //! It includes:
 //! - Network-specific parameters like block reward halving intervals and maturity periods
 //! - DNS seeds for peer discovery
+//! - Custom signets, defined by their challenge script
 //! - Assumable validation states for Utreexo
 //! - Block verification flag exceptions
 //!
@@ -18,16 +19,21 @@ use core::ffi::c_uint;
 
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::constants::SUBSIDY_HALVING_INTERVAL;
+use bitcoin::p2p::Magic;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::params::Params;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Network;
+use bitcoin::ScriptBuf;
 use floresta_common::acchashes;
 use floresta_common::bhash;
 use floresta_common::service_flags;
 use rustreexo::node_hash::BitcoinNodeHash;
 
+use super::chainparams_builder::ChainParamsBuilder;
+use super::chainparams_builder::ChainParamsBuilderError;
+use super::signet::default_signet_challenge;
 use crate::prelude::*;
 use crate::AssumeValidArg;
 
@@ -38,6 +44,9 @@ pub enum SubsidyHalvingInterval {
 
     /// Regtest: 150 blocks.
     Regtest,
+
+    /// Custom chains may use any interval.
+    Custom(u32),
 }
 
 impl SubsidyHalvingInterval {
@@ -45,6 +54,7 @@ impl SubsidyHalvingInterval {
         match self {
             Self::Bitcoin => SUBSIDY_HALVING_INTERVAL,
             Self::Regtest => 150,
+            Self::Custom(interval) => interval,
         }
     }
 }
@@ -74,6 +84,10 @@ pub struct ChainParams {
     /// The height at which csv(CHECK_SEQUENCE_VERIFY) is activated
     pub csv_activation_height: u32,
 
+    /// The height at which taproot is activated. Like Bitcoin Core, we enforce taproot on all
+    /// blocks of the default chains, except for a historical exception on mainnet.
+    pub taproot_activation_height: u32,
+
     /// A list of exceptions to the rules, where the key is the block hash and the value is the
     /// verification flags
     pub exceptions: HashMap<BlockHash, c_uint>,
@@ -83,6 +97,24 @@ pub struct ChainParams {
 
     /// Whether we should enforce BIP-094 "Testnet 4" rules
     pub enforce_bip94: bool,
+
+    /// The magic bytes that start every p2p message on this chain
+    pub magic: Magic,
+
+    /// For signets, the challenge every block must satisfy, as defined by BIP-325
+    pub signet_challenge: Option<ScriptBuf>,
+
+    /// The DNS seeds we may ask for peers on this chain
+    pub dns_seeds: Vec<DnsSeed>,
+
+    /// The port peers on this chain usually listen on
+    pub port: u16,
+
+    /// The block we assume valid if asked to use our hardcoded value, see [`AssumeValidArg`]
+    pub assume_valid_hash: Option<BlockHash>,
+
+    /// The accumulator we may start from, if asked to assume utreexo
+    pub assume_utreexo: Option<AssumeUtreexoValue>,
 }
 
 /// A dns seed is a authoritative DNS server that returns the IP addresses of nodes that are
@@ -93,12 +125,13 @@ pub struct ChainParams {
 ///
 /// Some seeds allow filtering by service flags, so we may use this to find peers that are
 /// likely to be running Utreexo, for example.
+#[derive(Debug, Clone)]
 pub struct DnsSeed {
     /// The network this peer supports (e.g, mainnet, testnet, etc)
     pub network: Network,
 
     /// The domain name of the seed
-    pub seed: &'static str,
+    pub seed: String,
 
     /// Useful filters we can use to find relevant peers
     pub filters: ServiceFlags,
@@ -107,10 +140,10 @@ pub struct DnsSeed {
 /// This functionality is used to create a new DNS seed with possible filters.
 impl DnsSeed {
     /// Create a new DNS seed
-    pub fn new(network: Network, seed: &'static str, filters: ServiceFlags) -> Self {
+    pub fn new(network: Network, seed: &str, filters: ServiceFlags) -> Self {
         DnsSeed {
             network,
-            seed,
+            seed: seed.to_string(),
             filters,
         }
     }
@@ -120,7 +153,7 @@ impl DnsSeed {
 /// start running from there. You may use this to make your node start faster, but you
 /// should be sure that the provided state is valid. You may or not verify the state,
 /// by downloading all blocks on background, and then verifying the final Utreexo state.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AssumeUtreexoValue {
     /// The latest block assumed to be valid. This acc is the roots at this block
     pub block_hash: BlockHash,
@@ -136,6 +169,44 @@ pub struct AssumeUtreexoValue {
 }
 
 impl ChainParams {
+    /// Creates the parameters for a custom signet, with the given challenge and DNS seeds.
+    ///
+    /// All signets share the same genesis block and consensus rules, they only differ by the
+    /// challenge their blocks must satisfy. The network magic is derived from the challenge, so
+    /// we only talk to peers on the same signet.
+    ///
+    /// This fails without the `bitcoinkernel` feature, since we couldn't check block solutions.
+    pub fn custom_signet(
+        challenge: ScriptBuf,
+        dns_seeds: Vec<String>,
+    ) -> Result<Self, ChainParamsBuilderError> {
+        let dns_seeds = dns_seeds
+            .iter()
+            .map(|seed| DnsSeed::new(Network::Signet, seed, ServiceFlags::NONE))
+            .collect();
+
+        ChainParamsBuilder::new(Network::Signet)
+            .with_signet_challenge(challenge)
+            .with_dns_seeds(dns_seeds)
+            .build()
+    }
+
+    /// Whether these parameters are for a chain other than the default one for [`Self::network`],
+    /// like a custom signet. Our hardcoded peer addresses are only valid for the default chains.
+    pub fn is_custom(&self) -> bool {
+        self.magic != self.network.magic()
+            || self.genesis.block_hash() != genesis_block(Params::new(self.network)).block_hash()
+    }
+
+    /// Returns the assume-valid checkpoint for this chain. With [`AssumeValidArg::Hardcoded`],
+    /// that's [`Self::assume_valid_hash`].
+    pub fn assume_valid(&self, arg: AssumeValidArg) -> Option<BlockHash> {
+        match arg {
+            AssumeValidArg::Hardcoded => self.assume_valid_hash,
+            arg => Self::get_assume_valid(self.network, arg),
+        }
+    }
+
     /// This method is called when Assume Utreexo is set to true. It means that the user will accept the hardcoded utreexo state for the specified block, if it is found in the best chain. We can then sync rapidly from this state.
     pub fn get_assume_utreexo(network: Network) -> AssumeUtreexoValue {
         let genesis = genesis_block(Params::new(network));
@@ -266,9 +337,11 @@ impl ChainParams {
         // mainnet.
         // For simplicity, always leave P2SH+WITNESS+TAPROOT on except for the two
         // violating blocks.
-        let mut flags = bitcoinkernel::VERIFY_P2SH
-            | bitcoinkernel::VERIFY_WITNESS
-            | bitcoinkernel::VERIFY_TAPROOT;
+        let mut flags = bitcoinkernel::VERIFY_P2SH | bitcoinkernel::VERIFY_WITNESS;
+
+        if height >= self.taproot_activation_height {
+            flags |= bitcoinkernel::VERIFY_TAPROOT;
+        }
 
         if height >= self.params.bip65_height {
             flags |= bitcoinkernel::VERIFY_CHECKLOCKTIMEVERIFY;
@@ -328,6 +401,8 @@ impl From<Network> for ChainParams {
     fn from(network: Network) -> Self {
         let genesis = genesis_block(Params::new(network));
         let exceptions = get_exceptions();
+        let port = get_default_port(network);
+        let assume_valid_hash = Self::get_assume_valid(network, AssumeValidArg::Hardcoded);
 
         match network {
             Network::Bitcoin => ChainParams {
@@ -340,6 +415,13 @@ impl From<Network> for ChainParams {
                 segwit_activation_height: 481_824,
                 csv_activation_height: 419_328,
                 exceptions,
+                magic: network.magic(),
+                signet_challenge: None,
+                dns_seeds: get_chain_dns_seeds(network),
+                port,
+                taproot_activation_height: 0,
+                assume_valid_hash,
+                assume_utreexo: Some(Self::get_assume_utreexo(network)),
                 enforce_bip94: false,
             },
             Network::Testnet => ChainParams {
@@ -352,6 +434,13 @@ impl From<Network> for ChainParams {
                 segwit_activation_height: 834_624,
                 csv_activation_height: 770_112,
                 exceptions,
+                magic: network.magic(),
+                signet_challenge: None,
+                dns_seeds: get_chain_dns_seeds(network),
+                port,
+                taproot_activation_height: 0,
+                assume_valid_hash,
+                assume_utreexo: Some(Self::get_assume_utreexo(network)),
                 enforce_bip94: false,
             },
             Network::Testnet4 => ChainParams {
@@ -364,6 +453,13 @@ impl From<Network> for ChainParams {
                 segwit_activation_height: 1,
                 csv_activation_height: 1,
                 exceptions,
+                magic: network.magic(),
+                signet_challenge: None,
+                dns_seeds: get_chain_dns_seeds(network),
+                port,
+                taproot_activation_height: 0,
+                assume_valid_hash,
+                assume_utreexo: Some(Self::get_assume_utreexo(network)),
                 enforce_bip94: true,
             },
             Network::Signet => ChainParams {
@@ -376,6 +472,13 @@ impl From<Network> for ChainParams {
                 csv_activation_height: 1,
                 segwit_activation_height: 1,
                 exceptions,
+                magic: network.magic(),
+                signet_challenge: Some(default_signet_challenge()),
+                dns_seeds: get_chain_dns_seeds(network),
+                port,
+                taproot_activation_height: 0,
+                assume_valid_hash,
+                assume_utreexo: Some(Self::get_assume_utreexo(network)),
                 enforce_bip94: false,
             },
             Network::Regtest => ChainParams {
@@ -388,12 +491,30 @@ impl From<Network> for ChainParams {
                 csv_activation_height: 0,
                 segwit_activation_height: 0,
                 exceptions,
+                magic: network.magic(),
+                signet_challenge: None,
+                dns_seeds: get_chain_dns_seeds(network),
+                port,
+                taproot_activation_height: 0,
+                assume_valid_hash,
+                assume_utreexo: Some(Self::get_assume_utreexo(network)),
                 enforce_bip94: false,
             },
         }
     }
 }
 
+/// Returns the port peers on `network` usually listen on.
+pub fn get_default_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8333,
+        Network::Signet => 38333,
+        Network::Testnet => 18333,
+        Network::Testnet4 => 48333,
+        Network::Regtest => 18444,
+    }
+}
+
 /// Get a list of [`DnsSeed`]s for a given [`Network`].
 ///
 /// Some DNS seeds allow requesting addresses using a [`ServiceFlags`] filter.
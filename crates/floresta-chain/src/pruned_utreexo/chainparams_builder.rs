@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! This module provides a builder for [`ChainParams`], used to run on chains other than the
+//! default ones, like private test networks.
+//!
+//! The builder starts from the parameters of one of the default networks, and lets you change:
+//! - The genesis block, network magic and default port
+//! - Proof-of-work limit and retargeting rules
+//! - Activation heights for BIP34, BIP65, BIP66, CSV, segwit and taproot
+//! - Subsidy halving interval and coinbase maturity
+//! - Signet challenge and DNS seeds
+//! - Assume-valid and assume-utreexo values
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::p2p::Magic;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use bitcoin::Network;
+use bitcoin::ScriptBuf;
+use bitcoin::Target;
+
+use super::chainparams::AssumeUtreexoValue;
+use super::chainparams::ChainParams;
+use super::chainparams::DnsSeed;
+use super::chainparams::SubsidyHalvingInterval;
+#[cfg(not(feature = "bitcoinkernel"))]
+use super::signet::default_signet_challenge;
+use super::signet::signet_magic;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents errors that can occur while building a [`ChainParams`].
+pub enum ChainParamsBuilderError {
+    /// The genesis header doesn't commit to the genesis transactions.
+    BadGenesisMerkleRoot,
+
+    /// The genesis header doesn't meet its own target, or that target is above the PoW limit.
+    BadGenesisPow,
+
+    /// The retarget timespan isn't a positive multiple of the block spacing.
+    BadRetargetInterval,
+
+    /// The subsidy halving interval is zero.
+    BadHalvingInterval,
+
+    /// A custom signet challenge was given, but we can't run scripts without the
+    /// `bitcoinkernel` feature, so we couldn't check its block solutions.
+    SignetChallengeUnsupported,
+}
+
+impl Display for ChainParamsBuilderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainParamsBuilderError::BadGenesisMerkleRoot => {
+                write!(f, "The genesis merkle root doesn't match its transactions")
+            }
+            ChainParamsBuilderError::BadGenesisPow => {
+                write!(f, "The genesis block doesn't have a valid proof-of-work")
+            }
+            ChainParamsBuilderError::BadRetargetInterval => {
+                write!(
+                    f,
+                    "The retarget timespan must be a positive multiple of the block spacing"
+                )
+            }
+            ChainParamsBuilderError::BadHalvingInterval => {
+                write!(f, "The subsidy halving interval can't be zero")
+            }
+            ChainParamsBuilderError::SignetChallengeUnsupported => {
+                write!(
+                    f,
+                    "Custom signets need the bitcoinkernel feature to check block solutions"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for ChainParamsBuilderError {}
+
+#[derive(Clone, Debug)]
+/// A builder for configuring and creating a [`ChainParams`].
+///
+/// Every parameter not set here is taken from the network passed to [`Self::new`]. Call
+/// `.build()` to consume the builder and produce the [`ChainParams`].
+pub struct ChainParamsBuilder {
+    /// The parameters being built.
+    params: ChainParams,
+
+    /// The block hash that is assumed to be valid, if set.
+    assume_valid: Option<BlockHash>,
+
+    /// The accumulator we may start from, if set.
+    assume_utreexo: Option<AssumeUtreexoValue>,
+}
+
+impl ChainParamsBuilder {
+    /// Creates a new builder, starting from the parameters of `network`.
+    pub fn new(network: Network) -> Self {
+        ChainParamsBuilder {
+            params: ChainParams::from(network),
+            assume_valid: None,
+            assume_utreexo: None,
+        }
+    }
+
+    /// Builds the chain parameters. Returns an error if the genesis block is invalid, or if the
+    /// retarget or halving intervals don't make sense.
+    ///
+    /// The hardcoded assume-valid and assume-utreexo values of the base network are only kept
+    /// if we are still on the same chain, i.e. the genesis block and magic didn't change.
+    pub fn build(self) -> Result<ChainParams, ChainParamsBuilderError> {
+        let mut params = self.params;
+
+        let genesis = &params.genesis.header;
+        if !params.genesis.check_merkle_root() {
+            return Err(ChainParamsBuilderError::BadGenesisMerkleRoot);
+        }
+
+        if genesis.target() > params.params.max_attainable_target
+            || genesis.validate_pow(genesis.target()).is_err()
+        {
+            return Err(ChainParamsBuilderError::BadGenesisPow);
+        }
+
+        let spacing = params.params.pow_target_spacing;
+        let timespan = params.params.pow_target_timespan;
+        if spacing == 0 || timespan < spacing || timespan % spacing != 0 {
+            return Err(ChainParamsBuilderError::BadRetargetInterval);
+        }
+
+        if params.subsidy_halving_interval.get() == 0 {
+            return Err(ChainParamsBuilderError::BadHalvingInterval);
+        }
+
+        // Without a script interpreter, we can only check that signet solutions are
+        // well-formed. Anyone could sign blocks for a custom signet, so we refuse it.
+        #[cfg(not(feature = "bitcoinkernel"))]
+        if params
+            .signet_challenge
+            .as_ref()
+            .is_some_and(|challenge| *challenge != default_signet_challenge())
+        {
+            return Err(ChainParamsBuilderError::SignetChallengeUnsupported);
+        }
+
+        match params.is_custom() {
+            true => {
+                params.assume_valid_hash = self.assume_valid;
+                params.assume_utreexo = self.assume_utreexo;
+            }
+            false => {
+                params.assume_valid_hash = self.assume_valid.or(params.assume_valid_hash);
+                params.assume_utreexo = self.assume_utreexo.or(params.assume_utreexo);
+            }
+        }
+
+        Ok(params)
+    }
+
+    /// Sets the genesis block. Its header must meet its own target, and commit to its
+    /// transactions.
+    pub fn with_genesis(mut self, genesis: Block) -> Self {
+        self.params.genesis = genesis;
+        self
+    }
+
+    /// Sets the magic bytes that start every p2p message on this chain.
+    pub fn with_magic(mut self, magic: Magic) -> Self {
+        self.params.magic = magic;
+        self
+    }
+
+    /// Sets the port peers on this chain usually listen on.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.params.port = port;
+        self
+    }
+
+    /// Sets the easiest target a block may have. Min-difficulty blocks use this target.
+    pub fn with_pow_limit(mut self, pow_limit: Target) -> Self {
+        self.params.params.max_attainable_target = pow_limit;
+        self
+    }
+
+    /// Sets the expected time between blocks, in seconds.
+    pub fn with_pow_target_spacing(mut self, spacing: u64) -> Self {
+        self.params.params.pow_target_spacing = spacing;
+        self
+    }
+
+    /// Sets the expected time between retargets, in seconds. The retarget interval, in blocks,
+    /// is this timespan divided by the block spacing.
+    pub fn with_pow_target_timespan(mut self, timespan: u64) -> Self {
+        self.params.params.pow_target_timespan = timespan;
+        self.params.pow_target_timespan = timespan;
+        self
+    }
+
+    /// Whether the difficulty never changes, like in regtest.
+    pub fn with_no_pow_retargeting(mut self, no_retargeting: bool) -> Self {
+        self.params.params.no_pow_retargeting = no_retargeting;
+        self
+    }
+
+    /// Whether a block may use the PoW limit as target, if it comes twice the block spacing
+    /// after its parent. This is a testnet rule.
+    pub fn with_min_difficulty_blocks(mut self, allow: bool) -> Self {
+        self.params.params.allow_min_difficulty_blocks = allow;
+        self
+    }
+
+    /// Whether we should enforce the BIP-094 "Testnet 4" rules.
+    pub fn with_bip94(mut self, enforce: bool) -> Self {
+        self.params.enforce_bip94 = enforce;
+        self
+    }
+
+    /// Sets after how many blocks the block reward halves.
+    pub fn with_subsidy_halving_interval(mut self, interval: u32) -> Self {
+        self.params.subsidy_halving_interval = SubsidyHalvingInterval::Custom(interval);
+        self
+    }
+
+    /// Sets how many blocks we wait before a coinbase output can be spent.
+    pub fn with_coinbase_maturity(mut self, maturity: u32) -> Self {
+        self.params.coinbase_maturity = maturity;
+        self
+    }
+
+    /// Sets the height at which blocks must commit to their height in the coinbase (BIP-34).
+    pub fn with_bip34_height(mut self, height: u32) -> Self {
+        self.params.params.bip34_height = height;
+        self
+    }
+
+    /// Sets the height at which `OP_CHECKLOCKTIMEVERIFY` is activated (BIP-65).
+    pub fn with_bip65_height(mut self, height: u32) -> Self {
+        self.params.params.bip65_height = height;
+        self
+    }
+
+    /// Sets the height at which strict DER signatures are enforced (BIP-66).
+    pub fn with_bip66_height(mut self, height: u32) -> Self {
+        self.params.params.bip66_height = height;
+        self
+    }
+
+    /// Sets the height at which `OP_CHECKSEQUENCEVERIFY` is activated.
+    pub fn with_csv_height(mut self, height: u32) -> Self {
+        self.params.csv_activation_height = height;
+        self
+    }
+
+    /// Sets the height at which segwit is activated.
+    pub fn with_segwit_height(mut self, height: u32) -> Self {
+        self.params.segwit_activation_height = height;
+        self
+    }
+
+    /// Sets the height at which taproot is activated.
+    pub fn with_taproot_height(mut self, height: u32) -> Self {
+        self.params.taproot_activation_height = height;
+        self
+    }
+
+    /// Makes this chain a signet with the given challenge. This also sets the magic, as derived
+    /// from the challenge, so call [`Self::with_magic`] after this one to override it.
+    ///
+    /// Checking block solutions for a custom challenge needs the `bitcoinkernel` feature,
+    /// otherwise [`Self::build`] fails.
+    pub fn with_signet_challenge(mut self, challenge: ScriptBuf) -> Self {
+        self.params.magic = signet_magic(&challenge);
+        self.params.signet_challenge = Some(challenge);
+        self
+    }
+
+    /// Sets the DNS seeds we may ask for peers.
+    pub fn with_dns_seeds(mut self, dns_seeds: Vec<DnsSeed>) -> Self {
+        self.params.dns_seeds = dns_seeds;
+        self
+    }
+
+    /// Sets the block we assume valid, if asked to use our hardcoded value.
+    pub fn with_assume_valid(mut self, hash: BlockHash) -> Self {
+        self.assume_valid = Some(hash);
+        self
+    }
+
+    /// Sets the accumulator we may start from, if asked to assume utreexo.
+    pub fn with_assume_utreexo(mut self, value: AssumeUtreexoValue) -> Self {
+        self.assume_utreexo = Some(value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::Magic;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::Network;
+    use bitcoin::Target;
+    use bitcoin::TxMerkleNode;
+
+    use super::ChainParamsBuilder;
+    use super::ChainParamsBuilderError;
+    use crate::pruned_utreexo::consensus::Consensus;
+    use crate::AssumeValidArg;
+    use crate::ChainParams;
+
+    #[test]
+    fn test_default_params() {
+        // Without changes, we get the same parameters as the base network
+        let params = ChainParamsBuilder::new(Network::Signet).build().unwrap();
+        let signet = ChainParams::from(Network::Signet);
+
+        assert!(!params.is_custom());
+        assert_eq!(params.magic, signet.magic);
+        assert_eq!(params.assume_valid_hash, signet.assume_valid_hash);
+        assert_eq!(
+            params.assume_valid(AssumeValidArg::Hardcoded),
+            signet.assume_valid(AssumeValidArg::Hardcoded)
+        );
+    }
+
+    #[test]
+    fn test_custom_params() {
+        let magic = Magic::from_bytes([0xca, 0xfe, 0xba, 0xbe]);
+        let assume_valid = BlockHash::from_byte_array([1; 32]);
+
+        let params = ChainParamsBuilder::new(Network::Regtest)
+            .with_magic(magic)
+            .with_port(12345)
+            .with_subsidy_halving_interval(1_000)
+            .with_pow_target_spacing(60)
+            .with_pow_target_timespan(60 * 100)
+            .with_no_pow_retargeting(false)
+            .with_bip34_height(10)
+            .with_taproot_height(20)
+            .with_assume_valid(assume_valid)
+            .build()
+            .unwrap();
+
+        assert!(params.is_custom());
+        assert_eq!(params.magic, magic);
+        assert_eq!(params.port, 12345);
+        assert_eq!(params.params.bip34_height, 10);
+        assert_eq!(params.taproot_activation_height, 20);
+        assert_eq!(params.params.difficulty_adjustment_interval(), 100);
+        assert_eq!(
+            params.assume_valid(AssumeValidArg::Hardcoded),
+            Some(assume_valid)
+        );
+
+        // Regtest's hardcoded accumulator is for a different chain
+        assert!(params.assume_utreexo.is_none());
+
+        let consensus = Consensus { parameters: params };
+        assert_eq!(consensus.get_subsidy(999).to_sat(), 50 * 100_000_000);
+        assert_eq!(consensus.get_subsidy(1_000).to_sat(), 25 * 100_000_000);
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        // Spacing doesn't divide the timespan
+        let err = ChainParamsBuilder::new(Network::Regtest)
+            .with_pow_target_spacing(600)
+            .with_pow_target_timespan(1_000)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChainParamsBuilderError::BadRetargetInterval);
+
+        let err = ChainParamsBuilder::new(Network::Regtest)
+            .with_subsidy_halving_interval(0)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChainParamsBuilderError::BadHalvingInterval);
+
+        // Regtest's genesis doesn't meet mainnet's PoW limit
+        let pow_limit = Target::from_compact(CompactTarget::from_consensus(0x1d00ffff));
+        let err = ChainParamsBuilder::new(Network::Regtest)
+            .with_pow_limit(pow_limit)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChainParamsBuilderError::BadGenesisPow);
+
+        // A genesis header that doesn't commit to its transactions
+        let mut genesis = ChainParams::from(Network::Regtest).genesis;
+        genesis.header.merkle_root = TxMerkleNode::all_zeros();
+        let err = ChainParamsBuilder::new(Network::Regtest)
+            .with_genesis(genesis)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, ChainParamsBuilderError::BadGenesisMerkleRoot);
+    }
+}
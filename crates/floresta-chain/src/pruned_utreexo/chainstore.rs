@@ -74,6 +74,58 @@ pub trait ChainStore {
     fn check_integrity(&self) -> Result<(), Self::Error>;
 }
 
+/// Copies all our chain data from one [ChainStore] into another, which may use another backend
+///
+/// We copy every header in our best chain, along with its block index entry and accumulator,
+/// the headers of the forks we keep track of, and our best chain data. Orphan headers aren't
+/// copied, since they aren't linked to any chain we know. `to` is flushed at the end.
+pub fn copy_chainstore<S: ChainStore, D: ChainStore>(
+    from: &mut S,
+    to: &mut D,
+) -> Result<(), BlockchainError> {
+    let mut height = 0;
+    while let Some(header) = from.get_header_by_height(height)? {
+        to.save_header(&header)?;
+        to.update_block_index(height, header.block_hash())?;
+
+        if let Some(roots) = from.load_roots_for_block(height)? {
+            to.save_roots_for_block(roots, height)?;
+        }
+
+        height += 1;
+    }
+
+    let Some(best_chain) = from.load_height()? else {
+        return Ok(to.flush()?);
+    };
+
+    for tip in &best_chain.alternative_tips {
+        // Walk back from this tip until we reach our best chain, then save the fork from its
+        // first block, so its headers are saved in the same order we've found them
+        let mut fork = Vec::new();
+        let mut block_hash = *tip;
+
+        while let Some(header) = from.get_header(&block_hash)? {
+            match header {
+                DiskBlockHeader::InFork(_, _) | DiskBlockHeader::InvalidChain(_) => {
+                    block_hash = header.prev_blockhash;
+                    fork.push(header);
+                }
+                _ => break,
+            }
+        }
+
+        for header in fork.iter().rev() {
+            if to.get_header(&header.block_hash())?.is_none() {
+                to.save_header(header)?;
+            }
+        }
+    }
+
+    to.save_height(&best_chain)?;
+    Ok(to.flush()?)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// This enum is used to store a block header in the database. It contains the header along with
 /// metadata about the validation state of the block, and, if applicable, also its height.
@@ -445,3 +497,225 @@ mod tests {
         assert_eq!(decoded, best);
     }
 }
+
+#[cfg(test)]
+/// Checks that every [ChainStore] implementation should pass
+///
+/// Each implementation runs these from its own tests, with a new, empty store.
+pub(crate) mod test_suite {
+    use std::io::Cursor;
+
+    use bitcoin::block::Header as BlockHeader;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::Decodable;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Block;
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::pruned_utreexo::UpdatableChainstate;
+    use crate::AssumeValidArg;
+    use crate::ChainState;
+
+    /// Returns the 151 blocks in `regtest_blocks.txt`
+    fn regtest_blocks() -> Vec<Block> {
+        include_str!("../../testdata/regtest_blocks.txt")
+            .lines()
+            .map(|line| deserialize(&hex::decode(line).unwrap()).unwrap())
+            .collect()
+    }
+
+    /// Saves `blocks` as our best chain, starting from genesis
+    fn save_best_chain(store: &mut impl ChainStore, blocks: &[Block]) {
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+        }
+    }
+
+    pub fn save_and_retrieve_headers(store: &mut impl ChainStore) {
+        let blocks = regtest_blocks();
+        save_best_chain(store, &blocks);
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            let header = store.get_header_by_height(height).unwrap().unwrap();
+            let hash = store.get_block_hash(height).unwrap().unwrap();
+
+            assert_eq!(hash, block.block_hash());
+            assert_eq!(header, DiskBlockHeader::FullyValid(block.header, height));
+            assert_eq!(store.get_header(&hash).unwrap(), Some(header));
+        }
+
+        let regtest_genesis = genesis_block(Network::Regtest);
+        assert_eq!(
+            store.get_block_hash(0).unwrap(),
+            Some(regtest_genesis.block_hash())
+        );
+
+        assert_eq!(store.get_block_hash(151).unwrap(), None);
+        assert_eq!(store.get_header_by_height(151).unwrap(), None);
+        assert_eq!(store.get_header(&BlockHash::all_zeros()).unwrap(), None);
+    }
+
+    pub fn save_height(store: &mut impl ChainStore) {
+        let height = BestChain {
+            alternative_tips: vec![genesis_block(Network::Regtest).block_hash()],
+            validation_index: genesis_block(Network::Signet).block_hash(),
+            depth: 1,
+            best_block: genesis_block(Network::Signet).block_hash(),
+        };
+
+        store.save_height(&height).unwrap();
+        assert_eq!(store.load_height().unwrap(), Some(height.clone()));
+
+        // Our alternative tips may go away, for instance after a reorg
+        let height = BestChain {
+            alternative_tips: Vec::new(),
+            ..height
+        };
+
+        store.save_height(&height).unwrap();
+        assert_eq!(store.load_height().unwrap(), Some(height));
+    }
+
+    pub fn fork_blocks(store: &mut impl ChainStore) {
+        let blocks = regtest_blocks();
+        let (best_chain, forks) = blocks.split_at(blocks.len() / 2);
+        save_best_chain(store, best_chain);
+
+        for (height, block) in forks.iter().enumerate() {
+            store
+                .save_header(&DiskBlockHeader::InFork(block.header, height as u32))
+                .unwrap();
+        }
+
+        // Fork headers are found by hash, but they don't take our best chain's heights
+        for (height, block) in forks.iter().enumerate() {
+            let header = store.get_header(&block.block_hash()).unwrap().unwrap();
+            assert_eq!(header, DiskBlockHeader::InFork(block.header, height as u32));
+
+            let hash = store.get_block_hash(height as u32).unwrap();
+            assert_eq!(hash, best_chain.get(height).map(Block::block_hash));
+        }
+    }
+
+    pub fn reorg_index(store: &mut impl ChainStore) {
+        let blocks = regtest_blocks();
+        save_best_chain(store, &blocks[..10]);
+        for height in 0..10 {
+            store
+                .save_roots_for_block(vec![height as u8], height)
+                .unwrap();
+        }
+
+        // Build a fork from height 5 by tweaking the time of our blocks, then reorg to it,
+        // moving the old blocks out of our best chain before indexing the new ones
+        let mut prev_blockhash = blocks[4].block_hash();
+        let mut fork = Vec::new();
+        for block in &blocks[5..12] {
+            let header = BlockHeader {
+                prev_blockhash,
+                time: block.header.time + 1,
+                ..block.header
+            };
+
+            prev_blockhash = header.block_hash();
+            fork.push(header);
+        }
+
+        for (i, block) in blocks[5..10].iter().enumerate() {
+            let height = 5 + i as u32;
+            store
+                .save_header(&DiskBlockHeader::InFork(block.header, height))
+                .unwrap();
+        }
+
+        for (i, header) in fork.iter().enumerate() {
+            let height = 5 + i as u32;
+            store
+                .update_block_index(height, header.block_hash())
+                .unwrap();
+            store
+                .save_header(&DiskBlockHeader::HeadersOnly(*header, height))
+                .unwrap();
+        }
+
+        for height in 0..12 {
+            let hash = store.get_block_hash(height).unwrap().unwrap();
+            let header = store.get_header_by_height(height).unwrap().unwrap();
+            let roots = store.load_roots_for_block(height).unwrap();
+
+            if height < 5 {
+                assert_eq!(hash, blocks[height as usize].block_hash());
+                assert_eq!(roots, Some(vec![height as u8]));
+                continue;
+            }
+
+            // The new blocks have replaced the old ones, and the roots of the old ones are gone
+            let new_header = fork[height as usize - 5];
+            assert_eq!(hash, new_header.block_hash());
+            assert_eq!(header, DiskBlockHeader::HeadersOnly(new_header, height));
+            assert_eq!(store.get_header(&hash).unwrap(), Some(header));
+            assert_eq!(roots, None);
+        }
+
+        // The old blocks are still found by hash
+        for (i, block) in blocks[5..10].iter().enumerate() {
+            let header = store.get_header(&block.block_hash()).unwrap();
+            assert_eq!(
+                header,
+                Some(DiskBlockHeader::InFork(block.header, 5 + i as u32))
+            );
+        }
+    }
+
+    pub fn save_and_load_roots(store: &mut impl ChainStore) {
+        let genesis = genesis_block(Network::Regtest);
+        store
+            .save_header(&DiskBlockHeader::FullyValid(genesis.header, 0))
+            .unwrap();
+        store.update_block_index(0, genesis.block_hash()).unwrap();
+
+        let acc = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        store.save_roots_for_block(acc.clone(), 0).unwrap();
+        store.flush().unwrap();
+
+        assert_eq!(store.load_roots_for_block(0).unwrap(), Some(acc.clone()));
+
+        // We can't save roots for a block we don't have
+        assert!(store.save_roots_for_block(acc, 10).is_err());
+    }
+
+    pub fn accept_mainnet_headers(store: impl ChainStore) {
+        // Accepts the first 10,237 mainnet headers
+        let file = include_bytes!("../../testdata/headers.zst");
+        let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
+        let chain = ChainState::open(store, Network::Bitcoin, AssumeValidArg::Hardcoded).unwrap();
+        let mut buffer = uncompressed.as_slice();
+
+        while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
+            chain.accept_header(header).unwrap();
+        }
+    }
+
+    pub fn accept_first_signet_headers(store: impl ChainStore) {
+        // Accepts the first 2016 signet headers
+        let file = include_bytes!("../../testdata/signet_headers.zst");
+        let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
+        let chain = ChainState::open(store, Network::Signet, AssumeValidArg::Hardcoded).unwrap();
+        let mut buffer = uncompressed.as_slice();
+
+        while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
+            chain.accept_header(header).unwrap();
+        }
+    }
+}
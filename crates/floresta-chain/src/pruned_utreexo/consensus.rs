@@ -15,6 +15,8 @@ use bitcoin::blockdata::Weight;
 use bitcoin::consensus::serialize;
 use bitcoin::hashes::sha256;
 use bitcoin::hashes::Hash;
+use bitcoin::locktime::absolute::LockTime;
+use bitcoin::locktime::relative;
 use bitcoin::merkle_tree;
 use bitcoin::script;
 use bitcoin::Amount;
@@ -22,6 +24,7 @@ use bitcoin::Block;
 use bitcoin::CompactTarget;
 use bitcoin::Network;
 use bitcoin::OutPoint;
+use bitcoin::Script;
 use bitcoin::ScriptBuf;
 use bitcoin::Target;
 use bitcoin::Transaction;
@@ -38,6 +41,7 @@ use swift_sync_agg::SwiftSyncAgg;
 use super::chainparams::ChainParams;
 use super::error::BlockValidationErrors;
 use super::error::BlockchainError;
+use super::signet::SignetTxs;
 use super::udata;
 use crate::extensions::Bip30UnspendableExt;
 use crate::pruned_utreexo::utxo_data::UtxoData;
@@ -101,6 +105,10 @@ impl From<Network> for Consensus {
 }
 
 impl Consensus {
+    /// Whether [`Consensus::verify_transaction`] can check input scripts. Script validation
+    /// is done by `libbitcoinkernel`, so this is only true with the `bitcoinkernel` feature.
+    pub const VERIFIES_SCRIPTS: bool = cfg!(feature = "bitcoinkernel");
+
     /// Returns the amount of block subsidy to be paid in a block, given its height.
     ///
     /// The Bitcoin Core source can be found [here](https://github.com/bitcoin/bitcoin/blob/2b211b41e36f914b8d0487e698b619039cc3c8e2/src/validation.cpp#L1501-L1512).
@@ -492,6 +500,7 @@ impl Consensus {
     /// - BIP34 coinbase-encoded height once activated (at `bip34_height`)
     /// - if there are SegWit transactions, the witness commitment is present and correct
     /// - total block weight is within the 4,000,000 WU limit
+    /// - on signets, the block solution satisfies the challenge (see [`Self::check_signet_solution`])
     pub fn check_block(&self, block: &Block, height: u32) -> Result<Vec<Txid>, BlockchainError> {
         let Some(txids) = Self::check_merkle_root(block) else {
             return Err(BlockValidationErrors::BadMerkleRoot)?;
@@ -511,9 +520,60 @@ impl Consensus {
             return Err(BlockValidationErrors::BlockTooBig)?;
         }
 
+        if let Some(challenge) = &self.parameters.signet_challenge {
+            self.check_signet_solution(block, challenge)?;
+        }
+
         Ok(txids)
     }
 
+    /// Checks that a signet block carries a valid solution for `challenge`, as defined by BIP-325.
+    ///
+    /// Running the challenge script requires the `bitcoinkernel` feature. Without it, we only
+    /// check that the solution is well-formed, which is why [`ChainParamsBuilder`] refuses
+    /// custom signets in that case.
+    ///
+    /// [`ChainParamsBuilder`]: super::chainparams_builder::ChainParamsBuilder
+    pub fn check_signet_solution(
+        &self,
+        block: &Block,
+        challenge: &Script,
+    ) -> Result<(), BlockchainError> {
+        // The genesis block is shared by all signets, and doesn't carry a solution
+        if block.block_hash() == self.parameters.genesis.block_hash() {
+            return Ok(());
+        }
+
+        #[cfg_attr(not(feature = "bitcoinkernel"), allow(unused_variables))]
+        let Some(txs) = SignetTxs::new(block, challenge) else {
+            return Err(BlockValidationErrors::BadSignetSolution)?;
+        };
+
+        #[cfg(feature = "bitcoinkernel")]
+        {
+            let to_spend = UtxoData {
+                txout: txs.to_spend.output[0].clone(),
+                is_coinbase: false,
+                creation_height: 0,
+                creation_time: 0,
+            };
+
+            let mut utxos = HashMap::new();
+            utxos.insert(txs.to_sign.input[0].previous_output, to_spend);
+
+            // The flags Core uses for signet solutions, instead of the full standard set
+            let flags = bitcoinkernel::VERIFY_P2SH
+                | bitcoinkernel::VERIFY_WITNESS
+                | bitcoinkernel::VERIFY_DERSIG
+                | bitcoinkernel::VERIFY_NULLDUMMY;
+
+            Self::verify_input_scripts(&txs.to_sign, &mut utxos, flags)
+                .map_err(|_| BlockValidationErrors::BadSignetSolution)?;
+        }
+
+        Ok(())
+    }
+
     /// Checks if the merkle root of the header matches the merkle root of the transaction list.
     ///
     /// Unlike [`Block::check_merkle_root`], this function returns the list of computed [`Txid`]s
@@ -592,13 +652,79 @@ impl Consensus {
         }
     }
 
-    #[allow(unused)]
-    fn validate_locktime(
-        input: &TxIn,
+    /// Checks that `transaction` is final, meaning its `nLockTime` allows it to be included in a
+    /// block at `height`, whose median time past is `median_time_past` (BIP 113).
+    ///
+    /// A zero locktime, or one where every input has a final sequence, is always final.
+    pub fn check_final(
         transaction: &Transaction,
         height: u32,
-    ) -> Result<(), BlockValidationErrors> {
-        unimplemented!("validate_locktime")
+        median_time_past: u32,
+    ) -> Result<(), TransactionError> {
+        let txid = || transaction.compute_txid();
+
+        let is_final = match transaction.lock_time {
+            LockTime::ZERO => true,
+            LockTime::Blocks(lock_height) => lock_height.to_consensus_u32() < height,
+            LockTime::Seconds(lock_time) => lock_time.to_consensus_u32() < median_time_past,
+        };
+
+        if is_final
+            || transaction
+                .input
+                .iter()
+                .all(|input| input.sequence.is_final())
+        {
+            return Ok(());
+        }
+
+        Err(tx_err!(txid, NonFinalTransaction))
+    }
+
+    /// Checks the relative lock times of `transaction` (BIP 68), for a block at `height` whose
+    /// previous block has a median time past of `median_time_past`.
+    ///
+    /// `utxos` must have every UTXO spent by this transaction. For inputs with a time-based lock,
+    /// their [`UtxoData::creation_time`] must be set.
+    pub fn check_sequence_locks(
+        transaction: &Transaction,
+        utxos: &HashMap<OutPoint, UtxoData>,
+        height: u32,
+        median_time_past: u32,
+    ) -> Result<(), TransactionError> {
+        let txid = || transaction.compute_txid();
+
+        // Relative lock times only apply to version 2 transactions and up. Like Core, we
+        // compare the version as unsigned.
+        if (transaction.version.0 as u32) < 2 {
+            return Ok(());
+        }
+
+        for input in transaction.input.iter() {
+            let Some(lock) = input.sequence.to_relative_lock_time() else {
+                continue;
+            };
+
+            let utxo = Self::get_utxo(input, utxos, txid)?;
+
+            // The lock is satisfied once we are past the last height, or time, where it holds
+            let is_locked = match lock {
+                relative::LockTime::Blocks(blocks) => {
+                    utxo.creation_height.saturating_add(blocks.value() as u32) > height
+                }
+                relative::LockTime::Time(time) => {
+                    utxo.creation_time
+                        .saturating_add((time.value() as u32) << 9)
+                        > median_time_past
+                }
+            };
+
+            if is_locked {
+                return Err(tx_err!(txid, SequenceLockNotSatisfied));
+            }
+        }
+
+        Ok(())
     }
 
     /// Validates the script size and the number of sigops in a prevout scriptPubKey or scriptSig.
@@ -1306,6 +1432,120 @@ mod tests {
         );
     }
 
+    /// Builds a transaction with the given version and locktime, spending one input per sequence
+    fn locked_transaction(version: i32, lock_time: u32, sequences: &[u32]) -> Transaction {
+        let input = sequences
+            .iter()
+            .enumerate()
+            .map(|(vout, sequence)| TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), vout as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_consensus(*sequence),
+                witness: Witness::new(),
+            })
+            .collect();
+
+        Transaction {
+            version: Version(version),
+            lock_time: LockTime::from_consensus(lock_time),
+            input,
+            output: vec![txout!(50_000, ScriptBuf::new())],
+        }
+    }
+
+    #[test]
+    fn test_check_final() {
+        // A zero locktime is always final
+        let tx = locked_transaction(1, 0, &[0]);
+        assert_ok!(Consensus::check_final(&tx, 0, 0));
+
+        // A height locktime is final in the blocks after it
+        let tx = locked_transaction(1, 100, &[0]);
+        assert_ok!(Consensus::check_final(&tx, 101, 0));
+        assert_eq!(
+            Consensus::check_final(&tx, 100, 0),
+            Err(tx_err!(|| tx.compute_txid(), NonFinalTransaction))
+        );
+
+        // A time locktime is final once the median time past is after it
+        let tx = locked_transaction(1, 1_600_000_000, &[0]);
+        assert_ok!(Consensus::check_final(&tx, 100, 1_600_000_001));
+        assert_eq!(
+            Consensus::check_final(&tx, 1_000_000, 1_600_000_000),
+            Err(tx_err!(|| tx.compute_txid(), NonFinalTransaction))
+        );
+
+        // Final sequences disable the locktime, but only if every input has one
+        let tx = locked_transaction(1, 100, &[u32::MAX, u32::MAX]);
+        assert_ok!(Consensus::check_final(&tx, 100, 0));
+
+        let tx = locked_transaction(1, 100, &[u32::MAX, 0]);
+        assert_eq!(
+            Consensus::check_final(&tx, 100, 0),
+            Err(tx_err!(|| tx.compute_txid(), NonFinalTransaction))
+        );
+    }
+
+    #[test]
+    fn test_check_sequence_locks() {
+        let utxos = |tx: &Transaction, creation_height, creation_time| {
+            tx.input
+                .iter()
+                .map(|input| {
+                    let utxo = UtxoData {
+                        txout: txout!(100_000, ScriptBuf::new()),
+                        is_coinbase: false,
+                        creation_height,
+                        creation_time,
+                    };
+
+                    (input.previous_output, utxo)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        // Locked for 10 blocks after the one confirming the UTXO
+        let tx = locked_transaction(2, 0, &[10]);
+        let inputs = utxos(&tx, 100, 0);
+        assert_ok!(Consensus::check_sequence_locks(&tx, &inputs, 110, 0));
+        assert_eq!(
+            Consensus::check_sequence_locks(&tx, &inputs, 109, 0),
+            Err(tx_err!(|| tx.compute_txid(), SequenceLockNotSatisfied))
+        );
+
+        // Locked for 2 * 512 seconds after the UTXO creation time
+        let tx = locked_transaction(2, 0, &[(1 << 22) | 2]);
+        let inputs = utxos(&tx, 100, 1_600_000_000);
+        assert_ok!(Consensus::check_sequence_locks(
+            &tx,
+            &inputs,
+            101,
+            1_600_001_024
+        ));
+        assert_eq!(
+            Consensus::check_sequence_locks(&tx, &inputs, 101, 1_600_001_023),
+            Err(tx_err!(|| tx.compute_txid(), SequenceLockNotSatisfied))
+        );
+
+        // The disable flag turns off the lock for that input
+        let tx = locked_transaction(2, 0, &[(1 << 31) | 10]);
+        let inputs = utxos(&tx, 100, 0);
+        assert_ok!(Consensus::check_sequence_locks(&tx, &inputs, 101, 0));
+
+        // Version 1 transactions don't have relative locktimes
+        let tx = locked_transaction(1, 0, &[10]);
+        let inputs = utxos(&tx, 100, 0);
+        assert_ok!(Consensus::check_sequence_locks(&tx, &inputs, 101, 0));
+
+        // Every input must be unlocked
+        let tx = locked_transaction(2, 0, &[1, 10]);
+        let inputs = utxos(&tx, 100, 0);
+        assert_eq!(
+            Consensus::check_sequence_locks(&tx, &inputs, 105, 0),
+            Err(tx_err!(|| tx.compute_txid(), SequenceLockNotSatisfied))
+        );
+    }
+
     #[test]
     #[cfg(feature = "bitcoinkernel")]
     fn test_consume_utxos() {
@@ -70,6 +70,10 @@ pub enum BlockchainError {
 
     /// A [`ChainState`](crate::ChainState) operation overflowed.
     OperationOverflow(ChainWorkOverflow),
+
+    /// We can't verify scripts without the `bitcoinkernel` feature, so we can't tell
+    /// whether an unconfirmed transaction is valid.
+    ScriptValidationUnavailable,
 }
 
 impl_error_from!(BlockchainError, ChainWorkOverflow, OperationOverflow);
@@ -97,6 +101,10 @@ impl Display for BlockchainError {
             Self::InvalidTip(e) => write!(f, "The ChainState's tip is invalid: {e}"),
             Self::BadValidationIndex => write!(f, "The ChainState's validation index is invalid"),
             Self::OperationOverflow(_) => write!(f, "A ChainState operation overflowed"),
+            Self::ScriptValidationUnavailable => write!(
+                f,
+                "Can't validate unconfirmed transactions without script validation"
+            ),
         }
     }
 }
@@ -145,6 +153,9 @@ pub enum BlockValidationErrors {
     CoinbaseNotMatured,
     UnspendableUTXO,
     BIP94TimeWarp,
+    BadSignetSolution,
+    NonFinalTransaction,
+    SequenceLockNotSatisfied,
 }
 
 // Helpful macro for generating a TransactionError
@@ -235,6 +246,18 @@ impl Display for BlockValidationErrors {
             BlockValidationErrors::BIP94TimeWarp => {
                 write!(f, "BIP94 time warp detected")
             }
+            BlockValidationErrors::BadSignetSolution => {
+                write!(f, "Invalid signet block solution")
+            }
+            BlockValidationErrors::NonFinalTransaction => {
+                write!(f, "This transaction's locktime isn't satisfied yet")
+            }
+            BlockValidationErrors::SequenceLockNotSatisfied => {
+                write!(
+                    f,
+                    "This transaction's relative locktime isn't satisfied yet"
+                )
+            }
         }
     }
 }
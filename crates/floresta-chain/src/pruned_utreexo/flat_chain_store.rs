@@ -45,8 +45,20 @@
 //! 900k blocks on mainnet. So we would have 32 * 16 + 8 = 520 bytes per accumulator.
 //! 520 * 900k = 468 MiB. This is the absolute worst case for the almost two decades that Bitcoin
 //! existed. However, although this is a pretty manageable number, we can safely get rid of some
-//! older roots, only storing the latest ones, and a few old ones for very deep reorgs. This is,
-//! however, a TODO.
+//! older roots, only storing the latest ones, and a few old ones for very deep reorgs. If a
+//! [`RootsRetention`] is set, we do exactly that, compacting the accumulators file every now and
+//! then. Pruned roots are never needed in normal operation; if a reorg goes deeper than the
+//! roots we keep, the chainstate goes back to the last roots we have and validates the blocks
+//! after it again.
+//!
+//! ## Crash consistency
+//!
+//! Our files only make sense together: the metadata points to headers, headers point to roots,
+//! and the index points to headers. If we let the OS write each memory map back whenever it
+//! wants, a power cut can leave them out of sync. So we map our files as copy-on-write, keep
+//! track of the pages we change, and only write them back when flushing, through a write-ahead
+//! journal (see the `journal` module). Either a whole flush reaches the disk, or none of it
+//! does, and when opening the store we always find it as it was after some flush.
 //!
 //! # Good to know
 //!
@@ -66,8 +78,8 @@
 //!           of the flat file
 //!     (iii): we must make sure that the load factor **never** reaches one
 //! i and ii will cause a segfault, iii will turn the addition (or search for non-existent values)
-//! an infinite loop. If we are about to reach the map's capacity, we should re-hash with a new
-//! capacity.
+//! an infinite loop. To make sure iii never happens, once the map is more than half full, we
+//! re-hash it into a new one with twice the capacity.
 
 extern crate std;
 
@@ -77,6 +89,9 @@ use core::fmt::Display;
 use core::fmt::Formatter;
 use core::mem::size_of;
 use core::num::NonZeroUsize;
+use core::ops::Deref;
+use std::collections::BTreeSet;
+use std::fs;
 use std::fs::DirBuilder;
 use std::fs::File;
 use std::fs::OpenOptions;
@@ -98,9 +113,11 @@ use bitcoin::BlockHash;
 use floresta_common::impl_error_from;
 use floresta_common::prelude::*;
 use index_impl::Index;
+use journal::Journal;
 use lru::LruCache;
 use memmap2::MmapMut;
 use memmap2::MmapOptions;
+use tracing::debug;
 use tracing::info;
 use twox_hash::XxHash3_64;
 
@@ -118,6 +135,22 @@ const FLAT_CHAINSTORE_MAGIC: u32 = 0x74_73_6C_66; // "flst" backwards
 /// The version of our flat chain store
 const FLAT_CHAINSTORE_VERSION: u32 = 1;
 
+/// We re-hash the block index once more than this percentage of its buckets are occupied
+///
+/// With open addressing, the more occupied buckets we have, the longer we probe for an entry.
+/// At a load factor of 0.5, we expect less than two probes for most lookups.
+const MAX_INDEX_LOAD_PERCENT: usize = 50;
+
+/// The size of the pages we keep track of, when looking for changes to write back
+const PAGE_SIZE: usize = 4096;
+
+/// How many changed pages we keep in memory before flushing them on our own
+///
+/// Our changes only reach the disk when we flush, until then they live in private memory. This
+/// bounds how much of it we use (64 MiB), since we may save millions of headers between flushes
+/// during IBD.
+const MAX_DIRTY_PAGES: usize = 16_384;
+
 /// We use a LRU cache to keep the last n blocks we've touched, so we don't need to do a map search
 /// again. This is the type of our cache
 type CacheType = LruCache<BlockHash, DiskBlockHeader>;
@@ -140,7 +173,8 @@ pub struct FlatChainStoreConfig {
     /// This index holds our map from block hashes to block heights. We use an open-addressing hash
     /// map to map block hashes to block heights. Ideally, size should be way bigger than the
     /// number of blocks we expect to have in our chain, therefore reducing the load factor to a
-    /// negligible value. The default value is having space for 10 million blocks.
+    /// negligible value. The default value is having space for 10 million blocks. If the map ever
+    /// gets more than half full, we re-hash it into one twice as big.
     ///
     /// We compute the actual capacity by rounding the requested size up to the next power of two,
     /// so we can use `hash & (capacity - 1)` instead of `hash % capacity`.
@@ -175,6 +209,13 @@ pub struct FlatChainStoreConfig {
     /// We compute the actual capacity by rounding the requested size up to the next power of two.
     pub fork_file_size: Option<usize>,
 
+    /// Which accumulator roots we keep for old blocks
+    ///
+    /// By default, we keep the roots for every block, so we can go back to any of them in a reorg.
+    /// If set, we only keep the roots allowed by this policy, deleting the others. See
+    /// [`RootsRetention`] for more information.
+    pub roots_retention: Option<RootsRetention>,
+
     /// The path where we store our files
     ///
     /// We'll create a few files (namely, the index map, headers file, forks file, and metadata file).
@@ -192,11 +233,51 @@ impl FlatChainStoreConfig {
             headers_file_size: Some(10_000_000),
             block_index_size: Some(10_000_000),
             cache_size: Some(10_000),
+            roots_retention: None,
             path: path.as_ref().into(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which accumulator roots we keep for blocks that are not at our tip anymore
+///
+/// We only need the roots of a block if we reorg back to it, and reorgs almost always happen
+/// within the last few blocks. So we keep the roots of the `keep_recent` latest blocks, and of
+/// every block with a height multiple of `checkpoint_interval`, to go back to if a deeper reorg
+/// ever happens. With the default values, this is about 250 KiB of roots for mainnet, instead
+/// of several hundreds of MiB.
+pub struct RootsRetention {
+    /// For how many of our latest blocks we keep the roots
+    pub keep_recent: u32,
+
+    /// We also keep the roots of every block whose height is a multiple of this. Zero means we
+    /// don't keep any old roots
+    pub checkpoint_interval: u32,
+}
+
+impl Default for RootsRetention {
+    fn default() -> Self {
+        RootsRetention {
+            // About one day of blocks
+            keep_recent: 144,
+            // One difficulty adjustment period
+            checkpoint_interval: 2016,
+        }
+    }
+}
+
+impl RootsRetention {
+    /// Whether we keep the roots for the block at `height`, given our tip is at `tip`
+    pub fn keeps(&self, height: u32, tip: u32) -> bool {
+        if height.saturating_add(self.keep_recent) >= tip {
+            return true;
+        }
+
+        self.checkpoint_interval != 0 && height % self.checkpoint_interval == 0
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct FileChecksum(u64);
@@ -428,12 +509,126 @@ impl From<PoisonError<MutexGuard<'_, CacheType>>> for FlatChainstoreError {
     }
 }
 
+/// A memory-mapped file, where our changes only reach the disk when we flush
+///
+/// The file is mapped as copy-on-write, so the OS never writes our changes back on its own, and
+/// we keep track of the pages we've changed since our last flush. Those are written through the
+/// [`journal`] by [`FlatChainStore::do_flush`].
+struct JournaledMap {
+    /// The private memory map for this file
+    map: MmapMut,
+
+    /// The pages we've changed since our last flush
+    dirty: Mutex<BTreeSet<usize>>,
+
+    /// The file we've mapped
+    file: File,
+}
+
+impl JournaledMap {
+    /// Maps the file at `path` as copy-on-write, creating it with `size` bytes if needed
+    unsafe fn open(
+        path: impl AsRef<Path>,
+        size: usize,
+        mode: u32,
+    ) -> Result<Self, FlatChainstoreError> {
+        let file = FlatChainStore::open_file(path, size, mode)?;
+
+        Ok(Self {
+            map: MmapOptions::default().len(size).map_copy(&file)?,
+            dirty: Mutex::new(BTreeSet::new()),
+            file,
+        })
+    }
+
+    /// Maps the file again, dropping our changes, since they were written to it
+    unsafe fn remap(&mut self) -> Result<(), FlatChainstoreError> {
+        self.map = MmapOptions::default()
+            .len(self.map.len())
+            .map_copy(&self.file)?;
+
+        self.dirty
+            .lock()
+            .map_err(|_| FlatChainstoreError::PoisonedLock)?
+            .clear();
+
+        Ok(())
+    }
+
+    /// Writes our changes straight into the file, without going through the journal
+    ///
+    /// Tests use this to leave our files like a crash in the middle of an operation would.
+    #[cfg(test)]
+    fn flush(&self) -> Result<(), FlatChainstoreError> {
+        for (offset, data) in self.dirty_pages()? {
+            (&self.file).seek(SeekFrom::Start(offset))?;
+            (&self.file).write_all(&data)?;
+        }
+
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Records that we've changed `len` bytes, starting at `offset`
+    fn mark_dirty(&self, offset: usize, len: usize) -> Result<(), FlatChainstoreError> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut dirty = self
+            .dirty
+            .lock()
+            .map_err(|_| FlatChainstoreError::PoisonedLock)?;
+
+        dirty.extend(offset / PAGE_SIZE..=(offset + len - 1) / PAGE_SIZE);
+        Ok(())
+    }
+
+    /// How many pages we've changed since our last flush
+    fn dirty_count(&self) -> Result<usize, FlatChainstoreError> {
+        let dirty = self
+            .dirty
+            .lock()
+            .map_err(|_| FlatChainstoreError::PoisonedLock)?;
+
+        Ok(dirty.len())
+    }
+
+    /// Returns the pages we've changed since our last flush, as (offset, contents)
+    fn dirty_pages(&self) -> Result<Vec<(u64, Vec<u8>)>, FlatChainstoreError> {
+        let dirty = self
+            .dirty
+            .lock()
+            .map_err(|_| FlatChainstoreError::PoisonedLock)?;
+
+        let pages = dirty
+            .iter()
+            .map(|page| {
+                let start = page * PAGE_SIZE;
+                let end = (start + PAGE_SIZE).min(self.map.len());
+
+                (start as u64, self.map[start..end].to_vec())
+            })
+            .collect();
+
+        Ok(pages)
+    }
+}
+
+impl Deref for JournaledMap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
 /// A hash map implementation that maps block hashes to u32 indexes. Indexes are stored scattered
 /// across the memory-mapped file and accessed via `hash_map_find_pos`. We keep track of how many
 /// buckets are occupied in the metadata file (so we can re-hash the map when needed).
 struct BlockIndex {
     /// The memory map for the block indexes
-    index_map: MmapMut,
+    index_map: JournaledMap,
 
     /// The maximum size of the index map, in buckets
     index_size: usize,
@@ -445,23 +640,13 @@ impl BlockIndex {
     /// This function should only be called by [FlatChainStore::new], and it should never be called
     /// directly. It creates a new block index, given a mutable memory-mapped buffer for the index
     /// map and its maximum size in buckets.
-    fn new(index_map: MmapMut, index_size: usize) -> Self {
+    fn new(index_map: JournaledMap, index_size: usize) -> Self {
         Self {
             index_map,
             index_size,
         }
     }
 
-    /// Flushes the index map to disk
-    ///
-    /// If we have enough changes that we don't want to lose, we should flush the index map to disk.
-    /// This makes sure the indexes are persisted, and we can recover them in case of a crash.
-    fn flush(&self) -> Result<(), FlatChainstoreError> {
-        self.index_map.flush()?;
-
-        Ok(())
-    }
-
     /// Updates our index to map a block hash to an index
     ///
     /// After accepting a new block, this should be updated to record its position in the chain.
@@ -475,20 +660,21 @@ impl BlockIndex {
     ) -> Result<bool, FlatChainstoreError> {
         let pos = self.hash_map_find_pos(hash, get_header_by_index)?;
 
-        match pos {
-            IndexBucket::Empty { ptr } => {
-                ptr.write(index);
-                Ok(true)
-            }
+        let (ptr, is_new) = match pos {
+            IndexBucket::Empty { ptr } => (ptr, true),
 
             // A position may be re-written if we happen to have a reorg.
             // If this is the case, we should update the fork block to make it into the main chain,
             // and mark the old main chain block as a fork.
-            IndexBucket::Occupied { ptr, .. } => {
-                ptr.write(index);
-                Ok(false)
-            }
-        }
+            IndexBucket::Occupied { ptr, .. } => (ptr, false),
+        };
+
+        ptr.write(index);
+
+        let offset = ptr as usize - self.index_map.as_ptr() as usize;
+        self.index_map.mark_dirty(offset, size_of::<Index>())?;
+
+        Ok(is_new)
     }
 
     /// Returns the block index for a given block hash and its fetched header, if present
@@ -591,29 +777,95 @@ impl BlockIndex {
 /// chainstate to interact with the chainstore, even in a multi-threaded environment.
 pub struct FlatChainStore {
     /// The memory map for our headers
-    headers: MmapMut,
+    headers: JournaledMap,
 
     /// The memory map for our metadata
-    metadata: MmapMut,
+    metadata: JournaledMap,
 
     /// The memory map for our block index
     block_index: BlockIndex,
 
     /// The memory map for our fork files
-    fork_headers: MmapMut,
+    fork_headers: JournaledMap,
 
     /// The file containing the accumulators for each blocks
     accumulator_file: File,
 
     /// A LRU cache for the last n blocks we've touched
     cache: Mutex<LruCache<BlockHash, DiskBlockHeader>>,
+
+    /// Which roots we keep for old blocks, if we don't keep all of them
+    roots_retention: Option<RootsRetention>,
+
+    /// We've already pruned the roots we don't keep below this height
+    roots_pruned_height: u32,
+
+    /// The directory where our files are
+    path: PathBuf,
+
+    /// The permission for the files we create
+    file_mode: u32,
+
+    /// Which files we've rebuilt since our last flush, and should be replaced by their `.new`
+    /// version when flushing. See [`journal::REPLACEABLE_FILES`]
+    replaced: u8,
+
+    /// The roots we've truncated away from our accumulators file since our last flush, and
+    /// where they started. See [`FlatChainStore::truncate_roots`]
+    roots_undo: Option<(u64, Vec<u8>)>,
+
+    /// Where we should pretend to crash during our next flush
+    #[cfg(test)]
+    crash_at: Option<CrashPoint>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Points of a flush where we may simulate a crash, to test our recovery
+enum CrashPoint {
+    /// We've only written part of the journal
+    WritingJournal,
+
+    /// The journal is on disk, but we haven't written anything else
+    BeforeApplying,
+
+    /// We've only written some of the journaled pages into our files
+    Applying,
+}
+
+#[cfg(test)]
+impl CrashPoint {
+    /// Does the part of a flush that comes before this point, then aborts the whole process
+    fn simulate(self, datadir: &Path, journal: &Journal) -> Result<(), FlatChainstoreError> {
+        let journal_path = datadir.join(journal::JOURNAL_FILE);
+
+        match self {
+            CrashPoint::WritingJournal => {
+                let data = journal.serialize();
+                fs::write(&journal_path, &data[..data.len() / 2])?;
+            }
+
+            CrashPoint::BeforeApplying => journal.write(&journal_path)?,
+
+            CrashPoint::Applying => {
+                journal.write(&journal_path)?;
+
+                let mut partial = journal.clone();
+                partial.pages.truncate(journal.pages.len() / 2);
+                partial.apply(datadir)?;
+            }
+        }
+
+        std::process::abort()
+    }
 }
 
 impl FlatChainStore {
-    /// Creates a new storage, given a configuration
+    /// Creates the files for a new storage, given a configuration
     ///
-    /// If any of the I/O operations fail, this function should return an error
-    fn create_chain_store(config: FlatChainStoreConfig) -> Result<Self, FlatChainstoreError> {
+    /// The metadata file is written last, since its existence means we have a store. If any of
+    /// the I/O operations fail, this function should return an error
+    fn create_chain_store(config: &FlatChainStoreConfig) -> Result<(), FlatChainstoreError> {
         let file_mode = config.file_permission.unwrap_or(0o600);
         let datadir: &Path = config.path.as_ref();
 
@@ -641,18 +893,24 @@ impl FlatChainStore {
         let accumulator_file_path = datadir.join("accumulators.bin");
 
         let index_map_file_size = index_size * size_of::<u32>();
-        let index_map = unsafe { Self::init_file(&index_path, index_map_file_size, file_mode)? };
+        Self::open_file(&index_path, index_map_file_size, file_mode)?;
 
         let headers_file_size = headers_size * size_of::<HashedDiskHeader>();
-        let headers = unsafe { Self::init_file(&headers_path, headers_file_size, file_mode)? };
+        Self::open_file(&headers_path, headers_file_size, file_mode)?;
+
+        let fork_headers_file_size = fork_size * size_of::<HashedDiskHeader>();
+        Self::open_file(&fork_headers_path, fork_headers_file_size, file_mode)?;
+
+        File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(accumulator_file_path)?;
 
         let metadata =
             unsafe { Self::init_file(&metadata_path, size_of::<Metadata>(), file_mode)? };
 
-        let fork_headers_file_size = fork_size * size_of::<HashedDiskHeader>();
-        let fork_headers =
-            unsafe { Self::init_file(&fork_headers_path, fork_headers_file_size, file_mode)? };
-
         let _metadata = metadata.as_ptr() as *mut Metadata;
         let _metadata = unsafe { &mut *_metadata };
 
@@ -677,25 +935,9 @@ impl FlatChainStore {
             fork_headers_checksum: FileChecksum(0),
         };
 
-        let cache_size = config.cache_size.and_then(NonZeroUsize::new).unwrap_or(
-            NonZeroUsize::new(1000).expect("Infallible: Hard-coded default is always non-zero"),
-        );
-
-        let accumulator_file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(accumulator_file_path)?;
+        metadata.flush()?;
 
-        Ok(Self {
-            headers,
-            accumulator_file,
-            metadata,
-            block_index: BlockIndex::new(index_map, index_size),
-            fork_headers,
-            cache: LruCache::new(cache_size).into(),
-        })
+        Ok(())
     }
 
     /// Opens a new storage. If it already exists, just load. If not, create a new one
@@ -704,6 +946,9 @@ impl FlatChainStore {
         let metadata_path = datadir.join("metadata.bin");
         let file_mode = config.file_permission.unwrap_or(0o600);
 
+        // Finish, or throw away, the flush we were doing when we last stopped
+        journal::recover(datadir)?;
+
         // Maybe migrate our database if it's the old version 0
         migrate_v0_to_v1::maybe_migrate(&metadata_path, file_mode)?;
 
@@ -712,13 +957,24 @@ impl FlatChainStore {
 
         if !metadata_exists {
             // Metadata doesn't exist, create a new chain store
-            let mut store = Self::create_chain_store(config)?;
+            Self::create_chain_store(&config)?;
+
+            let mut store = Self::open(config)?;
             store.flush()?;
             return Ok(store);
         }
 
+        Self::open(config)
+    }
+
+    /// Loads an existing storage
+    fn open(config: FlatChainStoreConfig) -> Result<Self, FlatChainstoreError> {
+        let datadir = &config.path;
+        let metadata_path = datadir.join("metadata.bin");
+        let file_mode = config.file_permission.unwrap_or(0o600);
+
         let metadata_file =
-            unsafe { Self::init_file(&metadata_path, size_of::<Metadata>(), file_mode) }?;
+            unsafe { JournaledMap::open(&metadata_path, size_of::<Metadata>(), file_mode) }?;
 
         let metadata = metadata_file.as_ptr() as *const Metadata;
         let metadata = unsafe {
@@ -741,13 +997,35 @@ impl FlatChainStore {
         let fork_file_path = datadir.join("fork_headers.bin");
         let accumulator_file_path = datadir.join("accumulators.bin");
 
-        let index_file_size = metadata.index_capacity * size_of::<u32>();
+        // We may have crashed while re-hashing the block index. If we didn't replace the old
+        // index yet, we just throw the new one away. Otherwise, the new index is complete, but
+        // our metadata still has the old capacity. See `rehash_index`.
+        let new_index_path = datadir.join("blocks_index.bin.new");
+        if new_index_path.exists() {
+            fs::remove_file(&new_index_path)?;
+        }
+
+        let index_buckets = index_path
+            .metadata()
+            .map(|file| file.len() as usize / size_of::<u32>())
+            .unwrap_or(0);
+
+        let rehashed = index_buckets > metadata.index_capacity;
+        let index_capacity = if rehashed {
+            index_buckets
+        } else {
+            metadata.index_capacity
+        };
+
+        let index_file_size = index_capacity * size_of::<u32>();
         let headers_file_size = metadata.headers_file_size * size_of::<HashedDiskHeader>();
         let fork_file_size = metadata.fork_file_size * size_of::<HashedDiskHeader>();
 
-        let index_map = unsafe { Self::init_file(&index_path, index_file_size, file_mode)? };
-        let headers = unsafe { Self::init_file(&headers_file_path, headers_file_size, file_mode)? };
-        let fork_headers = unsafe { Self::init_file(&fork_file_path, fork_file_size, file_mode)? };
+        let index_map = unsafe { JournaledMap::open(&index_path, index_file_size, file_mode)? };
+        let headers =
+            unsafe { JournaledMap::open(&headers_file_path, headers_file_size, file_mode)? };
+        let fork_headers =
+            unsafe { JournaledMap::open(&fork_file_path, fork_file_size, file_mode)? };
         let cache_size = config.cache_size.and_then(NonZeroUsize::new).unwrap_or(
             NonZeroUsize::new(1000).expect("Infallible: Hard-coded default is always non-zero"),
         );
@@ -759,14 +1037,35 @@ impl FlatChainStore {
             .truncate(false)
             .open(accumulator_file_path)?;
 
-        Ok(Self {
+        let mut store = Self {
             headers,
             accumulator_file,
+            block_index: BlockIndex::new(index_map, index_capacity),
             metadata: metadata_file,
-            block_index: BlockIndex::new(index_map, metadata.index_capacity),
             fork_headers,
             cache: LruCache::new(cache_size).into(),
-        })
+            roots_retention: config.roots_retention,
+            roots_pruned_height: 0,
+            path: datadir.to_path_buf(),
+            file_mode,
+            replaced: 0,
+            roots_undo: None,
+            #[cfg(test)]
+            crash_at: None,
+        };
+
+        if rehashed {
+            info!(
+                "Finishing an interrupted re-hash of the block index, with {index_buckets} buckets"
+            );
+
+            unsafe {
+                store.get_metadata_mut()?.index_capacity = index_buckets;
+                store.do_flush()?;
+            }
+        }
+
+        Ok(store)
     }
 
     /// Adds a new entry into the block index, given a block hash and its `Index`
@@ -781,6 +1080,11 @@ impl FlatChainStore {
         let metadata = self.get_metadata()?;
         let next_occupancy = metadata.block_index_occupancy + 1;
 
+        if next_occupancy * 100 > metadata.index_capacity * MAX_INDEX_LOAD_PERCENT {
+            self.rehash_index(metadata.index_capacity * 2)?;
+        }
+
+        let metadata = self.get_metadata()?;
         if next_occupancy >= metadata.index_capacity {
             return Err(FlatChainstoreError::FullIndex);
         }
@@ -798,6 +1102,82 @@ impl FlatChainStore {
         Ok(())
     }
 
+    /// Re-hashes our block index into a new one with `capacity` buckets
+    ///
+    /// We first flush, so the new index only points to headers that are on disk. Then we build
+    /// the new index in a separate file, sync it to disk and atomically rename it over the old
+    /// one. Only then we update our metadata. If we crash before the rename, the old index is
+    /// still there, and the leftover file is deleted the next time we open the store. If we crash
+    /// after it, [`FlatChainStore::open`] finds out the new capacity from the file size.
+    ///
+    /// Only the entries the old index actually resolves to are copied, so stale entries left by
+    /// reorgs are dropped, and the new occupancy may be lower than the old one.
+    unsafe fn rehash_index(&mut self, capacity: usize) -> Result<(), FlatChainstoreError> {
+        let index_path = self.path.join("blocks_index.bin");
+        let new_index_path = self.path.join("blocks_index.bin.new");
+        let index_size = capacity * size_of::<u32>();
+
+        info!(
+            "Re-hashing the block index, from {} to {capacity} buckets",
+            self.block_index.index_size
+        );
+
+        self.do_flush()?;
+
+        // We write straight into this file, so we don't need to track what we change
+        let file = Self::open_file(&new_index_path, index_size, self.file_mode)?;
+        let index_map = JournaledMap {
+            map: MmapOptions::default().len(index_size).map_mut(&file)?,
+            dirty: Mutex::new(BTreeSet::new()),
+            file,
+        };
+
+        let new_index = BlockIndex::new(index_map, capacity);
+        let get_header = |index| self.get_disk_header(index).copied();
+
+        let mut occupancy = 0;
+        let buckets = self.block_index.index_map.as_ptr() as *const Index;
+        for bucket in 0..self.block_index.index_size {
+            let index = *buckets.add(bucket);
+
+            // The genesis block is found on any empty bucket, so we don't need to copy it
+            if index.is_empty() {
+                continue;
+            }
+
+            // This entry points to a header we've overwritten
+            let Ok(header) = self.get_disk_header(index) else {
+                continue;
+            };
+
+            let current = self
+                .block_index
+                .get_index_for_hash(header.hash, get_header)?;
+            if current.map(|(current, _)| current) != Some(index) {
+                continue;
+            }
+
+            if new_index.set_index_for_hash(header.hash, index, get_header)? {
+                occupancy += 1;
+            }
+        }
+
+        new_index.index_map.map.flush()?;
+        drop(new_index);
+
+        fs::rename(&new_index_path, &index_path)?;
+        journal::sync_dir(&self.path)?;
+
+        let index_map = JournaledMap::open(&index_path, index_size, self.file_mode)?;
+        self.block_index = BlockIndex::new(index_map, capacity);
+
+        let metadata = self.get_metadata_mut()?;
+        metadata.index_capacity = capacity;
+        metadata.block_index_occupancy = occupancy;
+
+        self.do_flush()
+    }
+
     /// Checks the integrity of our database
     ///
     /// This function will check the integrity of our database by comparing the checksum of the
@@ -822,9 +1202,8 @@ impl FlatChainStore {
     /// Computes the XXH3-64 checksum for our database
     pub fn compute_checksum(&self) -> DbCheckSum {
         // a function that computes the xxHash of a memory map
-        let checksum_fn = |mmap: &MmapMut| {
-            let mmap_as_slice = mmap.iter().as_slice();
-            let hash = XxHash3_64::oneshot(mmap_as_slice);
+        let checksum_fn = |mmap: &[u8]| {
+            let hash = XxHash3_64::oneshot(mmap);
 
             FileChecksum(hash)
         };
@@ -859,11 +1238,26 @@ impl FlatChainStore {
 
     /// Initializes a memory-mapped file with the specified byte size and permissions (mode).
     /// If the underlying file does not exist, it will be created.
+    ///
+    /// Changes to this map are written back to the file by the OS whenever it wants, so this
+    /// should only be used for files we aren't journaling. Use [`JournaledMap::open`] otherwise.
     unsafe fn init_file(
         path: impl AsRef<Path>,
         size: usize,
-        _mode: u32,
+        mode: u32,
     ) -> Result<MmapMut, FlatChainstoreError> {
+        let file = Self::open_file(path, size, mode)?;
+
+        // Return the `MmapMut` instance that represents the file
+        Ok(MmapOptions::default().len(size).map_mut(&file)?)
+    }
+
+    /// Opens a file with the specified byte size and permissions (mode), creating it if needed
+    fn open_file(
+        path: impl AsRef<Path>,
+        size: usize,
+        _mode: u32,
+    ) -> Result<File, FlatChainstoreError> {
         let file = OpenOptions::new()
             // Set read and write access
             .read(true)
@@ -881,8 +1275,7 @@ impl FlatChainStore {
 
         file.set_len(size as u64)?;
 
-        // Return the `MmapMut` instance that represents the file
-        Ok(MmapOptions::default().len(size).map_mut(&file)?)
+        Ok(file)
     }
 
     /// Returns a reference to the respective disk header from the file. Errors if nothing is found.
@@ -920,9 +1313,9 @@ impl FlatChainStore {
         index: Index,
     ) -> Result<&mut HashedDiskHeader, FlatChainstoreError> {
         let metadata = self.get_metadata()?;
-        let (max_size, base_ptr) = match index.is_main_chain() {
-            true => (metadata.headers_file_size, self.headers.as_ptr()),
-            false => (metadata.fork_file_size, self.fork_headers.as_ptr()),
+        let (max_size, map) = match index.is_main_chain() {
+            true => (metadata.headers_file_size, &self.headers),
+            false => (metadata.fork_file_size, &self.fork_headers),
         };
 
         let index = index.index() as usize;
@@ -930,8 +1323,11 @@ impl FlatChainStore {
             return Err(FlatChainstoreError::FullIndex);
         }
 
+        let header_size = size_of::<HashedDiskHeader>();
+        map.mark_dirty(index * header_size, header_size)?;
+
         // SAFETY: we've checked index < max_size
-        let ptr = (base_ptr as *mut HashedDiskHeader).add(index);
+        let ptr = (map.as_ptr() as *mut HashedDiskHeader).add(index);
 
         Ok(&mut *ptr)
     }
@@ -943,15 +1339,13 @@ impl FlatChainStore {
         metadata.depth = best_block.depth;
         metadata.validation_index = best_block.validation_index;
 
-        assert!(best_block.alternative_tips.len() <= 64);
+        let tips = &best_block.alternative_tips;
+        assert!(tips.len() <= 64);
 
-        metadata
-            .alternative_tips
-            .as_mut_ptr()
-            .copy_from_nonoverlapping(
-                best_block.alternative_tips.as_ptr(),
-                best_block.alternative_tips.len(),
-            );
+        // Our list may have shrunk since we last saved it, so clear the old tips before writing
+        // the new ones. Otherwise, tips we've dropped would come back once we load this list.
+        metadata.alternative_tips.fill(BlockHash::all_zeros());
+        metadata.alternative_tips[..tips.len()].copy_from_slice(tips);
 
         Ok(())
     }
@@ -995,6 +1389,7 @@ impl FlatChainStore {
     }
 
     unsafe fn get_metadata_mut(&mut self) -> Result<&mut Metadata, FlatChainstoreError> {
+        self.metadata.mark_dirty(0, size_of::<Metadata>())?;
         let ptr = self.metadata.as_ptr() as *mut Metadata;
 
         Ok(ptr
@@ -1090,62 +1485,251 @@ impl FlatChainStore {
         Ok(())
     }
 
-    unsafe fn do_flush(&mut self) -> Result<(), FlatChainstoreError> {
-        self.headers.flush()?;
-        self.block_index.flush()?;
-        self.fork_headers.flush()?;
-
-        let checksum = self.compute_checksum();
-        let metadata = self.get_metadata_mut()?;
+    /// Returns where the roots of the blocks before `height` end in the accumulators file
+    ///
+    /// This is the end of the roots of the last block before `height` that we have roots for.
+    /// Roots are written in height order, and a reorg lowers `roots_pruned_height` to the height
+    /// it goes back to, so if `height` isn't above that, every root we have for an older block
+    /// comes before this position.
+    unsafe fn roots_end_before(&self, height: u32) -> Result<u64, FlatChainstoreError> {
+        for height in (0..height).rev() {
+            let header = self.get_disk_header(Index::new(height)?)?;
+            if header.acc_len != 0 {
+                return Ok(header.acc_pos as u64 + header.acc_len as u64);
+            }
+        }
 
-        metadata.checksum = checksum;
-        self.metadata.flush()?;
-        Ok(())
+        Ok(0)
     }
 
-    #[inline(always)]
-    #[doc(hidden)]
-    fn get_cache_mut(
-        &self,
-    ) -> Result<MutexGuard<'_, CacheType>, PoisonError<MutexGuard<'_, CacheType>>> {
-        self.cache.lock()
-    }
-}
+    /// Deletes the roots that `retention` doesn't keep anymore, given our tip is at `tip`
+    ///
+    /// Our headers can't point to roots we've moved before the next flush is committed, so we
+    /// can't compact the file in place. Instead, we copy the roots we've already pruned as they
+    /// are, then each root we keep right after the previous one, into a new file. It replaces the
+    /// old one once our next flush is committed (see [`journal`]). We only go through the blocks
+    /// we haven't pruned yet, so this is cheap, except for the first time it runs after the node
+    /// starts.
+    unsafe fn prune_roots(
+        &mut self,
+        retention: RootsRetention,
+        tip: u32,
+    ) -> Result<(), FlatChainstoreError> {
+        let start = self.roots_pruned_height;
+        let prefix = self.roots_end_before(start)?;
 
-impl ChainStore for FlatChainStore {
-    type Error = FlatChainstoreError;
+        let tmp_roots_path = self.path.join("accumulators.bin.tmp");
+        let mut new_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_roots_path)?;
 
-    fn check_integrity(&self) -> Result<(), Self::Error> {
-        self.check_integrity()
-    }
+        self.accumulator_file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&self.accumulator_file).take(prefix), &mut new_file)?;
 
-    fn flush(&mut self) -> Result<(), Self::Error> {
-        unsafe { self.do_flush() }
-    }
+        let mut cursor = prefix;
+        let mut pruned = 0;
 
-    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
-        let index = Index::new(height)?;
+        for height in start..=tip {
+            let index = Index::new(height)?;
+            let header = *self.get_disk_header(index)?;
+            if header.acc_len == 0 {
+                continue;
+            }
 
-        let metadata = unsafe { self.get_metadata()? };
-        let validation_index = self
-            .get_header(&metadata.validation_index)?
-            .map(|h| {
-                h.try_height()
-                    .map_err(|_| FlatChainstoreError::InvalidValidationIndex)
-            })
-            .transpose()?
-            .unwrap_or(0);
+            if !retention.keeps(height, tip) {
+                let header = self.get_disk_header_mut(index)?;
+                header.acc_pos = 0;
+                header.acc_len = 0;
+                pruned += 1;
+                continue;
+            }
 
-        if height <= validation_index {
-            // this is probably a reorg, truncate the file up to the previous block height
-            let header = unsafe { self.get_disk_header(index)? };
+            let mut roots = vec![0; header.acc_len as usize];
+            self.accumulator_file
+                .seek(SeekFrom::Start(header.acc_pos as u64))?;
+            self.accumulator_file.read_exact(&mut roots)?;
+            new_file.write_all(&roots)?;
 
-            // this is where the new acc starts, truncating the file to this position
-            let pos = header.acc_pos as u64;
+            if header.acc_pos as u64 != cursor {
+                self.get_disk_header_mut(index)?.acc_pos = cursor as u32;
+            }
 
-            self.accumulator_file
-                .set_len(pos)
-                .map_err(FlatChainstoreError::Io)?;
+            cursor += header.acc_len as u64;
+        }
+
+        new_file.sync_data()?;
+        fs::rename(&tmp_roots_path, self.path.join("accumulators.bin.new"))?;
+
+        self.accumulator_file = new_file;
+        self.replaced |= journal::REPLACE_ROOTS;
+        self.roots_pruned_height = tip.saturating_sub(retention.keep_recent);
+
+        debug!("Pruned the roots of {pruned} blocks, up to height {tip}");
+        Ok(())
+    }
+
+    /// Truncates our accumulators file at `pos`, so we can write the roots of a reorg over the
+    /// roots of the blocks it replaces
+    ///
+    /// The headers we've last flushed may still point to the roots we drop, and that's where we
+    /// go back to if we crash. So, before truncating the file our last flush committed, we save
+    /// what we drop into an undo file, that [`journal::recover`] writes back if we crash before
+    /// our next flush. A compacted file we didn't commit yet is just thrown away after a crash,
+    /// so we truncate that one right away.
+    unsafe fn truncate_roots(&mut self, pos: u64) -> Result<(), FlatChainstoreError> {
+        let len = self.accumulator_file.metadata()?.len();
+        if pos >= len {
+            return Ok(());
+        }
+
+        // Anything after an earlier truncation was written after our last flush
+        let committed_end = match &self.roots_undo {
+            Some((start, _)) => len.min(*start),
+            None => len,
+        };
+
+        if self.replaced & journal::REPLACE_ROOTS == 0 && pos < committed_end {
+            let mut dropped = vec![0; (committed_end - pos) as usize];
+            self.accumulator_file.seek(SeekFrom::Start(pos))?;
+            self.accumulator_file.read_exact(&mut dropped)?;
+
+            if let Some((_, older)) = self.roots_undo.take() {
+                dropped.extend(older);
+            }
+
+            let undo = Journal {
+                replaced: 0,
+                pages: vec![(journal::ROOTS, pos, dropped.clone())],
+            };
+
+            undo.write(&self.path.join(journal::ROOTS_UNDO_FILE))?;
+            journal::sync_dir(&self.path)?;
+            self.roots_undo = Some((pos, dropped));
+        }
+
+        self.accumulator_file.set_len(pos)?;
+        Ok(())
+    }
+
+    /// Flushes our changes on our own, if we're keeping too many of them in memory
+    unsafe fn maybe_flush(&mut self) -> Result<(), FlatChainstoreError> {
+        let dirty = self.headers.dirty_count()?
+            + self.block_index.index_map.dirty_count()?
+            + self.fork_headers.dirty_count()?
+            + self.metadata.dirty_count()?;
+
+        if dirty > MAX_DIRTY_PAGES {
+            self.do_flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Atomically writes all our changes since the last flush, see [`journal`]
+    unsafe fn do_flush(&mut self) -> Result<(), FlatChainstoreError> {
+        // The roots our headers point to must be on disk before we commit those headers
+        self.accumulator_file.sync_data()?;
+
+        let checksum = self.compute_checksum();
+        self.get_metadata_mut()?.checksum = checksum;
+
+        let mut journal = Journal {
+            replaced: self.replaced,
+            pages: Vec::new(),
+        };
+
+        let maps = [
+            (journal::HEADERS, &self.headers),
+            (journal::INDEX, &self.block_index.index_map),
+            (journal::FORK_HEADERS, &self.fork_headers),
+            (journal::METADATA, &self.metadata),
+        ];
+
+        for (file, map) in maps {
+            for (offset, data) in map.dirty_pages()? {
+                journal.pages.push((file, offset, data));
+            }
+        }
+
+        #[cfg(test)]
+        if let Some(crash) = self.crash_at {
+            return crash.simulate(&self.path, &journal);
+        }
+
+        // Once the journal is on disk, this flush is committed
+        let journal_path = self.path.join(journal::JOURNAL_FILE);
+        journal.write(&journal_path)?;
+
+        journal.apply(&self.path)?;
+
+        // The roots we've truncated away are gone for good. This must go before the journal, or
+        // we could find the undo without a journal after a crash, and roll it back
+        if self.roots_undo.take().is_some() {
+            fs::remove_file(self.path.join(journal::ROOTS_UNDO_FILE))?;
+        }
+
+        fs::remove_file(&journal_path)?;
+        self.replaced = 0;
+
+        self.remap()
+    }
+
+    /// Maps our files again, now that our changes are written to them
+    ///
+    /// This frees the private copies of the pages we've changed, and the list of dirty pages.
+    unsafe fn remap(&mut self) -> Result<(), FlatChainstoreError> {
+        self.headers.remap()?;
+        self.fork_headers.remap()?;
+        self.metadata.remap()?;
+        self.block_index.index_map.remap()?;
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    #[doc(hidden)]
+    fn get_cache_mut(
+        &self,
+    ) -> Result<MutexGuard<'_, CacheType>, PoisonError<MutexGuard<'_, CacheType>>> {
+        self.cache.lock()
+    }
+}
+
+impl ChainStore for FlatChainStore {
+    type Error = FlatChainstoreError;
+
+    fn check_integrity(&self) -> Result<(), Self::Error> {
+        self.check_integrity()
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        unsafe { self.do_flush() }
+    }
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        let index = Index::new(height)?;
+
+        let metadata = unsafe { self.get_metadata()? };
+        let validation_index = self
+            .get_header(&metadata.validation_index)?
+            .map(|h| {
+                h.try_height()
+                    .map_err(|_| FlatChainstoreError::InvalidValidationIndex)
+            })
+            .transpose()?
+            .unwrap_or(0);
+
+        if height <= validation_index {
+            // this is probably a reorg, truncate the file up to the previous block height
+            // this is where the new acc starts, truncating the file to this position
+            let pos = unsafe { self.roots_end_before(height)? };
+            unsafe { self.truncate_roots(pos)? };
+
+            // The roots we write from here on weren't pruned yet
+            self.roots_pruned_height = self.roots_pruned_height.min(height);
         }
 
         let pos = self.accumulator_file.seek(SeekFrom::End(0))?;
@@ -1165,7 +1749,15 @@ impl ChainStore for FlatChainStore {
         header.acc_len = size as u32;
 
         self.accumulator_file.write_all(&roots)?;
-        self.accumulator_file.flush()?;
+
+        if let Some(retention) = self.roots_retention {
+            // Only compact once we have `keep_recent` new roots to delete, so we don't rewrite
+            // the end of the file for every block
+            let prunable = height.saturating_sub(retention.keep_recent);
+            if prunable >= self.roots_pruned_height + retention.keep_recent.max(1) {
+                unsafe { self.prune_roots(retention, height)? };
+            }
+        }
 
         Ok(())
     }
@@ -1238,12 +1830,14 @@ impl ChainStore for FlatChainStore {
             DiskBlockHeader::FullyValid(_, _)
             | DiskBlockHeader::HeadersOnly(_, _)
             | DiskBlockHeader::AssumedValid(_, _) => unsafe {
-                self.write_header_to_storage(*header)
+                self.write_header_to_storage(*header)?
             },
             DiskBlockHeader::InFork(_, _)
             | DiskBlockHeader::Orphan(_)
-            | DiskBlockHeader::InvalidChain(_) => unsafe { self.save_fork_block(*header) },
+            | DiskBlockHeader::InvalidChain(_) => unsafe { self.save_fork_block(*header)? },
         }
+
+        unsafe { self.maybe_flush() }
     }
 
     fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
@@ -1261,7 +1855,303 @@ impl ChainStore for FlatChainStore {
     fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), Self::Error> {
         let index = Index::new(height)?;
 
-        unsafe { self.add_index_entry(hash, index) }
+        unsafe {
+            self.add_index_entry(hash, index)?;
+            self.maybe_flush()
+        }
+    }
+}
+
+/// A write-ahead journal, making each flush atomic across all our files
+///
+/// While running, we never write to our files directly: they're mapped as copy-on-write, and we
+/// keep track of which pages we've changed (see [`JournaledMap`]). When flushing, we first write
+/// all those pages into a journal file, ending with a checksum, and sync it. Once the journal is
+/// on disk, the flush is committed: we then write the pages into their files, and delete the
+/// journal. If we crash before the journal is complete, our files are just like they were after
+/// the previous flush, and we throw the journal away. If we crash after that, we write the pages
+/// from the journal again when opening the store.
+///
+/// Files we rebuild from scratch, like the accumulators file when we compact it, are written next
+/// to the old ones with a `.new` extension. The journal tells us to rename them over the old ones,
+/// before writing its pages.
+///
+/// The accumulators file is the only one we change in place without journaling, since we mostly
+/// append to it. The exception is a reorg, where we truncate it, and the roots we drop go to an
+/// undo file first. It has the same format as the journal, and it's written back if we crash
+/// before committing our next flush.
+mod journal {
+    extern crate std;
+
+    use std::fs;
+    use std::fs::File;
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+    use std::path::Path;
+    use std::vec::Vec;
+
+    use tracing::info;
+    use tracing::warn;
+    use twox_hash::XxHash3_64;
+
+    /// The name of our journal file
+    pub(super) const JOURNAL_FILE: &str = "journal.bin";
+
+    /// The magic number at the start of our journal
+    ///
+    /// Just like [`super::FLAT_CHAINSTORE_MAGIC`], this is backwards so it reads right in a hex
+    /// dump.
+    const JOURNAL_MAGIC: u32 = 0x6C_6E_6A_66; // "fjnl" backwards
+
+    /// The name of the file where we keep the roots a reorg has dropped, until our next flush
+    pub(super) const ROOTS_UNDO_FILE: &str = "accumulators.undo";
+
+    /// The files we journal, indexed by the file ids below
+    const JOURNALED_FILES: [&str; 5] = [
+        "headers.bin",
+        "blocks_index.bin",
+        "fork_headers.bin",
+        "metadata.bin",
+        "accumulators.bin",
+    ];
+
+    /// The id of the headers file
+    pub(super) const HEADERS: u8 = 0;
+
+    /// The id of the block index file
+    pub(super) const INDEX: u8 = 1;
+
+    /// The id of the fork headers file
+    pub(super) const FORK_HEADERS: u8 = 2;
+
+    /// The id of the metadata file
+    pub(super) const METADATA: u8 = 3;
+
+    /// The id of the accumulators file
+    ///
+    /// This is only used in the undo file, and a page of it replaces everything from its offset
+    /// to the end of the file.
+    pub(super) const ROOTS: u8 = 4;
+
+    /// The files we may replace by their `.new` version, indexed by the flag bits below
+    pub(super) const REPLACEABLE_FILES: [&str; 1] = ["accumulators.bin"];
+
+    /// We've compacted the accumulators file
+    pub(super) const REPLACE_ROOTS: u8 = 1 << 0;
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    /// All the changes of one flush
+    pub(super) struct Journal {
+        /// Which files we replace by their `.new` version, see [`REPLACEABLE_FILES`]
+        pub(super) replaced: u8,
+
+        /// The pages we write, as (file id, offset, contents)
+        pub(super) pages: Vec<(u8, u64, Vec<u8>)>,
+    }
+
+    impl Journal {
+        /// Serializes this journal, as it's written to disk
+        ///
+        /// The format is: magic (4 bytes), replaced flags (1 byte), the number of pages (4 bytes),
+        /// then each page as file id (1 byte), offset (8 bytes), length (4 bytes) and contents.
+        /// Everything is followed by the XXH3-64 of what comes before it (8 bytes). All integers
+        /// are little-endian.
+        pub(super) fn serialize(&self) -> Vec<u8> {
+            let mut data = Vec::new();
+            data.extend_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+            data.push(self.replaced);
+            data.extend_from_slice(&(self.pages.len() as u32).to_le_bytes());
+
+            for (file, offset, page) in &self.pages {
+                data.push(*file);
+                data.extend_from_slice(&offset.to_le_bytes());
+                data.extend_from_slice(&(page.len() as u32).to_le_bytes());
+                data.extend_from_slice(page);
+            }
+
+            let checksum = XxHash3_64::oneshot(&data);
+            data.extend_from_slice(&checksum.to_le_bytes());
+
+            data
+        }
+
+        /// Parses a journal we've written, returning `None` if it's incomplete or corrupted
+        pub(super) fn deserialize(data: &[u8]) -> Option<Self> {
+            let body_len = data.len().checked_sub(8)?;
+            let (body, checksum) = data.split_at(body_len);
+            if XxHash3_64::oneshot(body).to_le_bytes() != checksum {
+                return None;
+            }
+
+            let mut reader = Reader(body);
+            if u32::from_le_bytes(reader.take()?) != JOURNAL_MAGIC {
+                return None;
+            }
+
+            let [replaced] = reader.take()?;
+            let count = u32::from_le_bytes(reader.take()?);
+            let mut pages = Vec::new();
+
+            for _ in 0..count {
+                let [file] = reader.take()?;
+                let offset = u64::from_le_bytes(reader.take()?);
+                let len = u32::from_le_bytes(reader.take()?) as usize;
+
+                if file as usize >= JOURNALED_FILES.len() {
+                    return None;
+                }
+
+                pages.push((file, offset, reader.take_slice(len)?.to_vec()));
+            }
+
+            Some(Journal { replaced, pages })
+        }
+
+        /// Writes this journal to `path`, and makes sure it reached the disk
+        pub(super) fn write(&self, path: &Path) -> io::Result<()> {
+            let mut file = File::create(path)?;
+            file.write_all(&self.serialize())?;
+            file.sync_data()?;
+
+            Ok(())
+        }
+
+        /// Writes the changes in this journal into our files
+        ///
+        /// Doing this more than once is harmless, so we can always do it again after a crash.
+        pub(super) fn apply(&self, datadir: &Path) -> io::Result<()> {
+            for (bit, name) in REPLACEABLE_FILES.iter().enumerate() {
+                if self.replaced & (1 << bit) == 0 {
+                    continue;
+                }
+
+                // If it isn't there, we've renamed it already, before crashing
+                let new_path = datadir.join(name).with_extension("bin.new");
+                if new_path.exists() {
+                    fs::rename(&new_path, datadir.join(name))?;
+                }
+            }
+
+            sync_dir(datadir)?;
+
+            let mut files: [Option<File>; JOURNALED_FILES.len()] = Default::default();
+            for (file, offset, page) in &self.pages {
+                let id = *file as usize;
+                if files[id].is_none() {
+                    let path = datadir.join(JOURNALED_FILES[id]);
+                    files[id] = Some(OpenOptions::new().write(true).open(path)?);
+                }
+
+                let Some(file) = files[id].as_mut() else {
+                    unreachable!("we've just opened this file");
+                };
+
+                if id == ROOTS as usize {
+                    file.set_len(*offset)?;
+                }
+
+                file.seek(SeekFrom::Start(*offset))?;
+                file.write_all(page)?;
+            }
+
+            for file in files.into_iter().flatten() {
+                file.sync_data()?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// A cursor over a journal we're parsing
+    struct Reader<'a>(&'a [u8]);
+
+    impl Reader<'_> {
+        /// Takes the next `N` bytes, if we have that many
+        fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+            self.take_slice(N)?.try_into().ok()
+        }
+
+        /// Takes the next `len` bytes, if we have that many
+        fn take_slice(&mut self, len: usize) -> Option<&[u8]> {
+            if self.0.len() < len {
+                return None;
+            }
+
+            let (taken, rest) = self.0.split_at(len);
+            self.0 = rest;
+
+            Some(taken)
+        }
+    }
+
+    /// Makes sure the files we've created, renamed or deleted inside `dir` are persisted
+    pub(super) fn sync_dir(_dir: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(_dir)?.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Finishes, or throws away, the flush we were doing when we last stopped
+    ///
+    /// If we find a complete journal, that flush was committed, so we write its changes again.
+    /// Otherwise, our files are as they were after the flush before it, except for the roots a
+    /// reorg may have dropped since, which we write back from the undo file. Either way, we
+    /// delete any file we were rebuilding that didn't get committed.
+    pub(super) fn recover(datadir: &Path) -> io::Result<()> {
+        let journal_path = datadir.join(JOURNAL_FILE);
+        let undo_path = datadir.join(ROOTS_UNDO_FILE);
+
+        match fs::read(&journal_path) {
+            Ok(data) => match Journal::deserialize(&data) {
+                Some(journal) => {
+                    info!("Finishing our last FlatChainStore flush, from the journal");
+                    journal.apply(datadir)?;
+
+                    // That flush made the roots we've dropped obsolete
+                    if undo_path.exists() {
+                        fs::remove_file(&undo_path)?;
+                    }
+                }
+                None => warn!("Discarding an incomplete FlatChainStore flush"),
+            },
+
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        match fs::read(&undo_path) {
+            Ok(data) => {
+                // If it's incomplete, we crashed before truncating anything
+                if let Some(undo) = Journal::deserialize(&data) {
+                    info!("Restoring the accumulators dropped by an uncommitted reorg");
+                    undo.apply(datadir)?;
+                }
+
+                fs::remove_file(&undo_path)?;
+            }
+
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if journal_path.exists() {
+            fs::remove_file(&journal_path)?;
+        }
+
+        for name in REPLACEABLE_FILES {
+            for extension in ["bin.new", "bin.tmp"] {
+                let path = datadir.join(name).with_extension(extension);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -1364,11 +2254,13 @@ pub mod migrate_v0_to_v1 {
 #[cfg(test)]
 mod tests {
     use core::mem::size_of;
+    use core::ops::RangeInclusive;
+    use std::env;
     use std::fs;
+    use std::process::Command;
+    use std::process::Stdio;
 
-    use bitcoin::block::Header;
     use bitcoin::consensus::deserialize;
-    use bitcoin::consensus::Decodable;
     use bitcoin::constants::genesis_block;
     use bitcoin::hashes::Hash;
     use bitcoin::Block;
@@ -1378,20 +2270,22 @@ mod tests {
     use tempfile::TempDir;
     use twox_hash::XxHash3_64;
 
+    use super::journal;
+    use super::CrashPoint;
     use super::FlatChainStore;
     use super::FlatChainStoreConfig;
     use super::FlatChainstoreError;
     use super::Index;
+    use super::Journal;
+    use super::RootsRetention;
     use super::FLAT_CHAINSTORE_MAGIC;
     use super::FLAT_CHAINSTORE_VERSION;
     use crate::migrate_v0_to_v1::init_mmap;
     use crate::migrate_v0_to_v1::maybe_migrate;
+    use crate::pruned_utreexo::chainstore::test_suite;
     use crate::pruned_utreexo::flat_chain_store::FileChecksum;
     use crate::pruned_utreexo::flat_chain_store::Metadata;
-    use crate::pruned_utreexo::UpdatableChainstate;
-    use crate::AssumeValidArg;
     use crate::BestChain;
-    use crate::ChainState;
     use crate::ChainStore;
     use crate::DbCheckSum;
     use crate::DiskBlockHeader;
@@ -1434,6 +2328,7 @@ mod tests {
             fork_file_size: Some(10_000), // Will be rounded up to 16,384
             cache_size: Some(10),
             file_permission: Some(0o660),
+            roots_retention: None,
             path: format!("./tmp-db/{test_id}/").into(),
         };
 
@@ -1565,26 +2460,9 @@ mod tests {
     #[test]
     fn test_save_and_retrieve_headers() {
         let mut store = get_test_chainstore(None).unwrap();
-        let blocks = include_str!("../../testdata/regtest_blocks.txt");
-
-        for (i, line) in blocks.lines().enumerate() {
-            let block = hex::decode(line).unwrap();
-            let block: Block = deserialize(&block).unwrap();
-
-            store
-                .save_header(&DiskBlockHeader::FullyValid(block.header, i as u32))
-                .unwrap();
-            // Map hashes to block indices such that we can later fetch headers from hashes
-            // (hash -> index -> header)
-            store
-                .update_block_index(i as u32, block.block_hash())
-                .unwrap();
-        }
+        test_suite::save_and_retrieve_headers(&mut store);
 
         for i in 0..151 {
-            // Reads the disk header at index `i` directly from the file
-            let header = store.get_header_by_height(i).unwrap().unwrap();
-            // Reads the header hash at index `i` directly from the file
             let hash = store.get_block_hash(i).unwrap().unwrap();
 
             // Gets the header via the LRU cache, or else via hash -> index -> header
@@ -1592,40 +2470,7 @@ mod tests {
             // Gets the header via hash -> index -> header
             let header_by_hash = unsafe { store.get_header_by_hash(hash).unwrap().unwrap() };
 
-            assert_eq!(header, header_by_hash);
-            assert_eq!(header, header_by_hash_cached);
-            assert_eq!(
-                header.block_hash(),
-                hash,
-                "Returned header matches the hash"
-            );
-            assert_eq!(
-                i,
-                header.try_height().unwrap(),
-                "Returned header has the correct height"
-            );
-
-            if i == 0 {
-                // Must be the regtest genesis header
-                let regtest_genesis = genesis_block(Network::Regtest);
-                assert_eq!(regtest_genesis.block_hash(), hash);
-                assert_eq!(regtest_genesis.header, *header);
-            }
-        }
-
-        match store.get_block_hash(151).unwrap() {
-            None => (),
-            Some(hash) => panic!("Should not have found a block at height 151, hash: {hash}"),
-        }
-        match store.get_header_by_height(151).unwrap() {
-            None => (),
-            Some(header) => panic!("Should not have found a header at height 151: {header:?}"),
-        }
-        match store.get_header(&BlockHash::all_zeros()).unwrap() {
-            None => (),
-            Some(header) => {
-                panic!("Should not have found a header with hash all_zeros: {header:?}")
-            }
+            assert_eq!(header_by_hash, header_by_hash_cached);
         }
 
         // Test that the inner header-fetching function returns the proper error for mainnet indices
@@ -1677,18 +2522,37 @@ mod tests {
 
     #[test]
     fn test_save_height() {
-        let mut store = get_test_chainstore(None).unwrap();
-        let height = BestChain {
-            alternative_tips: Vec::new(),
-            validation_index: genesis_block(Network::Signet).block_hash(),
+        test_suite::save_height(&mut get_test_chainstore(None).unwrap());
+    }
+
+    #[test]
+    fn test_save_height_shrinks_tips() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+
+        let genesis = genesis_block(Network::Regtest).block_hash();
+        let tips: Vec<BlockHash> = (1..=3)
+            .map(|n| BlockHash::from_byte_array([n; 32]))
+            .collect();
+        let mut best_chain = BestChain {
+            best_block: genesis,
             depth: 1,
-            best_block: genesis_block(Network::Signet).block_hash(),
+            validation_index: genesis,
+            alternative_tips: tips.clone(),
         };
 
-        store.save_height(&height).unwrap();
+        store.save_height(&best_chain).unwrap();
+        store.flush().unwrap();
+
+        // After a reorg, we may have fewer alternative tips than before
+        best_chain.alternative_tips = vec![tips[2]];
+        store.save_height(&best_chain).unwrap();
+        store.flush().unwrap();
+        drop(store);
 
-        let recovered = store.load_height().unwrap().unwrap();
-        assert_eq!(recovered, height);
+        // The tips we've dropped must not come back after a restart
+        let store = get_test_chainstore(Some(test_id)).unwrap();
+        assert_eq!(store.load_height().unwrap(), Some(best_chain));
     }
 
     #[test]
@@ -1723,70 +2587,27 @@ mod tests {
 
     #[test]
     fn accept_mainnet_headers() {
-        // Accepts the first 10235 mainnet headers
-        let file = include_bytes!("../../testdata/headers.zst");
-        let uncompressed: Vec<u8> = zstd::decode_all(std::io::Cursor::new(file)).unwrap();
-        let store = get_test_chainstore(None).unwrap();
-        let chain = ChainState::open(store, Network::Bitcoin, AssumeValidArg::Hardcoded).unwrap();
-        let mut buffer = uncompressed.as_slice();
-
-        while let Ok(header) = Header::consensus_decode(&mut buffer) {
-            chain.accept_header(header).unwrap();
-        }
+        test_suite::accept_mainnet_headers(get_test_chainstore(None).unwrap());
     }
 
     #[test]
     fn accept_first_signet_headers() {
-        // Accepts the first 2016 signet headers
-        let file = include_bytes!("../../testdata/signet_headers.zst");
-        let uncompressed: Vec<u8> = zstd::decode_all(std::io::Cursor::new(file)).unwrap();
-        let store = get_test_chainstore(None).unwrap();
-        let chain = ChainState::open(store, Network::Signet, AssumeValidArg::Hardcoded).unwrap();
-        let mut buffer = uncompressed.as_slice();
-
-        while let Ok(header) = Header::consensus_decode(&mut buffer) {
-            chain.accept_header(header).unwrap();
-        }
+        test_suite::accept_first_signet_headers(get_test_chainstore(None).unwrap());
     }
 
     #[test]
     fn test_fork_blocks() {
-        let mut store = get_test_chainstore(None).unwrap();
-        let file = include_str!("../../testdata/regtest_blocks.txt");
-        let headers = file
-            .lines()
-            .map(|x| hex::decode(x).unwrap())
-            .collect::<Vec<_>>();
-
-        let (blocks, forks) = headers.split_at(headers.len() / 2);
-        for (i, block) in blocks.iter().enumerate() {
-            let block: Block = deserialize(block).unwrap();
-
-            store
-                .save_header(&DiskBlockHeader::FullyValid(block.header, i as u32))
-                .unwrap();
-
-            store
-                .update_block_index(i as u32, block.block_hash())
-                .unwrap();
-        }
-
-        let mut hashes = Vec::new();
-        for (i, block) in forks.iter().enumerate() {
-            let block: Block = deserialize(block).unwrap();
-
-            hashes.push(block.block_hash());
-
-            store
-                .save_header(&DiskBlockHeader::InFork(block.header, i as u32))
-                .unwrap();
-        }
+        test_suite::fork_blocks(&mut get_test_chainstore(None).unwrap());
+    }
 
-        for hash in hashes {
-            let header = store.get_header(&hash).unwrap().unwrap();
+    #[test]
+    fn test_reorg_index() {
+        test_suite::reorg_index(&mut get_test_chainstore(None).unwrap());
+    }
 
-            assert_eq!(header.block_hash(), hash);
-        }
+    #[test]
+    fn test_save_and_load_roots() {
+        test_suite::save_and_load_roots(&mut get_test_chainstore(None).unwrap());
     }
 
     #[test]
@@ -1835,4 +2656,431 @@ mod tests {
             Ok(_) => panic!("Should not have been able to save roots for a block we don't have"),
         }
     }
+
+    #[test]
+    fn test_prune_roots() {
+        let retention = RootsRetention {
+            keep_recent: 10,
+            checkpoint_interval: 50,
+        };
+
+        let mut store = get_test_chainstore(None).unwrap();
+        store.roots_retention = Some(retention);
+
+        let blocks = include_str!("../../testdata/regtest_blocks.txt");
+        let mut hashes = Vec::new();
+        for (i, line) in blocks.lines().enumerate() {
+            let block: Block = deserialize(&hex::decode(line).unwrap()).unwrap();
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, i as u32))
+                .unwrap();
+            store
+                .update_block_index(i as u32, block.block_hash())
+                .unwrap();
+            hashes.push(block.block_hash());
+        }
+
+        // Accumulators have different sizes, so moving them around is actually tested
+        let roots = |height: u32| vec![height as u8; 8 + height as usize % 3];
+        for height in 1..=150 {
+            store.save_roots_for_block(roots(height), height).unwrap();
+        }
+
+        let mut kept_size = 0;
+        for height in 1..=150 {
+            let loaded = store.load_roots_for_block(height).unwrap();
+            if retention.keeps(height, 150) {
+                assert_eq!(loaded, Some(roots(height)), "roots for {height}");
+                kept_size += roots(height).len() as u64;
+            } else {
+                assert_eq!(loaded, None, "roots for {height} should be pruned");
+            }
+        }
+
+        assert_eq!(store.accumulator_file.metadata().unwrap().len(), kept_size);
+
+        // A reorg within the roots we keep
+        store
+            .save_height(&BestChain {
+                best_block: hashes[150],
+                depth: 150,
+                validation_index: hashes[150],
+                alternative_tips: vec![],
+            })
+            .unwrap();
+
+        store.save_roots_for_block(vec![0xff; 8], 145).unwrap();
+        assert_eq!(store.load_roots_for_block(144).unwrap(), Some(roots(144)));
+        assert_eq!(
+            store.load_roots_for_block(145).unwrap(),
+            Some(vec![0xff; 8])
+        );
+
+        // A deeper one, where we've pruned the roots. New roots go right after the last
+        // checkpoint, without touching it
+        store.save_roots_for_block(vec![0xfe; 8], 120).unwrap();
+        assert_eq!(store.load_roots_for_block(100).unwrap(), Some(roots(100)));
+        assert_eq!(
+            store.load_roots_for_block(120).unwrap(),
+            Some(vec![0xfe; 8])
+        );
+        assert_eq!(
+            store.accumulator_file.metadata().unwrap().len(),
+            (roots(50).len() + roots(100).len() + 8) as u64
+        );
+    }
+
+    /// Creates a store with a tiny block index, and fills it with the regtest blocks
+    fn get_small_index_chainstore(test_id: u64) -> (FlatChainStore, Vec<BlockHash>) {
+        let config = FlatChainStoreConfig {
+            block_index_size: Some(64),
+            headers_file_size: Some(1024),
+            fork_file_size: Some(1024),
+            cache_size: Some(10),
+            file_permission: Some(0o660),
+            roots_retention: None,
+            path: format!("./tmp-db/{test_id}/").into(),
+        };
+
+        let mut store = FlatChainStore::new(config).unwrap();
+        let blocks = include_str!("../../testdata/regtest_blocks.txt");
+        let mut hashes = Vec::new();
+
+        for (i, line) in blocks.lines().enumerate() {
+            let block: Block = deserialize(&hex::decode(line).unwrap()).unwrap();
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, i as u32))
+                .unwrap();
+            store
+                .update_block_index(i as u32, block.block_hash())
+                .unwrap();
+            hashes.push(block.block_hash());
+        }
+
+        store.flush().unwrap();
+        (store, hashes)
+    }
+
+    /// Opens an existing store, taking the sizes from its metadata
+    fn reopen_test_chainstore(test_id: u64) -> FlatChainStore {
+        let mut config = FlatChainStoreConfig::new(format!("./tmp-db/{test_id}/"));
+        config.cache_size = Some(10);
+
+        FlatChainStore::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_rehash_index() {
+        let test_id = rand::random::<u64>();
+        let (store, hashes) = get_small_index_chainstore(test_id);
+
+        // 150 blocks (the genesis isn't stored) don't fit in half of 256 buckets
+        let metadata = unsafe { store.get_metadata().unwrap() };
+        assert_eq!(metadata.index_capacity, 512);
+        assert_eq!(metadata.block_index_occupancy, 150);
+        assert_eq!(store.block_index.index_size, 512);
+        assert_eq!(
+            fs::metadata(format!("./tmp-db/{test_id}/blocks_index.bin"))
+                .unwrap()
+                .len(),
+            512 * 4
+        );
+
+        let check_blocks = |store: &FlatChainStore| {
+            for (height, hash) in hashes.iter().enumerate() {
+                // Skip the cache, so we actually look into the index
+                let header = unsafe { store.get_header_by_hash(*hash).unwrap().unwrap() };
+                assert_eq!(header.try_height().unwrap(), height as u32);
+            }
+        };
+
+        check_blocks(&store);
+        store.check_integrity().unwrap();
+        drop(store);
+
+        let store = reopen_test_chainstore(test_id);
+        check_blocks(&store);
+        store.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_rehash() {
+        let test_id = rand::random::<u64>();
+        let (mut store, hashes) = get_small_index_chainstore(test_id);
+        let old_metadata = unsafe { *store.get_metadata().unwrap() };
+
+        // We crash after renaming the new index, but before updating our metadata
+        unsafe {
+            store.rehash_index(1024).unwrap();
+            *store.get_metadata_mut().unwrap() = old_metadata;
+        }
+
+        store.metadata.flush().unwrap();
+        drop(store);
+
+        // A crash before the rename leaves the new index behind
+        let new_index_path = format!("./tmp-db/{test_id}/blocks_index.bin.new");
+        fs::write(&new_index_path, [0xff; 64]).unwrap();
+
+        let store = reopen_test_chainstore(test_id);
+        assert!(fs::metadata(&new_index_path).is_err());
+        assert_eq!(store.block_index.index_size, 1024);
+        assert_eq!(
+            unsafe { store.get_metadata().unwrap() }.index_capacity,
+            1024
+        );
+        store.check_integrity().unwrap();
+
+        for hash in hashes {
+            let header = unsafe { store.get_header_by_hash(hash).unwrap().unwrap() };
+            assert_eq!(header.block_hash(), hash);
+        }
+    }
+
+    /// The roots we save for each block in the crash tests
+    fn crash_test_roots(height: u32) -> Vec<u8> {
+        vec![height as u8; 8 + height as usize % 3]
+    }
+
+    /// Saves the regtest blocks in `heights` with their roots, and makes the last one our tip
+    fn save_crash_test_blocks(
+        store: &mut FlatChainStore,
+        blocks: &[Block],
+        heights: RangeInclusive<u32>,
+    ) {
+        for height in heights.clone() {
+            let block = &blocks[height as usize];
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+
+            if height != 0 {
+                store
+                    .save_roots_for_block(crash_test_roots(height), height)
+                    .unwrap();
+            }
+        }
+
+        let tip = blocks[*heights.end() as usize].block_hash();
+        store
+            .save_height(&BestChain {
+                best_block: tip,
+                depth: *heights.end(),
+                validation_index: tip,
+                alternative_tips: vec![],
+            })
+            .unwrap();
+    }
+
+    /// Checks that `store` is exactly like it was after saving the blocks up to `tip`
+    fn check_crash_test_store(store: &mut FlatChainStore, blocks: &[Block], tip: u32) {
+        store.check_integrity().unwrap();
+
+        let best_chain = store.load_height().unwrap().unwrap();
+        assert_eq!(best_chain.depth, tip);
+        assert_eq!(best_chain.best_block, blocks[tip as usize].block_hash());
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+
+            // Skip the cache, so we actually look into the index
+            let header = unsafe { store.get_header_by_hash(block.block_hash()).unwrap() };
+            if height > tip {
+                assert_eq!(header, None, "block {height} was never flushed");
+                assert_eq!(store.get_block_hash(height).unwrap(), None);
+                continue;
+            }
+
+            assert_eq!(header.unwrap().try_height().unwrap(), height);
+
+            // Roots may be pruned a bit later than they could, but they are never wrong
+            let roots = store.load_roots_for_block(height).unwrap();
+            match roots {
+                Some(roots) => assert_eq!(roots, crash_test_roots(height), "roots for {height}"),
+                None => assert!(
+                    height == 0 || !RETENTION.keeps(height, tip),
+                    "roots for {height} are missing"
+                ),
+            }
+        }
+
+        for name in [
+            "journal.bin",
+            "blocks_index.bin.new",
+            "accumulators.bin.new",
+            "accumulators.undo",
+        ] {
+            assert!(
+                fs::metadata(store.path.join(name)).is_err(),
+                "{name} is left"
+            );
+        }
+    }
+
+    /// The roots we keep in the crash tests, so we also prune roots while crashing
+    const RETENTION: RootsRetention = RootsRetention {
+        keep_recent: 10,
+        checkpoint_interval: 50,
+    };
+
+    /// Tells a crash test that it's running in the child process that should crash, and which
+    /// store it should use
+    const CRASH_TEST_ENV: &str = "FLAT_CHAINSTORE_CRASH_TEST_ID";
+
+    /// Flushes the regtest blocks up to 100, makes some `changes`, then crashes at `crash` while
+    /// flushing them
+    ///
+    /// The crash is real: `test` runs again in a child process, which does all of the above and
+    /// aborts halfway through the flush. Back in the parent, this returns the id of the store the
+    /// child left behind, for the test to reopen and check.
+    fn crash_while_flushing(
+        test: &str,
+        crash: CrashPoint,
+        changes: impl FnOnce(&mut FlatChainStore, &[Block]),
+    ) -> (u64, Vec<Block>) {
+        let blocks: Vec<Block> = include_str!("../../testdata/regtest_blocks.txt")
+            .lines()
+            .map(|line| deserialize(&hex::decode(line).unwrap()).unwrap())
+            .collect();
+
+        if let Ok(test_id) = env::var(CRASH_TEST_ENV) {
+            let config = FlatChainStoreConfig {
+                block_index_size: Some(512),
+                headers_file_size: Some(1024),
+                fork_file_size: Some(1024),
+                cache_size: Some(10),
+                file_permission: Some(0o660),
+                roots_retention: Some(RETENTION),
+                path: format!("./tmp-db/{test_id}/").into(),
+            };
+
+            let mut store = FlatChainStore::new(config).unwrap();
+            save_crash_test_blocks(&mut store, &blocks, 0..=100);
+            store.flush().unwrap();
+
+            changes(&mut store, &blocks);
+            store.crash_at = Some(crash);
+            let _ = store.flush();
+
+            unreachable!("we should have crashed while flushing");
+        }
+
+        let test_id = rand::random::<u64>();
+        let (_, module) = module_path!().split_once("::").unwrap();
+        let status = Command::new(env::current_exe().unwrap())
+            .args([&format!("{module}::{test}"), "--exact", "--nocapture"])
+            .env(CRASH_TEST_ENV, test_id.to_string())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+
+        // Libtest exits with 101 if the test fails, and with 0 if it didn't run any test
+        assert_ne!(status.code(), Some(0), "the child process didn't crash");
+        assert_ne!(status.code(), Some(101), "the child process panicked");
+
+        (test_id, blocks)
+    }
+
+    /// Saves the regtest blocks from 101 to 150, so our flush also replaces the accumulators file
+    fn save_more_blocks(store: &mut FlatChainStore, blocks: &[Block]) {
+        save_crash_test_blocks(store, blocks, 101..=150);
+        assert_ne!(store.replaced, 0);
+    }
+
+    #[test]
+    fn test_crash_writing_journal() {
+        let (test_id, blocks) = crash_while_flushing(
+            "test_crash_writing_journal",
+            CrashPoint::WritingJournal,
+            save_more_blocks,
+        );
+
+        // The journal is incomplete, so we go back to our first flush
+        let mut store = reopen_test_chainstore(test_id);
+        store.roots_retention = Some(RETENTION);
+        check_crash_test_store(&mut store, &blocks, 100);
+
+        // And we can carry on from there
+        save_crash_test_blocks(&mut store, &blocks, 101..=150);
+        store.flush().unwrap();
+        drop(store);
+
+        let mut store = reopen_test_chainstore(test_id);
+        check_crash_test_store(&mut store, &blocks, 150);
+    }
+
+    #[test]
+    fn test_crash_before_applying_journal() {
+        let (test_id, blocks) = crash_while_flushing(
+            "test_crash_before_applying_journal",
+            CrashPoint::BeforeApplying,
+            save_more_blocks,
+        );
+
+        // The flush was committed, so we finish it
+        let mut store = reopen_test_chainstore(test_id);
+        check_crash_test_store(&mut store, &blocks, 150);
+    }
+
+    #[test]
+    fn test_crash_applying_journal() {
+        let (test_id, blocks) = crash_while_flushing(
+            "test_crash_applying_journal",
+            CrashPoint::Applying,
+            save_more_blocks,
+        );
+
+        let mut store = reopen_test_chainstore(test_id);
+        check_crash_test_store(&mut store, &blocks, 150);
+    }
+
+    #[test]
+    fn test_crash_after_reorg() {
+        let (test_id, blocks) = crash_while_flushing(
+            "test_crash_after_reorg",
+            CrashPoint::WritingJournal,
+            |store, _| {
+                // Replacing the roots of our last blocks truncates the file we've flushed
+                for height in 95..=100 {
+                    store.save_roots_for_block(vec![0xff; 8], height).unwrap();
+                }
+
+                assert!(store.path.join(journal::ROOTS_UNDO_FILE).exists());
+            },
+        );
+
+        // We get the roots we've truncated away back
+        let mut store = reopen_test_chainstore(test_id);
+        check_crash_test_store(&mut store, &blocks, 100);
+    }
+
+    #[test]
+    fn test_journal_serialization() {
+        let journal = Journal {
+            replaced: journal::REPLACE_ROOTS,
+            pages: vec![
+                (journal::HEADERS, 4096, vec![1; 4096]),
+                (journal::METADATA, 0, vec![2; 100]),
+            ],
+        };
+
+        let data = journal.serialize();
+        assert_eq!(Journal::deserialize(&data), Some(journal));
+
+        // Incomplete or corrupted journals are never applied
+        for len in [0, 8, data.len() / 2, data.len() - 1] {
+            assert_eq!(Journal::deserialize(&data[..len]), None);
+        }
+
+        let mut corrupted = data.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(Journal::deserialize(&corrupted), None);
+    }
 }
@@ -0,0 +1,504 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A chainstore on top of [`kv`], an embedded key-value database
+//!
+//! Unlike the `FlatChainStore`, this store doesn't use memory maps or any unsafe code, so it
+//! works anywhere we have a regular filesystem, like sandboxed environments where we can't mmap
+//! files. In exchange, it's slower and takes more disk space, since every header is indexed in a
+//! B-tree, instead of sitting in a flat file.
+//!
+//! Everything lives in a single bucket, and each key starts with a byte telling what it holds:
+//! headers are indexed by block hash, and the block index and accumulators by height. Our writes
+//! are kept in memory until we flush, and then written as a single batch, which the database
+//! applies atomically. So after a crash, we always find the store as it was after some flush.
+
+extern crate std;
+
+use core::error;
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use std::path::Path;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use floresta_common::impl_error_from;
+use floresta_common::prelude::*;
+use kv::Batch;
+use kv::Bucket;
+use kv::Config;
+use kv::Raw;
+use kv::Store;
+
+use crate::BestChain;
+use crate::ChainStore;
+use crate::DatabaseError;
+use crate::DiskBlockHeader;
+
+/// The version of our data layout
+const KV_CHAINSTORE_VERSION: u32 = 1;
+
+/// Headers are keyed by this byte, followed by their block hash
+const HEADER_PREFIX: u8 = b'h';
+
+/// Block hashes in our best chain are keyed by this byte, followed by their big-endian height
+const INDEX_PREFIX: u8 = b'i';
+
+/// Accumulators are keyed by this byte, followed by their block's big-endian height
+const ROOTS_PREFIX: u8 = b'r';
+
+/// The key for our best chain
+const BEST_CHAIN_KEY: &[u8] = b"best_chain";
+
+/// The key for the version of our data layout
+const VERSION_KEY: &[u8] = b"version";
+
+/// How many writes we keep in memory before flushing them on our own
+///
+/// During IBD, we may save hundreds of thousands of headers between flushes. This bounds how much
+/// memory we use for them.
+const MAX_PENDING_WRITES: usize = 100_000;
+
+#[derive(Debug)]
+/// Errors that can happen whilst interacting with the [`KvChainStore`].
+pub enum KvChainStoreError {
+    /// An error from the underlying database.
+    Kv(kv::Error),
+
+    /// We couldn't decode some data we've read from the database.
+    ///
+    /// Usually indicates that the database is corrupted.
+    Encoding(encode::Error),
+
+    /// Attempted to open a [`KvChainStore`] database using an unsupported schema.
+    UnsupportedSchema(u32),
+
+    /// We tried to save the roots for a height we have no block for.
+    HeaderNotFound(u32),
+}
+
+impl Display for KvChainStoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kv(e) => write!(f, "KvChainStore database error: {e}"),
+            Self::Encoding(e) => write!(f, "KvChainStore has invalid data: {e}"),
+            Self::UnsupportedSchema(version) => write!(f, "Attempted to open the KvChainStore database using an unsupported schema with version={version}"),
+            Self::HeaderNotFound(height) => write!(f, "There's no block at height {height} in the KvChainStore"),
+        }
+    }
+}
+
+impl error::Error for KvChainStoreError {}
+
+/// Need this to use [KvChainStoreError] as a [DatabaseError] in [ChainStore]
+impl DatabaseError for KvChainStoreError {}
+
+impl_error_from!(KvChainStoreError, kv::Error, Kv);
+impl_error_from!(KvChainStoreError, encode::Error, Encoding);
+
+/// A [`ChainStore`] backed by [`kv`]
+///
+/// See the [module documentation](self) for how we store our data.
+pub struct KvChainStore {
+    /// Our database, we must keep it open while we use our bucket
+    _store: Store,
+
+    /// The bucket holding all our data
+    bucket: Bucket<'static, Raw, Vec<u8>>,
+
+    /// Headers we've saved since our last flush
+    headers: HashMap<BlockHash, DiskBlockHeader>,
+
+    /// Block hashes we've indexed since our last flush, by height
+    index: HashMap<u32, BlockHash>,
+
+    /// Accumulators we've saved since our last flush, by height
+    ///
+    /// `None` means the accumulator at this height was reset, and should be removed on flush.
+    roots: HashMap<u32, Option<Vec<u8>>>,
+
+    /// Our best chain, if we've saved it since our last flush
+    best_chain: Option<BestChain>,
+}
+
+impl KvChainStore {
+    /// Opens the store in `datadir`, creating a new one if it doesn't exist
+    pub fn new(datadir: impl AsRef<Path>) -> Result<Self, KvChainStoreError> {
+        let store = Store::new(Config::new(datadir.as_ref()))?;
+        let bucket = store.bucket::<Raw, Vec<u8>>(Some("chainstore"))?;
+
+        match bucket.get(&Raw::from(VERSION_KEY))? {
+            Some(version) => {
+                let version: u32 = deserialize(&version)?;
+                if version > KV_CHAINSTORE_VERSION {
+                    return Err(KvChainStoreError::UnsupportedSchema(version));
+                }
+            }
+
+            None => {
+                bucket.set(&Raw::from(VERSION_KEY), &serialize(&KV_CHAINSTORE_VERSION))?;
+                bucket.flush()?;
+            }
+        }
+
+        Ok(Self {
+            _store: store,
+            bucket,
+            headers: HashMap::new(),
+            index: HashMap::new(),
+            roots: HashMap::new(),
+            best_chain: None,
+        })
+    }
+
+    /// Returns the key for something we keep by height
+    fn height_key(prefix: u8, height: u32) -> Raw {
+        let mut key = vec![prefix];
+        key.extend_from_slice(&height.to_be_bytes());
+
+        Raw::from(key)
+    }
+
+    /// Returns the key for a header
+    fn header_key(block_hash: &BlockHash) -> Raw {
+        let mut key = vec![HEADER_PREFIX];
+        key.extend_from_slice(block_hash.as_byte_array());
+
+        Raw::from(key)
+    }
+
+    /// Flushes our writes on our own, if we're keeping too many of them in memory
+    fn maybe_flush(&mut self) -> Result<(), KvChainStoreError> {
+        let pending = self.headers.len() + self.index.len() + self.roots.len();
+        if pending > MAX_PENDING_WRITES {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ChainStore for KvChainStore {
+    type Error = KvChainStoreError;
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        if self.get_block_hash(height)?.is_none() {
+            return Err(KvChainStoreError::HeaderNotFound(height));
+        }
+
+        self.roots.insert(height, Some(roots));
+        self.maybe_flush()
+    }
+
+    fn load_roots_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        if let Some(roots) = self.roots.get(&height) {
+            return Ok(roots.clone());
+        }
+
+        Ok(self.bucket.get(&Self::height_key(ROOTS_PREFIX, height))?)
+    }
+
+    fn load_height(&self) -> Result<Option<BestChain>, Self::Error> {
+        if let Some(best_chain) = &self.best_chain {
+            return Ok(Some(best_chain.clone()));
+        }
+
+        let Some(best_chain) = self.bucket.get(&Raw::from(BEST_CHAIN_KEY))? else {
+            return Ok(None);
+        };
+
+        Ok(Some(deserialize(&best_chain)?))
+    }
+
+    fn save_height(&mut self, height: &BestChain) -> Result<(), Self::Error> {
+        self.best_chain = Some(height.clone());
+        Ok(())
+    }
+
+    fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        if let Some(header) = self.headers.get(block_hash) {
+            return Ok(Some(*header));
+        }
+
+        let Some(header) = self.bucket.get(&Self::header_key(block_hash))? else {
+            return Ok(None);
+        };
+
+        Ok(Some(deserialize(&header)?))
+    }
+
+    fn get_header_by_height(&self, height: u32) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        let Some(block_hash) = self.get_block_hash(height)? else {
+            return Ok(None);
+        };
+
+        self.get_header(&block_hash)
+    }
+
+    fn save_header(&mut self, header: &DiskBlockHeader) -> Result<(), Self::Error> {
+        self.headers.insert(header.block_hash(), *header);
+
+        match header {
+            DiskBlockHeader::FullyValid(_, height)
+            | DiskBlockHeader::HeadersOnly(_, height)
+            | DiskBlockHeader::AssumedValid(_, height) => {
+                // Like in the other stores, (re)writing a header in our best chain resets its
+                // roots, so we don't keep the roots of a block we've reorged away from
+                self.roots.insert(*height, None);
+            }
+
+            DiskBlockHeader::InFork(_, _)
+            | DiskBlockHeader::Orphan(_)
+            | DiskBlockHeader::InvalidChain(_) => {}
+        }
+
+        self.maybe_flush()
+    }
+
+    fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
+        if let Some(block_hash) = self.index.get(&height) {
+            return Ok(Some(*block_hash));
+        }
+
+        let Some(block_hash) = self.bucket.get(&Self::height_key(INDEX_PREFIX, height))? else {
+            return Ok(None);
+        };
+
+        Ok(Some(deserialize(&block_hash)?))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let mut batch = Batch::new();
+
+        for (block_hash, header) in self.headers.drain() {
+            batch.set(&Self::header_key(&block_hash), &serialize(&header))?;
+        }
+
+        for (height, block_hash) in self.index.drain() {
+            batch.set(
+                &Self::height_key(INDEX_PREFIX, height),
+                &serialize(&block_hash),
+            )?;
+        }
+
+        for (height, roots) in self.roots.drain() {
+            let key = Self::height_key(ROOTS_PREFIX, height);
+            match roots {
+                Some(roots) => batch.set(&key, &roots)?,
+                None => batch.remove(&key)?,
+            }
+        }
+
+        if let Some(best_chain) = self.best_chain.take() {
+            batch.set(&Raw::from(BEST_CHAIN_KEY), &serialize(&best_chain))?;
+        }
+
+        // The whole batch is applied atomically
+        self.bucket.batch(batch)?;
+        self.bucket.flush()?;
+
+        Ok(())
+    }
+
+    fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), Self::Error> {
+        self.index.insert(height, hash);
+        self.maybe_flush()
+    }
+
+    fn check_integrity(&self) -> Result<(), Self::Error> {
+        // The database checksums its own data, and refuses to open if it's corrupted
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::Block;
+    use bitcoin::Network;
+    use kv::Raw;
+
+    use super::KvChainStore;
+    use super::KvChainStoreError;
+    use super::KV_CHAINSTORE_VERSION;
+    use super::VERSION_KEY;
+    use crate::pruned_utreexo::chainstore::test_suite;
+    use crate::BestChain;
+    use crate::ChainStore;
+    use crate::DiskBlockHeader;
+
+    fn get_test_chainstore(test_id: u64) -> KvChainStore {
+        KvChainStore::new(format!("./tmp-db/{test_id}.kv")).unwrap()
+    }
+
+    /// Saves the regtest blocks, with some roots for each, and returns them
+    fn save_regtest_blocks(store: &mut KvChainStore) -> Vec<Block> {
+        let blocks: Vec<Block> = include_str!("../../testdata/regtest_blocks.txt")
+            .lines()
+            .map(|line| deserialize(&hex::decode(line).unwrap()).unwrap())
+            .collect();
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+            store
+                .save_roots_for_block(vec![height as u8; 8], height)
+                .unwrap();
+        }
+
+        let tip = blocks.last().unwrap().block_hash();
+        store
+            .save_height(&BestChain {
+                best_block: tip,
+                depth: blocks.len() as u32 - 1,
+                validation_index: tip,
+                alternative_tips: vec![],
+            })
+            .unwrap();
+
+        blocks
+    }
+
+    fn check_regtest_blocks(store: &mut KvChainStore, blocks: &[Block]) {
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            let header = DiskBlockHeader::FullyValid(block.header, height);
+
+            assert_eq!(store.get_header(&block.block_hash()).unwrap(), Some(header));
+            assert_eq!(store.get_header_by_height(height).unwrap(), Some(header));
+            assert_eq!(
+                store.get_block_hash(height).unwrap(),
+                Some(block.block_hash())
+            );
+            assert_eq!(
+                store.load_roots_for_block(height).unwrap(),
+                Some(vec![height as u8; 8])
+            );
+        }
+
+        let best_chain = store.load_height().unwrap().unwrap();
+        assert_eq!(best_chain.depth, 150);
+        assert_eq!(best_chain.best_block, blocks[150].block_hash());
+    }
+
+    #[test]
+    fn test_save_and_retrieve_headers() {
+        test_suite::save_and_retrieve_headers(&mut get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn test_save_height() {
+        test_suite::save_height(&mut get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn test_fork_blocks() {
+        test_suite::fork_blocks(&mut get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn test_reorg_index() {
+        test_suite::reorg_index(&mut get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn test_save_and_load_roots() {
+        test_suite::save_and_load_roots(&mut get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn accept_mainnet_headers() {
+        test_suite::accept_mainnet_headers(get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn accept_first_signet_headers() {
+        test_suite::accept_first_signet_headers(get_test_chainstore(rand::random()));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(test_id);
+
+        assert_eq!(store.load_height().unwrap(), None);
+        assert_eq!(store.get_block_hash(0).unwrap(), None);
+
+        let blocks = save_regtest_blocks(&mut store);
+        check_regtest_blocks(&mut store, &blocks);
+
+        store.flush().unwrap();
+        check_regtest_blocks(&mut store, &blocks);
+        drop(store);
+
+        let mut store = get_test_chainstore(test_id);
+        check_regtest_blocks(&mut store, &blocks);
+        store.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn test_fork_headers() {
+        let mut store = get_test_chainstore(rand::random());
+        let genesis = genesis_block(Network::Regtest);
+
+        // Fork headers are only indexed by hash
+        let fork = DiskBlockHeader::InFork(genesis.header, 1);
+        store.save_header(&fork).unwrap();
+        store.flush().unwrap();
+
+        assert_eq!(store.get_header(&genesis.block_hash()).unwrap(), Some(fork));
+        assert_eq!(store.get_header_by_height(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unflushed_writes_are_lost() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(test_id);
+        let blocks = save_regtest_blocks(&mut store);
+        store.flush().unwrap();
+
+        // Changes after our last flush never reach the disk
+        let genesis = blocks[0].header;
+        store
+            .save_header(&DiskBlockHeader::InvalidChain(genesis))
+            .unwrap();
+        store.save_roots_for_block(vec![0xff; 8], 150).unwrap();
+        drop(store);
+
+        let mut store = get_test_chainstore(test_id);
+        check_regtest_blocks(&mut store, &blocks);
+    }
+
+    #[test]
+    fn test_unsupported_schema() {
+        let test_id = rand::random::<u64>();
+        let store = get_test_chainstore(test_id);
+        store
+            .bucket
+            .set(
+                &Raw::from(VERSION_KEY),
+                &bitcoin::consensus::serialize(&(KV_CHAINSTORE_VERSION + 1)),
+            )
+            .unwrap();
+        store.bucket.flush().unwrap();
+        drop(store);
+
+        match KvChainStore::new(format!("./tmp-db/{test_id}.kv")) {
+            Err(KvChainStoreError::UnsupportedSchema(version)) => {
+                assert_eq!(version, KV_CHAINSTORE_VERSION + 1)
+            }
+            Err(e) => panic!("Unexpected error: {e:?}"),
+            Ok(_) => panic!("Opened a store with an unsupported schema"),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A chainstore that keeps everything in memory
+//!
+//! Nothing is ever written to disk, so all data is lost once the store is dropped. This is useful
+//! for tests, and for running a throwaway chain where we don't have, or don't want, a filesystem.
+//!
+//! We mimic the [`FlatChainStore`](crate::FlatChainStore) where the trait leaves room for
+//! interpretation: headers in our best chain have a slot for their height, and saving another
+//! header in that slot forgets the roots we had for it. Headers outside our best chain are only
+//! found by their hash.
+
+use core::error;
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::BlockHash;
+
+use crate::prelude::*;
+use crate::BestChain;
+use crate::ChainStore;
+use crate::DatabaseError;
+use crate::DiskBlockHeader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors that can happen whilst interacting with the [`MemoryChainStore`].
+pub enum MemoryChainStoreError {
+    /// We tried to save the roots for a height we have no header for.
+    HeaderNotFound,
+}
+
+impl Display for MemoryChainStoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HeaderNotFound => write!(f, "Header not found in the MemoryChainStore"),
+        }
+    }
+}
+
+impl error::Error for MemoryChainStoreError {}
+
+/// Need this to use [MemoryChainStoreError] as a [DatabaseError] in [ChainStore]
+impl DatabaseError for MemoryChainStoreError {}
+
+#[derive(Debug, Clone, Default)]
+/// A [`ChainStore`] that keeps all its data in memory
+///
+/// See the [module documentation](self) for more information.
+pub struct MemoryChainStore {
+    /// Every header we know, by block hash
+    headers: HashMap<BlockHash, DiskBlockHeader>,
+
+    /// The block hash in each height of our best chain
+    index: HashMap<u32, BlockHash>,
+
+    /// The accumulator for each block in our best chain, by height
+    roots: HashMap<u32, Vec<u8>>,
+
+    /// Our best chain, if we've saved one
+    best_chain: Option<BestChain>,
+}
+
+impl MemoryChainStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChainStore for MemoryChainStore {
+    type Error = MemoryChainStoreError;
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        if !self.index.contains_key(&height) {
+            return Err(MemoryChainStoreError::HeaderNotFound);
+        }
+
+        self.roots.insert(height, roots);
+        Ok(())
+    }
+
+    fn load_roots_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.roots.get(&height).cloned())
+    }
+
+    fn load_height(&self) -> Result<Option<BestChain>, Self::Error> {
+        Ok(self.best_chain.clone())
+    }
+
+    fn save_height(&mut self, height: &BestChain) -> Result<(), Self::Error> {
+        self.best_chain = Some(height.clone());
+        Ok(())
+    }
+
+    fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        Ok(self.headers.get(block_hash).copied())
+    }
+
+    fn get_header_by_height(&self, height: u32) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        let Some(block_hash) = self.index.get(&height) else {
+            return Ok(None);
+        };
+
+        self.get_header(block_hash)
+    }
+
+    fn save_header(&mut self, header: &DiskBlockHeader) -> Result<(), Self::Error> {
+        let block_hash = header.block_hash();
+        self.headers.insert(block_hash, *header);
+
+        match header {
+            DiskBlockHeader::FullyValid(_, height)
+            | DiskBlockHeader::HeadersOnly(_, height)
+            | DiskBlockHeader::AssumedValid(_, height) => {
+                // Like in the flat store, (re)writing a header in our best chain resets its roots
+                self.index.insert(*height, block_hash);
+                self.roots.remove(height);
+            }
+
+            DiskBlockHeader::InFork(_, _)
+            | DiskBlockHeader::Orphan(_)
+            | DiskBlockHeader::InvalidChain(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
+        Ok(self.index.get(&height).copied())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // There's nowhere to flush to
+        Ok(())
+    }
+
+    fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), Self::Error> {
+        self.index.insert(height, hash);
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryChainStore;
+    use crate::pruned_utreexo::chainstore::test_suite;
+
+    #[test]
+    fn test_save_and_retrieve_headers() {
+        test_suite::save_and_retrieve_headers(&mut MemoryChainStore::new());
+    }
+
+    #[test]
+    fn test_save_height() {
+        test_suite::save_height(&mut MemoryChainStore::new());
+    }
+
+    #[test]
+    fn test_fork_blocks() {
+        test_suite::fork_blocks(&mut MemoryChainStore::new());
+    }
+
+    #[test]
+    fn test_reorg_index() {
+        test_suite::reorg_index(&mut MemoryChainStore::new());
+    }
+
+    #[test]
+    fn test_save_and_load_roots() {
+        test_suite::save_and_load_roots(&mut MemoryChainStore::new());
+    }
+
+    #[test]
+    fn accept_mainnet_headers() {
+        test_suite::accept_mainnet_headers(MemoryChainStore::new());
+    }
+
+    #[test]
+    fn accept_first_signet_headers() {
+        test_suite::accept_first_signet_headers(MemoryChainStore::new());
+    }
+}
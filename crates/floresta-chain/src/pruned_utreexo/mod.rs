@@ -13,23 +13,32 @@ extern crate alloc;
 pub mod chain_state;
 pub mod chain_state_builder;
 pub mod chainparams;
+pub mod chainparams_builder;
 pub mod chainstore;
 #[macro_use]
 pub mod error;
 pub mod consensus;
 #[cfg(feature = "flat-chainstore")]
 pub mod flat_chain_store;
+#[cfg(feature = "kv-chainstore")]
+pub mod kv_chain_store;
+#[cfg(any(test, feature = "memory-chainstore"))]
+pub mod memory_chain_store;
 pub mod partial_chain;
+pub mod signet;
 pub mod udata;
+pub mod utreexo_snapshot;
 
 use alloc::sync::Arc;
 use core::error::Error;
 
 use bitcoin::block::Header as BlockHeader;
 use bitcoin::hashes::sha256;
+use bitcoin::Amount;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::OutPoint;
+use bitcoin::Transaction;
 use bitcoin::Work;
 use rustreexo::node_hash::BitcoinNodeHash;
 use rustreexo::proof::Proof;
@@ -117,6 +126,17 @@ pub trait BlockchainInterface {
         acc: Stump,
     ) -> Result<(), Self::Error>;
 
+    /// Validates an unconfirmed transaction on top of our current tip, without modifying our
+    /// chain. `inputs` must have every UTXO spent by this transaction, and `proof` must prove
+    /// that the ones in `del_hashes` are in our current accumulator. Returns the transaction's fee.
+    fn validate_transaction(
+        &self,
+        transaction: &Transaction,
+        proof: Proof,
+        inputs: HashMap<OutPoint, UtxoData>,
+        del_hashes: Vec<sha256::Hash>,
+    ) -> Result<Amount, Self::Error>;
+
     /// Find the last common ancestor between the current best chain and `block`
     fn get_fork_point(&self, block: BlockHash) -> Result<BlockHash, Self::Error>;
 
@@ -358,6 +378,16 @@ impl<T: BlockchainInterface> BlockchainInterface for Arc<T> {
         T::validate_block(self, block, proof, inputs, del_hashes, acc)
     }
 
+    fn validate_transaction(
+        &self,
+        transaction: &Transaction,
+        proof: Proof,
+        inputs: HashMap<OutPoint, UtxoData>,
+        del_hashes: Vec<sha256::Hash>,
+    ) -> Result<Amount, Self::Error> {
+        T::validate_transaction(self, transaction, proof, inputs, del_hashes)
+    }
+
     fn get_fork_point(&self, block: BlockHash) -> Result<BlockHash, Self::Error> {
         T::get_fork_point(self, block)
     }
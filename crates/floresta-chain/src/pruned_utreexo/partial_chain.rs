@@ -57,9 +57,8 @@ pub(crate) struct PartialChainStateInner {
     /// The height we are syncing up to, trying to push more blocks than this will
     /// result in an error.
     pub(crate) final_height: u32,
-    /// The error that occurred during validation, if any. It is here so we can
-    /// pull that afterwards.
-    pub(crate) error: Option<BlockValidationErrors>,
+    /// The block we found to be invalid, if any. It is here so we can pull that afterwards.
+    pub(crate) invalid_block: Option<BlockHash>,
     /// The consensus parameters, we need this to validate the blocks.
     pub(crate) consensus: Consensus,
     /// Whether we assume the signatures in this interval as valid, this is used to
@@ -139,19 +138,10 @@ impl PartialChainStateInner {
     ) -> Result<u32, BlockchainError> {
         let height = self.current_height + 1;
 
-        if let Err(BlockchainError::BlockValidation(e)) = self.validate_block(block, height, inputs)
-        {
-            self.error = Some(e.clone());
-            return Err(BlockchainError::BlockValidation(e));
-        }
+        self.validate_block(block, height, inputs)?;
 
-        let acc = match Consensus::update_acc(&self.current_acc, block, height, proof, del_hashes) {
-            Ok(acc) => acc,
-            Err(_) => {
-                self.error = Some(BlockValidationErrors::InvalidUtreexoProof);
-                return Err(BlockchainError::InvalidUtreexoProof);
-            }
-        };
+        let acc = Consensus::update_acc(&self.current_acc, block, height, proof, del_hashes)
+            .map_err(|_| BlockchainError::InvalidUtreexoProof)?;
 
         // ... If we came this far, we consider this block valid ...
 
@@ -253,7 +243,12 @@ impl PartialChainState {
 
     /// Returns whether any block inside this interval is invalid
     pub fn has_invalid_blocks(&self) -> bool {
-        self.inner().error.is_some()
+        self.inner().invalid_block.is_some()
+    }
+
+    /// Returns the block inside this interval we found to be invalid, if any
+    pub fn invalid_block(&self) -> Option<BlockHash> {
+        self.inner().invalid_block
     }
 }
 
@@ -307,8 +302,14 @@ impl UpdatableChainstate for PartialChainState {
         unimplemented!("We are a partial chain")
     }
 
-    fn invalidate_block(&self, _block: BlockHash) -> Result<(), BlockchainError> {
-        unimplemented!("we know if a block is invalid, just break out of your loop and use the is_valid() method")
+    fn invalidate_block(&self, block: BlockHash) -> Result<(), BlockchainError> {
+        // We can't validate anything after an invalid block, so this interval ends here. Whoever
+        // is driving us will see that we are synced, and may check `has_invalid_blocks`.
+        let inner = self.inner_mut();
+        inner.invalid_block = Some(block);
+        inner.final_height = inner.current_height;
+
+        Ok(())
     }
 
     fn handle_transaction(&self) -> Result<(), BlockchainError> {
@@ -410,6 +411,16 @@ impl BlockchainInterface for PartialChainState {
         unimplemented!("PartialChainState::validate_block")
     }
 
+    fn validate_transaction(
+        &self,
+        _transaction: &bitcoin::Transaction,
+        _proof: rustreexo::proof::Proof,
+        _inputs: HashMap<bitcoin::OutPoint, UtxoData>,
+        _del_hashes: Vec<bitcoin::hashes::sha256::Hash>,
+    ) -> Result<bitcoin::Amount, Self::Error> {
+        unimplemented!("PartialChainState::validate_transaction")
+    }
+
     fn get_fork_point(&self, _block: BlockHash) -> Result<BlockHash, Self::Error> {
         unimplemented!("PartialChainState::get_fork_point")
     }
@@ -482,6 +493,7 @@ mod tests {
     use crate::pruned_utreexo::consensus::Consensus;
     use crate::pruned_utreexo::error::BlockValidationErrors;
     use crate::pruned_utreexo::partial_chain::PartialChainStateInner;
+    use crate::pruned_utreexo::BlockchainInterface;
     use crate::pruned_utreexo::UpdatableChainstate;
     use crate::BlockchainError;
 
@@ -502,6 +514,43 @@ mod tests {
         run("0000002000226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f39adbcd7823048d34357bdca86cd47172afe2a4af8366b5b34db36df89386d49b23ec964ffff7f20000000000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff165108feddb99c6b8435060b2f503253482f627463642fffffffff0100f2052a01000000160014806cef41295922d32ddfca09c26cc4acd36c3ed000000000", BlockValidationErrors::BlockExtendsAnOrphanChain);
         run("0000002000226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f40adbcd7823048d34357bdca86cd47172afe2a4af8366b5b34db36df89386d49b23ec964ffff7f20000000000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff165108feddb99c6b8435060b2f503253482f627463642fffffffff0100f2052a01000000160014806cef41295922d32ddfca09c26cc4acd36c3ed000000000", BlockValidationErrors::BadMerkleRoot);
     }
+    #[test]
+    fn test_invalidate_block() {
+        let blocks = include_str!("../../testdata/regtest_blocks.txt");
+        let parsed_blocks: Vec<Block> = blocks.lines().take(11).map(parse_block).collect();
+        let chainstate: PartialChainState = PartialChainStateInner {
+            assume_valid: true,
+            consensus: Consensus {
+                parameters: ChainParams::from(Network::Regtest),
+            },
+            current_height: 0,
+            current_acc: Stump::default(),
+            final_height: 10,
+            blocks: parsed_blocks.iter().map(|block| block.header).collect(),
+            invalid_block: None,
+        }
+        .into();
+
+        for block in &parsed_blocks[1..5] {
+            chainstate
+                .connect_block(block, Proof::default(), HashMap::new(), Vec::new())
+                .unwrap();
+        }
+
+        assert!(!chainstate.has_invalid_blocks());
+        assert!(chainstate.is_in_ibd());
+
+        // Invalidating the next block ends this interval at our current height
+        let invalid = parsed_blocks[5].block_hash();
+        chainstate.invalidate_block(invalid).unwrap();
+
+        assert!(chainstate.has_invalid_blocks());
+        assert_eq!(chainstate.invalid_block(), Some(invalid));
+        assert!(!chainstate.is_in_ibd());
+        assert_eq!(chainstate.get_best_block().unwrap().0, 4);
+        assert_eq!(chainstate.list_valid_blocks().len(), 4);
+    }
+
     fn parse_block(hex: &str) -> Block {
         deserialize_hex(hex).unwrap()
     }
@@ -515,7 +564,7 @@ mod tests {
             current_acc: Stump::default(),
             final_height: 1,
             blocks,
-            error: None,
+            invalid_block: None,
         }
         .into()
     }
@@ -539,7 +588,7 @@ mod tests {
             current_acc: Stump::default(),
             final_height: 100,
             blocks: parsed_blocks.iter().map(|block| block.header).collect(),
-            error: None,
+            invalid_block: None,
         }
         .into();
         parsed_blocks.remove(0);
@@ -575,7 +624,7 @@ mod tests {
             current_acc: Stump::default(),
             final_height: 100,
             blocks: parsed_blocks.iter().map(|block| block.header).collect(),
-            error: None,
+            invalid_block: None,
         };
 
         // We need to add the last block of the first chain to the second chain, so that
@@ -619,7 +668,7 @@ mod tests {
             current_acc: acc2,
             final_height: 150,
             blocks: parsed_blocks.iter().map(|block| block.header).collect(),
-            error: None,
+            invalid_block: None,
         }
         .into();
 
@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Signet support, as defined by [BIP325](https://github.com/bitcoin/bips/blob/master/bip-0325.mediawiki).
+//!
+//! A signet is a test network where blocks must be signed, besides having a valid proof-of-work.
+//! Each signet is defined by a challenge script, and every block must carry a solution for it
+//! inside its coinbase, in the witness commitment output. To check a solution, we build two
+//! virtual transactions: one paying to the challenge, committing to the block, and one spending
+//! it with the solution. The block is valid if that spend is.
+//!
+//! The challenge also defines the network magic, so peers from different signets won't talk
+//! to each other.
+
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::merkle_tree;
+use bitcoin::opcodes::all::OP_RETURN;
+use bitcoin::opcodes::OP_0;
+use bitcoin::p2p::Magic;
+use bitcoin::script::Builder;
+use bitcoin::script::Instruction;
+use bitcoin::script::PushBytesBuf;
+use bitcoin::transaction::Version;
+use bitcoin::Amount;
+use bitcoin::Block;
+use bitcoin::OutPoint;
+use bitcoin::Script;
+use bitcoin::ScriptBuf;
+use bitcoin::Sequence;
+use bitcoin::Transaction;
+use bitcoin::TxIn;
+use bitcoin::TxOut;
+use bitcoin::Witness;
+
+use crate::prelude::*;
+
+/// The challenge of the default signet, a 1-of-2 multisig
+const DEFAULT_SIGNET_CHALLENGE: [u8; 71] = [
+    0x51, 0x21, 0x03, 0xad, 0x5e, 0x0e, 0xda, 0xd1, 0x8c, 0xb1, 0xf0, 0xfc, 0x0d, 0x28, 0xa3, 0xd4,
+    0xf1, 0xf3, 0xe4, 0x45, 0x64, 0x03, 0x37, 0x48, 0x9a, 0xbb, 0x10, 0x40, 0x4f, 0x2d, 0x1e, 0x08,
+    0x6b, 0xe4, 0x30, 0x21, 0x03, 0x59, 0xef, 0x50, 0x21, 0x96, 0x4f, 0xe2, 0x2d, 0x6f, 0x8e, 0x05,
+    0xb2, 0x46, 0x3c, 0x95, 0x40, 0xce, 0x96, 0x88, 0x3f, 0xe3, 0xb2, 0x78, 0x76, 0x0f, 0x04, 0x8f,
+    0x51, 0x89, 0xf2, 0xe6, 0xc4, 0x52, 0xae,
+];
+
+/// Every signet solution starts with these bytes, inside a push in the witness commitment output
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// The first bytes of a witness commitment output: OP_RETURN, a 36 bytes push and a tag
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Returns the challenge of the default signet
+pub fn default_signet_challenge() -> ScriptBuf {
+    ScriptBuf::from_bytes(DEFAULT_SIGNET_CHALLENGE.to_vec())
+}
+
+/// Returns the network magic for a signet with this challenge. It's the first four bytes of the
+/// double-sha256 of the challenge, serialized with its length.
+pub fn signet_magic(challenge: &Script) -> Magic {
+    let mut data = Vec::new();
+    challenge
+        .consensus_encode(&mut data)
+        .expect("writing to a vec can't fail");
+
+    let hash = sha256d::Hash::hash(&data);
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&hash[..4]);
+
+    Magic::from_bytes(magic)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The virtual transactions we use to check a signet block solution
+pub struct SignetTxs {
+    /// Pays to the challenge, committing to the block in its scriptSig
+    pub to_spend: Transaction,
+
+    /// Spends `to_spend`, using the solution found in the block
+    pub to_sign: Transaction,
+}
+
+impl SignetTxs {
+    /// Builds the transactions for a block and challenge. Returns `None` if the block doesn't
+    /// have a witness commitment, or its solution can't be parsed.
+    ///
+    /// A block without a solution is fine, as long as the challenge can be satisfied with an
+    /// empty one (e.g. `OP_TRUE`).
+    pub fn new(block: &Block, challenge: &Script) -> Option<Self> {
+        let mut coinbase = block.txdata.first()?.clone();
+        let commitment = coinbase.output.iter().rposition(|output| {
+            output.script_pubkey.len() >= 38
+                && output.script_pubkey.as_bytes()[..6] == WITNESS_COMMITMENT_HEADER
+        })?;
+
+        let mut script_sig = ScriptBuf::new();
+        let mut witness = Witness::new();
+
+        let script_pubkey = &mut coinbase.output[commitment].script_pubkey;
+        if let Some((script, solution)) = take_solution(script_pubkey) {
+            *script_pubkey = script;
+
+            let mut reader = solution.as_slice();
+            script_sig = ScriptBuf::consensus_decode(&mut reader).ok()?;
+            witness = Witness::consensus_decode(&mut reader).ok()?;
+
+            // Extraneous data after the solution
+            if !reader.is_empty() {
+                return None;
+            }
+        }
+
+        // The merkle root of the block, without the solution
+        let txids = core::iter::once(coinbase.compute_txid())
+            .chain(block.txdata.iter().skip(1).map(|tx| tx.compute_txid()))
+            .map(|txid| txid.to_raw_hash());
+
+        let merkle_root = merkle_tree::calculate_root(txids)?;
+
+        let mut block_data = Vec::new();
+        block
+            .header
+            .version
+            .consensus_encode(&mut block_data)
+            .ok()?;
+        block
+            .header
+            .prev_blockhash
+            .consensus_encode(&mut block_data)
+            .ok()?;
+        merkle_root.consensus_encode(&mut block_data).ok()?;
+        block.header.time.consensus_encode(&mut block_data).ok()?;
+
+        let to_spend = Transaction {
+            version: Version(0),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new()
+                    .push_opcode(OP_0)
+                    .push_slice(PushBytesBuf::try_from(block_data).ok()?)
+                    .into_script(),
+                sequence: Sequence::ZERO,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: challenge.to_owned(),
+            }],
+        };
+
+        let to_sign = Transaction {
+            version: Version(0),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+                script_sig,
+                sequence: Sequence::ZERO,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+
+        Some(SignetTxs { to_spend, to_sign })
+    }
+}
+
+/// Finds the first push in the witness commitment script starting with [SIGNET_HEADER], and
+/// followed by some data. Returns the script without that data, and the data itself.
+fn take_solution(script: &Script) -> Option<(ScriptBuf, Vec<u8>)> {
+    let mut replacement = Builder::new();
+    let mut solution = None;
+
+    // Like Bitcoin Core, we stop at the first instruction we can't parse
+    for instruction in script.instructions().map_while(Result::ok) {
+        match instruction {
+            Instruction::PushBytes(data) if !data.is_empty() => {
+                let data = data.as_bytes();
+                let is_solution = solution.is_none()
+                    && data.len() > SIGNET_HEADER.len()
+                    && data.starts_with(&SIGNET_HEADER);
+
+                let data = match is_solution {
+                    true => {
+                        solution = Some(data[SIGNET_HEADER.len()..].to_vec());
+                        &data[..SIGNET_HEADER.len()]
+                    }
+                    false => data,
+                };
+
+                let push = PushBytesBuf::try_from(data.to_vec()).ok()?;
+                replacement = replacement.push_slice(push);
+            }
+            Instruction::PushBytes(_) => replacement = replacement.push_opcode(OP_0),
+            Instruction::Op(opcode) => replacement = replacement.push_opcode(opcode),
+        }
+    }
+
+    solution.map(|solution| (replacement.into_script(), solution))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::Encodable;
+    use bitcoin::hashes::Hash;
+    use bitcoin::opcodes::all::OP_RETURN;
+    use bitcoin::opcodes::OP_TRUE;
+    use bitcoin::script::Builder;
+    use bitcoin::script::PushBytesBuf;
+    use bitcoin::Amount;
+    use bitcoin::Block;
+    #[cfg(feature = "bitcoinkernel")]
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
+    use bitcoin::ScriptBuf;
+    use bitcoin::TxOut;
+    use bitcoin::Witness;
+
+    use super::default_signet_challenge;
+    use super::signet_magic;
+    use super::SignetTxs;
+    use super::SIGNET_HEADER;
+    #[cfg(not(feature = "bitcoinkernel"))]
+    use crate::pruned_utreexo::chainparams_builder::ChainParamsBuilder;
+    #[cfg(not(feature = "bitcoinkernel"))]
+    use crate::pruned_utreexo::chainparams_builder::ChainParamsBuilderError;
+    #[cfg(feature = "bitcoinkernel")]
+    use crate::pruned_utreexo::consensus::Consensus;
+    #[cfg(feature = "bitcoinkernel")]
+    use crate::AssumeValidArg;
+    #[cfg(feature = "bitcoinkernel")]
+    use crate::BlockValidationErrors;
+    #[cfg(feature = "bitcoinkernel")]
+    use crate::BlockchainError;
+    use crate::ChainParams;
+
+    /// Returns a regtest block, with a witness commitment output carrying `solution` after
+    /// the signet header
+    fn block_with_solution(solution: Option<&[u8]>) -> Block {
+        let blocks = include_str!("../../testdata/regtest_blocks.txt");
+        let block = blocks.lines().nth(1).unwrap();
+        let mut block: Block = deserialize(&hex::decode(block).unwrap()).unwrap();
+
+        let mut commitment = vec![0xaa, 0x21, 0xa9, 0xed];
+        commitment.extend_from_slice(&[0; 32]);
+
+        let mut script = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(PushBytesBuf::try_from(commitment).unwrap());
+
+        if let Some(solution) = solution {
+            let mut data = SIGNET_HEADER.to_vec();
+            data.extend_from_slice(solution);
+            script = script.push_slice(PushBytesBuf::try_from(data).unwrap());
+        }
+
+        block.txdata[0].output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script.into_script(),
+        });
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+
+        block
+    }
+
+    #[test]
+    fn test_signet_magic() {
+        assert_eq!(
+            signet_magic(&default_signet_challenge()),
+            Network::Signet.magic()
+        );
+
+        let custom = Builder::new().push_opcode(OP_TRUE).into_script();
+        assert_ne!(signet_magic(&custom), Network::Signet.magic());
+    }
+
+    #[test]
+    fn test_signet_txs() {
+        let challenge = default_signet_challenge();
+
+        let script_sig = ScriptBuf::from_bytes(vec![0x51]);
+        let witness = Witness::from_slice(&[vec![1, 2, 3], vec![4; 64]]);
+        let mut solution = Vec::new();
+        script_sig.consensus_encode(&mut solution).unwrap();
+        witness.consensus_encode(&mut solution).unwrap();
+
+        let block = block_with_solution(Some(&solution));
+        let txs = SignetTxs::new(&block, &challenge).unwrap();
+
+        assert_eq!(txs.to_spend.output[0].script_pubkey, challenge);
+        assert_eq!(txs.to_sign.input[0].script_sig, script_sig);
+        assert_eq!(txs.to_sign.input[0].witness, witness);
+        assert_eq!(
+            txs.to_sign.input[0].previous_output.txid,
+            txs.to_spend.compute_txid()
+        );
+
+        // The solution isn't committed to, only the header that precedes it. So we commit to the
+        // same merkle root as a block with only the header.
+        let unsigned = block_with_solution(Some(&[]));
+        let merkle_root = unsigned.compute_merkle_root().unwrap();
+        let committed = &txs.to_spend.input[0].script_sig.as_bytes()[38..70];
+        assert_eq!(committed, merkle_root.as_byte_array());
+
+        // Extraneous data after the solution
+        let mut bad_solution = solution.clone();
+        bad_solution.push(0);
+        let block = block_with_solution(Some(&bad_solution));
+        assert!(SignetTxs::new(&block, &challenge).is_none());
+
+        // No solution at all, only useful with trivial challenges
+        let block = block_with_solution(None);
+        let txs = SignetTxs::new(&block, &challenge).unwrap();
+        assert!(txs.to_sign.input[0].script_sig.is_empty());
+        assert!(txs.to_sign.input[0].witness.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoinkernel")]
+    fn test_custom_signet() {
+        let challenge = Builder::new().push_opcode(OP_TRUE).into_script();
+        let params =
+            ChainParams::custom_signet(challenge.clone(), vec!["seed.example.com".to_string()])
+                .unwrap();
+
+        assert!(params.is_custom());
+        assert!(!ChainParams::from(Network::Signet).is_custom());
+        assert_eq!(params.magic, signet_magic(&challenge));
+        assert_eq!(params.dns_seeds[0].seed, "seed.example.com");
+        assert_eq!(
+            params.genesis.block_hash(),
+            ChainParams::from(Network::Signet).genesis.block_hash()
+        );
+
+        // We don't have hardcoded checkpoints for custom signets
+        assert_eq!(params.assume_valid(AssumeValidArg::Hardcoded), None);
+        let hash = BlockHash::all_zeros();
+        assert_eq!(
+            params.assume_valid(AssumeValidArg::UserInput(hash)),
+            Some(hash)
+        );
+
+        // Anything satisfies `OP_TRUE`, as long as the solution is well-formed
+        let consensus = Consensus { parameters: params };
+        let block = block_with_solution(None);
+        consensus.check_signet_solution(&block, &challenge).unwrap();
+
+        let block = block_with_solution(Some(&[0xff]));
+        assert!(matches!(
+            consensus.check_signet_solution(&block, &challenge),
+            Err(BlockchainError::BlockValidation(
+                BlockValidationErrors::BadSignetSolution
+            ))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "bitcoinkernel"))]
+    fn test_custom_signet_needs_bitcoinkernel() {
+        // We can't run the challenge script, so anyone could sign blocks for this signet
+        let challenge = Builder::new().push_opcode(OP_TRUE).into_script();
+        assert_eq!(
+            ChainParams::custom_signet(challenge, Vec::new()).unwrap_err(),
+            ChainParamsBuilderError::SignetChallengeUnsupported
+        );
+
+        // The default signet is still fine
+        let params = ChainParamsBuilder::new(Network::Signet)
+            .with_signet_challenge(default_signet_challenge())
+            .build()
+            .unwrap();
+        assert!(!params.is_custom());
+    }
+}
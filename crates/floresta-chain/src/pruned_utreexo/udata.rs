@@ -435,36 +435,93 @@ pub mod proof_util {
                     None => continue,
                 };
 
-                let creation_height = leaf.header_code >> 1;
-                // The coinbase flag is the LSB
-                let is_coinbase = (leaf.header_code & 1) != 0;
-
-                let hash = get_block_hash(creation_height)?;
-                let leaf =
-                    reconstruct_leaf_data(&leaf, input, hash).map_err(|e| UtreexoLeafError {
-                        leaf,
-                        txid,
-                        vin,
-                        kind: e,
-                    })?;
+                let (del_hash, prevout, utxo) =
+                    process_leaf(leaf, input, txid, vin, &get_block_hash)?;
 
                 // Push the UTXO to remove from the set and its leaf hash (deletion hash)
-                del_hashes.push(leaf._get_leaf_hashes());
-                utxos.insert(
-                    leaf.prevout,
-                    UtxoData {
-                        txout: leaf.utxo,
-                        is_coinbase,
-                        creation_height,
-                        creation_time: 0, // TODO add MTP(`creation_height` - 1)
-                    },
-                );
+                del_hashes.push(del_hash);
+                utxos.insert(prevout, utxo);
             }
         }
 
         Ok((del_hashes, utxos))
     }
 
+    /// Like [process_proof], but for a single unconfirmed transaction.
+    ///
+    /// Inputs spending an output found in `unconfirmed`, e.g. one created by another mempool
+    /// transaction, are taken from there and don't need a leaf. Every other input must have its
+    /// [`CompactLeafData`] in `leaves`, in the same order they appear in the transaction. The
+    /// returned `UtxoMap` has both kinds of inputs.
+    pub fn process_tx_proof<F, E>(
+        leaves: &[CompactLeafData],
+        tx: &Transaction,
+        unconfirmed: UtxoMap,
+        get_block_hash: F,
+    ) -> Result<ProcessedProof, E>
+    where
+        F: Fn(u32) -> Result<BlockHash, E>,
+        E: From<UtreexoLeafError>,
+    {
+        let mut del_hashes = Vec::new();
+        let mut utxos = unconfirmed;
+
+        let txid = tx.compute_txid();
+        let mut leaves_iter = leaves.iter().cloned();
+
+        for (vin, input) in tx.input.iter().enumerate() {
+            if utxos.contains_key(&input.previous_output) {
+                continue;
+            }
+
+            let Some(leaf) = leaves_iter.next() else {
+                break;
+            };
+
+            let (del_hash, prevout, utxo) = process_leaf(leaf, input, txid, vin, &get_block_hash)?;
+            del_hashes.push(del_hash);
+            utxos.insert(prevout, utxo);
+        }
+
+        Ok((del_hashes, utxos))
+    }
+
+    /// Rebuilds the UTXO spent by `input` from its [`CompactLeafData`], returning its leaf hash
+    /// (the hash we should delete from the accumulator), its outpoint and the UTXO itself.
+    fn process_leaf<F, E>(
+        leaf: CompactLeafData,
+        input: &TxIn,
+        txid: Txid,
+        vin: usize,
+        get_block_hash: &F,
+    ) -> Result<(sha256::Hash, OutPoint, UtxoData), E>
+    where
+        F: Fn(u32) -> Result<BlockHash, E>,
+        E: From<UtreexoLeafError>,
+    {
+        let creation_height = leaf.header_code >> 1;
+        // The coinbase flag is the LSB
+        let is_coinbase = (leaf.header_code & 1) != 0;
+
+        let hash = get_block_hash(creation_height)?;
+        let leaf = reconstruct_leaf_data(&leaf, input, hash).map_err(|e| UtreexoLeafError {
+            leaf,
+            txid,
+            vin,
+            kind: e,
+        })?;
+
+        let del_hash = leaf._get_leaf_hashes();
+        let utxo = UtxoData {
+            txout: leaf.utxo,
+            is_coinbase,
+            creation_height,
+            creation_time: 0, // TODO add MTP(`creation_height` - 1)
+        };
+
+        Ok((del_hash, leaf.prevout, utxo))
+    }
+
     /// Reconstructs the output script, also called scriptPubKey, from a [CompactLeafData] and
     /// the spending tx input. Returns an error if we can't reconstruct the script (the input
     /// doesn't contain the required data).
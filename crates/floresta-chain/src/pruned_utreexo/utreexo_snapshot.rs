@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Utreexo accumulator snapshots, used to share an [`AssumeUtreexoValue`] between nodes.
+//!
+//! A snapshot holds the accumulator at some block, so a node can start from there instead of
+//! validating the whole chain, and validate the skipped blocks later with backfill. Snapshots are
+//! only valid for one chain, so they also carry its network magic. They are serialized as:
+//!
+//! | Field        | Size    | Description                                         |
+//! |--------------|---------|-----------------------------------------------------|
+//! | header       | 8       | `utreexo` followed by `0xff`                        |
+//! | version      | 2       | The format version, currently `1`                   |
+//! | magic        | 4       | The magic of the chain this snapshot belongs to     |
+//! | block hash   | 32      | The block this accumulator is for                   |
+//! | height       | 4       | The height of that block                            |
+//! | leaves       | 8       | How many leaves were ever added to the accumulator  |
+//! | roots count  | 1       | How many roots follow                               |
+//! | roots        | 32 each | The accumulator roots                               |
+//! | checksum     | 32      | The double-sha256 of everything above               |
+//!
+//! All integers are little-endian. The checksum only catches corruption; if you get snapshots
+//! from somewhere else, you should check their authenticity by other means, like a signature.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::Magic;
+use bitcoin::BlockHash;
+use rustreexo::node_hash::BitcoinNodeHash;
+
+use super::chainparams::AssumeUtreexoValue;
+use crate::prelude::*;
+
+/// The first bytes of every snapshot
+const SNAPSHOT_HEADER: [u8; 8] = *b"utreexo\xff";
+
+/// The snapshot format version we write, and the only one we read
+const SNAPSHOT_VERSION: u16 = 1;
+
+/// How many bytes come before the roots
+const SNAPSHOT_PREFIX_LEN: usize = 8 + 2 + 4 + 32 + 4 + 8 + 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents errors that can occur while reading a Utreexo snapshot
+pub enum UtreexoSnapshotError {
+    /// This isn't a Utreexo snapshot
+    BadHeader,
+
+    /// This snapshot uses a format version we don't know
+    UnknownVersion(u16),
+
+    /// This snapshot is for another chain, with this magic
+    WrongNetwork(Magic),
+
+    /// The snapshot is shorter or longer than its contents say
+    BadLength,
+
+    /// The checksum doesn't match the contents, so the snapshot is corrupted
+    BadChecksum,
+}
+
+impl Display for UtreexoSnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UtreexoSnapshotError::BadHeader => write!(f, "Not a Utreexo snapshot"),
+            UtreexoSnapshotError::UnknownVersion(version) => {
+                write!(f, "Unknown snapshot version {version}")
+            }
+            UtreexoSnapshotError::WrongNetwork(magic) => {
+                write!(f, "This snapshot is for another chain, with magic {magic}")
+            }
+            UtreexoSnapshotError::BadLength => write!(f, "Invalid snapshot length"),
+            UtreexoSnapshotError::BadChecksum => write!(f, "Snapshot checksum mismatch"),
+        }
+    }
+}
+
+impl core::error::Error for UtreexoSnapshotError {}
+
+impl AssumeUtreexoValue {
+    /// Serializes this value as a snapshot for the chain with this magic
+    pub fn to_snapshot(&self, magic: Magic) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SNAPSHOT_PREFIX_LEN + (self.roots.len() + 1) * 32);
+        data.extend_from_slice(&SNAPSHOT_HEADER);
+        data.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        data.extend_from_slice(&magic.to_bytes());
+        data.extend_from_slice(self.block_hash.as_byte_array());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&self.leaves.to_le_bytes());
+        // An accumulator has at most one root per bit of its leaf count
+        data.push(self.roots.len() as u8);
+        for root in &self.roots {
+            data.extend_from_slice(&root[..]);
+        }
+
+        let checksum = sha256d::Hash::hash(&data);
+        data.extend_from_slice(checksum.as_byte_array());
+        data
+    }
+
+    /// Reads a snapshot, checking that it's for the chain with this magic
+    pub fn from_snapshot(data: &[u8], magic: Magic) -> Result<Self, UtreexoSnapshotError> {
+        if data.len() < SNAPSHOT_PREFIX_LEN + 32 {
+            return Err(UtreexoSnapshotError::BadLength);
+        }
+
+        if data[..8] != SNAPSHOT_HEADER {
+            return Err(UtreexoSnapshotError::BadHeader);
+        }
+
+        let version = u16::from_le_bytes(array(&data[8..10]));
+        if version != SNAPSHOT_VERSION {
+            return Err(UtreexoSnapshotError::UnknownVersion(version));
+        }
+
+        let roots_count = data[SNAPSHOT_PREFIX_LEN - 1] as usize;
+        let (contents, checksum) = data.split_at(data.len() - 32);
+        if contents.len() != SNAPSHOT_PREFIX_LEN + roots_count * 32 {
+            return Err(UtreexoSnapshotError::BadLength);
+        }
+
+        if sha256d::Hash::hash(contents).as_byte_array()[..] != checksum[..] {
+            return Err(UtreexoSnapshotError::BadChecksum);
+        }
+
+        let snapshot_magic = Magic::from_bytes(array(&data[10..14]));
+        if snapshot_magic != magic {
+            return Err(UtreexoSnapshotError::WrongNetwork(snapshot_magic));
+        }
+
+        let roots = contents[SNAPSHOT_PREFIX_LEN..]
+            .chunks_exact(32)
+            .map(|root| BitcoinNodeHash::from(array::<32>(root)))
+            .collect();
+
+        Ok(AssumeUtreexoValue {
+            block_hash: BlockHash::from_byte_array(array(&data[14..46])),
+            height: u32::from_le_bytes(array(&data[46..50])),
+            leaves: u64::from_le_bytes(array(&data[50..58])),
+            roots,
+        })
+    }
+}
+
+/// Copies a slice we already know the length of into an array
+fn array<const N: usize>(slice: &[u8]) -> [u8; N] {
+    slice.try_into().expect("slice has the right length")
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
+    use rustreexo::node_hash::BitcoinNodeHash;
+
+    use super::UtreexoSnapshotError;
+    use crate::AssumeUtreexoValue;
+    use crate::ChainParams;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let value = ChainParams::get_assume_utreexo(Network::Signet);
+        let magic = Network::Signet.magic();
+
+        let snapshot = value.to_snapshot(magic);
+        assert_eq!(snapshot.len(), 59 + value.roots.len() * 32 + 32);
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&snapshot, magic),
+            Ok(value)
+        );
+
+        // An empty accumulator is also valid
+        let empty = AssumeUtreexoValue {
+            block_hash: BlockHash::all_zeros(),
+            height: 0,
+            roots: Vec::new(),
+            leaves: 0,
+        };
+        let snapshot = empty.to_snapshot(magic);
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&snapshot, magic),
+            Ok(empty)
+        );
+    }
+
+    #[test]
+    fn test_invalid_snapshot() {
+        let value = AssumeUtreexoValue {
+            block_hash: BlockHash::all_zeros(),
+            height: 10,
+            roots: vec![
+                BitcoinNodeHash::from([1; 32]),
+                BitcoinNodeHash::from([2; 32]),
+            ],
+            leaves: 3,
+        };
+        let magic = Network::Regtest.magic();
+        let snapshot = value.to_snapshot(magic);
+
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&snapshot, Network::Bitcoin.magic()),
+            Err(UtreexoSnapshotError::WrongNetwork(magic))
+        );
+
+        let mut corrupted = snapshot.clone();
+        corrupted[50] ^= 1;
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&corrupted, magic),
+            Err(UtreexoSnapshotError::BadChecksum)
+        );
+
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&snapshot[..snapshot.len() - 1], magic),
+            Err(UtreexoSnapshotError::BadLength)
+        );
+
+        let mut bad_header = snapshot.clone();
+        bad_header[0] = b'x';
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&bad_header, magic),
+            Err(UtreexoSnapshotError::BadHeader)
+        );
+
+        let mut bad_version = snapshot;
+        bad_version[8] = 2;
+        assert_eq!(
+            AssumeUtreexoValue::from_snapshot(&bad_version, magic),
+            Err(UtreexoSnapshotError::UnknownVersion(2))
+        );
+    }
+}
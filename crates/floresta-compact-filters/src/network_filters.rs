@@ -49,6 +49,39 @@ impl<Storage: IterableFilterStore> NetworkFilters<Storage> {
         Ok(blocks)
     }
 
+    /// Like [match_any](Self::match_any), but only looks at filters in the closed interval
+    /// `start_height..=stop_height`, and returns the height of each block as well.
+    ///
+    /// Filters are found by seeking to the closest checkpoint in the filter store, so reading a
+    /// range that starts right at a checkpoint avoids going through filters we'd skip anyway.
+    pub fn match_range(
+        &self,
+        query: Vec<&[u8]>,
+        start_height: u32,
+        stop_height: u32,
+        chain: impl BlockchainInterface,
+    ) -> Result<Vec<(u32, BlockHash)>, IterableFilterStoreError> {
+        let mut blocks = Vec::new();
+        let iter = query.into_iter();
+
+        for (height, filter) in self.filters.iter(Some(start_height as usize))? {
+            if height > stop_height {
+                break;
+            }
+
+            if height < start_height {
+                continue;
+            }
+
+            let hash = chain.get_block_hash(height).unwrap();
+            if filter.match_any(&hash, &mut iter.clone()).unwrap() {
+                blocks.push((height, hash));
+            }
+        }
+
+        Ok(blocks)
+    }
+
     pub fn push_filter(
         &self,
         filter: BlockFilter,
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A builder for [ElectrumServer], letting applications that embed this crate pick their own
+//! wallet storage and compact filter store.
+//!
+//! The builder starts with the same backends used by `florestad`, a [KvDatabase] for the wallet
+//! and a [FlatFiltersStore] for the filters, and each one can be swapped by calling the
+//! respective `with_*` method. For instance, a server that keeps everything in memory, like the
+//! ones we use in tests or a mobile app would use, can be created with:
+//!
+//! ```ignore
+//! let server = ElectrumServerBuilder::new()
+//!     .with_chain(chain)
+//!     .with_address_cache(Arc::new(AddressCache::new(MemoryDatabase::new())))
+//!     .with_node_interface(node.get_handle())
+//!     .build()?;
+//! ```
+//!
+//! Clients can then be connected with [add_client](crate::electrum_protocol::add_client), using
+//! any stream, or with [client_accept_loop](crate::electrum_protocol::client_accept_loop).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use floresta_chain::pruned_utreexo::BlockchainInterface;
+use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
+use floresta_compact_filters::network_filters::NetworkFilters;
+use floresta_compact_filters::IterableFilterStore;
+use floresta_watch_only::kv_database::KvDatabase;
+use floresta_watch_only::AddressCache;
+use floresta_watch_only::AddressCacheDatabase;
+use floresta_wire::node_interface::NodeInterface;
+use floresta_wire::rescan::RescanManager;
+use thiserror::Error;
+
+use crate::electrum_protocol::ElectrumServer;
+use crate::limits::ElectrumLimits;
+use crate::peers::PeerDiscoveryConfig;
+use crate::tenants::TenantConfig;
+
+#[derive(Error, Debug)]
+/// Errors that can occur while building an [ElectrumServer]
+pub enum ElectrumBuilderError {
+    #[error("The blockchain backend is missing")]
+    MissingChain,
+
+    #[error("The address cache is missing")]
+    MissingAddressCache,
+
+    #[error("The node interface is missing")]
+    MissingNodeInterface,
+
+    #[error("Tenant {0} has no wallet")]
+    MissingTenantWallet(String),
+}
+
+/// A builder for configuring and creating an [ElectrumServer].
+///
+/// The chain, address cache and node interface are **always required**, everything else is
+/// optional. Call `.build()` to consume the builder and produce the server.
+pub struct ElectrumServerBuilder<
+    Blockchain: BlockchainInterface,
+    Database: AddressCacheDatabase = KvDatabase,
+    Filters: IterableFilterStore = FlatFiltersStore,
+> {
+    /// The blockchain backend
+    chain: Option<Arc<Blockchain>>,
+
+    /// The watch-only wallet used to answer address queries
+    address_cache: Option<Arc<AddressCache<Database>>>,
+
+    /// Compact block filters, used to rescan for new addresses
+    block_filters: Option<Arc<NetworkFilters<Filters>>>,
+
+    /// A handle to the running node
+    node_interface: Option<NodeInterface>,
+
+    /// The limits enforced on clients
+    limits: ElectrumLimits,
+
+    /// How the server takes part in the Electrum server network
+    peer_discovery: PeerDiscoveryConfig,
+
+    /// The tenants, in multi-tenant mode
+    tenants: TenantConfig,
+
+    /// The wallet of each tenant
+    tenant_wallets: HashMap<String, Arc<AddressCache<Database>>>,
+
+    /// Where rescans are tracked
+    rescans: Option<RescanManager>,
+}
+
+impl<Blockchain: BlockchainInterface> ElectrumServerBuilder<Blockchain> {
+    /// Creates a new builder, using the default wallet and filter backends
+    pub fn new() -> Self {
+        ElectrumServerBuilder {
+            chain: None,
+            address_cache: None,
+            block_filters: None,
+            node_interface: None,
+            limits: ElectrumLimits::default(),
+            peer_discovery: PeerDiscoveryConfig::default(),
+            tenants: TenantConfig::default(),
+            tenant_wallets: HashMap::new(),
+            rescans: None,
+        }
+    }
+}
+
+impl<Blockchain: BlockchainInterface> Default for ElectrumServerBuilder<Blockchain> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Blockchain, Database, Filters> ElectrumServerBuilder<Blockchain, Database, Filters>
+where
+    Blockchain: BlockchainInterface,
+    Database: AddressCacheDatabase,
+    Filters: IterableFilterStore,
+{
+    /// Builds the server. Returns an error if the chain, the address cache or the node interface
+    /// are missing, or if some tenant doesn't have a wallet.
+    pub fn build(
+        self,
+    ) -> Result<ElectrumServer<Blockchain, Database, Filters>, ElectrumBuilderError> {
+        let chain = self.chain.ok_or(ElectrumBuilderError::MissingChain)?;
+        let address_cache = self
+            .address_cache
+            .ok_or(ElectrumBuilderError::MissingAddressCache)?;
+        let node_interface = self
+            .node_interface
+            .ok_or(ElectrumBuilderError::MissingNodeInterface)?;
+
+        if let Some(tenant) = self
+            .tenants
+            .tenants()
+            .into_iter()
+            .find(|tenant| !self.tenant_wallets.contains_key(tenant))
+        {
+            return Err(ElectrumBuilderError::MissingTenantWallet(tenant));
+        }
+
+        let mut server =
+            ElectrumServer::from_parts(address_cache, chain, self.block_filters, node_interface)
+                .with_limits(self.limits)
+                .with_peer_discovery(self.peer_discovery)
+                .with_tenants(self.tenants, self.tenant_wallets);
+
+        if let Some(rescans) = self.rescans {
+            server = server.with_rescan_manager(rescans);
+        }
+
+        Ok(server)
+    }
+
+    /// Sets the blockchain backend. **Always required**.
+    pub fn with_chain(mut self, chain: Arc<Blockchain>) -> Self {
+        self.chain = Some(chain);
+        self
+    }
+
+    /// Sets the handle to the running node, used to broadcast transactions and fetch blocks.
+    /// **Always required**.
+    pub fn with_node_interface(mut self, node_interface: NodeInterface) -> Self {
+        self.node_interface = Some(node_interface);
+        self
+    }
+
+    /// Sets the watch-only wallet, over any [AddressCacheDatabase]. **Always required**.
+    ///
+    /// Tenant wallets use the same database type, so they are dropped if this changes it. Call
+    /// [with_tenants](Self::with_tenants) after this.
+    pub fn with_address_cache<NewDatabase: AddressCacheDatabase>(
+        self,
+        address_cache: Arc<AddressCache<NewDatabase>>,
+    ) -> ElectrumServerBuilder<Blockchain, NewDatabase, Filters> {
+        ElectrumServerBuilder {
+            chain: self.chain,
+            address_cache: Some(address_cache),
+            block_filters: self.block_filters,
+            node_interface: self.node_interface,
+            limits: self.limits,
+            peer_discovery: self.peer_discovery,
+            tenants: self.tenants,
+            tenant_wallets: HashMap::new(),
+            rescans: self.rescans,
+        }
+    }
+
+    /// Sets the compact block filters, over any [IterableFilterStore]. Without filters, we can't
+    /// rescan for addresses we learn about after they've been used.
+    pub fn with_block_filters<NewFilters: IterableFilterStore>(
+        self,
+        block_filters: Arc<NetworkFilters<NewFilters>>,
+    ) -> ElectrumServerBuilder<Blockchain, Database, NewFilters> {
+        ElectrumServerBuilder {
+            chain: self.chain,
+            address_cache: self.address_cache,
+            block_filters: Some(block_filters),
+            node_interface: self.node_interface,
+            limits: self.limits,
+            peer_discovery: self.peer_discovery,
+            tenants: self.tenants,
+            tenant_wallets: self.tenant_wallets,
+            rescans: self.rescans,
+        }
+    }
+
+    /// Sets the limits enforced on clients, see [ElectrumLimits].
+    pub fn with_limits(mut self, limits: ElectrumLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets how the server takes part in the Electrum server network, see
+    /// [PeerDiscoveryConfig].
+    pub fn with_peer_discovery(mut self, peer_discovery: PeerDiscoveryConfig) -> Self {
+        self.peer_discovery = peer_discovery;
+        self
+    }
+
+    /// Turns multi-tenant mode on, giving each tenant in `tenants` its own wallet from
+    /// `wallets`, indexed by tenant name. See [crate::tenants].
+    pub fn with_tenants(
+        mut self,
+        tenants: TenantConfig,
+        wallets: HashMap<String, Arc<AddressCache<Database>>>,
+    ) -> Self {
+        self.tenants = tenants;
+        self.tenant_wallets = wallets;
+        self
+    }
+
+    /// Sets where rescans are tracked, see [RescanManager]. If not set, rescans aren't saved
+    /// to disk, and can't be resumed after a restart.
+    pub fn with_rescan_manager(mut self, rescans: RescanManager) -> Self {
+        self.rescans = Some(rescans);
+        self
+    }
+}
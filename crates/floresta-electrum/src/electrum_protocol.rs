@@ -1,8 +1,12 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use core::error;
+use core::net::IpAddr;
+use core::net::SocketAddr;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
@@ -11,6 +15,7 @@ use bitcoin::consensus::deserialize;
 use bitcoin::consensus::encode::serialize_hex;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::sha256;
+use bitcoin::BlockHash;
 use bitcoin::ScriptBuf;
 use bitcoin::Transaction;
 use bitcoin::TxOut;
@@ -21,14 +26,20 @@ use floresta_common::get_spk_hash;
 use floresta_common::spsc::Channel;
 use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
 use floresta_compact_filters::network_filters::NetworkFilters;
+use floresta_compact_filters::IterableFilterStore;
 use floresta_watch_only::kv_database::KvDatabase;
 use floresta_watch_only::AddressCache;
+use floresta_watch_only::AddressCacheDatabase;
 use floresta_watch_only::CachedTransaction;
 use floresta_wire::node_interface::NodeInterface;
+use floresta_wire::rescan::RescanJobId;
+use floresta_wire::rescan::RescanManager;
+use floresta_wire::rescan::RescanSource;
 use serde_json::json;
 use serde_json::Value;
 use tokio::io::AsyncBufReadExt;
 use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
@@ -44,13 +55,38 @@ use tracing::trace;
 
 use crate::get_arg;
 use crate::json_rpc_res;
+use crate::limits::base_request_cost;
+use crate::limits::response_cost;
+use crate::limits::ConnectionGate;
+use crate::limits::ElectrumLimits;
+use crate::limits::RequestBudget;
+use crate::limits::TLS_HANDSHAKE_TIMEOUT;
+use crate::peers::check_peer;
+use crate::peers::hosts_features;
+use crate::peers::hosts_from_features;
+use crate::peers::ElectrumPeer;
+use crate::peers::PeerDiscoveryConfig;
+use crate::peers::PeerError;
+use crate::peers::PeerStore;
+use crate::peers::ServerHost;
+use crate::peers::MAX_KNOWN_PEERS;
 use crate::request::Request;
+use crate::tenants::certificate_fingerprint;
+use crate::tenants::TenantConfig;
 
 /// How often do we re-broadcast our transactions, until it gets confirmed
 ///
 /// One day, in seconds
 const REBROADCAST_INTERVAL: u64 = 24 * 3600;
 
+/// How often do we re-check known Electrum peers, and announce ourselves to them
+///
+/// One day, in seconds
+const PEER_CHECK_INTERVAL: u64 = 24 * 3600;
+
+/// How many `server.add_peer` checks may be running at the same time
+const MAX_PENDING_PEER_CHECKS: usize = 16;
+
 /// Type alias for u32 representing a ClientId
 type ClientId = u32;
 
@@ -67,14 +103,31 @@ struct TcpActor<S: AsyncStream> {
     receiver: UnboundedReceiver<SenderMessage>,
     message_transmitter: UnboundedSender<Message>,
     client_id: ClientId,
+
+    /// The largest request we accept from this client, in bytes
+    max_request_bytes: usize,
+
+    /// For how long this client may stay silent before we disconnect it
+    idle_timeout: Duration,
 }
 
 impl<S: AsyncStream> TcpActor<S> {
     async fn run(&mut self) {
         let (reader, mut writer) = tokio::io::split(&mut self.stream);
-        let mut lines = BufReader::new(reader).lines();
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+
+        let idle = tokio::time::sleep(self.idle_timeout);
+        tokio::pin!(idle);
 
         loop {
+            // We never read more than one byte past the limit, so we can tell an oversized
+            // request from one that is exactly at the limit. `read_until` keeps partially
+            // read data in `line`, so it is fine to cancel it inside `select!`.
+            let remaining = self.max_request_bytes.saturating_add(1) - line.len();
+            let mut limited_reader =
+                (&mut reader).take(u64::try_from(remaining).unwrap_or(u64::MAX));
+
             tokio::select! {
                 Some(message) = self.receiver.recv() => {
                     match message {
@@ -89,20 +142,47 @@ impl<S: AsyncStream> TcpActor<S> {
                         }
                     }
                 }
-                result = lines.next_line() => {
+                _ = &mut idle => {
+                    info!("Client {} has been idle for too long, disconnecting", self.client_id);
+                    self.message_transmitter
+                        .send(Message::Disconnect(self.client_id))
+                        .expect("Main loop is broken");
+                    break;
+                }
+                result = limited_reader.read_until(b'\n', &mut line) => {
                     match result {
-                        Ok(Some(line)) => {
+                        Ok(0) if line.is_empty() => {
+                            info!("Client closed connection: {}", self.client_id);
                             self.message_transmitter
-                                .send(Message::Message((self.client_id, line)))
+                                .send(Message::Disconnect(self.client_id))
                                 .expect("Main loop is broken");
+                            break;
                         }
-                        Ok(None) => {
-                            info!("Client closed connection: {}", self.client_id);
+                        Ok(_) if line.len() > self.max_request_bytes => {
+                            info!("Client {} sent an oversized request", self.client_id);
                             self.message_transmitter
-                                .send(Message::Disconnect(self.client_id))
+                                .send(Message::Misbehaving(self.client_id))
                                 .expect("Main loop is broken");
                             break;
                         }
+                        Ok(read) => {
+                            // A line without its terminator is only complete if the client
+                            // closed the connection right after it.
+                            if read != 0 && !line.ends_with(b"\n") {
+                                continue;
+                            }
+
+                            idle.as_mut().reset(tokio::time::Instant::now() + self.idle_timeout);
+
+                            let request = String::from_utf8_lossy(&line)
+                                .trim_end_matches(['\n', '\r'])
+                                .to_string();
+                            line.clear();
+
+                            self.message_transmitter
+                                .send(Message::Message((self.client_id, request)))
+                                .expect("Main loop is broken");
+                        }
                         Err(e) => {
                             error!("Error reading from client: {e:?}");
                             self.message_transmitter
@@ -121,8 +201,11 @@ impl<S: AsyncStream> TcpActor<S> {
 #[derive(Debug, Clone)]
 pub struct Client {
     client_id: ClientId,
-    _addresses: HashSet<ScriptBuf>,
+    address: SocketAddr,
     sender: UnboundedSender<SenderMessage>,
+
+    /// The fingerprint of the TLS certificate this client presented, if any
+    certificate: Option<sha256::Hash>,
 }
 
 impl Client {
@@ -136,11 +219,19 @@ impl Client {
         Ok(())
     }
 
+    /// Closes the connection with this client
+    fn shutdown(&self) {
+        let _ = self.sender.send(SenderMessage::Shutdown);
+    }
+
     /// Create a new client from a stream
     pub fn new<S: AsyncStream + 'static>(
         client_id: ClientId,
+        address: SocketAddr,
+        certificate: Option<sha256::Hash>,
         stream: S,
         message_transmitter: UnboundedSender<Message>,
+        limits: &ElectrumLimits,
     ) -> Self {
         let (sender, receiver) = unbounded_channel();
         let mut actor = TcpActor {
@@ -148,14 +239,17 @@ impl Client {
             receiver,
             message_transmitter,
             client_id,
+            max_request_bytes: limits.max_request_bytes,
+            idle_timeout: limits.idle_timeout,
         };
         tokio::spawn(async move {
             actor.run().await;
         });
         Client {
             client_id,
-            _addresses: HashSet::new(),
+            address,
             sender,
+            certificate,
         }
     }
 }
@@ -167,16 +261,31 @@ pub enum Message {
     Message((ClientId, String)),
     /// A client just disconnected
     Disconnect(ClientId),
+    /// A client broke one of our limits and should be disconnected and banned
+    Misbehaving(ClientId),
+    /// An Electrum peer was checked, with the hostname we checked and the outcome
+    PeerChecked((String, Result<ElectrumPeer, PeerError>)),
 }
 
-pub struct ElectrumServer<Blockchain: BlockchainInterface> {
+/// An Electrum server, backed by a blockchain, a watch-only wallet and, optionally, compact
+/// block filters.
+///
+/// By default, the wallet is stored in a [KvDatabase] and the filters in a [FlatFiltersStore],
+/// but any [AddressCacheDatabase] and [IterableFilterStore] can be used. See
+/// [ElectrumServerBuilder](crate::builder::ElectrumServerBuilder) for a convenient way of
+/// creating one.
+pub struct ElectrumServer<
+    Blockchain: BlockchainInterface,
+    Database: AddressCacheDatabase = KvDatabase,
+    Filters: IterableFilterStore = FlatFiltersStore,
+> {
     /// The blockchain backend we are using. This will be used to query
     /// blockchain information and broadcast transactions.
     chain: Arc<Blockchain>,
 
     /// The address cache is used to store addresses and transactions, like a
     /// watch-only wallet, but it is adapted to the electrum protocol.
-    address_cache: Arc<AddressCache<KvDatabase>>,
+    address_cache: Arc<AddressCache<Database>>,
 
     /// The clients are the clients connected to our server, we keep track of them
     /// using a unique id.
@@ -190,42 +299,102 @@ pub struct ElectrumServer<Blockchain: BlockchainInterface> {
     message_transmitter: UnboundedSender<Message>,
 
     /// The client_addresses is used to keep track of the addresses of each client.
-    /// We keep the script_hash and which client has it, so we can notify the
+    /// We keep the script_hash and which clients have it, so we can notify the
     /// clients when a new transaction is received.
-    client_addresses: HashMap<sha256::Hash, Arc<Client>>,
+    client_addresses: HashMap<sha256::Hash, HashMap<ClientId, Arc<Client>>>,
 
     /// A Arc-ed copy of the block filters backend that we can use to check if a
     /// block contains a transaction that we are interested in.
-    block_filters: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
+    block_filters: Option<Arc<NetworkFilters<Filters>>>,
 
     /// An interface to a running node, used to broadcast transactions and request
     /// blocks.
     node_interface: NodeInterface,
 
     /// A list of addresses that we've just learned about and need to rescan for
-    /// transactions.
+    /// transactions, with the tenant they belong to, if any.
     ///
     /// We accumulate those addresses here and then periodically
     /// scan, since a wallet will often send multiple addresses, but
     /// in different requests.
-    addresses_to_scan: Vec<ScriptBuf>,
+    addresses_to_scan: Vec<(Option<String>, ScriptBuf)>,
 
     /// Last time we've re-broadcasted our transactions. We want to do this every hour, to make
     /// sure our transactions don't get stuck in the mempool if they are not getting confirmed for
     /// some reason. We keep track of this time to know when to re-broadcast them.
     last_rebroadcast: Option<Instant>,
+
+    /// The limits we enforce on our clients, see [ElectrumLimits].
+    limits: ElectrumLimits,
+
+    /// How much each client may still spend on requests.
+    budgets: HashMap<ClientId, RequestBudget>,
+
+    /// Which script hashes each client is subscribed to.
+    subscriptions: HashMap<ClientId, HashSet<sha256::Hash>>,
+
+    /// Our bans and client count, shared with the accept loops.
+    gate: Arc<ConnectionGate>,
+
+    /// Where our server is publicly reachable, as reported on `server.features`.
+    public_hosts: Vec<ServerHost>,
+
+    /// Servers we should check and announce ourselves to, even if we don't know them yet.
+    seed_peers: Vec<ServerHost>,
+
+    /// Other Electrum servers we've verified.
+    peers: PeerStore,
+
+    /// Hostnames of the peers we are checking right now.
+    pending_peer_checks: HashSet<String>,
+
+    /// Last time we've checked our known peers.
+    last_peer_check: Option<Instant>,
+
+    /// Who our tenants are, and how they authenticate. Empty, unless we are in multi-tenant
+    /// mode.
+    tenant_config: TenantConfig,
+
+    /// The wallet of each tenant, used instead of `address_cache` for their clients.
+    tenant_wallets: HashMap<String, Arc<AddressCache<Database>>>,
+
+    /// Which tenant each authenticated client belongs to.
+    client_tenants: HashMap<ClientId, String>,
+
+    /// Keeps track of our rescans, and lets them be resumed if interrupted.
+    rescans: RescanManager,
 }
 
-impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
+impl<Blockchain, Database, Filters> ElectrumServer<Blockchain, Database, Filters>
+where
+    Blockchain: BlockchainInterface,
+    Database: AddressCacheDatabase,
+    Filters: IterableFilterStore,
+{
     pub fn new(
-        address_cache: Arc<AddressCache<KvDatabase>>,
+        address_cache: Arc<AddressCache<Database>>,
+        chain: Arc<Blockchain>,
+        block_filters: Option<Arc<NetworkFilters<Filters>>>,
+        node_interface: NodeInterface,
+    ) -> Result<ElectrumServer<Blockchain, Database, Filters>, Box<dyn error::Error>> {
+        Ok(Self::from_parts(
+            address_cache,
+            chain,
+            block_filters,
+            node_interface,
+        ))
+    }
+
+    /// Creates a server with the default limits and no peer discovery
+    pub(crate) fn from_parts(
+        address_cache: Arc<AddressCache<Database>>,
         chain: Arc<Blockchain>,
-        block_filters: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
+        block_filters: Option<Arc<NetworkFilters<Filters>>>,
         node_interface: NodeInterface,
-    ) -> Result<ElectrumServer<Blockchain>, Box<dyn error::Error>> {
+    ) -> Self {
         let (tx, rx) = unbounded_channel();
 
-        Ok(ElectrumServer {
+        ElectrumServer {
             last_rebroadcast: None,
             chain,
             address_cache,
@@ -236,7 +405,74 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             message_transmitter: tx,
             client_addresses: HashMap::new(),
             addresses_to_scan: Vec::new(),
-        })
+            limits: ElectrumLimits::default(),
+            budgets: HashMap::new(),
+            subscriptions: HashMap::new(),
+            gate: Arc::new(ConnectionGate::default()),
+            public_hosts: Vec::new(),
+            seed_peers: Vec::new(),
+            peers: PeerStore::default(),
+            pending_peer_checks: HashSet::new(),
+            last_peer_check: None,
+            tenant_config: TenantConfig::default(),
+            tenant_wallets: HashMap::new(),
+            client_tenants: HashMap::new(),
+            rescans: RescanManager::new(None),
+        }
+    }
+
+    /// Configures how this server takes part in the Electrum server network.
+    ///
+    /// This loads the peers we've verified before, if a data dir is given.
+    pub fn with_peer_discovery(mut self, config: PeerDiscoveryConfig) -> Self {
+        self.peers = PeerStore::load(config.datadir.as_deref());
+        self.public_hosts = config.public_hosts;
+        self.seed_peers = config.seed_peers;
+        self
+    }
+
+    /// Sets the limits enforced on this server's clients.
+    ///
+    /// The same limits should be passed to [client_accept_loop], as the per-connection
+    /// limits are enforced there.
+    pub fn with_limits(mut self, limits: ElectrumLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Turns multi-tenant mode on, see [crate::tenants].
+    ///
+    /// Each tenant in `config` needs a wallet in `wallets`, indexed by the tenant name, and
+    /// clients of tenants without one won't be able to authenticate. Clients that didn't
+    /// authenticate can't use any method that touches a wallet.
+    pub fn with_tenants(
+        mut self,
+        config: TenantConfig,
+        wallets: HashMap<String, Arc<AddressCache<Database>>>,
+    ) -> Self {
+        self.tenant_config = config;
+        self.tenant_wallets = wallets;
+        self
+    }
+
+    /// Sets where rescans are tracked. Sharing a [RescanManager] with other parts of the node
+    /// lets them report on, or abort, rescans started by our clients.
+    ///
+    /// Rescans that a previous run of this server left unfinished are resumed once we are
+    /// out of IBD.
+    pub fn with_rescan_manager(mut self, rescans: RescanManager) -> Self {
+        self.rescans = rescans;
+        self
+    }
+
+    /// The limits enforced on this server's clients
+    pub fn get_limits(&self) -> ElectrumLimits {
+        self.limits.clone()
+    }
+
+    /// Who may connect to this server, to be passed to [client_accept_loop]
+    pub fn get_connection_gate(&self) -> Arc<ConnectionGate> {
+        self.gate.clone()
     }
 
     /// Notifier to send messages to the main loop
@@ -315,7 +551,8 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             "blockchain.relayfee" => json_rpc_res!(request, 0.00001),
             "blockchain.scripthash.get_balance" => {
                 let script_hash = get_arg!(request, sha256::Hash, 0);
-                let balance = self.address_cache.get_address_balance(&script_hash);
+                let wallet = self.wallet_of(&client)?;
+                let balance = wallet.get_address_balance(&script_hash);
                 let result = json!({
                     "confirmed": balance,
                     "unconfirmed": 0
@@ -324,7 +561,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }
             "blockchain.scripthash.get_history" => {
                 let script_hash = get_arg!(request, sha256::Hash, 0);
-                self.address_cache
+                self.wallet_of(&client)?
                     .get_address_history(&script_hash)
                     .map(|transactions| {
                         let res = Self::process_history(&transactions);
@@ -338,18 +575,22 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                         }))
                     })
             }
-            "blockchain.scripthash.get_mempool" => json_rpc_res!(request, []),
+            "blockchain.scripthash.get_mempool" => {
+                self.wallet_of(&client)?;
+                json_rpc_res!(request, [])
+            }
             "blockchain.scripthash.listunspent" => {
                 let hash = get_arg!(request, sha256::Hash, 0);
-                let utxos = self.address_cache.get_address_utxos(&hash);
+                let wallet = self.wallet_of(&client)?;
+                let utxos = wallet.get_address_utxos(&hash);
                 if utxos.is_none() {
                     return json_rpc_res!(request, []);
                 }
                 let mut final_utxos = Vec::new();
                 for (utxo, prevout) in utxos.unwrap().into_iter() {
-                    let height = self.address_cache.get_height(&prevout.txid).unwrap();
+                    let height = wallet.get_height(&prevout.txid).unwrap();
 
-                    let position = self.address_cache.get_position(&prevout.txid).unwrap();
+                    let position = wallet.get_position(&prevout.txid).unwrap();
 
                     final_utxos.push(json!({
                         "height": height,
@@ -363,9 +604,10 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }
             "blockchain.scripthash.subscribe" => {
                 let hash = get_arg!(request, sha256::Hash, 0);
-                self.client_addresses.insert(hash, client);
+                let wallet = self.wallet_of(&client)?;
+                self.add_subscription(&client, hash)?;
 
-                let history = self.address_cache.get_address_history(&hash);
+                let history = wallet.get_address_history(&hash);
                 match history {
                     Some(transactions) if !transactions.is_empty() => {
                         let res = get_status(transactions);
@@ -378,7 +620,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }
             "blockchain.scripthash.unsubscribe" => {
                 let address = get_arg!(request, sha256::Hash, 0);
-                self.client_addresses.remove(&address);
+                self.remove_subscription(&client, &address);
                 json_rpc_res!(request, true)
             }
 
@@ -386,10 +628,11 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             "blockchain.scriptpubkey.get_balance" => {
                 let script = get_arg!(request, ScriptBuf, 0);
                 let hash = get_spk_hash(&script);
+                let wallet = self.wallet_of(&client)?;
 
-                if !self.address_cache.is_address_cached(&hash) {
-                    self.address_cache.cache_address(script.clone());
-                    self.addresses_to_scan.push(script);
+                if !wallet.is_address_cached(&hash) {
+                    self.queue_rescan(&client, script.clone())?;
+                    wallet.cache_address(script);
                     let res = json!({
                         "confirmed": 0,
                         "unconfirmed": 0
@@ -397,7 +640,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                     return json_rpc_res!(request, res);
                 }
 
-                let balance = self.address_cache.get_address_balance(&hash);
+                let balance = wallet.get_address_balance(&hash);
                 let result = json!({
                     "confirmed": balance,
                     "unconfirmed": 0
@@ -407,14 +650,15 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             "blockchain.scriptpubkey.get_history" => {
                 let script = get_arg!(request, ScriptBuf, 0);
                 let hash = get_spk_hash(&script);
+                let wallet = self.wallet_of(&client)?;
 
-                if !self.address_cache.is_address_cached(&hash) {
-                    self.address_cache.cache_address(script.clone());
-                    self.addresses_to_scan.push(script);
+                if !wallet.is_address_cached(&hash) {
+                    self.queue_rescan(&client, script.clone())?;
+                    wallet.cache_address(script);
                     return json_rpc_res!(request, null);
                 }
 
-                self.address_cache
+                wallet
                     .get_address_history(&hash)
                     .map(|transactions| {
                         let res = Self::process_history(&transactions);
@@ -431,9 +675,10 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             "blockchain.scriptpubkey.subscribe" => {
                 let script = get_arg!(request, ScriptBuf, 0);
                 let hash = get_spk_hash(&script);
-                self.client_addresses.insert(hash, client);
+                let wallet = self.wallet_of(&client)?;
+                self.add_subscription(&client, hash)?;
 
-                let history = self.address_cache.get_address_history(&hash);
+                let history = wallet.get_address_history(&hash);
                 match history {
                     Some(transactions) if !transactions.is_empty() => {
                         let res = get_status(transactions);
@@ -443,7 +688,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                         json_rpc_res!(request, null)
                     }
                     None => {
-                        self.addresses_to_scan.push(script);
+                        self.queue_rescan(&client, script)?;
                         json_rpc_res!(request, null)
                     }
                 }
@@ -451,7 +696,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             "blockchain.scriptpubkey.unsubscribe" => {
                 let script = get_arg!(request, ScriptBuf, 0);
                 let hash = get_spk_hash(&script);
-                self.client_addresses.remove(&hash);
+                self.remove_subscription(&client, &hash);
                 json_rpc_res!(request, true)
             }
 
@@ -473,19 +718,24 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                     return Err(super::error::Error::Mempool(Box::new(e)));
                 };
 
-                let updated = self
-                    .address_cache
-                    .cache_mempool_transaction(&tx)
-                    .into_iter()
-                    .map(|spend| (tx.clone(), spend))
-                    .collect::<Vec<_>>();
+                // Clients that didn't authenticate may still broadcast, but we don't keep track
+                // of their transactions
+                if let Ok(wallet) = self.wallet_of(&client) {
+                    let updated = wallet
+                        .cache_mempool_transaction(&tx)
+                        .into_iter()
+                        .map(|spend| (tx.clone(), spend))
+                        .collect::<Vec<_>>();
+
+                    let tenant = self.client_tenants.get(&client.client_id).cloned();
+                    self.wallet_notify(tenant.as_deref(), &wallet, &updated);
+                }
 
-                self.wallet_notify(&updated);
                 json_rpc_res!(request, txid)
             }
             "blockchain.transaction.get" => {
                 let tx_id = get_arg!(request, Txid, 0);
-                let tx = self.address_cache.get_cached_transaction(&tx_id);
+                let tx = self.wallet_of(&client)?.get_cached_transaction(&tx_id);
                 if let Some(tx) = tx {
                     return json_rpc_res!(request, tx);
                 }
@@ -494,8 +744,9 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }
             "blockchain.transaction.get_merkle" => {
                 let tx_id = get_arg!(request, Txid, 0);
-                let proof = self.address_cache.get_merkle_proof(&tx_id);
-                let height = self.address_cache.get_height(&tx_id);
+                let wallet = self.wallet_of(&client)?;
+                let proof = wallet.get_merkle_proof(&tx_id);
+                let height = wallet.get_height(&tx_id);
                 if let Some(proof) = proof {
                     let result = json!({
                         "merkle": proof.hashes,
@@ -510,30 +761,59 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             //blockchain.transaction.id_from_pos
             // TODO: Create an actual histogram
             "mempool.get_fee_histogram" => json_rpc_res!(request, []),
-            "server.add_peer" => json_rpc_res!(request, true),
-            "server.banner" => json_rpc_res!(request, "Welcome to Floresta's Electrum Server."),
-            "server.donation_address" => {
-                json_rpc_res!(request, "")
-            }
-            "server.features" => {
+            "server.add_peer" => {
+                let features = get_arg!(request, Value, 0);
                 let genesis_hash = self
                     .chain
                     .get_block_hash(0)
                     .expect("Genesis block should be present");
-                let res = json!(
-                    {
-                        "genesis_hash": genesis_hash,
-                        "hosts": {"127.0.0.1": {"tcp_port": 50001}},
-                        "protocol_max": "1.5",
-                        "protocol_min": "1.4",
-                        "pruning": null,
-                        "server_version": format!("Floresta {}", env!("CARGO_PKG_VERSION")),
-                        "hash_function": "sha256"
+
+                // We only consider hosts that resolve to the address calling us, so nobody
+                // can make us connect to random servers.
+                let mut queued = false;
+                for host in hosts_from_features(&features) {
+                    if host.is_onion() || host.tcp_port.is_none() {
+                        debug!("Can't check Electrum peer {}, skipping", host.hostname);
+                        continue;
                     }
-                );
+
+                    queued |= self.check_peer(host, genesis_hash, Some(client.address.ip()), None);
+                }
+
+                json_rpc_res!(request, queued)
+            }
+            "server.authenticate" => {
+                let token = get_arg!(request, String, 0);
+                let tenant = self
+                    .tenant_config
+                    .authenticate_token(&token)
+                    .filter(|tenant| self.tenant_wallets.contains_key(*tenant))
+                    .ok_or(super::error::Error::AuthenticationFailed)?
+                    .to_string();
+
+                // A client can't switch tenants, since its subscriptions belong to the
+                // first one
+                let current = self.client_tenants.get(&client.client_id);
+                if current.is_some_and(|current| *current != tenant) {
+                    return Err(super::error::Error::AuthenticationFailed);
+                }
+
+                debug!("Client {} authenticated as {tenant}", client.address);
+                self.client_tenants.insert(client.client_id, tenant);
+                json_rpc_res!(request, true)
+            }
+            "server.banner" => json_rpc_res!(request, "Welcome to Floresta's Electrum Server."),
+            "server.donation_address" => {
+                json_rpc_res!(request, "")
+            }
+            "server.features" => {
+                let res = self.features();
+                json_rpc_res!(request, res)
+            }
+            "server.peers.subscribe" => {
+                let res = self.peers.subscribe_response();
                 json_rpc_res!(request, res)
             }
-            "server.peers.subscribe" => json_rpc_res!(request, []),
             "server.ping" => json_rpc_res!(request, null),
             "server.version" => json_rpc_res!(
                 request,
@@ -544,8 +824,181 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
         }
     }
 
+    /// Our `server.features`, as defined by the Electrum protocol
+    fn features(&self) -> Value {
+        let genesis_hash = self
+            .chain
+            .get_block_hash(0)
+            .expect("Genesis block should be present");
+
+        json!({
+            "genesis_hash": genesis_hash,
+            "hosts": hosts_features(&self.public_hosts),
+            "protocol_max": "1.5",
+            "protocol_min": "1.4",
+            "pruning": null,
+            "server_version": format!("Floresta {}", env!("CARGO_PKG_VERSION")),
+            "hash_function": "sha256"
+        })
+    }
+
+    /// Starts checking an Electrum peer in the background. The result is sent back to the
+    /// main loop as a [Message::PeerChecked].
+    ///
+    /// Returns false if this check wasn't started, because there are too many checks running
+    /// or we are already checking this peer.
+    fn check_peer(
+        &mut self,
+        host: ServerHost,
+        genesis_hash: BlockHash,
+        expected_address: Option<IpAddr>,
+        our_features: Option<Value>,
+    ) -> bool {
+        if self.pending_peer_checks.len() >= MAX_PENDING_PEER_CHECKS
+            || self.pending_peer_checks.contains(&host.hostname)
+        {
+            return false;
+        }
+
+        if !self.peers.contains(&host.hostname) && self.peers.len() >= MAX_KNOWN_PEERS {
+            return false;
+        }
+
+        self.pending_peer_checks.insert(host.hostname.clone());
+
+        let message_transmitter = self.message_transmitter.clone();
+        tokio::spawn(async move {
+            let hostname = host.hostname.clone();
+            let result = check_peer(host, genesis_hash, expected_address, our_features).await;
+            let _ = message_transmitter.send(Message::PeerChecked((hostname, result)));
+        });
+
+        true
+    }
+
+    /// Re-checks every peer we know, plus our seed peers, announcing ourselves to them if we
+    /// have public hosts.
+    fn check_known_peers(&mut self) {
+        let genesis_hash = self
+            .chain
+            .get_block_hash(0)
+            .expect("Genesis block should be present");
+
+        let our_features = (!self.public_hosts.is_empty()).then(|| self.features());
+
+        let mut hosts = self.peers.hosts();
+        for seed in &self.seed_peers {
+            if !self.peers.contains(&seed.hostname) {
+                hosts.push(seed.clone());
+            }
+        }
+
+        for host in hosts {
+            self.check_peer(host, genesis_hash, None, our_features.clone());
+        }
+    }
+
+    /// The wallet this client may use: its tenant's wallet in multi-tenant mode, or our only
+    /// wallet otherwise
+    fn wallet_of(
+        &self,
+        client: &Client,
+    ) -> Result<Arc<AddressCache<Database>>, super::error::Error> {
+        if self.tenant_config.is_empty() {
+            return Ok(self.address_cache.clone());
+        }
+
+        self.client_tenants
+            .get(&client.client_id)
+            .and_then(|tenant| self.tenant_wallets.get(tenant))
+            .cloned()
+            .ok_or(super::error::Error::AuthenticationRequired)
+    }
+
+    /// A tenant's wallet, or our own if `tenant` is `None`
+    fn wallet(&self, tenant: Option<&str>) -> Option<Arc<AddressCache<Database>>> {
+        match tenant {
+            Some(tenant) => self.tenant_wallets.get(tenant).cloned(),
+            None => Some(self.address_cache.clone()),
+        }
+    }
+
+    /// Every wallet we keep up to date, our own first, with the tenant it belongs to
+    fn wallets(&self) -> Vec<(Option<String>, Arc<AddressCache<Database>>)> {
+        let tenants = self
+            .tenant_wallets
+            .iter()
+            .map(|(tenant, wallet)| (Some(tenant.clone()), wallet.clone()));
+
+        std::iter::once((None, self.address_cache.clone()))
+            .chain(tenants)
+            .collect()
+    }
+
+    /// Records a new subscription for this client, enforcing the per-client limit
+    fn add_subscription(
+        &mut self,
+        client: &Arc<Client>,
+        hash: sha256::Hash,
+    ) -> Result<(), super::error::Error> {
+        let subscriptions = self.subscriptions.entry(client.client_id).or_default();
+        if subscriptions.len() >= self.limits.max_subscriptions_per_client
+            && !subscriptions.contains(&hash)
+        {
+            return Err(super::error::Error::TooManySubscriptions);
+        }
+
+        subscriptions.insert(hash);
+        self.client_addresses
+            .entry(hash)
+            .or_default()
+            .insert(client.client_id, client.clone());
+
+        Ok(())
+    }
+
+    /// Removes a subscription, if this client is subscribed to it
+    fn remove_subscription(&mut self, client: &Client, hash: &sha256::Hash) {
+        if let Some(subscriptions) = self.subscriptions.get_mut(&client.client_id) {
+            subscriptions.remove(hash);
+        }
+
+        self.remove_subscriber(client.client_id, hash);
+    }
+
+    /// Stops notifying a client about a script hash
+    fn remove_subscriber(&mut self, id: ClientId, hash: &sha256::Hash) {
+        if let Some(subscribers) = self.client_addresses.get_mut(hash) {
+            subscribers.remove(&id);
+
+            if subscribers.is_empty() {
+                self.client_addresses.remove(hash);
+            }
+        }
+    }
+
+    /// Adds a script to the list of scripts waiting for a rescan, unless there are too many
+    /// of them already. The rescan only touches the wallet of the client asking for it.
+    fn queue_rescan(
+        &mut self,
+        client: &Client,
+        script: ScriptBuf,
+    ) -> Result<(), super::error::Error> {
+        if self.addresses_to_scan.len() >= self.limits.max_pending_rescans {
+            return Err(super::error::Error::TooManyPendingRescans);
+        }
+
+        let tenant = self.client_tenants.get(&client.client_id).cloned();
+        self.addresses_to_scan.push((tenant, script));
+        Ok(())
+    }
+
     pub async fn rebroadcast_mempool_transactions(&self) {
-        let unconfirmed = self.address_cache.find_unconfirmed().unwrap();
+        let unconfirmed = self
+            .wallets()
+            .into_iter()
+            .flat_map(|(_, wallet)| wallet.find_unconfirmed().unwrap());
+
         for tx in unconfirmed {
             let txid = tx.compute_txid();
             if let Ok(Err(e)) = self.node_interface.broadcast_transaction(tx.clone()).await {
@@ -564,7 +1017,7 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
 
         loop {
             for (block, height) in blocks.recv() {
-                self.handle_block(block, height);
+                self.handle_block(&block, height);
             }
 
             // handles client requests
@@ -593,19 +1046,46 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                 self.last_rebroadcast = Some(Instant::now());
             }
 
+            // Check our Electrum peers, and tell them about us
+            let should_check_peers = self
+                .last_peer_check
+                .map(|last| last.elapsed() > Duration::from_secs(PEER_CHECK_INTERVAL))
+                .unwrap_or(true);
+
+            if should_check_peers {
+                self.check_known_peers();
+                self.last_peer_check = Some(Instant::now());
+            }
+
             // rescan for new addresses, if any
             if !self.addresses_to_scan.is_empty() {
                 if self.chain.is_in_ibd() {
                     continue;
                 }
 
-                self.addresses_to_scan.iter().for_each(|address| {
-                    self.address_cache.cache_address(address.clone());
-                });
+                // Each wallet is rescanned on its own, so blocks found for one tenant never
+                // end up in someone else's wallet
+                let mut by_tenant: HashMap<Option<String>, Vec<ScriptBuf>> = HashMap::new();
+                for (tenant, address) in self.addresses_to_scan.drain(..) {
+                    by_tenant.entry(tenant).or_default().push(address);
+                }
+
+                for (tenant, addresses) in by_tenant {
+                    let Some(wallet) = self.wallet(tenant.as_deref()) else {
+                        continue;
+                    };
+
+                    addresses.iter().for_each(|address| {
+                        wallet.cache_address(address.clone());
+                    });
 
-                info!("Catching up with addresses {:?}", self.addresses_to_scan);
-                let addresses: Vec<_> = self.addresses_to_scan.drain(..).collect();
-                self.rescan_for_addresses(addresses).await?;
+                    info!("Catching up with addresses {addresses:?}");
+                    self.rescan_for_addresses(tenant, wallet, addresses).await?;
+                }
+            }
+
+            if !self.chain.is_in_ibd() {
+                self.resume_rescans().await;
             }
         }
     }
@@ -613,67 +1093,81 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
     /// If a user adds a new address that we didn't have cached, this method
     /// will look for historical transactions for it.
     ///
-    /// Usually, we'll rely on compact block filters to speed things up. If
-    /// we don't have compact block filters, we may rescan using the older,
-    /// more bandwidth-intensive method of actually downloading blocks.
+    /// We rely on compact block filters to find the blocks we need, so if they
+    /// aren't enabled, there's nothing we can do.
     async fn rescan_for_addresses(
         &mut self,
+        tenant: Option<String>,
+        wallet: Arc<AddressCache<Database>>,
         addresses: Vec<ScriptBuf>,
     ) -> Result<(), super::error::Error> {
-        // If compact block filters are enabled, use them. Otherwise, fallback
-        // to the "old-school" rescaning.
-        if let Some(cfilters) = &self.block_filters {
-            self.rescan_with_block_filters(cfilters.clone(), None, None, addresses)
-                .await?;
+        if self.block_filters.is_none() {
+            return Ok(());
         }
 
+        let stop_height = self
+            .chain
+            .get_height()
+            .map_err(|e| super::error::Error::Blockchain(Box::new(e)))?;
+        let source = RescanSource::Electrum {
+            tenant: tenant.clone(),
+        };
+
+        let id = self.rescans.start(source, addresses, 0, stop_height);
+        self.rescan_with_block_filters(id, tenant, wallet).await;
+
         Ok(())
     }
 
-    /// If we have compact block filters enabled, this method will use them to
-    /// find blocks of interest and download for our wallet to learn about new
-    /// transactions, once a new address is added by subscription.
-    async fn rescan_with_block_filters(
-        &mut self,
-        cfilters: Arc<NetworkFilters<FlatFiltersStore>>,
-        start_height: Option<u32>,
-        stop_height: Option<u32>,
-        addresses: Vec<ScriptBuf>,
-    ) -> Result<(), super::error::Error> {
-        // By default, we look from 1..tip
-        let mut _addresses = addresses
-            .iter()
-            .map(|address| address.as_bytes())
-            .collect::<Vec<_>>();
-
-        let Ok(blocks) =
-            cfilters.match_any(_addresses, start_height, stop_height, self.chain.clone())
-        else {
-            self.addresses_to_scan.extend(addresses); // push them back to get a retry
-            return Ok(());
-        };
+    /// Picks up rescans that got interrupted, either because we couldn't download some block,
+    /// or because we were shut down in the middle of them.
+    async fn resume_rescans(&mut self) {
+        if self.block_filters.is_none() {
+            return;
+        }
 
-        info!("filters told us to scan blocks: {blocks:?}");
+        let interrupted = self
+            .rescans
+            .take_interrupted(|source| matches!(source, RescanSource::Electrum { .. }));
 
-        // Tells users about the transactions we found
-        for block in blocks {
-            let block = self.node_interface.get_block(block).await;
-            let Ok(Some(block)) = block else {
-                self.addresses_to_scan.extend(addresses); // push them back to get a retry
-                return Ok(());
+        for (id, checkpoint) in interrupted {
+            let RescanSource::Electrum { tenant } = checkpoint.source else {
+                continue;
             };
 
-            let height = self
-                .chain
-                .get_block_height(&block.block_hash())
-                .ok()
-                .flatten()
-                .unwrap();
+            // The tenant may have been removed since this rescan started
+            let Some(wallet) = self.wallet(tenant.as_deref()) else {
+                self.rescans.discard(id);
+                continue;
+            };
 
-            self.handle_block(block, height);
+            info!("Resuming rescan from height {}", checkpoint.next_height);
+            self.rescan_with_block_filters(id, tenant, wallet).await;
         }
+    }
 
-        Ok(())
+    /// Runs the rescan job `id`, using our compact block filters to find blocks of interest.
+    /// Blocks are downloaded and given to `wallet`, so clients learn about the transactions
+    /// we found. See [RescanManager] for how progress is tracked.
+    async fn rescan_with_block_filters(
+        &self,
+        id: RescanJobId,
+        tenant: Option<String>,
+        wallet: Arc<AddressCache<Database>>,
+    ) {
+        let Some(cfilters) = &self.block_filters else {
+            return;
+        };
+
+        self.rescans
+            .run(
+                id,
+                cfilters,
+                &self.chain,
+                &self.node_interface,
+                |block, height| self.process_block(tenant.as_deref(), &wallet, block, height),
+            )
+            .await;
     }
 
     fn process_history(transactions: &[CachedTransaction]) -> Vec<Value> {
@@ -697,7 +1191,8 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
         res
     }
 
-    fn handle_block(&self, block: bitcoin::Block, height: u32) {
+    /// Handles a new block, telling clients about it and updating every wallet
+    fn handle_block(&self, block: &bitcoin::Block, height: u32) {
         let result = json!({
             "jsonrpc": "2.0",
             "method": "blockchain.headers.subscribe",
@@ -707,12 +1202,6 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }]
         });
 
-        let current_height = self.address_cache.get_cache_height();
-
-        if (!self.chain.is_in_ibd() || height % 1000 == 0) && (height > current_height) {
-            self.address_cache.bump_height(height);
-        }
-
         if self.chain.get_height().unwrap() == height {
             for client in &mut self.clients.values() {
                 let res = client.write(serde_json::to_string(&result).unwrap().as_bytes());
@@ -722,74 +1211,84 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
             }
         }
 
-        let transactions = self.address_cache.block_process(&block, height);
+        for (tenant, wallet) in self.wallets() {
+            self.process_block(tenant.as_deref(), &wallet, block, height);
+        }
+    }
+
+    /// Looks for a wallet's transactions inside a block, and notifies the clients subscribed to
+    /// the affected scripts
+    fn process_block(
+        &self,
+        tenant: Option<&str>,
+        wallet: &AddressCache<Database>,
+        block: &bitcoin::Block,
+        height: u32,
+    ) {
+        let current_height = wallet.get_cache_height();
+
+        if (!self.chain.is_in_ibd() || height % 1000 == 0) && (height > current_height) {
+            wallet.bump_height(height);
+        }
+
+        let transactions = wallet.block_process(block, height);
 
-        self.wallet_notify(&transactions);
+        self.wallet_notify(tenant, wallet, &transactions);
     }
 
     /// Handles each kind of Message
     async fn handle_message(&mut self, message: Message) -> Result<(), crate::error::Error> {
         match message {
             Message::NewClient((id, client)) => {
+                // The accept loop already checked this, but we may have banned this client's IP
+                // address during its handshake
+                if self.gate.is_banned(client.address.ip()) {
+                    debug!("Rejecting banned client {}", client.address);
+                    client.shutdown();
+                    return Ok(());
+                }
+
+                if self.clients.len() >= self.limits.max_clients {
+                    info!("Too many clients, rejecting {}", client.address);
+                    client.shutdown();
+                    return Ok(());
+                }
+
+                let tenant = client
+                    .certificate
+                    .and_then(|fingerprint| {
+                        self.tenant_config.authenticate_certificate(&fingerprint)
+                    })
+                    .filter(|tenant| self.tenant_wallets.contains_key(*tenant));
+
+                if let Some(tenant) = tenant {
+                    debug!("Client {} authenticated as {tenant}", client.address);
+                    self.client_tenants.insert(id, tenant.to_string());
+                }
+
+                self.budgets.insert(id, RequestBudget::new(&self.limits));
                 self.clients.insert(id, client);
+                self.gate.set_clients(self.clients.len());
             }
 
             Message::Message((client, msg)) => {
                 trace!("Message: {msg}");
+                let Some(client) = self.clients.get(&client).cloned() else {
+                    error!("Client sent a message but is not listed as client");
+                    return Ok(());
+                };
+
                 if let Ok(req) = serde_json::from_str::<Request>(msg.as_str()) {
-                    let client = self.clients.get(&client);
-                    if client.is_none() {
-                        error!("Client sent a message but is not listed as client");
-                        return Ok(());
-                    }
-                    let client = client.unwrap().to_owned();
-                    let id = req.id.to_owned();
-                    let res = self.handle_client_request(client.clone(), req).await;
-
-                    if let Ok(res) = res {
-                        client.write(serde_json::to_string(&res).unwrap().as_bytes())?;
-                    } else {
-                        let res = json!({
-                            "jsonrpc": "2.0",
-                            "error": {
-                                "code": -32000,
-                                "message": "Internal JSON-RPC error.",
-                                "data": null
-                            },
-                            "id": id
-                        });
-                        client.write(serde_json::to_string(&res).unwrap().as_bytes())?;
-                    }
+                    let res = self.handle_metered_request(client.clone(), req).await;
+                    client.write(serde_json::to_string(&res).unwrap().as_bytes())?;
                 } else if let Ok(requests) = serde_json::from_str::<Vec<Request>>(&msg) {
                     let mut results = Vec::new();
                     for req in requests {
-                        let client = self.clients.get(&client);
-                        if client.is_none() {
-                            error!("Client sent a message but is not listed as client");
-                            return Ok(());
-                        }
-                        let client = client.unwrap().to_owned();
-                        let id = req.id.to_owned();
-                        let res = self.handle_client_request(client.clone(), req).await;
-
-                        if let Ok(res) = res {
-                            results.push(res);
-                        } else {
-                            let res = json!({
-                                "jsonrpc": "2.0",
-                                "error": {
-                                    "code": -32000,
-                                    "message": "Internal JSON-RPC error.",
-                                    "data": null
-                                },
-                                "id": id
-                            });
-                            results.push(res);
-                        }
-                    }
-                    if let Some(client) = self.clients.get(&client) {
-                        client.write(serde_json::to_string(&results).unwrap().as_bytes())?;
+                        let res = self.handle_metered_request(client.clone(), req).await;
+                        results.push(res);
                     }
+
+                    client.write(serde_json::to_string(&results).unwrap().as_bytes())?;
                 } else {
                     let res = json!({
                         "jsonrpc": "2.0",
@@ -800,32 +1299,168 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                         },
                         "id": null
                     });
-                    if let Some(client) = self.clients.get(&client) {
-                        client.write(serde_json::to_string(&res).unwrap().as_bytes())?;
-                    }
+                    client.write(serde_json::to_string(&res).unwrap().as_bytes())?;
                 }
             }
 
             Message::Disconnect(id) => {
-                self.clients.remove(&id);
+                self.remove_client(id);
+            }
+
+            Message::Misbehaving(id) => {
+                self.ban_client(id);
+            }
+
+            Message::PeerChecked((hostname, result)) => {
+                self.pending_peer_checks.remove(&hostname);
+
+                match result {
+                    Ok(peer) => {
+                        info!("Electrum peer {hostname} is up");
+                        self.peers.add(peer);
+                    }
+                    Err(e) => {
+                        debug!("Electrum peer {hostname} failed its check: {e}");
+                        self.peers.remove(&hostname);
+                    }
+                }
+
+                if let Err(e) = self.peers.save() {
+                    error!("Could not save Electrum peers: {e}");
+                }
             }
         }
 
         Ok(())
     }
 
-    fn wallet_notify(&self, transactions: &[(Transaction, TxOut)]) {
+    /// Handles a request, charging its cost from the client's budget.
+    ///
+    /// The base cost is charged before doing any work, and requests the client can't afford
+    /// are answered with an error. Whatever the response costs on top of that is charged
+    /// afterwards, and may leave the client in debt for a while.
+    async fn handle_metered_request(&mut self, client: Arc<Client>, req: Request) -> Value {
+        let id = req.id.to_owned();
+        let method = req.method.clone();
+
+        let base_cost = base_request_cost(&method);
+        let within_budget = self
+            .budgets
+            .get_mut(&client.client_id)
+            .map(|budget| budget.try_spend(&self.limits, base_cost))
+            .unwrap_or(false);
+
+        if !within_budget {
+            debug!("Client {} exceeded its request budget", client.address);
+            return json!({
+                "jsonrpc": "2.0",
+                "error": {
+                    "code": -32000,
+                    "message": "Request rate limit exceeded, try again later.",
+                    "data": null
+                },
+                "id": id
+            });
+        }
+
+        let res = self
+            .handle_client_request(client.clone(), req)
+            .await
+            .unwrap_or_else(|_| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "error": {
+                        "code": -32000,
+                        "message": "Internal JSON-RPC error.",
+                        "data": null
+                    },
+                    "id": id
+                })
+            });
+
+        let response_cost = response_cost(&method, &res);
+        if let Some(budget) = self.budgets.get_mut(&client.client_id) {
+            budget.charge(&self.limits, response_cost);
+        }
+
+        res
+    }
+
+    /// Whether we may ban this IP address.
+    ///
+    /// Clients coming through our onion service, or through any other local proxy, all show up
+    /// with a loopback address. Banning it would ban every one of them, so we only disconnect
+    /// misbehaving clients coming from there.
+    fn is_bannable(ip: IpAddr) -> bool {
+        !ip.to_canonical().is_loopback()
+    }
+
+    /// Disconnects a client and bans its IP address for a while
+    fn ban_client(&mut self, id: ClientId) {
+        let Some(client) = self.clients.get(&id).cloned() else {
+            return;
+        };
+
+        let ip = client.address.ip();
+        if Self::is_bannable(ip) {
+            info!(
+                "Banning {ip} for {} seconds",
+                self.limits.ban_duration.as_secs()
+            );
+
+            let until = Instant::now() + self.limits.ban_duration;
+            self.gate.ban(ip, until);
+        } else {
+            info!("Disconnecting misbehaving client {}", client.address);
+        }
+
+        client.shutdown();
+        self.remove_client(id);
+    }
+
+    /// Forgets everything we know about a client
+    fn remove_client(&mut self, id: ClientId) {
+        self.clients.remove(&id);
+        self.gate.set_clients(self.clients.len());
+        self.budgets.remove(&id);
+        self.client_tenants.remove(&id);
+
+        if let Some(subscriptions) = self.subscriptions.remove(&id) {
+            for hash in subscriptions {
+                self.remove_subscriber(id, &hash);
+            }
+        }
+    }
+
+    /// Tells clients subscribed to the scripts touched by these transactions about their new
+    /// status. Only clients of the wallet's tenant are notified.
+    fn wallet_notify(
+        &self,
+        tenant: Option<&str>,
+        wallet: &AddressCache<Database>,
+        transactions: &[(Transaction, TxOut)],
+    ) {
         for (_, out) in transactions {
             let hash = get_spk_hash(&out.script_pubkey);
-            if let Some(client) = self.client_addresses.get(&hash) {
-                let history = self.address_cache.get_address_history(&hash);
+            let Some(subscribers) = self.client_addresses.get(&hash) else {
+                continue;
+            };
+
+            let history = wallet.get_address_history(&hash);
+
+            let status_hash = get_status(history.unwrap());
+            let notify = json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.scripthash.subscribe",
+                "params": [hash, status_hash]
+            });
+
+            for client in subscribers.values() {
+                let client_tenant = self.client_tenants.get(&client.client_id);
+                if client_tenant.map(String::as_str) != tenant {
+                    continue;
+                }
 
-                let status_hash = get_status(history.unwrap());
-                let notify = json!({
-                    "jsonrpc": "2.0",
-                    "method": "blockchain.scripthash.subscribe",
-                    "params": [hash, status_hash]
-                });
                 if let Err(err) = client.write(serde_json::to_string(&notify).unwrap().as_bytes()) {
                     error!("{err}");
                 }
@@ -834,42 +1469,119 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
     }
 }
 
+/// The id of the next client to connect, shared by every accept loop so ids never collide
+static NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Connects a new client to the server, over any stream.
+///
+/// This is used by [client_accept_loop] for TCP and TLS connections, but can also be used to
+/// talk to the server in-process, e.g. through a [tokio::io::duplex] pipe. The `address` is
+/// only used for banning misbehaving clients.
+pub fn add_client<S: AsyncStream + 'static>(
+    stream: S,
+    address: SocketAddr,
+    message_transmitter: &UnboundedSender<Message>,
+    limits: &ElectrumLimits,
+) {
+    add_client_with_certificate(stream, address, None, message_transmitter, limits);
+}
+
+/// Connects a new client that presented a TLS client certificate with this fingerprint. In
+/// multi-tenant mode, pinned certificates authenticate their clients, see
+/// [TenantConfig::add_certificate].
+pub fn add_client_with_certificate<S: AsyncStream + 'static>(
+    stream: S,
+    address: SocketAddr,
+    certificate: Option<sha256::Hash>,
+    message_transmitter: &UnboundedSender<Message>,
+    limits: &ElectrumLimits,
+) {
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    let client = Arc::new(Client::new(
+        client_id,
+        address,
+        certificate,
+        stream,
+        message_transmitter.clone(),
+        limits,
+    ));
+
+    message_transmitter
+        .send(Message::NewClient((client_id, client)))
+        .expect("Main loop is broken");
+}
+
 /// Listens to new TCP connections in a loop
+///
+/// The `limits` and `gate` should be the ones used by the [ElectrumServer], see
+/// [ElectrumServer::get_limits] and [ElectrumServer::get_connection_gate]. Connections from
+/// banned addresses, or above our client limit, are dropped right away.
+///
+/// TLS handshakes run in their own tasks, so a slow client can't hold up everyone else, and
+/// must finish within [TLS_HANDSHAKE_TIMEOUT].
 pub async fn client_accept_loop(
     listener: Arc<TcpListener>,
     message_transmitter: UnboundedSender<Message>,
     tls_acceptor: Option<TlsAcceptor>,
+    limits: ElectrumLimits,
+    gate: Arc<ConnectionGate>,
 ) {
-    let mut id_count = 0;
     loop {
-        if let Ok((stream, _addr)) = listener.accept().await {
-            info!("New client connection");
-            let message_transmitter = message_transmitter.clone();
-            if let Some(acceptor) = tls_acceptor.clone() {
-                match acceptor.accept(stream).await {
-                    Ok(tls_stream) => {
-                        let client = Arc::new(Client::new(
-                            id_count,
-                            tls_stream,
-                            message_transmitter.clone(),
-                        ));
-                        message_transmitter
-                            .send(Message::NewClient((client.client_id, client)))
-                            .expect("Main loop is broken");
-                        id_count += 1;
-                    }
-                    Err(e) => {
-                        error!("TLS accept error: {e:?}");
-                    }
+        let Ok((stream, addr)) = listener.accept().await else {
+            continue;
+        };
+
+        if gate.is_banned(addr.ip()) {
+            debug!("Rejecting banned client {addr}");
+            continue;
+        }
+
+        if gate.is_full(limits.max_clients) {
+            info!("Too many clients, rejecting {addr}");
+            continue;
+        }
+
+        info!("New client connection");
+        let Some(acceptor) = tls_acceptor.clone() else {
+            add_client(stream, addr, &message_transmitter, &limits);
+            continue;
+        };
+
+        gate.start_handshake();
+
+        let message_transmitter = message_transmitter.clone();
+        let limits = limits.clone();
+        let gate = gate.clone();
+        tokio::spawn(async move {
+            let handshake =
+                tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await;
+            gate.finish_handshake();
+
+            match handshake {
+                Ok(Ok(tls_stream)) => {
+                    let certificate = tls_stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certificates| certificates.first())
+                        .map(|certificate| certificate_fingerprint(certificate));
+
+                    add_client_with_certificate(
+                        tls_stream,
+                        addr,
+                        certificate,
+                        &message_transmitter,
+                        &limits,
+                    );
+                }
+                Ok(Err(e)) => {
+                    error!("TLS accept error: {e:?}");
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {addr} timed out");
                 }
-            } else {
-                let client = Arc::new(Client::new(id_count, stream, message_transmitter.clone()));
-                message_transmitter
-                    .send(Message::NewClient((client.client_id, client)))
-                    .expect("Main loop is broken");
-                id_count += 1;
             }
-        }
+        });
     }
 }
 
@@ -941,9 +1653,11 @@ macro_rules! get_arg {
 #[cfg(test)]
 mod test {
     use core::str::FromStr;
+    use std::collections::HashMap;
     use std::io;
     use std::sync::Arc;
     use std::time::Duration;
+    use std::time::Instant;
 
     use bitcoin::address::NetworkChecked;
     use bitcoin::block::Header as BlockHeader;
@@ -962,8 +1676,10 @@ mod test {
     use floresta_common::get_spk_hash;
     use floresta_mempool::Mempool;
     use floresta_watch_only::kv_database::KvDatabase;
+    use floresta_watch_only::memory_database::MemoryDatabase;
     use floresta_watch_only::merkle::MerkleProof;
     use floresta_watch_only::AddressCache;
+    use floresta_watch_only::AddressCacheDatabase;
     use floresta_wire::address_man::AddressMan;
     use floresta_wire::address_man::SUPPORTED_NETWORKS;
     use floresta_wire::node::running_ctx::RunningNode;
@@ -975,8 +1691,12 @@ mod test {
     use serde_json::Number;
     use serde_json::Value;
     use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncReadExt;
     use tokio::io::AsyncWriteExt;
     use tokio::io::BufReader;
+    use tokio::io::DuplexStream;
+    use tokio::io::ReadHalf;
+    use tokio::io::WriteHalf;
     use tokio::net::TcpListener;
     use tokio::net::TcpStream;
     use tokio::sync::Mutex;
@@ -988,8 +1708,15 @@ mod test {
     use tokio_rustls::rustls::ServerConfig;
     use tokio_rustls::TlsAcceptor;
 
+    use super::add_client;
     use super::client_accept_loop;
     use super::ElectrumServer;
+    use crate::builder::ElectrumServerBuilder;
+    use crate::limits::ConnectionGate;
+    use crate::limits::ElectrumLimits;
+    use crate::peers::PeerDiscoveryConfig;
+    use crate::peers::ServerHost;
+    use crate::tenants::TenantConfig;
 
     /// A size used for mempool tests, no specific meaning just a randomly
     /// chosen size.
@@ -1027,8 +1754,10 @@ mod test {
     fn get_test_cache() -> Arc<AddressCache<KvDatabase>> {
         let test_id: u32 = rand::random();
         let cache = KvDatabase::new(format!("./tmp-db/{test_id}.floresta")).unwrap();
-        let cache = AddressCache::new(cache);
+        fill_test_cache(AddressCache::new(cache))
+    }
 
+    fn fill_test_cache<D: AddressCacheDatabase>(cache: AddressCache<D>) -> Arc<AddressCache<D>> {
         // Inserting test transactions in the wallet
         let (transaction, proof) = get_test_transaction();
         cache.cache_transaction(
@@ -1078,10 +1807,62 @@ mod test {
 
     // Returns the port assigned by the OS
     async fn start_electrum() -> u16 {
+        start_electrum_with(ElectrumLimits::default(), PeerDiscoveryConfig::default()).await
+    }
+
+    async fn start_electrum_with(
+        limits: ElectrumLimits,
+        peer_discovery: PeerDiscoveryConfig,
+    ) -> u16 {
         let e_addr = "0.0.0.0:0";
         let ssl_e_addr = "0.0.0.0:0";
         let wallet = get_test_cache();
+        let (chain, chain_provider) = get_test_node();
+        let node_interface = chain_provider.get_handle();
+
+        let tls_config = Some(create_tls_config().expect("Failed to create TLS config"));
+        let tls_acceptor = tls_config.map(TlsAcceptor::from);
+
+        let electrum_server: ElectrumServer<ChainState<FlatChainStore>> =
+            ElectrumServer::new(wallet, chain, None, node_interface)
+                .unwrap()
+                .with_limits(limits)
+                .with_peer_discovery(peer_discovery);
+        let non_tls_listener = Arc::new(TcpListener::bind(e_addr).await.unwrap());
+        let assigned_port = non_tls_listener.local_addr().unwrap().port();
 
+        let (stop_signal, _) = tokio::sync::oneshot::channel();
+        task::spawn(chain_provider.run(stop_signal));
+        task::spawn(client_accept_loop(
+            non_tls_listener,
+            electrum_server.message_transmitter.clone(),
+            None,
+            electrum_server.get_limits(),
+            electrum_server.get_connection_gate(),
+        ));
+
+        // TLS Electrum accept loop
+        if let Some(tls_acceptor) = tls_acceptor {
+            let tls_listener = Arc::new(TcpListener::bind(ssl_e_addr).await.unwrap());
+            task::spawn(client_accept_loop(
+                tls_listener,
+                electrum_server.message_transmitter.clone(),
+                Some(tls_acceptor),
+                electrum_server.get_limits(),
+                electrum_server.get_connection_gate(),
+            ));
+        }
+
+        // Electrum main loop
+        task::spawn(electrum_server.main_loop());
+        assigned_port
+    }
+
+    type TestNode = UtreexoNode<Arc<ChainState<FlatChainStore>>, RunningNode>;
+
+    /// Creates a signet chain with some headers, and a node on top of it that isn't
+    /// connected to anyone
+    fn get_test_node() -> (Arc<ChainState<FlatChainStore>>, TestNode) {
         // Create test_chain_state
         let test_id = rand::random::<u32>();
         let conf = FlatChainStoreConfig::new(format!("./tmp-db/{test_id}.floresta/"));
@@ -1117,37 +1898,7 @@ mod test {
             )
             .unwrap();
 
-        let node_interface = chain_provider.get_handle();
-
-        let tls_config = Some(create_tls_config().expect("Failed to create TLS config"));
-        let tls_acceptor = tls_config.map(TlsAcceptor::from);
-
-        let electrum_server: ElectrumServer<ChainState<FlatChainStore>> =
-            ElectrumServer::new(wallet, chain, None, node_interface).unwrap();
-        let non_tls_listener = Arc::new(TcpListener::bind(e_addr).await.unwrap());
-        let assigned_port = non_tls_listener.local_addr().unwrap().port();
-
-        let (stop_signal, _) = tokio::sync::oneshot::channel();
-        task::spawn(chain_provider.run(stop_signal));
-        task::spawn(client_accept_loop(
-            non_tls_listener,
-            electrum_server.message_transmitter.clone(),
-            None,
-        ));
-
-        // TLS Electrum accept loop
-        if let Some(tls_acceptor) = tls_acceptor {
-            let tls_listener = Arc::new(TcpListener::bind(ssl_e_addr).await.unwrap());
-            task::spawn(client_accept_loop(
-                tls_listener,
-                electrum_server.message_transmitter.clone(),
-                Some(tls_acceptor),
-            ));
-        }
-
-        // Electrum main loop
-        task::spawn(electrum_server.main_loop());
-        assigned_port
+        (chain, chain_provider)
     }
 
     /// Create a tls config thats valid for localhost with a random
@@ -1212,6 +1963,8 @@ mod test {
             "blockchain.transaction.broadcast" => vec![req_params.pop().unwrap()],
             "blockchain.transaction.get" => vec![req_params.pop().unwrap()],
             "blockchain.transaction.get_merkle" => vec![req_params.pop().unwrap()],
+            "server.add_peer" => vec![req_params.pop().unwrap()],
+            "server.authenticate" => vec![req_params.pop().unwrap()],
 
             _ => vec![],
         };
@@ -1443,9 +2196,9 @@ mod test {
         let method = Value::String("mempool.get_fee_histogram".to_string());
         batch_req_params.push(vec![method]);
 
-        // server.add_peer
+        // server.add_peer, with no hosts we could check
         let method = Value::String("server.add_peer".to_string());
-        batch_req_params.push(vec![method]);
+        batch_req_params.push(vec![json!({ "hosts": {} }), method]);
 
         // server.donation_address
         let method = Value::String("server.donation_address".to_string());
@@ -1475,7 +2228,7 @@ mod test {
         let batch_response = send_request(batch_req, port).await.unwrap();
 
         assert!(batch_response[0]["result"].as_array().unwrap().is_empty());
-        assert!(batch_response[1]["result"].as_bool().unwrap());
+        assert!(!batch_response[1]["result"].as_bool().unwrap());
         assert_eq!(batch_response[2]["result"], "".to_string());
         assert_eq!(
             batch_response[3]["result"]["genesis_hash"],
@@ -1488,4 +2241,413 @@ mod test {
             format!("Floresta {}", env!("CARGO_PKG_VERSION"))
         );
     }
+
+    #[tokio::test]
+    async fn test_max_subscriptions() {
+        let port = start_electrum_with(
+            ElectrumLimits {
+                max_subscriptions_per_client: 1,
+                ..Default::default()
+            },
+            PeerDiscoveryConfig::default(),
+        )
+        .await;
+
+        let (_, script_hash) = get_test_address();
+        let other_hash = sha256::Hash::from_str(
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        )
+        .unwrap();
+
+        let method = Value::String("blockchain.scripthash.subscribe".to_string());
+        let mut batch_req_params = vec![
+            vec![Value::String(script_hash.to_string()), method.clone()],
+            vec![Value::String(script_hash.to_string()), method.clone()],
+            vec![Value::String(other_hash.to_string()), method],
+        ];
+
+        let batch_req = generate_batch_request(&mut batch_req_params);
+        let mut batch_req = json!(batch_req).to_string();
+        batch_req.push('\n');
+
+        let batch_response = send_request(batch_req, port).await.unwrap();
+
+        // Subscribing twice to the same script is fine, but a second script is over the limit
+        assert!(batch_response[0]["error"].is_null());
+        assert!(batch_response[1]["error"].is_null());
+        assert!(!batch_response[2]["error"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_disconnects_client() {
+        let port = start_electrum_with(
+            ElectrumLimits {
+                max_request_bytes: 256,
+                ..Default::default()
+            },
+            PeerDiscoveryConfig::default(),
+        )
+        .await;
+
+        let method = Value::String("server.banner".to_string());
+        let mut request = generate_request(&mut vec![method]).to_string();
+        request.push('\n');
+
+        // A normal request works
+        assert_ok!(send_request(request.clone(), port).await);
+
+        // This one is too big, so we get disconnected without an answer
+        let mut oversized = "x".repeat(512);
+        oversized.push('\n');
+        assert!(send_request(oversized, port).await.is_err());
+
+        // We are connecting from localhost, which is never banned, so we can reconnect
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_ok!(send_request(request, port).await);
+    }
+
+    #[test]
+    fn test_is_bannable() {
+        type Server = ElectrumServer<ChainState<FlatChainStore>>;
+
+        assert!(Server::is_bannable("203.0.113.7".parse().unwrap()));
+        assert!(Server::is_bannable("2001:db8::1".parse().unwrap()));
+
+        // Onion and proxied clients all show up as loopback
+        assert!(!Server::is_bannable("127.0.0.1".parse().unwrap()));
+        assert!(!Server::is_bannable("::1".parse().unwrap()));
+        assert!(!Server::is_bannable("::ffff:127.0.0.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_accept_loop_drops_connections_before_handshake() {
+        let listener = Arc::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let port = listener.local_addr().unwrap().port();
+        let (transmitter, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let acceptor = TlsAcceptor::from(create_tls_config().unwrap());
+        let gate = Arc::new(ConnectionGate::default());
+
+        task::spawn(client_accept_loop(
+            listener,
+            transmitter,
+            Some(acceptor),
+            ElectrumLimits {
+                max_clients: 1,
+                ..Default::default()
+            },
+            gate.clone(),
+        ));
+
+        // Closed right away, without waiting for a handshake
+        let closed = |mut stream: TcpStream| async move {
+            let mut buf = [0; 1];
+            let read = timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+            matches!(read, Ok(Ok(0)) | Ok(Err(_)))
+        };
+
+        // This client never starts its handshake, and takes the only slot we have
+        let _stalled = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(gate.is_full(1));
+
+        // But it doesn't keep us from dropping the next one, since we are full
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        assert!(closed(stream).await);
+
+        // Banned clients are dropped too
+        gate.ban(
+            "127.0.0.1".parse().unwrap(),
+            Instant::now() + Duration::from_secs(60),
+        );
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        assert!(closed(stream).await);
+
+        // None of them made it to the server
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_requests_are_refused() {
+        let port = start_electrum_with(
+            ElectrumLimits {
+                request_cost_per_second: 0,
+                request_cost_burst: 2,
+                ..Default::default()
+            },
+            PeerDiscoveryConfig::default(),
+        )
+        .await;
+
+        let method = Value::String("server.banner".to_string());
+        let mut batch_req_params = vec![vec![method.clone()], vec![method.clone()], vec![method]];
+        let batch_req = generate_batch_request(&mut batch_req_params);
+        let mut batch_req = json!(batch_req).to_string();
+        batch_req.push('\n');
+
+        // We only have budget for two requests, the third one is refused, but we still get an
+        // answer for the whole batch
+        let batch_response = send_request(batch_req.clone(), port).await.unwrap();
+        assert!(batch_response[0]["error"].is_null());
+        assert!(batch_response[1]["error"].is_null());
+        assert!(batch_response[2]["result"].is_null());
+        assert_eq!(
+            batch_response[2]["error"]["message"],
+            "Request rate limit exceeded, try again later."
+        );
+
+        // Running out of budget doesn't get us banned
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let batch_response = send_request(batch_req, port).await.unwrap();
+        assert!(batch_response[0]["error"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let port = start_electrum_with(
+            ElectrumLimits {
+                idle_timeout: Duration::from_secs(1),
+                ..Default::default()
+            },
+            PeerDiscoveryConfig::default(),
+        )
+        .await;
+
+        let stream = TcpStream::connect(format!("localhost:{port}"))
+            .await
+            .unwrap();
+
+        // We never send anything, so the server should close the connection
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        let read = timeout(Duration::from_secs(10), reader.read_line(&mut line))
+            .await
+            .expect("server should have closed the connection");
+
+        assert_eq!(read.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_peer() {
+        let other_port = start_electrum().await;
+        let public_host = ServerHost::parse("floresta.example.com:t50001:s50002").unwrap();
+        let port = start_electrum_with(
+            ElectrumLimits::default(),
+            PeerDiscoveryConfig {
+                public_hosts: vec![public_host],
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Our public hosts are reported on server.features
+        let method = Value::String("server.features".to_string());
+        let mut request = generate_request(&mut vec![method]).to_string();
+        request.push('\n');
+
+        let features = send_request(request, port).await.unwrap();
+        assert_eq!(
+            features["result"]["hosts"],
+            json!({ "floresta.example.com": { "tcp_port": 50001, "ssl_port": 50002 } })
+        );
+
+        // Announce the other server, which is reachable from the same address as us
+        let method = Value::String("server.add_peer".to_string());
+        let other_features = json!({ "hosts": { "localhost": { "tcp_port": other_port } } });
+        let mut request = generate_request(&mut vec![other_features, method]).to_string();
+        request.push('\n');
+
+        assert!(send_request(request, port).await.unwrap()["result"]
+            .as_bool()
+            .unwrap());
+
+        // Wait for the server to connect back and check it
+        let method = Value::String("server.peers.subscribe".to_string());
+        let mut request = generate_request(&mut vec![method]).to_string();
+        request.push('\n');
+
+        for _ in 0..20 {
+            let peers = send_request(request.clone(), port).await.unwrap();
+            if let Some(peer) = peers["result"].as_array().and_then(|peers| peers.first()) {
+                assert_eq!(peer[1], "localhost");
+                assert_eq!(peer[2], json!(["v1.5", format!("t{other_port}")]));
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        panic!("The peer was never added");
+    }
+
+    /// A client talking to the server through an in-memory pipe, without any sockets
+    struct InProcessClient {
+        reader: BufReader<ReadHalf<DuplexStream>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl InProcessClient {
+        fn connect<D: AddressCacheDatabase>(
+            server: &ElectrumServer<ChainState<FlatChainStore>, D>,
+        ) -> Self {
+            let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+            add_client(
+                server_stream,
+                "127.0.0.1:0".parse().unwrap(),
+                &server.get_notifier(),
+                &server.get_limits(),
+            );
+
+            let (reader, writer) = tokio::io::split(client_stream);
+            InProcessClient {
+                reader: BufReader::new(reader),
+                writer,
+            }
+        }
+
+        async fn request(&mut self, mut params: Vec<Value>) -> Value {
+            let mut request = generate_request(&mut params).to_string();
+            request.push('\n');
+            self.writer.write_all(request.as_bytes()).await.unwrap();
+
+            let mut line = String::new();
+            timeout(Duration::from_secs(10), self.reader.read_line(&mut line))
+                .await
+                .unwrap()
+                .unwrap();
+
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_process_memory_database() {
+        let (chain, chain_provider) = get_test_node();
+        let wallet = fill_test_cache(AddressCache::new(MemoryDatabase::new()));
+
+        let electrum_server = ElectrumServerBuilder::new()
+            .with_chain(chain)
+            .with_address_cache(wallet)
+            .with_node_interface(chain_provider.get_handle())
+            .build()
+            .unwrap();
+
+        let mut client = InProcessClient::connect(&electrum_server);
+
+        let (stop_signal, _) = tokio::sync::oneshot::channel();
+        task::spawn(chain_provider.run(stop_signal));
+        task::spawn(electrum_server.main_loop());
+
+        let (_, hash) = get_test_address();
+        let method = Value::String("blockchain.scripthash.get_balance".to_string());
+        let response = client
+            .request(vec![Value::String(hash.to_string()), method])
+            .await;
+        assert_eq!(response["result"]["confirmed"], 999890);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_isolation() {
+        let (chain, chain_provider) = get_test_node();
+
+        // Only alice's wallet knows about the test transaction
+        let mut tenants = TenantConfig::default();
+        tenants.add_token("alice", "alice-token").unwrap();
+        tenants.add_token("bob", "bob-token").unwrap();
+
+        let wallets = HashMap::from([
+            (
+                "alice".to_string(),
+                fill_test_cache(AddressCache::new(MemoryDatabase::new())),
+            ),
+            (
+                "bob".to_string(),
+                Arc::new(AddressCache::new(MemoryDatabase::new())),
+            ),
+        ]);
+
+        let missing_wallet = ElectrumServerBuilder::new()
+            .with_chain(chain.clone())
+            .with_address_cache(Arc::new(AddressCache::new(MemoryDatabase::new())))
+            .with_node_interface(chain_provider.get_handle())
+            .with_tenants(tenants.clone(), HashMap::new())
+            .build();
+        assert!(missing_wallet.is_err());
+
+        let electrum_server = ElectrumServerBuilder::new()
+            .with_chain(chain)
+            .with_address_cache(fill_test_cache(AddressCache::new(MemoryDatabase::new())))
+            .with_node_interface(chain_provider.get_handle())
+            .with_tenants(tenants, wallets)
+            .build()
+            .unwrap();
+
+        let mut alice = InProcessClient::connect(&electrum_server);
+        let mut bob = InProcessClient::connect(&electrum_server);
+
+        let (stop_signal, _) = tokio::sync::oneshot::channel();
+        task::spawn(chain_provider.run(stop_signal));
+        task::spawn(electrum_server.main_loop());
+
+        let (_, hash) = get_test_address();
+        let txid = get_test_transaction().0.compute_txid();
+        let get_balance = || {
+            vec![
+                Value::String(hash.to_string()),
+                Value::String("blockchain.scripthash.get_balance".to_string()),
+            ]
+        };
+        let authenticate = |token: &str| {
+            vec![
+                Value::String(token.to_string()),
+                Value::String("server.authenticate".to_string()),
+            ]
+        };
+
+        // Clients can't touch any wallet before authenticating, not even the shared one
+        let response = alice.request(get_balance()).await;
+        assert!(response["error"].is_object());
+
+        let method = Value::String("server.banner".to_string());
+        let response = alice.request(vec![method]).await;
+        assert!(response["result"].is_string());
+
+        let response = alice.request(authenticate("wrong-token")).await;
+        assert!(response["error"].is_object());
+
+        let response = alice.request(authenticate("alice-token")).await;
+        assert_eq!(response["result"], true);
+        let response = alice.request(get_balance()).await;
+        assert_eq!(response["result"]["confirmed"], 999890);
+
+        // Bob only sees his own, empty, wallet, where this script is unknown
+        let response = bob.request(authenticate("bob-token")).await;
+        assert_eq!(response["result"], true);
+
+        let response = bob.request(get_balance()).await;
+        assert!(response["result"]["confirmed"].is_null());
+
+        let method = Value::String("blockchain.scripthash.get_history".to_string());
+        let response = bob
+            .request(vec![Value::String(hash.to_string()), method])
+            .await;
+        assert_eq!(response["result"], json!([]));
+
+        let method = Value::String("blockchain.scripthash.subscribe".to_string());
+        let response = bob
+            .request(vec![Value::String(hash.to_string()), method])
+            .await;
+        assert!(response["result"].is_null());
+
+        let method = Value::String("blockchain.transaction.get".to_string());
+        let response = bob
+            .request(vec![Value::String(txid.to_string()), method])
+            .await;
+        assert!(response["error"].is_object());
+
+        // And can't switch to alice's tenant
+        let response = bob.request(authenticate("alice-token")).await;
+        assert!(response["error"].is_object());
+        let response = bob.request(get_balance()).await;
+        assert!(response["result"]["confirmed"].is_null());
+    }
 }
@@ -22,4 +22,16 @@ pub enum Error {
 
     #[error("Node isn't working")]
     NodeInterface(#[from] oneshot::error::RecvError),
+
+    #[error("Too many subscriptions for this client")]
+    TooManySubscriptions,
+
+    #[error("Too many addresses waiting to be rescanned")]
+    TooManyPendingRescans,
+
+    #[error("This method requires authentication")]
+    AuthenticationRequired,
+
+    #[error("Authentication failed")]
+    AuthenticationFailed,
 }
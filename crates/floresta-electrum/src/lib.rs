@@ -12,9 +12,13 @@
 use serde::Deserialize;
 use serde::Serialize;
 
+pub mod builder;
 pub mod electrum_protocol;
 pub mod error;
+pub mod limits;
+pub mod peers;
 pub mod request;
+pub mod tenants;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TransactionHistoryEntry {
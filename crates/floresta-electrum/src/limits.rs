@@ -0,0 +1,400 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Resource limits for the Electrum server.
+//!
+//! A public Electrum server is an easy target for resource exhaustion: every connection holds
+//! a task and a channel, every subscription ends up in our [AddressCache] and every request
+//! costs us some database lookups. This module holds the knobs used to keep that under control,
+//! and the small amount of per-client accounting needed to enforce them.
+//!
+//! [AddressCache]: floresta_watch_only::AddressCache
+
+use core::net::IpAddr;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde_json::Value;
+
+/// Default for [ElectrumLimits::max_clients].
+pub const DEFAULT_MAX_CLIENTS: usize = 256;
+
+/// Default for [ElectrumLimits::max_subscriptions_per_client].
+pub const DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT: usize = 5_000;
+
+/// Default for [ElectrumLimits::max_request_bytes]: 1 MB.
+///
+/// This is enough for a batch of a few thousand requests, or for broadcasting the largest
+/// standard transaction (400k weight units, so at most 800 kB of hex).
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 1_000_000;
+
+/// Default for [ElectrumLimits::request_cost_per_second].
+pub const DEFAULT_REQUEST_COST_PER_SECOND: u32 = 100;
+
+/// Default for [ElectrumLimits::request_cost_burst]: 100 seconds worth of requests.
+///
+/// This must be above [DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT], so a wallet can subscribe to
+/// as many scripts as we allow right after connecting.
+pub const DEFAULT_REQUEST_COST_BURST: u32 = 10_000;
+
+/// Default for [ElectrumLimits::idle_timeout]: 10 minutes.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Default for [ElectrumLimits::ban_duration]: 1 hour.
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How long a client has to finish its TLS handshake, before we drop the connection.
+pub const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default for [ElectrumLimits::max_pending_rescans].
+pub const DEFAULT_MAX_PENDING_RESCANS: usize = 10_000;
+
+/// How many history entries (or UTXOs) a client gets for one unit of cost.
+const ENTRIES_PER_COST_UNIT: usize = 50;
+
+/// How many block headers a client gets for one unit of cost.
+const HEADERS_PER_COST_UNIT: usize = 100;
+
+#[derive(Debug, Clone)]
+/// Limits enforced by the Electrum server on its clients.
+pub struct ElectrumLimits {
+    /// How many clients may be connected at the same time. New connections above this
+    /// limit are closed right away.
+    pub max_clients: usize,
+
+    /// How many scripts a single client may be subscribed to.
+    pub max_subscriptions_per_client: usize,
+
+    /// The largest request (a single line of JSON, including batches) we accept. Clients
+    /// sending more than this are considered abusive.
+    pub max_request_bytes: usize,
+
+    /// How much request cost a client may spend per second, on average.
+    ///
+    /// Most requests cost one unit, but requests returning a lot of data, like `get_history`
+    /// for a script with a big history, cost more. See [request_cost].
+    pub request_cost_per_second: u32,
+
+    /// How much request cost a client may accumulate and spend at once. Requests from a
+    /// client that has exhausted this budget are answered with an error, until it refills.
+    ///
+    /// This should be at least [ElectrumLimits::max_subscriptions_per_client], otherwise a
+    /// client can't make use of all the subscriptions it is allowed to have.
+    pub request_cost_burst: u32,
+
+    /// For how long a client may stay silent before we disconnect it.
+    pub idle_timeout: Duration,
+
+    /// For how long an abusive client's IP address is banned.
+    pub ban_duration: Duration,
+
+    /// How many newly learned scripts may be waiting for a rescan, across all clients.
+    pub max_pending_rescans: usize,
+}
+
+impl Default for ElectrumLimits {
+    fn default() -> Self {
+        ElectrumLimits {
+            max_clients: DEFAULT_MAX_CLIENTS,
+            max_subscriptions_per_client: DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            request_cost_per_second: DEFAULT_REQUEST_COST_PER_SECOND,
+            request_cost_burst: DEFAULT_REQUEST_COST_BURST,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            ban_duration: DEFAULT_BAN_DURATION,
+            max_pending_rescans: DEFAULT_MAX_PENDING_RESCANS,
+        }
+    }
+}
+
+/// Computes how much a request costs, given the method and the response we've built for it.
+///
+/// This is [base_request_cost] plus [response_cost].
+pub fn request_cost(method: &str, response: &Value) -> u32 {
+    base_request_cost(method).saturating_add(response_cost(method, response))
+}
+
+/// Computes how much a request costs before we've done any work for it.
+///
+/// Every request has a base cost of one. Broadcasting a transaction is more expensive, since it
+/// hits the mempool and the network, and so is authenticating, to slow down anyone trying to
+/// guess a tenant's token.
+pub fn base_request_cost(method: &str) -> u32 {
+    match method {
+        "blockchain.transaction.broadcast" => 10,
+        // Guessing tokens should be slow
+        "server.authenticate" => 100,
+        _ => 1,
+    }
+}
+
+/// Computes the extra cost of a response, proportional to how much data we had to fetch to
+/// build it.
+pub fn response_cost(method: &str, response: &Value) -> u32 {
+    let result_len = response
+        .get("result")
+        .and_then(Value::as_array)
+        .map(Vec::len)
+        .unwrap_or(0);
+
+    let extra = match method {
+        "blockchain.scripthash.get_history"
+        | "blockchain.scripthash.listunspent"
+        | "blockchain.scriptpubkey.get_history" => result_len / ENTRIES_PER_COST_UNIT,
+        "blockchain.block.headers" => response
+            .get("result")
+            .and_then(|result| result.get("count"))
+            .and_then(Value::as_u64)
+            .and_then(|count| usize::try_from(count).ok())
+            .map(|count| count / HEADERS_PER_COST_UNIT)
+            .unwrap_or(0),
+        _ => 0,
+    };
+
+    u32::try_from(extra).unwrap_or(u32::MAX)
+}
+
+#[derive(Debug, Clone)]
+/// A token bucket keeping track of how much a client may still spend on requests.
+pub(crate) struct RequestBudget {
+    /// How much cost the client may still spend right now.
+    available: f64,
+
+    /// The last time we've refilled this bucket.
+    last_refill: Instant,
+}
+
+impl RequestBudget {
+    /// Creates a full budget.
+    pub(crate) fn new(limits: &ElectrumLimits) -> Self {
+        RequestBudget {
+            available: f64::from(limits.request_cost_burst),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since the last refill.
+    fn refill(&mut self, limits: &ElectrumLimits, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        let refill = elapsed * f64::from(limits.request_cost_per_second);
+
+        self.available = (self.available + refill).min(f64::from(limits.request_cost_burst));
+        self.last_refill = now;
+    }
+
+    /// Spends `cost` from this budget, if there's enough of it left. Returns false, without
+    /// spending anything, if the client can't afford it.
+    ///
+    /// This should be called before doing any work for a request.
+    pub(crate) fn try_spend(&mut self, limits: &ElectrumLimits, cost: u32) -> bool {
+        self.try_spend_at(limits, cost, Instant::now())
+    }
+
+    fn try_spend_at(&mut self, limits: &ElectrumLimits, cost: u32, now: Instant) -> bool {
+        self.refill(limits, now);
+        if self.available < f64::from(cost) {
+            return false;
+        }
+
+        self.available -= f64::from(cost);
+        true
+    }
+
+    /// Charges `cost` from this budget, even if the client can't afford it.
+    ///
+    /// This is for costs we only learn after answering a request. A client that goes into
+    /// debt has its next requests refused until the budget refills.
+    pub(crate) fn charge(&mut self, limits: &ElectrumLimits, cost: u32) {
+        self.charge_at(limits, cost, Instant::now())
+    }
+
+    fn charge_at(&mut self, limits: &ElectrumLimits, cost: u32, now: Instant) {
+        self.refill(limits, now);
+        self.available -= f64::from(cost);
+    }
+}
+
+#[derive(Debug, Default)]
+/// Who may connect to an [ElectrumServer], shared between the server and its accept loops.
+///
+/// The server keeps this up to date with its bans and how many clients it has, so the accept
+/// loops can drop connections we'd reject anyway, before spending a TLS handshake or a task on
+/// them.
+///
+/// [ElectrumServer]: crate::electrum_protocol::ElectrumServer
+pub struct ConnectionGate {
+    /// IP addresses that misbehaved, and until when they are banned.
+    banned: Mutex<HashMap<IpAddr, Instant>>,
+
+    /// How many clients the server has.
+    clients: AtomicUsize,
+
+    /// How many connections are still doing their TLS handshake.
+    handshakes: AtomicUsize,
+}
+
+impl ConnectionGate {
+    /// Whether this IP address is banned right now.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut banned = self.banned.lock().expect("poisoned lock");
+        banned.retain(|_, until| *until > now);
+
+        banned.contains_key(&ip)
+    }
+
+    /// Bans this IP address until `until`.
+    pub(crate) fn ban(&self, ip: IpAddr, until: Instant) {
+        self.banned.lock().expect("poisoned lock").insert(ip, until);
+    }
+
+    /// Updates how many clients the server has.
+    pub(crate) fn set_clients(&self, clients: usize) {
+        self.clients.store(clients, Ordering::Relaxed);
+    }
+
+    /// Whether a new connection would take us above `max_clients`, counting the connections
+    /// still doing their handshake.
+    pub fn is_full(&self, max_clients: usize) -> bool {
+        let clients = self.clients.load(Ordering::Relaxed);
+        let handshakes = self.handshakes.load(Ordering::Relaxed);
+
+        clients + handshakes >= max_clients
+    }
+
+    /// Counts a connection that started its TLS handshake.
+    pub(crate) fn start_handshake(&self) {
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stops counting a connection whose TLS handshake is over, whether it succeeded or not.
+    pub(crate) fn finish_handshake(&self) {
+        self.handshakes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use super::base_request_cost;
+    use super::request_cost;
+    use super::ConnectionGate;
+    use super::ElectrumLimits;
+    use super::RequestBudget;
+    use super::DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT;
+    use super::DEFAULT_REQUEST_COST_BURST;
+
+    #[test]
+    fn test_request_cost() {
+        let empty = json!({ "result": [] });
+        assert_eq!(request_cost("server.ping", &json!({ "result": null })), 1);
+        assert_eq!(request_cost("blockchain.scripthash.get_history", &empty), 1);
+
+        let history: Vec<_> = (0..500).map(|i| json!({ "height": i })).collect();
+        let response = json!({ "result": history });
+        assert_eq!(
+            request_cost("blockchain.scripthash.get_history", &response),
+            11
+        );
+
+        let headers = json!({ "result": { "count": 2016, "hex": "", "max": 2016 } });
+        assert_eq!(request_cost("blockchain.block.headers", &headers), 21);
+
+        // The base cost is what we charge upfront, and never depends on the response
+        assert_eq!(base_request_cost("blockchain.scripthash.get_history"), 1);
+        assert_eq!(
+            request_cost("server.authenticate", &json!({ "result": true })),
+            base_request_cost("server.authenticate")
+        );
+    }
+
+    #[test]
+    fn test_default_limits_are_consistent() {
+        // A client must be able to reach the subscription limit without running out of budget
+        assert!(DEFAULT_REQUEST_COST_BURST as usize >= DEFAULT_MAX_SUBSCRIPTIONS_PER_CLIENT);
+    }
+
+    #[test]
+    fn test_request_budget() {
+        let limits = ElectrumLimits {
+            request_cost_per_second: 10,
+            request_cost_burst: 20,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut budget = RequestBudget::new(&limits);
+        budget.last_refill = start;
+
+        // We can spend the whole burst at once, but not more
+        assert!(budget.try_spend_at(&limits, 20, start));
+        assert!(!budget.try_spend_at(&limits, 1, start));
+
+        // A refused request doesn't cost anything: after one second we get exactly 10 units back
+        let later = start + Duration::from_secs(1);
+        assert!(!budget.try_spend_at(&limits, 11, later));
+        assert!(budget.try_spend_at(&limits, 10, later));
+        assert!(!budget.try_spend_at(&limits, 1, later));
+
+        // The budget never grows beyond the burst size
+        let much_later = later + Duration::from_secs(3600);
+        assert!(budget.try_spend_at(&limits, 20, much_later));
+        assert!(!budget.try_spend_at(&limits, 1, much_later));
+    }
+
+    #[test]
+    fn test_request_budget_debt() {
+        let limits = ElectrumLimits {
+            request_cost_per_second: 10,
+            request_cost_burst: 20,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut budget = RequestBudget::new(&limits);
+        budget.last_refill = start;
+
+        // Charging after the fact may put the client in debt
+        assert!(budget.try_spend_at(&limits, 1, start));
+        budget.charge_at(&limits, 29, start);
+        assert!(!budget.try_spend_at(&limits, 1, start));
+
+        // It needs one second to pay the debt back, and only then can it spend again
+        let later = start + Duration::from_secs(1);
+        assert!(!budget.try_spend_at(&limits, 1, later));
+        let even_later = later + Duration::from_millis(100);
+        assert!(budget.try_spend_at(&limits, 1, even_later));
+    }
+
+    #[test]
+    fn test_connection_gate() {
+        let gate = ConnectionGate::default();
+        let ip = "203.0.113.7".parse().unwrap();
+
+        assert!(!gate.is_banned(ip));
+        gate.ban(ip, Instant::now() + Duration::from_secs(60));
+        assert!(gate.is_banned(ip));
+        assert!(!gate.is_banned("203.0.113.8".parse().unwrap()));
+
+        // Expired bans are forgotten
+        gate.ban(ip, Instant::now());
+        assert!(!gate.is_banned(ip));
+
+        // Connections still doing their handshake count towards the limit
+        gate.set_clients(1);
+        assert!(!gate.is_full(2));
+        gate.start_handshake();
+        assert!(gate.is_full(2));
+        gate.finish_handshake();
+        assert!(!gate.is_full(2));
+    }
+}
@@ -0,0 +1,534 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Electrum server peer discovery.
+//!
+//! Electrum servers gossip about each other using `server.add_peer` and
+//! `server.peers.subscribe`. A server announces itself to another one by calling `add_peer` with
+//! its own `server.features`, and the other side connects back to check that the announced server
+//! is actually there and serves the same chain. Servers that pass this check are returned to
+//! wallets asking for `server.peers.subscribe`, so wallets can find more servers to use.
+//!
+//! This module keeps the list of servers we've verified, persisting it in our data dir, and
+//! implements the connect-back check. We can only check servers reachable over plain TCP, since
+//! we don't have a TLS client or a way to reach onion services from here. Our own onion and TLS
+//! hosts are still advertised normally.
+
+use core::net::IpAddr;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use bitcoin::BlockHash;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::debug;
+use tracing::warn;
+
+/// The name of the file, inside our data dir, where we keep the verified peers
+pub const PEERS_FILE_NAME: &str = "electrum_peers.json";
+
+/// How long we wait for a peer to connect and answer all our requests
+const PEER_CHECK_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// After this many seconds without a successful check, a peer is forgotten
+const PEER_EXPIRY: u64 = 7 * 24 * 3600;
+
+/// We won't keep more than this many peers, to avoid being used as a spam relay
+pub const MAX_KNOWN_PEERS: usize = 1_000;
+
+/// The oldest protocol version we speak when checking peers
+const PROTOCOL_MIN: &str = "1.4";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// A hostname where an Electrum server can be reached, and which ports it uses.
+///
+/// This is the same information an Electrum server reports in the `hosts` field of
+/// `server.features`.
+pub struct ServerHost {
+    /// The public hostname, IP address or onion address of the server
+    pub hostname: String,
+
+    /// The port for plain TCP connections, if any
+    pub tcp_port: Option<u16>,
+
+    /// The port for TLS connections, if any
+    pub ssl_port: Option<u16>,
+}
+
+impl ServerHost {
+    /// Whether this host is a Tor onion service
+    pub fn is_onion(&self) -> bool {
+        self.hostname.ends_with(".onion")
+    }
+
+    /// Parses a host in the format `<hostname>[:t<port>][:s<port>]`, e.g. `example.com:t50001:s50002`.
+    ///
+    /// This mirrors the way Electrum wallets write servers, and lets users configure which ports
+    /// are advertised for each hostname.
+    pub fn parse(host: &str) -> Result<Self, PeerError> {
+        let mut parts = host.split(':');
+        let hostname = parts
+            .next()
+            .filter(|hostname| !hostname.is_empty())
+            .ok_or_else(|| PeerError::InvalidHost(host.to_string()))?;
+
+        let mut server_host = ServerHost {
+            hostname: hostname.to_string(),
+            tcp_port: None,
+            ssl_port: None,
+        };
+
+        for part in parts {
+            let parse_port = |port: &str| {
+                port.parse::<u16>()
+                    .map_err(|_| PeerError::InvalidHost(host.to_string()))
+            };
+
+            match part.split_at_checked(1) {
+                Some(("t", port)) => server_host.tcp_port = Some(parse_port(port)?),
+                Some(("s", port)) => server_host.ssl_port = Some(parse_port(port)?),
+                _ => return Err(PeerError::InvalidHost(host.to_string())),
+            }
+        }
+
+        if server_host.tcp_port.is_none() && server_host.ssl_port.is_none() {
+            return Err(PeerError::InvalidHost(host.to_string()));
+        }
+
+        Ok(server_host)
+    }
+
+    /// The value describing this host inside `server.features`
+    fn features_entry(&self) -> Value {
+        let mut entry = Map::new();
+        if let Some(port) = self.tcp_port {
+            entry.insert("tcp_port".into(), json!(port));
+        }
+        if let Some(port) = self.ssl_port {
+            entry.insert("ssl_port".into(), json!(port));
+        }
+
+        Value::Object(entry)
+    }
+}
+
+/// Builds the `hosts` field of `server.features` for the given hosts
+pub fn hosts_features(hosts: &[ServerHost]) -> Value {
+    let hosts = hosts
+        .iter()
+        .map(|host| (host.hostname.clone(), host.features_entry()))
+        .collect::<Map<_, _>>();
+
+    Value::Object(hosts)
+}
+
+/// Reads the hosts announced in a `server.features` object
+pub fn hosts_from_features(features: &Value) -> Vec<ServerHost> {
+    let Some(hosts) = features.get("hosts").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+
+    let port = |entry: &Value, name: &str| {
+        entry
+            .get(name)
+            .and_then(Value::as_u64)
+            .and_then(|port| u16::try_from(port).ok())
+    };
+
+    hosts
+        .iter()
+        .map(|(hostname, entry)| ServerHost {
+            hostname: hostname.clone(),
+            tcp_port: port(entry, "tcp_port"),
+            ssl_port: port(entry, "ssl_port"),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// An Electrum server that we've checked and that serves the same chain as us
+pub struct ElectrumPeer {
+    /// The address we reached this peer at
+    pub address: IpAddr,
+
+    /// The hostname and ports this peer announced
+    pub host: ServerHost,
+
+    /// The newest protocol version supported by this peer
+    pub protocol_max: String,
+
+    /// The pruning limit reported by this peer, if it's pruned
+    pub pruning: Option<u64>,
+
+    /// When we last checked this peer, in seconds since the UNIX epoch
+    pub last_verified: u64,
+}
+
+impl ElectrumPeer {
+    /// This peer formatted as an entry of `server.peers.subscribe`, that is,
+    /// `[ip, hostname, ["v1.4", "t50001", "s50002", "p10000"]]`.
+    fn subscribe_entry(&self) -> Value {
+        let mut features = vec![format!("v{}", self.protocol_max)];
+        if let Some(port) = self.host.tcp_port {
+            features.push(format!("t{port}"));
+        }
+        if let Some(port) = self.host.ssl_port {
+            features.push(format!("s{port}"));
+        }
+        if let Some(pruning) = self.pruning {
+            features.push(format!("p{pruning}"));
+        }
+
+        json!([self.address.to_string(), self.host.hostname, features])
+    }
+}
+
+#[derive(Error, Debug)]
+/// Things that can go wrong while handling Electrum peers
+pub enum PeerError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Peer didn't answer in time")]
+    Timeout,
+
+    #[error("Invalid response from peer: {0}")]
+    InvalidResponse(String),
+
+    #[error("Peer is on another chain, with genesis {0}")]
+    WrongGenesis(String),
+
+    #[error("Peer isn't reachable at {0}")]
+    AddressMismatch(IpAddr),
+
+    #[error("Invalid host: {0}")]
+    InvalidHost(String),
+}
+
+#[derive(Debug, Clone, Default)]
+/// How we take part in the Electrum server network
+pub struct PeerDiscoveryConfig {
+    /// The hosts where our server is publicly reachable. Those are reported on
+    /// `server.features`, and announced to other servers. If empty, we don't announce ourselves.
+    pub public_hosts: Vec<ServerHost>,
+
+    /// Servers we check, and announce ourselves to, when we start. This is how we learn
+    /// about the rest of the network.
+    pub seed_peers: Vec<ServerHost>,
+
+    /// Our data dir. If set, the verified peers are saved to [PEERS_FILE_NAME] inside it.
+    pub datadir: Option<String>,
+}
+
+#[derive(Debug, Default)]
+/// The Electrum servers we know about
+pub struct PeerStore {
+    /// Where we persist peers, if anywhere
+    path: Option<String>,
+
+    /// Verified peers, indexed by hostname
+    peers: HashMap<String, ElectrumPeer>,
+}
+
+impl PeerStore {
+    /// Loads the persisted peers from `datadir`, if any, dropping the ones that expired
+    pub fn load(datadir: Option<&str>) -> Self {
+        let path = datadir.map(|datadir| format!("{datadir}/{PEERS_FILE_NAME}"));
+        let mut store = PeerStore {
+            path,
+            peers: HashMap::new(),
+        };
+
+        let Some(path) = &store.path else {
+            return store;
+        };
+
+        let peers = match std::fs::read_to_string(path) {
+            Ok(peers) => peers,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return store,
+            Err(e) => {
+                warn!("Could not read Electrum peers from {path}: {e}");
+                return store;
+            }
+        };
+
+        match serde_json::from_str::<Vec<ElectrumPeer>>(&peers) {
+            Ok(peers) => {
+                let now = now();
+                store.peers = peers
+                    .into_iter()
+                    .filter(|peer| now.saturating_sub(peer.last_verified) < PEER_EXPIRY)
+                    .map(|peer| (peer.host.hostname.clone(), peer))
+                    .collect();
+            }
+            Err(e) => warn!("Could not parse Electrum peers from {path}: {e}"),
+        }
+
+        store
+    }
+
+    /// Persists the peers, if we have somewhere to persist them
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let peers: Vec<_> = self.peers.values().collect();
+        std::fs::write(path, serde_json::to_vec(&peers)?)
+    }
+
+    /// Adds or refreshes a verified peer. Returns false if we already know too many peers.
+    pub fn add(&mut self, peer: ElectrumPeer) -> bool {
+        if self.peers.len() >= MAX_KNOWN_PEERS && !self.peers.contains_key(&peer.host.hostname) {
+            return false;
+        }
+
+        self.peers.insert(peer.host.hostname.clone(), peer);
+        true
+    }
+
+    /// Forgets a peer
+    pub fn remove(&mut self, hostname: &str) {
+        self.peers.remove(hostname);
+    }
+
+    /// Whether we know this hostname
+    pub fn contains(&self, hostname: &str) -> bool {
+        self.peers.contains_key(hostname)
+    }
+
+    /// How many peers we know
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Whether we know no peers
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// The hosts of every known peer
+    pub fn hosts(&self) -> Vec<ServerHost> {
+        self.peers.values().map(|peer| peer.host.clone()).collect()
+    }
+
+    /// The response for `server.peers.subscribe`
+    pub fn subscribe_response(&self) -> Value {
+        let mut peers: Vec<_> = self.peers.values().collect();
+        peers.sort_by(|a, b| a.host.hostname.cmp(&b.host.hostname));
+
+        Value::Array(
+            peers
+                .into_iter()
+                .map(ElectrumPeer::subscribe_entry)
+                .collect(),
+        )
+    }
+}
+
+/// Seconds since the UNIX epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Connects to an Electrum server and checks it serves the chain starting at `genesis`.
+///
+/// If `expected_address` is set, the server must be reachable at this address. We use this when
+/// a server announces itself through `server.add_peer`, so nobody can make us spam third parties.
+/// If `our_features` is set, we'll also announce ourselves to this server.
+pub async fn check_peer(
+    host: ServerHost,
+    genesis: BlockHash,
+    expected_address: Option<IpAddr>,
+    our_features: Option<Value>,
+) -> Result<ElectrumPeer, PeerError> {
+    timeout(
+        PEER_CHECK_TIMEOUT,
+        check_peer_inner(host, genesis, expected_address, our_features),
+    )
+    .await
+    .map_err(|_| PeerError::Timeout)?
+}
+
+async fn check_peer_inner(
+    host: ServerHost,
+    genesis: BlockHash,
+    expected_address: Option<IpAddr>,
+    our_features: Option<Value>,
+) -> Result<ElectrumPeer, PeerError> {
+    let Some(tcp_port) = host.tcp_port.filter(|_| !host.is_onion()) else {
+        return Err(PeerError::InvalidHost(host.hostname));
+    };
+
+    let stream = TcpStream::connect((host.hostname.as_str(), tcp_port)).await?;
+    let address = stream.peer_addr()?.ip();
+
+    if let Some(expected) = expected_address {
+        if expected != address {
+            return Err(PeerError::AddressMismatch(expected));
+        }
+    }
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let mut requests = vec![
+        json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "server.version",
+            "params": [format!("Floresta {}", env!("CARGO_PKG_VERSION")), PROTOCOL_MIN]
+        }),
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "server.features", "params": [] }),
+    ];
+
+    if let Some(features) = our_features {
+        requests.push(json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "server.add_peer",
+            "params": [features]
+        }));
+    }
+
+    let mut features = None;
+    for request in &requests {
+        let mut request = request.to_string();
+        request.push('\n');
+        writer.write_all(request.as_bytes()).await?;
+
+        let line = lines
+            .next_line()
+            .await?
+            .ok_or_else(|| PeerError::InvalidResponse("connection closed".into()))?;
+
+        let response: Value =
+            serde_json::from_str(&line).map_err(|e| PeerError::InvalidResponse(e.to_string()))?;
+
+        if response["id"] == 1 {
+            features = Some(response["result"].clone());
+        }
+    }
+
+    let features = features.ok_or_else(|| PeerError::InvalidResponse("no features".into()))?;
+    let their_genesis = features["genesis_hash"].as_str().unwrap_or_default();
+    if their_genesis != genesis.to_string() {
+        return Err(PeerError::WrongGenesis(their_genesis.to_string()));
+    }
+
+    // Prefer what the server says about itself, it may use a different TLS port than the one
+    // we were told about.
+    let host = hosts_from_features(&features)
+        .into_iter()
+        .find(|announced| announced.hostname == host.hostname)
+        .unwrap_or(host);
+
+    debug!("Verified Electrum peer {} at {address}", host.hostname);
+
+    Ok(ElectrumPeer {
+        address,
+        host,
+        protocol_max: features["protocol_max"]
+            .as_str()
+            .unwrap_or(PROTOCOL_MIN)
+            .to_string(),
+        pruning: features["pruning"].as_u64(),
+        last_verified: now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::IpAddr;
+    use core::net::Ipv4Addr;
+
+    use serde_json::json;
+
+    use super::hosts_features;
+    use super::hosts_from_features;
+    use super::now;
+    use super::ElectrumPeer;
+    use super::PeerStore;
+    use super::ServerHost;
+
+    #[test]
+    fn test_parse_host() {
+        let host = ServerHost::parse("example.com:t50001:s50002").unwrap();
+        assert_eq!(host.hostname, "example.com");
+        assert_eq!(host.tcp_port, Some(50001));
+        assert_eq!(host.ssl_port, Some(50002));
+        assert!(!host.is_onion());
+
+        let onion = ServerHost::parse("abcdef.onion:t50001").unwrap();
+        assert!(onion.is_onion());
+        assert_eq!(onion.ssl_port, None);
+
+        assert!(ServerHost::parse("example.com").is_err());
+        assert!(ServerHost::parse("example.com:50001").is_err());
+        assert!(ServerHost::parse(":t50001").is_err());
+        assert!(ServerHost::parse("example.com:t99999").is_err());
+    }
+
+    #[test]
+    fn test_hosts_features_roundtrip() {
+        let hosts = vec![
+            ServerHost::parse("example.com:t50001:s50002").unwrap(),
+            ServerHost::parse("abcdef.onion:t50001").unwrap(),
+        ];
+
+        let features = json!({ "hosts": hosts_features(&hosts) });
+        assert_eq!(features["hosts"]["example.com"]["ssl_port"], 50002);
+        assert!(features["hosts"]["abcdef.onion"]["ssl_port"].is_null());
+
+        let mut parsed = hosts_from_features(&features);
+        parsed.sort_by(|a, b| b.hostname.cmp(&a.hostname));
+        assert_eq!(parsed, hosts);
+    }
+
+    #[test]
+    fn test_peer_store_persistence() {
+        let datadir = format!("./tmp-db/{}.peers", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let peer = ElectrumPeer {
+            address: IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+            host: ServerHost::parse("example.com:t50001:s50002").unwrap(),
+            protocol_max: "1.4".into(),
+            pruning: None,
+            last_verified: now(),
+        };
+
+        let expired = ElectrumPeer {
+            host: ServerHost::parse("old.example.com:t50001").unwrap(),
+            last_verified: 0,
+            ..peer.clone()
+        };
+
+        let mut store = PeerStore::load(Some(&datadir));
+        assert!(store.is_empty());
+        assert!(store.add(peer.clone()));
+        assert!(store.add(expired));
+        store.save().unwrap();
+
+        let store = PeerStore::load(Some(&datadir));
+        assert_eq!(store.len(), 1);
+        assert!(store.contains("example.com"));
+        assert_eq!(
+            store.subscribe_response(),
+            json!([["1.2.3.4", "example.com", ["v1.4", "t50001", "s50002"]]])
+        );
+    }
+}
@@ -0,0 +1,314 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Multi-tenant mode for the Electrum server.
+//!
+//! By default, every script a client subscribes to ends up in a single, shared
+//! [AddressCache](floresta_watch_only::AddressCache), and any client can query what is in there.
+//! That's fine when the server is only used by its owner, but it leaks one user's addresses to
+//! everyone else when a node serves several people.
+//!
+//! In multi-tenant mode, each tenant gets a wallet of its own, and a client can only touch its
+//! tenant's wallet. Clients that didn't authenticate can still use methods that don't depend on a
+//! wallet, like fetching headers or broadcasting transactions, but can't query any script. Since
+//! wallets aren't shared, a tenant can't learn whether some script is being watched by someone
+//! else, not even by timing its queries.
+//!
+//! A client is bound to a tenant either by calling `server.authenticate` with a token, or by
+//! presenting a pinned TLS client certificate, see [PinnedClientCertVerifier].
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use thiserror::Error;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::HandshakeSignatureValid;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::crypto::verify_tls12_signature;
+use tokio_rustls::rustls::crypto::verify_tls13_signature;
+use tokio_rustls::rustls::crypto::WebPkiSupportedAlgorithms;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::UnixTime;
+use tokio_rustls::rustls::server::danger::ClientCertVerified;
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::CertificateError;
+use tokio_rustls::rustls::DigitallySignedStruct;
+use tokio_rustls::rustls::DistinguishedName;
+use tokio_rustls::rustls::SignatureScheme;
+
+/// The directory, inside our data dir, where each tenant's wallet is kept
+pub const TENANTS_DIR_NAME: &str = "electrum-tenants";
+
+#[derive(Error, Debug)]
+/// Things that can go wrong while configuring tenants
+pub enum TenantError {
+    #[error("Invalid tenant credential {0}, expected <name>:<value>")]
+    InvalidCredential(String),
+
+    #[error("Invalid tenant name {0}, only letters, digits, '-' and '_' are allowed")]
+    InvalidName(String),
+
+    #[error("Invalid certificate fingerprint {0}, expected a hex-encoded sha256")]
+    InvalidFingerprint(String),
+}
+
+/// The SHA256 of a DER-encoded certificate, as printed by
+/// `openssl x509 -noout -fingerprint -sha256`
+pub fn certificate_fingerprint(certificate: &[u8]) -> sha256::Hash {
+    sha256::Hash::hash(certificate)
+}
+
+#[derive(Debug, Clone, Default)]
+/// Who the tenants of a multi-tenant Electrum server are, and how their clients authenticate.
+///
+/// Tokens are never stored in the clear, we only keep their hashes.
+pub struct TenantConfig {
+    /// The hash of each token, and the tenant it belongs to
+    tokens: HashMap<sha256::Hash, String>,
+
+    /// The fingerprint of each pinned client certificate, and the tenant it belongs to
+    certificates: HashMap<sha256::Hash, String>,
+}
+
+impl TenantConfig {
+    /// Checks whether `name` can be used as a tenant name. Since each tenant has its own
+    /// wallet on disk, names must be safe to use as directory names.
+    fn validate_name(name: &str) -> Result<(), TenantError> {
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        if !is_valid {
+            return Err(TenantError::InvalidName(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Splits a credential in the format `<name>:<value>`
+    fn split_credential(credential: &str) -> Result<(&str, &str), TenantError> {
+        let (name, value) = credential
+            .split_once(':')
+            .filter(|(_, value)| !value.is_empty())
+            .ok_or_else(|| TenantError::InvalidCredential(credential.to_string()))?;
+
+        Self::validate_name(name)?;
+        Ok((name, value))
+    }
+
+    /// Lets clients authenticate as `tenant` by calling `server.authenticate` with `token`
+    pub fn add_token(&mut self, tenant: &str, token: &str) -> Result<(), TenantError> {
+        Self::validate_name(tenant)?;
+
+        let hash = sha256::Hash::hash(token.as_bytes());
+        self.tokens.insert(hash, tenant.to_string());
+        Ok(())
+    }
+
+    /// Lets clients authenticate as `tenant` by presenting the TLS client certificate with
+    /// this fingerprint
+    pub fn add_certificate(
+        &mut self,
+        tenant: &str,
+        fingerprint: sha256::Hash,
+    ) -> Result<(), TenantError> {
+        Self::validate_name(tenant)?;
+
+        self.certificates.insert(fingerprint, tenant.to_string());
+        Ok(())
+    }
+
+    /// Adds a token in the format `<name>:<token>`, e.g. `alice:some-long-random-string`
+    pub fn parse_token(&mut self, credential: &str) -> Result<(), TenantError> {
+        let (name, token) = Self::split_credential(credential)?;
+        self.add_token(name, token)
+    }
+
+    /// Adds a certificate in the format `<name>:<fingerprint>`, where the fingerprint is the
+    /// hex-encoded sha256 of the certificate. Colons between bytes, as printed by openssl, are
+    /// accepted.
+    pub fn parse_certificate(&mut self, credential: &str) -> Result<(), TenantError> {
+        let (name, fingerprint) = Self::split_credential(credential)?;
+        let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+        let fingerprint = hex
+            .parse::<sha256::Hash>()
+            .map_err(|_| TenantError::InvalidFingerprint(fingerprint.to_string()))?;
+
+        self.add_certificate(name, fingerprint)
+    }
+
+    /// The name of every configured tenant, sorted
+    pub fn tenants(&self) -> BTreeSet<String> {
+        self.tokens
+            .values()
+            .chain(self.certificates.values())
+            .cloned()
+            .collect()
+    }
+
+    /// Whether there are no tenants, that is, the server isn't in multi-tenant mode
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty() && self.certificates.is_empty()
+    }
+
+    /// Whether any tenant authenticates with a client certificate. If so, the TLS acceptor
+    /// should ask clients for one, see [PinnedClientCertVerifier].
+    pub fn has_certificates(&self) -> bool {
+        !self.certificates.is_empty()
+    }
+
+    /// The tenant a token belongs to, if any
+    pub fn authenticate_token(&self, token: &str) -> Option<&str> {
+        let hash = sha256::Hash::hash(token.as_bytes());
+        self.tokens.get(&hash).map(String::as_str)
+    }
+
+    /// The tenant a client certificate fingerprint belongs to, if any
+    pub fn authenticate_certificate(&self, fingerprint: &sha256::Hash) -> Option<&str> {
+        self.certificates.get(fingerprint).map(String::as_str)
+    }
+}
+
+#[derive(Debug)]
+/// A TLS client certificate verifier that accepts the certificates pinned in a [TenantConfig].
+///
+/// Certificates are identified by their fingerprint only, so self-signed ones work fine and no
+/// CA is needed. Presenting a certificate is optional: clients without one can still connect, and
+/// authenticate with a token if they want to. Clients presenting a certificate that isn't pinned
+/// are rejected during the handshake.
+pub struct PinnedClientCertVerifier {
+    /// The fingerprints we accept
+    fingerprints: Vec<sha256::Hash>,
+
+    /// The signature algorithms we support, used to check the client owns its certificate
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedClientCertVerifier {
+    /// Creates a verifier accepting every certificate pinned in `config`
+    pub fn new(config: &TenantConfig) -> Arc<Self> {
+        Arc::new(PinnedClientCertVerifier {
+            fingerprints: config.certificates.keys().copied().collect(),
+            algorithms: ring::default_provider().signature_verification_algorithms,
+        })
+    }
+}
+
+impl ClientCertVerifier for PinnedClientCertVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let fingerprint = certificate_fingerprint(end_entity);
+        if !self.fingerprints.contains(&fingerprint) {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::generate_simple_self_signed;
+    use rcgen::CertifiedKey;
+    use tokio_rustls::rustls::pki_types::UnixTime;
+    use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+
+    use super::certificate_fingerprint;
+    use super::PinnedClientCertVerifier;
+    use super::TenantConfig;
+
+    #[test]
+    fn test_authenticate_token() {
+        let mut config = TenantConfig::default();
+        assert!(config.is_empty());
+
+        config.parse_token("alice:secret-a").unwrap();
+        config.parse_token("bob:secret:b").unwrap();
+
+        assert_eq!(config.authenticate_token("secret-a"), Some("alice"));
+        assert_eq!(config.authenticate_token("secret:b"), Some("bob"));
+        assert_eq!(config.authenticate_token("secret-c"), None);
+        assert_eq!(
+            config.tenants().into_iter().collect::<Vec<_>>(),
+            vec!["alice", "bob"]
+        );
+
+        assert!(config.parse_token("alice").is_err());
+        assert!(config.parse_token("alice:").is_err());
+        assert!(config.parse_token(":secret").is_err());
+        assert!(config.parse_token("../alice:secret").is_err());
+    }
+
+    #[test]
+    fn test_pinned_certificate() {
+        let CertifiedKey { cert: pinned, .. } =
+            generate_simple_self_signed(vec!["alice".into()]).unwrap();
+        let CertifiedKey { cert: other, .. } =
+            generate_simple_self_signed(vec!["mallory".into()]).unwrap();
+
+        let fingerprint = certificate_fingerprint(pinned.der());
+        let with_colons = fingerprint
+            .to_string()
+            .as_bytes()
+            .chunks(2)
+            .map(|byte| String::from_utf8(byte.to_vec()).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let mut config = TenantConfig::default();
+        config
+            .parse_certificate(&format!("alice:{with_colons}"))
+            .unwrap();
+        assert!(config.has_certificates());
+        assert_eq!(config.authenticate_certificate(&fingerprint), Some("alice"));
+        assert!(config.parse_certificate("alice:not-hex").is_err());
+
+        let verifier = PinnedClientCertVerifier::new(&config);
+        assert!(!verifier.client_auth_mandatory());
+        assert!(verifier
+            .verify_client_cert(pinned.der(), &[], UnixTime::now())
+            .is_ok());
+        assert!(verifier
+            .verify_client_cert(other.der(), &[], UnixTime::now())
+            .is_err());
+    }
+}
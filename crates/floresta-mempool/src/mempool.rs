@@ -1,8 +1,9 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-//! A simple mempool that keeps our transactions in memory. It try to rebroadcast
+//! A simple mempool that keeps unconfirmed transactions in memory. It try to rebroadcast
 //! our transactions every 1 hour.
-//! Once our transaction is included in a block, we remove it from the mempool.
+//! Once a transaction is included in a block, we remove it from the mempool, along with any
+//! transaction that conflicts with that block.
 
 use core::error::Error;
 use core::fmt;
@@ -16,13 +17,19 @@ use std::time::Instant;
 use bitcoin::block::Header;
 use bitcoin::block::Version;
 use bitcoin::hashes::Hash;
+use bitcoin::transaction;
+use bitcoin::Amount;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::CompactTarget;
+use bitcoin::FeeRate;
 use bitcoin::OutPoint;
 use bitcoin::Transaction;
 use bitcoin::TxMerkleNode;
+use bitcoin::TxOut;
 use bitcoin::Txid;
+use bitcoin::Weight;
+use bitcoin::Wtxid;
 use floresta_chain::pruned_utreexo::consensus::Consensus;
 use floresta_chain::BlockchainError;
 use tracing::debug;
@@ -34,6 +41,27 @@ use tracing::debug;
 /// that only we know. This way, peers can't cause collisions and make our mempool slow.
 type ShortTxid = u64;
 
+/// The lowest fee rate a transaction from the network must pay to get into our mempool.
+///
+/// We also tell our peers not to announce transactions paying less than this.
+pub const MIN_RELAY_FEE_RATE: FeeRate = FeeRate::BROADCAST_MIN;
+
+/// The maximum number of transactions in a package, same as Bitcoin Core
+pub const MAX_PACKAGE_COUNT: usize = 25;
+
+/// The maximum total weight of a package, same as Bitcoin Core
+pub const MAX_PACKAGE_WEIGHT: u64 = 404_000;
+
+/// Transactions with this version opt into the TRUC (Topologically Restricted Until
+/// Confirmation) policy from BIP 431.
+pub const TRUC_VERSION: transaction::Version = transaction::Version(3);
+
+/// The maximum virtual size of a TRUC transaction
+const TRUC_MAX_VSIZE: usize = 10_000;
+
+/// The maximum virtual size of a TRUC transaction spending another unconfirmed transaction
+const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+
 #[derive(Debug)]
 /// A transaction in the mempool.
 ///
@@ -45,6 +73,10 @@ struct MempoolTransaction {
     time: Instant,
     depends: Vec<ShortTxid>,
     children: Vec<ShortTxid>,
+
+    /// How much this transaction pays in fees, if we know it. We only know the fee of
+    /// transactions we've validated against the UTXO set.
+    fee: Option<Amount>,
 }
 
 /// Holds the transactions that we broadcasted and are still in the mempool.
@@ -66,6 +98,12 @@ pub struct Mempool {
     /// A queue of transaction we know about, but we haven't downloaded yet
     queue: Vec<Txid>,
 
+    /// Which mempool transaction spends each outpoint, used to find conflicts
+    spent: HashMap<OutPoint, ShortTxid>,
+
+    /// The short id of each transaction, indexed by its short wtxid
+    wtxids: HashMap<ShortTxid, ShortTxid>,
+
     /// A hasher that we use to compute the short transaction ids.
     hasher: ahash::RandomState,
 }
@@ -86,6 +124,19 @@ pub enum MempoolError {
     // instead of reusing BlockchainError.
     /// The [`Transaction`] failed consensus validation.
     ConsensusValidation(BlockchainError),
+
+    /// The [`Transaction`] pays less than [`MIN_RELAY_FEE_RATE`].
+    FeeTooLow(FeeRate),
+
+    /// The package isn't a child with its unconfirmed parents, sorted topologically, or it's
+    /// too big.
+    InvalidPackage(&'static str),
+
+    /// The [`Transaction`] breaks the TRUC (v3) topology rules from BIP 431.
+    TrucViolation(&'static str),
+
+    /// The [`Transaction`] has dust outputs that aren't spent in the same package.
+    EphemeralDust(&'static str),
 }
 
 impl Display for MempoolError {
@@ -109,6 +160,22 @@ impl Display for MempoolError {
             Self::ConsensusValidation(e) => {
                 write!(f, "The transaction failed consensus validation: {e}")
             }
+            Self::FeeTooLow(fee_rate) => {
+                write!(
+                    f,
+                    "The transaction pays {} sat/kwu, less than the minimum relay fee rate",
+                    fee_rate.to_sat_per_kwu()
+                )
+            }
+            Self::InvalidPackage(reason) => {
+                write!(f, "The package is invalid: {reason}")
+            }
+            Self::TrucViolation(reason) => {
+                write!(f, "The transaction breaks the TRUC rules: {reason}")
+            }
+            Self::EphemeralDust(reason) => {
+                write!(f, "The transaction has invalid dust outputs: {reason}")
+            }
         }
     }
 }
@@ -128,6 +195,8 @@ impl Mempool {
         Mempool {
             transactions: HashMap::new(),
             queue: Vec::new(),
+            spent: HashMap::new(),
+            wtxids: HashMap::new(),
             mempool_size: 0,
             max_mempool_size,
             hasher,
@@ -216,8 +285,11 @@ impl Mempool {
     }
 
     /// Consume a block and remove all transactions that were included in it.
+    ///
+    /// Transactions spending an output that this block also spends can never be mined anymore,
+    /// so we remove them too, along with their descendants.
     pub fn consume_block(&mut self, block: &Block) -> Vec<Txid> {
-        block
+        let confirmed = block
             .txdata
             .iter()
             .map(|tx| {
@@ -225,9 +297,7 @@ impl Mempool {
 
                 // Remove this transaction from the mempool, and also remove it from the depends list of all
                 // its children, since they don't depend on it anymore.
-                if let Some(removed) = self.transactions.remove(&short_txid) {
-                    self.mempool_size -= removed.transaction.total_size();
-
+                if let Some(removed) = self.remove_entry(short_txid) {
                     for child in &removed.children {
                         if let Some(child_tx) = self.transactions.get_mut(child) {
                             child_tx.depends.retain(|depend| *depend != short_txid);
@@ -236,28 +306,69 @@ impl Mempool {
                 }
                 tx.compute_txid()
             })
-            .collect()
+            .collect();
+
+        let conflicts: Vec<_> = block
+            .txdata
+            .iter()
+            .flat_map(|tx| tx.input.iter())
+            .filter_map(|input| self.spent.get(&input.previous_output).copied())
+            .collect();
+
+        for conflict in conflicts {
+            self.remove_with_descendants(conflict);
+        }
+
+        confirmed
     }
 
-    /// Checks if an outpoint is already spent in the mempool.
+    /// Removes a transaction from all our indexes, returning it if it was in the mempool.
     ///
-    /// This can be used to find conflicts before adding a transaction to the mempool.
-    fn is_already_spent(&self, outpoint: &OutPoint) -> bool {
-        let short_txid = self.hasher.hash_one(outpoint.txid);
-        let Some(tx) = self.transactions.get(&short_txid) else {
-            return false;
+    /// This doesn't touch the transactions related to this one, it's up to the caller to keep
+    /// `depends` and `children` consistent.
+    fn remove_entry(&mut self, short_txid: ShortTxid) -> Option<MempoolTransaction> {
+        let removed = self.transactions.remove(&short_txid)?;
+        self.mempool_size -= removed.transaction.total_size();
+
+        for input in removed.transaction.input.iter() {
+            if self.spent.get(&input.previous_output) == Some(&short_txid) {
+                self.spent.remove(&input.previous_output);
+            }
+        }
+
+        let short_wtxid = self.hasher.hash_one(removed.transaction.compute_wtxid());
+        self.wtxids.remove(&short_wtxid);
+
+        Some(removed)
+    }
+
+    /// Removes a transaction and everything that spends from it.
+    fn remove_with_descendants(&mut self, short_txid: ShortTxid) {
+        let Some(removed) = self.remove_entry(short_txid) else {
+            return;
         };
 
-        tx.children.iter().any(|child| {
-            let Some(child_tx) = self.transactions.get(child) else {
-                return false;
-            };
+        debug!(
+            "Removing {} from mempool",
+            removed.transaction.compute_txid()
+        );
 
-            child_tx.transaction.input.iter().any(|input| {
-                input.previous_output.txid == outpoint.txid
-                    && input.previous_output.vout == outpoint.vout
-            })
-        })
+        for depend in removed.depends.iter() {
+            if let Some(parent) = self.transactions.get_mut(depend) {
+                parent.children.retain(|child| *child != short_txid);
+            }
+        }
+
+        for child in removed.children {
+            self.remove_with_descendants(child);
+        }
+    }
+
+    /// Checks if an outpoint is already spent in the mempool.
+    ///
+    /// This can be used to find conflicts before adding a transaction to the mempool.
+    fn is_already_spent(&self, outpoint: &OutPoint) -> bool {
+        self.spent.contains_key(outpoint)
     }
 
     /// Checks if the transaction doesn't have conflicting inputs or spends the same input twice.
@@ -299,6 +410,349 @@ impl Mempool {
     ///  - If either vIn or vOut are empty
     ///  - If any script is larger than the maximum allowed size
     pub fn accept_to_mempool(&mut self, transaction: Transaction) -> Result<(), MempoolError> {
+        self.insert(transaction, None)
+    }
+
+    /// Accepts a transaction we got from the network to mempool
+    ///
+    /// This works like [`accept_to_mempool`](Self::accept_to_mempool), but since we've
+    /// validated this transaction against our UTXO set, we know its fee. Transactions paying
+    /// less than [`MIN_RELAY_FEE_RATE`] are rejected.
+    pub fn accept_to_mempool_with_fee(
+        &mut self,
+        transaction: Transaction,
+        fee: Amount,
+    ) -> Result<(), MempoolError> {
+        let fee_rate = fee / transaction.weight();
+        if fee_rate < MIN_RELAY_FEE_RATE {
+            return Err(MempoolError::FeeTooLow(fee_rate));
+        }
+
+        // Dust is only allowed if a child in the same package spends it
+        if !Self::dust_outputs(&transaction).is_empty() {
+            return Err(MempoolError::EphemeralDust(
+                "dust must be spent by a child in the same package",
+            ));
+        }
+
+        self.insert(transaction, Some(fee))
+    }
+
+    /// Accepts a package of transactions to mempool
+    ///
+    /// A package is a child with all its unconfirmed parents, sorted so that every transaction
+    /// comes after the ones it spends. This lets the child pay for parents that can't get in on
+    /// their own, like the zero-fee transactions used with TRUC and anchor outputs.
+    ///
+    /// Transactions that are already in our mempool are skipped. The other ones are all
+    /// validated before we add any of them, so either the whole package gets in, or nothing does.
+    pub fn accept_package(&mut self, package: Vec<Transaction>) -> Result<(), MempoolError> {
+        let fees = vec![None; package.len()];
+        self.insert_package(package, fees)
+    }
+
+    /// Accepts a package we got from the network to mempool
+    ///
+    /// This works like [`accept_package`](Self::accept_package), with `fees` holding the fee of
+    /// each transaction, in the same order. Individual transactions may pay less than
+    /// [`MIN_RELAY_FEE_RATE`], but the package as a whole can't.
+    pub fn accept_package_with_fees(
+        &mut self,
+        package: Vec<Transaction>,
+        fees: Vec<Amount>,
+    ) -> Result<(), MempoolError> {
+        if fees.len() != package.len() {
+            return Err(MempoolError::InvalidPackage(
+                "we need the fee of every transaction",
+            ));
+        }
+
+        let fees = fees.into_iter().map(Some).collect();
+        self.insert_package(package, fees)
+    }
+
+    /// Validates all new transactions in a package, then adds them to our mempool
+    fn insert_package(
+        &mut self,
+        package: Vec<Transaction>,
+        fees: Vec<Option<Amount>>,
+    ) -> Result<(), MempoolError> {
+        Self::check_package_topology(&package)?;
+
+        let (transactions, fees): (Vec<_>, Vec<_>) = package
+            .into_iter()
+            .zip(fees)
+            .filter(|(tx, _)| self.get_from_mempool(&tx.compute_txid()).is_none())
+            .unzip();
+
+        if transactions.is_empty() {
+            return Ok(());
+        }
+
+        let size: usize = transactions.iter().map(Transaction::total_size).sum();
+        if self.mempool_size + size > self.max_mempool_size {
+            return Err(MempoolError::FullMempool);
+        }
+
+        for transaction in transactions.iter() {
+            Consensus::check_transaction_context_free(transaction)
+                .map_err(MempoolError::ConsensusValidation)?;
+
+            self.check_for_conflicts(transaction)?;
+            self.check_truc_rules(transaction, &transactions)?;
+        }
+
+        Self::check_ephemeral_dust(&transactions, &fees)?;
+
+        // If we know the fees, the package must pay for itself
+        if let Some(fee) = fees.iter().copied().sum::<Option<Amount>>() {
+            let weight: Weight = transactions.iter().map(Transaction::weight).sum();
+            let fee_rate = fee / weight;
+            if fee_rate < MIN_RELAY_FEE_RATE {
+                return Err(MempoolError::FeeTooLow(fee_rate));
+            }
+        }
+
+        for (transaction, fee) in transactions.into_iter().zip(fees) {
+            debug!("Accepting {} to mempool", transaction.compute_txid());
+            self.add_entry(transaction, fee);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `package` is a child with its parents, sorted topologically, and that it
+    /// isn't too big. Its transactions also can't spend the same output twice.
+    fn check_package_topology(package: &[Transaction]) -> Result<(), MempoolError> {
+        let Some((child, parents)) = package.split_last() else {
+            return Err(MempoolError::InvalidPackage("the package is empty"));
+        };
+
+        if package.len() > MAX_PACKAGE_COUNT {
+            return Err(MempoolError::InvalidPackage(
+                "the package has too many transactions",
+            ));
+        }
+
+        let weight: u64 = package.iter().map(|tx| tx.weight().to_wu()).sum();
+        if weight > MAX_PACKAGE_WEIGHT {
+            return Err(MempoolError::InvalidPackage("the package is too large"));
+        }
+
+        let txids: Vec<Txid> = package.iter().map(Transaction::compute_txid).collect();
+        if txids.iter().collect::<BTreeSet<_>>().len() != txids.len() {
+            return Err(MempoolError::InvalidPackage(
+                "the package has duplicated transactions",
+            ));
+        }
+
+        // A transaction can only spend the ones that come before it
+        for (i, tx) in package.iter().enumerate() {
+            let spends_later = tx
+                .input
+                .iter()
+                .any(|input| txids[i..].contains(&input.previous_output.txid));
+
+            if spends_later {
+                return Err(MempoolError::InvalidPackage(
+                    "the package isn't sorted topologically",
+                ));
+            }
+        }
+
+        let inputs = package.iter().flat_map(|tx| tx.input.iter());
+        let outpoints: BTreeSet<_> = inputs.clone().map(|input| input.previous_output).collect();
+
+        if outpoints.len() != inputs.count() {
+            return Err(MempoolError::InvalidPackage(
+                "the package spends the same output twice",
+            ));
+        }
+
+        let child_parents: BTreeSet<_> = child
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect();
+
+        let is_child_with_parents = txids[..parents.len()]
+            .iter()
+            .all(|txid| child_parents.contains(txid));
+
+        if !is_child_with_parents {
+            return Err(MempoolError::InvalidPackage(
+                "the package isn't a child with its parents",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Finds the unconfirmed parents of a transaction, either in our mempool or in `package`
+    fn unconfirmed_parents<'a>(
+        &'a self,
+        transaction: &Transaction,
+        package: &'a [Transaction],
+    ) -> Vec<&'a Transaction> {
+        let parent_txids: BTreeSet<Txid> = transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect();
+
+        parent_txids
+            .iter()
+            .filter_map(|txid| {
+                self.get_from_mempool(txid)
+                    .or_else(|| package.iter().find(|tx| tx.compute_txid() == *txid))
+            })
+            .collect()
+    }
+
+    /// Checks the TRUC (v3) topology rules from BIP 431, with `package` holding the other new
+    /// transactions we are adding along with this one
+    ///
+    /// TRUC transactions may have at most one unconfirmed parent and one unconfirmed child, and
+    /// they can't be mixed with non-TRUC ones while unconfirmed. Since we don't do sibling
+    /// eviction, a second child of a TRUC transaction is rejected.
+    fn check_truc_rules(
+        &self,
+        transaction: &Transaction,
+        package: &[Transaction],
+    ) -> Result<(), MempoolError> {
+        let parents = self.unconfirmed_parents(transaction, package);
+
+        if transaction.version != TRUC_VERSION {
+            if parents.iter().any(|parent| parent.version == TRUC_VERSION) {
+                return Err(MempoolError::TrucViolation(
+                    "non-TRUC transaction spends an unconfirmed TRUC transaction",
+                ));
+            }
+
+            return Ok(());
+        }
+
+        if transaction.vsize() > TRUC_MAX_VSIZE {
+            return Err(MempoolError::TrucViolation("the transaction is too large"));
+        }
+
+        let parent = match parents.as_slice() {
+            [] => return Ok(()),
+            [parent] => *parent,
+            _ => {
+                return Err(MempoolError::TrucViolation(
+                    "the transaction has more than one unconfirmed parent",
+                ))
+            }
+        };
+
+        if parent.version != TRUC_VERSION {
+            return Err(MempoolError::TrucViolation(
+                "TRUC transaction spends an unconfirmed non-TRUC transaction",
+            ));
+        }
+
+        if !self.unconfirmed_parents(parent, package).is_empty() {
+            return Err(MempoolError::TrucViolation(
+                "the transaction has more than one unconfirmed ancestor",
+            ));
+        }
+
+        if transaction.vsize() > TRUC_CHILD_MAX_VSIZE {
+            return Err(MempoolError::TrucViolation(
+                "the child transaction is too large",
+            ));
+        }
+
+        let txid = transaction.compute_txid();
+        let parent_txid = parent.compute_txid();
+        let spends_parent = |tx: &Transaction| {
+            tx.input
+                .iter()
+                .any(|input| input.previous_output.txid == parent_txid)
+        };
+
+        let has_sibling = self
+            .transactions
+            .get(&self.hasher.hash_one(parent_txid))
+            .is_some_and(|parent| !parent.children.is_empty())
+            || package
+                .iter()
+                .any(|tx| tx.compute_txid() != txid && spends_parent(tx));
+
+        if has_sibling {
+            return Err(MempoolError::TrucViolation(
+                "the parent already has an unconfirmed child",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of each dust output in this transaction
+    fn dust_outputs(transaction: &Transaction) -> Vec<u32> {
+        transaction
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.value < output.script_pubkey.minimal_non_dust())
+            .map(|(vout, _)| vout as u32)
+            .collect()
+    }
+
+    /// Checks the ephemeral dust rules for the new transactions in a package
+    ///
+    /// A transaction may create one dust output, like a zero-value anchor, only if it pays no
+    /// fees and the child in the same package spends it. This way the dust never stays in the
+    /// UTXO set, and whoever wants to bump the parent's fee has an output to spend.
+    fn check_ephemeral_dust(
+        package: &[Transaction],
+        fees: &[Option<Amount>],
+    ) -> Result<(), MempoolError> {
+        let Some(child) = package.last() else {
+            return Ok(());
+        };
+
+        for (transaction, fee) in package.iter().zip(fees) {
+            let dust = Self::dust_outputs(transaction);
+            let vout = match dust.as_slice() {
+                [] => continue,
+                [vout] => *vout,
+                _ => {
+                    return Err(MempoolError::EphemeralDust(
+                        "the transaction has more than one dust output",
+                    ))
+                }
+            };
+
+            if fee.is_some_and(|fee| fee != Amount::ZERO) {
+                return Err(MempoolError::EphemeralDust(
+                    "a transaction with dust must pay no fees",
+                ));
+            }
+
+            let dust = OutPoint::new(transaction.compute_txid(), vout);
+            if !child
+                .input
+                .iter()
+                .any(|input| input.previous_output == dust)
+            {
+                return Err(MempoolError::EphemeralDust(
+                    "dust must be spent by a child in the same package",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Actually adds a transaction to mempool, after making sure it fits and doesn't conflict
+    /// with anything we already have
+    fn insert(
+        &mut self,
+        transaction: Transaction,
+        fee: Option<Amount>,
+    ) -> Result<(), MempoolError> {
         debug!(
             "Accepting {} to mempool {:?}",
             transaction.compute_txid(),
@@ -324,6 +778,16 @@ impl Mempool {
 
         // Make sure transaction won't conflict with other mempool transaction
         self.check_for_conflicts(&transaction)?;
+        self.check_truc_rules(&transaction, &[])?;
+
+        self.add_entry(transaction, fee);
+        Ok(())
+    }
+
+    /// Adds a transaction we've already validated to all our indexes
+    fn add_entry(&mut self, transaction: Transaction, fee: Option<Amount>) {
+        let tx_size = transaction.total_size();
+        let short_txid = self.hasher.hash_one(transaction.compute_txid());
 
         // List dependants for this transaction
         let depends = self.find_mempool_depends(&transaction);
@@ -332,6 +796,13 @@ impl Mempool {
             tx.children.push(short_txid);
         }
 
+        for input in transaction.input.iter() {
+            self.spent.insert(input.previous_output, short_txid);
+        }
+
+        let short_wtxid = self.hasher.hash_one(transaction.compute_wtxid());
+        self.wtxids.insert(short_wtxid, short_txid);
+
         // Insert it into our mempool
         self.transactions.insert(
             short_txid,
@@ -340,11 +811,10 @@ impl Mempool {
                 depends,
                 transaction,
                 children: Vec::new(),
+                fee,
             },
         );
         self.mempool_size += tx_size;
-
-        Ok(())
     }
 
     /// From a transaction that is already in the mempool, computes which transaction it depends.
@@ -364,6 +834,53 @@ impl Mempool {
         self.transactions.get(&id).map(|tx| &tx.transaction)
     }
 
+    /// Get a transaction from the mempool, given its wtxid.
+    pub fn get_by_wtxid<'a>(&'a self, wtxid: &Wtxid) -> Option<&'a Transaction> {
+        let short_wtxid = self.hasher.hash_one(wtxid);
+        let id = self.wtxids.get(&short_wtxid)?;
+        self.transactions.get(id).map(|tx| &tx.transaction)
+    }
+
+    /// Returns a mempool transaction along with all its unconfirmed ancestors, sorted so that
+    /// parents come before their children. The transaction itself is the last one.
+    pub fn get_ancestor_package(&self, wtxid: &Wtxid) -> Option<Vec<Transaction>> {
+        let short_wtxid = self.hasher.hash_one(wtxid);
+        let short_txid = self.wtxids.get(&short_wtxid)?;
+
+        let mut package = Vec::new();
+        self.add_transaction_to_block(&mut package, *short_txid);
+
+        Some(package)
+    }
+
+    /// Whether we have a transaction with this wtxid in the mempool.
+    pub fn contains_wtxid(&self, wtxid: &Wtxid) -> bool {
+        self.wtxids.contains_key(&self.hasher.hash_one(wtxid))
+    }
+
+    /// The fee rate paid by a mempool transaction, if we know it.
+    pub fn get_fee_rate(&self, id: &Txid) -> Option<FeeRate> {
+        let id = self.hasher.hash_one(id);
+        let tx = self.transactions.get(&id)?;
+        tx.fee.map(|fee| fee / tx.transaction.weight())
+    }
+
+    /// Returns the outputs that `transaction` spends from other mempool transactions.
+    ///
+    /// Those outputs aren't in the UTXO set yet, so we can't get a proof for them.
+    pub fn get_unconfirmed_prevouts(&self, transaction: &Transaction) -> HashMap<OutPoint, TxOut> {
+        transaction
+            .input
+            .iter()
+            .filter_map(|input| {
+                let outpoint = input.previous_output;
+                let parent = self.get_from_mempool(&outpoint.txid)?;
+                let output = parent.output.get(outpoint.vout as usize)?;
+                Some((outpoint, output.clone()))
+            })
+            .collect()
+    }
+
     /// Get all transactions that were in the mempool for more than 1 hour, if any
     pub fn get_stale(&mut self) -> Vec<Txid> {
         self.transactions
@@ -394,6 +911,7 @@ mod tests {
     use bitcoin::BlockHash;
     use bitcoin::OutPoint;
     use bitcoin::Script;
+    use bitcoin::ScriptBuf;
     use bitcoin::Sequence;
     use bitcoin::Target;
     use bitcoin::Transaction;
@@ -407,6 +925,7 @@ mod tests {
     use rand::SeedableRng;
 
     use super::Mempool;
+    use super::TRUC_VERSION;
     use crate::mempool::MempoolError;
 
     /// builds a list of transactions in a pseudo-random way
@@ -515,7 +1034,7 @@ mod tests {
     #[test]
     fn test_gbt_with_conflict() {
         let mut mempool = Mempool::new(10_000_000);
-        let transactions = build_transactions(21, true);
+        let transactions = build_transactions(3, true);
         let mut did_conflict = false;
 
         for tx in transactions {
@@ -545,11 +1064,52 @@ mod tests {
 
         assert!(block.check_merkle_root());
 
-        // we can't really call check_block_transactions here, because the conflict logic only
-        // looks for inputs that are presently on mempool.
+        // we can't really call check_block_transactions here, because the mempool can't tell
+        // whether an input not spending a mempool transaction is actually confirmed. Some of
+        // these transactions spend outputs of the ones we've rejected.
         //
-        // To fix this, we need to add proof verification to mempool acceptance, so that we can
-        // know which inputs are actually valid.
+        // Transactions from the network have their proofs verified before being accepted, so
+        // this doesn't happen outside of tests.
+    }
+
+    #[test]
+    fn test_gbt_with_conflicting_transactions() {
+        let mut mempool = Mempool::new(10_000_000);
+        let transactions = build_transactions(21, true);
+        let mut did_conflict = false;
+
+        for tx in transactions {
+            match mempool.accept_to_mempool(tx) {
+                Ok(_) | Err(MempoolError::DuplicatedInputs) => {}
+                Err(MempoolError::ConflictingTransaction) => {
+                    did_conflict = true;
+                }
+
+                Err(e) => {
+                    panic!("unexpected error: {:?}", e);
+                }
+            }
+        }
+
+        // we expect at least one transaction spending an output another mempool transaction spends
+        assert!(did_conflict);
+
+        let target = Target::MAX_ATTAINABLE_REGTEST;
+        let block = mempool.get_block_template(
+            block::Version::ONE,
+            bitcoin::BlockHash::all_zeros(),
+            0,
+            target.to_compact_lossy(),
+            4_000_000,
+        );
+
+        assert!(block.check_merkle_root());
+
+        // since we've rejected the double spends, no output is spent twice in this block
+        let mut spent = HashSet::new();
+        for input in block.txdata.iter().flat_map(|tx| tx.input.iter()) {
+            assert!(spent.insert(input.previous_output));
+        }
     }
 
     fn check_block_transactions(block: Block) {
@@ -745,4 +1305,294 @@ mod tests {
             .depends
             .contains(&parent_short_txid));
     }
+
+    /// Builds a transaction spending `previous_output`, with one output of `value` sats
+    fn spend(previous_output: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::from_consensus(0),
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::new().into(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: Script::from_bytes(&[0x51]).into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_consume_block_removes_conflicts() {
+        let mut mempool = Mempool::new(10_000_000);
+        let funding = OutPoint {
+            txid: Txid::all_zeros(),
+            vout: 0,
+        };
+
+        let parent = spend(funding, 50_000);
+        let child = spend(OutPoint::new(parent.compute_txid(), 0), 49_000);
+        let unrelated = spend(OutPoint::new(Txid::all_zeros(), 1), 10_000);
+
+        mempool.accept_to_mempool(parent.clone()).unwrap();
+        mempool.accept_to_mempool(child.clone()).unwrap();
+        mempool.accept_to_mempool(unrelated.clone()).unwrap();
+
+        // Another transaction spending the same output can't get in
+        let double_spend = spend(funding, 40_000);
+        assert!(matches!(
+            mempool.accept_to_mempool(double_spend.clone()),
+            Err(MempoolError::ConflictingTransaction)
+        ));
+
+        // But it may get mined, evicting both parent and child
+        let block = Block {
+            header: Header {
+                version: block::Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: Target::MAX_ATTAINABLE_REGTEST.to_compact_lossy(),
+                nonce: 0,
+            },
+            txdata: vec![double_spend],
+        };
+        mempool.consume_block(&block);
+
+        assert!(mempool.get_from_mempool(&parent.compute_txid()).is_none());
+        assert!(mempool.get_from_mempool(&child.compute_txid()).is_none());
+        assert!(mempool.get_by_wtxid(&child.compute_wtxid()).is_none());
+        assert_eq!(mempool.list_mempool(), vec![unrelated.compute_txid()]);
+        assert_eq!(mempool.mempool_size, unrelated.total_size());
+
+        // The conflicting output is free again
+        mempool.accept_to_mempool(spend(funding, 30_000)).unwrap();
+    }
+
+    #[test]
+    fn test_accept_with_fee() {
+        let mut mempool = Mempool::new(10_000_000);
+        let tx = spend(OutPoint::new(Txid::all_zeros(), 0), 50_000);
+
+        // One sat per vbyte is the minimum
+        let min_fee = Amount::from_sat(tx.vsize() as u64);
+        assert!(matches!(
+            mempool.accept_to_mempool_with_fee(tx.clone(), min_fee - Amount::from_sat(1)),
+            Err(MempoolError::FeeTooLow(_))
+        ));
+
+        mempool
+            .accept_to_mempool_with_fee(tx.clone(), min_fee)
+            .unwrap();
+
+        let fee_rate = mempool.get_fee_rate(&tx.compute_txid()).unwrap();
+        assert_eq!(fee_rate.to_sat_per_vb_floor(), 1);
+        assert_eq!(mempool.get_by_wtxid(&tx.compute_wtxid()), Some(&tx));
+        assert!(mempool.contains_wtxid(&tx.compute_wtxid()));
+
+        // We know the outputs of mempool transactions, even though they aren't confirmed
+        let child = spend(OutPoint::new(tx.compute_txid(), 0), 40_000);
+        let prevouts = mempool.get_unconfirmed_prevouts(&child);
+        assert_eq!(
+            prevouts.get(&OutPoint::new(tx.compute_txid(), 0)),
+            Some(&tx.output[0])
+        );
+    }
+
+    /// Builds a child spending the first output of each parent, with one output of `value` sats
+    fn spend_all(parents: &[&Transaction], value: u64) -> Transaction {
+        let mut child = spend(OutPoint::new(parents[0].compute_txid(), 0), value);
+        child.input = parents
+            .iter()
+            .map(|parent| TxIn {
+                previous_output: OutPoint::new(parent.compute_txid(), 0),
+                ..child.input[0].clone()
+            })
+            .collect();
+
+        child
+    }
+
+    #[test]
+    fn test_accept_package() {
+        let mut mempool = Mempool::new(10_000_000);
+
+        // A parent paying nothing can't get in by itself...
+        let parent = spend(OutPoint::new(Txid::all_zeros(), 0), 50_000);
+        assert!(matches!(
+            mempool.accept_to_mempool_with_fee(parent.clone(), Amount::ZERO),
+            Err(MempoolError::FeeTooLow(_))
+        ));
+
+        // ...but its child can pay for both
+        let child = spend(OutPoint::new(parent.compute_txid(), 0), 49_000);
+        let package = vec![parent.clone(), child.clone()];
+        let child_fee = Amount::from_sat(1_000);
+
+        // The package must be sorted
+        assert!(matches!(
+            mempool.accept_package_with_fees(
+                vec![child.clone(), parent.clone()],
+                vec![child_fee, Amount::ZERO]
+            ),
+            Err(MempoolError::InvalidPackage(_))
+        ));
+
+        // And pay enough for all of it
+        assert!(matches!(
+            mempool.accept_package_with_fees(
+                package.clone(),
+                vec![Amount::ZERO, Amount::from_sat(100)]
+            ),
+            Err(MempoolError::FeeTooLow(_))
+        ));
+
+        mempool
+            .accept_package_with_fees(package.clone(), vec![Amount::ZERO, child_fee])
+            .unwrap();
+
+        assert!(mempool.get_from_mempool(&parent.compute_txid()).is_some());
+        assert!(mempool.get_from_mempool(&child.compute_txid()).is_some());
+        assert_eq!(
+            mempool.get_ancestor_package(&child.compute_wtxid()),
+            Some(package.clone())
+        );
+
+        // Submitting it again is a no-op
+        mempool.accept_package(package).unwrap();
+        assert_eq!(mempool.list_mempool().len(), 2);
+
+        // Every parent must be spent by the child
+        let unrelated = spend(OutPoint::new(Txid::all_zeros(), 1), 50_000);
+        let other_child = spend(OutPoint::new(Txid::all_zeros(), 2), 50_000);
+        assert!(matches!(
+            mempool.accept_package(vec![unrelated, other_child]),
+            Err(MempoolError::InvalidPackage(_))
+        ));
+
+        // Parents already in the mempool are fine
+        let grandchild = spend(OutPoint::new(child.compute_txid(), 0), 48_000);
+        mempool
+            .accept_package(vec![child.clone(), grandchild.clone()])
+            .unwrap();
+        assert_eq!(
+            mempool.get_ancestor_package(&grandchild.compute_wtxid()),
+            Some(vec![parent, child, grandchild])
+        );
+    }
+
+    #[test]
+    fn test_truc_rules() {
+        let mut mempool = Mempool::new(10_000_000);
+
+        let mut parent = spend(OutPoint::new(Txid::all_zeros(), 0), 50_000);
+        parent.output.push(parent.output[0].clone());
+        parent.version = TRUC_VERSION;
+        mempool.accept_to_mempool(parent.clone()).unwrap();
+
+        // A non-TRUC transaction can't spend an unconfirmed TRUC one
+        let non_truc = spend(OutPoint::new(parent.compute_txid(), 0), 40_000);
+        assert!(matches!(
+            mempool.accept_to_mempool(non_truc),
+            Err(MempoolError::TrucViolation(_))
+        ));
+
+        // TRUC children must be small
+        let mut big_child = spend(OutPoint::new(parent.compute_txid(), 0), 40_000);
+        big_child.version = TRUC_VERSION;
+        big_child.output[0].script_pubkey = ScriptBuf::from_bytes(vec![0x51; 1_000]);
+        assert!(matches!(
+            mempool.accept_to_mempool(big_child),
+            Err(MempoolError::TrucViolation(_))
+        ));
+
+        let mut child = spend(OutPoint::new(parent.compute_txid(), 0), 40_000);
+        child.version = TRUC_VERSION;
+        mempool.accept_to_mempool(child.clone()).unwrap();
+
+        // Only one child is allowed
+        let mut sibling = spend(OutPoint::new(parent.compute_txid(), 1), 40_000);
+        sibling.version = TRUC_VERSION;
+        assert!(matches!(
+            mempool.accept_to_mempool(sibling),
+            Err(MempoolError::TrucViolation(_))
+        ));
+
+        // And only one unconfirmed ancestor
+        let mut grandchild = spend(OutPoint::new(child.compute_txid(), 0), 30_000);
+        grandchild.version = TRUC_VERSION;
+        assert!(matches!(
+            mempool.accept_to_mempool(grandchild),
+            Err(MempoolError::TrucViolation(_))
+        ));
+
+        // The same rules apply inside a package, a TRUC child can't have two parents
+        let mut first = spend(OutPoint::new(Txid::all_zeros(), 1), 50_000);
+        first.version = TRUC_VERSION;
+        let mut second = spend(OutPoint::new(Txid::all_zeros(), 2), 50_000);
+        second.version = TRUC_VERSION;
+        let mut child = spend_all(&[&first, &second], 90_000);
+        child.version = TRUC_VERSION;
+
+        assert!(matches!(
+            mempool.accept_package(vec![first.clone(), second.clone(), child]),
+            Err(MempoolError::TrucViolation(_))
+        ));
+
+        // Nothing from the failed package got in
+        assert!(mempool.get_from_mempool(&first.compute_txid()).is_none());
+        assert!(mempool.get_from_mempool(&second.compute_txid()).is_none());
+    }
+
+    #[test]
+    fn test_ephemeral_dust() {
+        let mut mempool = Mempool::new(10_000_000);
+
+        // A zero-fee TRUC parent with a zero-value anchor, like a Lightning commitment
+        let mut parent = spend(OutPoint::new(Txid::all_zeros(), 0), 50_000);
+        parent.version = TRUC_VERSION;
+        parent.output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_p2a(),
+        });
+
+        // The dust can't get in by itself, even if the fee was enough
+        assert!(matches!(
+            mempool.accept_to_mempool_with_fee(parent.clone(), Amount::from_sat(1_000)),
+            Err(MempoolError::EphemeralDust(_))
+        ));
+
+        let mut child = spend(OutPoint::new(parent.compute_txid(), 0), 45_000);
+        child.version = TRUC_VERSION;
+        let fees = vec![Amount::ZERO, Amount::from_sat(5_000)];
+
+        // The child must spend the anchor
+        assert!(matches!(
+            mempool.accept_package_with_fees(vec![parent.clone(), child.clone()], fees.clone()),
+            Err(MempoolError::EphemeralDust(_))
+        ));
+
+        child.input.push(TxIn {
+            previous_output: OutPoint::new(parent.compute_txid(), 1),
+            ..child.input[0].clone()
+        });
+
+        // And the parent can't pay any fees
+        assert!(matches!(
+            mempool.accept_package_with_fees(
+                vec![parent.clone(), child.clone()],
+                vec![Amount::from_sat(1), Amount::from_sat(5_000)]
+            ),
+            Err(MempoolError::EphemeralDust(_))
+        ));
+
+        mempool
+            .accept_package_with_fees(vec![parent.clone(), child.clone()], fees)
+            .unwrap();
+
+        assert!(mempool.get_from_mempool(&parent.compute_txid()).is_some());
+        assert!(mempool.get_from_mempool(&child.compute_txid()).is_some());
+    }
 }
@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Chain spec files, describing a custom chain for private test networks.
+//!
+//! A chain spec is a TOML (or JSON, if the file ends with `.json`) file that overrides the
+//! parameters of the network we're running on. Every field is optional: anything missing is
+//! taken from `--network`. For example, a regtest-like chain with its own magic, port and
+//! genesis time could look like this:
+//!
+//! ```toml
+//! magic = "f00dbabe"
+//! port = 28444
+//! segwit_height = 0
+//! taproot_height = 0
+//!
+//! [genesis]
+//! time = 1700000000
+//! nonce = 0
+//! ```
+
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::block::Version;
+use bitcoin::constants::genesis_block;
+use bitcoin::p2p::Magic;
+use bitcoin::p2p::ServiceFlags;
+use bitcoin::params::Params;
+use bitcoin::BlockHash;
+use bitcoin::CompactTarget;
+use bitcoin::Network;
+use bitcoin::ScriptBuf;
+use bitcoin::Target;
+use floresta_chain::pruned_utreexo::chainparams_builder::ChainParamsBuilder;
+use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::ChainParams;
+use floresta_chain::DnsSeed;
+use rustreexo::node_hash::BitcoinNodeHash;
+use serde::Deserialize;
+
+use crate::error::FlorestadError;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Fields of the genesis header to override. The genesis coinbase is kept as is.
+pub struct GenesisSpec {
+    pub version: Option<i32>,
+    pub time: Option<u32>,
+    /// The genesis target, in compact form
+    pub bits: Option<u32>,
+    pub nonce: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// A Utreexo accumulator we may start from, see [AssumeUtreexoValue]
+pub struct AssumeUtreexoSpec {
+    pub block_hash: String,
+    pub height: u32,
+    pub roots: Vec<String>,
+    pub leaves: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+/// The contents of a chain spec file
+pub struct ChainSpec {
+    /// The network magic, as four hex-encoded bytes
+    pub magic: Option<String>,
+    pub port: Option<u16>,
+    pub genesis: Option<GenesisSpec>,
+
+    /// The easiest target allowed, in compact form
+    pub pow_limit: Option<u32>,
+    pub pow_target_spacing: Option<u64>,
+    pub pow_target_timespan: Option<u64>,
+    pub no_pow_retargeting: Option<bool>,
+    pub allow_min_difficulty_blocks: Option<bool>,
+    pub enforce_bip94: Option<bool>,
+
+    pub subsidy_halving_interval: Option<u32>,
+    pub coinbase_maturity: Option<u32>,
+
+    pub bip34_height: Option<u32>,
+    pub bip65_height: Option<u32>,
+    pub bip66_height: Option<u32>,
+    pub csv_height: Option<u32>,
+    pub segwit_height: Option<u32>,
+    pub taproot_height: Option<u32>,
+
+    /// A signet challenge, as a hex-encoded script
+    pub signet_challenge: Option<String>,
+    pub dns_seeds: Option<Vec<String>>,
+
+    pub assume_valid: Option<String>,
+    pub assume_utreexo: Option<AssumeUtreexoSpec>,
+}
+
+impl ChainSpec {
+    /// Reads a chain spec from a file, in JSON if its extension is `.json`, or TOML otherwise
+    pub fn from_file(filename: &str) -> Result<Self, FlorestadError> {
+        let file = std::fs::read_to_string(filename)?;
+
+        match Path::new(filename).extension() {
+            Some(ext) if ext == "json" => Ok(serde_json::from_str(&file)?),
+            _ => Ok(toml::from_str(&file)?),
+        }
+    }
+
+    /// Sets a signet challenge and DNS seeds, overriding the ones in this spec
+    pub fn with_signet(mut self, challenge: String, seeds: Vec<String>) -> Self {
+        self.signet_challenge = Some(challenge);
+        if !seeds.is_empty() {
+            self.dns_seeds = Some(seeds);
+        }
+
+        self
+    }
+
+    /// Builds the parameters for this chain, using `network` for anything not in the spec
+    pub fn chain_params(self, network: Network) -> Result<ChainParams, FlorestadError> {
+        let mut builder = ChainParamsBuilder::new(network);
+
+        if let Some(genesis) = self.genesis {
+            let mut block = genesis_block(Params::new(network));
+            if let Some(version) = genesis.version {
+                block.header.version = Version::from_consensus(version);
+            }
+            if let Some(time) = genesis.time {
+                block.header.time = time;
+            }
+            if let Some(bits) = genesis.bits {
+                block.header.bits = CompactTarget::from_consensus(bits);
+            }
+            if let Some(nonce) = genesis.nonce {
+                block.header.nonce = nonce;
+            }
+
+            builder = builder.with_genesis(block);
+        }
+
+        if let Some(magic) = self.magic {
+            let magic = Magic::from_str(&magic)
+                .map_err(|_| FlorestadError::InvalidChainSpec(format!("bad magic {magic}")))?;
+            builder = builder.with_magic(magic);
+        }
+
+        if let Some(challenge) = self.signet_challenge {
+            let script = ScriptBuf::from_hex(&challenge)
+                .map_err(|_| FlorestadError::InvalidSignetChallenge(challenge))?;
+            builder = builder.with_signet_challenge(script);
+        }
+
+        if let Some(seeds) = self.dns_seeds {
+            let seeds = seeds
+                .iter()
+                .map(|seed| DnsSeed::new(network, seed, ServiceFlags::NONE))
+                .collect();
+            builder = builder.with_dns_seeds(seeds);
+        }
+
+        if let Some(pow_limit) = self.pow_limit {
+            let pow_limit = Target::from_compact(CompactTarget::from_consensus(pow_limit));
+            builder = builder.with_pow_limit(pow_limit);
+        }
+
+        if let Some(hash) = self.assume_valid {
+            builder = builder.with_assume_valid(parse_block_hash(&hash)?);
+        }
+
+        if let Some(acc) = self.assume_utreexo {
+            let roots = acc
+                .roots
+                .iter()
+                .map(|root| {
+                    BitcoinNodeHash::from_str(root).map_err(|_| {
+                        FlorestadError::InvalidChainSpec(format!("bad utreexo root {root}"))
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+
+            builder = builder.with_assume_utreexo(AssumeUtreexoValue {
+                block_hash: parse_block_hash(&acc.block_hash)?,
+                height: acc.height,
+                roots,
+                leaves: acc.leaves,
+            });
+        }
+
+        macro_rules! apply {
+            ($($field:ident => $setter:ident),* $(,)?) => {
+                $(
+                    if let Some(value) = self.$field {
+                        builder = builder.$setter(value);
+                    }
+                )*
+            };
+        }
+
+        apply!(
+            port => with_port,
+            pow_target_spacing => with_pow_target_spacing,
+            pow_target_timespan => with_pow_target_timespan,
+            no_pow_retargeting => with_no_pow_retargeting,
+            allow_min_difficulty_blocks => with_min_difficulty_blocks,
+            enforce_bip94 => with_bip94,
+            subsidy_halving_interval => with_subsidy_halving_interval,
+            coinbase_maturity => with_coinbase_maturity,
+            bip34_height => with_bip34_height,
+            bip65_height => with_bip65_height,
+            bip66_height => with_bip66_height,
+            csv_height => with_csv_height,
+            segwit_height => with_segwit_height,
+            taproot_height => with_taproot_height,
+        );
+
+        Ok(builder.build()?)
+    }
+}
+
+fn parse_block_hash(hash: &str) -> Result<BlockHash, FlorestadError> {
+    BlockHash::from_str(hash)
+        .map_err(|_| FlorestadError::InvalidChainSpec(format!("bad block hash {hash}")))
+}
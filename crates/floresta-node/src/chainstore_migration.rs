@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Converts our chain data between the flat and kv chainstore backends
+//!
+//! The new store is built next to the old one, in `chaindata.migrating`, and only replaces it
+//! once it's complete and flushed. The old store is kept in `chaindata.old`, so an interrupted
+//! migration never leaves us without chain data.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use std::fs;
+use std::path::Path;
+
+use floresta_chain::copy_chainstore;
+use floresta_chain::FlatChainStore;
+use floresta_chain::FlatChainStoreConfig;
+use floresta_chain::KvChainStore;
+use tracing::info;
+
+use crate::error::FlorestadError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The chainstore backends we can convert between
+pub enum ChainStoreBackend {
+    /// The mmap-based `FlatChainStore`
+    Flat,
+
+    /// The `KvChainStore`, on top of an embedded key-value database
+    Kv,
+}
+
+impl Display for ChainStoreBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainStoreBackend::Flat => write!(f, "flat"),
+            ChainStoreBackend::Kv => write!(f, "kv"),
+        }
+    }
+}
+
+impl ChainStoreBackend {
+    /// Finds out which backend wrote the chain data in `path`, if any
+    pub fn detect(path: &Path) -> Option<Self> {
+        // The flat chainstore always has a metadata file, and the kv one keeps its data in
+        // sled's `db` file
+        if path.join("metadata.bin").exists() {
+            return Some(ChainStoreBackend::Flat);
+        }
+
+        if path.join("db").exists() {
+            return Some(ChainStoreBackend::Kv);
+        }
+
+        None
+    }
+}
+
+/// Converts the chain data in `data_dir` to the `to` backend, in place
+pub fn migrate_chainstore(data_dir: &str, to: ChainStoreBackend) -> Result<(), FlorestadError> {
+    let data_dir = Path::new(data_dir);
+    let path = data_dir.join("chaindata");
+    let new_path = data_dir.join("chaindata.migrating");
+    let old_path = data_dir.join("chaindata.old");
+
+    let from = ChainStoreBackend::detect(&path).ok_or_else(|| {
+        FlorestadError::ChainStoreMigration(format!("No chain data found in {path:?}"))
+    })?;
+
+    if from == to {
+        return Err(FlorestadError::ChainStoreMigration(format!(
+            "The chain data in {path:?} already uses the {to} chainstore"
+        )));
+    }
+
+    if old_path.exists() {
+        return Err(FlorestadError::ChainStoreMigration(format!(
+            "{old_path:?} already exists, remove it before migrating again"
+        )));
+    }
+
+    // This is what's left of an interrupted migration, we never used it
+    if new_path.exists() {
+        fs::remove_dir_all(&new_path)?;
+    }
+
+    info!("Migrating the chain data in {path:?} from the {from} to the {to} chainstore");
+    match to {
+        ChainStoreBackend::Flat => {
+            let mut from = open_kv(&path)?;
+            let mut to = open_flat(&new_path)?;
+            copy_chainstore(&mut from, &mut to).map_err(FlorestadError::CouldNotLoadChainStore)?;
+        }
+
+        ChainStoreBackend::Kv => {
+            let mut from = open_flat(&path)?;
+            let mut to = open_kv(&new_path)?;
+            copy_chainstore(&mut from, &mut to).map_err(FlorestadError::CouldNotLoadChainStore)?;
+        }
+    }
+
+    fs::rename(&path, &old_path)?;
+    fs::rename(&new_path, &path)?;
+
+    info!("Done, the old chain data is in {old_path:?} and may be removed");
+    Ok(())
+}
+
+fn open_flat(path: &Path) -> Result<FlatChainStore, FlorestadError> {
+    FlatChainStore::new(FlatChainStoreConfig::new(path))
+        .map_err(|e| FlorestadError::CouldNotLoadChainStore(e.into()))
+}
+
+fn open_kv(path: &Path) -> Result<KvChainStore, FlorestadError> {
+    KvChainStore::new(path).map_err(|e| FlorestadError::CouldNotLoadChainStore(e.into()))
+}
@@ -11,9 +11,16 @@ pub struct Wallet {
     pub addresses: Option<Vec<String>>,
 }
 
+#[derive(Default, Debug, Deserialize)]
+pub struct Signet {
+    pub challenge: Option<String>,
+    pub seeds: Option<Vec<String>>,
+}
+
 #[derive(Default, Debug, Deserialize)]
 pub struct ConfigFile {
     pub wallet: Wallet,
+    pub signet: Option<Signet>,
 }
 
 impl ConfigFile {
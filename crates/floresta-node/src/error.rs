@@ -7,10 +7,15 @@ use core::fmt::Formatter;
 use core::net::AddrParseError;
 
 use bitcoin::consensus::encode;
+use bitcoin::Network;
+use floresta_chain::pruned_utreexo::chainparams_builder::ChainParamsBuilderError;
 use floresta_chain::BlockValidationErrors;
 use floresta_chain::BlockchainError;
+use floresta_chain::UtreexoSnapshotError;
 #[cfg(feature = "compact-filters")]
 use floresta_compact_filters::IterableFilterStoreError;
+use floresta_electrum::peers::PeerError;
+use floresta_electrum::tenants::TenantError;
 use floresta_watch_only::descriptor::DescriptorError;
 use floresta_watch_only::kv_database::KvDatabaseError;
 use floresta_watch_only::WatchOnlyError;
@@ -100,6 +105,12 @@ pub enum FlorestadError {
     /// Failed to bind the Electrum server to a socket.
     FailedToBindElectrumServer(std::io::Error),
 
+    /// An Electrum public host or peer couldn't be parsed.
+    InvalidElectrumHost(PeerError),
+
+    /// An Electrum tenant couldn't be parsed.
+    InvalidElectrumTenant(TenantError),
+
     /// Failed to create the TLS data directory.
     CouldNotCreateTLSDataDir(String, std::io::Error),
 
@@ -118,8 +129,32 @@ pub enum FlorestadError {
     /// Resolve a hostname error.
     CouldNotResolveHostname(std::io::Error),
 
-    /// Load a flat chain store error.
-    CouldNotLoadFlatChainStore(BlockchainError),
+    /// Load a chain store error.
+    CouldNotLoadChainStore(BlockchainError),
+
+    /// Our chain data was written by another chainstore backend than the one we're built with.
+    ChainStoreBackendMismatch(&'static str),
+
+    /// Converting our chain data to another chainstore backend failed.
+    ChainStoreMigration(String),
+
+    /// Load an ASmap file error.
+    CouldNotLoadAsmap(String, std::io::Error),
+
+    /// The signet challenge isn't a hex-encoded script.
+    InvalidSignetChallenge(String),
+
+    /// A signet challenge was given, but we aren't running on signet.
+    SignetChallengeWithoutSignet(Network),
+
+    /// A field of the chain spec couldn't be parsed.
+    InvalidChainSpec(String),
+
+    /// The chain parameters are inconsistent, like a genesis block with invalid PoW.
+    InvalidChainParams(ChainParamsBuilderError),
+
+    /// The Utreexo snapshot at this path couldn't be read.
+    InvalidUtreexoSnapshot(String, UtreexoSnapshotError),
 }
 
 impl Display for FlorestadError {
@@ -191,6 +226,12 @@ impl Display for FlorestadError {
             FlorestadError::FailedToBindElectrumServer(err) => {
                 write!(f, "Failed to bind Electrum server: {err}")
             }
+            FlorestadError::InvalidElectrumHost(err) => {
+                write!(f, "Invalid Electrum host: {err}")
+            }
+            FlorestadError::InvalidElectrumTenant(err) => {
+                write!(f, "Invalid Electrum tenant: {err}")
+            }
             FlorestadError::CouldNotCreateTLSDataDir(path, err) => {
                 write!(f, "Could not create TLS data directory {path}: {err}")
             }
@@ -209,8 +250,36 @@ impl Display for FlorestadError {
             FlorestadError::CouldNotResolveHostname(host) => {
                 write!(f, "Could not resolve hostname: {host}")
             }
-            FlorestadError::CouldNotLoadFlatChainStore(err) => {
-                write!(f, "Failure while loading flat chainstore: {err:?}")
+            FlorestadError::CouldNotLoadChainStore(err) => {
+                write!(f, "Failure while loading chainstore: {err:?}")
+            }
+            FlorestadError::ChainStoreBackendMismatch(backend) => {
+                write!(f, "Our chain data was written by the {backend} chainstore, but we're built with another one. Convert it with `migrate-chainstore`")
+            }
+            FlorestadError::ChainStoreMigration(reason) => {
+                write!(f, "Could not migrate the chainstore: {reason}")
+            }
+            FlorestadError::CouldNotLoadAsmap(path, err) => {
+                write!(f, "Could not load asmap file {path}: {err}")
+            }
+            FlorestadError::InvalidSignetChallenge(challenge) => {
+                write!(
+                    f,
+                    "Invalid signet challenge, expected a hex script: {challenge}"
+                )
+            }
+            FlorestadError::SignetChallengeWithoutSignet(network) => {
+                write!(
+                    f,
+                    "A signet challenge was given, but we are running on {network}"
+                )
+            }
+            FlorestadError::InvalidChainSpec(err) => write!(f, "Invalid chain spec: {err}"),
+            FlorestadError::InvalidChainParams(err) => {
+                write!(f, "Invalid chain parameters: {err}")
+            }
+            FlorestadError::InvalidUtreexoSnapshot(path, err) => {
+                write!(f, "Invalid Utreexo snapshot {path}: {err}")
             }
         }
     }
@@ -240,6 +309,7 @@ impl_from_error!(TomlParsing, toml::de::Error);
 impl_from_error!(BlockValidation, BlockValidationErrors);
 impl_from_error!(AddressParsing, bitcoin::address::ParseError);
 impl_from_error!(Miniscript, miniscript::Error);
+impl_from_error!(InvalidChainParams, ChainParamsBuilderError);
 impl_from_error!(CouldNotObtainWalletCache, WatchOnlyError<KvDatabaseError>);
 
 impl error::Error for FlorestadError {}
@@ -2,9 +2,10 @@
 
 #[cfg(feature = "metrics")]
 use core::net::IpAddr;
-#[cfg(feature = "metrics")]
 use core::net::Ipv4Addr;
+use core::net::Ipv6Addr;
 use core::net::SocketAddr;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
@@ -14,6 +15,7 @@ use std::sync::Mutex;
 #[cfg(feature = "json-rpc")]
 use std::sync::OnceLock;
 
+use bitcoin::p2p::Magic;
 use bitcoin::Address;
 pub use bitcoin::Network;
 use bitcoin::ScriptBuf;
@@ -23,22 +25,43 @@ pub use floresta_chain::AssumeUtreexoValue;
 pub use floresta_chain::AssumeValidArg;
 use floresta_chain::ChainParams;
 use floresta_chain::ChainState;
+#[cfg(not(feature = "kv-chainstore"))]
 use floresta_chain::FlatChainStore as ChainStore;
+#[cfg(not(feature = "kv-chainstore"))]
 use floresta_chain::FlatChainStoreConfig;
+#[cfg(feature = "kv-chainstore")]
+use floresta_chain::KvChainStore as ChainStore;
+#[cfg(not(feature = "kv-chainstore"))]
+use floresta_chain::RootsRetention;
 #[cfg(feature = "compact-filters")]
 use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
 #[cfg(feature = "compact-filters")]
 use floresta_compact_filters::network_filters::NetworkFilters;
 use floresta_electrum::electrum_protocol::client_accept_loop;
 use floresta_electrum::electrum_protocol::ElectrumServer;
+pub use floresta_electrum::limits::ElectrumLimits;
+use floresta_electrum::peers::PeerDiscoveryConfig;
+use floresta_electrum::peers::ServerHost;
+use floresta_electrum::tenants::PinnedClientCertVerifier;
+use floresta_electrum::tenants::TenantConfig;
+use floresta_electrum::tenants::TENANTS_DIR_NAME;
 use floresta_mempool::Mempool;
 use floresta_watch_only::kv_database::KvDatabase;
 use floresta_watch_only::AddressCache;
 use floresta_watch_only::WatchOnlyError;
 use floresta_wire::address_man::AddressMan;
+use floresta_wire::address_man::ReachableNetworks;
 use floresta_wire::address_man::SUPPORTED_NETWORKS;
+use floresta_wire::asmap::AsMap;
+use floresta_wire::i2p::DEFAULT_SAM_PORT;
 use floresta_wire::node::running_ctx::RunningNode;
 use floresta_wire::node::UtreexoNode;
+use floresta_wire::node_interface::NodeInterface;
+use floresta_wire::rescan::RescanManager;
+use floresta_wire::tor_control::onion_key_file;
+use floresta_wire::tor_control::OnionPort;
+use floresta_wire::tor_control::OnionService;
+use floresta_wire::tor_control::DEFAULT_TOR_CONTROL_PORT;
 use floresta_wire::UtreexoNodeConfig;
 use rcgen::BasicConstraints;
 use rcgen::CertificateParams;
@@ -47,9 +70,7 @@ use rcgen::KeyPair;
 use tokio::net::TcpListener;
 use tokio::sync::RwLock;
 use tokio::task;
-#[cfg(feature = "metrics")]
 use tokio::time::Duration;
-#[cfg(feature = "metrics")]
 use tokio::time::{self};
 use tokio_rustls::rustls::pki_types::pem::PemObject;
 use tokio_rustls::rustls::pki_types::CertificateDer;
@@ -61,6 +82,7 @@ use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use crate::chain_spec::ChainSpec;
 use crate::config_file::ConfigFile;
 use crate::error::FlorestadError;
 use crate::florestad::fs::OpenOptions;
@@ -129,9 +151,62 @@ pub struct Config {
     /// will be made through this one, except dns seed connections.
     pub proxy: Option<String>,
 
+    /// The SAM bridge of an I2P router, used to connect with peers in the I2P network
+    ///
+    /// If this isn't set, we won't try to connect to I2P addresses.
+    pub i2psam: Option<String>,
+
+    /// Whether we should keep the same I2P address across restarts
+    ///
+    /// If set, our I2P private key is saved inside our data directory. Otherwise, we get a new
+    /// I2P address every time we start.
+    pub i2p_persistent: bool,
+
+    /// Whether we should broadcast our own transactions through short-lived connections, made
+    /// over our proxy or I2P, instead of announcing them to our regular peers
+    ///
+    /// This requires either [Config::proxy] or [Config::i2psam].
+    pub private_broadcast: bool,
+
+    /// How many MiB we may upload to our peers every 24 hours, if limited
+    ///
+    /// Close to this target, we stop answering bandwidth-heavy requests, and once we reach it,
+    /// we also stop serving mempool transactions.
+    pub max_upload_target: Option<u64>,
+
+    /// Tor's control port, used to publish an onion service that other nodes can connect to
+    ///
+    /// Our Electrum and RPC servers may be published too, see [Config::onion_electrum] and
+    /// [Config::onion_rpc].
+    pub tor_control: Option<String>,
+
+    /// The password for Tor's control port, if it uses one instead of a cookie
+    pub tor_password: Option<String>,
+
+    /// Whether our onion service should include our Electrum server
+    pub onion_electrum: bool,
+
+    /// Whether our onion service should include our JSON-RPC server
+    ///
+    /// Anyone who knows our onion address will be able to use it, so only enable this if you
+    /// know what you are doing.
+    pub onion_rpc: bool,
+
     /// The network we are running in, it may be one of: bitcoin, signet, regtest or testnet.
     pub network: Network,
 
+    /// The challenge of a custom signet, as a hex-encoded script
+    ///
+    /// If set, `network` must be signet. Every block must satisfy this challenge, and we only
+    /// talk to peers on the same signet. May also be set in the config file.
+    pub signet_challenge: Option<String>,
+
+    /// DNS seeds for our custom signet, only used with `signet_challenge`
+    pub signet_seeds: Vec<String>,
+
+    /// A chain spec file, overriding the parameters of `network`. See [ChainSpec].
+    pub chain_spec: Option<String>,
+
     /// Whether we should build and store compact block filters
     ///
     /// Those filters are used for rescanning our wallet for historical transactions. If you don't
@@ -174,6 +249,13 @@ pub struct Config {
     /// Whether we should use assume utreexo
     pub assume_utreexo: bool,
 
+    /// Whether we should use PoW fraud proofs
+    ///
+    /// If set, we don't use the hardcoded assume utreexo value, we ask our peers for the
+    /// accumulator of the most-work chain instead, and check any other tip they announce. A
+    /// snapshot or an explicit assume utreexo value still take precedence.
+    pub pow_fraud_proofs: bool,
+
     /// Whether we should post debug information to the console
     pub debug: bool,
 
@@ -183,6 +265,9 @@ pub struct Config {
     /// The value to use for assumeutreexo
     pub assumeutreexo_value: Option<AssumeUtreexoValue>,
 
+    /// A Utreexo snapshot file to start from, taking precedence over `assumeutreexo_value`
+    pub utreexo_snapshot: Option<String>,
+
     /// Address the Electrum Server will listen to.
     pub electrum_address: Option<String>,
 
@@ -192,6 +277,36 @@ pub struct Config {
     /// Address the Electrum TLS Server will listen to.
     pub electrum_address_tls: Option<String>,
 
+    /// Resource limits enforced by the Electrum Server on its clients.
+    ///
+    /// Those protect the node from clients opening too many connections, subscribing to too
+    /// many addresses or flooding us with requests. See [ElectrumLimits] for the defaults.
+    pub electrum_limits: ElectrumLimits,
+
+    /// Where our Electrum Server is publicly reachable, in the format
+    /// `<hostname>[:t<tcp_port>][:s<ssl_port>]`.
+    ///
+    /// Those hosts are reported to wallets on `server.features`, and announced to other Electrum
+    /// servers, so our server can take part in the Electrum server network. Onion addresses are
+    /// also accepted. If empty, we don't announce our server to anyone.
+    pub electrum_public_hosts: Vec<String>,
+
+    /// Electrum servers we should connect to, check and announce ourselves to, in the same
+    /// format as `electrum_public_hosts`.
+    pub electrum_seed_peers: Vec<String>,
+
+    /// Tenants of our Electrum Server, in the format `<name>:<token>`.
+    ///
+    /// If any tenant is configured, the Electrum Server runs in multi-tenant mode: each tenant
+    /// gets its own wallet, stored in `{data_dir}/electrum-tenants/<name>`, and clients must call
+    /// `server.authenticate` with their tenant's token before querying any address.
+    pub electrum_tenants: Vec<String>,
+
+    /// Tenants authenticated by a TLS client certificate, in the format
+    /// `<name>:<sha256 fingerprint>`. Clients presenting one of those certificates to the
+    /// Electrum TLS Server are bound to its tenant, without calling `server.authenticate`.
+    pub electrum_tenant_certs: Vec<String>,
+
     /// TLS private key path (defaults to `{data_dir}/tls/key.pem`).
     /// It must be PKCS#8-encoded. You can use `openssl` to generate it:
     ///
@@ -213,6 +328,13 @@ pub struct Config {
 
     /// Whether to allow fallback to v1 transport if v2 connection fails.
     pub allow_v1_fallback: bool,
+
+    /// An ASmap file, in Bitcoin Core's format
+    ///
+    /// If provided, we'll group addresses by the Autonomous System announcing them, instead of
+    /// their IP prefix, when deciding which peers to connect to.
+    pub asmap: Option<String>,
+
     /// Whether we should backfill
     ///
     /// If we assumeutreexo or use pow fraud proofs, you have the option to download and validate
@@ -220,6 +342,24 @@ pub struct Config {
     /// and won't affect the node's operation. You may notice that this will take a lot of CPU
     /// and bandwidth to run.
     pub backfill: bool,
+
+    /// Trusted accumulators to backfill from, in parallel
+    ///
+    /// Each of these values splits the backfill into one more range, validated at the same
+    /// time as the others. The final accumulator of each range is checked against the one
+    /// given here for the next range, so a wrong value only costs us time.
+    pub backfill_checkpoints: Vec<AssumeUtreexoValue>,
+
+    /// Utreexo snapshot files to backfill from, in parallel
+    ///
+    /// These are loaded like `utreexo_snapshot`, and used along with `backfill_checkpoints`.
+    pub backfill_checkpoint_snapshots: Vec<String>,
+
+    /// Whether we should prune the accumulator roots of old blocks
+    ///
+    /// We keep the roots of every block, in case we need to reorg back to it. If set, we only
+    /// keep the roots of recent blocks and a few old ones, see [RootsRetention].
+    pub prune_roots: bool,
 }
 
 impl Config {
@@ -232,7 +372,18 @@ impl Config {
             wallet_descriptor: None,
             config_file: None,
             proxy: None,
+            i2psam: None,
+            i2p_persistent: false,
+            private_broadcast: false,
+            max_upload_target: None,
+            tor_control: None,
+            tor_password: None,
+            onion_electrum: false,
+            onion_rpc: false,
             network,
+            signet_challenge: None,
+            signet_seeds: Vec::new(),
+            chain_spec: None,
             cfilters: false,
             filters_start_height: None,
             #[cfg(feature = "zmq-server")]
@@ -243,17 +394,28 @@ impl Config {
             log_to_stdout: false,
             log_to_file: false,
             assume_utreexo: false,
+            pow_fraud_proofs: false,
             debug: false,
             user_agent: String::new(),
             assumeutreexo_value: None,
+            utreexo_snapshot: None,
             electrum_address: None,
             enable_electrum_tls: false,
             electrum_address_tls: None,
+            electrum_limits: ElectrumLimits::default(),
+            electrum_public_hosts: Vec::new(),
+            electrum_seed_peers: Vec::new(),
+            electrum_tenants: Vec::new(),
+            electrum_tenant_certs: Vec::new(),
             generate_cert: false,
             tls_key_path: None,
             tls_cert_path: None,
             allow_v1_fallback: false,
+            asmap: None,
             backfill: false,
+            backfill_checkpoints: Vec::new(),
+            backfill_checkpoint_snapshots: Vec::new(),
+            prune_roots: false,
         }
     }
 }
@@ -368,11 +530,18 @@ impl Florestad {
         info!("Loading watch-only wallet");
         let wallet = self.setup_wallet()?;
 
+        let chain_params = self.chain_params()?;
+        let magic = chain_params.magic;
+        if chain_params.is_custom() {
+            info!("Running on a custom chain, magic={}", chain_params.magic);
+        }
+
         info!("Loading blockchain database");
         let blockchain_state = Arc::new(Self::load_chain_state(
             data_dir.clone(),
-            self.config.network,
+            chain_params.clone(),
             self.config.assume_valid,
+            self.config.prune_roots,
         )?);
 
         #[cfg(feature = "compact-filters")]
@@ -393,13 +562,24 @@ impl Florestad {
         #[cfg(not(feature = "compact-filters"))]
         let cfilters = None;
 
-        // If this network already allows pow fraud proofs, we should use it instead of assumeutreexo
-        let assume_utreexo = match self.config.assume_utreexo {
-            true => Some(ChainParams::get_assume_utreexo(self.config.network)),
+        // If we use pow fraud proofs, our peers give us the accumulator, so we shouldn't
+        // assume the hardcoded one
+        let assume_utreexo = match self.config.assume_utreexo && !self.config.pow_fraud_proofs {
+            true => chain_params.assume_utreexo.clone(),
 
             _ => None,
         };
 
+        let snapshot = match &self.config.utreexo_snapshot {
+            Some(path) => Some(Self::load_utreexo_snapshot(path, magic)?),
+            None => None,
+        };
+
+        let mut backfill_checkpoints = self.config.backfill_checkpoints.clone();
+        for path in &self.config.backfill_checkpoint_snapshots {
+            backfill_checkpoints.push(Self::load_utreexo_snapshot(path, magic)?);
+        }
+
         let proxy = self
             .config
             .proxy
@@ -407,24 +587,65 @@ impl Florestad {
             .map(|addr| Self::resolve_hostname(addr, 9050))
             .transpose()?;
 
+        let i2p_sam = self
+            .config
+            .i2psam
+            .as_ref()
+            .map(|addr| Self::resolve_hostname(addr, DEFAULT_SAM_PORT))
+            .transpose()?;
+
+        // Peers reach us through our onion service, which forwards them to this local port
+        let p2p_port = chain_params.port;
+        let listen = self
+            .config
+            .tor_control
+            .as_ref()
+            .map(|_| SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+
         let config = UtreexoNodeConfig {
             disable_dns_seeds: self.config.disable_dns_seeds,
             network: self.config.network,
-            pow_fraud_proofs: false,
+            chain_params: Some(chain_params),
+            pow_fraud_proofs: self.config.pow_fraud_proofs,
             proxy,
+            i2p_sam,
+            i2p_persistent: self.config.i2p_persistent,
+            private_broadcast: self.config.private_broadcast,
+            max_upload_target: self
+                .config
+                .max_upload_target
+                .map(|mib| mib.saturating_mul(1024 * 1024)),
             datadir: data_dir.clone(),
             fixed_peer: self.config.connect.clone(),
             compact_filters: self.config.cfilters,
-            assume_utreexo: self.config.assumeutreexo_value.clone().or(assume_utreexo),
+            assume_utreexo: snapshot
+                .or(self.config.assumeutreexo_value.clone())
+                .or(assume_utreexo),
             backfill: self.config.backfill,
+            backfill_checkpoints,
             filter_start_height: self.config.filters_start_height,
             user_agent: self.config.user_agent.clone(),
             allow_v1_fallback: self.config.allow_v1_fallback,
+            listen,
             ..Default::default()
         };
 
         let kill_signal = self.stop_signal.clone();
 
+        let mut reachable_networks = SUPPORTED_NETWORKS.to_vec();
+        if i2p_sam.is_some() {
+            reachable_networks.push(ReachableNetworks::I2P);
+        }
+
+        let mut address_man = AddressMan::new(None, &reachable_networks);
+        if let Some(path) = &self.config.asmap {
+            let asmap = AsMap::from_file(path)
+                .map_err(|e| FlorestadError::CouldNotLoadAsmap(path.clone(), e))?;
+
+            info!("Using asmap from {path}");
+            address_man.set_asmap(asmap);
+        }
+
         // Chain Provider (p2p)
         let chain_provider = UtreexoNode::<_, RunningNode>::new(
             config,
@@ -434,7 +655,7 @@ impl Florestad {
             ))),
             cfilters.clone(),
             kill_signal.clone(),
-            AddressMan::new(None, SUPPORTED_NETWORKS),
+            address_man,
         )
         .map_err(|e| FlorestadError::CouldNotCreateChainProvider(format!("{e}")))?;
 
@@ -458,22 +679,50 @@ impl Florestad {
         info!("Starting server");
         let wallet = Arc::new(wallet);
 
+        // Rescans are shared by the RPC and Electrum servers, and resumed if we got shut down
+        // in the middle of one
+        let rescans = RescanManager::new(Some(data_dir));
+
+        // The services we'll publish in our onion service, if any. Our P2P port always is.
+        let mut onion_ports = Vec::new();
+        if let Some(target) = chain_provider.listen_address() {
+            onion_ports.push(OnionPort {
+                virtual_port: p2p_port,
+                target,
+            });
+        }
+
         // JSON-RPC
         #[cfg(feature = "json-rpc")]
         {
+            let json_rpc_address = self
+                .config
+                .json_rpc_address
+                .as_ref()
+                .map(|x| Self::resolve_hostname(x, 8332))
+                .transpose()?
+                .unwrap_or_else(|| {
+                    let port = json_rpc::server::RpcImpl::<Arc<ChainState<ChainStore>>>::get_port(
+                        &self.config.network,
+                    );
+                    SocketAddr::from(([127, 0, 0, 1], port))
+                });
+
+            if self.config.onion_rpc {
+                onion_ports.push(Self::onion_port(json_rpc_address));
+            }
+
             let server = tokio::spawn(json_rpc::server::RpcImpl::create(
                 blockchain_state.clone(),
                 wallet.clone(),
                 chain_provider.get_handle(),
                 self.stop_signal.clone(),
                 self.config.network,
+                magic,
                 cfilters.clone(),
-                self.config
-                    .json_rpc_address
-                    .as_ref()
-                    .map(|x| Self::resolve_hostname(x, 8332))
-                    .transpose()?,
+                Some(json_rpc_address),
                 format!("{data_dir}/debug.log"),
+                rescans.clone(),
             ));
 
             if self.json_rpc.set(server).is_err() {
@@ -482,6 +731,22 @@ impl Florestad {
         }
 
         // Electrum Server configuration.
+        let parse_hosts = |hosts: &[String]| {
+            hosts
+                .iter()
+                .map(|host| ServerHost::parse(host))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(FlorestadError::InvalidElectrumHost)
+        };
+
+        let peer_discovery = PeerDiscoveryConfig {
+            public_hosts: parse_hosts(&self.config.electrum_public_hosts)?,
+            seed_peers: parse_hosts(&self.config.electrum_seed_peers)?,
+            datadir: Some(data_dir.clone()),
+        };
+
+        let tenants = self.get_electrum_tenants()?;
+        let tenant_wallets = Self::open_tenant_wallets(&tenants, data_dir)?;
 
         // Instantiate the Electrum Server.
         let electrum_server = ElectrumServer::new(
@@ -490,7 +755,11 @@ impl Florestad {
             cfilters,
             chain_provider.get_handle(),
         )
-        .map_err(FlorestadError::CouldNotCreateElectrumServer)?;
+        .map_err(FlorestadError::CouldNotCreateElectrumServer)?
+        .with_limits(self.config.electrum_limits.clone())
+        .with_peer_discovery(peer_discovery)
+        .with_tenants(tenants.clone(), tenant_wallets)
+        .with_rescan_manager(rescans);
 
         // Default Electrum Server port.
         let default_electrum_port: u16 =
@@ -518,9 +787,15 @@ impl Florestad {
             non_tls_listener,
             electrum_server.get_notifier(),
             None,
+            electrum_server.get_limits(),
+            electrum_server.get_connection_gate(),
         ));
         info!("Electrum Server is running at {electrum_addr}");
 
+        if self.config.onion_electrum {
+            onion_ports.push(Self::onion_port(electrum_addr));
+        }
+
         // with-TLS Electrum listener.
         if self.config.enable_electrum_tls {
             // Default Electrum TLS port.
@@ -569,7 +844,7 @@ impl Florestad {
             }
 
             // Assemble TLS configuration from file.
-            let tls_config = self.create_tls_config(data_dir)?;
+            let tls_config = self.create_tls_config(data_dir, &tenants)?;
 
             // Electrum TLS accept loop.
             let tls_listener = TcpListener::bind(electrum_addr_tls)
@@ -583,13 +858,34 @@ impl Florestad {
                 tls_listener,
                 electrum_server.get_notifier(),
                 Some(tls_acceptor),
+                electrum_server.get_limits(),
+                electrum_server.get_connection_gate(),
             ));
             info!("Electrum TLS Server is running at {electrum_addr_tls}");
+
+            if self.config.onion_electrum {
+                onion_ports.push(Self::onion_port(electrum_addr_tls));
+            }
         }
 
         // Electrum Server's main loop.
         task::spawn(electrum_server.main_loop());
 
+        // Onion service
+        if let Some(tor_control) = &self.config.tor_control {
+            let tor_control = Self::resolve_hostname(tor_control, DEFAULT_TOR_CONTROL_PORT)?;
+
+            task::spawn(Self::keep_onion_service(
+                tor_control,
+                self.config.tor_password.clone(),
+                onion_key_file(data_dir),
+                onion_ports,
+                chain_provider.get_handle(),
+                p2p_port,
+                self.stop_signal.clone(),
+            ));
+        }
+
         // Chain provider
         let (sender, receiver) = tokio::sync::oneshot::channel();
 
@@ -699,14 +995,90 @@ impl Florestad {
 
     fn load_chain_state(
         data_dir: String,
-        network: Network,
+        chain_params: ChainParams,
         assume_valid: AssumeValidArg,
+        prune_roots: bool,
     ) -> Result<ChainState<ChainStore>, FlorestadError> {
-        let config = FlatChainStoreConfig::new(data_dir + "/chaindata");
-        let store = ChainStore::new(config)
-            .map_err(|e| FlorestadError::CouldNotLoadFlatChainStore(e.into()))?;
-        ChainState::open(store, network, assume_valid)
-            .map_err(FlorestadError::CouldNotLoadFlatChainStore)
+        let path = data_dir + "/chaindata";
+
+        #[cfg(not(feature = "kv-chainstore"))]
+        let store = {
+            // The kv chainstore keeps its data in sled's `db` file
+            if Path::new(&path).join("db").exists() {
+                return Err(FlorestadError::ChainStoreBackendMismatch("kv"));
+            }
+
+            let mut config = FlatChainStoreConfig::new(path);
+            if prune_roots {
+                config.roots_retention = Some(RootsRetention::default());
+            }
+
+            ChainStore::new(config)
+        };
+
+        #[cfg(feature = "kv-chainstore")]
+        let store = {
+            if Path::new(&path).join("metadata.bin").exists() {
+                return Err(FlorestadError::ChainStoreBackendMismatch("flat"));
+            }
+
+            if prune_roots {
+                warn!("The kv chainstore can't prune accumulator roots, keeping all of them");
+            }
+
+            ChainStore::new(path)
+        };
+
+        let store = store.map_err(|e| FlorestadError::CouldNotLoadChainStore(e.into()))?;
+        ChainState::open_with_params(store, chain_params, assume_valid)
+            .map_err(FlorestadError::CouldNotLoadChainStore)
+    }
+
+    /// Reads a Utreexo snapshot, made with the `dumputreexostate` RPC, for the chain with this
+    /// magic.
+    fn load_utreexo_snapshot(
+        path: &str,
+        magic: Magic,
+    ) -> Result<AssumeUtreexoValue, FlorestadError> {
+        let data = fs::read(path)?;
+        let value = AssumeUtreexoValue::from_snapshot(&data, magic)
+            .map_err(|e| FlorestadError::InvalidUtreexoSnapshot(path.to_string(), e))?;
+
+        info!(
+            "Loaded Utreexo snapshot at height={} block={}",
+            value.height, value.block_hash
+        );
+        Ok(value)
+    }
+
+    /// Returns the parameters for the chain we are in.
+    ///
+    /// We start from our chain spec file, if any. A custom signet challenge (and its seeds) may
+    /// come from our config or from the config file, the former taking precedence.
+    fn chain_params(&self) -> Result<ChainParams, FlorestadError> {
+        let spec = match &self.config.chain_spec {
+            Some(path) => ChainSpec::from_file(path)?,
+            None => ChainSpec::default(),
+        };
+
+        let signet = self.get_config_file().signet.unwrap_or_default();
+        let Some(challenge) = self.config.signet_challenge.clone().or(signet.challenge) else {
+            return spec.chain_params(self.config.network);
+        };
+
+        if self.config.network != Network::Signet {
+            return Err(FlorestadError::SignetChallengeWithoutSignet(
+                self.config.network,
+            ));
+        }
+
+        let seeds = match self.config.signet_seeds.is_empty() {
+            true => signet.seeds.unwrap_or_default(),
+            false => self.config.signet_seeds.clone(),
+        };
+
+        spec.with_signet(challenge, seeds)
+            .chain_params(self.config.network)
     }
 
     /// Setup the wallet by initializing the database and adding descriptors, xpubs, and addresses.
@@ -750,6 +1122,48 @@ impl Florestad {
         Ok(wallet)
     }
 
+    /// Get the Electrum Server tenants from our config
+    fn get_electrum_tenants(&self) -> Result<TenantConfig, FlorestadError> {
+        let mut tenants = TenantConfig::default();
+
+        for credential in &self.config.electrum_tenants {
+            tenants
+                .parse_token(credential)
+                .map_err(FlorestadError::InvalidElectrumTenant)?;
+        }
+
+        for credential in &self.config.electrum_tenant_certs {
+            tenants
+                .parse_certificate(credential)
+                .map_err(FlorestadError::InvalidElectrumTenant)?;
+        }
+
+        Ok(tenants)
+    }
+
+    /// Opens the wallet of each Electrum Server tenant, creating them if needed
+    fn open_tenant_wallets(
+        tenants: &TenantConfig,
+        data_dir: &str,
+    ) -> Result<HashMap<String, Arc<AddressCache<KvDatabase>>>, FlorestadError> {
+        let mut wallets = HashMap::new();
+
+        for tenant in tenants.tenants() {
+            let database = KvDatabase::new(format!("{data_dir}/{TENANTS_DIR_NAME}/{tenant}"))
+                .map_err(FlorestadError::CouldNotOpenKvDatabase)?;
+
+            let wallet = AddressCache::new(database);
+            wallet
+                .setup()
+                .map_err(FlorestadError::CouldNotInitializeWallet)?;
+
+            info!("Loaded wallet for Electrum tenant {tenant}");
+            wallets.insert(tenant, Arc::new(wallet));
+        }
+
+        Ok(wallets)
+    }
+
     /// Get the wallet descriptors from the config file
     fn get_descriptors(&self) -> Vec<String> {
         self.config
@@ -792,6 +1206,55 @@ impl Florestad {
             .collect()
     }
 
+    /// Where Tor should forward connections to a service listening on `address`
+    fn onion_port(address: SocketAddr) -> OnionPort {
+        let mut target = address;
+        if target.ip().is_unspecified() {
+            target.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+
+        OnionPort {
+            virtual_port: address.port(),
+            target,
+        }
+    }
+
+    /// Publishes our onion service, and publishes it again if we lose our connection to Tor.
+    /// Once it's up, our node announces it to other peers, so they can connect to us on
+    /// `p2p_port`.
+    async fn keep_onion_service(
+        tor_control: SocketAddr,
+        password: Option<String>,
+        key_file: PathBuf,
+        ports: Vec<OnionPort>,
+        node: NodeInterface,
+        p2p_port: u16,
+        stop_signal: Arc<RwLock<bool>>,
+    ) {
+        loop {
+            match OnionService::publish(tor_control, password.as_deref(), &key_file, &ports).await {
+                Ok(service) => {
+                    info!("Our onion service is available at {}", service.hostname());
+                    if let Err(e) = node.advertise_address(service.address(), p2p_port).await {
+                        error!("Could not announce our onion service: {e}");
+                    }
+
+                    service.closed().await;
+                    warn!("Lost our connection to Tor, our onion service is down");
+                }
+                Err(e) => error!("Could not publish our onion service: {e}"),
+            }
+
+            time::sleep(Duration::from_secs(30)).await;
+            if *stop_signal.read().await {
+                break;
+            }
+        }
+    }
+
     /// Get the default Electrum port for the Network and TLS combination.
     ///
     /// Bitcoin  => 50001 (50002 TLS)
@@ -844,7 +1307,11 @@ impl Florestad {
     }
 
     /// Create the TLS configuration from a PKCS#8 private key and certificate.
-    fn create_tls_config(&self, data_dir: &str) -> Result<Arc<ServerConfig>, FlorestadError> {
+    fn create_tls_config(
+        &self,
+        data_dir: &str,
+        tenants: &TenantConfig,
+    ) -> Result<Arc<ServerConfig>, FlorestadError> {
         // Use an agnostic way to build paths for platforms and fix the differences
         // in how Unix and Windows represent strings, maybe a user could use a weird
         // string on his/her path.
@@ -878,9 +1345,16 @@ impl Florestad {
         let tls_key =
             PrivateKeyDer::from_pem_file(tls_key_path).map_err(FlorestadError::InvalidPrivKey)?;
 
+        // Ask clients for a certificate only if some tenant authenticates with one.
+        let tls_config = if tenants.has_certificates() {
+            ServerConfig::builder()
+                .with_client_cert_verifier(PinnedClientCertVerifier::new(tenants))
+        } else {
+            ServerConfig::builder().with_no_client_auth()
+        };
+
         // Assemble the TLS configuration.
-        let tls_config = ServerConfig::builder()
-            .with_no_client_auth()
+        let tls_config = tls_config
             .with_single_cert(vec![tls_cert_chain], tls_key)
             .map_err(FlorestadError::CouldNotConfigureTLS)?;
 
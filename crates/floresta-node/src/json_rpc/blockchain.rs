@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use std::fs;
+
 use bitcoin::block::Header;
 use bitcoin::consensus::encode::serialize_hex;
 use bitcoin::consensus::Encodable;
@@ -19,6 +21,7 @@ use corepc_types::v30::GetBlockVerboseOne;
 use corepc_types::ScriptPubkey;
 use floresta_chain::extensions::HeaderExt;
 use floresta_chain::extensions::WorkExt;
+use floresta_chain::AssumeUtreexoValue;
 use miniscript::descriptor::checksum;
 use serde_json::json;
 use serde_json::Value;
@@ -27,6 +30,9 @@ use tracing::debug;
 use super::res::GetBlockchainInfoRes;
 use super::res::GetTxOutProof;
 use super::res::JsonRpcError;
+use super::res::PowFraudProofsRes;
+use super::res::TipCheckRes;
+use super::res::UtreexoStateRes;
 use super::server::RpcChain;
 use super::server::RpcImpl;
 use crate::json_rpc::res::GetBlockRes;
@@ -243,7 +249,7 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
     }
 
     // getblockchaininfo
-    pub(super) fn get_blockchain_info(&self) -> Result<GetBlockchainInfoRes, JsonRpcError> {
+    pub(super) async fn get_blockchain_info(&self) -> Result<GetBlockchainInfoRes, JsonRpcError> {
         let (height, hash) = self.chain.get_best_block().unwrap();
         let validated = self.chain.get_validation_index().unwrap();
         let ibd = self.chain.is_in_ibd();
@@ -270,6 +276,26 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             0.0
         };
 
+        let fraud_proofs = self
+            .node
+            .get_fraud_proof_status()
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+        let checks = fraud_proofs
+            .checks
+            .into_iter()
+            .map(|check| TipCheckRes {
+                tip: check.tip.to_string(),
+                best_block: check.best_block.to_string(),
+                fork_point: check.fork_point.map(|hash| hash.to_string()),
+                fork_height: check.fork_height,
+                outcome: check.outcome.to_string(),
+                error: check.error,
+                time: check.time,
+            })
+            .collect();
+
         Ok(GetBlockchainInfoRes {
             best_block: hash.to_string(),
             height,
@@ -283,6 +309,10 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             chain: self.network.to_string(),
             difficulty: latest_header.difficulty(self.chain.get_params()) as u64,
             progress: validated_percentage,
+            pow_fraud_proofs: PowFraudProofsRes {
+                enabled: fraud_proofs.enabled,
+                checks,
+            },
         })
     }
 
@@ -658,6 +688,66 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         Ok(hashes.iter().map(|h| h.to_string()).collect())
     }
 
+    /// Writes our accumulator, at our validation index, to a snapshot file
+    pub(super) fn dump_utreexo_state(&self, path: &str) -> Result<UtreexoStateRes, JsonRpcError> {
+        // We may connect a block while reading our state, so we make sure our validation index
+        // didn't change while we got the accumulator
+        let (height, acc) = loop {
+            let height = self
+                .chain
+                .get_validation_index()
+                .map_err(|_| JsonRpcError::Chain)?;
+            let acc = self.chain.acc();
+
+            if self.chain.get_validation_index().ok() == Some(height) {
+                break (height, acc);
+            }
+        };
+
+        let value = AssumeUtreexoValue {
+            block_hash: self
+                .chain
+                .get_block_hash(height)
+                .map_err(|_| JsonRpcError::Chain)?,
+            height,
+            roots: acc.roots,
+            leaves: acc.leaves,
+        };
+
+        fs::write(path, value.to_snapshot(self.magic))
+            .map_err(|e| JsonRpcError::Snapshot(e.to_string()))?;
+
+        Ok(Self::utreexo_state_res(value, path))
+    }
+
+    /// Reads a snapshot file, and starts from its accumulator
+    pub(super) async fn load_utreexo_state(
+        &self,
+        path: &str,
+    ) -> Result<UtreexoStateRes, JsonRpcError> {
+        let data = fs::read(path).map_err(|e| JsonRpcError::Snapshot(e.to_string()))?;
+        let value = AssumeUtreexoValue::from_snapshot(&data, self.magic)
+            .map_err(|e| JsonRpcError::Snapshot(e.to_string()))?;
+
+        self.node
+            .load_utreexo_state(value.clone())
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?
+            .map_err(|e| JsonRpcError::Snapshot(e.to_string()))?;
+
+        Ok(Self::utreexo_state_res(value, path))
+    }
+
+    fn utreexo_state_res(value: AssumeUtreexoValue, path: &str) -> UtreexoStateRes {
+        UtreexoStateRes {
+            block_hash: value.block_hash.to_string(),
+            height: value.height,
+            leaves: value.leaves,
+            roots: value.roots.iter().map(|root| root.to_string()).collect(),
+            path: path.to_string(),
+        }
+    }
+
     pub(super) fn list_descriptors(&self) -> Result<Vec<String>, JsonRpcError> {
         let descriptors = self
             .wallet
@@ -4,18 +4,27 @@
 
 use core::net::IpAddr;
 use core::net::SocketAddr;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use bitcoin::Network;
+use floresta_wire::ban_man::Subnet;
 use floresta_wire::node_interface::PeerInfo;
 use serde_json::json;
 use serde_json::Value;
 
+use super::res::BannedInfo;
+use super::res::GetNetTotalsRes;
 use super::res::JsonRpcError;
+use super::res::UploadTargetRes;
 use super::server::RpcChain;
 use super::server::RpcImpl;
 
 type Result<T> = std::result::Result<T, JsonRpcError>;
 
+/// How long a `setban add` lasts if no `bantime` is given, same as Bitcoin Core's default
+const DEFAULT_BAN_TIME: u64 = 60 * 60 * 24;
+
 impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
     pub(crate) async fn ping(&self) -> Result<bool> {
         self.node
@@ -112,6 +121,121 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         Ok(json!(null))
     }
 
+    pub(crate) async fn set_ban(
+        &self,
+        subnet: String,
+        command: String,
+        bantime: u64,
+        absolute: bool,
+    ) -> Result<Value> {
+        let subnet = subnet
+            .parse::<Subnet>()
+            .map_err(|_| JsonRpcError::InvalidSubnet)?;
+
+        match command.as_str() {
+            "add" => {
+                let now = Self::now();
+                let until = match (absolute, bantime) {
+                    (true, until) if until <= now => return Err(JsonRpcError::InvalidBanTime),
+                    (true, until) => until,
+                    (false, 0) => now + DEFAULT_BAN_TIME,
+                    (false, bantime) => now.saturating_add(bantime),
+                };
+
+                let banned = self
+                    .node
+                    .ban_subnet(subnet, until)
+                    .await
+                    .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+                if !banned {
+                    return Err(JsonRpcError::AlreadyBanned);
+                }
+            }
+            "remove" => {
+                let unbanned = self
+                    .node
+                    .unban_subnet(subnet)
+                    .await
+                    .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+                if !unbanned {
+                    return Err(JsonRpcError::NotBanned);
+                }
+            }
+            _ => return Err(JsonRpcError::InvalidSetbanCommand),
+        }
+
+        Ok(json!(null))
+    }
+
+    pub(crate) async fn list_banned(&self) -> Result<Vec<BannedInfo>> {
+        let bans = self
+            .node
+            .list_banned()
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+        let now = Self::now();
+        let bans = bans
+            .into_iter()
+            .map(|ban| BannedInfo {
+                address: ban.subnet.to_string(),
+                ban_created: ban.created,
+                banned_until: ban.until,
+                ban_duration: ban.until.saturating_sub(ban.created),
+                time_remaining: ban.until.saturating_sub(now),
+                ban_reason: ban.reason,
+            })
+            .collect();
+
+        Ok(bans)
+    }
+
+    pub(crate) async fn clear_banned(&self) -> Result<Value> {
+        self.node
+            .clear_banned()
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+        Ok(json!(null))
+    }
+
+    pub(crate) async fn get_net_totals(&self) -> Result<GetNetTotalsRes> {
+        let totals = self
+            .node
+            .get_net_totals()
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?;
+
+        let timemillis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+
+        let target = totals.upload_target;
+        Ok(GetNetTotalsRes {
+            totalbytesrecv: totals.total_bytes_recv,
+            totalbytessent: totals.total_bytes_sent,
+            timemillis,
+            uploadtarget: UploadTargetRes {
+                timeframe: target.timeframe,
+                target: target.target,
+                target_reached: target.target_reached,
+                serve_historical_blocks: target.serve_historical_blocks,
+                bytes_left_in_cycle: target.bytes_left_in_cycle,
+                time_left_in_cycle: target.time_left_in_cycle,
+            },
+        })
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
     pub(crate) async fn get_peer_info(&self) -> Result<Vec<PeerInfo>> {
         self.node
             .get_peer_info()
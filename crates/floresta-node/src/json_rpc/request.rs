@@ -137,6 +137,33 @@ pub mod arg_parser {
             .collect()
     }
 
+    /// Extracts an array of strings from the request parameters at the specified index.
+    ///
+    /// This function checks if the parameter exists and is an array of strings. Returns an error
+    /// otherwise.
+    pub fn get_string_array(
+        params: &[Value],
+        index: usize,
+        opt_name: &str,
+    ) -> Result<Vec<String>, JsonRpcError> {
+        let v = params
+            .get(index)
+            .ok_or_else(|| JsonRpcError::MissingParameter(opt_name.to_string()))?;
+
+        let array = v.as_array().ok_or_else(|| {
+            JsonRpcError::InvalidParameterType(format!("{opt_name} must be an array of strings"))
+        })?;
+
+        array
+            .iter()
+            .map(|v| {
+                v.as_str().map(str::to_string).ok_or_else(|| {
+                    JsonRpcError::InvalidParameterType(format!("{opt_name} must be a string"))
+                })
+            })
+            .collect()
+    }
+
     /// Extracts an optional field from the request parameters at the specified index.
     ///
     /// This function checks if the parameter exists and is of the expected type. If the parameter
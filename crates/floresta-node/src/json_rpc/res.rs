@@ -4,6 +4,7 @@ use core::fmt;
 use core::fmt::Debug;
 use core::fmt::Display;
 use core::fmt::Formatter;
+use std::collections::BTreeMap;
 
 use axum::response::IntoResponse;
 use corepc_types::v30::GetBlockVerboseOne;
@@ -28,6 +29,50 @@ pub struct GetBlockchainInfoRes {
     pub chain: String,
     pub progress: f32,
     pub difficulty: u64,
+    pub pow_fraud_proofs: PowFraudProofsRes,
+}
+
+/// Whether we use PoW fraud proofs, in a `getblockchaininfo` call
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PowFraudProofsRes {
+    /// Whether PoW fraud proofs are enabled
+    pub enabled: bool,
+    /// The tips that disagreed with our best chain, oldest first
+    pub checks: Vec<TipCheckRes>,
+}
+
+/// A tip we've checked with PoW fraud proofs, and what we concluded
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TipCheckRes {
+    /// The disputed tip
+    pub tip: String,
+    /// Our best block when we checked it
+    pub best_block: String,
+    /// The last block in both chains, if we got to find it
+    pub fork_point: Option<String>,
+    /// The height of the fork point
+    pub fork_height: Option<u32>,
+    /// Either `our_chain_invalid`, `tip_rejected` or `failed`
+    pub outcome: String,
+    /// Why the check failed, if it did
+    pub error: Option<String>,
+    /// When we finished this check, as a UNIX timestamp
+    pub time: u64,
+}
+
+/// A Utreexo accumulator we've written to, or read from, a snapshot file
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UtreexoStateRes {
+    /// The block this accumulator is for
+    pub block_hash: String,
+    /// The height of that block
+    pub height: u32,
+    /// How many leaves were ever added to the accumulator
+    pub leaves: u64,
+    /// The accumulator roots
+    pub roots: Vec<String>,
+    /// The snapshot file
+    pub path: String,
 }
 
 /// A confidence enum to auxiliate rescan timestamp values.
@@ -144,6 +189,79 @@ pub struct RpcError {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetTxOutProof(pub Vec<u8>);
 
+/// Return type for the `listbanned` rpc command, one entry per banned subnet.
+///
+/// Mirrors Bitcoin Core's fields, plus the reason for each ban.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BannedInfo {
+    /// The banned subnet, like `1.2.3.4/32`
+    pub address: String,
+    /// When this ban was created, as a UNIX timestamp
+    pub ban_created: u64,
+    /// When this ban expires, as a UNIX timestamp
+    pub banned_until: u64,
+    /// How long this ban lasts, in seconds
+    pub ban_duration: u64,
+    /// How many seconds are left until this ban expires
+    pub time_remaining: u64,
+    /// Why this subnet was banned
+    pub ban_reason: String,
+}
+
+/// Return type for the `submitpackage` rpc command.
+///
+/// Mirrors Bitcoin Core's fields, except for the fees, since we don't know them for
+/// transactions submitted by our user.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SubmitPackageRes {
+    /// `success` if the whole package was accepted
+    pub package_msg: String,
+    /// The result for each transaction in the package, indexed by wtxid
+    #[serde(rename = "tx-results")]
+    pub tx_results: BTreeMap<String, SubmitPackageTxResult>,
+}
+
+/// The result for one transaction in a `submitpackage` call
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SubmitPackageTxResult {
+    /// The transaction's txid
+    pub txid: String,
+    /// The transaction's virtual size
+    pub vsize: u64,
+}
+
+/// Return type for the `getnettotals` rpc command.
+///
+/// Mirrors Bitcoin Core's fields.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetNetTotalsRes {
+    /// How many bytes we received from our peers since we started
+    pub totalbytesrecv: u64,
+    /// How many bytes we sent to our peers since we started
+    pub totalbytessent: u64,
+    /// The current UNIX time, in milliseconds
+    pub timemillis: u64,
+    /// The state of our upload target
+    pub uploadtarget: UploadTargetRes,
+}
+
+/// The state of our upload target, in a `getnettotals` call
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UploadTargetRes {
+    /// How long each cycle lasts, in seconds
+    pub timeframe: u64,
+    /// How many bytes we may send in each cycle, or zero if there's no target
+    pub target: u64,
+    /// Whether we sent all bytes allowed in this cycle
+    pub target_reached: bool,
+    /// Whether we still serve bandwidth-heavy requests in this cycle
+    pub serve_historical_blocks: bool,
+    /// How many bytes we may still send in this cycle
+    pub bytes_left_in_cycle: u64,
+    /// How many seconds until the next cycle starts
+    pub time_left_in_cycle: u64,
+}
+
 #[derive(Debug)]
 pub enum JsonRpcError {
     /// There was a rescan request but we do not have any addresses in the watch-only wallet.
@@ -221,11 +339,29 @@ pub enum JsonRpcError {
     /// Peer was not found in the peer list.
     PeerNotFound,
 
+    /// The `setban` command is neither `add` nor `remove`
+    InvalidSetbanCommand,
+
+    /// We couldn't parse an IP address or subnet
+    InvalidSubnet,
+
+    /// This subnet is already banned
+    AlreadyBanned,
+
+    /// We tried to unban a subnet that isn't banned
+    NotBanned,
+
+    /// The ban would expire in the past
+    InvalidBanTime,
+
     /// Raised if when the rescanblockchain command, with the timestamp flag activated, contains some timestamp thats less than the genesis one and not zero which is the default value for this arg.
     InvalidTimestamp,
 
     /// Something went wrong when attempting to publish a transaction to mempool
     MempoolAccept(MempoolError),
+
+    /// We couldn't write, read or load a Utreexo snapshot
+    Snapshot(String),
 }
 
 impl_error_from!(JsonRpcError, MempoolError, MempoolAccept);
@@ -259,7 +395,13 @@ impl Display for JsonRpcError {
             JsonRpcError::InvalidAddnodeCommand => write!(f, "Invalid addnode command"),
             JsonRpcError::InvalidDisconnectNodeCommand => write!(f, "Invalid disconnectnode command"),
             JsonRpcError::PeerNotFound => write!(f, "Peer not found in the peer list"),
+            JsonRpcError::InvalidSetbanCommand => write!(f, "Invalid setban command, should be add or remove"),
+            JsonRpcError::InvalidSubnet => write!(f, "Invalid IP/Subnet"),
+            JsonRpcError::AlreadyBanned => write!(f, "IP/Subnet already banned"),
+            JsonRpcError::NotBanned => write!(f, "Unban failed. Requested address/subnet was not previously manually banned"),
+            JsonRpcError::InvalidBanTime => write!(f, "Absolute timestamp is in the past"),
             JsonRpcError::MempoolAccept(e) => write!(f, "Could not send transaction to mempool due to {e}"),
+            JsonRpcError::Snapshot(e) => write!(f, "Utreexo snapshot error: {e}"),
         }
     }
 }
@@ -1,8 +1,10 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use core::net::SocketAddr;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 
 use axum::body::Body;
@@ -19,6 +21,7 @@ use bitcoin::consensus::encode::serialize_hex;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::Hash;
 use bitcoin::hex::DisplayHex;
+use bitcoin::p2p::Magic;
 use bitcoin::Address;
 use bitcoin::BlockHash;
 use bitcoin::Network;
@@ -34,6 +37,11 @@ use floresta_watch_only::kv_database::KvDatabase;
 use floresta_watch_only::AddressCache;
 use floresta_watch_only::CachedTransaction;
 use floresta_wire::node_interface::NodeInterface;
+use floresta_wire::rescan::RescanInfo;
+use floresta_wire::rescan::RescanJobId;
+use floresta_wire::rescan::RescanManager;
+use floresta_wire::rescan::RescanOutcome;
+use floresta_wire::rescan::RescanSource;
 use serde_json::json;
 use serde_json::Value;
 use tokio::sync::RwLock;
@@ -47,6 +55,8 @@ use super::res::RawTxJson;
 use super::res::RpcError;
 use super::res::ScriptPubKeyJson;
 use super::res::ScriptSigJson;
+use super::res::SubmitPackageRes;
+use super::res::SubmitPackageTxResult;
 use super::res::TxInJson;
 use super::res::TxOutJson;
 use crate::json_rpc::request::arg_parser::get_bool;
@@ -55,9 +65,13 @@ use crate::json_rpc::request::arg_parser::get_hashes_array;
 use crate::json_rpc::request::arg_parser::get_numeric;
 use crate::json_rpc::request::arg_parser::get_optional_field;
 use crate::json_rpc::request::arg_parser::get_string;
+use crate::json_rpc::request::arg_parser::get_string_array;
 use crate::json_rpc::request::RpcRequest;
 use crate::json_rpc::res::RescanConfidence;
 
+/// How often we try to resume rescans that got interrupted
+const RESCAN_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
 pub(super) struct InflightRpc {
     pub method: String,
     pub when: Instant,
@@ -74,6 +88,7 @@ impl<T> RpcChain for T where T: ThreadSafeChain + Clone {}
 pub struct RpcImpl<Blockchain: RpcChain> {
     pub(super) block_filter_storage: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
     pub(super) network: Network,
+    pub(super) magic: Magic,
     pub(super) chain: Blockchain,
     pub(super) wallet: Arc<AddressCache<KvDatabase>>,
     pub(super) node: NodeInterface,
@@ -81,6 +96,7 @@ pub struct RpcImpl<Blockchain: RpcChain> {
     pub(super) inflight: Arc<RwLock<HashMap<Value, InflightRpc>>>,
     pub(super) log_path: String,
     pub(super) start_time: Instant,
+    pub(super) rescans: RescanManager,
 }
 
 type Result<T> = std::result::Result<T, JsonRpcError>;
@@ -107,18 +123,11 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         debug!("Rescanning with block filters for addresses: {addresses:?}");
 
         let addresses = self.wallet.get_cached_addresses();
-        let wallet = self.wallet.clone();
         if self.block_filter_storage.is_none() {
             return Err(JsonRpcError::InInitialBlockDownload);
         };
 
-        let cfilters = self.block_filter_storage.as_ref().unwrap().clone();
-        let node = self.node.clone();
-        let chain = self.chain.clone();
-
-        tokio::task::spawn(Self::rescan_with_block_filters(
-            addresses, chain, wallet, cfilters, node, None, None,
-        ));
+        self.start_rescan(addresses, 0, None)?;
 
         Ok(true)
     }
@@ -149,28 +158,60 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             return Err(JsonRpcError::NoAddressesToRescan);
         }
 
-        let wallet = self.wallet.clone();
-
         if self.block_filter_storage.is_none() {
             return Err(JsonRpcError::NoBlockFilters);
         };
 
-        let cfilters = self.block_filter_storage.as_ref().unwrap().clone();
+        // A stop height of zero means "up to our tip"
+        self.start_rescan(
+            addresses,
+            start_height,
+            (stop_height != 0).then_some(stop_height),
+        )?;
 
-        let node = self.node.clone();
+        Ok(true)
+    }
 
-        let chain = self.chain.clone();
+    /// Creates a rescan job for `addresses`, from `start_height` up to `stop_height`, or
+    /// our tip if it's `None`, and runs it in the background
+    fn start_rescan(
+        &self,
+        addresses: Vec<ScriptBuf>,
+        start_height: u32,
+        stop_height: Option<u32>,
+    ) -> Result<()> {
+        let cfilters = self
+            .block_filter_storage
+            .clone()
+            .ok_or(JsonRpcError::NoBlockFilters)?;
+
+        let stop_height = match stop_height {
+            Some(height) => height,
+            None => self.chain.get_height().map_err(|_| JsonRpcError::Chain)?,
+        };
+
+        let id = self
+            .rescans
+            .start(RescanSource::Rpc, addresses, start_height, stop_height);
 
         tokio::task::spawn(Self::rescan_with_block_filters(
-            addresses,
-            chain,
-            wallet,
+            id,
+            self.rescans.clone(),
+            self.chain.clone(),
+            self.wallet.clone(),
             cfilters,
-            node,
-            (start_height != 0).then_some(start_height), // Its ugly but to maintain the API here its necessary to recast to a Option.
-            (stop_height != 0).then_some(stop_height),
+            self.node.clone(),
         ));
-        Ok(true)
+
+        Ok(())
+    }
+
+    fn get_rescan_info(&self) -> Result<Vec<RescanInfo>> {
+        Ok(self.rescans.info())
+    }
+
+    fn abort_rescan(&self) -> Result<bool> {
+        Ok(self.rescans.abort_all())
     }
 
     async fn send_raw_transaction(&self, tx: String) -> Result<Txid> {
@@ -184,6 +225,38 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             .await
             .map_err(|e| JsonRpcError::Node(e.to_string()))??)
     }
+
+    async fn submit_package(&self, package: Vec<String>) -> Result<SubmitPackageRes> {
+        let package = package
+            .iter()
+            .map(|tx| {
+                let tx_hex = Vec::from_hex(tx).map_err(|_| JsonRpcError::InvalidHex)?;
+                deserialize::<Transaction>(&tx_hex).map_err(|e| JsonRpcError::Decode(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let tx_results = package
+            .iter()
+            .map(|tx| {
+                let result = SubmitPackageTxResult {
+                    txid: tx.compute_txid().to_string(),
+                    vsize: tx.vsize() as u64,
+                };
+
+                (tx.compute_wtxid().to_string(), result)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        self.node
+            .submit_package(package)
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))??;
+
+        Ok(SubmitPackageRes {
+            package_msg: "success".to_string(),
+            tx_results,
+        })
+    }
 }
 
 async fn handle_json_rpc_request(
@@ -232,6 +305,7 @@ async fn handle_json_rpc_request(
 
         "getblockchaininfo" => state
             .get_blockchain_info()
+            .await
             .map(|v| serde_json::to_value(v).unwrap()),
 
         "getblockcount" => state
@@ -296,6 +370,21 @@ async fn handle_json_rpc_request(
 
         "getroots" => state.get_roots().map(|v| serde_json::to_value(v).unwrap()),
 
+        "dumputreexostate" => {
+            let path = get_string(&params, 0, "path")?;
+            state
+                .dump_utreexo_state(&path)
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
+        "loadutreexostate" => {
+            let path = get_string(&params, 0, "path")?;
+            state
+                .load_utreexo_state(&path)
+                .await
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
         "findtxout" => {
             let txid = get_hash(&params, 0, "txid")?;
             let vout = get_numeric(&params, 1, "vout")?;
@@ -364,6 +453,33 @@ async fn handle_json_rpc_request(
                 .map(|v| serde_json::to_value(v).unwrap())
         }
 
+        "setban" => {
+            let subnet = get_string(&params, 0, "subnet")?;
+            let command = get_string(&params, 1, "command")?;
+            let bantime = get_optional_field(&params, 2, "bantime", get_numeric)?.unwrap_or(0);
+            let absolute = get_optional_field(&params, 3, "absolute", get_bool)?.unwrap_or(false);
+
+            state
+                .set_ban(subnet, command, bantime, absolute)
+                .await
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
+        "listbanned" => state
+            .list_banned()
+            .await
+            .map(|v| serde_json::to_value(v).unwrap()),
+
+        "clearbanned" => state
+            .clear_banned()
+            .await
+            .map(|v| serde_json::to_value(v).unwrap()),
+
+        "getnettotals" => state
+            .get_net_totals()
+            .await
+            .map(|v| serde_json::to_value(v).unwrap()),
+
         "ping" => {
             state.ping().await?;
 
@@ -400,6 +516,14 @@ async fn handle_json_rpc_request(
                 .map(|v| serde_json::to_value(v).unwrap())
         }
 
+        "getrescaninfo" => state
+            .get_rescan_info()
+            .map(|v| serde_json::to_value(v).unwrap()),
+
+        "abortrescan" => state
+            .abort_rescan()
+            .map(|v| serde_json::to_value(v).unwrap()),
+
         "sendrawtransaction" => {
             let tx = get_string(&params, 0, "hex")?;
             state
@@ -408,6 +532,14 @@ async fn handle_json_rpc_request(
                 .map(|v| serde_json::to_value(v).unwrap())
         }
 
+        "submitpackage" => {
+            let package = get_string_array(&params, 0, "package")?;
+            state
+                .submit_package(package)
+                .await
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
         "listdescriptors" => state
             .list_descriptors()
             .map(|v| serde_json::to_value(v).unwrap()),
@@ -434,6 +566,11 @@ fn get_http_error_code(err: &JsonRpcError) -> u16 {
         | JsonRpcError::InvalidAddnodeCommand
         | JsonRpcError::InvalidDisconnectNodeCommand
         | JsonRpcError::PeerNotFound
+        | JsonRpcError::InvalidSetbanCommand
+        | JsonRpcError::InvalidSubnet
+        | JsonRpcError::AlreadyBanned
+        | JsonRpcError::NotBanned
+        | JsonRpcError::InvalidBanTime
         | JsonRpcError::InvalidTimestamp
         | JsonRpcError::InvalidRescanVal
         | JsonRpcError::NoAddressesToRescan
@@ -441,6 +578,7 @@ fn get_http_error_code(err: &JsonRpcError) -> u16 {
         | JsonRpcError::MissingParameter(_)
         | JsonRpcError::ChainWorkOverflow
         | JsonRpcError::MempoolAccept(_)
+        | JsonRpcError::Snapshot(_)
         | JsonRpcError::Wallet(_) => 400,
 
         // idunnolol
@@ -477,11 +615,17 @@ fn get_json_rpc_error_code(err: &JsonRpcError) -> i32 {
         | JsonRpcError::InvalidAddnodeCommand
         | JsonRpcError::InvalidDisconnectNodeCommand
         | JsonRpcError::PeerNotFound
+        | JsonRpcError::InvalidSetbanCommand
+        | JsonRpcError::InvalidSubnet
+        | JsonRpcError::AlreadyBanned
+        | JsonRpcError::NotBanned
+        | JsonRpcError::InvalidBanTime
         | JsonRpcError::InvalidRescanVal
         | JsonRpcError::NoAddressesToRescan
         | JsonRpcError::ChainWorkOverflow
         | JsonRpcError::Wallet(_)
-        | JsonRpcError::MempoolAccept(_) => -32600,
+        | JsonRpcError::MempoolAccept(_)
+        | JsonRpcError::Snapshot(_) => -32600,
 
         // server error
         JsonRpcError::InInitialBlockDownload
@@ -567,38 +711,51 @@ async fn cannot_get(_state: State<Arc<RpcImpl<impl RpcChain>>>) -> Json<serde_js
 }
 
 impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
+    /// Runs the rescan job `id`, giving every block that matches our filters to the wallet
     async fn rescan_with_block_filters(
-        addresses: Vec<ScriptBuf>,
+        id: RescanJobId,
+        rescans: RescanManager,
         chain: Blockchain,
         wallet: Arc<AddressCache<KvDatabase>>,
         cfilters: Arc<NetworkFilters<FlatFiltersStore>>,
         node: NodeInterface,
-        start_height: Option<u32>,
-        stop_height: Option<u32>,
-    ) -> Result<()> {
-        let blocks = cfilters
-            .match_any(
-                addresses.iter().map(|a| a.as_bytes()).collect(),
-                start_height,
-                stop_height,
-                chain.clone(),
-            )
-            .unwrap();
-
-        info!("rescan filter hits: {blocks:?}");
-
-        for block in blocks {
-            if let Ok(Some(block)) = node.get_block(block).await {
-                let height = chain
-                    .get_block_height(&block.block_hash())
-                    .unwrap()
-                    .unwrap();
+    ) -> RescanOutcome {
+        rescans
+            .run(id, &cfilters, &chain, &node, |block, height| {
+                wallet.block_process(block, height);
+            })
+            .await
+    }
 
-                wallet.block_process(&block, height);
+    /// Keeps resuming rescans that got interrupted, either because we couldn't download some
+    /// block, or because we were shut down in the middle of them
+    async fn resume_rescans(
+        rescans: RescanManager,
+        chain: Blockchain,
+        wallet: Arc<AddressCache<KvDatabase>>,
+        cfilters: Arc<NetworkFilters<FlatFiltersStore>>,
+        node: NodeInterface,
+    ) {
+        loop {
+            if !chain.is_in_ibd() {
+                let interrupted = rescans.take_interrupted(|source| *source == RescanSource::Rpc);
+
+                for (id, checkpoint) in interrupted {
+                    info!("Resuming rescan from height {}", checkpoint.next_height);
+                    Self::rescan_with_block_filters(
+                        id,
+                        rescans.clone(),
+                        chain.clone(),
+                        wallet.clone(),
+                        cfilters.clone(),
+                        node.clone(),
+                    )
+                    .await;
+                }
             }
-        }
 
-        Ok(())
+            tokio::time::sleep(RESCAN_RETRY_INTERVAL).await;
+        }
     }
 
     fn make_vin(&self, input: TxIn) -> TxInJson {
@@ -710,7 +867,7 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
 
     // TODO(@luisschwab): get rid of this once
     // https://github.com/rust-bitcoin/rust-bitcoin/pull/4639 makes it into a release.
-    fn get_port(net: &Network) -> u16 {
+    pub(crate) fn get_port(net: &Network) -> u16 {
         match net {
             Network::Bitcoin => 8332,
             Network::Signet => 38332,
@@ -727,9 +884,11 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         node: NodeInterface,
         kill_signal: Arc<RwLock<bool>>,
         network: Network,
+        magic: Magic,
         block_filter_storage: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
         address: Option<SocketAddr>,
         log_path: String,
+        rescans: RescanManager,
     ) {
         let address = address.unwrap_or_else(|| {
             format!("127.0.0.1:{}", Self::get_port(&network))
@@ -753,6 +912,16 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             }
         };
 
+        if let Some(cfilters) = &block_filter_storage {
+            tokio::task::spawn(Self::resume_rescans(
+                rescans.clone(),
+                chain.clone(),
+                wallet.clone(),
+                cfilters.clone(),
+                node.clone(),
+            ));
+        }
+
         let router = Router::new()
             .route("/", post(json_rpc_request).get(cannot_get))
             .layer(
@@ -766,10 +935,12 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
                 node,
                 kill_signal,
                 network,
+                magic,
                 block_filter_storage,
                 inflight: Arc::new(RwLock::new(HashMap::new())),
                 log_path,
                 start_time: Instant::now(),
+                rescans,
             }));
 
         axum::serve(listener, router)
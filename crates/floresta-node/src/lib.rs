@@ -7,6 +7,9 @@
     html_favicon_url = "https://raw.githubusercontent.com/getfloresta/floresta-media/master/logo_png/Icon-Green(main).png"
 )]
 
+mod chain_spec;
+#[cfg(feature = "kv-chainstore")]
+mod chainstore_migration;
 mod config_file;
 mod error;
 mod florestad;
@@ -15,7 +18,12 @@ mod json_rpc;
 #[cfg(feature = "zmq-server")]
 mod zmq;
 
+#[cfg(feature = "kv-chainstore")]
+pub use chainstore_migration::migrate_chainstore;
+#[cfg(feature = "kv-chainstore")]
+pub use chainstore_migration::ChainStoreBackend;
 pub use florestad::AssumeUtreexoValue;
 pub use florestad::AssumeValidArg;
 pub use florestad::Config;
+pub use florestad::ElectrumLimits;
 pub use florestad::Florestad;
@@ -80,12 +80,18 @@ pub trait FlorestaRPC {
     /// hexadecimal string. If the transaction is valid, it will be broadcast to the network, and
     /// return the transaction id. If the transaction is invalid, an error will be returned.
     fn send_raw_transaction(&self, tx: String) -> Result<Txid>;
+    #[doc = include_str!("../../../doc/rpc/submitpackage.md")]
+    fn submit_package(&self, package: Vec<String>) -> Result<SubmitPackageResult>;
     /// Gets the current accumulator for the chain we're on
     ///
     /// This method returns the current accumulator for the chain we're on. The accumulator is
     /// a set of roots, that let's us prove that a UTXO exists in the chain. This method returns
     /// a vector of hexadecimal strings, each of which is a root in the accumulator.
     fn get_roots(&self) -> Result<Vec<String>>;
+    #[doc = include_str!("../../../doc/rpc/dumputreexostate.md")]
+    fn dump_utreexo_state(&self, path: String) -> Result<UtreexoStateResult>;
+    #[doc = include_str!("../../../doc/rpc/loadutreexostate.md")]
+    fn load_utreexo_state(&self, path: String) -> Result<UtreexoStateResult>;
     /// Gets information about the peers we're connected with
     ///
     /// This method returns information about the peers we're connected with. This includes
@@ -122,6 +128,20 @@ pub trait FlorestaRPC {
     /// The peer can be referenced either by node_address or node_id.
     /// If referencing by node_id, an empty string must be passed as the node_address.
     fn disconnect_node(&self, node_address: String, node_id: Option<usize>) -> Result<Value>;
+    #[doc = include_str!("../../../doc/rpc/setban.md")]
+    fn set_ban(
+        &self,
+        subnet: String,
+        command: SetBanCommand,
+        bantime: Option<u64>,
+        absolute: bool,
+    ) -> Result<Value>;
+    #[doc = include_str!("../../../doc/rpc/listbanned.md")]
+    fn list_banned(&self) -> Result<Vec<BannedInfo>>;
+    #[doc = include_str!("../../../doc/rpc/clearbanned.md")]
+    fn clear_banned(&self) -> Result<Value>;
+    #[doc = include_str!("../../../doc/rpc/getnettotals.md")]
+    fn get_net_totals(&self) -> Result<GetNetTotalsResult>;
     /// Finds an specific utxo in the chain
     ///
     /// You can use this to look for a utxo. If it exists, it will return the amount and
@@ -144,6 +164,10 @@ pub trait FlorestaRPC {
     fn uptime(&self) -> Result<u32>;
     /// Returns a list of all descriptors currently loaded in the wallet
     fn list_descriptors(&self) -> Result<Vec<String>>;
+    #[doc = include_str!("../../../doc/rpc/getrescaninfo.md")]
+    fn get_rescan_info(&self) -> Result<Vec<RescanInfo>>;
+    #[doc = include_str!("../../../doc/rpc/abortrescan.md")]
+    fn abort_rescan(&self) -> Result<bool>;
     /// Sends a ping to all peers, checking if they are still alive
     fn ping(&self) -> Result<()>;
 }
@@ -215,6 +239,36 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
         }
     }
 
+    fn set_ban(
+        &self,
+        subnet: String,
+        command: SetBanCommand,
+        bantime: Option<u64>,
+        absolute: bool,
+    ) -> Result<Value> {
+        self.call(
+            "setban",
+            &[
+                Value::String(subnet),
+                Value::String(command.to_string()),
+                Value::Number(Number::from(bantime.unwrap_or(0))),
+                Value::Bool(absolute),
+            ],
+        )
+    }
+
+    fn list_banned(&self) -> Result<Vec<BannedInfo>> {
+        self.call("listbanned", &[])
+    }
+
+    fn clear_banned(&self) -> Result<Value> {
+        self.call("clearbanned", &[])
+    }
+
+    fn get_net_totals(&self) -> Result<GetNetTotalsResult> {
+        self.call("getnettotals", &[])
+    }
+
     fn stop(&self) -> Result<String> {
         self.call("stop", &[])
     }
@@ -245,6 +299,14 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
         self.call("getroots", &[])
     }
 
+    fn dump_utreexo_state(&self, path: String) -> Result<UtreexoStateResult> {
+        self.call("dumputreexostate", &[Value::String(path)])
+    }
+
+    fn load_utreexo_state(&self, path: String) -> Result<UtreexoStateResult> {
+        self.call("loadutreexostate", &[Value::String(path)])
+    }
+
     fn get_block(&self, hash: BlockHash, verbosity: Option<u32>) -> Result<GetBlockRes> {
         let verbosity = verbosity.unwrap_or(1);
 
@@ -335,10 +397,23 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
         self.call("sendrawtransaction", &[Value::String(tx)])
     }
 
+    fn submit_package(&self, package: Vec<String>) -> Result<SubmitPackageResult> {
+        let package = package.into_iter().map(Value::String).collect();
+        self.call("submitpackage", &[Value::Array(package)])
+    }
+
     fn list_descriptors(&self) -> Result<Vec<String>> {
         self.call("listdescriptors", &[])
     }
 
+    fn get_rescan_info(&self) -> Result<Vec<RescanInfo>> {
+        self.call("getrescaninfo", &[])
+    }
+
+    fn abort_rescan(&self) -> Result<bool> {
+        self.call("abortrescan", &[])
+    }
+
     fn ping(&self) -> Result<()> {
         self.call("ping", &[])
     }
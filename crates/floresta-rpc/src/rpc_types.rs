@@ -4,6 +4,7 @@ use core::error;
 use core::fmt;
 use core::fmt::Display;
 use core::fmt::Formatter;
+use std::collections::BTreeMap;
 
 use corepc_types::v30::GetBlockVerboseOne;
 use serde::Deserialize;
@@ -55,6 +56,42 @@ pub struct GetBlockchainInfoRes {
     /// On average, miners needs to make `difficulty` hashes before finding one that
     /// solves a block's PoW
     pub difficulty: u64,
+    /// Whether we use PoW fraud proofs, and the tips we've checked with them
+    pub pow_fraud_proofs: PowFraudProofsRes,
+}
+
+/// Whether we use PoW fraud proofs, in a `getblockchaininfo` call
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PowFraudProofsRes {
+    /// Whether PoW fraud proofs are enabled
+    pub enabled: bool,
+    /// The tips that disagreed with our best chain, oldest first
+    ///
+    /// We only remember the most recent checks, and keep them across restarts
+    pub checks: Vec<TipCheckRes>,
+}
+
+/// A tip we've checked with PoW fraud proofs, and what we concluded
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TipCheckRes {
+    /// The disputed tip
+    pub tip: String,
+    /// Our best block when we checked it
+    pub best_block: String,
+    /// The last block in both chains, if we got to find it
+    pub fork_point: Option<String>,
+    /// The height of the fork point
+    pub fork_height: Option<u32>,
+    /// What we've concluded
+    ///
+    /// `our_chain_invalid` means our chain had an invalid block after the fork, so we moved to
+    /// this tip. `tip_rejected` means our chain is valid, so we banned the peers announcing this
+    /// tip. `failed` means we couldn't finish the check, see `error`.
+    pub outcome: String,
+    /// Why the check failed, if it did
+    pub error: Option<String>,
+    /// When we finished this check, as a UNIX timestamp
+    pub time: u64,
 }
 
 /// The information returned by a get_raw_tx
@@ -178,7 +215,7 @@ pub struct PeerInfo {
     ///
     /// We can connect with peers for different reasons. E.g. we can connect to a peer to
     /// see if it has a block we're missing, or just to check if that address is still alive.
-    /// Possible values are: Feeler, Regular and Extra
+    /// Possible values are: feeler, regular, extra, manual and block-relay-only
     pub kind: String,
     /// The state of this peer
     ///
@@ -186,6 +223,14 @@ pub struct PeerInfo {
     pub state: String,
     /// The transport protocol used with peer.
     pub transport_protocol: String,
+    /// How many bytes we sent to this peer
+    pub bytes_sent: u64,
+    /// How many bytes we received from this peer
+    pub bytes_recv: u64,
+    /// How many bytes we sent to this peer, for each message command
+    pub bytes_sent_per_msg: BTreeMap<String, u64>,
+    /// How many bytes we received from this peer, for each message command
+    pub bytes_recv_per_msg: BTreeMap<String, u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -318,6 +363,141 @@ pub struct GetRpcInfoRes {
     pub logpath: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The progress of a rescan, as returned by `getrescaninfo`
+pub struct RescanInfo {
+    /// An identifier for this rescan, unique while the node is running
+    pub id: u64,
+
+    /// Who asked for this rescan, either `rpc` or `electrum`
+    pub source: String,
+
+    /// The Electrum tenant this rescan is for, if any
+    pub tenant: Option<String>,
+
+    /// Whether this rescan is running right now, interrupted ones are retried later
+    pub running: bool,
+
+    /// The height this rescan started at
+    pub start_height: u32,
+
+    /// The last height this rescan will look at
+    pub stop_height: u32,
+
+    /// The last height we went through
+    pub current_height: u32,
+
+    /// How many blocks matched our filters so far
+    pub matched_blocks: u32,
+
+    /// How much of the interval we went through, from 0 to 1
+    pub progress: f64,
+
+    /// How many seconds we expect this rescan to take, if it's running
+    pub eta: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The result of a `submitpackage` call
+pub struct SubmitPackageResult {
+    /// `success` if the whole package was accepted
+    pub package_msg: String,
+
+    /// The result for each transaction in the package, indexed by wtxid
+    #[serde(rename = "tx-results")]
+    pub tx_results: BTreeMap<String, SubmitPackageTxResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The result for one transaction in a `submitpackage` call
+pub struct SubmitPackageTxResult {
+    /// The transaction's txid
+    pub txid: String,
+
+    /// The transaction's virtual size
+    pub vsize: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A banned subnet, as returned by `listbanned`
+pub struct BannedInfo {
+    /// The banned subnet, like `1.2.3.4/32`
+    pub address: String,
+
+    /// When this ban was created, as a UNIX timestamp
+    pub ban_created: u64,
+
+    /// When this ban expires, as a UNIX timestamp
+    pub banned_until: u64,
+
+    /// How long this ban lasts, in seconds
+    pub ban_duration: u64,
+
+    /// How many seconds are left until this ban expires
+    pub time_remaining: u64,
+
+    /// Why this subnet was banned, e.g. the misbehaviour of a peer inside it
+    pub ban_reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How many bytes we exchanged with our peers, as returned by `getnettotals`
+pub struct GetNetTotalsResult {
+    /// How many bytes we received since we started
+    pub totalbytesrecv: u64,
+
+    /// How many bytes we sent since we started
+    pub totalbytessent: u64,
+
+    /// The current UNIX time, in milliseconds
+    pub timemillis: u64,
+
+    /// The state of our upload target
+    pub uploadtarget: UploadTarget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The state of a node's upload target
+pub struct UploadTarget {
+    /// How long each cycle lasts, in seconds
+    pub timeframe: u64,
+
+    /// How many bytes the node may send in each cycle, or zero if there's no target
+    pub target: u64,
+
+    /// Whether the node sent all bytes allowed in this cycle
+    pub target_reached: bool,
+
+    /// Whether the node still serves bandwidth-heavy requests in this cycle
+    pub serve_historical_blocks: bool,
+
+    /// How many bytes the node may still send in this cycle
+    pub bytes_left_in_cycle: u64,
+
+    /// How many seconds until the next cycle starts
+    pub time_left_in_cycle: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A Utreexo accumulator written to, or read from, a snapshot file, as returned by
+/// `dumputreexostate` and `loadutreexostate`
+pub struct UtreexoStateResult {
+    /// The block this accumulator is for
+    pub block_hash: String,
+
+    /// The height of that block
+    pub height: u32,
+
+    /// How many leaves were ever added to the accumulator
+    pub leaves: u64,
+
+    /// The accumulator roots
+    pub roots: Vec<String>,
+
+    /// The snapshot file
+    pub path: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
 #[serde(rename_all = "lowercase")]
@@ -347,4 +527,26 @@ impl Display for AddNodeCommand {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+/// Enum to represent the different subcommands for the setban command
+pub enum SetBanCommand {
+    /// Ban an IP address or subnet, disconnecting the peers inside it
+    Add,
+
+    /// Lift the ban on an IP address or subnet
+    Remove,
+}
+
+impl Display for SetBanCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let cmd = match self {
+            SetBanCommand::Add => "add",
+            SetBanCommand::Remove => "remove",
+        };
+        write!(f, "{cmd}")
+    }
+}
+
 impl error::Error for Error {}
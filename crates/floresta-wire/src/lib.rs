@@ -26,16 +26,38 @@ mod p2p_wire;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::address_man;
 #[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::asmap;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::ban_man;
+#[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::block_proof;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::error;
 #[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::fraud_proofs;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::i2p;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::minisketch;
+#[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::node;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::node_context;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::node_interface;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::package_relay;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::rescan;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::tor_control;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::traffic;
 pub use p2p_wire::transport::TransportProtocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::tx_proof;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::txreconciliation;
 pub use p2p_wire::UtreexoNodeConfig;
 
 /// NodeHooks is a trait that defines the hooks that a node can use to interact with the network
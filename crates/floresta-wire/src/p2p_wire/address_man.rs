@@ -3,6 +3,15 @@
 //! Address manager is a module that keeps track of known peer addresses and associated
 //! metadata. This module is very important in keeping our node protected against targeted
 //! attacks, like eclipse attacks.
+//!
+//! Like Bitcoin Core's `addrman`, addresses are kept in two tables: the new table, for addresses
+//! we've heard about but never connected to, and the tried table, for addresses we've had a
+//! successful connection with. Each table is split into buckets, and the position of an address
+//! is given by a keyed hash of its network group (see [LocalAddress::netgroup]) and, for the new
+//! table, the network group of the peer that told us about it. Since the key is random and
+//! private, an attacker can't choose where their addresses will land, and since all addresses
+//! coming from one source group can only fill a small number of buckets, flooding us with
+//! `addr` messages can't take over our tables.
 
 use core::net::IpAddr;
 use core::net::Ipv4Addr;
@@ -15,6 +24,10 @@ use std::fs::read_to_string;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
 use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::address::AddrV2Message;
 use bitcoin::p2p::ServiceFlags;
@@ -29,6 +42,8 @@ use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use super::asmap::AsMap;
+
 /// How long we'll wait before trying to connect to a peer that failed
 const RETRY_TIME: u64 = 10 * 60; // 10 minutes
 
@@ -48,6 +63,47 @@ const ASSUME_STALE: u64 = 24 * 60 * 60; // 24 hours
 /// How many addresses we keep in our address manager
 const MAX_ADDRESSES: usize = 50_000;
 
+/// The file, inside our data directory, where we keep our block-relay-only anchors
+const BLOCK_RELAY_ANCHORS_FILE: &str = "block_relay_anchors.json";
+
+/// How many block-relay-only anchors we reconnect to after a restart
+const MAX_BLOCK_RELAY_ANCHORS: usize = 2;
+
+/// How many buckets the new table has
+const NEW_BUCKET_COUNT: usize = 1024;
+
+/// How many buckets the tried table has
+const TRIED_BUCKET_COUNT: usize = 256;
+
+/// How many addresses fit in a bucket
+const BUCKET_SIZE: usize = 64;
+
+/// Addresses from the same source group can only land in this many new buckets
+const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 64;
+
+/// Addresses from the same group can only land in this many tried buckets
+const TRIED_BUCKETS_PER_GROUP: u64 = 8;
+
+/// How many tried table collisions we keep waiting to be resolved
+const MAX_TRIED_COLLISIONS: usize = 10;
+
+/// If a feeler couldn't test the address occupying a tried slot after this long, we evict it
+const TRIED_COLLISION_TIMEOUT: u64 = 40 * 60; // 40 minutes
+
+/// How long we give a connection attempt to finish, before considering it failed
+const CONNECTION_GRACE_TIME: u64 = 60;
+
+/// Addresses we haven't heard about in this long may be replaced by new ones
+const ADDRESS_HORIZON: u64 = 30 * 24 * 60 * 60; // 30 days
+
+/// Network classes, used as the first byte of a network group (same values as Bitcoin Core)
+const NET_UNROUTABLE: u8 = 0;
+const NET_IPV4: u8 = 1;
+const NET_IPV6: u8 = 2;
+const NET_ONION: u8 = 3;
+const NET_I2P: u8 = 4;
+const NET_CJDNS: u8 = 5;
+
 /// The [`ReachableNetworks`] this implementation currently supports.
 pub const SUPPORTED_NETWORKS: &[ReachableNetworks] =
     &[ReachableNetworks::IPv4, ReachableNetworks::IPv6];
@@ -329,6 +385,89 @@ impl LocalAddress {
         matches!(self.state, AddressState::Connected)
             || matches!(self.state, AddressState::Tried(_))
     }
+
+    /// Returns the network group of this address
+    ///
+    /// Addresses in the same group are likely controlled by the same entity, so we avoid
+    /// connecting to more than one of them, and limit how much of our tables they can take. The
+    /// group is the /16 prefix for IPv4, /32 for IPv6 and the first bits for Tor, I2P and CJDNS.
+    /// If we have an [AsMap], IP addresses are grouped by their AS number instead.
+    pub fn netgroup(&self, asmap: Option<&AsMap>) -> Vec<u8> {
+        if !self.is_routable() {
+            return vec![NET_UNROUTABLE];
+        }
+
+        // IPv4-mapped addresses are just IPv4 addresses
+        let address = match self.address {
+            AddrV2::Ipv6(ip) => ip
+                .to_ipv4_mapped()
+                .map(AddrV2::Ipv4)
+                .unwrap_or(AddrV2::Ipv6(ip)),
+            ref address => address.clone(),
+        };
+
+        let ip = match address {
+            AddrV2::Ipv4(ip) => Some(IpAddr::V4(ip)),
+            AddrV2::Ipv6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        };
+
+        if let Some(asn) = ip.zip(asmap).and_then(|(ip, asmap)| asmap.get_asn(&ip)) {
+            let mut group = vec![NET_IPV6];
+            group.extend(asn.to_le_bytes());
+            return group;
+        }
+
+        match address {
+            AddrV2::Ipv4(ip) => {
+                let octets = ip.octets();
+                vec![NET_IPV4, octets[0], octets[1]]
+            }
+            AddrV2::Ipv6(ip) => {
+                let octets = ip.octets();
+                vec![NET_IPV6, octets[0], octets[1], octets[2], octets[3]]
+            }
+            AddrV2::TorV2(key) => vec![NET_ONION, key[0] >> 4],
+            AddrV2::TorV3(key) => vec![NET_ONION, key[0] >> 4],
+            AddrV2::I2p(key) => vec![NET_I2P, key[0] >> 4],
+            // The first byte is always 0xfc, so we use the next 12 bits
+            AddrV2::Cjdns(ip) => {
+                let octets = ip.octets();
+                vec![NET_CJDNS, octets[1], octets[2] >> 4]
+            }
+            AddrV2::Unknown(_, _) => vec![NET_UNROUTABLE],
+        }
+    }
+
+    /// The bytes identifying this address and port, used to find its position in a bucket
+    fn address_key(&self) -> Vec<u8> {
+        let mut key = serialize(&self.address);
+        key.extend(self.port.to_le_bytes());
+        key
+    }
+
+    /// Whether this address is so bad that any other address is better
+    ///
+    /// Those may be replaced when a new address collides with them in the new table.
+    fn is_terrible(&self, now: u64) -> bool {
+        match self.state {
+            AddressState::Banned(_) => true,
+            // Don't evict an address while we're trying to connect to it
+            AddressState::Failed(when) => when + CONNECTION_GRACE_TIME < now,
+            AddressState::NeverTried => self.last_connected + ADDRESS_HORIZON < now,
+            AddressState::Tried(_) | AddressState::Connected => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where an address is stored inside the [`AddressMan`] tables
+enum TablePosition {
+    /// Index in the new table, for addresses we've never connected to
+    New(usize),
+
+    /// Index in the tried table, for addresses we've successfully connected to
+    Tried(usize),
 }
 
 #[derive(Clone)]
@@ -359,6 +498,31 @@ pub struct AddressMan {
 
     /// The networks we can reach
     reachable_networks: HashSet<ReachableNetworks>,
+
+    /// A secret key used to decide where each address goes in our tables
+    ///
+    /// Since our peers don't know this key, they can't pick addresses that will land in a
+    /// specific bucket.
+    key: [u8; 32],
+
+    /// The new table, with the id of the address in each position
+    new_table: Vec<Option<usize>>,
+
+    /// The tried table, with the id of the address in each position
+    tried_table: Vec<Option<usize>>,
+
+    /// Where each address is in our tables
+    table_positions: HashMap<usize, TablePosition>,
+
+    /// Addresses that should go to the tried table, but whose position is taken
+    ///
+    /// Instead of just evicting the old entry, we first test it with a feeler connection, and
+    /// only evict it if it doesn't answer. This maps the id of the new address to when the
+    /// collision happened.
+    tried_collisions: HashMap<usize, u64>,
+
+    /// An optional map from IP addresses to AS numbers, used to compute network groups
+    asmap: Option<AsMap>,
 }
 
 impl AddressMan {
@@ -377,6 +541,35 @@ impl AddressMan {
             peers_by_service: HashMap::new(),
             max_size: max_size.unwrap_or(MAX_ADDRESSES),
             reachable_networks,
+            key: rand::random(),
+            new_table: vec![None; NEW_BUCKET_COUNT * BUCKET_SIZE],
+            tried_table: vec![None; TRIED_BUCKET_COUNT * BUCKET_SIZE],
+            table_positions: HashMap::new(),
+            tried_collisions: HashMap::new(),
+            asmap: None,
+        }
+    }
+
+    /// Uses this [AsMap] to group IP addresses by their AS number, instead of their prefix
+    ///
+    /// Since this changes the group of our addresses, all known addresses are rearranged.
+    pub fn set_asmap(&mut self, asmap: AsMap) {
+        self.asmap = Some(asmap);
+
+        // We don't keep the sources, so we use the address itself, like we do for seeds
+        self.new_table.iter_mut().for_each(|slot| *slot = None);
+        self.tried_table.iter_mut().for_each(|slot| *slot = None);
+        self.table_positions.clear();
+        self.tried_collisions.clear();
+
+        let addresses: Vec<_> = self.addresses.values().cloned().collect();
+        for address in addresses {
+            let source_group = address.netgroup(self.asmap.as_ref());
+            match self.find_position(&address, &source_group) {
+                Some(position) => self.place(address.id, position),
+                None if address.is_good_address() => {}
+                None => self.remove_address(address.id),
+            }
         }
     }
 
@@ -389,7 +582,31 @@ impl AddressMan {
     }
 
     /// Add a new address to our list of known address
+    ///
+    /// Use this for addresses we've learned by ourselves, like from DNS seeds or our own
+    /// config. Addresses gossiped by peers should use [AddressMan::push_addresses_from].
     pub fn push_addresses(&mut self, addresses: &[LocalAddress]) {
+        self.push_addresses_with_source(addresses, None);
+    }
+
+    /// Add addresses received from the peer at `source` to our list of known addresses
+    ///
+    /// All addresses from the same source group will land in a small subset of our new table,
+    /// so a single peer can't fill our tables with addresses it controls.
+    pub fn push_addresses_from(&mut self, addresses: &[LocalAddress], source: &AddrV2) {
+        let source = LocalAddress::from(source.clone());
+        let source_group = source.netgroup(self.asmap.as_ref());
+
+        self.push_addresses_with_source(addresses, Some(&source_group));
+    }
+
+    /// Adds addresses to the new table, using `source_group` to pick their buckets. If no
+    /// source is given, each address is treated as its own source.
+    fn push_addresses_with_source(
+        &mut self,
+        addresses: &[LocalAddress],
+        source_group: Option<&[u8]>,
+    ) {
         for address in addresses {
             let id = address.id;
             // don't add addresses that don't have the minimum required services
@@ -417,16 +634,39 @@ impl AddressMan {
                 continue;
             }
 
-            if let std::collections::hash_map::Entry::Vacant(e) = self.addresses.entry(id) {
-                e.insert(address.clone());
-                if address.is_good_address() {
-                    self.good_addresses.push(id);
+            if self.addresses.contains_key(&id) {
+                continue;
+            }
+
+            // Find a place for it in the new table, if its position is taken by an address we
+            // want to keep, we just drop this one.
+            let own_group;
+            let source_group = match source_group {
+                Some(group) => group,
+                None => {
+                    own_group = address.netgroup(self.asmap.as_ref());
+                    &own_group
                 }
+            };
+
+            let position = self.find_position(address, source_group);
+            if position.is_none() && !address.is_good_address() {
+                debug!("No room for address {:?} in the new table", address.address);
+                continue;
+            }
+
+            self.addresses.insert(id, address.clone());
+            if let Some(position) = position {
+                self.place(id, position);
+            }
 
-                self.push_if_has_service(address, service_flags::UTREEXO.into());
-                self.push_if_has_service(address, ServiceFlags::NONE); // this means any peer
-                self.push_if_has_service(address, ServiceFlags::COMPACT_FILTERS);
+            if address.is_good_address() {
+                self.good_addresses.push(id);
             }
+
+            self.push_if_has_service(address, service_flags::UTREEXO.into());
+            self.push_if_has_service(address, ServiceFlags::NONE); // this means any peer
+            self.push_if_has_service(address, ServiceFlags::COMPACT_FILTERS);
         }
 
         // Open up space by pruning old addresses
@@ -435,13 +675,19 @@ impl AddressMan {
 
     /// Check if we can reach this address based on our reachable networks
     fn is_net_reachable(&self, address: &LocalAddress) -> bool {
-        match address.address {
-            AddrV2::Ipv4(_) => self.reachable_networks.contains(&ReachableNetworks::IPv4),
-            AddrV2::Ipv6(_) => self.reachable_networks.contains(&ReachableNetworks::IPv6),
-            AddrV2::TorV3(_) => self.reachable_networks.contains(&ReachableNetworks::TorV3),
-            AddrV2::I2p(_) => self.reachable_networks.contains(&ReachableNetworks::I2P),
-            AddrV2::Cjdns(_) => self.reachable_networks.contains(&ReachableNetworks::Cjdns),
-            _ => false,
+        Self::address_network(&address.address)
+            .is_some_and(|network| self.reachable_networks.contains(&network))
+    }
+
+    /// The network an address belongs to, if it's one we know
+    fn address_network(address: &AddrV2) -> Option<ReachableNetworks> {
+        match address {
+            AddrV2::Ipv4(_) => Some(ReachableNetworks::IPv4),
+            AddrV2::Ipv6(_) => Some(ReachableNetworks::IPv6),
+            AddrV2::TorV3(_) => Some(ReachableNetworks::TorV3),
+            AddrV2::I2p(_) => Some(ReachableNetworks::I2P),
+            AddrV2::Cjdns(_) => Some(ReachableNetworks::Cjdns),
+            _ => None,
         }
     }
 
@@ -462,17 +708,255 @@ impl AddressMan {
         oldest_ids.sort_by_key(|&(_, last_connected)| last_connected);
 
         for (oldest_id, _) in oldest_ids.into_iter().take(excess) {
-            self.addresses.remove(&oldest_id);
-            self.good_addresses.retain(|&x| x != oldest_id);
-            for peers in self.good_peers_by_service.values_mut() {
-                peers.retain(|&x| x != oldest_id);
+            self.remove_address(oldest_id);
+        }
+    }
+
+    /// Forgets an address, removing it from all our indexes and tables
+    fn remove_address(&mut self, id: usize) {
+        self.addresses.remove(&id);
+        self.good_addresses.retain(|&x| x != id);
+        for peers in self.good_peers_by_service.values_mut() {
+            peers.retain(|&x| x != id);
+        }
+        for peers in self.peers_by_service.values_mut() {
+            peers.retain(|&x| x != id);
+        }
+
+        self.clear_position(id);
+        self.tried_collisions.remove(&id);
+    }
+
+    /// A hash of `data`, keyed with our secret key
+    fn keyed_hash(&self, data: &[&[u8]]) -> u64 {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.key);
+        for item in data {
+            engine.input(item);
+        }
+
+        let hash = sha256::Hash::from_engine(engine).to_byte_array();
+        u64::from_le_bytes(hash[..8].try_into().expect("slice has 8 bytes"))
+    }
+
+    /// Returns the index of this address in the new table, if `source_group` told us about it
+    fn new_index(&self, address: &LocalAddress, source_group: &[u8]) -> usize {
+        let group = address.netgroup(self.asmap.as_ref());
+        let hash1 = self.keyed_hash(&[&group, source_group]) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket =
+            self.keyed_hash(&[source_group, &hash1.to_le_bytes()]) % NEW_BUCKET_COUNT as u64;
+        let slot = self.keyed_hash(&[b"N", &bucket.to_le_bytes(), &address.address_key()])
+            % BUCKET_SIZE as u64;
+
+        bucket as usize * BUCKET_SIZE + slot as usize
+    }
+
+    /// Returns the index of this address in the tried table
+    fn tried_index(&self, address: &LocalAddress) -> usize {
+        let group = address.netgroup(self.asmap.as_ref());
+        let key = address.address_key();
+        let hash1 = self.keyed_hash(&[&key]) % TRIED_BUCKETS_PER_GROUP;
+        let bucket = self.keyed_hash(&[&group, &hash1.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64;
+        let slot = self.keyed_hash(&[b"K", &bucket.to_le_bytes(), &key]) % BUCKET_SIZE as u64;
+
+        bucket as usize * BUCKET_SIZE + slot as usize
+    }
+
+    /// Finds a position for an address we are adding
+    ///
+    /// Addresses we know to be good, like our persisted peers, go straight to the tried table if
+    /// their slot is free. Everything else goes to the new table.
+    fn find_position(
+        &mut self,
+        address: &LocalAddress,
+        source_group: &[u8],
+    ) -> Option<TablePosition> {
+        if address.is_good_address() {
+            let index = self.tried_index(address);
+            if self.tried_table[index].is_none() {
+                return Some(TablePosition::Tried(index));
             }
-            for peers in self.peers_by_service.values_mut() {
-                peers.retain(|&x| x != oldest_id);
+        }
+
+        self.claim_new_slot(address, source_group)
+            .map(TablePosition::New)
+    }
+
+    /// Finds the new table position for this address, evicting the current occupant if it's
+    /// terrible. Returns `None` if the position is taken by an address we'd rather keep.
+    fn claim_new_slot(&mut self, address: &LocalAddress, source_group: &[u8]) -> Option<usize> {
+        let index = self.new_index(address, source_group);
+        let Some(occupant) = self.new_table[index] else {
+            return Some(index);
+        };
+
+        if occupant == address.id {
+            return Some(index);
+        }
+
+        let now = Self::time_since_unix();
+        let is_terrible = self
+            .addresses
+            .get(&occupant)
+            .map(|occupant| occupant.is_terrible(now))
+            .unwrap_or(true);
+
+        if !is_terrible {
+            return None;
+        }
+
+        self.remove_address(occupant);
+        Some(index)
+    }
+
+    /// Moves an address to this position, leaving the one it was in before
+    fn place(&mut self, id: usize, position: TablePosition) {
+        self.clear_position(id);
+
+        match position {
+            TablePosition::New(index) => self.new_table[index] = Some(id),
+            TablePosition::Tried(index) => self.tried_table[index] = Some(id),
+        }
+
+        self.table_positions.insert(id, position);
+    }
+
+    /// Takes an address out of our tables
+    fn clear_position(&mut self, id: usize) {
+        let table_entry = match self.table_positions.remove(&id) {
+            Some(TablePosition::New(index)) => &mut self.new_table[index],
+            Some(TablePosition::Tried(index)) => &mut self.tried_table[index],
+            None => return,
+        };
+
+        if *table_entry == Some(id) {
+            *table_entry = None;
+        }
+    }
+
+    /// Moves an address we've connected to into the tried table
+    ///
+    /// If its position is taken, we don't evict the old entry right away. Instead, we record a
+    /// collision, and a feeler will check whether the old entry is still alive.
+    fn add_to_tried(&mut self, id: usize) {
+        if let Some(TablePosition::Tried(_)) = self.table_positions.get(&id) {
+            return;
+        }
+
+        let Some(address) = self.addresses.get(&id) else {
+            return;
+        };
+
+        let index = self.tried_index(address);
+        match self.tried_table[index] {
+            None => self.place(id, TablePosition::Tried(index)),
+            Some(_) => {
+                if self.tried_collisions.len() < MAX_TRIED_COLLISIONS {
+                    let now = Self::time_since_unix();
+                    self.tried_collisions.entry(id).or_insert(now);
+                }
+            }
+        }
+    }
+
+    /// Moves an address out of the tried table, back into the new table
+    fn evict_from_tried(&mut self, id: usize) {
+        let Some(address) = self.addresses.get(&id).cloned() else {
+            return;
+        };
+
+        let own_group = address.netgroup(self.asmap.as_ref());
+        match self.claim_new_slot(&address, &own_group) {
+            Some(index) => self.place(id, TablePosition::New(index)),
+            None => self.remove_address(id),
+        }
+    }
+
+    /// Checks whether the tried table collisions can be resolved
+    ///
+    /// If the old entry answered a feeler after the collision, we keep it and forget the new
+    /// address. If it didn't, or we couldn't test it in time, the new address takes its place.
+    fn resolve_tried_collisions(&mut self) {
+        let now = Self::time_since_unix();
+        let collisions: Vec<_> = self
+            .tried_collisions
+            .iter()
+            .map(|(&id, &since)| (id, since))
+            .collect();
+
+        for (id, since) in collisions {
+            let Some(address) = self.addresses.get(&id) else {
+                self.tried_collisions.remove(&id);
+                continue;
+            };
+
+            if let Some(TablePosition::Tried(_)) = self.table_positions.get(&id) {
+                self.tried_collisions.remove(&id);
+                continue;
+            }
+
+            let index = self.tried_index(address);
+            let old = self.tried_table[index].and_then(|old| self.addresses.get(&old));
+
+            let Some(old) = old else {
+                self.tried_collisions.remove(&id);
+                self.place(id, TablePosition::Tried(index));
+                continue;
+            };
+
+            let old_is_alive = match old.state {
+                AddressState::Connected => true,
+                AddressState::Tried(when) => when >= since,
+                _ => false,
+            };
+
+            if old_is_alive {
+                self.tried_collisions.remove(&id);
+                continue;
+            }
+
+            let old_is_dead = match old.state {
+                AddressState::Failed(when) => when >= since && when + CONNECTION_GRACE_TIME < now,
+                AddressState::Banned(_) => true,
+                _ => false,
+            };
+
+            let timed_out = since + TRIED_COLLISION_TIMEOUT < now;
+            if old_is_dead || timed_out {
+                let old_id = old.id;
+                self.tried_collisions.remove(&id);
+                self.evict_from_tried(old_id);
+                self.place(id, TablePosition::Tried(index));
             }
         }
     }
 
+    /// Returns the address occupying the tried slot of a pending collision, so a feeler can
+    /// check whether it's still alive.
+    fn get_tried_collision_to_test(&self) -> Option<(usize, LocalAddress)> {
+        let id = self
+            .tried_collisions
+            .keys()
+            .choose(&mut rand::thread_rng())?;
+        let address = self.addresses.get(id)?;
+        let old_id = self.tried_table[self.tried_index(address)]?;
+        let old = self.addresses.get(&old_id)?;
+
+        match old.state {
+            AddressState::Banned(_) | AddressState::Connected => None,
+            _ => Some((old_id, old.clone())),
+        }
+    }
+
+    /// The network groups of all addresses we are connected to
+    fn connected_netgroups(&self) -> HashSet<Vec<u8>> {
+        self.addresses
+            .values()
+            .filter(|address| address.state == AddressState::Connected)
+            .map(|address| address.netgroup(self.asmap.as_ref()))
+            .collect()
+    }
+
     /// Return addresses from the [`AddressMan`] filtered by their [`ServiceFlags`].
     fn get_addresses_by_service(&self, service: ServiceFlags) -> Vec<LocalAddress> {
         self.good_peers_by_service
@@ -633,7 +1117,7 @@ impl AddressMan {
 
         // ask for any peer (if filtering isn't available)
         if seed.filters == ServiceFlags::NONE {
-            let _addresses = Self::do_lookup(seed.seed, default_port, socks5);
+            let _addresses = Self::do_lookup(&seed.seed, default_port, socks5);
             let _addresses = _addresses.into_iter().map(|mut x| {
                 x.services = ServiceFlags::NETWORK_LIMITED | ServiceFlags::WITNESS;
                 x.state = AddressState::Tried(now);
@@ -665,6 +1149,11 @@ impl AddressMan {
         // the features it supports or even if it's a valid peer. The only thing we care about
         // is that we haven't banned it.
         if feeler {
+            // Testing the old entry of a tried collision comes first
+            if let Some(collision) = self.get_tried_collision_to_test() {
+                return Some(collision);
+            }
+
             let idx = rand::random::<usize>() % self.addresses.len();
             let peer = self.addresses.keys().nth(idx)?;
             let address = self.addresses.get(peer)?.to_owned();
@@ -679,11 +1168,19 @@ impl AddressMan {
             return Some((*peer, address));
         };
 
+        // Don't connect to two peers in the same network group, they are likely controlled by
+        // the same entity
+        let connected_groups = self.connected_netgroups();
+
         for _ in 0..10 {
             let (id, peer) = self
                 .get_address_by_service(required_service)
                 .or_else(|| self.get_random_address(required_service))?;
 
+            if connected_groups.contains(&peer.netgroup(self.asmap.as_ref())) {
+                continue;
+            }
+
             match peer.state {
                 AddressState::NeverTried | AddressState::Tried(_) => {
                     return Some((id, peer));
@@ -714,6 +1211,28 @@ impl AddressMan {
         None
     }
 
+    /// Returns a random address in one of these networks, that we aren't connected to and
+    /// didn't ban
+    pub fn get_address_in_networks(
+        &self,
+        networks: &[ReachableNetworks],
+    ) -> Option<(usize, LocalAddress)> {
+        self.addresses
+            .iter()
+            .filter(|(_, address)| {
+                Self::address_network(&address.address)
+                    .is_some_and(|network| networks.contains(&network))
+            })
+            .filter(|(_, address)| {
+                !matches!(
+                    address.state,
+                    AddressState::Banned(_) | AddressState::Connected
+                )
+            })
+            .choose(&mut rand::thread_rng())
+            .map(|(id, address)| (*id, address.clone()))
+    }
+
     pub fn dump_peers(&self, datadir: &str) -> std::io::Result<()> {
         let peers: Vec<_> = self
             .addresses
@@ -742,6 +1261,43 @@ impl AddressMan {
         Ok(())
     }
 
+    /// Dumps the addresses of our block-relay-only peers to `datadir/block_relay_anchors.json`
+    pub fn dump_anchors(&self, datadir: &str, anchors: &[LocalAddress]) -> std::io::Result<()> {
+        let anchors: Vec<DiskLocalAddress> = anchors.iter().cloned().map(Into::into).collect();
+        let anchors = serde_json::to_string(&anchors).map_err(std::io::Error::other)?;
+
+        std::fs::write(format!("{datadir}/{BLOCK_RELAY_ANCHORS_FILE}"), anchors)
+    }
+
+    /// Reads the block-relay-only anchors saved by [AddressMan::dump_anchors]
+    ///
+    /// The file is deleted after reading, so if an anchor makes us crash, or is malicious, we
+    /// won't keep reconnecting to it on every restart.
+    pub fn take_anchors(&self, datadir: &str) -> Vec<LocalAddress> {
+        let path = format!("{datadir}/{BLOCK_RELAY_ANCHORS_FILE}");
+        let anchors = read_to_string(&path)
+            .ok()
+            .and_then(|anchors| serde_json::from_str::<Vec<DiskLocalAddress>>(&anchors).ok())
+            .unwrap_or_default();
+
+        let _ = std::fs::remove_file(&path);
+
+        anchors
+            .into_iter()
+            .map(Into::<LocalAddress>::into)
+            .filter(|address| self.is_net_reachable(address))
+            .take(MAX_BLOCK_RELAY_ANCHORS)
+            .collect()
+    }
+
+    /// Returns the address we know for this address and port, if any
+    pub fn get_address(&self, address: &AddrV2, port: u16) -> Option<LocalAddress> {
+        self.addresses
+            .values()
+            .find(|local| local.address == *address && local.port == port)
+            .cloned()
+    }
+
     fn get_address_by_service(&self, service: ServiceFlags) -> Option<(usize, LocalAddress)> {
         let candidates = self.good_peers_by_service.get(&service)?;
 
@@ -785,8 +1341,10 @@ impl AddressMan {
 
     /// This function moves addresses between buckets, like if the ban time of a peer expired,
     /// or if we tried to connect to a peer and it failed in the past, but now it might be online
-    /// again.
+    /// again. It also resolves pending collisions in the tried table.
     pub fn rearrange_buckets(&mut self) {
+        self.resolve_tried_collisions();
+
         let now = Self::time_since_unix();
 
         for address in self.addresses.values_mut() {
@@ -881,6 +1439,8 @@ impl AddressMan {
                     self.good_addresses.push(idx);
                 }
 
+                self.add_to_tried(idx);
+
                 if let Some(address) = self.addresses.get(&idx).cloned() {
                     self.push_if_has_service(&address, service_flags::UTREEXO.into());
                     self.push_if_has_service(&address, service_flags::UTREEXO_ARCHIVE.into());
@@ -900,6 +1460,8 @@ impl AddressMan {
                     self.good_addresses.push(idx);
                 }
 
+                self.add_to_tried(idx);
+
                 // push to the good peers by service
                 if let Some(address) = self.addresses.get(&idx).cloned() {
                     self.push_if_has_service(&address, service_flags::UTREEXO.into());
@@ -972,16 +1534,7 @@ impl AddressMan {
     pub fn update_set_service_flag(&mut self, idx: usize, flags: ServiceFlags) -> &mut Self {
         // if this peer turns out to not have the minimum required services, we remove it
         if !flags.has(ServiceFlags::NETWORK_LIMITED) || !flags.has(ServiceFlags::WITNESS) {
-            self.addresses.remove(&idx);
-            for peers in self.peers_by_service.values_mut() {
-                peers.retain(|&x| x != idx);
-            }
-
-            self.good_addresses.retain(|&x| x != idx);
-            self.good_peers_by_service
-                .values_mut()
-                .for_each(|peers| peers.retain(|&x| x != idx));
-
+            self.remove_address(idx);
             return self;
         }
 
@@ -1225,6 +1778,10 @@ mod test {
 
     use super::AddressState;
     use super::LocalAddress;
+    use super::TablePosition;
+    use super::BUCKET_SIZE;
+    use super::NEW_BUCKETS_PER_SOURCE_GROUP;
+    use super::TRIED_COLLISION_TIMEOUT;
     use crate::address_man::AddressMan;
     use crate::address_man::DiskLocalAddress;
     use crate::address_man::ReachableNetworks;
@@ -1691,4 +2248,191 @@ mod test {
         address_man.add_fixed_addresses(Network::Signet);
         assert!(!address_man.addresses.is_empty());
     }
+
+    fn ipv4_address(ip: &str, id: usize) -> LocalAddress {
+        LocalAddress::new(
+            AddrV2::Ipv4(ip.parse().unwrap()),
+            AddressMan::time_since_unix(),
+            AddressState::NeverTried,
+            ServiceFlags::NETWORK_LIMITED | ServiceFlags::WITNESS,
+            8333,
+            id,
+        )
+    }
+
+    #[test]
+    fn test_netgroup() {
+        let group = |ip: &str| ipv4_address(ip, 0).netgroup(None);
+
+        // IPv4 addresses are grouped by their /16
+        assert_eq!(group("1.2.3.4"), vec![1, 1, 2]);
+        assert_eq!(group("1.2.3.4"), group("1.2.200.1"));
+        assert_ne!(group("1.2.3.4"), group("1.3.3.4"));
+
+        // IPv6 addresses are grouped by their /32, and IPv4-mapped addresses are just IPv4
+        let ipv6 = |ip: &str| {
+            let mut address = ipv4_address("1.1.1.1", 0);
+            address.address = AddrV2::Ipv6(ip.parse().unwrap());
+            address.netgroup(None)
+        };
+        assert_eq!(ipv6("2a01:4f8:1::1"), ipv6("2a01:4f8:ffff::1"));
+        assert_ne!(ipv6("2a01:4f8:1::1"), ipv6("2a01:4f9:1::1"));
+        assert_eq!(ipv6("::ffff:1.2.3.4"), group("1.2.3.4"));
+
+        // Tor addresses are grouped by network and first bits
+        let mut onion = ipv4_address("1.1.1.1", 0);
+        onion.address = AddrV2::TorV3([0xab; 32]);
+        assert_eq!(onion.netgroup(None), vec![3, 0xa]);
+
+        // Unroutable addresses are all in the same group
+        assert_eq!(group("127.0.0.1"), group("192.168.0.1"));
+
+        // With an asmap, the AS number is the group. This map puts everything in AS100
+        let mut bits = vec![0, 0];
+        bits.extend((0..15).rev().map(|b| (99 >> b) & 1));
+        let bytes = bits
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, bit)| byte | ((*bit as u8) << i))
+            })
+            .collect();
+
+        let asmap = crate::asmap::AsMap::new(bytes).unwrap();
+        let address = ipv4_address("1.2.3.4", 0);
+        assert_eq!(address.netgroup(Some(&asmap)), vec![2, 100, 0, 0, 0]);
+        assert_eq!(
+            address.netgroup(Some(&asmap)),
+            ipv4_address("8.8.8.8", 0).netgroup(Some(&asmap))
+        );
+    }
+
+    #[test]
+    fn test_source_flooding() {
+        let mut address_man = AddressMan::new(None, &[ReachableNetworks::IPv4]);
+        let source = AddrV2::Ipv4("30.0.0.1".parse().unwrap());
+
+        // A single peer sending us addresses in many different groups
+        let addresses: Vec<_> = (0..2000)
+            .map(|i| ipv4_address(&format!("{}.{}.0.1", 40 + i / 256, i % 256), i))
+            .collect();
+
+        address_man.push_addresses_from(&addresses, &source);
+
+        let buckets: std::collections::HashSet<_> = address_man
+            .table_positions
+            .values()
+            .map(|position| match position {
+                TablePosition::New(index) => index / BUCKET_SIZE,
+                TablePosition::Tried(_) => panic!("unexpected address in tried table"),
+            })
+            .collect();
+
+        // They can only fill a few of our buckets, and once those are full, we drop the rest
+        assert!(buckets.len() as u64 <= NEW_BUCKETS_PER_SOURCE_GROUP);
+        assert!(address_man.addresses.len() <= BUCKET_SIZE * buckets.len());
+        assert!(address_man.addresses.len() < addresses.len());
+    }
+
+    #[test]
+    fn test_tried_collision() {
+        let now = AddressMan::time_since_unix();
+        let setup = || {
+            let mut address_man = AddressMan::new(None, &[ReachableNetworks::IPv4]);
+            let old = ipv4_address("40.0.0.1", 1);
+            let new = ipv4_address("50.0.0.1", 2);
+            address_man.push_addresses(&[old.clone(), new.clone()]);
+
+            // Make the old address sit where the new one would go in the tried table
+            let index = address_man.tried_index(&new);
+            address_man.place(old.id, TablePosition::Tried(index));
+            address_man.update_set_state(old.id, AddressState::Tried(now - 3600));
+
+            address_man.update_set_state(new.id, AddressState::Connected);
+            assert!(address_man.tried_collisions.contains_key(&new.id));
+            assert!(matches!(
+                address_man.table_positions.get(&new.id),
+                Some(TablePosition::New(_))
+            ));
+
+            // A feeler should test the old entry first
+            let (feeler, _) = address_man
+                .get_address_to_connect(ServiceFlags::NONE, true)
+                .unwrap();
+            assert_eq!(feeler, old.id);
+
+            (address_man, index)
+        };
+
+        // The old entry is still alive, so we keep it
+        let (mut address_man, index) = setup();
+        address_man.update_set_state(1, AddressState::Tried(now));
+        address_man.rearrange_buckets();
+        assert!(address_man.tried_collisions.is_empty());
+        assert_eq!(address_man.tried_table[index], Some(1));
+
+        // The old entry didn't answer, so the new one takes its place
+        let (mut address_man, index) = setup();
+        address_man.tried_collisions.insert(2, now - 600);
+        address_man.update_set_state(1, AddressState::Failed(now - 300));
+        address_man.rearrange_buckets();
+        assert!(address_man.tried_collisions.is_empty());
+        assert_eq!(address_man.tried_table[index], Some(2));
+        assert!(matches!(
+            address_man.table_positions.get(&1),
+            Some(TablePosition::New(_)) | None
+        ));
+
+        // We couldn't test the old entry in time
+        let (mut address_man, index) = setup();
+        address_man
+            .tried_collisions
+            .insert(2, now - TRIED_COLLISION_TIMEOUT - 1);
+        address_man.rearrange_buckets();
+        assert_eq!(address_man.tried_table[index], Some(2));
+    }
+
+    #[test]
+    fn test_netgroup_diversity() {
+        let mut address_man = AddressMan::new(None, &[ReachableNetworks::IPv4]);
+        let connected = ipv4_address("40.1.0.1", 1);
+        let same_group = ipv4_address("40.1.5.5", 2);
+        let other_group = ipv4_address("50.1.0.1", 3);
+
+        address_man.push_addresses(&[connected, same_group, other_group]);
+        address_man.update_set_state(1, AddressState::Connected);
+
+        for _ in 0..100 {
+            let Some((id, _)) = address_man.get_address_to_connect(ServiceFlags::NONE, false)
+            else {
+                continue;
+            };
+
+            assert_eq!(id, 3);
+        }
+    }
+
+    #[test]
+    fn test_block_relay_anchors() {
+        let datadir = format!("./tmp-db/{}.anchors", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let address_man = AddressMan::new(None, &[ReachableNetworks::IPv4]);
+        let anchors: Vec<_> = (0..3)
+            .map(|i| ipv4_address(&format!("40.0.0.{}", i + 1), i))
+            .collect();
+
+        address_man.dump_anchors(&datadir, &anchors).unwrap();
+
+        // We only reconnect to the first two
+        let loaded = address_man.take_anchors(&datadir);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].address, anchors[0].address);
+        assert_eq!(loaded[1].port, anchors[1].port);
+
+        // The file is removed after reading, so we never reuse anchors
+        assert!(address_man.take_anchors(&datadir).is_empty());
+    }
 }
@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! An interpreter for Bitcoin Core's ASmap format, used to map IP addresses to the Autonomous
+//! System (AS) announcing them.
+//!
+//! By default, the address manager groups addresses by their /16 (IPv4) or /32 (IPv6) prefix,
+//! so we don't connect to many peers inside the same network. However, a single entity may own
+//! many of those prefixes. With an ASmap, we can group addresses by the AS that announces them
+//! instead, which is a much better approximation of "who controls this address".
+//!
+//! The ASmap file is a compact bytecode, a binary trie over the bits of an IPv6 address
+//! (IPv4 addresses are mapped into `::ffff:0:0/96`). See Bitcoin Core's `util/asmap.cpp` and
+//! <https://github.com/sipa/asmap> for how those files are built.
+
+use core::net::IpAddr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Returned by the decoders if the bytecode ended unexpectedly
+const INVALID: u32 = u32::MAX;
+
+/// How many bits are used to encode the instruction type
+const TYPE_BIT_SIZES: &[u8] = &[0, 0, 1];
+
+/// How many bits are used to encode an ASN
+const ASN_BIT_SIZES: &[u8] = &[15, 16, 17, 18, 19, 20, 21, 22, 23, 24];
+
+/// How many bits are used to encode the bits that should be matched
+const MATCH_BIT_SIZES: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+
+/// How many bits are used to encode a jump offset
+const JUMP_BIT_SIZES: &[u8] = &[
+    5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29,
+    30,
+];
+
+/// The instructions of the ASmap bytecode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instruction {
+    /// Returns the ASN that follows
+    Return,
+    /// If the next bit of the IP is set, jump ahead by the encoded offset
+    Jump,
+    /// If the next bits of the IP don't match the encoded ones, return the default ASN
+    Match,
+    /// Sets the default ASN
+    Default,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A loaded ASmap
+///
+/// This is cheap to clone, as the actual data is behind an [Arc].
+pub struct AsMap {
+    /// The raw bytecode, bits are read from the least significant one of each byte
+    data: Arc<Vec<u8>>,
+}
+
+impl AsMap {
+    /// Creates an ASmap from its raw bytes, returning `None` if it doesn't look valid.
+    pub fn new(data: Vec<u8>) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        }
+
+        let asmap = AsMap {
+            data: Arc::new(data),
+        };
+
+        // A map that can't give us an ASN for any address is useless, and almost certainly
+        // corrupted.
+        if !asmap.returns_anything() {
+            return None;
+        }
+
+        Some(asmap)
+    }
+
+    /// Reads an ASmap from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Self::new(data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid asmap file"))
+    }
+
+    /// Returns the AS number announcing this address, or `None` if the map doesn't know it.
+    pub fn get_asn(&self, ip: &IpAddr) -> Option<u32> {
+        self.interpret(&Self::ip_bits(ip))
+    }
+
+    /// Whether this map has at least one well-formed `RETURN` instruction.
+    fn returns_anything(&self) -> bool {
+        let mut pos = 0;
+        while pos < self.len() {
+            let Some(opcode) = self.decode_type(&mut pos) else {
+                return false;
+            };
+
+            let value = match opcode {
+                Instruction::Return | Instruction::Default => {
+                    self.decode_bits(&mut pos, 1, ASN_BIT_SIZES)
+                }
+                Instruction::Jump => self.decode_bits(&mut pos, 17, JUMP_BIT_SIZES),
+                Instruction::Match => self.decode_bits(&mut pos, 2, MATCH_BIT_SIZES),
+            };
+
+            if value == INVALID {
+                return false;
+            }
+
+            if opcode == Instruction::Return {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// The number of bits in the bytecode
+    fn len(&self) -> usize {
+        self.data.len() * 8
+    }
+
+    /// Returns the bit at `pos`
+    fn bit(&self, pos: usize) -> bool {
+        (self.data[pos / 8] >> (pos % 8)) & 1 == 1
+    }
+
+    /// Returns the 128 bits of this address, most significant first. IPv4 addresses are
+    /// IPv4-mapped, just like in Bitcoin Core.
+    fn ip_bits(ip: &IpAddr) -> Vec<bool> {
+        let octets = match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
+        };
+
+        octets
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+            .collect()
+    }
+
+    /// Decodes a variable-length integer, as described in Bitcoin Core's `DecodeBits`.
+    fn decode_bits(&self, pos: &mut usize, min_val: u32, bit_sizes: &[u8]) -> u32 {
+        let mut val = min_val;
+        for (i, &size) in bit_sizes.iter().enumerate() {
+            // The last class has no continuation bit
+            let continues = match i + 1 == bit_sizes.len() {
+                true => false,
+                false => {
+                    if *pos >= self.len() {
+                        break;
+                    }
+
+                    let bit = self.bit(*pos);
+                    *pos += 1;
+                    bit
+                }
+            };
+
+            if continues {
+                val += 1 << size;
+                continue;
+            }
+
+            for b in 0..size {
+                if *pos >= self.len() {
+                    return INVALID;
+                }
+
+                let bit = self.bit(*pos) as u32;
+                *pos += 1;
+                val += bit << (size - 1 - b);
+            }
+
+            return val;
+        }
+
+        INVALID
+    }
+
+    /// Decodes the next instruction
+    fn decode_type(&self, pos: &mut usize) -> Option<Instruction> {
+        match self.decode_bits(pos, 0, TYPE_BIT_SIZES) {
+            0 => Some(Instruction::Return),
+            1 => Some(Instruction::Jump),
+            2 => Some(Instruction::Match),
+            3 => Some(Instruction::Default),
+            _ => None,
+        }
+    }
+
+    /// Runs the bytecode for those IP bits, returning the ASN if found.
+    fn interpret(&self, ip: &[bool]) -> Option<u32> {
+        let end = self.len();
+        let mut pos = 0;
+        let mut bits = ip.len();
+        let mut default_asn = 0;
+
+        while pos < end {
+            match self.decode_type(&mut pos)? {
+                Instruction::Return => {
+                    let asn = self.decode_bits(&mut pos, 1, ASN_BIT_SIZES);
+                    if asn == INVALID {
+                        break;
+                    }
+
+                    return Some(asn);
+                }
+                Instruction::Jump => {
+                    let jump = self.decode_bits(&mut pos, 17, JUMP_BIT_SIZES);
+                    if jump == INVALID || bits == 0 {
+                        break;
+                    }
+
+                    let jump = jump as usize;
+                    if jump >= end - pos {
+                        break;
+                    }
+
+                    if ip[ip.len() - bits] {
+                        pos += jump;
+                    }
+
+                    bits -= 1;
+                }
+                Instruction::Match => {
+                    let to_match = self.decode_bits(&mut pos, 2, MATCH_BIT_SIZES);
+                    if to_match == INVALID {
+                        break;
+                    }
+
+                    // The highest set bit is only a marker for the length
+                    let match_len = (u32::BITS - to_match.leading_zeros() - 1) as usize;
+                    if bits < match_len {
+                        break;
+                    }
+
+                    for bit in 0..match_len {
+                        let expected = (to_match >> (match_len - 1 - bit)) & 1 == 1;
+                        if ip[ip.len() - bits] != expected {
+                            return (default_asn != 0).then_some(default_asn);
+                        }
+
+                        bits -= 1;
+                    }
+                }
+                Instruction::Default => {
+                    default_asn = self.decode_bits(&mut pos, 1, ASN_BIT_SIZES);
+                    if default_asn == INVALID {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // 0 isn't a valid ASN, this means the bytecode is invalid
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::IpAddr;
+
+    use super::AsMap;
+
+    /// Packs bits into bytes, least significant bit first
+    fn pack(bits: &[u8]) -> Vec<u8> {
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, bit)| byte | (bit << i))
+            })
+            .collect()
+    }
+
+    /// Encodes an ASN in the smallest class (15 bits of mantissa)
+    fn encode_asn(asn: u32) -> Vec<u8> {
+        let mantissa = asn - 1;
+        let mut bits = vec![0];
+        bits.extend((0..15).rev().map(|b| ((mantissa >> b) & 1) as u8));
+        bits
+    }
+
+    #[test]
+    fn test_return() {
+        // RETURN 100: every address is in AS100
+        let mut bits = vec![0];
+        bits.extend(encode_asn(100));
+
+        let asmap = AsMap::new(pack(&bits)).unwrap();
+        let ip: IpAddr = "8.8.8.8".parse().unwrap();
+        assert_eq!(asmap.get_asn(&ip), Some(100));
+    }
+
+    #[test]
+    fn test_jump() {
+        // JUMP 17; RETURN 100; RETURN 200
+        // If the first bit is unset, we fall through to AS100, otherwise we skip the first
+        // RETURN (17 bits long) and get AS200.
+        let mut bits = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        bits.push(0);
+        bits.extend(encode_asn(100));
+        bits.push(0);
+        bits.extend(encode_asn(200));
+
+        let asmap = AsMap::new(pack(&bits)).unwrap();
+
+        // IPv4 addresses are mapped into ::ffff:0:0/96, the first bit is always unset
+        let v4: IpAddr = "1.2.3.4".parse().unwrap();
+        let v6_low: IpAddr = "2001:db8::1".parse().unwrap();
+        let v6_high: IpAddr = "8000::1".parse().unwrap();
+
+        assert_eq!(asmap.get_asn(&v4), Some(100));
+        assert_eq!(asmap.get_asn(&v6_low), Some(100));
+        assert_eq!(asmap.get_asn(&v6_high), Some(200));
+    }
+
+    #[test]
+    fn test_match() {
+        // DEFAULT 300; MATCH 0b11 (one bit, set); RETURN 400
+        let mut bits = vec![1, 1, 1];
+        bits.extend(encode_asn(300));
+        // MATCH: type `110`, then the class bit and one bit of mantissa. 0b11 = 2 + 1
+        bits.extend([1, 1, 0, 0, 1]);
+        bits.push(0);
+        bits.extend(encode_asn(400));
+
+        let asmap = AsMap::new(pack(&bits)).unwrap();
+
+        let low: IpAddr = "2001:db8::1".parse().unwrap();
+        let high: IpAddr = "8000::1".parse().unwrap();
+
+        assert_eq!(asmap.get_asn(&low), Some(300));
+        assert_eq!(asmap.get_asn(&high), Some(400));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(AsMap::new(Vec::new()).is_none());
+
+        // A lone JUMP, with no instructions to run
+        assert!(AsMap::new(pack(&[1, 0, 0, 0, 0, 0, 0, 0])).is_none());
+    }
+}
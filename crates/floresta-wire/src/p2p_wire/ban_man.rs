@@ -0,0 +1,442 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A persistent list of banned IP addresses and subnets.
+//!
+//! Peers end up here when they misbehave (see `UtreexoNode::increase_banscore`), or when our user
+//! bans them through the `setban` RPC. Each entry has an expiry time and the reason why it was
+//! added, and the whole list is saved to `banlist.json` inside our data directory on every change,
+//! so bans survive restarts.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::net::IpAddr;
+use core::net::Ipv4Addr;
+use core::net::Ipv6Addr;
+use core::str::FromStr;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use tracing::warn;
+
+/// The file, inside our data directory, where we keep the ban list
+pub const BAN_LIST_FILE: &str = "banlist.json";
+
+/// Returned when a string isn't a valid IP address or subnet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSubnet(pub String);
+
+impl Display for InvalidSubnet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid IP address or subnet: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSubnet {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A range of IP addresses, given by a network address and a prefix length
+///
+/// A single address is a subnet with the full prefix length (32 for IPv4, 128 for IPv6).
+/// IPv4-mapped IPv6 addresses are always treated as IPv4.
+pub struct Subnet {
+    /// The network address, with all bits after the prefix unset
+    network: IpAddr,
+    /// How many leading bits of an address must match the network's
+    prefix_len: u8,
+}
+
+impl Subnet {
+    /// Creates a subnet, masking out the bits of `ip` that are past `prefix_len`.
+    ///
+    /// Returns `None` if the prefix is longer than the address.
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Option<Self> {
+        let ip = Self::canonical(ip);
+        let max_len = Self::max_prefix_len(&ip);
+        if prefix_len > max_len {
+            return None;
+        }
+
+        let network = match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        };
+
+        Some(Subnet {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// A subnet containing only this address
+    pub fn single(ip: IpAddr) -> Self {
+        let ip = Self::canonical(ip);
+        Subnet {
+            network: ip,
+            prefix_len: Self::max_prefix_len(&ip),
+        }
+    }
+
+    /// Whether `ip` is inside this subnet
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = Self::canonical(*ip);
+        match (self.network, ip) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                Subnet::new(ip, self.prefix_len).is_some_and(|subnet| subnet == *self)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether every address in `other` is also in this subnet
+    pub fn includes(&self, other: &Subnet) -> bool {
+        self.prefix_len <= other.prefix_len && self.contains(&other.network)
+    }
+
+    /// Treats IPv4-mapped IPv6 addresses as plain IPv4
+    fn canonical(ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => ip,
+            },
+            IpAddr::V4(_) => ip,
+        }
+    }
+
+    fn max_prefix_len(ip: &IpAddr) -> u8 {
+        match ip {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Turns a netmask like `255.255.255.0` into a prefix length, if its bits are contiguous
+    fn mask_to_prefix_len(mask: IpAddr) -> Option<u8> {
+        let (ones, zeros) = match mask {
+            IpAddr::V4(mask) => {
+                let mask = u32::from(mask);
+                (mask.leading_ones(), mask.trailing_zeros())
+            }
+            IpAddr::V6(mask) => {
+                let mask = u128::from(mask);
+                (mask.leading_ones(), mask.trailing_zeros())
+            }
+        };
+
+        // The mask is only valid if all its ones come before all its zeros
+        let bits = Self::max_prefix_len(&mask) as u32;
+        (ones + zeros == bits).then_some(ones as u8)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = InvalidSubnet;
+
+    /// Parses an address (`1.2.3.4`), a CIDR subnet (`1.2.3.0/24`) or an address with a netmask
+    /// (`1.2.3.0/255.255.255.0`), just like Bitcoin Core does.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSubnet(s.to_string());
+
+        let Some((ip, prefix)) = s.split_once('/') else {
+            let ip = s.parse::<IpAddr>().map_err(|_| invalid())?;
+            return Ok(Subnet::single(ip));
+        };
+
+        let ip = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix_len = match prefix.parse::<u8>() {
+            Ok(len) => len,
+            Err(_) => {
+                let mask = prefix.parse::<IpAddr>().map_err(|_| invalid())?;
+                if mask.is_ipv4() != ip.is_ipv4() {
+                    return Err(invalid());
+                }
+
+                Self::mask_to_prefix_len(mask).ok_or_else(invalid)?
+            }
+        };
+
+        // For mapped addresses, the prefix refers to the IPv6 form
+        let prefix_len = match (ip, Self::canonical(ip)) {
+            (IpAddr::V6(_), IpAddr::V4(_)) => prefix_len.checked_sub(96).ok_or_else(invalid)?,
+            _ => prefix_len,
+        };
+
+        Subnet::new(ip, prefix_len).ok_or_else(invalid)
+    }
+}
+
+impl Display for Subnet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let subnet = String::deserialize(deserializer)?;
+        subnet.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A banned subnet, and why it was banned
+pub struct BanEntry {
+    /// The banned addresses
+    pub subnet: Subnet,
+    /// When this ban was created, as a UNIX timestamp
+    pub created: u64,
+    /// When this ban expires, as a UNIX timestamp
+    pub until: u64,
+    /// Why this subnet was banned
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+/// Keeps track of banned subnets, optionally persisting them in our data directory
+pub struct BanMan {
+    /// All bans we know about, some of them may have expired already
+    bans: HashMap<Subnet, BanEntry>,
+    /// Where to save the bans. If `None`, they only live in memory
+    path: Option<PathBuf>,
+}
+
+impl BanMan {
+    /// Loads the ban list saved inside `datadir`, or starts a new one if there's none.
+    ///
+    /// A corrupted list is ignored (and will be overwritten on the next change), as we shouldn't
+    /// refuse to start just because we can't remember who we've banned.
+    pub fn load(datadir: &str) -> Self {
+        let path = PathBuf::from(format!("{datadir}/{BAN_LIST_FILE}"));
+        let entries = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<Vec<BanEntry>>(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid ban list at {}: {e}", path.display());
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        let mut ban_man = BanMan {
+            bans: entries.into_iter().map(|ban| (ban.subnet, ban)).collect(),
+            path: Some(path),
+        };
+
+        ban_man.sweep();
+        ban_man
+    }
+
+    /// Bans `subnet` until the UNIX timestamp `until`.
+    ///
+    /// If it's already banned, the ban is extended (but never shortened) and its reason updated.
+    pub fn ban(&mut self, subnet: Subnet, until: u64, reason: impl Into<String>) -> io::Result<()> {
+        let now = Self::now();
+        let reason = reason.into();
+
+        self.bans
+            .entry(subnet)
+            .and_modify(|ban| {
+                if ban.until < until {
+                    ban.until = until;
+                    ban.reason = reason.clone();
+                }
+            })
+            .or_insert_with(|| BanEntry {
+                subnet,
+                created: now,
+                until,
+                reason,
+            });
+
+        self.save()
+    }
+
+    /// Lifts the ban on `subnet`, returning whether it was banned.
+    pub fn unban(&mut self, subnet: &Subnet) -> io::Result<bool> {
+        self.sweep();
+        if self.bans.remove(subnet).is_none() {
+            return Ok(false);
+        }
+
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Whether this address is inside any of the subnets we've banned
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        let now = Self::now();
+        self.bans
+            .values()
+            .any(|ban| ban.until > now && ban.subnet.contains(ip))
+    }
+
+    /// Whether this exact subnet has an active ban
+    pub fn is_subnet_banned(&self, subnet: &Subnet) -> bool {
+        let now = Self::now();
+        self.bans.get(subnet).is_some_and(|ban| ban.until > now)
+    }
+
+    /// Returns all active bans, ordered by creation time. Expired bans are removed.
+    pub fn list(&mut self) -> Vec<BanEntry> {
+        if self.sweep() {
+            if let Err(e) = self.save() {
+                warn!("Could not save the ban list: {e}");
+            }
+        }
+
+        let mut bans: Vec<_> = self.bans.values().cloned().collect();
+        bans.sort_by_key(|ban| (ban.created, ban.subnet.to_string()));
+        bans
+    }
+
+    /// Lifts all bans
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.bans.clear();
+        self.save()
+    }
+
+    /// Removes expired bans, returning whether any was removed
+    fn sweep(&mut self) -> bool {
+        let now = Self::now();
+        let len = self.bans.len();
+        self.bans.retain(|_, ban| ban.until > now);
+
+        len != self.bans.len()
+    }
+
+    /// Writes the ban list to disk, if we have a path for it.
+    ///
+    /// We write to a temporary file first, so a crash never leaves us with a truncated list.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut bans: Vec<_> = self.bans.values().collect();
+        bans.sort_by_key(|ban| ban.created);
+
+        let content = serde_json::to_string_pretty(&bans).map_err(io::Error::other)?;
+        let tmp = path.with_extension("json.tmp");
+
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::IpAddr;
+
+    use super::BanMan;
+    use super::Subnet;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_subnet() {
+        let single: Subnet = "1.2.3.4".parse().unwrap();
+        assert_eq!(single.to_string(), "1.2.3.4/32");
+
+        let cidr: Subnet = "1.2.3.4/24".parse().unwrap();
+        assert_eq!(cidr.to_string(), "1.2.3.0/24");
+
+        let netmask: Subnet = "1.2.3.4/255.255.0.0".parse().unwrap();
+        assert_eq!(netmask.to_string(), "1.2.0.0/16");
+
+        let v6: Subnet = "2001:db8::1/32".parse().unwrap();
+        assert_eq!(v6.to_string(), "2001:db8::/32");
+
+        let mapped: Subnet = "::ffff:1.2.3.4".parse().unwrap();
+        assert_eq!(mapped, single);
+
+        assert!("1.2.3.4/33".parse::<Subnet>().is_err());
+        assert!("1.2.3.4/255.0.255.0".parse::<Subnet>().is_err());
+        assert!("1.2.3.4/ffff::".parse::<Subnet>().is_err());
+        assert!("not an ip".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let subnet: Subnet = "10.0.0.0/8".parse().unwrap();
+        assert!(subnet.contains(&ip("10.1.2.3")));
+        assert!(subnet.contains(&ip("::ffff:10.1.2.3")));
+        assert!(!subnet.contains(&ip("11.0.0.1")));
+        assert!(!subnet.contains(&ip("2001:db8::1")));
+
+        let everything: Subnet = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(&ip("8.8.8.8")));
+        assert!(everything.includes(&subnet));
+        assert!(!subnet.includes(&everything));
+    }
+
+    #[test]
+    fn test_ban_man() {
+        let datadir = format!("./tmp-db/{}.banman", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let future = BanMan::now() + 3600;
+        let past = BanMan::now() - 1;
+
+        let mut ban_man = BanMan::load(&datadir);
+        ban_man
+            .ban("10.0.0.0/8".parse().unwrap(), future, "manually added")
+            .unwrap();
+        ban_man
+            .ban("1.1.1.1".parse().unwrap(), past, "already expired")
+            .unwrap();
+
+        assert!(ban_man.is_banned(&ip("10.20.30.40")));
+        assert!(!ban_man.is_banned(&ip("1.1.1.1")));
+
+        // Bans survive a restart, and expired ones are forgotten
+        let mut ban_man = BanMan::load(&datadir);
+        let bans = ban_man.list();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].subnet.to_string(), "10.0.0.0/8");
+        assert_eq!(bans[0].reason, "manually added");
+
+        // Re-banning never shortens a ban
+        ban_man
+            .ban("10.0.0.0/8".parse().unwrap(), future - 10, "again")
+            .unwrap();
+        assert_eq!(ban_man.list()[0].until, future);
+
+        assert!(ban_man.unban(&"10.0.0.0/8".parse().unwrap()).unwrap());
+        assert!(!ban_man.unban(&"10.0.0.0/8".parse().unwrap()).unwrap());
+        assert!(!ban_man.is_banned(&ip("10.20.30.40")));
+
+        ban_man
+            .ban("2001:db8::/32".parse().unwrap(), future, "v6")
+            .unwrap();
+        ban_man.clear().unwrap();
+        assert!(BanMan::load(&datadir).list().is_empty());
+    }
+}
@@ -6,6 +6,7 @@ use core::fmt::Formatter;
 use core::net::IpAddr;
 use std::io;
 
+use bitcoin::BlockHash;
 use floresta_chain::BlockchainError;
 use floresta_common::impl_error_from;
 use floresta_compact_filters::IterableFilterStoreError;
@@ -46,6 +47,9 @@ pub enum WireError {
     /// Peer already exists in our peers list
     PeerAlreadyExists(IpAddr, u16),
 
+    /// This address is in our ban list
+    AddressBanned(IpAddr),
+
     /// Peer not found with this given address and port, in our peer list
     PeerNotFoundAtAddress(IpAddr, u16),
 
@@ -90,6 +94,21 @@ pub enum WireError {
 
     /// Couldn't find the leaf data for a block
     LeafDataNotFound,
+
+    /// Private broadcast needs a proxy or an I2P router to reach peers anonymously
+    NoAnonymityNetwork,
+
+    /// The block of a Utreexo snapshot isn't in our best chain
+    SnapshotNotInChain(BlockHash),
+
+    /// We've already validated past the block of a Utreexo snapshot, at this height
+    SnapshotAlreadyValidated(u32),
+
+    /// We can't load a Utreexo snapshot while backfilling
+    BackfillInProgress,
+
+    /// Our peers didn't agree on the accumulator for this block
+    AccumulatorNotFound(BlockHash),
 }
 
 impl Display for WireError {
@@ -107,6 +126,7 @@ impl Display for WireError {
                 "Failed to init Utreexo peers: anchors.json does not exist yet"
             ),
             WireError::PeerAlreadyExists(ip, port) => write!(f, "Peer {ip}:{port} already exists"),
+            WireError::AddressBanned(ip) => write!(f, "Address {ip} is banned"),
             WireError::PeerNotFoundAtAddress(ip, port) => write!(f, "Peer {ip}:{port} not found"),
             WireError::Io(err) => write!(f, "Generic IO error: {err:?}"),
             WireError::Serde(err) => write!(f, "Serde error: {err:?}"),
@@ -134,6 +154,29 @@ impl Display for WireError {
                 "We tried to work on a block that we don't have a proof for yet"
             ),
             WireError::LeafDataNotFound => write!(f, "Couldn't find the leaf data for a block"),
+            WireError::NoAnonymityNetwork => write!(
+                f,
+                "Private broadcast needs a proxy or an I2P router to reach peers anonymously"
+            ),
+            WireError::SnapshotNotInChain(hash) => {
+                write!(f, "The snapshot block {hash} isn't in our best chain")
+            }
+            WireError::SnapshotAlreadyValidated(height) => write!(
+                f,
+                "We've already validated past the snapshot block, at height {height}"
+            ),
+            WireError::BackfillInProgress => {
+                write!(
+                    f,
+                    "Can't load a snapshot while backfilling, try again later"
+                )
+            }
+            WireError::AccumulatorNotFound(hash) => {
+                write!(
+                    f,
+                    "Our peers didn't agree on the accumulator for block {hash}"
+                )
+            }
         }
     }
 }
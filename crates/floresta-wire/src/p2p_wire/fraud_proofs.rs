@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A persistent record of the chain tips we've checked with PoW fraud proofs.
+//!
+//! With PoW fraud proofs, we don't validate the chain our peers give us, as long as they all
+//! follow the same one. When some peer announces a tip that isn't in our best chain, we validate
+//! the first block where our chain forks away from theirs, and either move to their tip or ban
+//! them (see `ChainSelector::check_tips`). Every check and its outcome is kept here, and saved
+//! to `pow_fraud_proofs.json` inside our data directory, so our user can audit why we're on a
+//! given chain, even after a restart.
+
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use bitcoin::BlockHash;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::warn;
+
+/// The file, inside our data directory, where we keep our tip checks
+pub const FRAUD_PROOFS_FILE: &str = "pow_fraud_proofs.json";
+
+/// How many tip checks we remember. Once we have more, the oldest ones are forgotten
+pub const MAX_TIP_CHECKS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// What we've concluded after checking a disputed tip
+pub enum TipCheckOutcome {
+    /// Our chain had an invalid block after the fork, so we moved to the disputed tip
+    OurChainInvalid,
+
+    /// Our chain is valid after the fork, so we banned the peers announcing the disputed tip
+    TipRejected,
+
+    /// We couldn't finish this check, see [`TipCheck::error`]
+    Failed,
+}
+
+impl Display for TipCheckOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            TipCheckOutcome::OurChainInvalid => "our_chain_invalid",
+            TipCheckOutcome::TipRejected => "tip_rejected",
+            TipCheckOutcome::Failed => "failed",
+        };
+
+        write!(f, "{outcome}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// A tip that disagreed with our best chain, and what we did about it
+pub struct TipCheck {
+    /// The disputed tip
+    pub tip: BlockHash,
+
+    /// Our best block when we started this check
+    pub best_block: BlockHash,
+
+    /// The last block in both chains, if we got to find it
+    pub fork_point: Option<BlockHash>,
+
+    /// The height of `fork_point`
+    pub fork_height: Option<u32>,
+
+    /// What we've concluded
+    pub outcome: TipCheckOutcome,
+
+    /// Why the check failed, if it did
+    pub error: Option<String>,
+
+    /// When we finished this check, as a UNIX timestamp
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// Whether we use PoW fraud proofs, and the tips we've checked with them
+pub struct FraudProofStatus {
+    /// Whether PoW fraud proofs are enabled
+    pub enabled: bool,
+
+    /// The tips we've checked, oldest first
+    pub checks: Vec<TipCheck>,
+}
+
+#[derive(Debug, Default)]
+/// Keeps the tips we've checked, optionally persisting them in our data directory
+pub struct FraudProofLog {
+    /// The last [MAX_TIP_CHECKS] checks, oldest first
+    checks: Vec<TipCheck>,
+
+    /// Where to save the checks. If `None`, they only live in memory
+    path: Option<PathBuf>,
+}
+
+impl FraudProofLog {
+    /// Loads the checks saved inside `datadir`, or starts a new log if there's none.
+    ///
+    /// A corrupted log is ignored (and will be overwritten on the next check), it's only there
+    /// for our user's information.
+    pub fn load(datadir: &str) -> Self {
+        let path = PathBuf::from(format!("{datadir}/{FRAUD_PROOFS_FILE}"));
+        let checks = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring invalid fraud proof log at {}: {e}",
+                    path.display()
+                );
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+
+        FraudProofLog {
+            checks,
+            path: Some(path),
+        }
+    }
+
+    /// Records a finished check, forgetting the oldest one if we're full
+    pub fn record(&mut self, mut check: TipCheck) -> io::Result<()> {
+        check.time = Self::now();
+        self.checks.push(check);
+
+        if self.checks.len() > MAX_TIP_CHECKS {
+            let excess = self.checks.len() - MAX_TIP_CHECKS;
+            self.checks.drain(..excess);
+        }
+
+        self.save()
+    }
+
+    /// All checks we remember, oldest first
+    pub fn checks(&self) -> &[TipCheck] {
+        &self.checks
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let content = serde_json::to_string_pretty(&self.checks).map_err(io::Error::other)?;
+        let tmp = path.with_extension("json.tmp");
+
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+
+    use super::FraudProofLog;
+    use super::TipCheck;
+    use super::TipCheckOutcome;
+    use super::MAX_TIP_CHECKS;
+
+    fn check(n: u8, outcome: TipCheckOutcome) -> TipCheck {
+        TipCheck {
+            tip: BlockHash::from_byte_array([n; 32]),
+            best_block: BlockHash::all_zeros(),
+            fork_point: None,
+            fork_height: None,
+            outcome,
+            error: None,
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn test_fraud_proof_log() {
+        let datadir = format!("./tmp-db/{}.fraud_proofs", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let mut log = FraudProofLog::load(&datadir);
+        assert!(log.checks().is_empty());
+
+        log.record(check(1, TipCheckOutcome::TipRejected)).unwrap();
+        log.record(check(2, TipCheckOutcome::OurChainInvalid))
+            .unwrap();
+
+        // Checks survive a restart, in the order they happened
+        let mut log = FraudProofLog::load(&datadir);
+        let outcomes: Vec<_> = log.checks().iter().map(|c| c.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                TipCheckOutcome::TipRejected,
+                TipCheckOutcome::OurChainInvalid
+            ]
+        );
+        assert!(log.checks().iter().all(|c| c.time > 0));
+
+        // Only the most recent checks are kept
+        for n in 0..MAX_TIP_CHECKS as u8 {
+            log.record(check(n, TipCheckOutcome::Failed)).unwrap();
+        }
+
+        let log = FraudProofLog::load(&datadir);
+        assert_eq!(log.checks().len(), MAX_TIP_CHECKS);
+        assert!(log
+            .checks()
+            .iter()
+            .all(|c| c.outcome == TipCheckOutcome::Failed));
+    }
+}
@@ -0,0 +1,758 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A client for the SAM v3 protocol, used to reach peers in the I2P network.
+//!
+//! I2P routers (like i2pd or Java I2P) don't give us a SOCKS proxy for arbitrary destinations,
+//! but they do expose the SAM bridge: a line-based text protocol over TCP. We open a control
+//! connection to the bridge and create a session in it, which gives us an I2P destination (our
+//! address in that network) for as long as the control connection stays open. Every stream,
+//! outgoing or incoming, then uses a new connection to the bridge that, after a short handshake,
+//! becomes a raw byte stream to the other side.
+//!
+//! Our destination can be transient, a new one for every session, or persistent, with its
+//! private key saved to a file so our I2P address survives restarts.
+//!
+//! I2P addresses in `addrv2` messages are the SHA256 of the peer's destination, which the router
+//! can resolve for us through its `<base32>.b32.i2p` name. See
+//! <https://geti2p.net/en/docs/api/samv3> for the protocol, and Bitcoin Core's `i2p.cpp`.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::net::SocketAddr;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use floresta_common::impl_error_from;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::debug;
+use tracing::info;
+
+/// The file, inside our data directory, where we keep our persistent I2P private key
+pub const I2P_PRIVATE_KEY_FILE: &str = "i2p_private_key";
+
+/// SAM 3.1 streams have no ports, so I2P addresses always use port 0, just like Bitcoin Core
+pub const I2P_SAM31_PORT: u16 = 0;
+
+/// The default port of the SAM bridge
+pub const DEFAULT_SAM_PORT: u16 = 7656;
+
+/// The SAM version we speak
+const SAM_VERSION: &str = "3.1";
+
+/// The longest line we accept from the SAM bridge
+const MAX_LINE_SIZE: usize = 65_536;
+
+/// The size of a destination, without its certificate
+const DESTINATION_MIN_SIZE: usize = 387;
+
+/// I2P uses base64 with `-` and `~` instead of `+` and `/`
+const I2P_BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-~";
+
+/// The RFC 4648 base32 alphabet, lowercase, as used by `.b32.i2p` names
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Debug)]
+pub enum I2pError {
+    /// We couldn't talk to the SAM bridge
+    Io(io::Error),
+
+    /// The SAM bridge refused our request
+    Refused {
+        /// The request we made, e.g. `STREAM CONNECT`
+        request: String,
+        /// The result code, e.g. `CANT_REACH_PEER`
+        result: String,
+        /// A human-readable explanation, if the bridge gave one
+        message: Option<String>,
+    },
+
+    /// The SAM bridge sent us something we don't understand
+    InvalidReply(String),
+
+    /// A private key or destination is malformed
+    InvalidDestination,
+}
+
+impl Display for I2pError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            I2pError::Io(e) => write!(f, "Error talking to the SAM bridge: {e}"),
+            I2pError::Refused {
+                request,
+                result,
+                message,
+            } => match message {
+                Some(message) => write!(f, "SAM bridge refused {request}: {result} ({message})"),
+                None => write!(f, "SAM bridge refused {request}: {result}"),
+            },
+            I2pError::InvalidReply(reply) => {
+                write!(f, "Invalid reply from the SAM bridge: {reply}")
+            }
+            I2pError::InvalidDestination => write!(f, "Invalid I2P destination"),
+        }
+    }
+}
+
+impl std::error::Error for I2pError {}
+
+impl_error_from!(I2pError, io::Error, Io);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Which I2P destination we use
+pub enum I2pIdentity {
+    /// A new destination for every session, so our I2P address changes on every restart
+    Transient,
+
+    /// A destination whose private key is kept in this file, created if it doesn't exist yet
+    Persistent(PathBuf),
+}
+
+/// A session we created, that lives as long as its control connection
+#[derive(Debug)]
+struct ActiveSession {
+    /// The id we gave this session, used to open streams in it
+    id: String,
+    /// The connection we created this session in. Closing it destroys the session
+    control: TcpStream,
+    /// Our address, the hash of our destination
+    address: [u8; 32],
+}
+
+impl ActiveSession {
+    /// Whether the bridge still has our control connection open
+    fn is_alive(&self) -> bool {
+        let mut buf = [0; 1];
+        match self.control.try_read(&mut buf) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SessionInner {
+    sam_address: SocketAddr,
+    identity: I2pIdentity,
+    /// Created on first use, and re-created if the router drops it
+    session: Mutex<Option<ActiveSession>>,
+}
+
+#[derive(Debug, Clone)]
+/// A SAM session, used to open streams to I2P peers and accept streams from them
+///
+/// The session is only created on first use, and is re-created if the router drops it. This is
+/// cheap to clone, all clones share the same session.
+pub struct I2pSession {
+    inner: Arc<SessionInner>,
+}
+
+impl I2pSession {
+    /// Creates a session handle for the SAM bridge listening on `sam_address`
+    pub fn new(sam_address: SocketAddr, identity: I2pIdentity) -> Self {
+        I2pSession {
+            inner: Arc::new(SessionInner {
+                sam_address,
+                identity,
+                session: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// The address of the SAM bridge we use
+    pub fn sam_address(&self) -> SocketAddr {
+        self.inner.sam_address
+    }
+
+    /// Returns our I2P address, creating the session if needed
+    pub async fn my_address(&self) -> Result<[u8; 32], I2pError> {
+        let (_, address) = self.session().await?;
+        Ok(address)
+    }
+
+    /// Opens a stream to the I2P peer with this address
+    pub async fn connect(&self, address: &[u8; 32]) -> Result<TcpStream, I2pError> {
+        let (id, _) = self.session().await?;
+        let mut stream = self.hello().await?;
+
+        let name = b32_name(address);
+        let lookup = send_request(
+            &mut stream,
+            &format!("NAMING LOOKUP NAME={name}"),
+            "NAMING REPLY",
+        )
+        .await?;
+
+        let destination = lookup
+            .get("VALUE")
+            .ok_or_else(|| I2pError::InvalidReply("NAMING REPLY without a VALUE".into()))?;
+
+        let connect = send_request(
+            &mut stream,
+            &format!("STREAM CONNECT ID={id} DESTINATION={destination} SILENT=false"),
+            "STREAM STATUS",
+        )
+        .await;
+
+        self.check_session(&connect).await;
+        connect?;
+
+        debug!("Opened an I2P stream to {name}");
+        Ok(stream)
+    }
+
+    /// Waits for an I2P peer to open a stream to us, returning it along with the peer's address
+    pub async fn accept(&self) -> Result<(TcpStream, [u8; 32]), I2pError> {
+        let (id, _) = self.session().await?;
+        let mut stream = self.hello().await?;
+
+        let accept = send_request(
+            &mut stream,
+            &format!("STREAM ACCEPT ID={id} SILENT=false"),
+            "STREAM STATUS",
+        )
+        .await;
+
+        self.check_session(&accept).await;
+        accept?;
+
+        // Once someone connects, the bridge sends us their destination, possibly followed by
+        // some options, in a line of its own
+        let peer = read_line(&mut stream).await?;
+        let destination = peer
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| I2pError::InvalidReply(peer.clone()))?;
+
+        let address = destination_to_address(&base64_decode(destination)?)?;
+
+        debug!("Accepted an I2P stream from {}", b32_name(&address));
+        Ok((stream, address))
+    }
+
+    /// Returns the id of our session and our address, creating the session if needed
+    async fn session(&self) -> Result<(String, [u8; 32]), I2pError> {
+        let mut session = self.inner.session.lock().await;
+
+        if let Some(active) = session.as_ref() {
+            if active.is_alive() {
+                return Ok((active.id.clone(), active.address));
+            }
+
+            info!("Lost our I2P session {}, creating a new one", active.id);
+        }
+
+        let active = self.create_session().await?;
+        let ret = (active.id.clone(), active.address);
+        *session = Some(active);
+
+        Ok(ret)
+    }
+
+    /// Forgets our session if the bridge doesn't know it anymore, so it's re-created next time
+    async fn check_session<T>(&self, reply: &Result<T, I2pError>) {
+        if let Err(I2pError::Refused { result, .. }) = reply {
+            if result == "INVALID_ID" {
+                self.inner.session.lock().await.take();
+            }
+        }
+    }
+
+    async fn create_session(&self) -> Result<ActiveSession, I2pError> {
+        let mut control = self.hello().await?;
+
+        let private_key = match &self.inner.identity {
+            I2pIdentity::Transient => None,
+            I2pIdentity::Persistent(path) => Some(load_or_generate_key(&mut control, path).await?),
+        };
+
+        let id = format!("{:016x}", rand::random::<u64>());
+        // Same options as Bitcoin Core: transient sessions are only used for a few outgoing
+        // connections, so they get fewer tunnels
+        let (destination, options) = match &private_key {
+            Some(key) => (key.as_str(), "inbound.quantity=3 outbound.quantity=3"),
+            None => (
+                "TRANSIENT",
+                "SIGNATURE_TYPE=7 inbound.quantity=1 outbound.quantity=1",
+            ),
+        };
+
+        let reply = send_request(
+            &mut control,
+            &format!(
+                "SESSION CREATE STYLE=STREAM ID={id} DESTINATION={destination} {options} i2cp.leaseSetEncType=4,0"
+            ),
+            "SESSION STATUS",
+        )
+        .await?;
+
+        let private_key = match private_key {
+            Some(key) => key,
+            None => reply.get("DESTINATION").cloned().ok_or_else(|| {
+                I2pError::InvalidReply("SESSION STATUS without DESTINATION".into())
+            })?,
+        };
+
+        let address = destination_to_address(&base64_decode(&private_key)?)?;
+        info!(
+            "Created I2P session {id}, our address is {}",
+            b32_name(&address)
+        );
+
+        Ok(ActiveSession {
+            id,
+            control,
+            address,
+        })
+    }
+
+    /// Opens a new connection to the SAM bridge and greets it
+    async fn hello(&self) -> Result<TcpStream, I2pError> {
+        let mut stream = TcpStream::connect(self.inner.sam_address).await?;
+        send_request(
+            &mut stream,
+            &format!("HELLO VERSION MIN={SAM_VERSION} MAX={SAM_VERSION}"),
+            "HELLO REPLY",
+        )
+        .await?;
+
+        Ok(stream)
+    }
+}
+
+/// Reads our private key from `path`, or asks the bridge for a new one and saves it there
+async fn load_or_generate_key(control: &mut TcpStream, path: &PathBuf) -> Result<String, I2pError> {
+    if let Ok(key) = fs::read_to_string(path) {
+        return Ok(key.trim().to_string());
+    }
+
+    let reply = send_request(control, "DEST GENERATE SIGNATURE_TYPE=7", "DEST REPLY").await?;
+    let key = reply
+        .get("PRIV")
+        .cloned()
+        .ok_or_else(|| I2pError::InvalidReply("DEST REPLY without PRIV".into()))?;
+
+    fs::write(path, &key)?;
+    info!("Saved our new I2P private key to {}", path.display());
+
+    Ok(key)
+}
+
+/// The `.b32.i2p` name for this address
+pub fn b32_name(address: &[u8; 32]) -> String {
+    format!("{}.b32.i2p", base32_encode(address))
+}
+
+/// Sends a request, and reads the reply that should start with `expected`, returning its fields.
+///
+/// Replies with a `RESULT` other than `OK` are turned into [I2pError::Refused].
+async fn send_request(
+    stream: &mut TcpStream,
+    request: &str,
+    expected: &str,
+) -> Result<HashMap<String, String>, I2pError> {
+    stream.write_all(format!("{request}\n").as_bytes()).await?;
+
+    let reply = read_line(stream).await?;
+    let fields = parse_reply(&reply, expected)?;
+
+    match fields.get("RESULT").map(String::as_str) {
+        None | Some("OK") => Ok(fields),
+        Some(result) => Err(I2pError::Refused {
+            // Only the command, so we never log a private key
+            request: request.split(' ').take(2).collect::<Vec<_>>().join(" "),
+            result: result.to_string(),
+            message: fields.get("MESSAGE").cloned(),
+        }),
+    }
+}
+
+/// Reads a single line from the bridge.
+///
+/// We read one byte at a time, as anything after the line belongs to the stream.
+async fn read_line(stream: &mut TcpStream) -> Result<String, I2pError> {
+    let mut line = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+
+        if line.len() >= MAX_LINE_SIZE {
+            return Err(I2pError::InvalidReply("line too long".into()));
+        }
+
+        line.push(byte);
+    }
+
+    String::from_utf8(line).map_err(|_| I2pError::InvalidReply("reply isn't utf-8".into()))
+}
+
+/// Parses `<expected> KEY=VALUE KEY="QUOTED VALUE" ...` into its fields
+fn parse_reply(reply: &str, expected: &str) -> Result<HashMap<String, String>, I2pError> {
+    let reply = reply.trim_end_matches('\r');
+    let Some(rest) = reply.strip_prefix(expected) else {
+        return Err(I2pError::InvalidReply(reply.to_string()));
+    };
+
+    let mut fields = HashMap::new();
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in rest.chars().chain([' ']) {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if let Some((key, value)) = token.split_once('=') {
+                    fields.insert(key.to_string(), value.to_string());
+                }
+
+                token.clear();
+            }
+            c => token.push(c),
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Our address is the hash of the public part of our destination, which is its first 387 bytes
+/// plus a certificate, whose length is in the last two of those bytes.
+fn destination_to_address(destination: &[u8]) -> Result<[u8; 32], I2pError> {
+    if destination.len() < DESTINATION_MIN_SIZE {
+        return Err(I2pError::InvalidDestination);
+    }
+
+    let cert_len = u16::from_be_bytes([
+        destination[DESTINATION_MIN_SIZE - 2],
+        destination[DESTINATION_MIN_SIZE - 1],
+    ]) as usize;
+
+    let public = destination
+        .get(..DESTINATION_MIN_SIZE + cert_len)
+        .ok_or(I2pError::InvalidDestination)?;
+
+    Ok(sha256::Hash::hash(public).to_byte_array())
+}
+
+fn base64_decode(data: &str) -> Result<Vec<u8>, I2pError> {
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0_u32;
+    let mut bits = 0;
+
+    for c in data.bytes().take_while(|&c| c != b'=') {
+        let value = I2P_BASE64_ALPHABET
+            .iter()
+            .position(|&x| x == c)
+            .ok_or(I2pError::InvalidDestination)? as u32;
+
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(out)
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut acc = 0_u32;
+    let mut bits = 0;
+
+    for &byte in data {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((acc >> bits) & 31) as usize] as char);
+        }
+        acc &= (1 << bits) - 1;
+    }
+
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((acc << (5 - bits)) & 31) as usize] as char);
+    }
+
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::net::SocketAddr;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use bitcoin::hashes::sha256;
+    use bitcoin::hashes::Hash;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
+
+    use super::base32_encode;
+    use super::base64_decode;
+    use super::parse_reply;
+    use super::read_line;
+    use super::I2pError;
+    use super::I2pIdentity;
+    use super::I2pSession;
+    use super::DESTINATION_MIN_SIZE;
+    use super::I2P_BASE64_ALPHABET;
+
+    fn base64_encode(data: &[u8]) -> String {
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let mut buf = [0; 3];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let acc = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+
+            for i in 0..=chunk.len() {
+                let value = (acc >> (18 - 6 * i)) & 63;
+                out.push(I2P_BASE64_ALPHABET[value as usize] as char);
+            }
+        }
+
+        let padding = (4 - out.len() % 4) % 4;
+        out.push_str(&"=".repeat(padding));
+
+        out
+    }
+
+    /// Creates a random keypair, returning the base64 public and private destinations, and the
+    /// address for it
+    fn random_destination() -> (String, String, [u8; 32]) {
+        // A destination with a 7 bytes key certificate, like the ones for signature type 7
+        let mut public: Vec<u8> = (0..DESTINATION_MIN_SIZE)
+            .map(|_| rand::random::<u8>())
+            .collect();
+        public[DESTINATION_MIN_SIZE - 2..].copy_from_slice(&7_u16.to_be_bytes());
+        public.extend((0..7).map(|_| rand::random::<u8>()));
+
+        let mut private = public.clone();
+        private.extend((0..64).map(|_| rand::random::<u8>()));
+
+        let address = sha256::Hash::hash(&public).to_byte_array();
+        (base64_encode(&public), base64_encode(&private), address)
+    }
+
+    #[derive(Default)]
+    struct MockSamState {
+        /// Sessions that are alive
+        sessions: HashSet<String>,
+        /// The public destination of the only peer in this mock network
+        peer: String,
+    }
+
+    /// A SAM bridge with a single peer, that echoes back everything we send it.
+    ///
+    /// When we accept a stream, that peer connects to us and says `hello`.
+    pub(crate) struct MockSam {
+        pub(crate) address: SocketAddr,
+        pub(crate) peer_address: [u8; 32],
+    }
+
+    impl MockSam {
+        pub(crate) async fn start() -> MockSam {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let (peer, _, peer_address) = random_destination();
+
+            let state = Arc::new(Mutex::new(MockSamState {
+                peer,
+                ..Default::default()
+            }));
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(Self::handle(stream, state.clone()));
+                }
+            });
+
+            MockSam {
+                address,
+                peer_address,
+            }
+        }
+
+        async fn handle(mut stream: TcpStream, state: Arc<Mutex<MockSamState>>) {
+            let mut session = None;
+
+            while let Ok(line) = read_line(&mut stream).await {
+                let mut words = line.splitn(3, ' ');
+                let command = format!(
+                    "{} {}",
+                    words.next().unwrap_or(""),
+                    words.next().unwrap_or("")
+                );
+                let fields = parse_reply(&line, &command).unwrap();
+                let field = |key: &str| fields.get(key).cloned().unwrap_or_default();
+
+                let reply = match command.as_str() {
+                    "HELLO VERSION" => "HELLO REPLY RESULT=OK VERSION=3.1".to_string(),
+                    "DEST GENERATE" => {
+                        let (public, private, _) = random_destination();
+                        format!("DEST REPLY PUB={public} PRIV={private}")
+                    }
+                    "SESSION CREATE" => {
+                        let id = field("ID");
+                        let destination = match field("DESTINATION").as_str() {
+                            "TRANSIENT" => random_destination().1,
+                            key => key.to_string(),
+                        };
+
+                        state.lock().unwrap().sessions.insert(id.clone());
+                        session = Some(id);
+                        format!("SESSION STATUS RESULT=OK DESTINATION={destination}")
+                    }
+                    "NAMING LOOKUP" => {
+                        let peer = state.lock().unwrap().peer.clone();
+                        let address = sha256::Hash::hash(&base64_decode(&peer).unwrap());
+                        let name = format!("{}.b32.i2p", base32_encode(address.as_ref()));
+
+                        match field("NAME") == name {
+                            true => format!("NAMING REPLY RESULT=OK NAME={name} VALUE={peer}"),
+                            false => "NAMING REPLY RESULT=KEY_NOT_FOUND".to_string(),
+                        }
+                    }
+                    "STREAM CONNECT" | "STREAM ACCEPT" => {
+                        let known = state.lock().unwrap().sessions.contains(&field("ID"));
+                        if !known {
+                            let reply = "STREAM STATUS RESULT=INVALID_ID MESSAGE=\"no session\"\n";
+                            let _ = stream.write_all(reply.as_bytes()).await;
+                            continue;
+                        }
+
+                        let _ = stream.write_all(b"STREAM STATUS RESULT=OK\n").await;
+                        if command == "STREAM ACCEPT" {
+                            let peer = state.lock().unwrap().peer.clone();
+                            let hello = format!("{peer} FROM_PORT=0 TO_PORT=0\nhello");
+                            let _ = stream.write_all(hello.as_bytes()).await;
+                        }
+
+                        // From now on, this is a stream to our peer, which echoes everything
+                        let mut buf = [0; 1024];
+                        while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                            let _ = stream.write_all(&buf[..n]).await;
+                        }
+
+                        return;
+                    }
+                    _ => "UNKNOWN".to_string(),
+                };
+
+                if stream
+                    .write_all(format!("{reply}\n").as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+
+            // Closing the control connection destroys the session
+            if let Some(id) = session {
+                state.lock().unwrap().sessions.remove(&id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        let data = b"floresta i2p";
+        assert_eq!(base64_decode(&base64_encode(data)).unwrap(), data);
+        assert_eq!(base64_decode("-~8=").unwrap(), [0xfb, 0xff]);
+        assert!(base64_decode("a+b/").is_err());
+
+        // RFC 4648 test vectors, lowercase and without padding
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "my");
+        assert_eq!(base32_encode(b"fo"), "mzxq");
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+
+        // A .b32.i2p name always has 52 characters
+        assert_eq!(base32_encode(&[0xff; 32]).len(), 52);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = "STREAM STATUS RESULT=CANT_REACH_PEER MESSAGE=\"peer not found\"";
+        let fields = parse_reply(reply, "STREAM STATUS").unwrap();
+        assert_eq!(fields["RESULT"], "CANT_REACH_PEER");
+        assert_eq!(fields["MESSAGE"], "peer not found");
+
+        assert!(parse_reply("HELLO REPLY RESULT=OK", "STREAM STATUS").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let sam = MockSam::start().await;
+        let session = I2pSession::new(sam.address, I2pIdentity::Transient);
+
+        let mut stream = session.connect(&sam.peer_address).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // This peer doesn't exist
+        let err = session.connect(&[1; 32]).await.unwrap_err();
+        assert!(
+            matches!(err, I2pError::Refused { ref result, .. } if result == "KEY_NOT_FOUND"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accept() {
+        let sam = MockSam::start().await;
+        let session = I2pSession::new(sam.address, I2pIdentity::Transient);
+
+        let (mut stream, peer) = session.accept().await.unwrap();
+        assert_eq!(peer, sam.peer_address);
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_identity() {
+        let sam = MockSam::start().await;
+        let datadir = format!("./tmp-db/{}.i2p", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+        let key_file = std::path::PathBuf::from(format!("{datadir}/i2p_private_key"));
+
+        let persistent = I2pSession::new(sam.address, I2pIdentity::Persistent(key_file.clone()));
+        let address = persistent.my_address().await.unwrap();
+        assert!(key_file.exists());
+
+        // Same key, same address
+        let restarted = I2pSession::new(sam.address, I2pIdentity::Persistent(key_file));
+        assert_eq!(restarted.my_address().await.unwrap(), address);
+
+        // Transient sessions always get a new address
+        let transient = I2pSession::new(sam.address, I2pIdentity::Transient);
+        let other = I2pSession::new(sam.address, I2pIdentity::Transient);
+        assert_ne!(transient.my_address().await.unwrap(), address);
+        assert_ne!(
+            transient.my_address().await.unwrap(),
+            other.my_address().await.unwrap()
+        );
+    }
+}
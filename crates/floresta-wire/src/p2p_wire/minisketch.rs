@@ -0,0 +1,493 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A pure-Rust implementation of [minisketch](https://github.com/sipa/minisketch), restricted
+//! to the 32-bit field used by Erlay (BIP 330).
+//!
+//! A sketch with capacity `c` holds the odd power sums `s_1, s_3, ..., s_(2c-1)` of a set of
+//! non-zero field elements. Adding an element twice removes it, so merging (xor-ing) two
+//! sketches gives a sketch of the symmetric difference of both sets, which can be decoded if it
+//! has at most `c` elements.
+//!
+//! Decoding recovers the even power sums (`s_2k = s_k^2` in characteristic 2), finds the
+//! polynomial whose roots are the elements with Berlekamp-Massey, and then finds its roots with
+//! the Berlekamp trace algorithm.
+
+/// How many bits each element has
+pub const FIELD_BITS: usize = 32;
+
+/// The irreducible polynomial defining our field, `x^32 + x^7 + x^3 + x^2 + 1`
+const MODULUS: u64 = (1 << 32) | 0x8D;
+
+/// Multiplies two elements of GF(2^32)
+fn field_mul(a: u32, b: u32) -> u32 {
+    // Carry-less multiplication
+    let mut product = 0u64;
+    let mut a = a as u64;
+    let mut b = b;
+    while b != 0 {
+        if b & 1 == 1 {
+            product ^= a;
+        }
+
+        a <<= 1;
+        b >>= 1;
+    }
+
+    // Reduce it back to 32 bits
+    for bit in (FIELD_BITS..64).rev() {
+        if (product >> bit) & 1 == 1 {
+            product ^= MODULUS << (bit - FIELD_BITS);
+        }
+    }
+
+    product as u32
+}
+
+fn field_sqr(a: u32) -> u32 {
+    field_mul(a, a)
+}
+
+/// Computes the multiplicative inverse of a non-zero element, as `a^(2^32 - 2)`
+fn field_inv(a: u32) -> u32 {
+    debug_assert_ne!(a, 0, "zero has no inverse");
+
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = u32::MAX - 1;
+    while exponent != 0 {
+        if exponent & 1 == 1 {
+            result = field_mul(result, base);
+        }
+
+        base = field_sqr(base);
+        exponent >>= 1;
+    }
+
+    result
+}
+
+/// Polynomials over GF(2^32), with coefficients from the lowest to the highest degree
+type Poly = Vec<u32>;
+
+/// Removes leading zeros, so the last coefficient is the highest non-zero one
+fn poly_trim(poly: &mut Poly) {
+    while poly.last() == Some(&0) {
+        poly.pop();
+    }
+}
+
+/// The remainder of `poly` divided by `modulus`, which must be monic
+fn poly_mod(mut poly: Poly, modulus: &[u32]) -> Poly {
+    let degree = modulus.len() - 1;
+    poly_trim(&mut poly);
+
+    while poly.len() > degree {
+        let coefficient = poly.pop().expect("poly isn't empty");
+        let offset = poly.len() - degree;
+        for (i, m) in modulus[..degree].iter().enumerate() {
+            poly[offset + i] ^= field_mul(coefficient, *m);
+        }
+
+        poly_trim(&mut poly);
+    }
+
+    poly
+}
+
+/// Multiplies two polynomials, modulo a monic polynomial
+fn poly_mul_mod(a: &[u32], b: &[u32], modulus: &[u32]) -> Poly {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+
+    let mut product = vec![0; a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        if *x == 0 {
+            continue;
+        }
+
+        for (j, y) in b.iter().enumerate() {
+            product[i + j] ^= field_mul(*x, *y);
+        }
+    }
+
+    poly_mod(product, modulus)
+}
+
+/// Divides a polynomial by its leading coefficient
+fn poly_monic(mut poly: Poly) -> Poly {
+    poly_trim(&mut poly);
+    if let Some(&leading) = poly.last() {
+        let inverse = field_inv(leading);
+        for coefficient in poly.iter_mut() {
+            *coefficient = field_mul(*coefficient, inverse);
+        }
+    }
+
+    poly
+}
+
+/// The monic greatest common divisor of two polynomials
+fn poly_gcd(a: Poly, b: Poly) -> Poly {
+    let mut a = poly_monic(a);
+    let mut b = poly_monic(b);
+    while !b.is_empty() {
+        let remainder = poly_monic(poly_mod(a, &b));
+        a = b;
+        b = remainder;
+    }
+
+    a
+}
+
+/// Divides `poly` by a monic `divisor`, assuming there is no remainder
+fn poly_div(mut poly: Poly, divisor: &[u32]) -> Poly {
+    let degree = divisor.len() - 1;
+    poly_trim(&mut poly);
+    if poly.len() < divisor.len() {
+        return vec![0];
+    }
+
+    let mut quotient = vec![0; poly.len() - degree];
+    while poly.len() > degree {
+        let coefficient = poly.pop().expect("poly isn't empty");
+        let offset = poly.len() - degree;
+        quotient[offset] = coefficient;
+        for (i, d) in divisor[..degree].iter().enumerate() {
+            poly[offset + i] ^= field_mul(coefficient, *d);
+        }
+    }
+
+    quotient
+}
+
+/// Finds the shortest linear recurrence generating `syndromes`, returning its connection
+/// polynomial, or `None` if it has a degree higher than `max_degree`.
+fn berlekamp_massey(syndromes: &[u32], max_degree: usize) -> Option<Poly> {
+    let mut current: Poly = vec![1];
+    let mut previous: Poly = vec![1];
+    let mut length = 0;
+    let mut shift = 1;
+    let mut previous_discrepancy = 1;
+
+    for n in 0..syndromes.len() {
+        let mut discrepancy = syndromes[n];
+        for i in 1..=length.min(current.len() - 1) {
+            discrepancy ^= field_mul(current[i], syndromes[n - i]);
+        }
+
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let factor = field_mul(discrepancy, field_inv(previous_discrepancy));
+        let last = current.clone();
+        if current.len() < previous.len() + shift {
+            current.resize(previous.len() + shift, 0);
+        }
+
+        for (i, coefficient) in previous.iter().enumerate() {
+            current[i + shift] ^= field_mul(factor, *coefficient);
+        }
+
+        if 2 * length <= n {
+            length = n + 1 - length;
+            previous = last;
+            previous_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+
+        if length > max_degree {
+            return None;
+        }
+    }
+
+    current.resize(length + 1, 0);
+    Some(current)
+}
+
+/// Finds the roots of a monic polynomial that splits into distinct linear factors, by
+/// splitting it with `gcd(poly, Tr(beta * x))` for different values of `beta`.
+fn find_roots(poly: Poly, first_beta: usize, roots: &mut Vec<u32>) -> bool {
+    match poly.len() {
+        0 | 1 => return true,
+        // x + r
+        2 => {
+            roots.push(poly[0]);
+            return true;
+        }
+        _ => {}
+    }
+
+    // For any two distinct roots, Tr(beta * root) differs for some beta in our basis. If a beta
+    // doesn't split this polynomial, it won't split any of its factors either.
+    for bit in first_beta..FIELD_BITS {
+        let beta = 1u32 << bit;
+
+        // Tr(beta * x) = sum (beta * x)^(2^i), for i in 0..32
+        let mut term = poly_mod(vec![0, beta], &poly);
+        let mut trace = term.clone();
+        for _ in 1..FIELD_BITS {
+            term = poly_mul_mod(&term, &term, &poly);
+            if trace.len() < term.len() {
+                trace.resize(term.len(), 0);
+            }
+
+            for (i, coefficient) in term.iter().enumerate() {
+                trace[i] ^= coefficient;
+            }
+        }
+
+        let factor = poly_gcd(poly.clone(), trace);
+        if factor.len() <= 1 || factor.len() == poly.len() {
+            continue;
+        }
+
+        let cofactor = poly_div(poly, &factor);
+        return find_roots(factor, bit + 1, roots) && find_roots(cofactor, bit + 1, roots);
+    }
+
+    false
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A sketch of a set of 32-bit, non-zero, elements
+pub struct Minisketch {
+    /// The odd power sums of our elements
+    syndromes: Vec<u32>,
+}
+
+impl Minisketch {
+    /// Creates an empty sketch, that can decode up to `capacity` elements
+    pub fn new(capacity: usize) -> Self {
+        Minisketch {
+            syndromes: vec![0; capacity],
+        }
+    }
+
+    /// How many elements this sketch can decode
+    pub fn capacity(&self) -> usize {
+        self.syndromes.len()
+    }
+
+    /// Adds an element to the sketch, or removes it, if it was already there. Zero can't be
+    /// added, and is ignored.
+    pub fn add(&mut self, element: u32) {
+        if element == 0 {
+            return;
+        }
+
+        let square = field_sqr(element);
+        let mut power = element;
+        for syndrome in self.syndromes.iter_mut() {
+            *syndrome ^= power;
+            power = field_mul(power, square);
+        }
+    }
+
+    /// Combines two sketches into a sketch of the symmetric difference of their sets.
+    ///
+    /// If their capacities don't match, the result has the smallest one.
+    pub fn merge(&mut self, other: &Minisketch) {
+        self.truncate(other.capacity());
+        for (syndrome, other) in self.syndromes.iter_mut().zip(other.syndromes.iter()) {
+            *syndrome ^= other;
+        }
+    }
+
+    /// Reduces this sketch's capacity. The result is the same as if we had created it with this
+    /// capacity in the first place.
+    pub fn truncate(&mut self, capacity: usize) {
+        self.syndromes.truncate(capacity);
+    }
+
+    /// Serializes this sketch, as four little-endian bytes for each power sum
+    pub fn serialize(&self) -> Vec<u8> {
+        self.syndromes
+            .iter()
+            .flat_map(|syndrome| syndrome.to_le_bytes())
+            .collect()
+    }
+
+    /// Parses a serialized sketch, returning `None` if its length isn't a multiple of four
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        let chunks = data.chunks_exact(4);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+
+        let syndromes = chunks
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().expect("chunks have 4 bytes")))
+            .collect();
+
+        Some(Minisketch { syndromes })
+    }
+
+    /// Recovers the elements in this sketch. Returns `None` if there are more than
+    /// `max_elements`, or more than this sketch's capacity.
+    pub fn decode(&self, max_elements: usize) -> Option<Vec<u32>> {
+        let max_elements = max_elements.min(self.capacity());
+
+        // All the power sums, s_1 to s_2c. For even ones, s_2k = s_k^2.
+        let mut power_sums = vec![0; 2 * self.capacity()];
+        for (i, syndrome) in self.syndromes.iter().enumerate() {
+            power_sums[2 * i] = *syndrome;
+        }
+
+        for i in (1..power_sums.len()).step_by(2) {
+            power_sums[i] = field_sqr(power_sums[i / 2]);
+        }
+
+        let connection = berlekamp_massey(&power_sums, max_elements)?;
+
+        // Our elements are the inverses of the roots of the connection polynomial, or
+        // equivalently, the roots of it with reversed coefficients.
+        let locator: Poly = connection.into_iter().rev().collect();
+        let locator = poly_monic(locator);
+        let degree = locator.len().saturating_sub(1);
+        if degree == 0 {
+            return Some(Vec::new());
+        }
+
+        // x^(2^32) = x (mod locator) if, and only if, it splits into distinct linear factors.
+        // Otherwise, we had too many elements to decode.
+        let mut power = poly_mod(vec![0, 1], &locator);
+        for _ in 0..FIELD_BITS {
+            power = poly_mul_mod(&power, &power, &locator);
+        }
+
+        if power != poly_mod(vec![0, 1], &locator) {
+            return None;
+        }
+
+        let mut roots = Vec::with_capacity(degree);
+        if !find_roots(locator, 0, &mut roots) || roots.len() != degree || roots.contains(&0) {
+            return None;
+        }
+
+        Some(roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::field_inv;
+    use super::field_mul;
+    use super::Minisketch;
+
+    /// A deterministic source of non-zero elements, so our tests are reproducible
+    fn elements(seed: u64, count: usize) -> Vec<u32> {
+        let mut state = seed;
+        let mut elements = HashSet::new();
+        while elements.len() < count {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+
+            let element = (state >> 32) as u32;
+            if element != 0 {
+                elements.insert(element);
+            }
+        }
+
+        elements.into_iter().collect()
+    }
+
+    #[test]
+    fn test_field() {
+        for a in elements(1, 100) {
+            assert_eq!(field_mul(a, 1), a);
+            assert_eq!(field_mul(a, 0), 0);
+            assert_eq!(field_mul(a, field_inv(a)), 1);
+        }
+
+        // x^31 * x = x^32 = x^7 + x^3 + x^2 + 1
+        assert_eq!(field_mul(1 << 31, 2), 0x8D);
+    }
+
+    #[test]
+    fn test_decode() {
+        for count in [0, 1, 2, 7, 20] {
+            let elements = elements(count as u64, count);
+            let mut sketch = Minisketch::new(20);
+            for element in elements.iter() {
+                sketch.add(*element);
+            }
+
+            let mut decoded = sketch.decode(20).unwrap();
+            let mut expected = elements.clone();
+            decoded.sort();
+            expected.sort();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let shared = elements(42, 50);
+        let only_ours = elements(43, 5);
+        let only_theirs = elements(44, 3);
+
+        let mut ours = Minisketch::new(10);
+        let mut theirs = Minisketch::new(12);
+        for element in shared.iter().chain(only_ours.iter()) {
+            ours.add(*element);
+        }
+
+        for element in shared.iter().chain(only_theirs.iter()) {
+            theirs.add(*element);
+        }
+
+        ours.merge(&theirs);
+        assert_eq!(ours.capacity(), 10);
+
+        let mut decoded = ours.decode(10).unwrap();
+        let mut expected: Vec<_> = only_ours.into_iter().chain(only_theirs).collect();
+        decoded.sort();
+        expected.sort();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_too_many_elements() {
+        let mut sketch = Minisketch::new(10);
+        for element in elements(7, 15) {
+            sketch.add(element);
+        }
+
+        assert_eq!(sketch.decode(10), None);
+
+        // Adding the same element twice removes it
+        let mut sketch = Minisketch::new(10);
+        sketch.add(5);
+        sketch.add(5);
+        assert_eq!(sketch.decode(10), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut sketch = Minisketch::new(2);
+        sketch.add(2);
+
+        // s_1 = x, s_3 = x^3
+        assert_eq!(sketch.serialize(), vec![2, 0, 0, 0, 8, 0, 0, 0]);
+        assert_eq!(Minisketch::deserialize(&sketch.serialize()), Some(sketch));
+        assert_eq!(Minisketch::deserialize(&[0; 5]), None);
+
+        // A truncated sketch is the same as a sketch with smaller capacity
+        let mut big = Minisketch::new(8);
+        let mut small = Minisketch::new(3);
+        for element in elements(3, 3) {
+            big.add(element);
+            small.add(element);
+        }
+
+        big.truncate(3);
+        assert_eq!(big, small);
+    }
+}
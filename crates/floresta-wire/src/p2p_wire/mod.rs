@@ -7,6 +7,7 @@ use core::net::SocketAddr;
 
 use bitcoin::Network;
 use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::ChainParams;
 
 #[derive(Debug, Clone)]
 /// Configuration for the Utreexo node.
@@ -14,11 +15,19 @@ pub struct UtreexoNodeConfig {
     /// The blockchain we are in, defaults to Bitcoin. Possible values are Bitcoin,
     /// Testnet, Regtest and Signet.
     pub network: Network,
+    /// Custom parameters for the chain we are in, like a custom signet. Defaults to None,
+    /// meaning the default parameters for `network`.
+    ///
+    /// If set, `network` must be the network these parameters are based on.
+    pub chain_params: Option<ChainParams>,
     /// Whether to use PoW fraud proofs. Defaults to false.
     ///
     /// PoW fraud proof is a mechanism to skip the verification of the whole blockchain,
-    /// but while also giving a better security than simple SPV. Check out the documentation
-    /// in `pow_fraud_proofs.md` under the `docs` folder.
+    /// but while also giving a better security than simple SPV. Check out the "PoW Fraud
+    /// Proofs" section of `run.md`, under the `doc` folder.
+    ///
+    /// The tips we check, and what we concluded about them, are saved to `pow_fraud_proofs.json`
+    /// inside our data directory.
     pub pow_fraud_proofs: bool,
     /// Whether to use compact filters. Defaults to false.
     ///
@@ -49,6 +58,14 @@ pub struct UtreexoNodeConfig {
     /// is that we are vulnerable to a fraud proof attack for a few hours, but we can spot it
     /// and react in a couple of hours at most, so the attack window is very small.
     pub backfill: bool,
+    /// Trusted accumulators at intermediate heights, used to backfill in parallel. Defaults to
+    /// none, meaning we backfill from genesis in a single range.
+    ///
+    /// The assumed chain is split at each of these heights, and each range is downloaded and
+    /// validated by its own sync node, starting from the accumulator given here. Once a range
+    /// finishes, its final accumulator must match the one the next range started from, so we
+    /// don't rely on these values for anything but speed.
+    pub backfill_checkpoints: Vec<AssumeUtreexoValue>,
     /// If we are using network-provided block filters, we may not need to download the whole
     /// chain of filters, as our wallets may not have been created at the beginning of the chain.
     /// With this option, we can make a rough estimate of the block height we need to start
@@ -65,6 +82,33 @@ pub struct UtreexoNodeConfig {
     pub allow_v1_fallback: bool,
     /// Whether to disable DNS seeds. Defaults to false.
     pub disable_dns_seeds: bool,
+    /// The SAM bridge of an I2P router, used to connect with I2P peers. Defaults to None.
+    pub i2p_sam: Option<SocketAddr>,
+    /// Whether to keep the same I2P address across restarts. Defaults to false.
+    ///
+    /// If set, our I2P private key is saved to `i2p_private_key` inside our data directory,
+    /// otherwise we get a new I2P address every time we start.
+    pub i2p_persistent: bool,
+    /// Whether to broadcast our own transactions privately. Defaults to false.
+    ///
+    /// If set, each transaction our user sends is pushed through a new, short-lived connection
+    /// to a random peer, made through our proxy or I2P, instead of being announced to our
+    /// regular peers. Once other peers announce it back to us, we know it propagated. This
+    /// requires either `proxy` or `i2p_sam`.
+    pub private_broadcast: bool,
+    /// How many bytes we may upload every 24 hours. Defaults to None, meaning no limit.
+    ///
+    /// Once we get close to this target, we stop answering bandwidth-heavy requests from our
+    /// peers, and once we reach it, we also stop serving mempool transactions. We always keep
+    /// downloading and validating new blocks.
+    pub max_upload_target: Option<u64>,
+    /// Where to accept connections from other peers. Defaults to None, meaning we only make
+    /// outbound connections.
+    ///
+    /// Florestad binds this to a local port and publishes it through our onion service, so
+    /// peers can reach us without us opening any ports in our router. Port zero picks a free
+    /// one, see [`UtreexoNode::listen_address`](node::UtreexoNode::listen_address).
+    pub listen: Option<SocketAddr>,
 }
 
 impl Default for UtreexoNodeConfig {
@@ -72,6 +116,7 @@ impl Default for UtreexoNodeConfig {
         UtreexoNodeConfig {
             disable_dns_seeds: false,
             network: Network::Bitcoin,
+            chain_params: None,
             pow_fraud_proofs: false,
             compact_filters: false,
             fixed_peer: None,
@@ -79,23 +124,40 @@ impl Default for UtreexoNodeConfig {
             datadir: ".floresta-node".to_string(),
             proxy: None,
             backfill: false,
+            backfill_checkpoints: Vec::new(),
             assume_utreexo: None,
             filter_start_height: None,
             user_agent: format!("floresta:{}", env!("CARGO_PKG_VERSION")),
             allow_v1_fallback: true,
+            i2p_sam: None,
+            i2p_persistent: false,
+            private_broadcast: false,
+            max_upload_target: None,
+            listen: None,
         }
     }
 }
 
 pub mod address_man;
+pub mod asmap;
+pub mod ban_man;
 pub mod block_proof;
 pub mod error;
+pub mod fraud_proofs;
+pub mod i2p;
+pub mod minisketch;
 pub mod node;
 pub mod node_context;
 pub mod node_interface;
+pub mod package_relay;
 pub mod peer;
+pub mod rescan;
 pub mod socks;
 #[cfg(test)]
 #[doc(hidden)]
 pub mod tests;
+pub mod tor_control;
+pub mod traffic;
 pub mod transport;
+pub mod tx_proof;
+pub mod txreconciliation;
@@ -0,0 +1,967 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Downloading and validating the blocks we've assumed, in parallel.
+//!
+//! With assumeutreexo or PoW fraud proofs, we skip validating most of the chain at startup. If
+//! [`UtreexoNodeConfig::backfill`] is set, we validate those blocks in the background afterwards.
+//!
+//! The assumed chain is split in ranges, one starting at genesis and one more for each of
+//! [`UtreexoNodeConfig::backfill_checkpoints`]. Each range is driven by its own sync node, with
+//! its own peers, holding a [`PartialChainState`] that starts from the accumulator at the
+//! beginning of that range. Every range's progress is saved on its own file, so we can stop at
+//! any time and resume where we left off.
+//!
+//! A checkpoint is only trusted until the range before it finishes. At that point, the final
+//! accumulator of that range must match the one the next range started from; otherwise, we
+//! validate the next range again, starting from the accumulator we computed ourselves. Likewise,
+//! if a range that started from a checkpoint finds an invalid block, we can't tell whether the
+//! block or the checkpoint is wrong, so it waits for the range before it and starts over. Only
+//! after every range is chained back to genesis, we mark those blocks as valid. If a range that
+//! started from a verified accumulator finds an invalid block, we've assumed an invalid chain: we
+//! stop that range and tell the node, which marks that block as invalid.
+//!
+//! Block validation is CPU-bound, so each range runs on its own thread, instead of holding the
+//! runtime's worker threads that also handle our network.
+//!
+//! [`UtreexoNodeConfig::backfill`]: crate::UtreexoNodeConfig::backfill
+//! [`UtreexoNodeConfig::backfill_checkpoints`]: crate::UtreexoNodeConfig::backfill_checkpoints
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bitcoin::BlockHash;
+use floresta_chain::proof_util;
+use floresta_chain::pruned_utreexo::partial_chain::PartialChainState;
+use floresta_chain::pruned_utreexo::BlockchainInterface;
+use floresta_chain::pruned_utreexo::UpdatableChainstate;
+use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::ThreadSafeChain;
+use floresta_mempool::Mempool;
+use rustreexo::stump::Stump;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Mutex;
+use tokio::sync::RwLock;
+use tokio::time;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+use super::running_ctx::RunningNode;
+use super::sync_ctx::SyncNode;
+use super::NodeNotification;
+use super::UtreexoNode;
+use crate::address_man::AddressMan;
+use crate::p2p_wire::error::WireError;
+use crate::UtreexoNodeConfig;
+
+/// The file where we used to keep the state of a single backfill range. Once we are done
+/// backfilling, we leave it empty, so we know we don't have to do it again.
+const BACKFILL_DONE_FILE: &str = ".sync_node_state";
+
+/// The directory, inside our datadir, where we keep the state of each backfill range
+const BACKFILL_RANGES_DIR: &str = ".backfill";
+
+/// How often we check whether the node is shutting down, to stop our workers
+const KILL_SIGNAL_CHECK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where a backfill range starts from
+pub(crate) enum RangeStart {
+    /// An accumulator we computed ourselves, either the empty one at genesis, or the final
+    /// accumulator of the previous range, once that one is verified too
+    Verified(Stump),
+
+    /// An accumulator we got from a checkpoint, and should be checked against the final
+    /// accumulator of the previous range
+    Trusted(Stump),
+
+    /// We don't know this accumulator yet, and have to wait for the previous range to finish
+    Pending,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A range of blocks we are backfilling, and how far we've got
+pub(crate) struct BackfillRange {
+    /// The height this range starts from, the first block we validate is the one after it
+    pub(crate) start: u32,
+
+    /// The last block in this range
+    pub(crate) end: u32,
+
+    /// The accumulator at `start`
+    pub(crate) start_acc: RangeStart,
+
+    /// The last block we've validated in this range
+    pub(crate) tip: u32,
+
+    /// The accumulator at `tip`
+    pub(crate) acc: Stump,
+}
+
+impl BackfillRange {
+    /// Creates a range we haven't started validating yet
+    fn new(start: u32, end: u32, start_acc: RangeStart) -> Self {
+        let acc = match &start_acc {
+            RangeStart::Verified(acc) | RangeStart::Trusted(acc) => acc.clone(),
+            RangeStart::Pending => Stump::default(),
+        };
+
+        BackfillRange {
+            start,
+            end,
+            start_acc,
+            tip: start,
+            acc,
+        }
+    }
+
+    /// Whether we've validated all blocks in this range
+    pub(crate) fn is_done(&self) -> bool {
+        self.tip == self.end
+    }
+
+    /// Whether the accumulator this range started from was computed by us
+    pub(crate) fn is_verified(&self) -> bool {
+        matches!(self.start_acc, RangeStart::Verified(_))
+    }
+
+    /// Whether we can start validating this range
+    fn can_start(&self) -> bool {
+        !self.is_done() && self.start_acc != RangeStart::Pending
+    }
+
+    /// Splits the blocks up to `end` in ranges, one starting at genesis and one for each
+    /// checkpoint. We expect checkpoints to be sorted by height, and below `end`.
+    pub(crate) fn split(end: u32, checkpoints: &[AssumeUtreexoValue]) -> Vec<BackfillRange> {
+        let mut starts = vec![(0, RangeStart::Verified(Stump::default()))];
+        for checkpoint in checkpoints {
+            let acc = Stump {
+                leaves: checkpoint.leaves,
+                roots: checkpoint.roots.clone(),
+            };
+
+            starts.push((checkpoint.height, RangeStart::Trusted(acc)));
+        }
+
+        let ends = starts
+            .iter()
+            .skip(1)
+            .map(|(height, _)| *height)
+            .chain([end])
+            .collect::<Vec<_>>();
+
+        starts
+            .into_iter()
+            .zip(ends)
+            .map(|((start, start_acc), end)| BackfillRange::new(start, end, start_acc))
+            .collect()
+    }
+
+    /// Serializes this range, to save it on disk
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.start.to_le_bytes());
+        data.extend_from_slice(&self.end.to_le_bytes());
+        data.extend_from_slice(&self.tip.to_le_bytes());
+
+        match &self.start_acc {
+            RangeStart::Verified(acc) => {
+                data.push(0);
+                acc.serialize(&mut data)
+                    .expect("writing to a vec can't fail");
+            }
+            RangeStart::Trusted(acc) => {
+                data.push(1);
+                acc.serialize(&mut data)
+                    .expect("writing to a vec can't fail");
+            }
+            RangeStart::Pending => data.push(2),
+        }
+
+        self.acc
+            .serialize(&mut data)
+            .expect("writing to a vec can't fail");
+
+        data
+    }
+
+    /// Reads a range we've serialized before
+    pub(crate) fn deserialize(mut data: &[u8]) -> Option<Self> {
+        let read_u32 = |data: &mut &[u8]| -> Option<u32> {
+            let (value, rest) = data.split_first_chunk::<4>()?;
+            *data = rest;
+            Some(u32::from_le_bytes(*value))
+        };
+
+        let start = read_u32(&mut data)?;
+        let end = read_u32(&mut data)?;
+        let tip = read_u32(&mut data)?;
+
+        let (kind, rest) = data.split_first()?;
+        data = rest;
+
+        let start_acc = match kind {
+            0 => RangeStart::Verified(Stump::deserialize(&mut data).ok()?),
+            1 => RangeStart::Trusted(Stump::deserialize(&mut data).ok()?),
+            2 => RangeStart::Pending,
+            _ => return None,
+        };
+
+        let acc = Stump::deserialize(&mut data).ok()?;
+        if !data.is_empty() || start > tip || tip > end {
+            return None;
+        }
+
+        Some(BackfillRange {
+            start,
+            end,
+            start_acc,
+            tip,
+            acc,
+        })
+    }
+
+    /// Reads a range saved by older versions, that always backfilled a single range from
+    /// genesis: the accumulator at our tip, followed by our tip and the last block
+    fn from_legacy_state(state: &[u8]) -> Option<Self> {
+        let len = state.len().checked_sub(8)?;
+        let acc = Stump::deserialize(&state[..len]).ok()?;
+        let tip = u32::from_le_bytes(state[len..(len + 4)].try_into().ok()?);
+        let end = u32::from_le_bytes(state[(len + 4)..].try_into().ok()?);
+
+        if tip > end {
+            return None;
+        }
+
+        Some(BackfillRange {
+            start: 0,
+            end,
+            start_acc: RangeStart::Verified(Stump::default()),
+            tip,
+            acc,
+        })
+    }
+}
+
+/// Chains the final accumulator of each finished range into the next one.
+///
+/// For each range whose previous range is verified and done, we check the accumulator it started
+/// from. If it was pending, it starts from there now. If it came from a checkpoint and matches,
+/// it becomes verified. If it doesn't match, the checkpoint is wrong, and we must validate that
+/// range again. Returns the start height of every range we've changed.
+pub(crate) fn chain_ranges(ranges: &mut [BackfillRange]) -> Vec<u32> {
+    let mut changed = Vec::new();
+
+    for i in 1..ranges.len() {
+        let (before, after) = ranges.split_at_mut(i);
+        let previous = &before[i - 1];
+        let range = &mut after[0];
+
+        if !previous.is_done() || !previous.is_verified() {
+            continue;
+        }
+
+        match &range.start_acc {
+            RangeStart::Verified(_) => continue,
+            RangeStart::Trusted(acc) if *acc == previous.acc => {
+                info!(
+                    "Backfill checkpoint at height {} matches the previous range",
+                    range.start
+                );
+                range.start_acc = RangeStart::Verified(acc.clone());
+            }
+            RangeStart::Trusted(_) => {
+                warn!(
+                    "Backfill checkpoint at height {} doesn't match the previous range, validating blocks {}..={} again",
+                    range.start,
+                    range.start + 1,
+                    range.end
+                );
+                *range = BackfillRange::new(
+                    range.start,
+                    range.end,
+                    RangeStart::Verified(previous.acc.clone()),
+                );
+            }
+            RangeStart::Pending => {
+                *range = BackfillRange::new(
+                    range.start,
+                    range.end,
+                    RangeStart::Verified(previous.acc.clone()),
+                );
+            }
+        }
+
+        changed.push(range.start);
+    }
+
+    changed
+}
+
+/// What a worker tells us once it stops
+struct RangeResult {
+    /// The start of the range this worker was validating
+    start: u32,
+
+    /// Which worker this is, so we can ignore the ones we've replaced
+    worker: u64,
+
+    /// The last block this worker validated
+    tip: u32,
+
+    /// The accumulator at `tip`
+    acc: Stump,
+
+    /// The block this worker found to be invalid, if any
+    invalid_block: Option<BlockHash>,
+}
+
+/// A sync node validating one of our ranges
+struct Worker {
+    /// An unique id for this worker
+    id: u64,
+
+    /// Tells only this worker to stop
+    kill_signal: Arc<RwLock<bool>>,
+}
+
+/// Drives all our backfill ranges to completion
+struct Backfill<Chain> {
+    config: UtreexoNodeConfig,
+    chain: Chain,
+    mempool: Arc<Mutex<Mempool>>,
+    address_man: AddressMan,
+
+    /// Tells us the whole node is shutting down
+    kill_signal: Arc<RwLock<bool>>,
+
+    /// Tells the node about invalid blocks in the chain we've assumed
+    node_tx: UnboundedSender<NodeNotification>,
+
+    /// Our ranges, sorted by height
+    ranges: Vec<BackfillRange>,
+
+    /// Ranges that found an invalid block in the chain we've assumed, by their start. We don't
+    /// validate them anymore.
+    stopped: HashSet<u32>,
+
+    /// The worker currently validating each range, by the range's start
+    workers: HashMap<u32, Worker>,
+
+    /// How many workers haven't reported back yet, including the ones we've replaced
+    running: usize,
+
+    /// The id of our next worker
+    next_worker: u64,
+
+    results_tx: UnboundedSender<RangeResult>,
+    results_rx: UnboundedReceiver<RangeResult>,
+}
+
+impl<Chain> Backfill<Chain>
+where
+    Chain: ThreadSafeChain + Clone,
+    WireError: From<Chain::Error>,
+    Chain::Error: From<proof_util::UtreexoLeafError>,
+{
+    async fn run(mut self, done_flag: std::sync::mpsc::Sender<()>) {
+        loop {
+            let killed = *self.kill_signal.read().await;
+            self.chain_ranges().await;
+
+            match killed {
+                true => self.stop_workers().await,
+                false => self.start_workers(),
+            }
+
+            if self.is_done() {
+                self.finish();
+                break;
+            }
+
+            if self.running == 0 {
+                break;
+            }
+
+            tokio::select! {
+                result = self.results_rx.recv() => {
+                    let Some(result) = result else {
+                        break;
+                    };
+                    self.handle_result(result);
+                }
+
+                _ = time::sleep(KILL_SIGNAL_CHECK) => {}
+            }
+        }
+
+        let _ = done_flag.send(());
+    }
+
+    /// Whether every range is done, and chained back to genesis
+    fn is_done(&self) -> bool {
+        self.ranges
+            .iter()
+            .all(|range| range.is_done() && range.is_verified())
+    }
+
+    /// Chains finished ranges into the next ones, stopping workers for ranges we have to
+    /// validate again
+    async fn chain_ranges(&mut self) {
+        for start in chain_ranges(&mut self.ranges) {
+            if let Some(worker) = self.workers.remove(&start) {
+                // This worker will still report back, but we'll ignore it
+                *worker.kill_signal.write().await = true;
+            }
+
+            self.save_range(start);
+        }
+    }
+
+    /// Starts a worker for each range that needs one
+    fn start_workers(&mut self) {
+        let ranges = self
+            .ranges
+            .iter()
+            .filter(|range| {
+                range.can_start()
+                    && !self.workers.contains_key(&range.start)
+                    && !self.stopped.contains(&range.start)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for range in ranges {
+            if let Err(e) = self.start_worker(&range) {
+                error!(
+                    "Could not start backfilling blocks {}..={}: {e:?}",
+                    range.start + 1,
+                    range.end
+                );
+            }
+        }
+    }
+
+    fn start_worker(&mut self, range: &BackfillRange) -> Result<(), WireError> {
+        info!(
+            "Backfilling blocks {}..={}, from height {}",
+            range.start + 1,
+            range.end,
+            range.tip
+        );
+
+        let chain = self
+            .chain
+            .get_partial_chain(range.tip, range.end, range.acc.clone())?;
+
+        let kill_signal = Arc::new(RwLock::new(false));
+        // Only our main node accepts inbound connections
+        let config = UtreexoNodeConfig {
+            listen: None,
+            ..self.config.clone()
+        };
+        let node = UtreexoNode::<PartialChainState, SyncNode>::new(
+            config,
+            chain,
+            self.mempool.clone(),
+            None,
+            kill_signal.clone(),
+            self.address_man.clone(),
+        )?;
+
+        let id = self.next_worker;
+        let start = range.start;
+        let results = self.results_tx.clone();
+
+        // Each range gets its own thread, so validating blocks doesn't hold the runtime's worker
+        // threads. Networking still runs on the runtime, through this handle.
+        let runtime = Handle::current();
+        thread::Builder::new()
+            .name(format!("backfill-{start}"))
+            .spawn(move || {
+                runtime.block_on(node.run(move |chain: &PartialChainState| {
+                    let result = RangeResult {
+                        start,
+                        worker: id,
+                        tip: chain
+                            .get_height()
+                            .expect("partial chains always have a height"),
+                        acc: chain.get_acc(),
+                        invalid_block: chain.invalid_block(),
+                    };
+
+                    let _ = results.send(result);
+                }))
+            })?;
+
+        self.next_worker += 1;
+        self.running += 1;
+        self.workers.insert(range.start, Worker { id, kill_signal });
+
+        Ok(())
+    }
+
+    /// Updates a range with what its worker did
+    fn handle_result(&mut self, result: RangeResult) {
+        self.running -= 1;
+
+        match self.workers.get(&result.start) {
+            Some(worker) if worker.id == result.worker => {
+                self.workers.remove(&result.start);
+            }
+            // We've replaced this worker, what it did doesn't matter anymore
+            _ => return,
+        }
+
+        let Some(range) = self
+            .ranges
+            .iter_mut()
+            .find(|range| range.start == result.start)
+        else {
+            return;
+        };
+
+        match result.invalid_block {
+            Some(block) if range.is_verified() => {
+                error!(
+                    "Block {block} is invalid, but it's part of the chain we've assumed. Stopped backfilling blocks {}..={}",
+                    range.start + 1,
+                    range.end
+                );
+
+                range.tip = result.tip;
+                range.acc = result.acc;
+                self.stopped.insert(result.start);
+
+                let _ = self
+                    .node_tx
+                    .send(NodeNotification::BackfillInvalidBlock(block));
+            }
+            Some(block) => {
+                warn!(
+                    "Block {block} is invalid, but we've started from the checkpoint at height {}, validating blocks {}..={} again after the previous range finishes",
+                    range.start,
+                    range.start + 1,
+                    range.end
+                );
+
+                *range = BackfillRange::new(range.start, range.end, RangeStart::Pending);
+            }
+            None => {
+                range.tip = result.tip;
+                range.acc = result.acc;
+
+                if range.is_done() {
+                    info!(
+                        "Finished backfilling blocks {}..={}",
+                        range.start + 1,
+                        range.end
+                    );
+                }
+            }
+        }
+
+        self.save_range(result.start);
+    }
+
+    /// Tells every worker to stop, so they save their progress
+    async fn stop_workers(&mut self) {
+        for worker in self.workers.values() {
+            *worker.kill_signal.write().await = true;
+        }
+    }
+
+    /// Marks all blocks we've backfilled as valid, and remembers we are done
+    fn finish(&self) {
+        let end = self.ranges.last().map(|range| range.end).unwrap_or(0);
+        for height in 1..=end {
+            let hash = self
+                .chain
+                .get_block_hash(height)
+                .expect("backfilled blocks are in our chain");
+
+            self.chain
+                .mark_block_as_valid(hash)
+                .expect("Failed to mark block as valid");
+        }
+
+        fs::write(self.done_file(), Vec::new()).expect("Failed to write sync node state");
+        let _ = fs::remove_dir_all(self.ranges_dir());
+
+        info!("Backfilling task shutting down...");
+    }
+
+    /// Saves the range starting at `start` on disk
+    fn save_range(&self, start: u32) {
+        let Some(range) = self.ranges.iter().find(|range| range.start == start) else {
+            return;
+        };
+
+        if let Err(e) = fs::write(range_file(&self.config.datadir, start), range.serialize()) {
+            error!("Could not save the backfill state: {e}");
+        }
+    }
+
+    fn done_file(&self) -> String {
+        format!("{}/{BACKFILL_DONE_FILE}", self.config.datadir)
+    }
+
+    fn ranges_dir(&self) -> String {
+        format!("{}/{BACKFILL_RANGES_DIR}", self.config.datadir)
+    }
+}
+
+/// The file where we save the range starting at `start`
+fn range_file(datadir: &str, start: u32) -> String {
+    format!("{datadir}/{BACKFILL_RANGES_DIR}/{start}")
+}
+
+/// Loads all ranges saved in `datadir`, sorted by height
+fn load_ranges(datadir: &str) -> io::Result<Vec<BackfillRange>> {
+    let mut ranges = Vec::new();
+    for entry in fs::read_dir(format!("{datadir}/{BACKFILL_RANGES_DIR}"))? {
+        let data = fs::read(entry?.path())?;
+        let range = BackfillRange::deserialize(&data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid backfill range"))?;
+
+        ranges.push(range);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+
+    // Every range must end where the next one starts
+    let contiguous = ranges.first().is_some_and(|range| range.start == 0)
+        && ranges.windows(2).all(|pair| pair[0].end == pair[1].start);
+
+    if !contiguous {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "backfill ranges aren't contiguous",
+        ));
+    }
+
+    Ok(ranges)
+}
+
+/// Adds the blocks we've just assumed, up to `end`, to our backfill. They are validated the next
+/// time we start backfilling, starting from `tip_acc`, our accumulator at `tip`.
+///
+/// If we've finished backfilling before, every block up to `tip` is verified, so we keep them as
+/// a finished range. If we never started, our next backfill will start from genesis anyway. But
+/// we can't change the ranges of a backfill that's still running.
+pub(crate) fn extend_backfill(
+    datadir: &str,
+    tip: u32,
+    tip_acc: Stump,
+    end: u32,
+) -> Result<(), WireError> {
+    let done_file = format!("{datadir}/{BACKFILL_DONE_FILE}");
+    let ranges_dir = format!("{datadir}/{BACKFILL_RANGES_DIR}");
+
+    if fs::metadata(&ranges_dir).is_ok() {
+        return Err(WireError::BackfillInProgress);
+    }
+
+    match fs::read(&done_file) {
+        Ok(state) if state.is_empty() => {}
+        Ok(_) => return Err(WireError::BackfillInProgress),
+        Err(_) => return Ok(()),
+    }
+
+    let verified = BackfillRange {
+        start: 0,
+        end: tip,
+        start_acc: RangeStart::Verified(Stump::default()),
+        tip,
+        acc: tip_acc.clone(),
+    };
+    let assumed = BackfillRange::new(tip, end, RangeStart::Verified(tip_acc));
+
+    fs::create_dir_all(&ranges_dir)?;
+    for range in [verified, assumed] {
+        fs::write(range_file(datadir, range.start), range.serialize())?;
+    }
+
+    fs::remove_file(&done_file)?;
+    Ok(())
+}
+
+impl<Chain> UtreexoNode<Chain, RunningNode>
+where
+    Chain: ThreadSafeChain + Clone,
+    WireError: From<Chain::Error>,
+    Chain::Error: From<proof_util::UtreexoLeafError>,
+{
+    /// If either PoW fraud proofs or assumeutreexo are enabled, we will "skip" IBD for all
+    /// historical blocks. This allow us to start the node faster, making it usable in a few
+    /// minutes. If you still want to validate all blocks, you can enable the backfill option.
+    ///
+    /// This function will spawn a background task that will download and validate all blocks
+    /// that got assumed, in parallel if we have backfill checkpoints. After completion, the task will shutdown and the node will continue running normally.
+    /// If we ever assume an invalid chain, we get a [`NodeNotification::BackfillInvalidBlock`],
+    /// and mark that block as invalid.
+    ///
+    /// Returns whether we've started backfilling. Once the task stops, either because it's done,
+    /// because we are shutting down, or because we've found an invalid block, it sends a
+    /// message through `done_flag`.
+    pub fn backfill(&self, done_flag: std::sync::mpsc::Sender<()>) -> Result<bool, WireError> {
+        let datadir = self.config.datadir.clone();
+        let done_file = format!("{datadir}/{BACKFILL_DONE_FILE}");
+
+        // try to recover from the disk state, if it exists. Otherwise, start from genesis
+        let legacy = match fs::read(&done_file) {
+            // if this file is empty, this means we've finished backfilling
+            Ok(state) if state.is_empty() => return Ok(false),
+            Ok(state) => BackfillRange::from_legacy_state(&state),
+            Err(_) => None,
+        };
+
+        let ranges = match (legacy, load_ranges(&datadir)) {
+            (Some(range), _) => {
+                info!(
+                    "Recovering backfill node from state tip={}, end={}",
+                    range.tip, range.end
+                );
+                vec![range]
+            }
+            (None, Ok(ranges)) => {
+                info!("Recovering {} backfill ranges", ranges.len());
+                ranges
+            }
+            // if the state doesn't exist or got corrupted, start from genesis
+            (None, Err(_)) => {
+                let end = self.chain.get_validation_index()?;
+                BackfillRange::split(end, &self.backfill_checkpoints(end))
+            }
+        };
+
+        let _ = fs::remove_dir_all(format!("{datadir}/{BACKFILL_RANGES_DIR}"));
+        fs::create_dir_all(format!("{datadir}/{BACKFILL_RANGES_DIR}"))?;
+        for range in ranges.iter() {
+            fs::write(range_file(&datadir, range.start), range.serialize())?;
+        }
+
+        // From now on, the state of each range lives in its own file
+        let _ = fs::remove_file(&done_file);
+
+        let (results_tx, results_rx) = unbounded_channel();
+        let backfill = Backfill {
+            config: self.config.clone(),
+            chain: self.chain.clone(),
+            mempool: self.mempool.clone(),
+            address_man: self.address_man.clone(),
+            kill_signal: self.kill_signal.clone(),
+            node_tx: self.common.node_tx.clone(),
+            ranges,
+            stopped: HashSet::new(),
+            workers: HashMap::new(),
+            running: 0,
+            next_worker: 0,
+            results_tx,
+            results_rx,
+        };
+
+        tokio::task::spawn(backfill.run(done_flag));
+        Ok(true)
+    }
+
+    /// Returns the checkpoints we may start a backfill range from, sorted by height. They must
+    /// be before `end`, and in our chain.
+    fn backfill_checkpoints(&self, end: u32) -> Vec<AssumeUtreexoValue> {
+        let mut checkpoints = self
+            .config
+            .backfill_checkpoints
+            .iter()
+            .filter(|checkpoint| {
+                let in_chain = self
+                    .chain
+                    .get_block_hash(checkpoint.height)
+                    .is_ok_and(|hash| hash == checkpoint.block_hash);
+
+                if !in_chain {
+                    warn!(
+                        "Ignoring backfill checkpoint {}, it's not in our chain",
+                        checkpoint.block_hash
+                    );
+                }
+
+                in_chain && checkpoint.height > 0 && checkpoint.height < end
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        checkpoints.sort_by_key(|checkpoint| checkpoint.height);
+        checkpoints.dedup_by_key(|checkpoint| checkpoint.height);
+        checkpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use floresta_chain::AssumeUtreexoValue;
+    use rustreexo::node_hash::BitcoinNodeHash;
+    use rustreexo::stump::Stump;
+
+    use super::chain_ranges;
+    use super::extend_backfill;
+    use super::load_ranges;
+    use super::BackfillRange;
+    use super::RangeStart;
+    use super::BACKFILL_DONE_FILE;
+    use crate::p2p_wire::error::WireError;
+
+    fn acc(leaves: u64) -> Stump {
+        Stump {
+            leaves,
+            roots: vec![BitcoinNodeHash::from([leaves as u8; 32])],
+        }
+    }
+
+    fn checkpoint(height: u32, leaves: u64) -> AssumeUtreexoValue {
+        let acc = acc(leaves);
+        AssumeUtreexoValue {
+            block_hash: BlockHash::all_zeros(),
+            height,
+            roots: acc.roots,
+            leaves,
+        }
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        let ranges = BackfillRange::split(300, &[]);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end, ranges[0].tip), (0, 300, 0));
+        assert!(ranges[0].is_verified());
+
+        let ranges = BackfillRange::split(300, &[checkpoint(100, 1), checkpoint(200, 2)]);
+        let bounds = ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(bounds, vec![(0, 100), (100, 200), (200, 300)]);
+        assert_eq!(ranges[1].start_acc, RangeStart::Trusted(acc(1)));
+        assert_eq!(ranges[2].acc, acc(2));
+        assert!(!ranges[2].is_verified());
+    }
+
+    #[test]
+    fn test_serialize_range() {
+        let mut range = BackfillRange::new(100, 200, RangeStart::Trusted(acc(1)));
+        range.tip = 150;
+        range.acc = acc(3);
+
+        for start_acc in [
+            RangeStart::Trusted(acc(1)),
+            RangeStart::Verified(acc(1)),
+            RangeStart::Pending,
+        ] {
+            range.start_acc = start_acc;
+            let data = range.serialize();
+            assert_eq!(BackfillRange::deserialize(&data), Some(range.clone()));
+
+            // Truncated data is rejected
+            assert_eq!(BackfillRange::deserialize(&data[..data.len() - 1]), None);
+        }
+
+        // The state saved by older versions
+        let mut legacy = Vec::new();
+        acc(3).serialize(&mut legacy).unwrap();
+        legacy.extend_from_slice(&150_u32.to_le_bytes());
+        legacy.extend_from_slice(&200_u32.to_le_bytes());
+
+        let range = BackfillRange::from_legacy_state(&legacy).unwrap();
+        assert_eq!((range.start, range.end, range.tip), (0, 200, 150));
+        assert_eq!(range.acc, acc(3));
+        assert!(range.is_verified());
+    }
+
+    #[test]
+    fn test_chain_ranges() {
+        let mut ranges = BackfillRange::split(300, &[checkpoint(100, 1), checkpoint(200, 2)]);
+
+        // Nothing is done, so there's nothing to check
+        assert!(chain_ranges(&mut ranges).is_empty());
+
+        // The last range finishes first, but we can't trust it until the one before it is verified
+        ranges[2].tip = 300;
+        ranges[2].acc = acc(3);
+        assert!(chain_ranges(&mut ranges).is_empty());
+
+        // The second range found an invalid block, so it waits for the first one
+        ranges[1] = BackfillRange::new(100, 200, RangeStart::Pending);
+        assert!(!ranges[1].can_start());
+
+        // Once the first range is done, the second starts from its final accumulator
+        ranges[0].tip = 100;
+        ranges[0].acc = acc(1);
+        assert_eq!(chain_ranges(&mut ranges), vec![100]);
+        assert_eq!(ranges[1].start_acc, RangeStart::Verified(acc(1)));
+        assert_eq!(ranges[1].tip, 100);
+        assert!(ranges[1].can_start());
+
+        // The second range got to a different accumulator than our checkpoint, so the last one
+        // must be validated again
+        ranges[1].tip = 200;
+        ranges[1].acc = acc(4);
+        assert_eq!(chain_ranges(&mut ranges), vec![200]);
+        assert_eq!(ranges[2].start_acc, RangeStart::Verified(acc(4)));
+        assert_eq!(ranges[2].tip, 200);
+        assert!(!ranges[2].is_done());
+
+        // If it matches, the checkpoint is verified and the range stays done
+        let mut ranges = BackfillRange::split(300, &[checkpoint(100, 1)]);
+        ranges[1].tip = 300;
+        ranges[0].tip = 100;
+        ranges[0].acc = acc(1);
+        assert_eq!(chain_ranges(&mut ranges), vec![100]);
+        assert!(ranges[1].is_verified());
+        assert!(ranges[1].is_done());
+        assert!(chain_ranges(&mut ranges).is_empty());
+    }
+
+    #[test]
+    fn test_extend_backfill() {
+        let datadir = format!("./tmp-db/{}.backfill", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        // Backfill is disabled, so there's nothing to extend
+        extend_backfill(&datadir, 100, acc(1), 200).unwrap();
+        assert!(load_ranges(&datadir).is_err());
+
+        // We've validated everything up to our tip, so only the assumed blocks are left
+        let done_file = format!("{datadir}/{BACKFILL_DONE_FILE}");
+        std::fs::write(&done_file, []).unwrap();
+        extend_backfill(&datadir, 100, acc(1), 200).unwrap();
+
+        let ranges = load_ranges(&datadir).unwrap();
+        let bounds = ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(bounds, vec![(0, 100), (100, 200)]);
+        assert!(ranges[0].is_done());
+        assert_eq!(ranges[1].start_acc, RangeStart::Verified(acc(1)));
+        assert!(ranges[1].can_start());
+        assert!(!ranges[1].is_done());
+        assert!(std::fs::metadata(&done_file).is_err());
+
+        // We can't extend a backfill that is still running
+        assert!(matches!(
+            extend_backfill(&datadir, 200, acc(2), 300),
+            Err(WireError::BackfillInProgress)
+        ));
+    }
+}
@@ -158,7 +158,7 @@ where
                 "Received utreexo proof for block {}, but we don't have it",
                 uproof.block_hash
             );
-            self.increase_banscore(peer, 5)?;
+            self.increase_banscore(peer, 5, "sent a utreexo proof for an unknown block")?;
 
             return Ok(());
         };
@@ -299,13 +299,22 @@ where
             return match self.handle_validation_errors(e, block, peer, utreexo_peer) {
                 // Disconnect the responsible peer and ban it.
                 Some(blamed_peer) => {
-                    self.disconnect_and_ban(blamed_peer)?;
+                    self.disconnect_and_ban(blamed_peer, "sent an invalid block")?;
                     Err(WireError::PeerMisbehaving)
                 }
                 None => Ok(()),
             };
         }
 
+        // Remove the confirmed and conflicting transactions from our mempool. During IBD our
+        // mempool is empty, so there's nothing to do.
+        if !self.chain.is_in_ibd() {
+            let mempool = self.mempool.clone();
+            tokio::spawn(async move {
+                mempool.lock().await.consume_block(&block);
+            });
+        }
+
         self.last_tip_update = Instant::now();
         Ok(())
     }
@@ -369,6 +378,9 @@ where
             | BlockValidationErrors::EmptyBlock
             | BlockValidationErrors::BadBip34
             | BlockValidationErrors::BIP94TimeWarp
+            | BlockValidationErrors::BadSignetSolution
+            | BlockValidationErrors::NonFinalTransaction
+            | BlockValidationErrors::SequenceLockNotSatisfied
             | BlockValidationErrors::UnspendableUTXO
             | BlockValidationErrors::CoinbaseNotMatured => {
                 try_and_log!(self.chain.invalidate_block(hash));
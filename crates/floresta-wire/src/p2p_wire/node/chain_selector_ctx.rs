@@ -73,6 +73,8 @@ use tracing::warn;
 
 use crate::address_man::AddressState;
 use crate::block_proof::Bitmap;
+use crate::fraud_proofs::TipCheck;
+use crate::fraud_proofs::TipCheckOutcome;
 use crate::node::periodic_job;
 use crate::node::try_and_log;
 use crate::node::InflightBlock;
@@ -99,7 +101,7 @@ pub struct ChainSelector {
     done_peers: HashSet<PeerId>,
 
     /// Keep track each peer's tip
-    tip_cache: HashMap<PeerId, BlockHash>,
+    pub(crate) tip_cache: HashMap<PeerId, BlockHash>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -182,7 +184,7 @@ where
             if let Err(e) = self.chain.accept_header(*header) {
                 error!("Error while downloading headers from peer={peer} err={e}");
 
-                self.disconnect_and_ban(peer)?;
+                self.disconnect_and_ban(peer, "sent an invalid header")?;
 
                 let peer = self.peers.get(&peer).unwrap();
                 self.common.address_man.update_set_state(
@@ -461,7 +463,7 @@ where
                 PeerMessages::Block(recv_block) => {
                     if recv_block.block_hash() != block_hash {
                         error!("peer {peer} sent us a block we didn't request");
-                        self.disconnect_and_ban(peer)?;
+                        self.disconnect_and_ban(peer, "sent a block we didn't request")?;
                         return Err(WireError::PeerMisbehaving);
                     }
 
@@ -474,7 +476,7 @@ where
                             "Peer {peer} sent us a mutated block {}",
                             recv_block.block_hash()
                         );
-                        self.disconnect_and_ban(peer)?;
+                        self.disconnect_and_ban(peer, "sent a mutated block")?;
                         return Err(WireError::PeerMisbehaving);
                     }
 
@@ -491,7 +493,7 @@ where
                 PeerMessages::UtreexoProof(uproof) => {
                     let Some(block) = block else {
                         error!("peer {peer} sent us a proof without sending the block first");
-                        self.disconnect_and_ban(peer)?;
+                        self.disconnect_and_ban(peer, "sent a utreexo proof without its block")?;
                         return Err(WireError::PeerMisbehaving);
                     };
 
@@ -566,7 +568,7 @@ where
 
             match liar_state {
                 PeerCheck::OneLying(liar) => {
-                    self.disconnect_and_ban(liar)?;
+                    self.disconnect_and_ban(liar, "lied about its utreexo accumulator")?;
                     if liar == peer1 {
                         invalid_accs.insert(peer[0].1.clone());
                         continue;
@@ -574,15 +576,15 @@ where
                     invalid_accs.insert(peer[1].1.clone());
                 }
                 PeerCheck::UnresponsivePeer(dead_peer) => {
-                    self.disconnect_and_ban(dead_peer)?;
+                    self.disconnect_and_ban(dead_peer, "unresponsive during accumulator checks")?;
                 }
                 PeerCheck::BothUnresponsivePeers => {
-                    self.disconnect_and_ban(peer1)?;
-                    self.disconnect_and_ban(peer2)?;
+                    self.disconnect_and_ban(peer1, "unresponsive during accumulator checks")?;
+                    self.disconnect_and_ban(peer2, "unresponsive during accumulator checks")?;
                 }
                 PeerCheck::BothLying => {
-                    self.disconnect_and_ban(peer1)?;
-                    self.disconnect_and_ban(peer2)?;
+                    self.disconnect_and_ban(peer1, "lied about its utreexo accumulator")?;
+                    self.disconnect_and_ban(peer2, "lied about its utreexo accumulator")?;
 
                     invalid_accs.insert(peer[0].1.clone());
                     invalid_accs.insert(peer[1].1.clone());
@@ -592,7 +594,9 @@ where
         //filter out the invalid accs
         candidate_accs.retain(|acc| !invalid_accs.contains(&acc.1));
         //we should have only one candidate left
-        assert_eq!(candidate_accs.len(), 1);
+        if candidate_accs.len() != 1 {
+            return Err(WireError::AccumulatorNotFound(hash));
+        }
 
         Self::parse_acc(candidate_accs.pop().unwrap().1)
     }
@@ -627,6 +631,21 @@ where
                     if self.chain.get_validation_index().unwrap() >= assume_utreexo.height {
                         return Ok(());
                     }
+
+                    // This value may come from a snapshot, make sure our peers agree with it
+                    let in_chain = self
+                        .chain
+                        .get_block_hash(assume_utreexo.height)
+                        .is_ok_and(|hash| hash == assume_utreexo.block_hash);
+
+                    if !in_chain {
+                        warn!(
+                            "The assumed block {} isn't in our best chain, validating all blocks instead",
+                            assume_utreexo.block_hash
+                        );
+                        return Ok(());
+                    }
+
                     info!(
                         "Assuming chain with height={} tip={}",
                         assume_utreexo.height, assume_utreexo.block_hash
@@ -656,26 +675,64 @@ where
         Ok(())
     }
 
-    async fn is_our_chain_invalid(&mut self, other_tip: BlockHash) -> Result<(), WireError> {
+    /// Checks a tip that forks away from our best chain, and records what we concluded
+    ///
+    /// See [`Self::is_our_chain_invalid`] for how we check it. Whatever the outcome, including
+    /// an error that stopped us from reaching one, it's saved to our fraud proof log.
+    async fn check_disputed_tip(&mut self, other_tip: BlockHash) -> Result<(), WireError> {
+        let mut check = TipCheck {
+            tip: other_tip,
+            best_block: self.chain.get_best_block()?.1,
+            fork_point: None,
+            fork_height: None,
+            outcome: TipCheckOutcome::Failed,
+            error: None,
+            time: 0,
+        };
+
+        match self.is_our_chain_invalid(other_tip, &mut check).await {
+            Ok(true) => check.outcome = TipCheckOutcome::OurChainInvalid,
+            Ok(false) => check.outcome = TipCheckOutcome::TipRejected,
+            Err(e) => {
+                warn!("Could not check tip {other_tip}: {e}");
+                check.error = Some(e.to_string());
+            }
+        }
+
+        info!("Checked tip {other_tip}, outcome={:?}", check.outcome);
+        if let Err(e) = self.common.fraud_proofs.record(check) {
+            warn!("Could not save the fraud proof log: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the first block of our best chain after the fork with `other_tip` is invalid
+    ///
+    /// We download this block from a peer following our chain, and validate it against the
+    /// accumulator our peers agree on for the fork point. If it's invalid, we ban the peers on
+    /// our tip and move to `other_tip`. Otherwise, we ban the peers on `other_tip`. The fork
+    /// point is written to `check` as soon as we find it.
+    async fn is_our_chain_invalid(
+        &mut self,
+        other_tip: BlockHash,
+        check: &mut TipCheck,
+    ) -> Result<bool, WireError> {
         let fork = self.chain.get_fork_point(other_tip)?;
         let fork_height = self.chain.get_block_height(&fork)?.unwrap_or(0);
+        check.fork_point = Some(fork);
+        check.fork_height = Some(fork_height);
 
-        let peers = self
-            .peer_by_service
-            .get(&service_flags::UTREEXO.into())
-            .ok_or(WireError::NoPeersAvailable)?;
-
-        let rand_peer = *peers
-            .choose(&mut thread_rng())
-            .ok_or(WireError::NoPeersAvailable)?;
+        // This is where our chain starts disagreeing with `other_tip`
+        let height = fork_height + 1;
+        let our_block = self.chain.get_block_hash(height)?;
 
-        let block = self.get_block_and_proof(rand_peer, fork).await?;
-        let (leaf_data, proof, _) = block
-            .aux_data
-            .expect("Block proof and leaf data should be present");
+        let peer = self.pick_peer_on_tip(check.best_block)?;
+        let block = self.get_block_and_proof(peer, our_block).await?;
+        let (leaf_data, proof, _) = block.aux_data.ok_or(WireError::BlockProofNotFound)?;
 
         let (del_hashes, inputs) =
-            proof_util::process_proof(&leaf_data, &block.block.txdata, fork_height, |h| {
+            proof_util::process_proof(&leaf_data, &block.block.txdata, height, |h| {
                 self.chain.get_block_hash(h)
             })?;
 
@@ -684,18 +741,44 @@ where
             .chain
             .validate_block(&block.block, proof, inputs, del_hashes, acc);
 
-        if is_valid.is_err() {
-            let best_block = self.chain.get_best_block()?.1;
-            self.ban_peers_on_tip(best_block)?;
+        if let Err(e) = is_valid {
+            warn!("Block {our_block} in our best chain is invalid ({e}), moving to {other_tip}");
+            self.ban_peers_on_tip(check.best_block)?;
 
+            // Invalidate it while it's still in our best chain, so we don't touch the blocks
+            // that take its height in the other one
+            self.chain.invalidate_block(our_block)?;
             self.chain.switch_chain(other_tip)?;
-            self.chain.invalidate_block(fork)?;
-            return Ok(());
+            return Ok(true);
         }
 
         // our chain's block is valid, therefore there's no reason for anyone be in this fork
         self.ban_peers_on_tip(other_tip)?;
-        Ok(())
+        Ok(false)
+    }
+
+    /// Picks a random Utreexo peer, preferring the ones that announced `tip`
+    fn pick_peer_on_tip(&self, tip: BlockHash) -> Result<PeerId, WireError> {
+        let peers = self
+            .peer_by_service
+            .get(&service_flags::UTREEXO.into())
+            .ok_or(WireError::NoPeersAvailable)?;
+
+        let on_tip: Vec<PeerId> = peers
+            .iter()
+            .copied()
+            .filter(|peer| self.context.tip_cache.get(peer) == Some(&tip))
+            .collect();
+
+        let candidates = match on_tip.is_empty() {
+            true => peers,
+            false => &on_tip,
+        };
+
+        candidates
+            .choose(&mut thread_rng())
+            .copied()
+            .ok_or(WireError::NoPeersAvailable)
     }
 
     fn ban_peers_on_tip(&mut self, tip: BlockHash) -> Result<(), WireError> {
@@ -705,43 +788,45 @@ where
                     peer.1.address_id as usize,
                     AddressState::Banned(ChainSelector::BAN_TIME),
                 );
-                self.disconnect_and_ban(peer.0)?;
+                self.disconnect_and_ban(peer.0, "announced an invalid chain")?;
             }
         }
 
         Ok(())
     }
 
-    async fn check_tips(&mut self) -> Result<(), WireError> {
-        let (height, _) = self.chain.get_best_block()?;
+    /// Checks our peers' tips, when we're using PoW fraud proofs
+    ///
+    /// If everyone agrees on our best chain, we assume it's valid, using the accumulator our
+    /// peers agree on for its tip. Otherwise, we check every other tip against our chain.
+    pub(crate) async fn check_tips(&mut self) -> Result<(), WireError> {
+        let (height, hash) = self.chain.get_best_block()?;
         let validation_index = self.chain.get_validation_index()?;
-        if (validation_index + 100) < height {
-            let mut tips = self.chain.get_chain_tips()?;
-            let (height, hash) = self.chain.get_best_block()?;
-            let acc = self.find_accumulator_for_block(height, hash).await?;
+        if (validation_index + 100) >= height {
+            info!("chain close enough to tip, not asking for utreexo state");
+            self.context.state = ChainSelectorState::Done;
+            return Ok(());
+        }
 
-            // only one tip, our peers are following the same chain
-            if tips.len() == 1 {
-                info!(
-                    "Assuming chain with {} blocks",
-                    self.chain.get_best_block()?.0
-                );
+        let mut tips = self.chain.get_chain_tips()?;
 
-                self.context.state = ChainSelectorState::Done;
-                self.chain.mark_chain_as_assumed(acc, tips[0]).unwrap();
-                self.chain.toggle_ibd(false);
-            }
-            // if we have more than one tip, we need to check if our best chain has an invalid block
-            tips.remove(0); // no need to check our best one
-            for tip in tips {
-                self.is_our_chain_invalid(tip).await?;
-            }
+        // only one tip, our peers are following the same chain
+        if tips.len() == 1 {
+            let acc = self.find_accumulator_for_block(height, hash).await?;
+            info!("Assuming chain with {height} blocks");
 
+            self.context.state = ChainSelectorState::Done;
+            self.chain.mark_chain_as_assumed(acc, tips[0])?;
+            self.chain.toggle_ibd(false);
             return Ok(());
         }
 
-        info!("chain close enough to tip, not asking for utreexo state");
-        self.context.state = ChainSelectorState::Done;
+        // if we have more than one tip, we need to check if our best chain has an invalid block
+        tips.remove(0); // no need to check our best one
+        for tip in tips {
+            self.check_disputed_tip(tip).await?;
+        }
+
         Ok(())
     }
 
@@ -935,6 +1020,12 @@ where
                     NodeNotification::FromUser(request, responder) => {
                         self.perform_user_request(request, responder).await;
                     }
+
+                    // We only backfill once we are running
+                    NodeNotification::BackfillInvalidBlock(_) => {}
+
+                    // We only accept inbound connections once we are running
+                    NodeNotification::InboundConnection(_, _) => {}
                 }
             }
 
@@ -982,6 +1073,12 @@ where
             NodeNotification::DnsSeedAddresses(addresses) => {
                 self.address_man.push_addresses(&addresses);
             }
+
+            // We only backfill once we are running
+            NodeNotification::BackfillInvalidBlock(_) => {}
+
+            // We only accept inbound connections once we are running
+            NodeNotification::InboundConnection(_, _) => {}
         }
         Ok(())
     }
@@ -1027,7 +1124,7 @@ where
 
                 if block.is_some() {
                     error!("peer {peer} sent us a block we didn't request");
-                    self.increase_banscore(peer, 5)?;
+                    self.increase_banscore(peer, 5, "sent a block we didn't request")?;
                 }
             }
 
@@ -9,13 +9,15 @@ use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use bitcoin::p2p::address::AddrV2;
+use bitcoin::p2p::Magic;
 use bitcoin::p2p::ServiceFlags;
-use bitcoin::Network;
 use floresta_chain::ChainBackend;
 use floresta_common::service_flags;
 use floresta_common::Ema;
 use floresta_mempool::Mempool;
 use tokio::net::tcp::WriteHalf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
 use tokio::spawn;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -40,8 +42,10 @@ use crate::address_man::LocalAddress;
 use crate::node_context::NodeContext;
 use crate::p2p_wire::error::AddrParseError;
 use crate::p2p_wire::error::WireError;
+use crate::p2p_wire::i2p::I2pSession;
 use crate::p2p_wire::peer::create_actors;
 use crate::p2p_wire::peer::Peer;
+use crate::p2p_wire::traffic::TrafficCounter;
 use crate::p2p_wire::transport;
 use crate::TransportProtocol;
 
@@ -99,7 +103,7 @@ where
 
         // Load hardcoded addresses to the address manager if no fixed or manual peers exist.
         let Some((peer_id, peer_address)) = candidate_peer else {
-            if !matches!(conn_kind, ConnectionKind::Manual) {
+            if !matches!(conn_kind, ConnectionKind::Manual) && !self.chain_params.is_custom() {
                 let net = self.network;
                 self.address_man.add_fixed_addresses(net);
             }
@@ -118,9 +122,14 @@ where
             .update_set_state(peer_id, AddressState::Failed(now));
 
         // Don't open duplicate connections to the same peer.
-        let is_peer_connected = |(_, old_peer): (_, &LocalPeerView)| {
-            peer_address.get_net_address() == old_peer.address
-                && peer_address.get_port() == old_peer.port
+        let is_peer_connected = |(_, old_peer): (_, &LocalPeerView)| match peer_address.get_addrv2()
+        {
+            AddrV2::Ipv4(_) | AddrV2::Ipv6(_) => {
+                peer_address.get_net_address() == old_peer.address
+                    && peer_address.get_port() == old_peer.port
+            }
+            // Other networks don't have an IP address for us to compare, so we use their ids
+            _ => old_peer.address_id == peer_id as u32,
         };
         if self.peers.iter().any(is_peer_connected) {
             return Err(WireError::PeerAlreadyExists(
@@ -129,6 +138,14 @@ where
             ));
         }
 
+        // Only our user can make us connect to a banned address
+        if !matches!(conn_kind, ConnectionKind::Manual) && self.is_banned_address(&peer_address) {
+            self.address_man
+                .update_set_state(peer_id, AddressState::Banned(T::BAN_TIME));
+
+            return Err(WireError::AddressBanned(peer_address.get_net_address()));
+        }
+
         // Only allow P2PV1 fallback if the peer's connection kind is manual,
         // or if the `--allow-v1-fallback` CLI argument was set.
         let allow_v1_fallback =
@@ -172,14 +189,43 @@ where
         allow_v1_fallback: bool,
     ) -> Result<(), WireError> {
         let (requests_tx, requests_rx) = unbounded_channel();
-        if let Some(ref proxy) = self.socks5 {
+        let traffic = Arc::new(TrafficCounter::new(self.traffic.clone()));
+        let i2p_session = match peer_address.get_addrv2() {
+            AddrV2::I2p(_) => self.i2p.clone(),
+            _ => None,
+        };
+
+        if let Some(session) = i2p_session {
+            spawn(timeout(
+                // Building I2P tunnels may take a while
+                Duration::from_secs(60),
+                Self::open_i2p_connection(
+                    session,
+                    kind,
+                    self.mempool.clone(),
+                    traffic.clone(),
+                    self.chain_params.magic,
+                    self.node_tx.clone(),
+                    peer_address.clone(),
+                    requests_rx,
+                    self.peer_id_count,
+                    self.config.user_agent.clone(),
+                    self.chain
+                        .get_best_block()
+                        .expect("infallible in ChainState")
+                        .0,
+                    allow_v1_fallback,
+                ),
+            ));
+        } else if let Some(ref proxy) = self.socks5 {
             spawn(timeout(
                 Duration::from_secs(10),
                 Self::open_proxy_connection(
                     proxy.address,
                     kind,
                     self.mempool.clone(),
-                    self.network,
+                    traffic.clone(),
+                    self.chain_params.magic,
                     self.node_tx.clone(),
                     peer_address.clone(),
                     requests_rx,
@@ -201,7 +247,8 @@ where
                     requests_rx,
                     self.peer_id_count,
                     self.mempool.clone(),
-                    self.network,
+                    traffic.clone(),
+                    self.chain_params.magic,
                     self.node_tx.clone(),
                     self.config.user_agent.clone(),
                     self.chain
@@ -237,6 +284,7 @@ where
                 banscore: 0,
                 // Will be downgraded to V1 if the V2 handshake fails, and we allow fallback
                 transport_protocol: TransportProtocol::V2,
+                traffic,
             },
         );
 
@@ -267,7 +315,8 @@ where
         requests_rx: UnboundedReceiver<NodeRequest>,
         peer_id_count: u32,
         mempool: Arc<Mutex<Mempool>>,
-        network: Network,
+        traffic: Arc<TrafficCounter>,
+        magic: Magic,
         node_tx: UnboundedSender<NodeNotification>,
         our_user_agent: String,
         our_best_block: u32,
@@ -275,7 +324,7 @@ where
     ) -> Result<(), WireError> {
         let (transport_reader, transport_writer, transport_protocol) = transport::connect(
             (peer_address.get_net_address(), peer_address.get_port()),
-            network,
+            magic,
             allow_v1_fallback,
         )
         .await?;
@@ -294,6 +343,7 @@ where
             peer_id_count,
             peer_address,
             mempool,
+            traffic,
             node_tx.clone(),
             requests_rx,
             kind,
@@ -314,7 +364,8 @@ where
         proxy: SocketAddr,
         kind: ConnectionKind,
         mempool: Arc<Mutex<Mempool>>,
-        network: Network,
+        traffic: Arc<TrafficCounter>,
+        magic: Magic,
         node_tx: UnboundedSender<NodeNotification>,
         peer_address: LocalAddress,
         requests_rx: UnboundedReceiver<NodeRequest>,
@@ -324,7 +375,53 @@ where
         allow_v1_fallback: bool,
     ) -> Result<(), WireError> {
         let (transport_reader, transport_writer, transport_protocol) =
-            transport::connect_proxy(proxy, peer_address.clone(), network, allow_v1_fallback)
+            transport::connect_proxy(proxy, peer_address.clone(), magic, allow_v1_fallback).await?;
+
+        let (cancellation_sender, cancellation_receiver) = oneshot::channel();
+        let (actor_receiver, actor) = create_actors(transport_reader);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancellation_receiver => {}
+                _ = actor.run() => {}
+            }
+        });
+
+        Peer::<WriteHalf>::create_peer(
+            peer_id_count,
+            peer_address,
+            mempool,
+            traffic,
+            node_tx,
+            requests_rx,
+            kind,
+            actor_receiver,
+            transport_writer,
+            our_user_agent,
+            our_best_block,
+            cancellation_sender,
+            transport_protocol,
+        );
+        Ok(())
+    }
+
+    /// Opens a connection to an I2P peer through our SAM session
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn open_i2p_connection(
+        session: I2pSession,
+        kind: ConnectionKind,
+        mempool: Arc<Mutex<Mempool>>,
+        traffic: Arc<TrafficCounter>,
+        magic: Magic,
+        node_tx: UnboundedSender<NodeNotification>,
+        peer_address: LocalAddress,
+        requests_rx: UnboundedReceiver<NodeRequest>,
+        peer_id_count: u32,
+        our_user_agent: String,
+        our_best_block: u32,
+        allow_v1_fallback: bool,
+    ) -> Result<(), WireError> {
+        let (transport_reader, transport_writer, transport_protocol) =
+            transport::connect_i2p(&session, peer_address.clone(), magic, allow_v1_fallback)
                 .await?;
 
         let (cancellation_sender, cancellation_receiver) = oneshot::channel();
@@ -340,6 +437,7 @@ where
             peer_id_count,
             peer_address,
             mempool,
+            traffic,
             node_tx,
             requests_rx,
             kind,
@@ -353,6 +451,177 @@ where
         Ok(())
     }
 
+    // === INBOUND CONNECTIONS ===
+
+    /// The local address we accept connections on, if [`UtreexoNodeConfig::listen`] is set.
+    ///
+    /// [`UtreexoNodeConfig::listen`]: crate::UtreexoNodeConfig::listen
+    pub fn listen_address(&self) -> Option<SocketAddr> {
+        self.listener.as_ref()?.local_addr().ok()
+    }
+
+    /// Starts a task that accepts connections on our listening socket, if we have one, and
+    /// passes them to the node.
+    pub(crate) fn start_listener(&mut self) -> Result<(), WireError> {
+        let Some(listener) = self.listener.take() else {
+            return Ok(());
+        };
+
+        let listener = TcpListener::from_std(listener)?;
+        info!(
+            "Accepting inbound connections on {}",
+            listener.local_addr()?
+        );
+
+        let node_tx = self.node_tx.clone();
+        spawn(async move {
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        // Probably out of file descriptors, give it some time
+                        debug!("Failed to accept an inbound connection: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+
+                let connection = NodeNotification::InboundConnection(stream, address);
+                if node_tx.send(connection).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Handles a peer that connected to us, creating a [`ConnectionKind::Inbound`] peer for it
+    /// if we have a free slot.
+    ///
+    /// Like [`Self::open_connection`], the handshake happens in the background, and the peer
+    /// sends us a [`PeerMessages::Ready`](crate::p2p_wire::peer::PeerMessages) once it's done.
+    pub(crate) fn handle_inbound_connection(
+        &mut self,
+        stream: TcpStream,
+        address: SocketAddr,
+    ) -> Result<(), WireError> {
+        let inbound_peers = self.peers.values().filter(|p| p.is_inbound()).count();
+        if inbound_peers >= T::MAX_INBOUND_PEERS {
+            debug!(
+                "Already have {inbound_peers} inbound peers, dropping connection from {address}"
+            );
+            return Ok(());
+        }
+
+        // Peers reaching us through our onion service show up as a loopback address, banning
+        // it would ban all of them.
+        if !address.ip().is_loopback() && self.ban_man.is_banned(&address.ip()) {
+            debug!("Dropping connection from banned address {address}");
+            return Ok(());
+        }
+
+        let (requests_tx, requests_rx) = unbounded_channel();
+        let traffic = Arc::new(TrafficCounter::new(self.traffic.clone()));
+        let mut peer_address = LocalAddress::from(self.to_addr_v2(address.ip()));
+        peer_address.set_port(address.port());
+
+        spawn(timeout(
+            Duration::from_secs(10),
+            Self::open_inbound_connection(
+                stream,
+                peer_address.clone(),
+                requests_rx,
+                self.peer_id_count,
+                self.mempool.clone(),
+                traffic.clone(),
+                self.chain_params.magic,
+                self.node_tx.clone(),
+                self.config.user_agent.clone(),
+                self.chain
+                    .get_best_block()
+                    .expect("infallible in ChainState")
+                    .0,
+            ),
+        ));
+
+        let peer_count: u32 = self.peer_id_count;
+
+        self.inflight.insert(
+            InflightRequests::Connect(peer_count),
+            (peer_count, Instant::now()),
+        );
+
+        self.peers.insert(
+            peer_count,
+            LocalPeerView {
+                message_times: Ema::with_half_life_50(),
+                address: address.ip(),
+                port: address.port(),
+                user_agent: "".to_string(),
+                state: PeerStatus::Awaiting,
+                channel: requests_tx,
+                services: ServiceFlags::NONE,
+                _last_message: Instant::now(),
+                kind: ConnectionKind::Inbound,
+                // This address isn't in our address manager
+                address_id: peer_address.id as u32,
+                height: 0,
+                banscore: 0,
+                // Updated once our peer picks one
+                transport_protocol: TransportProtocol::V2,
+                traffic,
+            },
+        );
+
+        self.peer_id_count += 1;
+        Ok(())
+    }
+
+    /// Negotiates the transport with a peer that connected to us, and creates its [`Peer`]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn open_inbound_connection(
+        stream: TcpStream,
+        peer_address: LocalAddress,
+        requests_rx: UnboundedReceiver<NodeRequest>,
+        peer_id_count: u32,
+        mempool: Arc<Mutex<Mempool>>,
+        traffic: Arc<TrafficCounter>,
+        magic: Magic,
+        node_tx: UnboundedSender<NodeNotification>,
+        our_user_agent: String,
+        our_best_block: u32,
+    ) -> Result<(), WireError> {
+        let (transport_reader, transport_writer, transport_protocol) =
+            transport::accept(stream, magic).await?;
+
+        let (cancellation_sender, cancellation_receiver) = oneshot::channel();
+        let (actor_receiver, actor) = create_actors(transport_reader);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancellation_receiver => {}
+                _ = actor.run() => {}
+            }
+        });
+
+        Peer::<WriteHalf>::create_peer(
+            peer_id_count,
+            peer_address,
+            mempool,
+            traffic,
+            node_tx,
+            requests_rx,
+            ConnectionKind::Inbound,
+            actor_receiver,
+            transport_writer,
+            our_user_agent,
+            our_best_block,
+            cancellation_sender,
+            transport_protocol,
+        );
+        Ok(())
+    }
+
     // === BOOTSTRAPPING ===
 
     /// Resolves a string address into a LocalAddress
@@ -470,23 +739,12 @@ where
         }
     }
 
-    // TODO(@luisschwab): get rid of this once
-    // https://github.com/rust-bitcoin/rust-bitcoin/pull/4639 makes it into a release.
-    pub(crate) fn get_port(network: Network) -> u16 {
-        match network {
-            Network::Bitcoin => 8333,
-            Network::Signet => 38333,
-            Network::Testnet => 18333,
-            Network::Testnet4 => 48333,
-            Network::Regtest => 18444,
-        }
-    }
-
     /// Fetch peers from DNS seeds, sending a `NodeNotification` with found ones. Returns
     /// immediately after spawning a background blocking task that performs the work.
     pub(crate) fn get_peers_from_dns(&self) -> Result<(), WireError> {
         let node_sender = self.node_tx.clone();
-        let network = self.network;
+        let default_port = self.chain_params.port;
+        let dns_seeds = self.chain_params.dns_seeds.clone();
 
         let proxy_addr = self.socks5.as_ref().map(|proxy| {
             let addr = proxy.address;
@@ -495,9 +753,6 @@ where
         });
 
         tokio::task::spawn_blocking(move || {
-            let default_port = Self::get_port(network);
-            let dns_seeds = floresta_chain::get_chain_dns_seeds(network);
-
             let mut addresses = Vec::new();
             for seed in &dns_seeds {
                 if let Ok(got) = AddressMan::get_seeds_from_dns(seed, default_port, proxy_addr) {
@@ -562,6 +817,11 @@ where
             return;
         }
 
+        // Our hardcoded addresses are only for the default chains
+        if self.chain_params.is_custom() {
+            return;
+        }
+
         let wait = HARDCODED_ADDRESSES_GRACE_PERIOD;
         if self.startup_time.elapsed() < wait {
             return;
@@ -575,6 +835,24 @@ where
     }
 
     pub(crate) fn init_peers(&mut self) -> Result<(), WireError> {
+        // Our block-relay-only anchors come first, before we learn any new address
+        if self.fixed_peer.is_none() {
+            let block_relay_anchors = self.common.address_man.take_anchors(&self.datadir);
+            for address in block_relay_anchors {
+                if self.is_banned_address(&address) {
+                    continue;
+                }
+
+                info!("Reconnecting to anchor {}", address.get_socket_address());
+                self.open_connection(
+                    ConnectionKind::BlockRelayOnly,
+                    address.id,
+                    address,
+                    self.config.allow_v1_fallback,
+                )?;
+            }
+        }
+
         let anchors = self.common.address_man.start_addr_man(self.datadir.clone());
         let enough_addresses = self.common.address_man.enough_addresses();
 
@@ -584,6 +862,10 @@ where
         }
 
         for address in anchors {
+            if self.is_banned_address(&address) {
+                continue;
+            }
+
             self.open_connection(
                 ConnectionKind::Regular(service_flags::UTREEXO.into()),
                 address.id,
@@ -632,6 +914,21 @@ where
         Ok(())
     }
 
+    /// Opens a new block-relay-only connection, if we have less than
+    /// [`NodeContext::MAX_BLOCK_RELAY_PEERS`] of them.
+    pub(crate) fn maybe_open_block_relay_connection(&mut self) -> Result<(), WireError> {
+        // If the user passes in a `--connect` cli argument, we only connect with that peer
+        if self.fixed_peer.is_some() {
+            return Ok(());
+        }
+
+        if self.block_relay_peers() >= T::MAX_BLOCK_RELAY_PEERS {
+            return Ok(());
+        }
+
+        self.create_connection(ConnectionKind::BlockRelayOnly)
+    }
+
     pub(crate) fn maybe_open_connection_with_added_peers(&mut self) -> Result<(), WireError> {
         if self.added_peers.is_empty() {
             return Ok(());
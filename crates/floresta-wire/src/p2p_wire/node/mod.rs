@@ -4,37 +4,47 @@
 //! events, such as new blocks, peer connection/disconnection, new addresses, etc.
 //! A node should not care about peer-specific messages, peers'll handle things like pings.
 
+mod backfill;
 mod blocks;
 pub mod chain_selector_ctx;
 mod conn;
 mod peer_man;
+mod private_broadcast;
 pub mod running_ctx;
 pub mod sync_ctx;
+mod tx_relay;
 mod user_req;
 
 use core::fmt::Debug;
 use core::net::IpAddr;
+use core::net::SocketAddr;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::sync::Arc;
 use std::time::Instant;
 
+use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::address::AddrV2Message;
+use bitcoin::p2p::message_blockdata::Inventory;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::BlockHash;
 use bitcoin::Network;
 use bitcoin::Txid;
+use bitcoin::Wtxid;
 pub(crate) use blocks::InflightBlock;
 use floresta_chain::ChainBackend;
+use floresta_chain::ChainParams;
 use floresta_common::Ema;
 use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
 use floresta_compact_filters::network_filters::NetworkFilters;
 use floresta_mempool::Mempool;
 pub use peer_man::AddedPeerInfo;
+use private_broadcast::PrivateBroadcast;
 use running_ctx::RunningNode;
 use serde::Deserialize;
 use serde::Serialize;
+use tokio::net::TcpStream;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
@@ -44,13 +54,20 @@ use tracing::info;
 
 use super::address_man::AddressMan;
 use super::address_man::LocalAddress;
+use super::ban_man::BanMan;
 use super::block_proof::Bitmap;
 use super::error::WireError;
+use super::fraud_proofs::FraudProofLog;
+use super::i2p::I2pIdentity;
+use super::i2p::I2pSession;
+use super::i2p::I2P_PRIVATE_KEY_FILE;
 use super::node_context::NodeContext;
 use super::node_interface::NodeResponse;
 use super::node_interface::UserRequest;
 use super::peer::PeerMessages;
 use super::socks::Socks5StreamBuilder;
+use super::traffic::NetTraffic;
+use super::traffic::TrafficCounter;
 use super::transport::TransportProtocol;
 use super::UtreexoNodeConfig;
 use crate::node_context::PeerId;
@@ -63,6 +80,10 @@ pub enum NodeNotification {
     DnsSeedAddresses(Vec<LocalAddress>),
     FromPeer(u32, PeerMessages, Instant),
     FromUser(UserRequest, oneshot::Sender<NodeResponse>),
+    /// Our backfill found an invalid block in the chain we've assumed
+    BackfillInvalidBlock(BlockHash),
+    /// A peer connected to our listening socket
+    InboundConnection(TcpStream, SocketAddr),
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
@@ -86,6 +107,12 @@ pub enum NodeRequest {
     /// Ask for an unconfirmed transaction
     MempoolTransaction(Txid),
 
+    /// Ask for an unconfirmed transaction, along with the utreexo proof for its inputs
+    GetUtreexoTransaction(Inventory),
+
+    /// Ask for the wtxids of an unconfirmed transaction and its unconfirmed ancestors
+    GetPackageInfo(Wtxid),
+
     /// Sends know addresses to our peers
     SendAddresses(Vec<AddrV2Message>),
 
@@ -158,6 +185,28 @@ pub enum ConnectionKind {
     /// misbehaving, and won't respect the [`ServiceFlags`] requirements when creating a
     /// connection.
     Manual,
+
+    /// An outbound connection only used to relay blocks.
+    ///
+    /// We don't relay transactions or addresses with those peers, so they can't be used to
+    /// learn our transaction-relay topology, and they are harder to find for an attacker trying
+    /// to eclipse us. The last of those peers are also kept as anchors, and we reconnect to them
+    /// first after a restart.
+    BlockRelayOnly,
+
+    /// A short-lived connection, through Tor or I2P, only used to push one of our own
+    /// transactions.
+    ///
+    /// This connection is closed right after our peer gets the transaction, so it can't be linked
+    /// to our other connections. See [`UtreexoNodeConfig::private_broadcast`].
+    PrivateBroadcast,
+
+    /// A peer that connected to us, see [`UtreexoNodeConfig::listen`].
+    ///
+    /// We serve those peers and take their transactions and block announcements, but never
+    /// pick them for our own requests or ask them for addresses. They don't take any of our
+    /// outbound slots.
+    Inbound,
 }
 
 impl Serialize for ConnectionKind {
@@ -170,6 +219,9 @@ impl Serialize for ConnectionKind {
             ConnectionKind::Regular(_) => serializer.serialize_str("regular"),
             ConnectionKind::Extra => serializer.serialize_str("extra"),
             ConnectionKind::Manual => serializer.serialize_str("manual"),
+            ConnectionKind::BlockRelayOnly => serializer.serialize_str("block-relay-only"),
+            ConnectionKind::PrivateBroadcast => serializer.serialize_str("private-broadcast"),
+            ConnectionKind::Inbound => serializer.serialize_str("inbound"),
         }
     }
 }
@@ -225,6 +277,9 @@ pub struct LocalPeerView {
 
     /// The transport protocol this peer is using (v1 or v2)
     pub(crate) transport_protocol: TransportProtocol,
+
+    /// How many bytes we exchanged with this peer, updated by the peer itself
+    pub(crate) traffic: Arc<TrafficCounter>,
 }
 
 impl LocalPeerView {
@@ -238,9 +293,24 @@ impl LocalPeerView {
         matches!(self.kind, ConnectionKind::Regular(_))
     }
 
+    /// Whether this is a block-relay-only peer
+    pub(crate) const fn is_block_relay_only(&self) -> bool {
+        matches!(self.kind, ConnectionKind::BlockRelayOnly)
+    }
+
+    /// Whether this connection is only used to push one of our transactions
+    pub(crate) const fn is_private_broadcast(&self) -> bool {
+        matches!(self.kind, ConnectionKind::PrivateBroadcast)
+    }
+
+    /// Whether this peer connected to us
+    pub(crate) const fn is_inbound(&self) -> bool {
+        matches!(self.kind, ConnectionKind::Inbound)
+    }
+
     // Connections expected to remain open if the peer doesn't die
     pub(crate) const fn is_long_lived(&self) -> bool {
-        self.is_manual_peer() || self.is_regular_peer()
+        self.is_manual_peer() || self.is_regular_peer() || self.is_block_relay_only()
     }
 }
 
@@ -259,7 +329,11 @@ pub struct NodeCommon<Chain: ChainBackend> {
     pub(crate) peer_by_service: HashMap<ServiceFlags, Vec<u32>>,
     pub(crate) max_banscore: u32,
     pub(crate) address_man: AddressMan,
+    pub(crate) ban_man: BanMan,
+    pub(crate) fraud_proofs: FraudProofLog,
     pub(crate) added_peers: Vec<AddedPeerInfo>,
+    pub(crate) private_broadcasts: HashMap<Txid, PrivateBroadcast>,
+    pub(crate) traffic: Arc<NetTraffic>,
 
     // 3. Internal Communication
     pub(crate) node_rx: UnboundedReceiver<NodeNotification>,
@@ -267,7 +341,10 @@ pub struct NodeCommon<Chain: ChainBackend> {
 
     // 4. Networking Configuration
     pub(crate) socks5: Option<Socks5StreamBuilder>,
+    pub(crate) i2p: Option<I2pSession>,
     pub(crate) fixed_peer: Option<LocalAddress>,
+    pub(crate) listener: Option<std::net::TcpListener>,
+    pub(crate) local_address: Option<(AddrV2, u16)>,
 
     // 5. Time and Event Tracking
     pub(crate) inflight: HashMap<InflightRequests, (u32, Instant)>,
@@ -289,6 +366,7 @@ pub struct NodeCommon<Chain: ChainBackend> {
     pub(crate) config: UtreexoNodeConfig,
     pub(crate) datadir: String,
     pub(crate) network: Network,
+    pub(crate) chain_params: ChainParams,
     pub(crate) kill_signal: Arc<tokio::sync::RwLock<bool>>,
 }
 
@@ -344,11 +422,42 @@ where
     ) -> Result<Self, WireError> {
         let (node_tx, node_rx) = unbounded_channel();
         let socks5 = config.proxy.map(Socks5StreamBuilder::new);
+        let i2p = config.i2p_sam.map(|sam| {
+            let identity = match config.i2p_persistent {
+                true => I2pIdentity::Persistent(
+                    format!("{}/{I2P_PRIVATE_KEY_FILE}", config.datadir).into(),
+                ),
+                false => I2pIdentity::Transient,
+            };
+
+            I2pSession::new(sam, identity)
+        });
+
+        let chain_params = config
+            .chain_params
+            .clone()
+            .unwrap_or_else(|| config.network.into());
 
         let fixed_peer = config
             .fixed_peer
             .as_ref()
-            .map(|address| Self::resolve_connect_host(address, Self::get_port(config.network)))
+            .map(|address| Self::resolve_connect_host(address, chain_params.port))
+            .transpose()?;
+
+        // We won't fall back to our regular peers, that would defeat the purpose
+        if config.private_broadcast && socks5.is_none() && i2p.is_none() {
+            return Err(WireError::NoAnonymityNetwork);
+        }
+
+        // Bind it right away, so our caller knows which port we got before we start running.
+        // Connections wait in the backlog until then.
+        let listener = config
+            .listen
+            .map(|address| {
+                let listener = std::net::TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Ok::<_, std::io::Error>(listener)
+            })
             .transpose()?;
 
         Ok(UtreexoNode {
@@ -369,9 +478,12 @@ where
                 peer_by_service: HashMap::new(),
                 mempool,
                 network: config.network,
+                chain_params,
                 node_rx,
                 node_tx,
                 address_man,
+                ban_man: BanMan::load(&config.datadir),
+                fraud_proofs: FraudProofLog::load(&config.datadir),
                 last_tip_update: Instant::now(),
                 last_connection: Instant::now(),
                 last_peer_db_dump: Instant::now(),
@@ -383,10 +495,15 @@ where
                 datadir: config.datadir.clone(),
                 max_banscore: config.max_banscore,
                 socks5,
+                i2p,
                 fixed_peer,
+                listener,
+                local_address: None,
+                traffic: Arc::new(NetTraffic::new(config.max_upload_target)),
                 config,
                 kill_signal,
                 added_peers: Vec::new(),
+                private_broadcasts: HashMap::new(),
             },
             context: T::default(),
         })
@@ -395,6 +512,7 @@ where
     pub(crate) fn shutdown(&mut self) {
         info!("Shutting down node...");
         try_and_warn!(self.save_utreexo_peers());
+        try_and_warn!(self.save_anchors());
         for peer in self.peer_ids.iter() {
             try_and_log!(self.send_to_peer(*peer, NodeRequest::Shutdown));
         }
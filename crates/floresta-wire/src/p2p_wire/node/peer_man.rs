@@ -16,6 +16,7 @@ use floresta_common::service_flags;
 use rand::distributions::Distribution;
 use rand::distributions::WeightedIndex;
 use rand::prelude::SliceRandom;
+use rand::seq::IteratorRandom;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
@@ -29,15 +30,18 @@ use super::PeerStatus;
 use super::UtreexoNode;
 use crate::address_man::AddressState;
 use crate::address_man::LocalAddress;
+use crate::ban_man::Subnet;
 use crate::block_proof::Bitmap;
 use crate::node::running_ctx::RunningNode;
 use crate::node::try_and_log;
+use crate::node::try_and_warn;
 use crate::node_context::NodeContext;
 use crate::node_context::PeerId;
 use crate::node_interface::NodeResponse;
 use crate::node_interface::PeerInfo;
 use crate::node_interface::UserRequest;
 use crate::p2p_wire::error::WireError;
+use crate::p2p_wire::peer::peer_utils;
 use crate::p2p_wire::peer::PeerMessages;
 use crate::p2p_wire::peer::Version;
 
@@ -76,6 +80,9 @@ where
             .peers
             .iter()
             .filter(|(_, peer)| peer.services.has(service) && peer.state == PeerStatus::Ready)
+            .filter(|(_, peer)| !peer.is_private_broadcast())
+            // Peers that connected to us are only served, they don't get our requests
+            .filter(|(_, peer)| !peer.is_inbound())
             .filter_map(|(id, peer)| {
                 // Get the average message latency from each peer
                 let Some(t) = peer.message_times.value() else {
@@ -104,11 +111,21 @@ where
     /// Returns how many connected peers we have.
     ///
     /// This function will only count peers that completed handshake and are ready
-    /// to be used.
+    /// to be used. Block-relay-only peers have their own slots, and aren't counted here.
     pub(crate) fn connected_peers(&self) -> usize {
         self.peers
             .values()
             .filter(|p| p.state == PeerStatus::Ready && p.is_long_lived())
+            .filter(|p| !p.is_block_relay_only())
+            .count()
+    }
+
+    /// Returns how many block-relay-only connections we have, including the ones still
+    /// handshaking.
+    pub(crate) fn block_relay_peers(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| p.is_block_relay_only())
             .count()
     }
 
@@ -166,6 +183,28 @@ where
         Ok(*peer)
     }
 
+    /// Sends a request to a random ready peer that supports `required_service` and relays
+    /// transactions and addresses, that is, any peer that isn't block-relay-only or only used
+    /// for a private broadcast.
+    pub(crate) fn send_to_random_relay_peer(
+        &self,
+        req: NodeRequest,
+        required_service: ServiceFlags,
+    ) -> Result<PeerId, WireError> {
+        let (peer_id, peer) = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerStatus::Ready && !peer.is_block_relay_only())
+            .filter(|(_, peer)| !peer.is_private_broadcast())
+            .filter(|(_, peer)| peer.services.has(required_service))
+            .choose(&mut rand::thread_rng())
+            .ok_or(WireError::NoPeersAvailable)?;
+
+        peer.channel.send(req).map_err(WireError::ChannelSend)?;
+
+        Ok(*peer_id)
+    }
+
     pub(crate) fn send_to_peer(&self, peer_id: u32, req: NodeRequest) -> Result<(), WireError> {
         if let Some(peer) = &self.peers.get(&peer_id) {
             if peer.state == PeerStatus::Awaiting {
@@ -185,7 +224,7 @@ where
     /// erroing one.
     pub(crate) fn broadcast_to_peers(&mut self, request: NodeRequest) {
         for peer in self.peers.values() {
-            if peer.state != PeerStatus::Ready {
+            if peer.state != PeerStatus::Ready || peer.is_private_broadcast() {
                 continue;
             }
 
@@ -196,7 +235,7 @@ where
     }
 
     pub(crate) fn ask_for_addresses(&mut self) -> Result<(), WireError> {
-        let _ = self.send_to_random_peer(NodeRequest::GetAddresses, ServiceFlags::NONE)?;
+        let _ = self.send_to_random_relay_peer(NodeRequest::GetAddresses, ServiceFlags::NONE)?;
         Ok(())
     }
 
@@ -222,19 +261,53 @@ where
             p.state = PeerStatus::Ready;
         });
 
-        // Ask for new addresses to populate our address manager
-        self.send_to_peer(peer, NodeRequest::GetAddresses)?;
-        self.inflight
-            .insert(InflightRequests::GetAddresses, (peer, Instant::now()));
+        // Private broadcast connections are only used to push a transaction, that's done by
+        // our running context
+        if version.kind == ConnectionKind::PrivateBroadcast {
+            return Ok(());
+        }
+
+        // We only serve peers that connected to us, and relay transactions with them. They
+        // aren't in our address manager, and we don't ask them for addresses.
+        if version.kind == ConnectionKind::Inbound {
+            info!(
+                "New inbound peer id={} version={} blocks={} services={}",
+                version.id, version.user_agent, version.blocks, version.services
+            );
+
+            if let Some(peer_data) = self.common.peers.get_mut(&peer) {
+                peer_data.services = version.services;
+                peer_data.user_agent.clone_from(&version.user_agent);
+                peer_data.height = version.blocks;
+                peer_data.transport_protocol = version.transport_protocol;
+            }
+
+            return Ok(());
+        }
+
+        // Ask for new addresses to populate our address manager, unless this is a
+        // block-relay-only peer, which shouldn't know we care about addresses.
+        if version.kind != ConnectionKind::BlockRelayOnly {
+            self.send_to_peer(peer, NodeRequest::GetAddresses)?;
+            self.inflight
+                .insert(InflightRequests::GetAddresses, (peer, Instant::now()));
+
+            // Tell them how to reach us, so they can spread it
+            if let Some(address) = self.local_address_message() {
+                self.send_to_peer(peer, NodeRequest::SendAddresses(vec![address]))?;
+            }
+        }
 
         let good_peers_count = self.connected_peers();
         if good_peers_count > T::MAX_OUTGOING_PEERS {
-            // We allow utreexo, extra and manual peers to bypass our connection limits
+            // We allow utreexo, extra, manual and block-relay-only peers to bypass our
+            // connection limits
             let is_utreexo_peer = matches!(version.kind, ConnectionKind::Regular(services) if services.has(service_flags::UTREEXO.into()));
             let is_manual_peer = version.kind == ConnectionKind::Manual;
             let is_extra = version.kind == ConnectionKind::Extra;
+            let is_block_relay = version.kind == ConnectionKind::BlockRelayOnly;
 
-            if !(is_utreexo_peer || is_manual_peer || is_extra) {
+            if !(is_utreexo_peer || is_manual_peer || is_extra || is_block_relay) {
                 debug!(
                     "Already have {} peers, disconnecting peer to avoid blowing up our max of {}",
                     good_peers_count,
@@ -433,7 +506,7 @@ where
             }
             PeerMessages::UtreexoState(_) => {
                 warn!("Utreexo state received from peer {peer}, but we didn't ask");
-                self.increase_banscore(peer, 5)?;
+                self.increase_banscore(peer, 5, "sent an unrequested utreexo state")?;
                 Ok(None)
             }
             _ => Ok(Some(msg)),
@@ -454,6 +527,8 @@ where
                 .as_secs();
 
             match p.state {
+                // Peers that connected to us aren't in our address manager
+                _ if p.kind == ConnectionKind::Inbound => {}
                 PeerStatus::Ready => {
                     self.address_man
                         .update_set_state(idx, AddressState::Tried(now));
@@ -502,8 +577,14 @@ where
     /// This is a always increasing number that, if reaches our `max_banscore` setting,
     /// will cause our peer to be banned for one BANTIME.
     /// The amount of each increment is given by factor, and it's calibrated for each misbehaving
-    /// action that a peer may incur in.
-    pub(crate) fn increase_banscore(&mut self, peer_id: u32, factor: u32) -> Result<(), WireError> {
+    /// action that a peer may incur in. `reason` describes this misbehaviour, and is recorded in
+    /// our ban list if the peer ends up banned.
+    pub(crate) fn increase_banscore(
+        &mut self,
+        peer_id: u32,
+        factor: u32,
+        reason: &str,
+    ) -> Result<(), WireError> {
         let Some(peer) = self.common.peers.get_mut(&peer_id) else {
             return Ok(());
         };
@@ -521,19 +602,24 @@ where
         let is_extra = peer.kind == ConnectionKind::Extra;
 
         if is_misbehaving || is_extra {
-            warn!("banning peer {peer_id} for misbehaving");
-            self.disconnect_and_ban(peer_id)?;
+            warn!("banning peer {peer_id} for misbehaving: {reason}");
+            self.disconnect_and_ban(peer_id, reason)?;
             return Ok(());
         }
 
-        debug!("increasing banscore for peer {peer_id}");
+        debug!("increasing banscore for peer {peer_id}: {reason}");
 
         Ok(())
     }
 
-    /// Disconnects a peer and bans it for `T::BAN_TIME`.
-    pub(crate) fn disconnect_and_ban(&mut self, peer: PeerId) -> Result<(), WireError> {
-        if let Some(peer) = self.peers.get_mut(&peer) {
+    /// Disconnects a peer and bans its address for `T::BAN_TIME`, recording `reason` in our
+    /// ban list.
+    pub(crate) fn disconnect_and_ban(
+        &mut self,
+        peer: PeerId,
+        reason: &str,
+    ) -> Result<(), WireError> {
+        if let Some(peer) = self.common.peers.get_mut(&peer) {
             // Manual connections are exempt from being punished
             if peer.is_manual_peer() {
                 return Ok(());
@@ -541,12 +627,40 @@ where
 
             // `handle_disconnection` will mark the address as banned when `Peer` object return
             peer.state = PeerStatus::Banned;
+
+            // Peers we reach by name, through our proxy or I2P, show up as a loopback address,
+            // banning it would be meaningless. So do peers reaching us through our onion service.
+            let reached_by_name = peer.address.is_loopback()
+                && (self.common.socks5.is_some() || self.common.i2p.is_some() || peer.is_inbound());
+            if !reached_by_name {
+                let until = Self::now() + T::BAN_TIME;
+                let subnet = Subnet::single(peer.address);
+                if let Err(e) = self.common.ban_man.ban(subnet, until, reason) {
+                    warn!("Could not save the ban for {subnet}: {e}");
+                }
+            }
         }
 
         self.send_to_peer(peer, NodeRequest::Shutdown)?;
         Ok(())
     }
 
+    /// Whether this address is inside a subnet we've banned. Only IP addresses can be banned.
+    pub(crate) fn is_banned_address(&self, address: &LocalAddress) -> bool {
+        match address.get_addrv2() {
+            AddrV2::Ipv4(_) | AddrV2::Ipv6(_) => self.ban_man.is_banned(&address.get_net_address()),
+            _ => false,
+        }
+    }
+
+    /// The current UNIX time, in seconds
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
     /// Checks whether some of our inflight requests have timed out.
     ///
     /// This function will check if any of our inflight requests have timed out, and if so,
@@ -597,7 +711,7 @@ where
 
             debug!("Request timed out: {req:?}");
             // Increase the banscore and try banning the peer if needed, then re-request
-            try_and_log!(self.increase_banscore(peer, 1));
+            try_and_log!(self.increase_banscore(peer, 1, "request timed out"));
 
             if let Err(e) = self.redo_inflight_request(&req) {
                 // CRITICAL: never drop the request, so we retry it later
@@ -617,15 +731,19 @@ where
         self.inflight.remove(&InflightRequests::GetAddresses);
         debug!("Got {} addresses from peer {}", addresses.len(), peer);
         let addresses: Vec<_> = addresses.into_iter().map(|addr| addr.into()).collect();
-        self.address_man.push_addresses(&addresses);
 
-        // For feeler peers, we ask for addresses to populate our address manager,
-        // then disconnect them
         let Some(peer_data) = self.peers.get(&peer) else {
             return Ok(());
         };
 
-        if matches!(peer_data.kind, ConnectionKind::Feeler) {
+        // Bucket them by who told us, so one peer can't flood our address manager
+        let source = self.to_addr_v2(peer_data.address);
+        let is_feeler = matches!(peer_data.kind, ConnectionKind::Feeler);
+        self.address_man.push_addresses_from(&addresses, &source);
+
+        // For feeler peers, we ask for addresses to populate our address manager,
+        // then disconnect them
+        if is_feeler {
             self.send_to_peer(peer, NodeRequest::Shutdown)?;
         }
 
@@ -729,6 +847,41 @@ where
             .map_err(WireError::Io)
     }
 
+    /// Saves our block-relay-only peers to disk, so we can reconnect to them first after a
+    /// restart
+    ///
+    /// Since they were already connected to us and never gossip our addresses or transactions,
+    /// those anchors make it harder to eclipse us by filling our address manager before a
+    /// restart.
+    pub(crate) fn save_anchors(&self) -> Result<(), WireError> {
+        let anchors: Vec<_> = self
+            .peers
+            .values()
+            .filter(|peer| peer.is_block_relay_only() && peer.state == PeerStatus::Ready)
+            .take(T::MAX_BLOCK_RELAY_PEERS)
+            .map(|peer| {
+                let address = self.to_addr_v2(peer.address);
+                self.address_man
+                    .get_address(&address, peer.port)
+                    .unwrap_or_else(|| {
+                        LocalAddress::new(
+                            address,
+                            0,
+                            AddressState::NeverTried,
+                            peer.services,
+                            peer.port,
+                            peer.address_id as usize,
+                        )
+                    })
+            })
+            .collect();
+
+        info!("Saving {} anchor peers to disk...", anchors.len());
+        self.address_man
+            .dump_anchors(&self.datadir, &anchors)
+            .map_err(WireError::Io)
+    }
+
     // === METRICS AND HELPERS ===
 
     /// Register a message on `self.inflights` and record the time taken to respond to it.
@@ -826,6 +979,19 @@ where
             state: peer.state,
             kind: peer.kind,
             transport_protocol: peer.transport_protocol,
+            traffic: peer.traffic.peer_traffic(),
+        })
+    }
+
+    /// Our own address, if we are reachable, as we announce it to our peers
+    pub(crate) fn local_address_message(&self) -> Option<AddrV2Message> {
+        let (addr, port) = self.local_address.clone()?;
+
+        Some(AddrV2Message {
+            time: Self::now() as u32,
+            services: peer_utils::our_services(),
+            addr,
+            port,
         })
     }
 
@@ -927,6 +1093,35 @@ where
         }
     }
 
+    /// Handles `setban add` requests, banning `subnet` until the UNIX timestamp `until` and
+    /// disconnecting every peer inside it, even manual ones.
+    ///
+    /// Returns `false` if this subnet is already banned.
+    pub fn handle_ban_subnet(&mut self, subnet: Subnet, until: u64) -> bool {
+        if self.ban_man.is_subnet_banned(&subnet) {
+            return false;
+        }
+
+        // Even if we can't save it, the ban is kept in memory
+        try_and_warn!(self.ban_man.ban(subnet, until, "manually added"));
+
+        let banned_peers: Vec<_> = self
+            .peers
+            .iter_mut()
+            .filter(|(_, peer)| subnet.contains(&peer.address))
+            .map(|(&peer_id, peer)| {
+                peer.state = PeerStatus::Banned;
+                peer_id
+            })
+            .collect();
+
+        for peer_id in banned_peers {
+            try_and_log!(self.send_to_peer(peer_id, NodeRequest::Shutdown));
+        }
+
+        true
+    }
+
     /// Handles addnode onetry requests, connecting to the node and this will try to connect to the given address and port.
     /// If it's successful, it will add the node to the peers list, but not to the added_peers list (e.g., it won't be reconnected if disconnected).
     pub fn handle_addnode_onetry_peer(
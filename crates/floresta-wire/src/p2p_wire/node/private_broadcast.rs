@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Private broadcast of our own transactions.
+//!
+//! Announcing a transaction we created to our regular peers lets anyone watching who announced it
+//! first link it to our IP address. Instead, with [`UtreexoNodeConfig::private_broadcast`], we
+//! open a new connection through our proxy or I2P to a random peer, push the transaction to it,
+//! and close that connection. That peer can't tell us apart from anyone else using Tor or I2P,
+//! and the connection can't be linked to our other ones.
+//!
+//! We don't announce those transactions to our regular peers. Once one of them announces it back
+//! to us, we know it propagated. If this doesn't happen in time, we try again with another peer.
+//!
+//! [`UtreexoNodeConfig::private_broadcast`]: crate::UtreexoNodeConfig::private_broadcast
+
+use std::time::Duration;
+use std::time::Instant;
+
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::Txid;
+use bitcoin::Wtxid;
+use floresta_chain::ChainBackend;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+use super::ConnectionKind;
+use super::NodeRequest;
+use super::UtreexoNode;
+use crate::address_man::ReachableNetworks;
+use crate::node_context::NodeContext;
+use crate::node_context::PeerId;
+use crate::p2p_wire::error::WireError;
+
+/// How long we wait for a transaction to be announced back to us, before trying another peer
+const PRIVATE_BROADCAST_RETRY: Duration = Duration::from_secs(2 * 60);
+
+/// How long a private broadcast connection may stay open
+const PRIVATE_BROADCAST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many peers we push a transaction to, before giving up
+const MAX_PRIVATE_BROADCAST_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone)]
+/// One of our transactions, that we are broadcasting privately
+pub(crate) struct PrivateBroadcast {
+    /// The transaction's wtxid, our peers may announce it by either id
+    wtxid: Wtxid,
+
+    /// How many peers we've pushed it to
+    attempts: u32,
+
+    /// When we last opened a connection to push it
+    last_attempt: Instant,
+
+    /// The connection we are pushing it through, if it's still open
+    peer: Option<PeerId>,
+}
+
+impl<T, Chain> UtreexoNode<Chain, T>
+where
+    T: 'static + Default + NodeContext,
+    Chain: ChainBackend + 'static,
+    WireError: From<Chain::Error>,
+{
+    /// Starts broadcasting one of our transactions privately. It must be in our mempool already.
+    pub(crate) fn start_private_broadcast(&mut self, txid: Txid, wtxid: Wtxid) {
+        info!("Broadcasting transaction {txid} privately");
+
+        let broadcast = PrivateBroadcast {
+            wtxid,
+            attempts: 0,
+            last_attempt: Instant::now(),
+            peer: None,
+        };
+
+        self.private_broadcasts.insert(txid, broadcast);
+        if let Err(e) = self.open_private_broadcast_connection(txid) {
+            // We'll try again later
+            warn!("Could not open a private broadcast connection for {txid}: {e}");
+        }
+    }
+
+    /// Pushes a transaction to a peer we've connected to for it, or disconnects the peer if
+    /// we don't need it anymore
+    pub(crate) fn handle_private_broadcast_ready(&mut self, peer: PeerId) -> Result<(), WireError> {
+        let txid = self
+            .private_broadcasts
+            .iter()
+            .find(|(_, broadcast)| broadcast.peer == Some(peer))
+            .map(|(txid, _)| *txid);
+
+        match txid {
+            Some(txid) => {
+                debug!("Pushing transaction {txid} to private broadcast peer {peer}");
+                self.send_to_peer(peer, NodeRequest::BroadcastTransaction(txid))
+            }
+            None => self.send_to_peer(peer, NodeRequest::Shutdown),
+        }
+    }
+
+    /// Marks the transactions in `inv` as propagated, since our peer announced them to us.
+    ///
+    /// Returns the inventories that aren't one of our private broadcasts.
+    pub(crate) fn handle_private_broadcast_inv(
+        &mut self,
+        inv: Vec<Inventory>,
+        peer: PeerId,
+    ) -> Vec<Inventory> {
+        if self.private_broadcasts.is_empty() {
+            return inv;
+        }
+
+        // Our private peers don't tell us anything, only regular peers can confirm propagation
+        let from_regular_peer = self
+            .peers
+            .get(&peer)
+            .is_some_and(|info| !info.is_private_broadcast());
+
+        if !from_regular_peer {
+            return inv;
+        }
+
+        inv.into_iter()
+            .filter(|inv| {
+                let txid = self
+                    .private_broadcasts
+                    .iter()
+                    .find_map(|(txid, broadcast)| {
+                        let announced = match inv {
+                            Inventory::Transaction(id) | Inventory::WitnessTransaction(id) => {
+                                id == txid
+                            }
+                            Inventory::WTx(wtxid) => *wtxid == broadcast.wtxid,
+                            _ => false,
+                        };
+
+                        announced.then_some(*txid)
+                    });
+
+                let Some(txid) = txid else {
+                    return true;
+                };
+
+                info!("Transaction {txid} was announced back to us by peer {peer}, it propagated");
+                self.private_broadcasts.remove(&txid);
+                false
+            })
+            .collect()
+    }
+
+    /// Closes private broadcast connections that lasted too long, forgets transactions that left
+    /// our mempool, and retries the ones that didn't propagate in time.
+    pub(crate) async fn check_private_broadcasts(&mut self) -> Result<(), WireError> {
+        if self.private_broadcasts.is_empty() {
+            return Ok(());
+        }
+
+        // Mined or evicted, either way, there's nothing left to do
+        let mempool = self.common.mempool.clone();
+        let mempool = mempool.lock().await;
+        self.common
+            .private_broadcasts
+            .retain(|txid, _| mempool.get_from_mempool(txid).is_some());
+        drop(mempool);
+
+        let mut to_retry = Vec::new();
+        let mut to_disconnect = Vec::new();
+
+        for (txid, broadcast) in self.common.private_broadcasts.iter_mut() {
+            let elapsed = broadcast.last_attempt.elapsed();
+
+            if let Some(peer) = broadcast.peer {
+                let connected = self.common.peers.contains_key(&peer);
+                if !connected {
+                    broadcast.peer = None;
+                } else if elapsed > PRIVATE_BROADCAST_TIMEOUT {
+                    to_disconnect.push(peer);
+                    broadcast.peer = None;
+                }
+            }
+
+            if elapsed > PRIVATE_BROADCAST_RETRY {
+                to_retry.push(*txid);
+            }
+        }
+
+        for peer in to_disconnect {
+            self.send_to_peer(peer, NodeRequest::Shutdown)?;
+        }
+
+        for txid in to_retry {
+            let attempts = self.private_broadcasts[&txid].attempts;
+            if attempts >= MAX_PRIVATE_BROADCAST_ATTEMPTS {
+                warn!("Giving up on broadcasting transaction {txid}, it didn't propagate after {attempts} attempts");
+                self.private_broadcasts.remove(&txid);
+                continue;
+            }
+
+            debug!("Transaction {txid} wasn't announced back to us yet, trying another peer");
+            self.open_private_broadcast_connection(txid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens a new connection, through our proxy or I2P, to push this transaction
+    fn open_private_broadcast_connection(&mut self, txid: Txid) -> Result<(), WireError> {
+        let Some(broadcast) = self.private_broadcasts.get_mut(&txid) else {
+            return Ok(());
+        };
+
+        // Count this attempt even if we can't connect, so we don't retry right away
+        broadcast.attempts += 1;
+        broadcast.last_attempt = Instant::now();
+
+        // Clearnet peers are fine, as long as we reach them through our proxy
+        let mut networks = Vec::new();
+        if self.socks5.is_some() {
+            networks.extend([ReachableNetworks::IPv4, ReachableNetworks::IPv6]);
+        }
+
+        if self.i2p.is_some() {
+            networks.push(ReachableNetworks::I2P);
+        }
+
+        if networks.is_empty() {
+            return Err(WireError::NoAnonymityNetwork);
+        }
+
+        let (address_id, address) = self
+            .address_man
+            .get_address_in_networks(&networks)
+            .ok_or(WireError::NoAddressesAvailable)?;
+
+        let peer = self.peer_id_count;
+        self.open_connection(
+            ConnectionKind::PrivateBroadcast,
+            address_id,
+            address,
+            self.config.allow_v1_fallback,
+        )?;
+
+        if let Some(broadcast) = self.private_broadcasts.get_mut(&txid) {
+            broadcast.peer = Some(peer);
+        }
+
+        Ok(())
+    }
+}
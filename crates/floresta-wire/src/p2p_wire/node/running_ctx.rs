@@ -6,22 +6,22 @@
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::time::Duration;
 use std::time::Instant;
 
 use bitcoin::bip158::BlockFilter;
+use bitcoin::hashes::Hash;
 use bitcoin::p2p::address::AddrV2Message;
+use bitcoin::p2p::message_blockdata::Inventory;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::BlockHash;
+use bitcoin::Wtxid;
 use floresta_chain::proof_util;
-use floresta_chain::pruned_utreexo::partial_chain::PartialChainState;
-use floresta_chain::pruned_utreexo::BlockchainInterface;
-use floresta_chain::pruned_utreexo::UpdatableChainstate;
 use floresta_chain::ThreadSafeChain;
 use floresta_common::service_flags;
 use rand::seq::IteratorRandom;
 use rand::thread_rng;
-use rustreexo::stump::Stump;
 use tokio::time;
 use tokio::time::MissedTickBehavior;
 use tracing::debug;
@@ -34,6 +34,7 @@ use crate::node::periodic_job;
 use crate::node::sync_ctx::SyncNode;
 use crate::node::try_and_log;
 use crate::node::try_and_warn;
+use crate::node::tx_relay::PendingPackage;
 use crate::node::ConnectionKind;
 use crate::node::InflightRequests;
 use crate::node::NodeNotification;
@@ -43,6 +44,8 @@ use crate::node::MAX_ADDRV2_ADDRESSES;
 use crate::node_context::LoopControl;
 use crate::node_context::NodeContext;
 use crate::node_context::PeerId;
+use crate::node_interface::NodeResponse;
+use crate::node_interface::UserRequest;
 use crate::p2p_wire::error::WireError;
 use crate::p2p_wire::peer::PeerMessages;
 
@@ -65,6 +68,15 @@ pub struct RunningNode {
     /// We also keep the moment we received the first inv message
     pub(crate) last_invs: HashMap<BlockHash, (Instant, Vec<PeerId>)>,
     pub(crate) inflight_filters: BTreeMap<u32, BlockFilter>,
+    /// Transactions we've asked for, who we asked, and when
+    pub(crate) inflight_txs: HashMap<Inventory, (PeerId, Instant)>,
+    /// Transactions we've found to be invalid, so we don't download them again
+    pub(crate) rejected_txs: HashSet<Inventory>,
+    /// Our tip when `rejected_txs` was last cleared
+    pub(crate) rejected_at_tip: BlockHash,
+    /// Transactions spending outputs we don't know about, while we download their ancestors,
+    /// indexed by their wtxid
+    pub(crate) pending_packages: HashMap<Wtxid, PendingPackage>,
 }
 
 impl NodeContext for RunningNode {
@@ -84,6 +96,10 @@ impl Default for RunningNode {
             last_address_rearrange: Instant::now(),
             last_invs: HashMap::default(),
             inflight_filters: BTreeMap::new(),
+            inflight_txs: HashMap::new(),
+            rejected_txs: HashSet::new(),
+            rejected_at_tip: BlockHash::all_zeros(),
+            pending_packages: HashMap::new(),
         }
     }
 }
@@ -95,20 +111,22 @@ where
     Chain::Error: From<proof_util::UtreexoLeafError>,
 {
     fn send_addresses(&mut self) -> Result<(), WireError> {
+        // If we are reachable, our own address goes first
         let addresses = self
-            .address_man
-            .get_addresses_to_send()
+            .local_address_message()
             .into_iter()
-            .map(|(addr, time, services, port)| AddrV2Message {
-                services,
-                addr,
-                port,
-                time: time as u32,
-            })
+            .chain(self.address_man.get_addresses_to_send().into_iter().map(
+                |(addr, time, services, port)| AddrV2Message {
+                    services,
+                    addr,
+                    port,
+                    time: time as u32,
+                },
+            ))
             .take(MAX_ADDRV2_ADDRESSES)
             .collect();
 
-        self.send_to_random_peer(NodeRequest::SendAddresses(addresses), ServiceFlags::NONE)?;
+        self.send_to_random_relay_peer(NodeRequest::SendAddresses(addresses), ServiceFlags::NONE)?;
         Ok(())
     }
 
@@ -139,6 +157,7 @@ where
     /// - 10 connections
     /// - At least one utreexo peer
     /// - At least one compact filters peer
+    /// - Our block-relay-only connections
     ///
     /// If we are missing the special peers but have 10 connections, we should disconnect one
     /// random peer and try to connect to a utreexo and a compact filters peer.
@@ -146,6 +165,10 @@ where
         // retry the added peers connections
         self.maybe_open_connection_with_added_peers()?;
 
+        // Block-relay-only peers have their own slots. Ignore the error, so failing to open one
+        // doesn't stop us from opening our regular connections
+        let _ = self.maybe_open_block_relay_connection();
+
         // if we have 10 connections, but not a single utreexo or CBF one, disconnect one random
         // peer and create a utreexo and CBS connection
         if !self.has_utreexo_peers() {
@@ -181,111 +204,6 @@ where
         Ok(())
     }
 
-    /// If either PoW fraud proofs or assumeutreexo are enabled, we will "skip" IBD for all
-    /// historical blocks. This allow us to start the node faster, making it usable in a few
-    /// minutes. If you still want to validate all blocks, you can enable the backfill option.
-    ///
-    /// This function will spawn a background task that will download and validate all blocks
-    /// that got assumed. After completion, the task will shutdown and the node will continue
-    /// running normally. If we ever assume an invalid chain, the node will [halt and catch fire].
-    ///
-    /// [halt and catch fire]: https://en.wikipedia.org/wiki/Halt_and_Catch_Fire_(computing)
-    pub fn backfill(&self, done_flag: std::sync::mpsc::Sender<()>) -> Result<bool, WireError> {
-        // try finding the last state of the sync node
-        let state = std::fs::read(self.config.datadir.clone() + "/.sync_node_state");
-        // try to recover from the disk state, if it exists. Otherwise, start from genesis
-        let (chain, end) = match state {
-            Ok(state) => {
-                // if this file is empty, this means we've finished backfilling
-                if state.is_empty() {
-                    return Ok(false);
-                }
-
-                let acc = Stump::deserialize(&state[..(state.len() - 8)]).unwrap();
-                let tip = u32::from_le_bytes(
-                    state[(state.len() - 8)..(state.len() - 4)]
-                        .try_into()
-                        .unwrap(),
-                );
-
-                let end = u32::from_le_bytes(state[(state.len() - 4)..].try_into().unwrap());
-                info!("Recovering backfill node from state tip={tip}, end={tip}");
-
-                (
-                    self.chain
-                        .get_partial_chain(tip, end, acc)
-                        .expect("Failed to get partial chain"),
-                    end,
-                )
-            }
-            Err(_) => {
-                // if the file doesn't exist or got corrupted, start from genesis
-                let end = self
-                    .chain
-                    .get_validation_index()
-                    .expect("can get the validation index");
-                (
-                    self.chain
-                        .get_partial_chain(0, end, Stump::default())
-                        .unwrap(),
-                    end,
-                )
-            }
-        };
-
-        let backfill = UtreexoNode::<PartialChainState, SyncNode>::new(
-            self.config.clone(),
-            chain,
-            self.mempool.clone(),
-            None,
-            self.kill_signal.clone(),
-            self.address_man.clone(),
-        )
-        .unwrap();
-
-        let datadir = self.config.datadir.clone();
-        let outer_chain = self.chain.clone();
-
-        let fut = UtreexoNode::<PartialChainState, SyncNode>::run(
-            backfill,
-            move |chain: &PartialChainState| {
-                if chain.has_invalid_blocks() {
-                    panic!("We assumed a chain with invalid blocks, something went really wrong");
-                }
-
-                done_flag.send(()).unwrap();
-
-                // we haven't finished the backfill yet, save the current state for the next run
-                if chain.is_in_ibd() {
-                    let acc = chain.get_acc();
-                    let tip = chain.get_height().unwrap();
-                    let mut ser_acc = Vec::new();
-                    acc.serialize(&mut ser_acc).unwrap();
-                    ser_acc.extend_from_slice(&tip.to_le_bytes());
-                    ser_acc.extend_from_slice(&end.to_le_bytes());
-                    std::fs::write(datadir + "/.sync_node_state", ser_acc)
-                        .expect("Failed to write sync node state");
-                    return;
-                }
-
-                // empty the file if we're done
-                std::fs::write(datadir + "/.sync_node_state", Vec::new())
-                    .expect("Failed to write sync node state");
-
-                for block in chain.list_valid_blocks() {
-                    outer_chain
-                        .mark_block_as_valid(block.block_hash())
-                        .expect("Failed to mark block as valid");
-                }
-
-                info!("Backfilling task shutting down...");
-            },
-        );
-
-        tokio::task::spawn(fut);
-        Ok(true)
-    }
-
     pub async fn run(mut self, stop_signal: tokio::sync::oneshot::Sender<()>) {
         try_and_warn!(self.init_peers());
 
@@ -355,6 +273,9 @@ where
                 .unwrap();
         }
 
+        // Only now we can serve peers, so we start accepting them
+        try_and_log!(self.start_listener());
+
         let mut ticker = time::interval(RunningNode::MAINTENANCE_TICK);
         // If we fall behind, don't "catch up" by running maintenance repeatedly
         ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
@@ -429,6 +350,8 @@ where
 
         // Check if some of our peers have timed out a request
         try_and_log!(self.check_for_timeout());
+        self.expire_tx_requests();
+        try_and_log!(self.check_private_broadcasts().await);
 
         // Open new feeler connection periodically
         periodic_job!(
@@ -622,6 +545,16 @@ where
         notification: NodeNotification,
     ) -> Result<(), WireError> {
         match notification {
+            // We can only validate packages once we are running
+            NodeNotification::FromUser(UserRequest::SendPackage(package), responder) => {
+                let result = self.handle_user_package(package).await;
+                if let Err(e) = &result {
+                    warn!("Could not broadcast package due to {e}");
+                }
+
+                let _ = responder.send(NodeResponse::PackageBroadcastResult(result));
+            }
+
             NodeNotification::FromUser(request, responder) => {
                 self.perform_user_request(request, responder).await;
             }
@@ -630,6 +563,15 @@ where
                 self.address_man.push_addresses(&addresses);
             }
 
+            NodeNotification::BackfillInvalidBlock(block) => {
+                error!("We've assumed a chain with invalid block {block}, invalidating it");
+                self.chain.invalidate_block(block)?;
+            }
+
+            NodeNotification::InboundConnection(stream, address) => {
+                self.handle_inbound_connection(stream, address)?;
+            }
+
             NodeNotification::FromPeer(peer, message, time) => {
                 self.register_message_time(&message, peer, time);
 
@@ -700,7 +642,7 @@ where
                         if is_extra {
                             // if this is an extra peer, and the headers message is empty, disconnect it
                             if headers.is_empty() {
-                                self.increase_banscore(peer, 5)?;
+                                self.increase_banscore(peer, 5, "sent empty headers on an extra connection")?;
                                 return Ok(());
                             }
 
@@ -740,7 +682,7 @@ where
 
                             let Some(previous_height) = self.chain.get_block_height(&header.prev_blockhash)? else {
                                 // Orphan chain
-                                self.disconnect_and_ban(peer)?;
+                                self.disconnect_and_ban(peer, "sent orphan headers")?;
                                 return Ok(());
                             };
 
@@ -748,7 +690,7 @@ where
                             if current_height.saturating_sub(MAX_REORG_DEPTH) > previous_height {
                                 // peer has a super deep reorg, this could be a disk fill attack
                                 warn!("Peer {peer} is trying to reorg a very deep block, might be a disk fill attack. Banning it");
-                                self.disconnect_and_ban(peer)?;
+                                self.disconnect_and_ban(peer, "tried a very deep reorg")?;
                                 return Ok(());
                             }
 
@@ -771,7 +713,12 @@ where
                             "handshake with peer={peer} succeeded feeler={:?}",
                             version.kind
                         );
+                        let is_private_broadcast = version.kind == ConnectionKind::PrivateBroadcast;
                         self.handle_peer_ready(peer, version)?;
+
+                        if is_private_broadcast {
+                            self.handle_private_broadcast_ready(peer)?;
+                        }
                     }
 
                     PeerMessages::Disconnected(idx) => {
@@ -814,6 +761,18 @@ where
                         }
                     }
 
+                    PeerMessages::TransactionInv(inv) => {
+                        self.handle_tx_inv(inv, peer).await?;
+                    }
+
+                    PeerMessages::UtreexoTransaction(utreexo_tx) => {
+                        self.handle_utreexo_tx(utreexo_tx, peer).await?;
+                    }
+
+                    PeerMessages::PackageInfo(wtxids) => {
+                        self.handle_package_info(wtxids, peer).await?;
+                    }
+
                     _ => unreachable!("Error: `handle_peer_msg_common` should have handled remaining PeerMessages"),
                 }
             }
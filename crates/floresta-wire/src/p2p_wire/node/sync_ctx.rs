@@ -296,6 +296,12 @@ where
                 self.address_man.push_addresses(&addresses);
             }
 
+            // We only backfill once we are running
+            NodeNotification::BackfillInvalidBlock(_) => {}
+
+            // We only accept inbound connections once we are running
+            NodeNotification::InboundConnection(_, _) => {}
+
             NodeNotification::FromPeer(peer, notification, time) => {
                 self.register_message_time(&notification, peer, time);
 
@@ -0,0 +1,594 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Transaction relay for a running node.
+//!
+//! When a peer announces a transaction we don't know, we ask a utreexo peer for it, along with
+//! the utreexo proof for its inputs (see [crate::tx_proof]). With the proof, we can validate the
+//! transaction against our accumulator just like we do with blocks, and then admit it to our
+//! mempool. Transactions we accept are announced to all our other peers, which will trickle
+//! them out at random intervals.
+//!
+//! If a transaction spends outputs we don't know about, its parents probably didn't pay enough
+//! to get into our mempool by themselves. If our peer supports package relay (see
+//! [crate::package_relay]), we ask which ancestors it has, download the ones we are missing, and
+//! validate all of them as a package, letting the child pay for its parents.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::ServiceFlags;
+use bitcoin::Amount;
+use bitcoin::Transaction;
+use bitcoin::Txid;
+use bitcoin::Wtxid;
+use floresta_chain::proof_util;
+use floresta_chain::pruned_utreexo::consensus::Consensus;
+use floresta_chain::CompactLeafData;
+use floresta_chain::ThreadSafeChain;
+use floresta_chain::UtxoData;
+use floresta_common::service_flags;
+use floresta_mempool::mempool::MempoolError;
+use rustreexo::proof::Proof;
+use tracing::debug;
+
+use super::running_ctx::RunningNode;
+use super::NodeRequest;
+use super::PeerStatus;
+use super::UtreexoNode;
+use crate::node_context::PeerId;
+use crate::p2p_wire::error::WireError;
+use crate::tx_proof::UtreexoTx;
+
+/// How long we wait for a peer to send us a transaction we've asked for
+const TX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many transactions we may be waiting for at the same time
+const MAX_INFLIGHT_TXS: usize = 100;
+
+/// How many rejected transactions we remember, so we don't download them again
+const MAX_REJECTED_TXS: usize = 50_000;
+
+/// How long we wait for a peer to send us the whole package of a transaction
+const PACKAGE_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many packages we may be downloading at the same time
+const MAX_PENDING_PACKAGES: usize = 10;
+
+#[derive(Debug, Clone)]
+/// A transaction spending outputs we don't know about, while we download its ancestors
+pub(crate) struct PendingPackage {
+    /// The peer that sent us this transaction, which we ask for its ancestors
+    peer: PeerId,
+
+    /// When we asked for this package
+    since: Instant,
+
+    /// The wtxids in this package, sorted topologically, once our peer tells us
+    wtxids: Vec<Wtxid>,
+
+    /// The transactions in this package we've got so far
+    transactions: HashMap<Wtxid, UtreexoTx>,
+}
+
+impl<Chain> UtreexoNode<Chain, RunningNode>
+where
+    Chain: ThreadSafeChain + Clone,
+    WireError: From<Chain::Error>,
+    Chain::Error: From<proof_util::UtreexoLeafError>,
+{
+    /// Requests the transactions announced by `peer` that we don't know about yet.
+    pub(crate) async fn handle_tx_inv(
+        &mut self,
+        inv: Vec<Inventory>,
+        peer: PeerId,
+    ) -> Result<(), WireError> {
+        // We can't validate anything without being in sync
+        if self.chain.is_in_ibd() {
+            return Ok(());
+        }
+
+        // Without script validation, we'd accept and relay transactions with invalid signatures
+        if !Consensus::VERIFIES_SCRIPTS {
+            return Ok(());
+        }
+
+        self.clear_rejected_on_new_tip()?;
+
+        // Our own transactions, if announced back to us, don't need to be downloaded
+        let inv = self.handle_private_broadcast_inv(inv, peer);
+
+        // If this peer can't give us the proofs, we'll ask someone else
+        let utreexo_service: ServiceFlags = service_flags::UTREEXO.into();
+        let peer_is_utreexo = self
+            .peers
+            .get(&peer)
+            .is_some_and(|info| info.services.has(utreexo_service));
+
+        for inv in inv {
+            if self.context.inflight_txs.len() >= MAX_INFLIGHT_TXS {
+                break;
+            }
+
+            if self.context.inflight_txs.contains_key(&inv)
+                || self.context.rejected_txs.contains(&inv)
+            {
+                continue;
+            }
+
+            let known = {
+                let mempool = self.mempool.lock().await;
+                match inv {
+                    Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                        mempool.get_from_mempool(&txid).is_some()
+                    }
+                    Inventory::WTx(wtxid) => mempool.contains_wtxid(&wtxid),
+                    _ => true,
+                }
+            };
+
+            if known {
+                continue;
+            }
+
+            let request = NodeRequest::GetUtreexoTransaction(inv);
+            let requested_from = match peer_is_utreexo {
+                true => {
+                    self.send_to_peer(peer, request)?;
+                    peer
+                }
+                false => match self.send_to_random_relay_peer(request, utreexo_service) {
+                    Ok(peer) => peer,
+                    // No utreexo peers right now, we'll get more announcements later
+                    Err(_) => break,
+                },
+            };
+
+            self.context
+                .inflight_txs
+                .insert(inv, (requested_from, Instant::now()));
+        }
+
+        Ok(())
+    }
+
+    /// Validates a transaction we've requested and, if it's valid, adds it to our mempool and
+    /// announces it to our other peers.
+    pub(crate) async fn handle_utreexo_tx(
+        &mut self,
+        utreexo_tx: UtreexoTx,
+        peer: PeerId,
+    ) -> Result<(), WireError> {
+        let txid = utreexo_tx.transaction.compute_txid();
+        let wtxid = utreexo_tx.transaction.compute_wtxid();
+        let invs = tx_inventories(txid, wtxid);
+
+        let requested_from: Vec<_> = invs
+            .iter()
+            .filter_map(|inv| self.context.inflight_txs.remove(inv))
+            .collect();
+
+        let requested = requested_from.iter().any(|(from, _)| *from == peer);
+
+        if !requested {
+            debug!("Peer {peer} sent us transaction {txid}, but we didn't ask for it");
+            return Ok(());
+        }
+
+        self.clear_rejected_on_new_tip()?;
+
+        // This may be the ancestor of a transaction we are waiting for
+        let child = self
+            .context
+            .pending_packages
+            .iter()
+            .find(|(_, package)| package.peer == peer && package.wtxids.contains(&wtxid))
+            .map(|(child, _)| *child);
+
+        if let Some(child) = child {
+            if let Some(package) = self.context.pending_packages.get_mut(&child) {
+                package.transactions.insert(wtxid, utreexo_tx);
+            }
+
+            return self.try_accept_package(child).await;
+        }
+
+        // Inputs that aren't confirmed, nor in our mempool, spend from parents we don't have
+        let unconfirmed = self
+            .mempool
+            .lock()
+            .await
+            .get_unconfirmed_prevouts(&utreexo_tx.transaction)
+            .len();
+
+        if utreexo_tx.leaf_data.len() + unconfirmed < utreexo_tx.transaction.input.len() {
+            debug!("Transaction {txid} from peer {peer} spends unknown outputs");
+            return self.request_package(utreexo_tx, peer);
+        }
+
+        let UtreexoTx {
+            transaction,
+            proof_hashes,
+            targets,
+            leaf_data,
+        } = utreexo_tx;
+
+        let proof = Proof {
+            targets,
+            hashes: proof_hashes,
+        };
+
+        let fee = match self
+            .validate_mempool_tx(&transaction, proof, &leaf_data, &[])
+            .await
+        {
+            Ok(fee) => fee,
+            Err(e) => {
+                debug!("Transaction {txid} from peer {peer} is invalid: {e:?}");
+                self.reject_tx(&invs);
+                return Ok(());
+            }
+        };
+
+        let accepted = self
+            .mempool
+            .lock()
+            .await
+            .accept_to_mempool_with_fee(transaction, fee);
+
+        if let Err(e) = accepted {
+            debug!("Transaction {txid} from peer {peer} not accepted: {e}");
+            self.reject_tx(&invs);
+            return Ok(());
+        }
+
+        debug!("Accepted transaction {txid} from peer {peer}");
+        self.announce_tx(txid, peer);
+
+        Ok(())
+    }
+
+    /// Asks `peer` for the ancestors of a transaction spending outputs we don't know about, so we
+    /// can validate all of them together
+    fn request_package(&mut self, utreexo_tx: UtreexoTx, peer: PeerId) -> Result<(), WireError> {
+        let wtxid = utreexo_tx.transaction.compute_wtxid();
+        let pending = &self.context.pending_packages;
+
+        // We may get this transaction again later, once we know its parents
+        if pending.len() >= MAX_PENDING_PACKAGES || pending.contains_key(&wtxid) {
+            return Ok(());
+        }
+
+        self.send_to_peer(peer, NodeRequest::GetPackageInfo(wtxid))?;
+        self.context.pending_packages.insert(
+            wtxid,
+            PendingPackage {
+                peer,
+                since: Instant::now(),
+                wtxids: Vec::new(),
+                transactions: HashMap::from([(wtxid, utreexo_tx)]),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Downloads the ancestors we are missing from a package our peer told us about
+    pub(crate) async fn handle_package_info(
+        &mut self,
+        wtxids: Vec<Wtxid>,
+        peer: PeerId,
+    ) -> Result<(), WireError> {
+        let Some(child) = wtxids.last().copied() else {
+            return Ok(());
+        };
+
+        let is_expected = self
+            .context
+            .pending_packages
+            .get(&child)
+            .is_some_and(|package| package.peer == peer && package.wtxids.is_empty());
+
+        if !is_expected {
+            debug!("Peer {peer} sent us a package we didn't ask for");
+            return Ok(());
+        }
+
+        let missing: Vec<Wtxid> = {
+            let mempool = self.mempool.lock().await;
+            wtxids
+                .iter()
+                .filter(|wtxid| **wtxid != child && !mempool.contains_wtxid(wtxid))
+                .copied()
+                .collect()
+        };
+
+        for wtxid in missing {
+            let inv = Inventory::WTx(wtxid);
+            self.send_to_peer(peer, NodeRequest::GetUtreexoTransaction(inv))?;
+            self.context
+                .inflight_txs
+                .insert(inv, (peer, Instant::now()));
+        }
+
+        if let Some(package) = self.context.pending_packages.get_mut(&child) {
+            package.wtxids = wtxids;
+        }
+
+        self.try_accept_package(child).await
+    }
+
+    /// Validates a package once we have all its transactions, and adds it to our mempool
+    async fn try_accept_package(&mut self, child: Wtxid) -> Result<(), WireError> {
+        let Some(package) = self.context.pending_packages.get(&child) else {
+            return Ok(());
+        };
+
+        // Ancestors already in our mempool don't need to be validated again, so they have no
+        // utreexo data
+        let mut transactions = Vec::new();
+        {
+            let mempool = self.mempool.lock().await;
+            for wtxid in package.wtxids.iter() {
+                match package.transactions.get(wtxid) {
+                    Some(utreexo_tx) => transactions
+                        .push((utreexo_tx.transaction.clone(), Some(utreexo_tx.clone()))),
+                    None => match mempool.get_by_wtxid(wtxid) {
+                        Some(tx) => transactions.push((tx.clone(), None)),
+                        // We are still waiting for something
+                        None => return Ok(()),
+                    },
+                }
+            }
+        }
+
+        if transactions.is_empty() {
+            return Ok(());
+        }
+
+        let Some(PendingPackage { peer, .. }) = self.context.pending_packages.remove(&child) else {
+            return Ok(());
+        };
+
+        let mut package = Vec::new();
+        let mut fees = Vec::new();
+        let mut new_txs = Vec::new();
+
+        for (transaction, utreexo_tx) in transactions {
+            // This fee is never used, the mempool skips transactions it already has
+            let Some(utreexo_tx) = utreexo_tx else {
+                package.push(transaction);
+                fees.push(Amount::ZERO);
+                continue;
+            };
+
+            let proof = Proof {
+                targets: utreexo_tx.targets,
+                hashes: utreexo_tx.proof_hashes,
+            };
+            let leaf_data = utreexo_tx.leaf_data;
+
+            let txid = transaction.compute_txid();
+            let wtxid = transaction.compute_wtxid();
+            new_txs.push((txid, wtxid));
+
+            match self
+                .validate_mempool_tx(&transaction, proof, &leaf_data, &package)
+                .await
+            {
+                Ok(fee) => fees.push(fee),
+                Err(e) => {
+                    debug!("Transaction {txid} from peer {peer} is invalid: {e:?}");
+                    self.reject_tx(&tx_inventories(txid, wtxid));
+                    return Ok(());
+                }
+            }
+
+            package.push(transaction);
+        }
+
+        let accepted = self
+            .mempool
+            .lock()
+            .await
+            .accept_package_with_fees(package, fees);
+
+        if let Err(e) = accepted {
+            debug!("Package from peer {peer} not accepted: {e}");
+            for (txid, wtxid) in new_txs {
+                self.reject_tx(&tx_inventories(txid, wtxid));
+            }
+
+            return Ok(());
+        }
+
+        debug!(
+            "Accepted a package with {} new transactions from peer {peer}",
+            new_txs.len()
+        );
+
+        for (txid, _) in new_txs {
+            self.announce_tx(txid, peer);
+        }
+
+        Ok(())
+    }
+
+    /// Validates a package submitted by our user, adds it to our mempool and broadcasts it.
+    ///
+    /// Users don't give us utreexo proofs, so this only works for transactions spending outputs
+    /// of our mempool or of earlier transactions in this package.
+    pub(crate) async fn handle_user_package(
+        &mut self,
+        package: Vec<Transaction>,
+    ) -> Result<Vec<Txid>, MempoolError> {
+        if self.chain.is_in_ibd() {
+            return Err(MempoolError::InvalidPackage(
+                "we can't validate packages before we are in sync",
+            ));
+        }
+
+        let mut fees = Vec::new();
+        for (i, transaction) in package.iter().enumerate() {
+            // This fee is never used, the mempool skips transactions it already has
+            let known = self
+                .mempool
+                .lock()
+                .await
+                .get_from_mempool(&transaction.compute_txid())
+                .is_some();
+
+            if known {
+                fees.push(Amount::ZERO);
+                continue;
+            }
+
+            let fee = self
+                .validate_mempool_tx(transaction, Proof::default(), &[], &package[..i])
+                .await
+                .map_err(|e| match e {
+                    WireError::Blockchain(e) => MempoolError::ConsensusValidation(e),
+                    e => {
+                        debug!("Failed to validate a package transaction: {e:?}");
+                        MempoolError::InvalidPackage("we couldn't validate this package")
+                    }
+                })?;
+
+            fees.push(fee);
+        }
+
+        let ids: Vec<_> = package
+            .iter()
+            .map(|tx| (tx.compute_txid(), tx.compute_wtxid()))
+            .collect();
+
+        self.mempool
+            .lock()
+            .await
+            .accept_package_with_fees(package, fees)?;
+
+        // Our peers will ask for the parents if they need them
+        for (txid, wtxid) in ids.iter() {
+            match self.config.private_broadcast {
+                true => self.start_private_broadcast(*txid, *wtxid),
+                false => self.broadcast_to_peers(NodeRequest::BroadcastTransaction(*txid)),
+            }
+        }
+
+        Ok(ids.into_iter().map(|(txid, _)| txid).collect())
+    }
+
+    /// Announces a transaction we've just accepted to all peers, except the one who sent it
+    fn announce_tx(&self, txid: Txid, from: PeerId) {
+        for (id, info) in self.peers.iter() {
+            if *id == from
+                || info.state != PeerStatus::Ready
+                || info.is_block_relay_only()
+                || info.is_private_broadcast()
+            {
+                continue;
+            }
+
+            let _ = info.channel.send(NodeRequest::BroadcastTransaction(txid));
+        }
+    }
+
+    /// Forgets transactions our peers took too long to send. They may be announced again later.
+    pub(crate) fn expire_tx_requests(&mut self) {
+        self.context
+            .inflight_txs
+            .retain(|_, (_, when)| when.elapsed() < TX_REQUEST_TIMEOUT);
+
+        self.context
+            .pending_packages
+            .retain(|_, package| package.since.elapsed() < PACKAGE_REQUEST_TIMEOUT);
+    }
+
+    /// Checks a transaction's inputs and scripts against our accumulator, returning its fee.
+    ///
+    /// Inputs spending outputs of transactions in our mempool, or in `package`, don't need a
+    /// proof.
+    async fn validate_mempool_tx(
+        &self,
+        transaction: &Transaction,
+        proof: Proof,
+        leaf_data: &[CompactLeafData],
+        package: &[Transaction],
+    ) -> Result<Amount, WireError> {
+        let mut unconfirmed = self
+            .mempool
+            .lock()
+            .await
+            .get_unconfirmed_prevouts(transaction);
+
+        for input in transaction.input.iter() {
+            let outpoint = input.previous_output;
+            let output = package
+                .iter()
+                .find(|tx| tx.compute_txid() == outpoint.txid)
+                .and_then(|tx| tx.output.get(outpoint.vout as usize));
+
+            if let Some(output) = output {
+                unconfirmed.insert(outpoint, output.clone());
+            }
+        }
+
+        // An unconfirmed output would be confirmed, at the earliest, in the next block
+        let height = self.chain.get_best_block()?.0 + 1;
+        let unconfirmed = unconfirmed
+            .into_iter()
+            .map(|(prevout, txout)| {
+                let utxo = UtxoData {
+                    txout,
+                    is_coinbase: false,
+                    creation_height: height,
+                    creation_time: 0,
+                };
+
+                (prevout, utxo)
+            })
+            .collect();
+
+        let (del_hashes, inputs) =
+            proof_util::process_tx_proof(leaf_data, transaction, unconfirmed, |h| {
+                self.chain.get_block_hash(h)
+            })?;
+
+        let fee = self
+            .chain
+            .validate_transaction(transaction, proof, inputs, del_hashes)?;
+
+        Ok(fee)
+    }
+
+    /// Remembers that these transactions are invalid, so we don't ask for them again.
+    fn reject_tx(&mut self, invs: &[Inventory]) {
+        if self.context.rejected_txs.len() + invs.len() > MAX_REJECTED_TXS {
+            self.context.rejected_txs.clear();
+        }
+
+        self.context.rejected_txs.extend(invs.iter().copied());
+    }
+
+    /// A transaction rejected for spending an unknown output may become valid after a new block,
+    /// so we forget the rejected transactions whenever our tip changes.
+    fn clear_rejected_on_new_tip(&mut self) -> Result<(), WireError> {
+        let (_, tip) = self.chain.get_best_block()?;
+        if self.context.rejected_at_tip != tip {
+            self.context.rejected_txs.clear();
+            self.context.rejected_at_tip = tip;
+        }
+
+        Ok(())
+    }
+}
+
+/// All the inventories a peer may use to announce this transaction
+fn tx_inventories(txid: Txid, wtxid: Wtxid) -> [Inventory; 3] {
+    [
+        Inventory::Transaction(txid),
+        Inventory::WitnessTransaction(txid),
+        Inventory::WTx(wtxid),
+    ]
+}
@@ -4,16 +4,24 @@ use std::time::Instant;
 
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::Block;
+use floresta_chain::AssumeUtreexoValue;
 use floresta_chain::ChainBackend;
+use floresta_mempool::mempool::MempoolError;
+use rustreexo::stump::Stump;
 use tokio::sync::oneshot;
 use tracing::debug;
 use tracing::info;
 use tracing::warn;
 
+use super::backfill::extend_backfill;
 use super::try_and_log;
+use super::try_and_warn;
+use super::InflightRequests;
 use super::NodeRequest;
+use super::PeerStatus;
 use super::UtreexoNode;
 use crate::block_proof::Bitmap;
+use crate::fraud_proofs::FraudProofStatus;
 use crate::node::running_ctx::RunningNode;
 use crate::node_context::NodeContext;
 use crate::node_interface::NodeInterface;
@@ -43,10 +51,64 @@ where
             peers.push(self.get_peer_info(peer));
         }
 
+        // Peers that connected to us aren't in `peer_ids`, since we don't send them requests
+        let inbound = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.is_inbound() && peer.state == PeerStatus::Ready);
+        for (peer, _) in inbound {
+            peers.push(self.get_peer_info(peer));
+        }
+
         let peers = peers.into_iter().flatten().collect();
         try_and_log!(responder.send(NodeResponse::GetPeerInfo(peers)));
     }
 
+    /// Starts from a Utreexo accumulator, assuming all blocks up to it are valid. Its block must
+    /// be in our best chain, and after our validation index.
+    fn load_utreexo_state(&mut self, value: AssumeUtreexoValue) -> Result<(), WireError> {
+        let in_chain = self
+            .chain
+            .get_block_hash(value.height)
+            .is_ok_and(|hash| hash == value.block_hash);
+
+        if !in_chain {
+            return Err(WireError::SnapshotNotInChain(value.block_hash));
+        }
+
+        let validation_index = self.chain.get_validation_index()?;
+        if validation_index >= value.height {
+            return Err(WireError::SnapshotAlreadyValidated(validation_index));
+        }
+
+        if self.config.backfill {
+            extend_backfill(
+                &self.datadir,
+                validation_index,
+                self.chain.acc(),
+                value.height,
+            )?;
+        }
+
+        info!(
+            "Assuming chain with height={} tip={}",
+            value.height, value.block_hash
+        );
+        let acc = Stump {
+            leaves: value.leaves,
+            roots: value.roots,
+        };
+        self.chain.mark_chain_as_assumed(acc, value.block_hash)?;
+
+        // We don't need the blocks before our new validation index anymore
+        self.blocks.clear();
+        self.inflight
+            .retain(|req, _| !matches!(req, InflightRequests::Blocks(_)));
+        self.last_block_request = value.height;
+
+        Ok(())
+    }
+
     /// Actually perform the user request
     ///
     /// These are requests made by some consumer of `floresta-wire` using the [`NodeInterface`], and may
@@ -64,7 +126,7 @@ where
 
         let req = match user_req {
             UserRequest::Config => {
-                let config = self.common.config.clone();
+                let config = Box::new(self.common.config.clone());
                 let _ = responder.send(NodeResponse::Config(config));
 
                 return;
@@ -162,6 +224,7 @@ where
 
             UserRequest::SendTransaction(transaction) => {
                 let txid = transaction.compute_txid();
+                let wtxid = transaction.compute_wtxid();
                 let mut mempool = self.mempool.lock().await;
 
                 if let Err(e) = mempool.accept_to_mempool(transaction) {
@@ -173,10 +236,88 @@ where
                 drop(mempool);
 
                 // Announce the transaction to our peers, broadcast from mempool if requested
-                self.broadcast_to_peers(NodeRequest::BroadcastTransaction(txid));
+                match self.config.private_broadcast {
+                    true => self.start_private_broadcast(txid, wtxid),
+                    false => self.broadcast_to_peers(NodeRequest::BroadcastTransaction(txid)),
+                }
+
                 let _ = responder.send(NodeResponse::TransactionBroadcastResult(Ok(txid)));
                 return;
             }
+
+            // The running node validates packages before accepting them, see
+            // `handle_user_package`. Before that, we can't validate them.
+            UserRequest::SendPackage(_) => {
+                let e = MempoolError::InvalidPackage(
+                    "we can't validate packages before we are in sync",
+                );
+                let _ = responder.send(NodeResponse::PackageBroadcastResult(Err(e)));
+                return;
+            }
+
+            UserRequest::Ban((subnet, until)) => {
+                let banned = self.handle_ban_subnet(subnet, until);
+                if banned {
+                    info!("Banned {subnet}");
+                }
+
+                let _ = responder.send(NodeResponse::Ban(banned));
+                return;
+            }
+
+            UserRequest::Unban(subnet) => {
+                let unbanned = match self.ban_man.unban(&subnet) {
+                    Ok(unbanned) => unbanned,
+                    Err(e) => {
+                        warn!("Could not save the ban list: {e}");
+                        true
+                    }
+                };
+
+                let _ = responder.send(NodeResponse::Unban(unbanned));
+                return;
+            }
+
+            UserRequest::ListBanned => {
+                let bans = self.ban_man.list();
+                let _ = responder.send(NodeResponse::ListBanned(bans));
+                return;
+            }
+
+            UserRequest::ClearBanned => {
+                try_and_warn!(self.ban_man.clear());
+                let _ = responder.send(NodeResponse::ClearBanned(true));
+                return;
+            }
+
+            UserRequest::GetNetTotals => {
+                let totals = self.traffic.totals();
+                let _ = responder.send(NodeResponse::GetNetTotals(totals));
+                return;
+            }
+
+            UserRequest::GetFraudProofStatus => {
+                let status = FraudProofStatus {
+                    enabled: self.config.pow_fraud_proofs,
+                    checks: self.fraud_proofs.checks().to_vec(),
+                };
+
+                let _ = responder.send(NodeResponse::GetFraudProofStatus(status));
+                return;
+            }
+
+            UserRequest::LoadUtreexoState(value) => {
+                let result = self.load_utreexo_state(value);
+                let _ = responder.send(NodeResponse::LoadUtreexoState(result));
+                return;
+            }
+
+            UserRequest::AdvertiseAddress((address, port)) => {
+                info!("Announcing {address:?} port={port} to our peers");
+                self.local_address = Some((address, port));
+                let _ = responder.send(NodeResponse::AdvertiseAddress(true));
+                return;
+            }
         };
 
         let peer = self.send_to_fast_peer(req, ServiceFlags::NONE);
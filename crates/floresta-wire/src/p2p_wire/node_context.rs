@@ -39,6 +39,12 @@ pub trait NodeContext {
     /// Max number of simultaneous connections we initiates we are willing to hold
     const MAX_OUTGOING_PEERS: usize = 10;
 
+    /// How many block-relay-only connections we keep, on top of [`Self::MAX_OUTGOING_PEERS`]
+    const MAX_BLOCK_RELAY_PEERS: usize = 2;
+
+    /// How many peers may be connected to us at the same time, on top of our outbound ones
+    const MAX_INBOUND_PEERS: usize = 16;
+
     /// We ask for peers every ASK_FOR_PEERS_INTERVAL seconds
     const ASK_FOR_PEERS_INTERVAL: u64 = 60 * 60; // One hour
 
@@ -7,20 +7,28 @@ use core::net::IpAddr;
 use core::net::SocketAddr;
 use std::time::Instant;
 
+use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Transaction;
 use bitcoin::Txid;
+use floresta_chain::AssumeUtreexoValue;
 use floresta_mempool::mempool::MempoolError;
 use rustreexo::proof::Proof;
 use serde::Serialize;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
 
+use super::ban_man::BanEntry;
+use super::ban_man::Subnet;
+use super::error::WireError;
+use super::fraud_proofs::FraudProofStatus;
 use super::node::ConnectionKind;
 use super::node::NodeNotification;
 use super::node::PeerStatus;
+use super::traffic::NetTotals;
+use super::traffic::PeerTraffic;
 use super::transport::TransportProtocol;
 use super::UtreexoNodeConfig;
 
@@ -96,6 +104,33 @@ pub enum UserRequest {
 
     /// Adds a transaction to mempool and advertises it
     SendTransaction(Transaction),
+
+    /// Adds a child with its unconfirmed parents to mempool and advertises them
+    SendPackage(Vec<Transaction>),
+
+    /// Bans a subnet until the given UNIX timestamp, disconnecting the peers inside it.
+    Ban((Subnet, u64)),
+
+    /// Lifts the ban on a subnet.
+    Unban(Subnet),
+
+    /// Return all active bans.
+    ListBanned,
+
+    /// Lifts all bans.
+    ClearBanned,
+
+    /// Return how many bytes we exchanged with our peers, and the state of our upload target.
+    GetNetTotals,
+
+    /// Return whether we use PoW fraud proofs, and the tips we've checked with them.
+    GetFraudProofStatus,
+
+    /// Start from this Utreexo accumulator, skipping the validation of all blocks up to it.
+    LoadUtreexoState(AssumeUtreexoValue),
+
+    /// Announce this address and port to our peers, as a way to reach us.
+    AdvertiseAddress((AddrV2, u16)),
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -114,6 +149,9 @@ pub struct PeerInfo {
     pub state: PeerStatus,
     pub kind: ConnectionKind,
     pub transport_protocol: TransportProtocol,
+    /// How many bytes we exchanged with this peer, in total and for each message command
+    #[serde(flatten)]
+    pub traffic: PeerTraffic,
 }
 
 #[derive(Debug)]
@@ -123,7 +161,7 @@ pub struct PeerInfo {
 /// represents all the possible responses that the node can send back to the user.
 pub enum NodeResponse {
     /// The [`UtreexoNodeConfig`] of the node.
-    Config(UtreexoNodeConfig),
+    Config(Box<UtreexoNodeConfig>),
 
     /// A response containing a block, if we could fetch it.
     Block(Option<Block>),
@@ -157,6 +195,33 @@ pub enum NodeResponse {
 
     /// Transaction broadcast
     TransactionBroadcastResult(Result<Txid, MempoolError>),
+
+    /// Package broadcast, with the txid of each transaction in the package
+    PackageBroadcastResult(Result<Vec<Txid>, MempoolError>),
+
+    /// A response indicating whether a subnet was banned, or `false` if it was already banned.
+    Ban(bool),
+
+    /// A response indicating whether a subnet was unbanned, or `false` if it wasn't banned.
+    Unban(bool),
+
+    /// A response containing all active bans.
+    ListBanned(Vec<BanEntry>),
+
+    /// A response indicating that all bans were lifted.
+    ClearBanned(bool),
+
+    /// A response containing our traffic totals.
+    GetNetTotals(NetTotals),
+
+    /// A response containing our PoW fraud proof status.
+    GetFraudProofStatus(FraudProofStatus),
+
+    /// A response indicating whether we've loaded a Utreexo accumulator.
+    LoadUtreexoState(Result<(), WireError>),
+
+    /// A response indicating that we'll announce our address.
+    AdvertiseAddress(bool),
 }
 
 #[derive(Debug, Clone)]
@@ -201,7 +266,10 @@ impl NodeInterface {
     pub async fn get_config(&self) -> Result<UtreexoNodeConfig, oneshot::error::RecvError> {
         let config = self.send_request(UserRequest::Config).await?;
 
-        extract_variant!(Config, config);
+        match config {
+            NodeResponse::Config(config) => Ok(*config),
+            _ => panic!("Unexpected variant"),
+        }
     }
 
     pub async fn broadcast_transaction(
@@ -215,6 +283,21 @@ impl NodeInterface {
         extract_variant!(TransactionBroadcastResult, val)
     }
 
+    /// Adds a package to our mempool and broadcasts it.
+    ///
+    /// The package must be a child with all its unconfirmed parents, sorted so parents come
+    /// first. Transactions are validated together, so the child can pay for parents that
+    /// wouldn't get into the mempool by themselves. We don't have proofs for confirmed outputs,
+    /// so transactions may only spend outputs of our mempool or of earlier package transactions.
+    pub async fn submit_package(
+        &self,
+        package: Vec<Transaction>,
+    ) -> Result<Result<Vec<Txid>, MempoolError>, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::SendPackage(package)).await?;
+
+        extract_variant!(PackageBroadcastResult, val)
+    }
+
     /// Connects to a specified address and port.
     /// This function will return a boolean indicating whether the connection was successful. It
     /// may be called multiple times, and may use hostnames or IP addresses.
@@ -327,6 +410,89 @@ impl NodeInterface {
 
         extract_variant!(Ping, val)
     }
+
+    /// Bans a subnet until the UNIX timestamp `until`, disconnecting every peer inside it.
+    ///
+    /// Returns `false` if this subnet is already banned.
+    pub async fn ban_subnet(
+        &self,
+        subnet: Subnet,
+        until: u64,
+    ) -> Result<bool, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::Ban((subnet, until))).await?;
+
+        extract_variant!(Ban, val);
+    }
+
+    /// Lifts the ban on a subnet, returning `false` if it wasn't banned.
+    pub async fn unban_subnet(&self, subnet: Subnet) -> Result<bool, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::Unban(subnet)).await?;
+
+        extract_variant!(Unban, val);
+    }
+
+    /// Returns all subnets we're currently banning.
+    pub async fn list_banned(&self) -> Result<Vec<BanEntry>, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::ListBanned).await?;
+
+        extract_variant!(ListBanned, val);
+    }
+
+    /// Lifts all bans.
+    pub async fn clear_banned(&self) -> Result<bool, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::ClearBanned).await?;
+
+        extract_variant!(ClearBanned, val);
+    }
+
+    /// Returns how many bytes we exchanged with all our peers since we started, and the state
+    /// of our upload target.
+    pub async fn get_net_totals(&self) -> Result<NetTotals, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::GetNetTotals).await?;
+
+        extract_variant!(GetNetTotals, val);
+    }
+
+    /// Returns whether we use PoW fraud proofs, and every disputed tip we've checked with them,
+    /// along with what we concluded.
+    pub async fn get_fraud_proof_status(
+        &self,
+    ) -> Result<FraudProofStatus, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::GetFraudProofStatus).await?;
+
+        extract_variant!(GetFraudProofStatus, val);
+    }
+
+    /// Starts from this Utreexo accumulator, assuming all blocks up to it are valid.
+    ///
+    /// The accumulator's block must be in our best chain, and after our validation index. If
+    /// backfill is enabled, the blocks we skip are validated the next time the node starts.
+    pub async fn load_utreexo_state(
+        &self,
+        value: AssumeUtreexoValue,
+    ) -> Result<Result<(), WireError>, oneshot::error::RecvError> {
+        let val = self
+            .send_request(UserRequest::LoadUtreexoState(value))
+            .await?;
+
+        extract_variant!(LoadUtreexoState, val);
+    }
+
+    /// Announces `address` to our peers as a way to reach us, on `port`.
+    ///
+    /// This is how peers learn about our onion service. We send it to each peer we connect to,
+    /// and along with the addresses we share every once in a while.
+    pub async fn advertise_address(
+        &self,
+        address: AddrV2,
+        port: u16,
+    ) -> Result<bool, oneshot::error::RecvError> {
+        let val = self
+            .send_request(UserRequest::AdvertiseAddress((address, port)))
+            .await?;
+
+        extract_variant!(AdvertiseAddress, val);
+    }
 }
 
 fn serialize_service_flags<S>(flags: &ServiceFlags, serializer: S) -> Result<S::Ok, S::Error>
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Ancestor package relay (BIP 331).
+//!
+//! With transactions relayed one at a time, a child can't pay for a parent that doesn't pay
+//! enough to get into mempools by itself, since nobody has the parent when the child arrives.
+//! Package relay lets peers ask for the unconfirmed ancestors of a transaction, so they can
+//! validate all of them together (see [`Mempool::accept_package_with_fees`]).
+//!
+//! Both sides signal support with a `sendpackages` message, between `version` and `verack`,
+//! and must also negotiate wtxid relay (BIP 339). When we get a transaction spending outputs we
+//! don't know about, we send a `getdata` with a [MSG_ANCPKGINFO] inventory for it, and our peer
+//! answers with an [AncPkgInfo], listing the wtxids of the transaction and its ancestors. We
+//! then download the ones we are missing, with their utreexo proofs (see [crate::tx_proof]).
+//!
+//! Peers may also ask for those transactions with a [GetPkgTxns], that we answer with a
+//! [PkgTxns].
+//!
+//! [`Mempool::accept_package_with_fees`]: floresta_mempool::Mempool::accept_package_with_fees
+
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::Transaction;
+use bitcoin::VarInt;
+use bitcoin::Wtxid;
+use floresta_common::read_bounded_len;
+use floresta_mempool::mempool::MAX_PACKAGE_COUNT;
+
+/// The command string for the message signaling support for package relay
+pub const SENDPACKAGES_CMD_STRING: &str = "sendpackages";
+
+/// The command string for the message listing a transaction's ancestor package
+pub const ANCPKGINFO_CMD_STRING: &str = "ancpkginfo";
+
+/// The command string for the message asking for a package's transactions
+pub const GETPKGTXNS_CMD_STRING: &str = "getpkgtxns";
+
+/// The command string for the message with a package's transactions
+pub const PKGTXNS_CMD_STRING: &str = "pkgtxns";
+
+/// The bit in `sendpackages` signaling support for ancestor packages
+pub const PKG_RELAY_ANCPKG: u64 = 1 << 0;
+
+/// The inventory type used to ask for a transaction's ancestor package
+pub const MSG_ANCPKGINFO: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Signals which kinds of package relay a node supports
+pub struct SendPackages {
+    /// A bit field with the supported package types, like [PKG_RELAY_ANCPKG]
+    pub versions: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The wtxids of a transaction and all its unconfirmed ancestors, sorted topologically. The
+/// transaction itself is the last one.
+pub struct AncPkgInfo {
+    pub wtxids: Vec<Wtxid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Asks for the transactions in a package
+pub struct GetPkgTxns {
+    pub wtxids: Vec<Wtxid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The transactions in a package, answering a [GetPkgTxns]
+pub struct PkgTxns {
+    pub transactions: Vec<Transaction>,
+}
+
+/// Returns the inventory we should use in a `getdata` to ask for this transaction's ancestor
+/// package
+pub fn ancpkginfo_inventory(wtxid: Wtxid) -> Inventory {
+    Inventory::Unknown {
+        inv_type: MSG_ANCPKGINFO,
+        hash: wtxid.to_byte_array(),
+    }
+}
+
+/// If this inventory asks for an ancestor package, returns the wtxid of the transaction
+pub fn ancpkginfo_wtxid(inv: &Inventory) -> Option<Wtxid> {
+    match inv {
+        Inventory::Unknown { inv_type, hash } if *inv_type == MSG_ANCPKGINFO => {
+            Some(Wtxid::from_byte_array(*hash))
+        }
+        _ => None,
+    }
+}
+
+/// Writes a list of wtxids, prefixed by its length
+fn encode_wtxids<W: bitcoin::io::Write + ?Sized>(
+    wtxids: &[Wtxid],
+    writer: &mut W,
+) -> Result<usize, bitcoin::io::Error> {
+    let mut len = VarInt(wtxids.len() as u64).consensus_encode(writer)?;
+    for wtxid in wtxids {
+        len += wtxid.consensus_encode(writer)?;
+    }
+
+    Ok(len)
+}
+
+/// Reads a list of wtxids, no larger than a package
+fn decode_wtxids<R: bitcoin::io::Read + ?Sized>(
+    reader: &mut R,
+) -> Result<Vec<Wtxid>, bitcoin::consensus::encode::Error> {
+    let n_wtxids = read_bounded_len(reader, MAX_PACKAGE_COUNT)?;
+    let mut wtxids = Vec::with_capacity(n_wtxids);
+    for _ in 0..n_wtxids {
+        wtxids.push(Wtxid::consensus_decode(reader)?);
+    }
+
+    Ok(wtxids)
+}
+
+impl Encodable for SendPackages {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        self.versions.consensus_encode(writer)
+    }
+}
+
+impl Decodable for SendPackages {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(SendPackages {
+            versions: u64::consensus_decode(reader)?,
+        })
+    }
+}
+
+impl Encodable for AncPkgInfo {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        encode_wtxids(&self.wtxids, writer)
+    }
+}
+
+impl Decodable for AncPkgInfo {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(AncPkgInfo {
+            wtxids: decode_wtxids(reader)?,
+        })
+    }
+}
+
+impl Encodable for GetPkgTxns {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        encode_wtxids(&self.wtxids, writer)
+    }
+}
+
+impl Decodable for GetPkgTxns {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(GetPkgTxns {
+            wtxids: decode_wtxids(reader)?,
+        })
+    }
+}
+
+impl Encodable for PkgTxns {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = VarInt(self.transactions.len() as u64).consensus_encode(writer)?;
+        for transaction in self.transactions.iter() {
+            len += transaction.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for PkgTxns {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let n_transactions = read_bounded_len(reader, MAX_PACKAGE_COUNT)?;
+        let mut transactions = Vec::with_capacity(n_transactions);
+        for _ in 0..n_transactions {
+            transactions.push(Transaction::consensus_decode(reader)?);
+        }
+
+        Ok(PkgTxns { transactions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::message_blockdata::Inventory;
+    use bitcoin::Wtxid;
+    use floresta_mempool::mempool::MAX_PACKAGE_COUNT;
+
+    use super::ancpkginfo_inventory;
+    use super::ancpkginfo_wtxid;
+    use super::AncPkgInfo;
+    use super::SendPackages;
+    use super::PKG_RELAY_ANCPKG;
+
+    #[test]
+    fn test_messages_roundtrip() {
+        let sendpackages = SendPackages {
+            versions: PKG_RELAY_ANCPKG,
+        };
+        assert_eq!(serialize(&sendpackages), vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            deserialize::<SendPackages>(&serialize(&sendpackages)).unwrap(),
+            sendpackages
+        );
+
+        let info = AncPkgInfo {
+            wtxids: vec![
+                Wtxid::from_byte_array([1; 32]),
+                Wtxid::from_byte_array([2; 32]),
+            ],
+        };
+        assert_eq!(deserialize::<AncPkgInfo>(&serialize(&info)).unwrap(), info);
+
+        // Packages can't be larger than our mempool accepts
+        let too_big = AncPkgInfo {
+            wtxids: vec![Wtxid::all_zeros(); MAX_PACKAGE_COUNT + 1],
+        };
+        assert!(deserialize::<AncPkgInfo>(&serialize(&too_big)).is_err());
+    }
+
+    #[test]
+    fn test_ancpkginfo_inventory() {
+        let wtxid = Wtxid::from_byte_array([7; 32]);
+        let inv = ancpkginfo_inventory(wtxid);
+
+        assert_eq!(ancpkginfo_wtxid(&inv), Some(wtxid));
+        assert_eq!(ancpkginfo_wtxid(&Inventory::WTx(wtxid)), None);
+    }
+}
@@ -22,8 +22,12 @@ use bitcoin::p2p::message_network::VersionMessage;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::Block;
 use bitcoin::BlockHash;
+use bitcoin::FeeRate;
 use bitcoin::Transaction;
+use bitcoin::Txid;
+use bitcoin::Wtxid;
 use floresta_common::impl_error_from;
+use floresta_mempool::mempool::MIN_RELAY_FEE_RATE;
 use floresta_mempool::Mempool;
 use tokio::io::AsyncRead;
 use tokio::io::AsyncWrite;
@@ -50,7 +54,31 @@ use crate::node::ConnectionKind;
 use crate::node::MAX_ADDRV2_ADDRESSES;
 use crate::p2p_wire::block_proof::GetUtreexoProof;
 use crate::p2p_wire::block_proof::UtreexoProof;
+use crate::p2p_wire::traffic::TrafficCounter;
 use crate::p2p_wire::transport::ReadTransport;
+use crate::package_relay::ancpkginfo_inventory;
+use crate::package_relay::AncPkgInfo;
+use crate::package_relay::GetPkgTxns;
+use crate::package_relay::PkgTxns;
+use crate::package_relay::SendPackages;
+use crate::package_relay::ANCPKGINFO_CMD_STRING;
+use crate::package_relay::GETPKGTXNS_CMD_STRING;
+use crate::package_relay::MSG_ANCPKGINFO;
+use crate::package_relay::PKGTXNS_CMD_STRING;
+use crate::package_relay::PKG_RELAY_ANCPKG;
+use crate::package_relay::SENDPACKAGES_CMD_STRING;
+use crate::tx_proof::is_utreexo_tx_inventory;
+use crate::tx_proof::utreexo_inventory;
+use crate::tx_proof::UtreexoTx;
+use crate::tx_proof::UTREEXO_TX_CMD_STRING;
+use crate::txreconciliation::SendTxRcncl;
+use crate::txreconciliation::Sketch;
+use crate::txreconciliation::TxReconciliationState;
+use crate::txreconciliation::RECONCILDIFF_CMD_STRING;
+use crate::txreconciliation::REQRECON_CMD_STRING;
+use crate::txreconciliation::SENDTXRCNCL_CMD_STRING;
+use crate::txreconciliation::SKETCH_CMD_STRING;
+use crate::txreconciliation::TXRECONCILIATION_VERSION;
 
 /// If we send a ping, and our peer takes more than PING_TIMEOUT to
 /// reply, disconnect.
@@ -81,6 +109,21 @@ const INV_MESSAGE_INTERVAL: Duration = Duration::from_secs(30); // 30 seconds
 /// If a peer sends more than this, we disconnect it.
 const MAX_MSGS_PER_SEC: u64 = 10_000;
 
+/// The maximum number of entries in an inv message, same as Bitcoin Core
+const MAX_INV_SZ: usize = 50_000;
+
+/// On average, how long we wait before announcing transactions to a peer.
+///
+/// Announcements are batched and sent at random (Poisson) intervals, so a spy connected to
+/// many nodes can't tell who announced a transaction first just by looking at timings.
+const TX_TRICKLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many transactions we announce to a peer at once
+const MAX_TX_ANNOUNCEMENTS_PER_TRICKLE: usize = 1_000;
+
+/// The first protocol version supporting wtxid-based relay (BIP 339)
+const WTXID_RELAY_VERSION: u32 = 70016;
+
 #[derive(Debug, PartialEq)]
 enum State {
     None,
@@ -97,9 +140,9 @@ pub struct MessageActor<R: AsyncRead + Unpin + Send> {
 impl<R: AsyncRead + Unpin + Send> MessageActor<R> {
     async fn inner(&mut self) -> std::result::Result<(), PeerError> {
         loop {
-            let msg = self.transport.read_message().await?;
+            let (msg, size) = self.transport.read_message().await?;
             let now = Instant::now();
-            self.sender.send(ReaderMessage::Message(msg, now))?;
+            self.sender.send(ReaderMessage::Message(msg, now, size))?;
         }
     }
 
@@ -150,6 +193,30 @@ pub struct Peer<T: AsyncWrite + Unpin + Send + Sync> {
     // This is kept as an option to avoid the need to keep the other half around during tests.
     cancellation_sender: Option<oneshot::Sender<()>>,
     transport_protocol: TransportProtocol,
+    /// Whether this peer wants transactions to be announced by wtxid (BIP 339)
+    wtxid_relay: bool,
+    /// The minimum fee rate of transactions this peer wants to hear about (BIP 133)
+    fee_filter: FeeRate,
+    /// Transactions we should announce to this peer in the next trickle
+    tx_announce_queue: Vec<Txid>,
+    /// When we'll announce the queued transactions
+    next_trickle: Instant,
+    /// Whether this private broadcast peer got our transaction, so we can disconnect once it
+    /// answers our ping
+    private_tx_sent: bool,
+    /// The salt we sent in our `sendtxrcncl`, if we offered to reconcile transactions (BIP 330)
+    recon_salt: Option<u64>,
+    /// The salt our peer sent in its `sendtxrcncl`, if it did
+    peer_recon_salt: Option<u64>,
+    /// Our reconciliation state, if both of us agreed to use it. Otherwise, we announce
+    /// transactions by flooding.
+    txreconciliation: Option<TxReconciliationState>,
+    /// Whether we sent a `sendpackages` to this peer (BIP 331)
+    sent_sendpackages: bool,
+    /// Whether both of us support ancestor package relay
+    package_relay: bool,
+    /// How many bytes we exchanged with this peer. Also feeds our totals and upload target.
+    traffic: Arc<TrafficCounter>,
 }
 
 #[derive(Debug)]
@@ -223,7 +290,8 @@ impl From<SendError<ReaderMessage>> for PeerError {
 }
 
 pub enum ReaderMessage {
-    Message(NetworkMessage, Instant),
+    /// A message from our peer, when we got it, and how many bytes it took on the wire
+    Message(NetworkMessage, Instant, usize),
     Error(PeerError),
 }
 
@@ -267,14 +335,12 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
     }
 
     async fn peer_loop_inner(&mut self) -> Result<()> {
-        // Send a `version` message to the peer.
-        let message_version = peer_utils::build_version_message(
-            self.our_user_agent.clone(),
-            self.our_best_block,
-            &self.address,
-        );
-        self.write(message_version).await?;
-        self.state = State::SentVersion(Instant::now());
+        // Peers that connected to us talk first, we answer their version in `handle_version`
+        if !self.is_inbound() {
+            self.send_version().await?;
+            self.state = State::SentVersion(Instant::now());
+        }
+
         loop {
             tokio::select! {
                 request = tokio::time::timeout(Duration::from_secs(2), self.node_requests.recv()) => {
@@ -298,7 +364,8 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                         Some(ReaderMessage::Error(e)) => {
                             return Err(e);
                         }
-                        Some(ReaderMessage::Message(msg, time)) => {
+                        Some(ReaderMessage::Message(msg, time, size)) => {
+                            self.traffic.record_recv(msg.command().as_ref(), size);
                             self.handle_peer_message(msg, time).await?;
                         }
                     }
@@ -348,6 +415,18 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     return Err(PeerError::UnexpectedMessage);
                 }
             }
+
+            // The same goes for inbound peers, which should send their version right away
+            if self.state == State::None && self.start_time.elapsed() > Duration::from_secs(10) {
+                return Err(PeerError::UnexpectedMessage);
+            }
+
+            if self.next_trickle <= Instant::now() {
+                self.next_trickle = Instant::now() + peer_utils::poisson_delay(TX_TRICKLE_INTERVAL);
+                self.announce_transactions().await?;
+            }
+
+            self.reconcile_transactions().await?;
         }
     }
 
@@ -387,12 +466,43 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 self.shutdown = true;
                 self.writer.shutdown().await?;
             }
+            // Block-relay-only peers never learn about the addresses we know or want
+            NodeRequest::GetAddresses | NodeRequest::SendAddresses(_)
+                if self.is_block_relay_only() => {}
             NodeRequest::GetAddresses => {
                 self.write(NetworkMessage::GetAddr).await?;
             }
-            NodeRequest::BroadcastTransaction(tx) => {
-                self.write(NetworkMessage::Inv(vec![Inventory::Transaction(tx)]))
-                    .await?;
+            NodeRequest::BroadcastTransaction(txid) if self.is_private_broadcast() => {
+                // This connection exists only for this transaction, no reason to wait
+                let wtxid = self
+                    .mempool
+                    .lock()
+                    .await
+                    .get_from_mempool(&txid)
+                    .map(|tx| tx.compute_wtxid());
+
+                let inv = match (self.wtxid_relay, wtxid) {
+                    (true, Some(wtxid)) => Inventory::WTx(wtxid),
+                    _ => Inventory::Transaction(txid),
+                };
+
+                self.write(NetworkMessage::Inv(vec![inv])).await?;
+            }
+            NodeRequest::BroadcastTransaction(txid) if !self.serves_mempool() => {
+                // Our peer would ask for it, and we wouldn't serve it
+                debug!(
+                    "Upload target reached, not announcing {txid} to peer {}",
+                    self.id
+                );
+            }
+            NodeRequest::BroadcastTransaction(txid) if self.txreconciliation.is_some() => {
+                self.add_to_reconciliation_set(txid).await;
+            }
+            NodeRequest::BroadcastTransaction(txid) => {
+                // Don't announce it right away, it'll go out in the next trickle
+                if !self.blocks_only && !self.tx_announce_queue.contains(&txid) {
+                    self.tx_announce_queue.push(txid);
+                }
             }
             NodeRequest::MempoolTransaction(txid) => {
                 self.write(NetworkMessage::GetData(vec![Inventory::Transaction(txid)]))
@@ -415,6 +525,21 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 self.last_ping = Some(Instant::now());
                 self.write(NetworkMessage::Ping(nonce)).await?;
             }
+            NodeRequest::GetUtreexoTransaction(inv) => {
+                if let Some(inv) = utreexo_inventory(&inv) {
+                    self.write(NetworkMessage::GetData(vec![inv])).await?;
+                }
+            }
+            NodeRequest::GetPackageInfo(wtxid) => {
+                // The node gives up on this package after a while
+                if !self.package_relay {
+                    debug!("Peer {} doesn't relay packages", self.id);
+                    return Ok(());
+                }
+
+                let inv = ancpkginfo_inventory(wtxid);
+                self.write(NetworkMessage::GetData(vec![inv])).await?;
+            }
             NodeRequest::GetBlockProof((block_hash, proof_hashes_bitmap, leaf_index_bitmap)) => {
                 let get_block_proof = GetUtreexoProof {
                     block_hash,
@@ -446,19 +571,33 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
         match self.state {
             State::Connected => match message {
                 NetworkMessage::Inv(inv) => {
-                    let mut block_inv_elements = 0;
-
-                    // Silently drop
-                    if self.last_inv.elapsed() < INV_MESSAGE_INTERVAL {
-                        return Ok(());
+                    if inv.len() > MAX_INV_SZ {
+                        return Err(PeerError::MessageTooBig);
                     }
 
-                    self.last_inv = Instant::now();
+                    let mut block_inv_elements = 0;
+                    let mut tx_inv = Vec::new();
+
+                    // Block announcements are rate limited, transactions are handled by the node
+                    let drop_blocks = self.last_inv.elapsed() < INV_MESSAGE_INTERVAL;
 
                     for inv_entry in inv {
                         match inv_entry {
                             Inventory::Error => {}
-                            Inventory::Transaction(_) => {}
+                            // After negotiating wtxid relay, peers should only announce by wtxid
+                            Inventory::Transaction(_) | Inventory::WitnessTransaction(_)
+                                if !self.wtxid_relay =>
+                            {
+                                tx_inv.push(inv_entry);
+                            }
+                            Inventory::WTx(wtxid) if self.wtxid_relay => {
+                                // Our peer has it already, no need to reconcile it
+                                if let Some(recon) = self.txreconciliation.as_mut() {
+                                    recon.remove_transaction(&wtxid);
+                                }
+
+                                tx_inv.push(inv_entry);
+                            }
                             Inventory::Block(block_hash)
                             | Inventory::WitnessBlock(block_hash)
                             | Inventory::CompactBlock(block_hash) => {
@@ -467,11 +606,25 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                                     return Err(PeerError::MessageTooBig);
                                 }
 
+                                // Silently drop
+                                if drop_blocks {
+                                    continue;
+                                }
+
                                 self.send_to_node(PeerMessages::NewBlock(block_hash), time);
                             }
                             _ => {}
                         }
                     }
+
+                    if block_inv_elements > 0 && !drop_blocks {
+                        self.last_inv = Instant::now();
+                    }
+
+                    // We told block-relay-only peers we don't want transactions
+                    if !tx_inv.is_empty() && !self.is_block_relay_only() {
+                        self.send_to_node(PeerMessages::TransactionInv(tx_inv), time);
+                    }
                 }
                 NetworkMessage::GetHeaders(_) => {
                     self.write(NetworkMessage::Headers(Vec::new())).await?;
@@ -486,8 +639,11 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 NetworkMessage::Ping(nonce) => {
                     self.handle_ping(nonce).await?;
                 }
-                NetworkMessage::FeeFilter(_) => {
-                    self.write(NetworkMessage::FeeFilter(1000)).await?;
+                NetworkMessage::FeeFilter(fee_rate) => {
+                    // The fee rate is in sat/kvB, a negative value is invalid and ignored
+                    if let Ok(sat_per_kvb) = u64::try_from(fee_rate) {
+                        self.fee_filter = FeeRate::from_sat_per_kwu(sat_per_kvb / 4);
+                    }
                 }
                 NetworkMessage::AddrV2(addresses) => {
                     // As per BIP 155, limit the number of addresses to 1,000
@@ -495,6 +651,10 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                         return Err(PeerError::MessageTooBig);
                     }
 
+                    if self.is_block_relay_only() {
+                        return Ok(());
+                    }
+
                     // Rate limit addrv2 messages
                     let now = Instant::now();
                     let elapsed = now.duration_since(self.last_addrv2);
@@ -524,7 +684,9 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     }
                 }
                 NetworkMessage::Tx(tx) => {
-                    self.send_to_node(PeerMessages::Transaction(tx), time);
+                    if !self.is_block_relay_only() {
+                        self.send_to_node(PeerMessages::Transaction(tx), time);
+                    }
                 }
                 NetworkMessage::NotFound(inv) => {
                     for inv_el in inv {
@@ -537,12 +699,60 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 }
                 NetworkMessage::Pong(_) => {
                     self.last_ping = None;
+
+                    // Our transaction was sent before the ping, so our peer has it by now
+                    if self.private_tx_sent {
+                        debug!("Private broadcast to peer {} is done", self.id);
+                        self.shutdown = true;
+                    }
                 }
                 NetworkMessage::Unknown { command, payload } => {
+                    match command.as_ref() {
+                        // BIP 330: this is only allowed before verack
+                        SENDTXRCNCL_CMD_STRING => return Err(PeerError::UnexpectedMessage),
+                        SKETCH_CMD_STRING => {
+                            let sketch: Sketch = deserialize(&payload)?;
+                            return self.handle_sketch(sketch).await;
+                        }
+                        // We are always the initiator, so our peer shouldn't send these
+                        REQRECON_CMD_STRING | RECONCILDIFF_CMD_STRING => {
+                            debug!("Peer {} sent an unexpected {command}, ignoring", self.id);
+                            return Ok(());
+                        }
+                        // BIP 331: this is only allowed before verack
+                        SENDPACKAGES_CMD_STRING => return Err(PeerError::UnexpectedMessage),
+                        ANCPKGINFO_CMD_STRING if self.package_relay => {
+                            let info: AncPkgInfo = deserialize(&payload)?;
+                            self.send_to_node(PeerMessages::PackageInfo(info.wtxids), time);
+                            return Ok(());
+                        }
+                        GETPKGTXNS_CMD_STRING if self.package_relay => {
+                            let request: GetPkgTxns = deserialize(&payload)?;
+                            return self.handle_get_package_txns(request).await;
+                        }
+                        // We download packages with their utreexo proofs, so we never ask for
+                        // these
+                        ANCPKGINFO_CMD_STRING | GETPKGTXNS_CMD_STRING | PKGTXNS_CMD_STRING => {
+                            debug!("Peer {} sent an unexpected {command}, ignoring", self.id);
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+
                     let utreexo_proof_cmd =
                         CommandString::try_from_static(UTREEXO_PROOF_CMD_STRING)
                             .expect("Invalid command string");
 
+                    let utreexo_tx_cmd = CommandString::try_from_static(UTREEXO_TX_CMD_STRING)
+                        .expect("Invalid command string");
+
+                    if command == utreexo_tx_cmd {
+                        let utreexo_tx: UtreexoTx = deserialize(&payload)?;
+                        self.send_to_node(PeerMessages::UtreexoTransaction(utreexo_tx), time);
+
+                        return Ok(());
+                    }
+
                     if command != utreexo_proof_cmd {
                         warn!("Unknown command string: {command}");
                         return Ok(());
@@ -605,6 +815,28 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
             State::SentVerack => match message {
                 bitcoin::p2p::message::NetworkMessage::Verack => {
                     self.state = State::Connected;
+
+                    // BIP 331 requires wtxid relay
+                    self.package_relay &= self.wtxid_relay;
+
+                    // BIP 330 also requires wtxid relay
+                    if let (Some(our_salt), Some(their_salt), true) =
+                        (self.recon_salt, self.peer_recon_salt, self.wtxid_relay)
+                    {
+                        debug!("Reconciling transactions with peer {}", self.id);
+                        self.txreconciliation =
+                            Some(TxReconciliationState::new(our_salt, their_salt));
+                    }
+
+                    // Tell our peer not to announce transactions we won't accept anyway.
+                    // The fee filter is expressed in sat/kvB. Block-relay-only peers won't
+                    // announce any transaction, so there's no point in sending it.
+                    if !self.is_block_relay_only() {
+                        let fee_filter = MIN_RELAY_FEE_RATE.to_sat_per_kwu() * 4;
+                        self.write(NetworkMessage::FeeFilter(fee_filter as i64))
+                            .await?;
+                    }
+
                     self.send_to_node(
                         PeerMessages::Ready(Version {
                             user_agent: self.user_agent.clone(),
@@ -625,7 +857,28 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 bitcoin::p2p::message::NetworkMessage::SendHeaders => {
                     self.send_headers = true;
                 }
-                bitcoin::p2p::message::NetworkMessage::WtxidRelay => {}
+                bitcoin::p2p::message::NetworkMessage::WtxidRelay => {
+                    self.wtxid_relay = true;
+                }
+                bitcoin::p2p::message::NetworkMessage::Unknown { command, payload }
+                    if command.as_ref() == SENDTXRCNCL_CMD_STRING =>
+                {
+                    let message: SendTxRcncl = deserialize(&payload)?;
+
+                    // Version 0 is invalid, and we only use it if we also offered to
+                    if message.version >= TXRECONCILIATION_VERSION && self.recon_salt.is_some() {
+                        self.peer_recon_salt = Some(message.salt);
+                    }
+                }
+                bitcoin::p2p::message::NetworkMessage::Unknown { command, payload }
+                    if command.as_ref() == SENDPACKAGES_CMD_STRING =>
+                {
+                    let message: SendPackages = deserialize(&payload)?;
+
+                    // We only relay ancestor packages, and only if we also offered to
+                    self.package_relay =
+                        self.sent_sendpackages && message.versions & PKG_RELAY_ANCPKG != 0;
+                }
                 _ => {
                     warn!("unexpected message: {:?} from peer {}", message, self.id);
                     return Err(PeerError::UnexpectedMessage);
@@ -638,35 +891,300 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
 
 impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
     pub async fn write(&mut self, msg: NetworkMessage) -> Result<()> {
-        debug!("Writing {} to peer {}", msg.command(), self.id);
-        self.writer.write_message(msg).await?;
+        let command = msg.command();
+        debug!("Writing {command} to peer {}", self.id);
+
+        let size = self.writer.write_message(msg).await?;
+        self.traffic.record_sent(command.as_ref(), size);
         Ok(())
     }
 
+    /// Whether this peer is exempt from our upload target. We always answer manual peers, and
+    /// private broadcast peers only ask for our own transactions.
+    fn is_upload_target_exempt(&self) -> bool {
+        self.kind == ConnectionKind::Manual || self.is_private_broadcast()
+    }
+
+    /// Whether we should still answer bandwidth-heavy requests from this peer, like packages
+    fn serves_heavy_requests(&self) -> bool {
+        self.is_upload_target_exempt() || self.traffic.net().serve_historical_data()
+    }
+
+    /// Whether we should still serve our mempool transactions to this peer
+    fn serves_mempool(&self) -> bool {
+        self.is_upload_target_exempt() || !self.traffic.net().target_reached()
+    }
+
     pub async fn handle_get_data(&mut self, inv: Inventory) -> Result<()> {
+        // Once we get close to our upload target, we only send what's needed to follow the chain
+        let allowed = match inv {
+            Inventory::Transaction(_) | Inventory::WitnessTransaction(_) | Inventory::WTx(_) => {
+                self.serves_mempool()
+            }
+            Inventory::Unknown {
+                inv_type: MSG_ANCPKGINFO,
+                ..
+            } => self.serves_heavy_requests(),
+            _ => true,
+        };
+
+        if !allowed {
+            debug!(
+                "Upload target reached, not serving {inv:?} to peer {}",
+                self.id
+            );
+            return self.write(NetworkMessage::NotFound(vec![inv])).await;
+        }
+
         match inv {
             Inventory::WitnessTransaction(txid) => {
                 let tx = self.mempool.lock().await.get_from_mempool(&txid).cloned();
                 if let Some(tx) = tx {
-                    self.write(NetworkMessage::Tx(tx)).await?;
+                    self.send_transaction(tx).await?;
                 }
             }
             Inventory::Transaction(txid) => {
                 let tx = self.mempool.lock().await.get_from_mempool(&txid).cloned();
                 if let Some(tx) = tx {
-                    self.write(NetworkMessage::Tx(tx)).await?;
+                    self.send_transaction(tx).await?;
+                }
+            }
+            Inventory::WTx(wtxid) => {
+                let tx = self.mempool.lock().await.get_by_wtxid(&wtxid).cloned();
+                if let Some(tx) = tx {
+                    self.send_transaction(tx).await?;
                 }
             }
+            // We don't keep the accumulator's leaves, so we can't build proofs for our
+            // mempool transactions
+            inv if is_utreexo_tx_inventory(&inv) => {
+                self.write(NetworkMessage::NotFound(vec![inv])).await?;
+            }
+            Inventory::Unknown {
+                inv_type: MSG_ANCPKGINFO,
+                hash,
+            } if self.package_relay => {
+                let package = self
+                    .mempool
+                    .lock()
+                    .await
+                    .get_ancestor_package(&Wtxid::from_byte_array(hash));
+
+                let Some(package) = package else {
+                    self.write(NetworkMessage::NotFound(vec![inv])).await?;
+                    return Ok(());
+                };
+
+                let info = AncPkgInfo {
+                    wtxids: package.iter().map(Transaction::compute_wtxid).collect(),
+                };
+                self.write_unknown(ANCPKGINFO_CMD_STRING, serialize(&info))
+                    .await?;
+            }
             _ => {}
         }
         Ok(())
     }
 
+    /// Sends the transactions of a package our peer asked for, or a `notfound` with the ones we
+    /// don't have
+    async fn handle_get_package_txns(&mut self, request: GetPkgTxns) -> Result<()> {
+        if !self.serves_heavy_requests() {
+            debug!(
+                "Upload target reached, not serving a package to peer {}",
+                self.id
+            );
+            let inv = request.wtxids.into_iter().map(Inventory::WTx).collect();
+            return self.write(NetworkMessage::NotFound(inv)).await;
+        }
+
+        let mempool = self.mempool.lock().await;
+        let (transactions, missing): (Vec<_>, Vec<_>) = request
+            .wtxids
+            .iter()
+            .map(|wtxid| (mempool.get_by_wtxid(wtxid).cloned(), *wtxid))
+            .partition(|(tx, _)| tx.is_some());
+
+        drop(mempool);
+
+        if !missing.is_empty() {
+            let inv = missing
+                .into_iter()
+                .map(|(_, wtxid)| Inventory::WTx(wtxid))
+                .collect();
+            return self.write(NetworkMessage::NotFound(inv)).await;
+        }
+
+        let message = PkgTxns {
+            transactions: transactions.into_iter().filter_map(|(tx, _)| tx).collect(),
+        };
+        self.write_unknown(PKGTXNS_CMD_STRING, serialize(&message))
+            .await
+    }
+
+    /// Sends a transaction our peer asked for. For private broadcasts, we follow it with a ping,
+    /// and disconnect once our peer answers it.
+    async fn send_transaction(&mut self, tx: Transaction) -> Result<()> {
+        self.write(NetworkMessage::Tx(tx)).await?;
+
+        if self.is_private_broadcast() && !self.private_tx_sent {
+            self.private_tx_sent = true;
+            self.last_ping = Some(Instant::now());
+            self.write(NetworkMessage::Ping(rand::random())).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Announces the transactions queued for this peer, skipping the ones that are no longer in
+    /// our mempool, or that pay less than this peer's fee filter.
+    async fn announce_transactions(&mut self) -> Result<()> {
+        if self.state != State::Connected || self.tx_announce_queue.is_empty() {
+            return Ok(());
+        }
+
+        // We wouldn't serve them anyway
+        if !self.serves_mempool() {
+            self.tx_announce_queue.clear();
+            return Ok(());
+        }
+
+        let queued = std::mem::take(&mut self.tx_announce_queue);
+        let mempool = self.mempool.lock().await;
+        let mut inv = Vec::new();
+
+        for txid in queued {
+            if inv.len() >= MAX_TX_ANNOUNCEMENTS_PER_TRICKLE {
+                // The remaining ones will go out in the next trickle
+                self.tx_announce_queue.push(txid);
+                continue;
+            }
+
+            let Some(tx) = mempool.get_from_mempool(&txid) else {
+                continue;
+            };
+
+            // If we don't know the fee, this came from our own user, always announce it
+            if let Some(fee_rate) = mempool.get_fee_rate(&txid) {
+                if fee_rate < self.fee_filter {
+                    continue;
+                }
+            }
+
+            match self.wtxid_relay {
+                true => inv.push(Inventory::WTx(tx.compute_wtxid())),
+                false => inv.push(Inventory::Transaction(txid)),
+            }
+        }
+
+        drop(mempool);
+
+        if inv.is_empty() {
+            return Ok(());
+        }
+
+        self.write(NetworkMessage::Inv(inv)).await
+    }
+
+    /// Adds a transaction to the set we'll reconcile with this peer, or queues it for flooding if
+    /// the set is full
+    async fn add_to_reconciliation_set(&mut self, txid: Txid) {
+        let mempool = self.mempool.lock().await;
+        let Some(tx) = mempool.get_from_mempool(&txid) else {
+            return;
+        };
+
+        // If we don't know the fee, this came from our own user, always announce it
+        if let Some(fee_rate) = mempool.get_fee_rate(&txid) {
+            if fee_rate < self.fee_filter {
+                return;
+            }
+        }
+
+        let wtxid = tx.compute_wtxid();
+        drop(mempool);
+
+        let Some(recon) = self.txreconciliation.as_mut() else {
+            return;
+        };
+
+        if !recon.add_transaction(wtxid) && !self.tx_announce_queue.contains(&txid) {
+            self.tx_announce_queue.push(txid);
+        }
+    }
+
+    /// Starts a new reconciliation round if it's time to, and floods the transactions of a
+    /// round our peer didn't answer in time
+    async fn reconcile_transactions(&mut self) -> Result<()> {
+        let Some(recon) = self.txreconciliation.as_mut() else {
+            return Ok(());
+        };
+
+        let expired = recon.expire();
+        let request = recon.request();
+
+        if !expired.is_empty() {
+            debug!("Peer {} didn't send us a sketch in time", self.id);
+            self.announce_wtxids(expired).await?;
+        }
+
+        if let Some(request) = request {
+            self.write_unknown(REQRECON_CMD_STRING, serialize(&request))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes a reconciliation round with our peer's sketch, asking for the transactions we
+    /// are missing and announcing the ones our peer is missing
+    async fn handle_sketch(&mut self, sketch: Sketch) -> Result<()> {
+        let outcome = self
+            .txreconciliation
+            .as_mut()
+            .and_then(|recon| recon.handle_sketch(&sketch));
+
+        let Some(outcome) = outcome else {
+            debug!("Peer {} sent a sketch we didn't ask for, ignoring", self.id);
+            return Ok(());
+        };
+
+        debug!(
+            "Reconciliation with peer {} {}: we are missing {} transactions, our peer is missing {}",
+            self.id,
+            if outcome.diff.success { "succeeded" } else { "failed" },
+            outcome.diff.ask_shortids.len(),
+            outcome.announce.len(),
+        );
+
+        self.write_unknown(RECONCILDIFF_CMD_STRING, serialize(&outcome.diff))
+            .await?;
+        self.announce_wtxids(outcome.announce).await
+    }
+
+    /// Announces transactions by wtxid right away
+    async fn announce_wtxids(&mut self, wtxids: Vec<Wtxid>) -> Result<()> {
+        if wtxids.is_empty() {
+            return Ok(());
+        }
+
+        let inv = wtxids.into_iter().map(Inventory::WTx).collect();
+        self.write(NetworkMessage::Inv(inv)).await
+    }
+
+    /// Writes a message rust-bitcoin doesn't know about
+    async fn write_unknown(&mut self, command: &'static str, payload: Vec<u8>) -> Result<()> {
+        let command = CommandString::try_from_static(command).expect("Invalid command string");
+        self.write(NetworkMessage::Unknown { command, payload })
+            .await
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn create_peer<W: AsyncWrite + Unpin + Send + Sync + 'static>(
         id: u32,
         address: LocalAddress,
         mempool: Arc<Mutex<Mempool>>,
+        traffic: Arc<TrafficCounter>,
         node_tx: UnboundedSender<NodeNotification>,
         node_requests: UnboundedReceiver<NodeRequest>,
         kind: ConnectionKind,
@@ -704,6 +1222,17 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
             our_best_block,
             cancellation_sender: Some(cancellation_sender),
             transport_protocol,
+            wtxid_relay: false,
+            fee_filter: FeeRate::ZERO,
+            tx_announce_queue: Vec::new(),
+            next_trickle: Instant::now(),
+            private_tx_sent: false,
+            recon_salt: None,
+            peer_recon_salt: None,
+            txreconciliation: None,
+            sent_sendpackages: false,
+            package_relay: false,
+            traffic,
         };
 
         spawn(peer.read_loop());
@@ -714,14 +1243,84 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
         self.write(pong).await
     }
 
+    /// Whether this is a block-relay-only connection, where we don't exchange transactions or
+    /// addresses
+    fn is_block_relay_only(&self) -> bool {
+        self.kind == ConnectionKind::BlockRelayOnly
+    }
+
+    /// Whether this is a short-lived connection, only used to push one of our transactions
+    fn is_private_broadcast(&self) -> bool {
+        self.kind == ConnectionKind::PrivateBroadcast
+    }
+
+    /// Whether this peer connected to us
+    fn is_inbound(&self) -> bool {
+        self.kind == ConnectionKind::Inbound
+    }
+
+    /// Sends our `version` message, which starts the handshake
+    async fn send_version(&mut self) -> Result<()> {
+        // Private broadcast connections shouldn't tell our peer anything about our node, and
+        // don't want transactions from them
+        let our_best_block = match self.is_private_broadcast() {
+            true => 0,
+            false => self.our_best_block,
+        };
+
+        let message_version = peer_utils::build_version_message(
+            self.our_user_agent.clone(),
+            our_best_block,
+            &self.address,
+            !self.is_block_relay_only() && !self.is_private_broadcast(),
+        );
+        self.write(message_version).await
+    }
+
     async fn handle_version(&mut self, version: VersionMessage) -> Result<()> {
+        // Our version must come before anything else we send
+        if self.is_inbound() {
+            self.send_version().await?;
+        }
+
         self.user_agent = version.user_agent;
-        self.blocks_only = !version.relay;
+        self.blocks_only = !version.relay || self.is_block_relay_only();
         self.current_best_block = version.start_height;
         self.services = version.services;
         if version.version >= 70016 {
             self.write(NetworkMessage::SendAddrV2).await?;
         }
+        // BIP 339 requires wtxidrelay to be sent before verack
+        if version.version >= WTXID_RELAY_VERSION {
+            self.write(NetworkMessage::WtxidRelay).await?;
+        }
+        // BIP 330 requires sendtxrcncl to be sent before verack too, and only if both of us
+        // want transactions. The same goes for sendpackages (BIP 331).
+        if version.version >= WTXID_RELAY_VERSION
+            && !self.blocks_only
+            && !self.is_private_broadcast()
+        {
+            // We can only start reconciliation rounds, which is up to whoever opened the
+            // connection, so peers that connected to us get our transactions by flooding
+            if !self.is_inbound() {
+                let salt = rand::random();
+                self.recon_salt = Some(salt);
+
+                let message = SendTxRcncl {
+                    version: TXRECONCILIATION_VERSION,
+                    salt,
+                };
+                self.write_unknown(SENDTXRCNCL_CMD_STRING, serialize(&message))
+                    .await?;
+            }
+
+            let message = SendPackages {
+                versions: PKG_RELAY_ANCPKG,
+            };
+            self.write_unknown(SENDPACKAGES_CMD_STRING, serialize(&message))
+                .await?;
+            self.sent_sendpackages = true;
+        }
         self.state = State::SentVerack;
         let verack = NetworkMessage::Verack;
         self.state = State::SentVerack;
@@ -738,6 +1337,7 @@ pub(super) mod peer_utils {
     use core::net::IpAddr;
     use core::net::Ipv4Addr;
     use core::net::SocketAddr;
+    use std::time::Duration;
     use std::time::SystemTime;
     use std::time::UNIX_EPOCH;
 
@@ -760,18 +1360,31 @@ pub(super) mod peer_utils {
         NetworkMessage::Pong(nonce)
     }
 
+    /// Returns a random delay following an exponential distribution with the given mean, so
+    /// events scheduled with it follow a Poisson process.
+    pub(super) fn poisson_delay(mean: Duration) -> Duration {
+        // `gen` returns a number in [0, 1), so `1 - gen` is never zero
+        let uniform: f64 = 1.0 - thread_rng().gen::<f64>();
+        mean.mul_f64(-uniform.ln())
+    }
+
+    /// The services we offer to our peers.
+    ///   - WITNESS: this implementation supports SegWit blocks and transactions.
+    ///   - P2P_V2: this implementation supports P2PV2 (BIP-0324) connections.
+    ///   - UTREEXO: this implementation supports Utreexo P2P (BIP-0183) messages.
+    pub(crate) fn our_services() -> ServiceFlags {
+        ServiceFlags::WITNESS | ServiceFlags::P2P_V2 | service_flags::UTREEXO.into()
+    }
+
     /// Build the [version](NetworkMessage::Version) message used to perform the peer connection
     /// handshake, as described in the [Bitcoin Wiki](https://en.bitcoin.it/wiki/Protocol_documentation#version).
     pub(crate) fn build_version_message(
         user_agent: String,
         best_block: u32,
         peer_address: &LocalAddress,
+        relay: bool,
     ) -> NetworkMessage {
-        // Services supported by this node.
-        //   - WITNESS: this implementation supports SegWit blocks and transactions.
-        //   - P2P_V2: this implementation supports P2PV2 (BIP-0324) connections.
-        //   - UTREEXO: this implementation supports Utreexo P2P (BIP-0183) messages.
-        let services = ServiceFlags::WITNESS | ServiceFlags::P2P_V2 | service_flags::UTREEXO.into();
+        let services = our_services();
 
         // The current UNIX timestamp.
         let timestamp = SystemTime::now()
@@ -797,9 +1410,8 @@ pub(super) mod peer_utils {
         // Inform the peer of this node's chain tip.
         let start_height = best_block as i32;
 
-        // Floresta does not implement transaction relay.
-        let relay = false;
-
+        // Whether we want this peer to announce transactions to us, which is only false for
+        // block-relay-only connections.
         NetworkMessage::Version(VersionMessage {
             version: PROTOCOL_VERSION,
             services,
@@ -863,6 +1475,16 @@ pub enum PeerMessages {
 
     /// Remote peer sent us a Utreexo proof,
     UtreexoProof(UtreexoProof),
+
+    /// Remote peer announced some transactions
+    TransactionInv(Vec<Inventory>),
+
+    /// Remote peer sent us a transaction with its utreexo proof
+    UtreexoTransaction(UtreexoTx),
+
+    /// Remote peer sent us the wtxids of a transaction's ancestor package, the transaction
+    /// itself being the last one
+    PackageInfo(Vec<Wtxid>),
 }
 
 #[cfg(test)]
@@ -872,10 +1494,26 @@ mod tests {
     use std::time::Duration;
     use std::time::Instant;
 
+    use bip324::serde::CommandString;
     use bip324::serde::NetworkMessage;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
     use bitcoin::p2p::address::AddrV2;
+    use bitcoin::p2p::address::AddrV2Message;
+    use bitcoin::p2p::message_blockdata::Inventory;
     use bitcoin::p2p::ServiceFlags;
+    use bitcoin::transaction::Version;
+    use bitcoin::Amount;
+    use bitcoin::FeeRate;
     use bitcoin::Network;
+    use bitcoin::OutPoint;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Transaction;
+    use bitcoin::TxIn;
+    use bitcoin::TxOut;
+    use bitcoin::Txid;
+    use bitcoin::Wtxid;
     use floresta_mempool::Mempool;
     use tokio::sync::mpsc::unbounded_channel;
     use tokio::sync::mpsc::UnboundedReceiver;
@@ -891,10 +1529,22 @@ mod tests {
     use crate::p2p_wire::peer::peer_utils;
     use crate::p2p_wire::peer::Peer;
     use crate::p2p_wire::peer::PeerError;
+    use crate::p2p_wire::peer::PeerMessages;
     use crate::p2p_wire::peer::ReaderMessage;
     use crate::p2p_wire::peer::State;
+    use crate::p2p_wire::peer::ADDRV2_MESSAGE_INTERVAL;
+    use crate::p2p_wire::traffic::NetTraffic;
+    use crate::p2p_wire::traffic::TrafficCounter;
     use crate::p2p_wire::transport::test_transport::Writer;
     use crate::p2p_wire::transport::WriteTransport;
+    use crate::package_relay::AncPkgInfo;
+    use crate::package_relay::SendPackages;
+    use crate::package_relay::ANCPKGINFO_CMD_STRING;
+    use crate::package_relay::PKG_RELAY_ANCPKG;
+    use crate::package_relay::SENDPACKAGES_CMD_STRING;
+    use crate::txreconciliation::SendTxRcncl;
+    use crate::txreconciliation::SENDTXRCNCL_CMD_STRING;
+    use crate::txreconciliation::TXRECONCILIATION_VERSION;
     use crate::TransportProtocol;
 
     /// All the data needed to run a test.
@@ -932,7 +1582,7 @@ mod tests {
         let peer = Peer {
             address,
             our_best_block: 0,
-            writer: WriteTransport::V1(Writer, Network::Regtest),
+            writer: WriteTransport::V1(Writer, Network::Regtest.magic()),
             state: State::Connected,
             kind: ConnectionKind::Manual,
             id: 0,
@@ -956,6 +1606,17 @@ mod tests {
             current_best_block: 0,
             transport_protocol: TransportProtocol::V1,
             cancellation_sender: Some(cancellation_sender),
+            wtxid_relay: false,
+            fee_filter: FeeRate::ZERO,
+            tx_announce_queue: Vec::new(),
+            next_trickle: Instant::now(),
+            private_tx_sent: false,
+            recon_salt: None,
+            peer_recon_salt: None,
+            txreconciliation: None,
+            sent_sendpackages: false,
+            package_relay: false,
+            traffic: Arc::new(TrafficCounter::new(Arc::new(NetTraffic::default()))),
         };
 
         SetupData {
@@ -971,7 +1632,7 @@ mod tests {
         network_message: NetworkMessage,
     ) {
         actor_sender
-            .send(ReaderMessage::Message(network_message, Instant::now()))
+            .send(ReaderMessage::Message(network_message, Instant::now(), 0))
             .unwrap();
     }
 
@@ -1010,7 +1671,7 @@ mod tests {
 
         send_to_peer(
             &mut actor_sender,
-            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address),
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
         );
 
         send_to_peer(&mut actor_sender, NetworkMessage::Verack);
@@ -1036,4 +1697,365 @@ mod tests {
         // Prevents those channels from being dropped, so we don't get a `Channel` error
         drop(node_receiver);
     }
+
+    #[tokio::test]
+    async fn test_wtxid_relay_and_fee_filter() {
+        let SetupData {
+            peer,
+            mut actor_sender,
+            mut node_receiver,
+            node_sender,
+        } = create_peer();
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+
+        // BIP 339: wtxidrelay is sent between version and verack
+        send_to_peer(&mut actor_sender, NetworkMessage::WtxidRelay);
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        // 4000 sat/kvB is 1000 sat/kwu
+        send_to_peer(&mut actor_sender, NetworkMessage::FeeFilter(4000));
+
+        // After negotiating wtxid relay, txid announcements must be ignored
+        let wtxid = Wtxid::from_byte_array([1; 32]);
+        let txid = Txid::from_byte_array([2; 32]);
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::Inv(vec![Inventory::WTx(wtxid), Inventory::Transaction(txid)]),
+        );
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        node_sender.send(NodeRequest::Shutdown).unwrap();
+
+        let peer = fut.await.unwrap().unwrap();
+        assert!(peer.wtxid_relay);
+        assert_eq!(peer.fee_filter, FeeRate::from_sat_per_kwu(1000));
+
+        let mut tx_invs = Vec::new();
+        while let Ok(NodeNotification::FromPeer(_, msg, _)) = node_receiver.try_recv() {
+            if let PeerMessages::TransactionInv(inv) = msg {
+                tx_invs.push(inv);
+            }
+        }
+
+        assert_eq!(tx_invs, vec![vec![Inventory::WTx(wtxid)]]);
+    }
+
+    #[tokio::test]
+    async fn test_block_relay_only() {
+        let SetupData {
+            mut peer,
+            mut actor_sender,
+            mut node_receiver,
+            node_sender,
+        } = create_peer();
+
+        peer.kind = ConnectionKind::BlockRelayOnly;
+        peer.last_addrv2 = Instant::now() - ADDRV2_MESSAGE_INTERVAL;
+
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        // Neither transactions nor addresses should reach the node
+        let txid = Txid::from_byte_array([2; 32]);
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+        );
+
+        let addr = AddrV2Message {
+            time: 0,
+            services: ServiceFlags::NONE,
+            addr: AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+            port: 8333,
+        };
+        send_to_peer(&mut actor_sender, NetworkMessage::AddrV2(vec![addr]));
+
+        // And we shouldn't announce transactions to it, once the handshake is done
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender
+            .send(NodeRequest::BroadcastTransaction(txid))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        node_sender.send(NodeRequest::Shutdown).unwrap();
+        let peer = fut.await.unwrap().unwrap();
+
+        assert!(peer.blocks_only);
+        assert!(peer.tx_announce_queue.is_empty());
+
+        while let Ok(NodeNotification::FromPeer(_, msg, _)) = node_receiver.try_recv() {
+            assert!(!matches!(
+                msg,
+                PeerMessages::TransactionInv(_) | PeerMessages::Addr(_)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_txreconciliation() {
+        let SetupData {
+            peer,
+            mut actor_sender,
+            node_receiver,
+            node_sender,
+        } = create_peer();
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        peer.mempool.lock().await.accept_to_mempool(tx).unwrap();
+
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        // BIP 330: sendtxrcncl goes between version and verack, along with wtxidrelay
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::WtxidRelay);
+
+        let sendtxrcncl = SendTxRcncl {
+            version: TXRECONCILIATION_VERSION,
+            salt: 42,
+        };
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::Unknown {
+                command: CommandString::try_from_static(SENDTXRCNCL_CMD_STRING).unwrap(),
+                payload: serialize(&sendtxrcncl),
+            },
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        // This transaction should be reconciled, not flooded
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender
+            .send(NodeRequest::BroadcastTransaction(txid))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender.send(NodeRequest::Shutdown).unwrap();
+        let peer = fut.await.unwrap().unwrap();
+
+        assert_eq!(peer.peer_recon_salt, Some(42));
+        assert!(peer.txreconciliation.is_some());
+        assert!(peer.tx_announce_queue.is_empty());
+
+        drop((node_receiver, node_sender));
+    }
+
+    #[tokio::test]
+    async fn test_package_relay() {
+        let SetupData {
+            peer,
+            mut actor_sender,
+            mut node_receiver,
+            node_sender,
+        } = create_peer();
+
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        // BIP 331: sendpackages goes between version and verack, and requires wtxid relay
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::WtxidRelay);
+
+        let sendpackages = SendPackages {
+            versions: PKG_RELAY_ANCPKG,
+        };
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::Unknown {
+                command: CommandString::try_from_static(SENDPACKAGES_CMD_STRING).unwrap(),
+                payload: serialize(&sendpackages),
+            },
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        // An ancestor package announcement should reach the node
+        let info = AncPkgInfo {
+            wtxids: vec![
+                Wtxid::from_byte_array([1; 32]),
+                Wtxid::from_byte_array([2; 32]),
+            ],
+        };
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::Unknown {
+                command: CommandString::try_from_static(ANCPKGINFO_CMD_STRING).unwrap(),
+                payload: serialize(&info),
+            },
+        );
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        node_sender.send(NodeRequest::Shutdown).unwrap();
+        let peer = fut.await.unwrap().unwrap();
+
+        assert!(peer.sent_sendpackages);
+        assert!(peer.package_relay);
+
+        let mut packages = Vec::new();
+        while let Ok(NodeNotification::FromPeer(_, msg, _)) = node_receiver.try_recv() {
+            if let PeerMessages::PackageInfo(wtxids) = msg {
+                packages.push(wtxids);
+            }
+        }
+
+        assert_eq!(packages, vec![info.wtxids]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_target() {
+        let SetupData {
+            mut peer,
+            mut actor_sender,
+            node_receiver,
+            node_sender,
+        } = create_peer();
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        peer.mempool.lock().await.accept_to_mempool(tx).unwrap();
+
+        // A tiny target, that our handshake alone will exhaust
+        peer.kind = ConnectionKind::Regular(ServiceFlags::NONE);
+        peer.traffic = Arc::new(TrafficCounter::new(Arc::new(NetTraffic::new(Some(100)))));
+
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender
+            .send(NodeRequest::BroadcastTransaction(txid))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender.send(NodeRequest::Shutdown).unwrap();
+        let peer = fut.await.unwrap().unwrap();
+
+        // We counted what we sent, by command
+        let traffic = peer.traffic.peer_traffic();
+        assert!(traffic.bytes_sent_per_msg.contains_key("version"));
+        assert!(traffic.bytes_sent_per_msg.contains_key("verack"));
+        assert_eq!(
+            traffic.bytes_sent,
+            traffic.bytes_sent_per_msg.values().sum::<u64>()
+        );
+        assert_eq!(
+            peer.traffic.net().totals().total_bytes_sent,
+            traffic.bytes_sent
+        );
+
+        // And stopped announcing transactions, once we reached our target
+        assert!(peer.traffic.net().target_reached());
+        assert!(!traffic.bytes_sent_per_msg.contains_key("inv"));
+        assert!(peer.tx_announce_queue.is_empty());
+
+        drop((node_receiver, node_sender));
+    }
+
+    #[tokio::test]
+    async fn test_private_broadcast() {
+        let SetupData {
+            mut peer,
+            mut actor_sender,
+            node_receiver,
+            node_sender,
+        } = create_peer();
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txid = tx.compute_txid();
+
+        peer.mempool.lock().await.accept_to_mempool(tx).unwrap();
+        peer.kind = ConnectionKind::PrivateBroadcast;
+
+        let address = peer.address.clone();
+        let fut = tokio::spawn(peer.read_loop());
+
+        send_to_peer(
+            &mut actor_sender,
+            peer_utils::build_version_message("/Floresta-test:0.0.0/".into(), 0, &address, true),
+        );
+        send_to_peer(&mut actor_sender, NetworkMessage::Verack);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        node_sender
+            .send(NodeRequest::BroadcastTransaction(txid))
+            .unwrap();
+
+        // Our peer asks for the transaction, and answers the ping we send after it
+        send_to_peer(
+            &mut actor_sender,
+            NetworkMessage::GetData(vec![Inventory::Transaction(txid)]),
+        );
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        send_to_peer(&mut actor_sender, NetworkMessage::Pong(0));
+
+        // The peer should disconnect on its own, without a `Shutdown` request
+        let peer = tokio::time::timeout(Duration::from_secs(5), fut)
+            .await
+            .expect("private broadcast peer didn't disconnect")
+            .unwrap()
+            .unwrap();
+
+        assert!(peer.private_tx_sent);
+        assert!(peer.shutdown);
+        assert!(peer.tx_announce_queue.is_empty());
+
+        drop((node_receiver, node_sender));
+    }
 }
@@ -0,0 +1,608 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Keeps track of wallet rescans, no matter who asked for them.
+//!
+//! A rescan goes through our compact block filters looking for blocks that may have
+//! transactions for a set of scripts, downloads those blocks and hands them to a wallet. On
+//! mainnet, this can take a long time, so the [RescanManager] lets users see how far each rescan
+//! got, abort them, and saves their progress to disk as they go. If we get shut down in the
+//! middle of a rescan, it resumes from the last block it processed, instead of starting over.
+//!
+//! The manager doesn't know anything about wallets, it just calls back whoever started the
+//! rescan with every block that matched. Since wallets usually aren't happy to process the
+//! same block twice, we never give the same block to a job twice, even across restarts.
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use bitcoin::Block;
+use bitcoin::ScriptBuf;
+use floresta_chain::BlockchainInterface;
+use floresta_compact_filters::network_filters::NetworkFilters;
+use floresta_compact_filters::IterableFilterStore;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::node_interface::NodeInterface;
+
+/// The file, inside our data dir, where unfinished rescans are saved
+pub const RESCAN_FILE_NAME: &str = "rescan.json";
+
+/// How many filters we go through before checking whether we should stop, and saving our
+/// progress. This is the distance between checkpoints in the flat filter store, so each chunk
+/// can be read without going through filters from the previous one.
+const RESCAN_CHUNK_SIZE: u32 = 50_000;
+
+/// An identifier for a rescan job, unique while we are running
+pub type RescanJobId = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "lowercase")]
+/// Who asked for a rescan, and therefore, which wallet should get the blocks we find
+pub enum RescanSource {
+    /// The `rescanblockchain` or `loaddescriptor` RPCs, for the node's wallet
+    Rpc,
+
+    /// An Electrum client subscribing to a script we didn't know. `tenant` is the wallet the
+    /// client belongs to, if the Electrum server is in multi-tenant mode.
+    Electrum { tenant: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Everything we need to know to continue a rescan
+pub struct RescanCheckpoint {
+    /// Who asked for this rescan
+    pub source: RescanSource,
+
+    /// The scripts we are looking for
+    pub scripts: Vec<ScriptBuf>,
+
+    /// The height this rescan started at
+    pub start_height: u32,
+
+    /// The last height this rescan will look at
+    pub stop_height: u32,
+
+    /// The next height we have to look at. Every block before it was already processed.
+    pub next_height: u32,
+
+    /// How many blocks matched our filters so far
+    pub matched_blocks: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How we save a [RescanCheckpoint] to disk, with scripts in hex
+struct DiskRescanCheckpoint {
+    #[serde(flatten)]
+    source: RescanSource,
+    scripts: Vec<String>,
+    start_height: u32,
+    stop_height: u32,
+    next_height: u32,
+    matched_blocks: u32,
+}
+
+impl From<&RescanCheckpoint> for DiskRescanCheckpoint {
+    fn from(checkpoint: &RescanCheckpoint) -> Self {
+        DiskRescanCheckpoint {
+            source: checkpoint.source.clone(),
+            scripts: checkpoint
+                .scripts
+                .iter()
+                .map(|script| script.to_hex_string())
+                .collect(),
+            start_height: checkpoint.start_height,
+            stop_height: checkpoint.stop_height,
+            next_height: checkpoint.next_height,
+            matched_blocks: checkpoint.matched_blocks,
+        }
+    }
+}
+
+impl TryFrom<DiskRescanCheckpoint> for RescanCheckpoint {
+    type Error = bitcoin::hex::HexToBytesError;
+
+    fn try_from(checkpoint: DiskRescanCheckpoint) -> Result<Self, Self::Error> {
+        let scripts = checkpoint
+            .scripts
+            .iter()
+            .map(|script| ScriptBuf::from_hex(script))
+            .collect::<Result<_, _>>()?;
+
+        Ok(RescanCheckpoint {
+            source: checkpoint.source,
+            scripts,
+            start_height: checkpoint.start_height,
+            stop_height: checkpoint.stop_height,
+            next_height: checkpoint.next_height,
+            matched_blocks: checkpoint.matched_blocks,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// A snapshot of a rescan's progress, as returned by `getrescaninfo`
+pub struct RescanInfo {
+    /// This job's id
+    pub id: RescanJobId,
+
+    /// Who asked for this rescan
+    #[serde(flatten)]
+    pub source: RescanSource,
+
+    /// Whether this rescan is running right now. Rescans that got interrupted, for example
+    /// because we couldn't download a block, are retried later.
+    pub running: bool,
+
+    /// The height this rescan started at
+    pub start_height: u32,
+
+    /// The last height this rescan will look at
+    pub stop_height: u32,
+
+    /// The last height we went through
+    pub current_height: u32,
+
+    /// How many blocks matched our filters so far
+    pub matched_blocks: u32,
+
+    /// How much of the interval we went through, from 0 to 1
+    pub progress: f64,
+
+    /// How many seconds we expect this rescan to take, if it's running
+    pub eta: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How a call to [RescanManager::run] ended
+pub enum RescanOutcome {
+    /// We went through every block in the interval
+    Finished,
+
+    /// Someone called [RescanManager::abort_all], the job is gone
+    Aborted,
+
+    /// We couldn't continue, e.g. a block couldn't be downloaded. The job is kept, and can be
+    /// picked up again with [RescanManager::take_interrupted].
+    Interrupted,
+}
+
+#[derive(Debug)]
+/// A rescan we know about
+struct RescanJob {
+    /// Where this job is at
+    checkpoint: RescanCheckpoint,
+
+    /// Whether someone is running this job
+    running: bool,
+
+    /// Whether we were asked to stop this job
+    abort: bool,
+
+    /// When this job was last (re)started, used to estimate how long it will take
+    resumed_at: Instant,
+
+    /// The height this job was at when it was last (re)started
+    resumed_from: u32,
+}
+
+impl RescanJob {
+    fn info(&self, id: RescanJobId) -> RescanInfo {
+        let checkpoint = &self.checkpoint;
+        let current_height = checkpoint.next_height.saturating_sub(1);
+
+        let total = checkpoint.stop_height - checkpoint.start_height + 1;
+        let done = checkpoint.next_height - checkpoint.start_height;
+        let progress = f64::from(done) / f64::from(total);
+
+        let scanned = checkpoint.next_height - self.resumed_from;
+        let elapsed = self.resumed_at.elapsed().as_secs_f64();
+        let eta = (self.running && scanned > 0).then(|| {
+            let remaining = f64::from(total - done);
+            (remaining * elapsed / f64::from(scanned)) as u64
+        });
+
+        RescanInfo {
+            id,
+            source: checkpoint.source.clone(),
+            running: self.running,
+            start_height: checkpoint.start_height,
+            stop_height: checkpoint.stop_height,
+            current_height,
+            matched_blocks: checkpoint.matched_blocks,
+            progress,
+            eta,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RescanJobs {
+    /// The id we'll give to the next job
+    next_id: RescanJobId,
+
+    /// Every job that didn't finish yet
+    jobs: BTreeMap<RescanJobId, RescanJob>,
+}
+
+#[derive(Debug, Clone)]
+/// Keeps track of every rescan, running or interrupted, and saves them to disk.
+///
+/// This is cheap to clone, and every clone refers to the same set of jobs, so the same
+/// manager can be shared between the RPC and Electrum servers.
+pub struct RescanManager {
+    /// The jobs we know about
+    inner: Arc<Mutex<RescanJobs>>,
+
+    /// Where we save unfinished jobs, if anywhere
+    path: Option<String>,
+}
+
+impl RescanManager {
+    /// Creates a manager that saves unfinished rescans inside `datadir`, and loads the ones
+    /// a previous run left behind. They are marked as interrupted, see
+    /// [take_interrupted](Self::take_interrupted).
+    ///
+    /// If `datadir` is `None`, nothing is saved to disk.
+    pub fn new(datadir: Option<&str>) -> Self {
+        let manager = RescanManager {
+            inner: Arc::new(Mutex::new(RescanJobs::default())),
+            path: datadir.map(|datadir| format!("{datadir}/{RESCAN_FILE_NAME}")),
+        };
+
+        for checkpoint in manager.load() {
+            manager.add_job(checkpoint, false);
+        }
+
+        manager
+    }
+
+    /// Reads the jobs saved by a previous run
+    fn load(&self) -> Vec<RescanCheckpoint> {
+        let Some(path) = &self.path else {
+            return Vec::new();
+        };
+
+        let checkpoints = match read_to_string(path) {
+            Ok(checkpoints) => checkpoints,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                warn!("Could not read unfinished rescans from {path}: {e}");
+                return Vec::new();
+            }
+        };
+
+        let checkpoints = serde_json::from_str::<Vec<DiskRescanCheckpoint>>(&checkpoints)
+            .map_err(|e| e.to_string())
+            .and_then(|checkpoints| {
+                checkpoints
+                    .into_iter()
+                    .map(RescanCheckpoint::try_from)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())
+            });
+
+        checkpoints.unwrap_or_else(|e| {
+            warn!("Ignoring invalid unfinished rescans in {path}: {e}");
+            Vec::new()
+        })
+    }
+
+    /// Saves every unfinished job to disk, except the ones we were asked to abort
+    fn save(&self, jobs: &RescanJobs) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let checkpoints = jobs
+            .jobs
+            .values()
+            .filter(|job| !job.abort)
+            .map(|job| DiskRescanCheckpoint::from(&job.checkpoint))
+            .collect::<Vec<_>>();
+
+        let res = serde_json::to_vec(&checkpoints)
+            .map_err(io::Error::from)
+            .and_then(|checkpoints| std::fs::write(path, checkpoints));
+
+        if let Err(e) = res {
+            warn!("Could not save rescan progress to {path}: {e}");
+        }
+    }
+
+    fn add_job(&self, checkpoint: RescanCheckpoint, running: bool) -> RescanJobId {
+        let mut inner = self.inner.lock().unwrap();
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        let job = RescanJob {
+            resumed_from: checkpoint.next_height,
+            checkpoint,
+            running,
+            abort: false,
+            resumed_at: Instant::now(),
+        };
+
+        inner.jobs.insert(id, job);
+        self.save(&inner);
+
+        id
+    }
+
+    /// Creates a new job looking for `scripts` in blocks `start_height..=stop_height`, and
+    /// saves it to disk. The job is considered running from now on, pass the returned id to
+    /// [run](Self::run) to actually do the work.
+    pub fn start(
+        &self,
+        source: RescanSource,
+        scripts: Vec<ScriptBuf>,
+        start_height: u32,
+        stop_height: u32,
+    ) -> RescanJobId {
+        let checkpoint = RescanCheckpoint {
+            source,
+            scripts,
+            start_height,
+            stop_height: stop_height.max(start_height),
+            next_height: start_height,
+            matched_blocks: 0,
+        };
+
+        self.add_job(checkpoint, true)
+    }
+
+    /// Takes every interrupted job whose source satisfies `predicate`, marking them as running.
+    /// Each of them should be given to [run](Self::run).
+    pub fn take_interrupted(
+        &self,
+        predicate: impl Fn(&RescanSource) -> bool,
+    ) -> Vec<(RescanJobId, RescanCheckpoint)> {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner
+            .jobs
+            .iter_mut()
+            .filter(|(_, job)| !job.running && predicate(&job.checkpoint.source))
+            .map(|(id, job)| {
+                job.running = true;
+                job.resumed_at = Instant::now();
+                job.resumed_from = job.checkpoint.next_height;
+
+                (*id, job.checkpoint.clone())
+            })
+            .collect()
+    }
+
+    /// Forgets about an interrupted job, e.g. because the wallet it was for doesn't exist
+    /// anymore
+    pub fn discard(&self, id: RescanJobId) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.jobs.remove(&id).is_some() {
+            self.save(&inner);
+        }
+    }
+
+    /// The progress of every job we know about, running or not
+    pub fn info(&self) -> Vec<RescanInfo> {
+        let inner = self.inner.lock().unwrap();
+
+        inner.jobs.iter().map(|(id, job)| job.info(*id)).collect()
+    }
+
+    /// Stops every job. Interrupted jobs are removed right away, running ones are removed as
+    /// soon as they notice it, which happens before the next block is processed.
+    ///
+    /// Returns whether there was any job to abort.
+    pub fn abort_all(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.jobs.is_empty() {
+            return false;
+        }
+
+        inner.jobs.retain(|_, job| job.running);
+        inner.jobs.values_mut().for_each(|job| job.abort = true);
+        self.save(&inner);
+
+        true
+    }
+
+    /// Whether we should stop working on this job
+    fn should_abort(&self, id: RescanJobId) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.jobs.get(&id) {
+            Some(job) => job.abort,
+            None => true,
+        }
+    }
+
+    /// Updates a job, and saves it to disk
+    fn checkpoint(&self, id: RescanJobId, next_height: u32, matched_blocks: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(job) = inner.jobs.get_mut(&id) else {
+            return;
+        };
+
+        job.checkpoint.next_height = next_height;
+        job.checkpoint.matched_blocks = matched_blocks;
+        self.save(&inner);
+    }
+
+    /// Marks a job as interrupted, so it can be picked up later
+    fn interrupt(&self, id: RescanJobId) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(job) = inner.jobs.get_mut(&id) {
+            job.running = false;
+        }
+    }
+
+    /// Does the actual work for job `id`, calling `on_block` with every block that matched
+    /// our filters, in order. Progress is saved after each block, so if this job gets
+    /// interrupted, `on_block` will never see the same block twice.
+    pub async fn run<Storage, Chain>(
+        &self,
+        id: RescanJobId,
+        filters: &NetworkFilters<Storage>,
+        chain: &Chain,
+        node: &NodeInterface,
+        mut on_block: impl FnMut(&Block, u32),
+    ) -> RescanOutcome
+    where
+        Storage: IterableFilterStore,
+        Chain: BlockchainInterface + Clone,
+    {
+        let checkpoint = {
+            let inner = self.inner.lock().unwrap();
+            match inner.jobs.get(&id) {
+                Some(job) => job.checkpoint.clone(),
+                None => return RescanOutcome::Aborted,
+            }
+        };
+
+        let RescanCheckpoint {
+            scripts,
+            stop_height,
+            mut next_height,
+            mut matched_blocks,
+            ..
+        } = checkpoint;
+
+        let query = scripts
+            .iter()
+            .map(|script| script.as_bytes())
+            .collect::<Vec<_>>();
+
+        while next_height <= stop_height {
+            if self.should_abort(id) {
+                info!("Rescan {id} aborted at height {next_height}");
+                self.discard(id);
+                return RescanOutcome::Aborted;
+            }
+
+            let chunk_end = (next_height / RESCAN_CHUNK_SIZE + 1) * RESCAN_CHUNK_SIZE - 1;
+            let chunk_end = chunk_end.min(stop_height);
+
+            let blocks =
+                match filters.match_range(query.clone(), next_height, chunk_end, chain.clone()) {
+                    Ok(blocks) => blocks,
+                    Err(e) => {
+                        warn!("Rescan {id} could not read block filters: {e}");
+                        self.interrupt(id);
+                        return RescanOutcome::Interrupted;
+                    }
+                };
+
+            for (height, hash) in blocks {
+                if self.should_abort(id) {
+                    info!("Rescan {id} aborted at height {height}");
+                    self.discard(id);
+                    return RescanOutcome::Aborted;
+                }
+
+                let Ok(Some(block)) = node.get_block(hash).await else {
+                    warn!("Rescan {id} could not download block {hash}, will retry later");
+                    self.interrupt(id);
+                    return RescanOutcome::Interrupted;
+                };
+
+                on_block(&block, height);
+
+                matched_blocks += 1;
+                next_height = height + 1;
+                self.checkpoint(id, next_height, matched_blocks);
+            }
+
+            next_height = chunk_end + 1;
+            self.checkpoint(id, next_height, matched_blocks);
+        }
+
+        info!("Rescan {id} finished, {matched_blocks} blocks matched");
+        self.discard(id);
+        RescanOutcome::Finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bitcoin::ScriptBuf;
+
+    use super::RescanManager;
+    use super::RescanSource;
+    use super::RESCAN_FILE_NAME;
+
+    fn datadir() -> String {
+        let datadir = format!("./tmp-db/{}.rescan", rand::random::<u32>());
+        fs::create_dir_all(&datadir).unwrap();
+
+        datadir
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let datadir = datadir();
+        let script = ScriptBuf::from_hex("0014ad9dd5a4b9c0e8a1e0ba4c3e2cb6a6cc9d6e3a2b").unwrap();
+
+        let manager = RescanManager::new(Some(&datadir));
+        let tenant = RescanSource::Electrum {
+            tenant: Some("alice".into()),
+        };
+        let id = manager.start(tenant.clone(), vec![script.clone()], 100, 199);
+        manager.checkpoint(id, 150, 3);
+
+        let info = manager.info();
+        assert_eq!(info.len(), 1);
+        assert!(info[0].running);
+        assert_eq!(info[0].current_height, 149);
+        assert_eq!(info[0].matched_blocks, 3);
+        assert_eq!(info[0].progress, 0.5);
+
+        // A new manager, like after a restart, picks up from where we stopped
+        let manager = RescanManager::new(Some(&datadir));
+        assert!(manager
+            .take_interrupted(|source| *source == RescanSource::Rpc)
+            .is_empty());
+
+        let jobs = manager.take_interrupted(|source| *source == tenant);
+        assert_eq!(jobs.len(), 1);
+        let (id, checkpoint) = &jobs[0];
+        assert_eq!(checkpoint.scripts, vec![script]);
+        assert_eq!(checkpoint.next_height, 150);
+        assert_eq!(checkpoint.stop_height, 199);
+
+        // Running jobs aren't given away twice
+        assert!(manager.take_interrupted(|_| true).is_empty());
+
+        manager.discard(*id);
+        assert!(manager.info().is_empty());
+        assert!(RescanManager::new(Some(&datadir)).info().is_empty());
+    }
+
+    #[test]
+    fn test_abort() {
+        let datadir = datadir();
+        let manager = RescanManager::new(Some(&datadir));
+        assert!(!manager.abort_all());
+
+        let running = manager.start(RescanSource::Rpc, Vec::new(), 0, 10);
+        let interrupted = manager.start(RescanSource::Rpc, Vec::new(), 0, 10);
+        manager.interrupt(interrupted);
+
+        assert!(manager.abort_all());
+
+        // Interrupted jobs are gone right away, running ones once they notice
+        assert_eq!(manager.info().len(), 1);
+        assert!(manager.should_abort(running));
+        assert!(manager.should_abort(interrupted));
+
+        let saved = fs::read_to_string(format!("{datadir}/{RESCAN_FILE_NAME}")).unwrap();
+        assert_eq!(saved, "[]");
+    }
+}
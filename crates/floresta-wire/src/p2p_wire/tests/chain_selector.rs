@@ -2,15 +2,31 @@
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use bitcoin::block::Header;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::Amount;
+    use bitcoin::Block;
     use bitcoin::BlockHash;
     use bitcoin::Network;
     use floresta_chain::pruned_utreexo::BlockchainInterface;
+    use floresta_chain::pruned_utreexo::UpdatableChainstate;
+    use floresta_chain::AssumeValidArg;
+    use floresta_chain::ChainState;
+    use floresta_chain::FlatChainStore;
+    use floresta_chain::FlatChainStoreConfig;
     use floresta_common::acchashes;
     use floresta_common::prelude::HashMap;
     use rustreexo::node_hash::BitcoinNodeHash;
     use rustreexo::stump::Stump;
 
+    use crate::fraud_proofs::FraudProofLog;
+    use crate::fraud_proofs::TipCheckOutcome;
+    use crate::node::PeerStatus;
     use crate::p2p_wire::tests::utils::create_false_acc;
+    use crate::p2p_wire::tests::utils::mine_regtest_block;
+    use crate::p2p_wire::tests::utils::setup_chain_selector;
     use crate::p2p_wire::tests::utils::setup_node;
     use crate::p2p_wire::tests::utils::signet_blocks;
     use crate::p2p_wire::tests::utils::signet_headers;
@@ -18,6 +34,9 @@ mod tests {
     use crate::p2p_wire::tests::utils::PeerData;
     const STARTING_LIE_BLOCK_HEIGHT: usize = 30;
 
+    /// The subsidy of the first regtest blocks
+    const SUBSIDY: Amount = Amount::from_sat(50 * 100_000_000);
+
     pub const NUM_BLOCKS: usize = 120;
 
     #[tokio::test]
@@ -114,4 +133,161 @@ mod tests {
         };
         assert_eq!(chain.acc(), expected_acc);
     }
+
+    /// Mines `count` blocks on top of `prev`, the first one paying `first_reward`
+    fn mine_branch(prev: &Header, count: u32, first_reward: Amount, tag: u8) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        // `prev` is the block at height 1
+        for height in 2..(2 + count) {
+            let reward = if blocks.is_empty() {
+                first_reward
+            } else {
+                SUBSIDY
+            };
+            let parent = blocks.last().map(|block| block.header).unwrap_or(*prev);
+            blocks.push(mine_regtest_block(&parent, height, reward, tag));
+        }
+
+        blocks
+    }
+
+    /// The data served by a peer following `branch`, on top of `block1`
+    fn branch_peer(block1: &Block, branch: &[Block]) -> PeerData {
+        let mut headers = vec![block1.header];
+        headers.extend(branch.iter().map(|block| block.header));
+
+        let blocks = branch
+            .iter()
+            .chain([block1])
+            .map(|block| (block.block_hash(), block.clone()))
+            .collect();
+
+        // Our peers agree on the accumulator for block 1, it has no leaves as we only need it
+        // to validate coinbase-only blocks
+        let accs = HashMap::from([(block1.block_hash(), 0u64.to_le_bytes().to_vec())]);
+
+        PeerData::new(headers, blocks, accs)
+    }
+
+    /// A regtest chain forking at height 1, and the peers following each branch
+    struct ForkChain {
+        /// Our chain, with the headers of both branches
+        chain: Arc<ChainState<FlatChainStore>>,
+
+        /// The last block in both branches
+        block1: Block,
+
+        /// The peer following the honest branch, and its tip
+        honest_peer: (PeerData, BlockHash),
+
+        /// The peer following the lying branch, and its tip
+        liar_peer: (PeerData, BlockHash),
+    }
+
+    /// Creates a regtest chain forking at height 1, with the headers of an honest and a
+    /// lying branch. The lying branch starts with a block that overpays its coinbase.
+    fn fork_chain(datadir: &str, honest_len: u32, liar_len: u32) -> ForkChain {
+        let genesis = genesis_block(Network::Regtest);
+        let block1 = mine_regtest_block(&genesis.header, 1, SUBSIDY, 0);
+        let honest = mine_branch(&block1.header, honest_len, SUBSIDY, 1);
+        let liar = mine_branch(&block1.header, liar_len, SUBSIDY * 2, 2);
+
+        let chainstore = FlatChainStore::new(FlatChainStoreConfig::new(datadir)).unwrap();
+        let chain =
+            ChainState::open(chainstore, Network::Regtest, AssumeValidArg::Disabled).unwrap();
+
+        chain.accept_header(block1.header).unwrap();
+        for block in honest.iter().chain(liar.iter()) {
+            chain.accept_header(block.header).unwrap();
+        }
+
+        let honest_peer = (
+            branch_peer(&block1, &honest),
+            honest.last().unwrap().block_hash(),
+        );
+        let liar_peer = (
+            branch_peer(&block1, &liar),
+            liar.last().unwrap().block_hash(),
+        );
+
+        ForkChain {
+            chain: Arc::new(chain),
+            block1,
+            honest_peer,
+            liar_peer,
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_fork_with_more_work() {
+        let datadir = format!("./tmp-db/{}.chain_selector", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let ForkChain {
+            chain,
+            block1,
+            honest_peer,
+            liar_peer,
+        } = fork_chain(&datadir, 109, 114);
+        let (honest_tip, liar_tip) = (honest_peer.1, liar_peer.1);
+        assert_eq!(chain.get_best_block().unwrap(), (115, liar_tip));
+        assert_eq!(chain.get_chain_tips().unwrap(), vec![liar_tip, honest_tip]);
+
+        let peers = vec![honest_peer, liar_peer];
+        let mut node = setup_chain_selector(peers, chain.clone(), &datadir);
+        node.check_tips().await.unwrap();
+
+        // We've found the invalid block, and moved to the honest chain
+        assert_eq!(chain.get_best_block().unwrap(), (110, honest_tip));
+        assert_eq!(chain.get_chain_tips().unwrap(), vec![honest_tip]);
+        assert_eq!(node.peers[&1].state, PeerStatus::Banned);
+
+        let checks = node.fraud_proofs.checks();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].tip, honest_tip);
+        assert_eq!(checks[0].best_block, liar_tip);
+        assert_eq!(checks[0].fork_point, Some(block1.block_hash()));
+        assert_eq!(checks[0].fork_height, Some(1));
+        assert_eq!(checks[0].outcome, TipCheckOutcome::OurChainInvalid);
+        assert_eq!(checks[0].error, None);
+
+        // The check is kept across restarts
+        let log = FraudProofLog::load(&datadir);
+        assert_eq!(log.checks(), checks);
+    }
+
+    #[tokio::test]
+    async fn invalid_fork_with_less_work() {
+        let datadir = format!("./tmp-db/{}.chain_selector", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let ForkChain {
+            chain,
+            block1,
+            honest_peer,
+            liar_peer,
+        } = fork_chain(&datadir, 109, 49);
+        let (honest_tip, liar_tip) = (honest_peer.1, liar_peer.1);
+        assert_eq!(chain.get_best_block().unwrap(), (110, honest_tip));
+        assert_eq!(chain.get_chain_tips().unwrap(), vec![honest_tip, liar_tip]);
+
+        let peers = vec![honest_peer, liar_peer];
+        let mut node = setup_chain_selector(peers, chain.clone(), &datadir);
+        node.check_tips().await.unwrap();
+
+        // Our chain is valid, so we stay there and ban whoever announced the other one
+        assert_eq!(chain.get_best_block().unwrap(), (110, honest_tip));
+        assert_eq!(node.peers[&0].state, PeerStatus::Ready);
+        assert_eq!(node.peers[&1].state, PeerStatus::Banned);
+
+        let checks = node.fraud_proofs.checks();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].tip, liar_tip);
+        assert_eq!(checks[0].best_block, honest_tip);
+        assert_eq!(checks[0].fork_point, Some(block1.block_hash()));
+        assert_eq!(checks[0].outcome, TipCheckOutcome::TipRejected);
+
+        let log = FraudProofLog::load(&datadir);
+        assert_eq!(log.checks(), checks);
+    }
 }
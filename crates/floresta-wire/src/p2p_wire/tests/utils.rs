@@ -6,6 +6,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use bitcoin::absolute::LockTime;
 use bitcoin::block::Header;
 use bitcoin::consensus::encode;
 use bitcoin::consensus::encode::deserialize_hex;
@@ -13,9 +14,19 @@ use bitcoin::consensus::Decodable;
 use bitcoin::hex::FromHex;
 use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::ServiceFlags;
+use bitcoin::script::Builder;
+use bitcoin::transaction::Version as TxVersion;
+use bitcoin::Amount;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Network;
+use bitcoin::OutPoint;
+use bitcoin::ScriptBuf;
+use bitcoin::Sequence;
+use bitcoin::Transaction;
+use bitcoin::TxIn;
+use bitcoin::TxOut;
+use bitcoin::Witness;
 use derive_more::Constructor;
 use floresta_chain::pruned_utreexo::UpdatableChainstate;
 use floresta_chain::AssumeValidArg;
@@ -39,6 +50,7 @@ use tokio::time::timeout;
 use zstd;
 
 use crate::address_man::AddressMan;
+use crate::node::chain_selector_ctx::ChainSelector;
 use crate::node::sync_ctx::SyncNode;
 use crate::node::ConnectionKind;
 use crate::node::InflightRequests;
@@ -50,6 +62,8 @@ use crate::node::UtreexoNode;
 use crate::p2p_wire::block_proof::UtreexoProof;
 use crate::p2p_wire::peer::PeerMessages;
 use crate::p2p_wire::peer::Version;
+use crate::p2p_wire::traffic::NetTraffic;
+use crate::p2p_wire::traffic::TrafficCounter;
 use crate::p2p_wire::transport::TransportProtocol;
 use crate::UtreexoNodeConfig;
 
@@ -187,6 +201,7 @@ pub fn create_peer(
         address_id: 0,
         _last_message: Instant::now(),
         transport_protocol: TransportProtocol::V2,
+        traffic: Arc::new(TrafficCounter::new(Arc::new(NetTraffic::default()))),
     }
 }
 
@@ -372,6 +387,107 @@ pub async fn setup_node(
     chain
 }
 
+/// Mines a regtest block on top of `prev`, with only a coinbase paying `reward`
+///
+/// Blocks with the same parent and height can be told apart by their `tag`. If `reward` is
+/// more than the subsidy, the block is invalid, while its header is still fine.
+pub fn mine_regtest_block(prev: &Header, height: u32, reward: Amount, tag: u8) -> Block {
+    let coinbase = Transaction {
+        version: TxVersion::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new()
+                .push_int(height as i64)
+                .push_int(tag as i64)
+                .into_script(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: reward,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+
+    let mut block = Block {
+        header: Header {
+            version: prev.version,
+            prev_blockhash: prev.block_hash(),
+            merkle_root: prev.merkle_root,
+            time: prev.time + 1,
+            bits: prev.bits,
+            nonce: 0,
+        },
+        txdata: vec![coinbase],
+    };
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+
+    // Regtest targets are so easy that we find a valid nonce after a couple of tries
+    while block.header.validate_pow(block.header.target()).is_err() {
+        block.header.nonce += 1;
+    }
+
+    block
+}
+
+/// Creates a [`ChainSelector`] node on top of `chain`, already connected to `peers`
+///
+/// Each peer is served by a [`SimulatedPeer`] and is known to be on the tip given along with
+/// it, so we can call the chain selection methods directly, without running the node.
+pub fn setup_chain_selector(
+    peers: Vec<(PeerData, BlockHash)>,
+    chain: Arc<ChainState<FlatChainStore>>,
+    datadir: &str,
+) -> UtreexoNode<Arc<ChainState<FlatChainStore>>, ChainSelector> {
+    let mempool = Arc::new(Mutex::new(Mempool::new(1000)));
+    let config = get_node_config(datadir.into(), Network::Regtest, true);
+    let kill_signal = Arc::new(RwLock::new(false));
+    let mut node = UtreexoNode::<Arc<ChainState<FlatChainStore>>, ChainSelector>::new(
+        config,
+        chain,
+        mempool,
+        None,
+        kill_signal,
+        AddressMan::new(None, &[]),
+    )
+    .unwrap();
+
+    for (i, (peer, tip)) in peers.into_iter().enumerate() {
+        let (sender, receiver) = unbounded_channel();
+        let peer_id = i as u32;
+
+        let mut peer = create_peer(
+            peer.headers,
+            peer.blocks,
+            peer.accs,
+            node.node_tx.clone(),
+            sender,
+            receiver,
+            peer_id,
+        );
+
+        // We only ask archive peers for their accumulators
+        peer.services = ServiceFlags::from(service_flags::UTREEXO | service_flags::UTREEXO_ARCHIVE);
+
+        node.peers.insert(peer_id, peer);
+        node.peer_ids.push(peer_id);
+        for service in [
+            service_flags::UTREEXO.into(),
+            service_flags::UTREEXO_ARCHIVE.into(),
+        ] {
+            node.peer_by_service
+                .entry(service)
+                .or_default()
+                .push(peer_id);
+        }
+
+        node.context.tip_cache.insert(peer_id, tip);
+    }
+
+    node
+}
+
 // TODO: remove this after bitcoin-0.33.0
 fn to_addr_v2(addr: IpAddr) -> AddrV2 {
     match addr {
@@ -0,0 +1,736 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! A client for Tor's control protocol, used to publish our services as an onion service.
+//!
+//! Tor exposes a control port, where controllers can authenticate and then ask Tor to do things
+//! for them. We use it to create an ephemeral v3 onion service (`ADD_ONION`) that forwards some
+//! virtual ports to services we run locally, so people can reach them over Tor without us
+//! opening any ports in our router.
+//!
+//! The onion service only lives as long as the control connection that created it. Its private
+//! key, on the other hand, is saved inside our data directory, so we keep the same onion address
+//! across restarts. See <https://spec.torproject.org/control-spec> for the protocol.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::net::SocketAddr;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use bitcoin::hashes::hmac;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
+use bitcoin::hex::DisplayHex;
+use bitcoin::hex::FromHex;
+use bitcoin::p2p::address::AddrV2;
+use floresta_common::impl_error_from;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::TcpStream;
+use tracing::debug;
+use tracing::info;
+
+/// The file, inside our data directory, where we keep the private key of our onion service
+pub const ONION_PRIVATE_KEY_FILE: &str = "onion_v3_private_key";
+
+/// The default port of Tor's control port
+pub const DEFAULT_TOR_CONTROL_PORT: u16 = 9051;
+
+/// The longest line we accept from Tor
+const MAX_LINE_SIZE: u64 = 65_536;
+
+/// The key for the HMAC Tor sends us to prove it knows the cookie
+const SAFECOOKIE_SERVER_KEY: &[u8] = b"Tor safe cookie authentication server-to-controller hash";
+
+/// The key for the HMAC we send to Tor to prove we know the cookie
+const SAFECOOKIE_CLIENT_KEY: &[u8] = b"Tor safe cookie authentication controller-to-server hash";
+
+/// The size of the authentication cookie
+const COOKIE_SIZE: usize = 32;
+
+/// The version byte at the end of every v3 onion address
+const ONION_V3_VERSION: u8 = 3;
+
+/// The RFC 4648 base32 alphabet, lowercase, as used by onion addresses
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Debug)]
+pub enum TorError {
+    /// We couldn't talk to Tor
+    Io(io::Error),
+
+    /// Tor refused a command
+    Refused {
+        /// The command we sent, e.g. `ADD_ONION`
+        command: String,
+        /// The status code, e.g. `515` for a failed authentication
+        code: u16,
+        /// Tor's explanation
+        message: String,
+    },
+
+    /// Tor sent us something we don't understand
+    InvalidReply(String),
+
+    /// We can't use any of the authentication methods Tor accepts
+    NoAuthMethod(String),
+
+    /// Tor couldn't prove it knows the cookie, so it isn't the Tor we think it is
+    InvalidServerHash,
+
+    /// Tor gave us an onion address that isn't a valid v3 address
+    InvalidServiceId(String),
+}
+
+impl Display for TorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TorError::Io(e) => write!(f, "Error talking to Tor's control port: {e}"),
+            TorError::Refused {
+                command,
+                code,
+                message,
+            } => write!(f, "Tor refused {command}: {code} {message}"),
+            TorError::InvalidReply(reply) => write!(f, "Invalid reply from Tor: {reply}"),
+            TorError::NoAuthMethod(methods) => write!(
+                f,
+                "Can't authenticate with Tor, it only accepts {methods}. Maybe a password is missing?"
+            ),
+            TorError::InvalidServerHash => {
+                write!(f, "Tor's SAFECOOKIE server hash doesn't match our cookie")
+            }
+            TorError::InvalidServiceId(id) => write!(f, "Invalid onion service id: {id}"),
+        }
+    }
+}
+
+impl std::error::Error for TorError {}
+
+impl_error_from!(TorError, io::Error, Io);
+
+/// A reply from Tor, all replies have a status code and one or more lines
+#[derive(Debug)]
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A port of our onion service, and where Tor should forward connections to it
+pub struct OnionPort {
+    /// The port people connect to, in our onion address
+    pub virtual_port: u16,
+    /// The local service that gets those connections
+    pub target: SocketAddr,
+}
+
+#[derive(Debug)]
+/// An authenticated connection to Tor's control port
+pub struct TorControl {
+    stream: BufReader<TcpStream>,
+}
+
+impl TorControl {
+    /// Connects to the control port at `address` and authenticates.
+    ///
+    /// If `password` is set, we use it if Tor accepts passwords. Otherwise, we use no
+    /// authentication or, if Tor asks for it, its authentication cookie.
+    pub async fn connect(address: SocketAddr, password: Option<&str>) -> Result<Self, TorError> {
+        let stream = TcpStream::connect(address).await?;
+        let mut control = TorControl {
+            stream: BufReader::new(stream),
+        };
+
+        control.authenticate(password).await?;
+        debug!("Authenticated with Tor's control port at {address}");
+
+        Ok(control)
+    }
+
+    /// Creates an onion service for these ports, returning its service id and, if we asked for
+    /// a new key, its private key.
+    ///
+    /// `private_key` should be in the format Tor gives us, e.g. `ED25519-V3:<base64>`.
+    pub async fn add_onion(
+        &mut self,
+        private_key: Option<&str>,
+        ports: &[OnionPort],
+    ) -> Result<(String, Option<String>), TorError> {
+        let mut command = format!("ADD_ONION {}", private_key.unwrap_or("NEW:ED25519-V3"));
+        for port in ports {
+            command.push_str(&format!(" Port={},{}", port.virtual_port, port.target));
+        }
+
+        let reply = self.send_command(&command).await?;
+        let fields = parse_fields(&reply.lines.join(" "));
+
+        let service_id = fields
+            .get("ServiceID")
+            .cloned()
+            .ok_or_else(|| TorError::InvalidReply("ADD_ONION without a ServiceID".into()))?;
+
+        Ok((service_id, fields.get("PrivateKey").cloned()))
+    }
+
+    /// Waits until Tor closes this connection, which also takes down our onion service
+    pub async fn closed(mut self) {
+        let mut line = String::new();
+        while let Ok(1..) = self.stream.read_line(&mut line).await {
+            line.clear();
+        }
+    }
+
+    async fn authenticate(&mut self, password: Option<&str>) -> Result<(), TorError> {
+        let reply = self.send_command("PROTOCOLINFO 1").await?;
+        let auth = reply
+            .lines
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .ok_or_else(|| TorError::InvalidReply("PROTOCOLINFO without AUTH".into()))?;
+
+        let fields = parse_fields(auth);
+        let methods = fields.get("METHODS").cloned().unwrap_or_default();
+        let accepts = |method: &str| methods.split(',').any(|m| m == method);
+
+        if let (true, Some(password)) = (accepts("HASHEDPASSWORD"), password) {
+            let password = password.replace('\\', "\\\\").replace('"', "\\\"");
+            self.send_command(&format!("AUTHENTICATE \"{password}\""))
+                .await?;
+            return Ok(());
+        }
+
+        if accepts("NULL") {
+            self.send_command("AUTHENTICATE").await?;
+            return Ok(());
+        }
+
+        let cookie_file = fields.get("COOKIEFILE");
+        if let (true, Some(cookie_file)) = (accepts("SAFECOOKIE"), cookie_file) {
+            let cookie = read_cookie(cookie_file)?;
+            return self.safecookie(&cookie).await;
+        }
+
+        if let (true, Some(cookie_file)) = (accepts("COOKIE"), cookie_file) {
+            let cookie = read_cookie(cookie_file)?;
+            self.send_command(&format!("AUTHENTICATE {}", cookie.to_lower_hex_string()))
+                .await?;
+            return Ok(());
+        }
+
+        Err(TorError::NoAuthMethod(methods))
+    }
+
+    /// The SAFECOOKIE challenge-response, where both sides prove they know the cookie without
+    /// revealing it
+    async fn safecookie(&mut self, cookie: &[u8]) -> Result<(), TorError> {
+        let client_nonce: [u8; 32] = rand::random();
+        let reply = self
+            .send_command(&format!(
+                "AUTHCHALLENGE SAFECOOKIE {}",
+                client_nonce.to_lower_hex_string()
+            ))
+            .await?;
+
+        let challenge = reply
+            .lines
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTHCHALLENGE "))
+            .ok_or_else(|| TorError::InvalidReply(reply.lines.join(" ")))?;
+
+        let fields = parse_fields(challenge);
+        let hex_field = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|value| Vec::<u8>::from_hex(value).ok())
+                .ok_or_else(|| TorError::InvalidReply(format!("AUTHCHALLENGE without {key}")))
+        };
+
+        let server_hash = hex_field("SERVERHASH")?;
+        let server_nonce = hex_field("SERVERNONCE")?;
+
+        let message = [cookie, &client_nonce, &server_nonce].concat();
+        if safecookie_hmac(SAFECOOKIE_SERVER_KEY, &message) != server_hash.as_slice() {
+            return Err(TorError::InvalidServerHash);
+        }
+
+        let client_hash = safecookie_hmac(SAFECOOKIE_CLIENT_KEY, &message);
+        self.send_command(&format!(
+            "AUTHENTICATE {}",
+            client_hash.to_lower_hex_string()
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sends a command and reads its reply, turning anything other than `250` into
+    /// [TorError::Refused]
+    async fn send_command(&mut self, command: &str) -> Result<Reply, TorError> {
+        self.stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+
+        let reply = self.read_reply().await?;
+        if reply.code != 250 {
+            return Err(TorError::Refused {
+                // Only the command, so we never log a password or a private key
+                command: command.split(' ').next().unwrap_or_default().to_string(),
+                code: reply.code,
+                message: reply.lines.join(" "),
+            });
+        }
+
+        Ok(reply)
+    }
+
+    /// Reads a reply, made of `<code>-<text>` lines and a final `<code> <text>` line.
+    ///
+    /// Lines like `<code>+<text>` are followed by a data block ending in a single `.`, which we
+    /// don't need and skip.
+    async fn read_reply(&mut self) -> Result<Reply, TorError> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            let (Some(code), Some(separator)) = (line.get(..3), line.get(3..4)) else {
+                return Err(TorError::InvalidReply(line));
+            };
+
+            let code = code
+                .parse()
+                .map_err(|_| TorError::InvalidReply(line.clone()))?;
+
+            lines.push(line[4..].to_string());
+            match separator {
+                " " => return Ok(Reply { code, lines }),
+                "-" => {}
+                "+" => while self.read_line().await? != "." {},
+                _ => return Err(TorError::InvalidReply(line)),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, TorError> {
+        let mut line = String::new();
+        let read = (&mut self.stream)
+            .take(MAX_LINE_SIZE)
+            .read_line(&mut line)
+            .await?;
+
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        if !line.ends_with('\n') {
+            return Err(TorError::InvalidReply("line too long".into()));
+        }
+
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+#[derive(Debug)]
+/// One of our onion services, it stays up as long as this is alive
+pub struct OnionService {
+    control: TorControl,
+    service_id: String,
+    public_key: [u8; 32],
+}
+
+impl OnionService {
+    /// Connects to Tor and publishes an onion service for these ports.
+    ///
+    /// If `key_file` exists, we use the private key in it, so our onion address stays the same.
+    /// Otherwise, Tor creates a new key, which we save there.
+    pub async fn publish(
+        control_address: SocketAddr,
+        password: Option<&str>,
+        key_file: &Path,
+        ports: &[OnionPort],
+    ) -> Result<OnionService, TorError> {
+        let mut control = TorControl::connect(control_address, password).await?;
+
+        let saved_key = fs::read_to_string(key_file)
+            .ok()
+            .map(|key| key.trim().to_string());
+
+        let (service_id, new_key) = control.add_onion(saved_key.as_deref(), ports).await?;
+        if let Some(key) = new_key {
+            fs::write(key_file, key)?;
+            info!(
+                "Saved the private key for our onion service to {}",
+                key_file.display()
+            );
+        }
+
+        let public_key = service_id_to_public_key(&service_id)?;
+        Ok(OnionService {
+            control,
+            service_id,
+            public_key,
+        })
+    }
+
+    /// Our onion address, e.g. `<service id>.onion`
+    pub fn hostname(&self) -> String {
+        format!("{}.onion", self.service_id)
+    }
+
+    /// Our onion address, as it goes in `addrv2` messages
+    pub fn address(&self) -> AddrV2 {
+        AddrV2::TorV3(self.public_key)
+    }
+
+    /// Waits until our connection to Tor is lost, and our onion service with it
+    pub async fn closed(self) {
+        self.control.closed().await
+    }
+}
+
+/// The default location of our onion service's private key, inside `datadir`
+pub fn onion_key_file(datadir: &str) -> PathBuf {
+    PathBuf::from(format!("{datadir}/{ONION_PRIVATE_KEY_FILE}"))
+}
+
+fn read_cookie(path: &str) -> Result<Vec<u8>, TorError> {
+    let cookie = fs::read(path)?;
+    if cookie.len() != COOKIE_SIZE {
+        return Err(TorError::InvalidReply(format!(
+            "cookie file {path} should have {COOKIE_SIZE} bytes"
+        )));
+    }
+
+    Ok(cookie)
+}
+
+fn safecookie_hmac(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(message);
+
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+/// A v3 service id is the base32 of our public key, a two bytes checksum and the version.
+///
+/// Checking the checksum needs SHA3, which we don't have. This came from Tor, so we only check
+/// the length and version.
+fn service_id_to_public_key(service_id: &str) -> Result<[u8; 32], TorError> {
+    let invalid = || TorError::InvalidServiceId(service_id.to_string());
+
+    let decoded = base32_decode(service_id).ok_or_else(invalid)?;
+    if decoded.len() != 35 || decoded[34] != ONION_V3_VERSION {
+        return Err(invalid());
+    }
+
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(&decoded[..32]);
+
+    Ok(public_key)
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 5 / 8);
+    let mut acc = 0_u32;
+    let mut bits = 0;
+
+    for c in data.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&x| x == c.to_ascii_lowercase())? as u32;
+
+        acc = (acc << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+/// Parses `KEY=VALUE KEY="QUOTED \"VALUE\"" ...` into its fields
+fn parse_fields(text: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().chain([' ']);
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => token.extend(chars.next()),
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if let Some((key, value)) = token.split_once('=') {
+                    fields.insert(key.to_string(), value.to_string());
+                }
+
+                token.clear();
+            }
+            c => token.push(c),
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use bitcoin::hex::DisplayHex;
+    use bitcoin::hex::FromHex;
+    use bitcoin::p2p::address::AddrV2;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+    use tokio::net::TcpListener;
+
+    use super::parse_fields;
+    use super::safecookie_hmac;
+    use super::service_id_to_public_key;
+    use super::OnionPort;
+    use super::OnionService;
+    use super::TorControl;
+    use super::TorError;
+    use super::BASE32_ALPHABET;
+    use super::SAFECOOKIE_CLIENT_KEY;
+    use super::SAFECOOKIE_SERVER_KEY;
+
+    /// How our mock Tor wants us to authenticate
+    #[derive(Clone)]
+    enum MockAuth {
+        Password(&'static str),
+        SafeCookie(PathBuf, [u8; 32]),
+    }
+
+    fn base32_encode(data: &[u8]) -> String {
+        let mut out = String::new();
+        let mut acc = 0_u32;
+        let mut bits = 0;
+
+        for &byte in data {
+            acc = (acc << 8) | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                out.push(BASE32_ALPHABET[((acc >> bits) & 31) as usize] as char);
+            }
+            acc &= (1 << bits) - 1;
+        }
+
+        if bits > 0 {
+            out.push(BASE32_ALPHABET[((acc << (5 - bits)) & 31) as usize] as char);
+        }
+
+        out
+    }
+
+    /// The service id for this key, with a dummy checksum
+    fn service_id(key: &[u8; 32]) -> String {
+        base32_encode(&[key.as_slice(), &[0, 0, 3]].concat())
+    }
+
+    /// A Tor control port that accepts a single controller, and logs its `ADD_ONION` commands
+    async fn mock_tor(auth: MockAuth) -> (SocketAddr, tokio::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut authenticated = false;
+            let mut server_nonce = [0; 32];
+            let mut client_nonce = Vec::new();
+
+            let mut line = String::new();
+            while let Ok(1..) = stream.read_line(&mut line).await {
+                let command = line.trim_end().to_string();
+                line.clear();
+
+                let (verb, args) = command.split_once(' ').unwrap_or((&command, ""));
+                let reply = match (verb, &auth) {
+                    ("PROTOCOLINFO", MockAuth::Password(_)) => {
+                        "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250 OK".to_string()
+                    }
+                    ("PROTOCOLINFO", MockAuth::SafeCookie(path, _)) => format!(
+                        "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"{}\"\r\n250-VERSION Tor=\"0.4.8.10\"\r\n250 OK",
+                        path.display()
+                    ),
+                    ("AUTHCHALLENGE", MockAuth::SafeCookie(_, cookie)) => {
+                        client_nonce = Vec::from_hex(args.split(' ').nth(1).unwrap()).unwrap();
+                        server_nonce = rand::random();
+                        let message = [cookie.as_slice(), &client_nonce, &server_nonce].concat();
+                        format!(
+                            "250 AUTHCHALLENGE SERVERHASH={} SERVERNONCE={}",
+                            safecookie_hmac(SAFECOOKIE_SERVER_KEY, &message).to_lower_hex_string(),
+                            server_nonce.to_lower_hex_string()
+                        )
+                    }
+                    ("AUTHENTICATE", auth) => {
+                        let expected = match auth {
+                            MockAuth::Password(password) => {
+                                format!("\"{}\"", password.replace('"', "\\\""))
+                            }
+                            MockAuth::SafeCookie(_, cookie) => {
+                                let message =
+                                    [cookie.as_slice(), &client_nonce, &server_nonce].concat();
+                                safecookie_hmac(SAFECOOKIE_CLIENT_KEY, &message)
+                                    .to_lower_hex_string()
+                            }
+                        };
+
+                        authenticated = args == expected;
+                        match authenticated {
+                            true => "250 OK".to_string(),
+                            false => "515 Authentication failed".to_string(),
+                        }
+                    }
+                    ("ADD_ONION", _) if authenticated => {
+                        tx.send(command.clone()).await.unwrap();
+                        let (key, _) = args.split_once(' ').unwrap();
+                        let public_key = [7; 32];
+
+                        match key {
+                            "NEW:ED25519-V3" => format!(
+                                "250-ServiceID={}\r\n250-PrivateKey=ED25519-V3:c2VjcmV0\r\n250 OK",
+                                service_id(&public_key)
+                            ),
+                            _ => format!("250-ServiceID={}\r\n250 OK", service_id(&public_key)),
+                        }
+                    }
+                    _ => "514 Authentication required".to_string(),
+                };
+
+                stream
+                    .write_all(format!("{reply}\r\n").as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (address, rx)
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let fields = parse_fields(
+            r#"METHODS=COOKIE,SAFECOOKIE COOKIEFILE="/var/run/tor/control \"auth\" cookie""#,
+        );
+        assert_eq!(fields["METHODS"], "COOKIE,SAFECOOKIE");
+        assert_eq!(
+            fields["COOKIEFILE"],
+            r#"/var/run/tor/control "auth" cookie"#
+        );
+    }
+
+    #[test]
+    fn test_service_id() {
+        let id = service_id(&[42; 32]);
+        assert_eq!(id.len(), 56);
+        assert_eq!(service_id_to_public_key(&id).unwrap(), [42; 32]);
+        assert_eq!(
+            service_id_to_public_key(&id.to_uppercase()).unwrap(),
+            [42; 32]
+        );
+
+        // A v2 address, which is too short
+        assert!(service_id_to_public_key("expyuzz4wqqyqhjn").is_err());
+        assert!(service_id_to_public_key(&format!("{}1", &id[1..])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_password() {
+        let (address, _rx) = mock_tor(MockAuth::Password("hunter\"2")).await;
+        assert!(TorControl::connect(address, Some("hunter\"2"))
+            .await
+            .is_ok());
+
+        let (address, _rx) = mock_tor(MockAuth::Password("hunter2")).await;
+        let err = TorControl::connect(address, None).await.unwrap_err();
+        assert!(matches!(err, TorError::NoAuthMethod(_)), "{err}");
+
+        let (address, _rx) = mock_tor(MockAuth::Password("hunter2")).await;
+        let err = TorControl::connect(address, Some("wrong"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TorError::Refused { code: 515, .. }), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let datadir = format!("./tmp-db/{}.tor", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let cookie: [u8; 32] = rand::random();
+        let cookie_file = PathBuf::from(format!("{datadir}/control_auth_cookie"));
+        std::fs::write(&cookie_file, cookie).unwrap();
+
+        let key_file = super::onion_key_file(&datadir);
+        let ports = [
+            OnionPort {
+                virtual_port: 50001,
+                target: "127.0.0.1:50001".parse().unwrap(),
+            },
+            OnionPort {
+                virtual_port: 8332,
+                target: "127.0.0.1:8332".parse().unwrap(),
+            },
+        ];
+
+        // The first time, Tor creates a key for us, and we save it
+        let (address, mut commands) =
+            mock_tor(MockAuth::SafeCookie(cookie_file.clone(), cookie)).await;
+        let service = OnionService::publish(address, None, &key_file, &ports)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            "ADD_ONION NEW:ED25519-V3 Port=50001,127.0.0.1:50001 Port=8332,127.0.0.1:8332"
+        );
+        assert_eq!(
+            service.hostname(),
+            format!("{}.onion", service_id(&[7; 32]))
+        );
+        assert_eq!(service.address(), AddrV2::TorV3([7; 32]));
+        assert_eq!(
+            std::fs::read_to_string(&key_file).unwrap(),
+            "ED25519-V3:c2VjcmV0"
+        );
+
+        // Then, we reuse it
+        let (address, mut commands) = mock_tor(MockAuth::SafeCookie(cookie_file, cookie)).await;
+        OnionService::publish(address, None, &key_file, &ports[..1])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            "ADD_ONION ED25519-V3:c2VjcmV0 Port=50001,127.0.0.1:50001"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrong_cookie() {
+        let datadir = format!("./tmp-db/{}.tor", rand::random::<u32>());
+        std::fs::create_dir_all(&datadir).unwrap();
+
+        let cookie_file = PathBuf::from(format!("{datadir}/control_auth_cookie"));
+        std::fs::write(&cookie_file, [1; 32]).unwrap();
+
+        // This Tor has a different cookie, so it can't prove it knows ours
+        let (address, _rx) = mock_tor(MockAuth::SafeCookie(cookie_file, [2; 32])).await;
+        let err = TorControl::connect(address, None).await.unwrap_err();
+        assert!(matches!(err, TorError::InvalidServerHash), "{err}");
+    }
+}
@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Traffic accounting and upload limits.
+//!
+//! Every peer counts the bytes it sends and receives, split by message command, in a
+//! [TrafficCounter]. Those counters also feed a [NetTraffic], shared by all peers, which keeps
+//! the node's totals.
+//!
+//! [NetTraffic] may also enforce an upload target: a number of bytes we are willing to send
+//! every [UPLOAD_TARGET_TIMEFRAME]. This is useful for nodes on metered connections. Once we get
+//! close to the target, we stop answering bandwidth-heavy requests, and once we reach it, we
+//! also stop serving mempool transactions. Block and header messages are never limited, so we
+//! keep following (and relaying) the chain. Manual peers are exempt from the target.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// How long each upload target cycle lasts, same as Bitcoin Core
+pub const UPLOAD_TARGET_TIMEFRAME: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Which fraction of the upload target we may use before we stop serving bandwidth-heavy
+/// requests, in percent. The rest is kept for relaying new data.
+const UPLOAD_TARGET_HISTORICAL_PERCENT: u64 = 90;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+/// How many bytes we exchanged with a peer
+pub struct PeerTraffic {
+    /// How many bytes we sent to this peer
+    pub bytes_sent: u64,
+
+    /// How many bytes we received from this peer
+    pub bytes_recv: u64,
+
+    /// How many bytes we sent to this peer, for each message command
+    pub bytes_sent_per_msg: BTreeMap<String, u64>,
+
+    /// How many bytes we received from this peer, for each message command
+    pub bytes_recv_per_msg: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// The state of our upload target, for the current cycle
+pub struct UploadTarget {
+    /// How long each cycle lasts, in seconds
+    pub timeframe: u64,
+
+    /// How many bytes we may send in each cycle, or zero if there's no target
+    pub target: u64,
+
+    /// Whether we sent all bytes allowed in this cycle
+    pub target_reached: bool,
+
+    /// Whether we still serve bandwidth-heavy requests in this cycle
+    pub serve_historical_blocks: bool,
+
+    /// How many bytes we may still send in this cycle
+    pub bytes_left_in_cycle: u64,
+
+    /// How many seconds until the next cycle starts
+    pub time_left_in_cycle: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// How many bytes we exchanged with all our peers, since we started
+pub struct NetTotals {
+    /// How many bytes we received
+    pub total_bytes_recv: u64,
+
+    /// How many bytes we sent
+    pub total_bytes_sent: u64,
+
+    /// The state of our upload target
+    pub upload_target: UploadTarget,
+}
+
+#[derive(Debug)]
+struct NetTrafficInner {
+    total_bytes_sent: u64,
+    total_bytes_recv: u64,
+    cycle_start: Instant,
+    cycle_bytes_sent: u64,
+}
+
+#[derive(Debug)]
+/// Traffic counters for the whole node, and our upload target
+pub struct NetTraffic {
+    /// How many bytes we may send every [UPLOAD_TARGET_TIMEFRAME], if limited
+    upload_target: Option<u64>,
+    inner: Mutex<NetTrafficInner>,
+}
+
+impl NetTraffic {
+    /// Creates new counters, optionally limiting how many bytes we send every
+    /// [UPLOAD_TARGET_TIMEFRAME]
+    pub fn new(upload_target: Option<u64>) -> Self {
+        NetTraffic {
+            upload_target,
+            inner: Mutex::new(NetTrafficInner {
+                total_bytes_sent: 0,
+                total_bytes_recv: 0,
+                cycle_start: Instant::now(),
+                cycle_bytes_sent: 0,
+            }),
+        }
+    }
+
+    /// Records bytes we sent to one of our peers
+    pub fn record_sent(&self, bytes: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        Self::maybe_start_cycle(&mut inner);
+
+        inner.total_bytes_sent += bytes;
+        inner.cycle_bytes_sent += bytes;
+    }
+
+    /// Records bytes we received from one of our peers
+    pub fn record_recv(&self, bytes: u64) {
+        self.inner.lock().unwrap().total_bytes_recv += bytes;
+    }
+
+    /// Whether we sent all bytes allowed in this cycle. If so, we should only send what's needed
+    /// to keep up with the chain.
+    pub fn target_reached(&self) -> bool {
+        self.sent_in_cycle()
+            .is_some_and(|(sent, target)| sent >= target)
+    }
+
+    /// Whether we should still serve bandwidth-heavy requests in this cycle
+    pub fn serve_historical_data(&self) -> bool {
+        match self.sent_in_cycle() {
+            Some((sent, target)) => sent < target / 100 * UPLOAD_TARGET_HISTORICAL_PERCENT,
+            None => true,
+        }
+    }
+
+    /// Returns our totals, and the state of our upload target
+    pub fn totals(&self) -> NetTotals {
+        let mut inner = self.inner.lock().unwrap();
+        Self::maybe_start_cycle(&mut inner);
+
+        let target = self.upload_target.unwrap_or(0);
+        let (bytes_left_in_cycle, time_left_in_cycle) = match self.upload_target {
+            Some(target) => (
+                target.saturating_sub(inner.cycle_bytes_sent),
+                UPLOAD_TARGET_TIMEFRAME
+                    .saturating_sub(inner.cycle_start.elapsed())
+                    .as_secs(),
+            ),
+            None => (0, 0),
+        };
+
+        let total_bytes_sent = inner.total_bytes_sent;
+        let total_bytes_recv = inner.total_bytes_recv;
+        drop(inner);
+
+        NetTotals {
+            total_bytes_recv,
+            total_bytes_sent,
+            upload_target: UploadTarget {
+                timeframe: UPLOAD_TARGET_TIMEFRAME.as_secs(),
+                target,
+                target_reached: self.target_reached(),
+                serve_historical_blocks: self.serve_historical_data(),
+                bytes_left_in_cycle,
+                time_left_in_cycle,
+            },
+        }
+    }
+
+    /// Returns how many bytes we sent in this cycle and our target, if we have one
+    fn sent_in_cycle(&self) -> Option<(u64, u64)> {
+        let target = self.upload_target?;
+        let mut inner = self.inner.lock().unwrap();
+        Self::maybe_start_cycle(&mut inner);
+
+        Some((inner.cycle_bytes_sent, target))
+    }
+
+    /// Starts a new cycle, if the current one is over
+    fn maybe_start_cycle(inner: &mut NetTrafficInner) {
+        if inner.cycle_start.elapsed() >= UPLOAD_TARGET_TIMEFRAME {
+            inner.cycle_start = Instant::now();
+            inner.cycle_bytes_sent = 0;
+        }
+    }
+}
+
+impl Default for NetTraffic {
+    fn default() -> Self {
+        NetTraffic::new(None)
+    }
+}
+
+#[derive(Debug)]
+/// Counts the traffic of a single peer, and adds it to the node's totals
+pub struct TrafficCounter {
+    peer: Mutex<PeerTraffic>,
+    net: Arc<NetTraffic>,
+}
+
+impl TrafficCounter {
+    pub fn new(net: Arc<NetTraffic>) -> Self {
+        TrafficCounter {
+            peer: Mutex::new(PeerTraffic::default()),
+            net,
+        }
+    }
+
+    /// Records a message we sent to this peer
+    pub fn record_sent(&self, command: &str, bytes: usize) {
+        let bytes = bytes as u64;
+        let mut peer = self.peer.lock().unwrap();
+        peer.bytes_sent += bytes;
+        *peer
+            .bytes_sent_per_msg
+            .entry(command.to_string())
+            .or_default() += bytes;
+
+        drop(peer);
+        self.net.record_sent(bytes);
+    }
+
+    /// Records a message we received from this peer
+    pub fn record_recv(&self, command: &str, bytes: usize) {
+        let bytes = bytes as u64;
+        let mut peer = self.peer.lock().unwrap();
+        peer.bytes_recv += bytes;
+        *peer
+            .bytes_recv_per_msg
+            .entry(command.to_string())
+            .or_default() += bytes;
+
+        drop(peer);
+        self.net.record_recv(bytes);
+    }
+
+    /// Returns this peer's counters
+    pub fn peer_traffic(&self) -> PeerTraffic {
+        self.peer.lock().unwrap().clone()
+    }
+
+    /// The node's counters, shared by all peers
+    pub fn net(&self) -> &NetTraffic {
+        &self.net
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::NetTraffic;
+    use super::TrafficCounter;
+
+    #[test]
+    fn test_peer_traffic() {
+        let net = Arc::new(NetTraffic::default());
+        let first = TrafficCounter::new(net.clone());
+        let second = TrafficCounter::new(net.clone());
+
+        first.record_sent("ping", 32);
+        first.record_sent("ping", 32);
+        first.record_recv("pong", 32);
+        second.record_sent("tx", 250);
+
+        let traffic = first.peer_traffic();
+        assert_eq!(traffic.bytes_sent, 64);
+        assert_eq!(traffic.bytes_recv, 32);
+        assert_eq!(traffic.bytes_sent_per_msg.get("ping"), Some(&64));
+        assert_eq!(traffic.bytes_recv_per_msg.get("pong"), Some(&32));
+        assert_eq!(
+            second.peer_traffic().bytes_sent_per_msg.get("tx"),
+            Some(&250)
+        );
+
+        let totals = net.totals();
+        assert_eq!(totals.total_bytes_sent, 314);
+        assert_eq!(totals.total_bytes_recv, 32);
+
+        // Without a target, we never stop serving anything
+        assert!(!net.target_reached());
+        assert!(net.serve_historical_data());
+        assert_eq!(totals.upload_target.target, 0);
+    }
+
+    #[test]
+    fn test_upload_target() {
+        let net = NetTraffic::new(Some(1_000));
+
+        net.record_sent(800);
+        assert!(net.serve_historical_data());
+        assert!(!net.target_reached());
+
+        // Getting close to the target, we stop serving heavy requests
+        net.record_sent(100);
+        assert!(!net.serve_historical_data());
+        assert!(!net.target_reached());
+
+        net.record_sent(100);
+        assert!(net.target_reached());
+
+        let target = net.totals().upload_target;
+        assert_eq!(target.target, 1_000);
+        assert_eq!(target.bytes_left_in_cycle, 0);
+        assert!(target.target_reached);
+        assert!(!target.serve_historical_blocks);
+        assert!(target.time_left_in_cycle > 0);
+
+        // Received bytes don't count towards the target
+        let net = NetTraffic::new(Some(1_000));
+        net.record_recv(10_000);
+        assert!(!net.target_reached());
+    }
+}
@@ -5,6 +5,7 @@ use core::fmt::Debug;
 use core::fmt::Display;
 use core::fmt::Formatter;
 use std::io;
+use std::time::Duration;
 
 use bip324::futures::Protocol;
 use bip324::futures::ProtocolReader;
@@ -46,11 +47,21 @@ use tokio::net::ToSocketAddrs;
 use tracing::debug;
 use tracing::error;
 
+use super::i2p;
+use super::i2p::I2pError;
+use super::i2p::I2pSession;
 use super::socks::Socks5Addr;
 use super::socks::Socks5Error;
 use super::socks::Socks5StreamBuilder;
 use crate::address_man::LocalAddress;
 
+/// The size of a P2PV1 message header: magic, command, length and checksum
+const V1_HEADER_SIZE: usize = 24;
+
+/// How many bytes a P2PV2 packet adds to its contents: a 3-byte length, a header byte and a
+/// 16-byte authentication tag
+const V2_PACKET_OVERHEAD: usize = 20;
+
 type TcpReadTransport = ReadTransport<BufReader<ReadHalf<TcpStream>>>;
 type TcpWriteTransport = WriteTransport<WriteHalf<TcpStream>>;
 type TransportResult =
@@ -111,6 +122,9 @@ pub enum TransportError {
     /// Proxy error
     Proxy(Socks5Error),
 
+    /// I2P SAM bridge error
+    I2p(I2pError),
+
     /// Message is too big
     OversizedMessage {
         max_size: usize,
@@ -135,6 +149,7 @@ impl Display for TransportError {
             TransportError::SerdeV2(err) => write!(f, "V2 serde error: {err:?}"),
             TransportError::SerdeV1(err) => write!(f, "V1 serde error: {err:?}"),
             TransportError::Proxy(err) => write!(f, "Proxy error: {err:?}"),
+            TransportError::I2p(err) => write!(f, "I2P error: {err}"),
             TransportError::OversizedMessage { max_size, message_size } => write!(f, "Peer sent us an oversized message: size {message_size} is greater than the max of {max_size}"),
             TransportError::BadChecksum { expected, provided } => write!(f, "Peer sent us a corrupted message: expected {expected}, got {provided}"),
             TransportError::BadMagicBits { expected, provided } => {
@@ -149,14 +164,15 @@ impl_error_from!(TransportError, ProtocolError, Protocol);
 impl_error_from!(TransportError, bip324::serde::Error, SerdeV2);
 impl_error_from!(TransportError, encode::Error, SerdeV1);
 impl_error_from!(TransportError, Socks5Error, Proxy);
+impl_error_from!(TransportError, I2pError, I2p);
 
 pub enum ReadTransport<R: AsyncRead + Unpin + Send> {
-    V1(R, Network),
+    V1(R, Magic),
     V2(ProtocolReader<R>),
 }
 
 pub enum WriteTransport<W: AsyncWrite + Unpin + Send + Sync> {
-    V1(W, Network),
+    V1(W, Magic),
     V2(ProtocolWriter<W>),
 }
 
@@ -220,7 +236,7 @@ impl Encodable for V1MessageHeader {
 /// # Arguments
 ///
 /// * `address` - The address of a target node
-/// * `network` - The bitcoin network
+/// * `magic` - The magic bytes of the network we are on
 /// * `allow_v1_fallback` - Whether to allow fallback to V1 protocol if V2 negotiation fails
 ///
 /// # Returns
@@ -232,15 +248,15 @@ impl Encodable for V1MessageHeader {
 /// Returns a `TransportError` if the connection cannot be established or protocol negotiation fails.
 pub async fn connect<A: ToSocketAddrs>(
     address: A,
-    network: Network,
+    magic: Magic,
     allow_v1_fallback: bool,
 ) -> TransportResult {
-    match try_connection(&address, network, false).await {
+    match try_connection(&address, magic, false).await {
         Ok(transport) => Ok(transport),
         Err(TransportError::Protocol(ProtocolError::Io(_, ProtocolFailureSuggestion::RetryV1)))
             if allow_v1_fallback =>
         {
-            try_connection(&address, network, true).await
+            try_connection(&address, magic, true).await
         }
         Err(e) => Err(e),
     }
@@ -248,7 +264,7 @@ pub async fn connect<A: ToSocketAddrs>(
 
 async fn try_connection<A: ToSocketAddrs>(
     address: &A,
-    network: Network,
+    magic: Magic,
     force_v1: bool,
 ) -> TransportResult {
     let tcp_stream = TcpStream::connect(address).await?;
@@ -264,30 +280,32 @@ async fn try_connection<A: ToSocketAddrs>(
     let (reader, writer) = tokio::io::split(tcp_stream);
     let reader = BufReader::new(reader);
 
-    match force_v1 {
-        true => {
+    match v2_network(magic, force_v1) {
+        None => {
             debug!("Established a P2PV1 connection with peer={peer_addr}");
             Ok((
-                ReadTransport::V1(reader, network),
-                WriteTransport::V1(writer, network),
+                ReadTransport::V1(reader, magic),
+                WriteTransport::V1(writer, magic),
                 TransportProtocol::V1,
             ))
         }
-        false => match Protocol::new(network, Role::Initiator, None, None, reader, writer).await {
-            Ok(protocol) => {
-                debug!("Established a P2PV2 connection with peer={peer_addr}");
-                let (reader_protocol, writer_protocol) = protocol.into_split();
-                Ok((
-                    ReadTransport::V2(reader_protocol),
-                    WriteTransport::V2(writer_protocol),
-                    TransportProtocol::V2,
-                ))
-            }
-            Err(e) => {
-                debug!("Failed to establish a P2PV2 connection with peer={peer_addr}: {e:?}");
-                Err(TransportError::Protocol(e))
+        Some(network) => {
+            match Protocol::new(network, Role::Initiator, None, None, reader, writer).await {
+                Ok(protocol) => {
+                    debug!("Established a P2PV2 connection with peer={peer_addr}");
+                    let (reader_protocol, writer_protocol) = protocol.into_split();
+                    Ok((
+                        ReadTransport::V2(reader_protocol),
+                        WriteTransport::V2(writer_protocol),
+                        TransportProtocol::V2,
+                    ))
+                }
+                Err(e) => {
+                    debug!("Failed to establish a P2PV2 connection with peer={peer_addr}: {e:?}");
+                    Err(TransportError::Protocol(e))
+                }
             }
-        },
+        }
     }
 }
 
@@ -302,7 +320,7 @@ async fn try_connection<A: ToSocketAddrs>(
 /// * `proxy_addr` - The address of the SOCKS5 proxy
 /// * `address` - The target address to connect to through the proxy
 /// * `port` - The port to connect to on the target
-/// * `network` - The bitcoin network
+/// * `magic` - The magic bytes of the network we are on
 /// * `allow_v1_fallback` - Whether to allow fallback to V1 protocol if V2 negotiation fails
 ///
 /// # Returns
@@ -316,7 +334,7 @@ async fn try_connection<A: ToSocketAddrs>(
 pub async fn connect_proxy<A: ToSocketAddrs + Clone + Debug>(
     proxy_addr: A,
     address: LocalAddress,
-    network: Network,
+    magic: Magic,
     allow_v1_fallback: bool,
 ) -> TransportResult {
     let addr = match address.get_addrv2() {
@@ -331,12 +349,12 @@ pub async fn connect_proxy<A: ToSocketAddrs + Clone + Debug>(
         }
     };
 
-    match try_proxy_connection(&proxy_addr, &addr, address.get_port(), network, false).await {
+    match try_proxy_connection(&proxy_addr, &addr, address.get_port(), magic, false).await {
         Ok(transport) => Ok(transport),
         Err(TransportError::Protocol(ProtocolError::Io(_, ProtocolFailureSuggestion::RetryV1)))
             if allow_v1_fallback =>
         {
-            try_proxy_connection(&proxy_addr, &addr, address.get_port(), network, true).await
+            try_proxy_connection(&proxy_addr, &addr, address.get_port(), magic, true).await
         }
         Err(e) => Err(e),
     }
@@ -346,25 +364,95 @@ async fn try_proxy_connection<A: ToSocketAddrs + Clone + Debug>(
     proxy_addr: A,
     target_addr: &Socks5Addr,
     port: u16,
-    network: Network,
+    magic: Magic,
     force_v1: bool,
 ) -> TransportResult {
     let proxy = TcpStream::connect(proxy_addr.clone()).await?;
     let stream = Socks5StreamBuilder::connect(proxy, target_addr, port).await?;
     let (reader, writer) = tokio::io::split(stream);
     let reader = BufReader::new(reader);
-    match force_v1 {
-        true => {
+    match v2_network(magic, force_v1) {
+        None => {
             debug!("Established a P2PV1 connection over SOCKS5 using proxy={proxy_addr:?} with peer={target_addr:?}");
             Ok((
-                ReadTransport::V1(reader, network),
-                WriteTransport::V1(writer, network),
+                ReadTransport::V1(reader, magic),
+                WriteTransport::V1(writer, magic),
                 TransportProtocol::V1,
             ))
         }
-        false => match Protocol::new(network, Role::Initiator, None, None, reader, writer).await {
+        Some(network) => {
+            match Protocol::new(network, Role::Initiator, None, None, reader, writer).await {
+                Ok(protocol) => {
+                    debug!("Established a P2PV2 connection over SOCKS5 using proxy={proxy_addr:?} with peer={target_addr:?}");
+                    let (reader_protocol, writer_protocol) = protocol.into_split();
+                    Ok((
+                        ReadTransport::V2(reader_protocol),
+                        WriteTransport::V2(writer_protocol),
+                        TransportProtocol::V2,
+                    ))
+                }
+                Err(e) => {
+                    error!("Failed to establish a P2PV2 connection over SOCKS5 using proxy={proxy_addr:?} with peer={target_addr:?}: {e:?}");
+                    Err(TransportError::Protocol(e))
+                }
+            }
+        }
+    }
+}
+
+/// Opens a stream to an I2P peer through our SAM session, and negotiates the bitcoin protocol.
+///
+/// Like `connect`, it first tries the V2 protocol and can fall back to V1 if needed and allowed.
+///
+/// # Errors
+///
+/// Returns a `TransportError` if the address isn't an I2P one, the SAM bridge can't reach the
+/// peer, or protocol negotiation fails.
+pub async fn connect_i2p(
+    session: &I2pSession,
+    address: LocalAddress,
+    magic: Magic,
+    allow_v1_fallback: bool,
+) -> TransportResult {
+    let AddrV2::I2p(i2p_address) = address.get_addrv2() else {
+        return Err(TransportError::I2p(I2pError::InvalidDestination));
+    };
+
+    match try_i2p_connection(session, &i2p_address, magic, false).await {
+        Ok(transport) => Ok(transport),
+        Err(TransportError::Protocol(ProtocolError::Io(_, ProtocolFailureSuggestion::RetryV1)))
+            if allow_v1_fallback =>
+        {
+            try_i2p_connection(session, &i2p_address, magic, true).await
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn try_i2p_connection(
+    session: &I2pSession,
+    address: &[u8; 32],
+    magic: Magic,
+    force_v1: bool,
+) -> TransportResult {
+    let stream = session.connect(address).await?;
+    let peer = i2p::b32_name(address);
+    let (reader, writer) = tokio::io::split(stream);
+    let reader = BufReader::new(reader);
+    match v2_network(magic, force_v1) {
+        None => {
+            debug!("Established a P2PV1 connection over I2P with peer={peer}");
+            Ok((
+                ReadTransport::V1(reader, magic),
+                WriteTransport::V1(writer, magic),
+                TransportProtocol::V1,
+            ))
+        }
+        Some(network) => match Protocol::new(network, Role::Initiator, None, None, reader, writer)
+            .await
+        {
             Ok(protocol) => {
-                debug!("Established a P2PV2 connection over SOCKS5 using proxy={proxy_addr:?} with peer={target_addr:?}");
+                debug!("Established a P2PV2 connection over I2P with peer={peer}");
                 let (reader_protocol, writer_protocol) = protocol.into_split();
                 Ok((
                     ReadTransport::V2(reader_protocol),
@@ -373,23 +461,97 @@ async fn try_proxy_connection<A: ToSocketAddrs + Clone + Debug>(
                 ))
             }
             Err(e) => {
-                error!("Failed to establish a P2PV2 connection over SOCKS5 using proxy={proxy_addr:?} with peer={target_addr:?}: {e:?}");
+                debug!("Failed to establish a P2PV2 connection over I2P with peer={peer}: {e:?}");
                 Err(TransportError::Protocol(e))
             }
         },
     }
 }
 
+/// Negotiates the bitcoin protocol with a peer that connected to us.
+///
+/// V1 peers start by sending their `version`, so the stream starts with our network magic.
+/// Anything else is taken as the start of a V2 handshake, where we act as the responder.
+///
+/// # Errors
+///
+/// Returns a `TransportError` if our peer closes the stream before saying anything, or protocol
+/// negotiation fails.
+pub async fn accept(tcp_stream: TcpStream, magic: Magic) -> TransportResult {
+    tcp_stream.set_nodelay(false)?;
+
+    let peer_addr = match tcp_stream.peer_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => String::from("unknown peer"),
+    };
+
+    // Look at the first bytes without consuming them, so the V1 reader still gets the header
+    let mut first_bytes = [0; 4];
+    loop {
+        match tcp_stream.peek(&mut first_bytes).await? {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            n if n == first_bytes.len() => break,
+            // Wait for the rest of them to arrive
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+
+    let (reader, writer) = tokio::io::split(tcp_stream);
+    let reader = BufReader::new(reader);
+    let is_v1 = first_bytes == magic.to_bytes();
+
+    match v2_network(magic, is_v1) {
+        None => {
+            debug!("Accepted a P2PV1 connection from peer={peer_addr}");
+            Ok((
+                ReadTransport::V1(reader, magic),
+                WriteTransport::V1(writer, magic),
+                TransportProtocol::V1,
+            ))
+        }
+        Some(network) => {
+            match Protocol::new(network, Role::Responder, None, None, reader, writer).await {
+                Ok(protocol) => {
+                    debug!("Accepted a P2PV2 connection from peer={peer_addr}");
+                    let (reader_protocol, writer_protocol) = protocol.into_split();
+                    Ok((
+                        ReadTransport::V2(reader_protocol),
+                        WriteTransport::V2(writer_protocol),
+                        TransportProtocol::V2,
+                    ))
+                }
+                Err(e) => {
+                    debug!("Failed to accept a P2PV2 connection from peer={peer_addr}: {e:?}");
+                    Err(TransportError::Protocol(e))
+                }
+            }
+        }
+    }
+}
+
+/// Returns the network to use for a V2 connection, or `None` if we should use V1. BIP-324 derives
+/// its keys from the network, so networks it doesn't know about (like custom signets) can only
+/// use V1.
+fn v2_network(magic: Magic, force_v1: bool) -> Option<Network> {
+    match force_v1 {
+        true => None,
+        false => Network::from_magic(magic),
+    }
+}
+
 impl<R> ReadTransport<R>
 where
     R: AsyncRead + Unpin + Send,
 {
     /// Read the next [`NetworkMessage`] from the transport's [`ProtocolReader`] buffer.
-    pub async fn read_message(&mut self) -> Result<NetworkMessage, TransportError> {
+    ///
+    /// Along with the message, returns how many bytes it took on the wire.
+    pub async fn read_message(&mut self) -> Result<(NetworkMessage, usize), TransportError> {
         match self {
             ReadTransport::V2(protocol) => {
                 let payload = protocol.read().await?;
                 let contents = payload.contents();
+                let size = contents.len() + V2_PACKET_OVERHEAD;
 
                 // TODO: remove this once https://github.com/rust-bitcoin/rust-bitcoin/pull/5671
                 // and https://github.com/rust-bitcoin/rust-bitcoin/pull/5009 make it into a release
@@ -401,14 +563,14 @@ where
                             .expect("`uproof` is a valid command string"),
                         payload: contents[1..].to_vec(),
                     };
-                    return Ok(msg);
+                    return Ok((msg, size));
                 }
 
                 let msg = deserialize_v2(contents)?;
-                Ok(msg)
+                Ok((msg, size))
             }
-            ReadTransport::V1(reader, network) => {
-                let mut data: Vec<u8> = vec![0; 24];
+            ReadTransport::V1(reader, magic) => {
+                let mut data: Vec<u8> = vec![0; V1_HEADER_SIZE];
                 reader.read_exact(&mut data).await?;
 
                 let header: V1MessageHeader = deserialize_partial(&data)?.0;
@@ -419,17 +581,17 @@ where
                     });
                 }
 
-                if header.magic != network.magic() {
+                if header.magic != *magic {
                     return Err(TransportError::BadMagicBits {
                         provided: header.magic,
-                        expected: network.magic(),
+                        expected: *magic,
                     });
                 }
 
-                data.resize(24 + header.length as usize, 0);
-                reader.read_exact(&mut data[24..]).await?;
+                data.resize(V1_HEADER_SIZE + header.length as usize, 0);
+                reader.read_exact(&mut data[V1_HEADER_SIZE..]).await?;
 
-                let checksum = P2PV1MessageChecksum::from_payload(&data[24..]);
+                let checksum = P2PV1MessageChecksum::from_payload(&data[V1_HEADER_SIZE..]);
                 if header.checksum != checksum {
                     return Err(TransportError::BadChecksum {
                         expected: checksum,
@@ -437,8 +599,9 @@ where
                     });
                 }
 
+                let size = data.len();
                 let msg: RawNetworkMessage = deserialize(&data)?;
-                Ok(msg.into_payload())
+                Ok((msg.into_payload(), size))
             }
         }
     }
@@ -449,7 +612,12 @@ where
     W: AsyncWrite + Unpin + Send + Sync,
 {
     /// Write a [`NetworkMessage`] to the transport's [`ProtocolWriter`] buffer.
-    pub async fn write_message(&mut self, message: NetworkMessage) -> Result<(), TransportError> {
+    ///
+    /// Returns how many bytes the message took on the wire.
+    pub async fn write_message(
+        &mut self,
+        message: NetworkMessage,
+    ) -> Result<usize, TransportError> {
         match self {
             WriteTransport::V2(protocol) => {
                 // TODO: remove this once https://github.com/rust-bitcoin/rust-bitcoin/pull/5671 and
@@ -458,56 +626,59 @@ where
                     /// P2PV2 BIP-0324 message type for `getuproof`.
                     const P2PV2_GETUPROOF_MSG_TYPE: u8 = 30;
 
-                    let expected_cmd = CommandString::try_from_static("getuproof")
+                    let getuproof_cmd = CommandString::try_from_static("getuproof")
                         .expect("`getuproof` is a valid command string");
-                    assert_eq!(
-                        command, expected_cmd,
-                        "getuproof is supported as unknown message"
-                    );
 
+                    // Messages without a short id are sent as a zero byte, followed by the
+                    // 12-byte command
                     let mut data = vec![];
-                    data.push(P2PV2_GETUPROOF_MSG_TYPE);
+                    if command == getuproof_cmd {
+                        data.push(P2PV2_GETUPROOF_MSG_TYPE);
+                    } else {
+                        data.push(0);
+                        data.extend(serialize(&command));
+                    }
+
                     data.extend(payload);
+                    let size = data.len() + V2_PACKET_OVERHEAD;
                     protocol.write(&Payload::genuine(data)).await?;
 
-                    return Ok(());
+                    return Ok(size);
                 }
 
                 let data = serialize_v2(message);
+                let size = data.len() + V2_PACKET_OVERHEAD;
                 protocol.write(&Payload::genuine(data)).await?;
+
+                Ok(size)
             }
-            WriteTransport::V1(writer, network) => {
+            WriteTransport::V1(writer, magic) => {
                 if let NetworkMessage::Unknown { payload, command } = message {
-                    let expected_cmd = CommandString::try_from_static("getuproof").unwrap();
-                    assert_eq!(
-                        command, expected_cmd,
-                        "Only getuproof is supported as unknown message"
-                    );
-
                     // FIXME: This little bit of ugliness is due to https://github.com/rust-bitcoin/rust-bitcoin/issues/4413
                     // Once that is solved upstream (or utreexo messages are added to
                     // rust-bitcoin), this can be removed.
                     let checksum = P2PV1MessageChecksum::from_payload(&payload);
 
-                    let mut message_header = [0u8; 24];
-                    message_header[0..4].copy_from_slice(&network.magic().to_bytes());
-                    message_header[4..13].copy_from_slice("getuproof".as_bytes());
+                    let mut message_header = [0u8; V1_HEADER_SIZE];
+                    message_header[0..4].copy_from_slice(&magic.to_bytes());
+                    message_header[4..16].copy_from_slice(&serialize(&command));
                     message_header[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
                     message_header[20..24].copy_from_slice(checksum.as_ref());
 
                     writer.write_all(&message_header).await?;
                     writer.write_all(&payload).await?;
                     writer.flush().await?;
-                    return Ok(());
+                    return Ok(V1_HEADER_SIZE + payload.len());
                 }
 
-                let data = &mut RawNetworkMessage::new(network.magic(), message);
+                let data = &mut RawNetworkMessage::new(*magic, message);
                 let data = serialize(&data);
                 writer.write_all(&data).await?;
                 writer.flush().await?;
+
+                Ok(data.len())
             }
         }
-        Ok(())
     }
 
     /// Shutdown the transport.
@@ -585,7 +756,7 @@ pub(crate) mod test_transport {
     }
 
     pub fn create_reader_v1(data: Vec<u8>) -> ReadTransport<Reader> {
-        ReadTransport::V1(Reader { data }, Network::Regtest)
+        ReadTransport::V1(Reader { data }, Network::Regtest.magic())
     }
 
     pub struct Writer;
@@ -632,10 +803,16 @@ mod tests {
     use bitcoin::consensus::serialize;
     use bitcoin::p2p::message::NetworkMessage;
     use bitcoin::p2p::message::RawNetworkMessage;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
 
     use super::test_transport::*;
+    use crate::p2p_wire::transport::accept;
+    use crate::p2p_wire::transport::connect;
     use crate::p2p_wire::transport::P2PV1MessageChecksum;
     use crate::p2p_wire::transport::TransportError;
+    use crate::p2p_wire::transport::TransportProtocol;
     use crate::p2p_wire::transport::V1MessageHeader;
 
     #[tokio::test]
@@ -712,11 +889,64 @@ mod tests {
         // make the size look one byte bigger than the actual message is, this will cause an EOF
         let mut transport_reader = create_reader_v1(data);
 
-        let res = transport_reader
+        let (res, size) = transport_reader
             .read_message()
             .await
             .expect("Message should be a valid ping");
 
         assert_eq!(res, NetworkMessage::Ping(0));
+        // A 24-byte header, followed by the 8-byte nonce
+        assert_eq!(size, 32);
+    }
+
+    #[tokio::test]
+    async fn test_accept_v1() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let message = RawNetworkMessage::new(Network::Regtest.magic(), NetworkMessage::Ping(1));
+            stream.write_all(&serialize(&message)).await.unwrap();
+            stream
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (mut reader, _writer, protocol) = accept(stream, Network::Regtest.magic())
+            .await
+            .expect("Should accept a V1 peer");
+
+        assert_eq!(protocol, TransportProtocol::V1);
+        // The bytes we peeked at must still be there for the reader
+        let (message, _) = reader.read_message().await.unwrap();
+        assert_eq!(message, NetworkMessage::Ping(1));
+
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_accept_v2() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let (_reader, mut writer, protocol) = connect(address, Network::Regtest.magic(), false)
+                .await
+                .expect("Should negotiate V2 with our listener");
+            assert_eq!(protocol, TransportProtocol::V2);
+            writer.write_message(NetworkMessage::Ping(2)).await.unwrap();
+            writer
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (mut reader, _writer, protocol) = accept(stream, Network::Regtest.magic())
+            .await
+            .expect("Should accept a V2 peer");
+
+        assert_eq!(protocol, TransportProtocol::V2);
+        let (message, _) = reader.read_message().await.unwrap();
+        assert_eq!(message, NetworkMessage::Ping(2));
+
+        client.await.unwrap();
     }
 }
@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! This module defines the messages used to relay unconfirmed transactions between utreexo nodes.
+//!
+//! A utreexo node doesn't keep the UTXO set, so to validate a transaction's inputs it needs
+//! the same data used for blocks: the leaf data for each UTXO being spent, and a proof that
+//! those UTXOs are in the accumulator. Utreexo peers can send it along with the transaction,
+//! if we ask them to.
+//!
+//! To do so, we send a `getdata` with the usual transaction inventory, but with the
+//! [MSG_UTREEXO_FLAG] bit set in the inventory type (see [utreexo_inventory]). Our peer answers
+//! with a [UtreexoTx] message, or a `notfound` if it doesn't have this transaction anymore.
+//!
+//! Inputs spending outputs of other unconfirmed transactions aren't in the accumulator, so they
+//! don't have leaf data, and we are expected to know their parents already.
+
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::Transaction;
+use bitcoin::VarInt;
+use floresta_chain::CompactLeafData;
+use floresta_chain::ScriptPubKeyKind;
+use floresta_common::read_bounded_len;
+use rustreexo::node_hash::BitcoinNodeHash;
+
+use crate::block_proof::MAX_INPUTS_PER_BLOCK;
+use crate::block_proof::MAX_PROOF_HASHES;
+
+/// The command string for the "utreexo transaction" message
+pub const UTREEXO_TX_CMD_STRING: &str = "utreexotx";
+
+/// This bit is set in an inventory type to ask for the utreexo proof of a transaction.
+pub const MSG_UTREEXO_FLAG: u32 = 1 << 24;
+
+/// The inventory type for a transaction, identified by its txid
+const MSG_TX: u32 = 1;
+
+/// The inventory type for a transaction with its witness, identified by its txid
+const MSG_WITNESS_TX: u32 = MSG_TX | (1 << 30);
+
+/// The inventory type for a transaction with its witness, identified by its wtxid (BIP 339)
+const MSG_WTX: u32 = 5;
+
+/// Returns the inventory we should use in a `getdata` to ask for this transaction along with
+/// its utreexo proof. Returns `None` if this inventory isn't a transaction.
+pub fn utreexo_inventory(inv: &Inventory) -> Option<Inventory> {
+    let (inv_type, hash) = match inv {
+        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+            (MSG_WITNESS_TX, txid.to_byte_array())
+        }
+        Inventory::WTx(wtxid) => (MSG_WTX, wtxid.to_byte_array()),
+        _ => return None,
+    };
+
+    Some(Inventory::Unknown {
+        inv_type: inv_type | MSG_UTREEXO_FLAG,
+        hash,
+    })
+}
+
+/// Whether this inventory asks for a transaction with its utreexo proof.
+pub fn is_utreexo_tx_inventory(inv: &Inventory) -> bool {
+    match inv {
+        Inventory::Unknown { inv_type, .. } => {
+            let base = inv_type & !MSG_UTREEXO_FLAG;
+            inv_type & MSG_UTREEXO_FLAG != 0
+                && (base == MSG_TX || base == MSG_WITNESS_TX || base == MSG_WTX)
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An unconfirmed transaction, with everything needed to validate it against our accumulator.
+///
+/// This message will be sent in response to a `getdata` for a [utreexo_inventory].
+pub struct UtreexoTx {
+    /// The transaction itself.
+    pub transaction: Transaction,
+
+    /// The proof hashes for the UTXOs spent by this transaction.
+    pub proof_hashes: Vec<BitcoinNodeHash>,
+
+    /// The positions of the UTXOs spent by this transaction inside the forest.
+    pub targets: Vec<u64>,
+
+    /// The leaf data for each confirmed input, in the same order they appear in the
+    /// transaction.
+    pub leaf_data: Vec<CompactLeafData>,
+}
+
+impl Decodable for UtreexoTx {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let transaction = Transaction::consensus_decode(reader)?;
+
+        // Read the proof hashes
+        let n_hashes = read_bounded_len(reader, MAX_PROOF_HASHES)?;
+        let mut proof_hashes = Vec::with_capacity(n_hashes);
+        for _ in 0..n_hashes {
+            let hash = sha256::Hash::consensus_decode(reader)?;
+            proof_hashes.push(BitcoinNodeHash::Some(hash.to_byte_array()));
+        }
+
+        // Read the targets, one for each confirmed input
+        let n_targets = read_bounded_len(reader, MAX_INPUTS_PER_BLOCK)?;
+        let mut targets = Vec::with_capacity(n_targets);
+        for _ in 0..n_targets {
+            let target = VarInt::consensus_decode(reader)?;
+            targets.push(target.0);
+        }
+
+        // Read the leaf data
+        let n_leaf_data = read_bounded_len(reader, MAX_INPUTS_PER_BLOCK)?;
+        let mut leaf_data = Vec::with_capacity(n_leaf_data);
+        for _ in 0..n_leaf_data {
+            let leaf = CompactLeafData {
+                header_code: u32::consensus_decode(reader)?,
+                amount: u64::consensus_decode(reader)?,
+                spk_ty: ScriptPubKeyKind::consensus_decode(reader)?,
+            };
+
+            leaf_data.push(leaf);
+        }
+
+        Ok(UtreexoTx {
+            transaction,
+            proof_hashes,
+            targets,
+            leaf_data,
+        })
+    }
+}
+
+impl Encodable for UtreexoTx {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = self.transaction.consensus_encode(writer)?;
+
+        len += VarInt(self.proof_hashes.len() as u64).consensus_encode(writer)?;
+        for hash in self.proof_hashes.iter() {
+            len += (**hash).consensus_encode(writer)?;
+        }
+
+        len += VarInt(self.targets.len() as u64).consensus_encode(writer)?;
+        for target in self.targets.iter() {
+            len += VarInt(*target).consensus_encode(writer)?;
+        }
+
+        len += VarInt(self.leaf_data.len() as u64).consensus_encode(writer)?;
+        for leaf in self.leaf_data.iter() {
+            len += leaf.header_code.consensus_encode(writer)?;
+            len += leaf.amount.consensus_encode(writer)?;
+            len += leaf.spk_ty.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::serialize;
+    use bitcoin::p2p::message_blockdata::Inventory;
+    use bitcoin::BlockHash;
+    use bitcoin::Transaction;
+    use bitcoin::Txid;
+    use bitcoin::Wtxid;
+    use floresta_chain::CompactLeafData;
+    use floresta_chain::ScriptPubKeyKind;
+    use floresta_common::bhash;
+    use rustreexo::node_hash::BitcoinNodeHash;
+
+    use super::is_utreexo_tx_inventory;
+    use super::utreexo_inventory;
+    use super::UtreexoTx;
+    use super::MSG_UTREEXO_FLAG;
+
+    #[test]
+    fn test_utreexo_inventory() {
+        let hash = bhash!("000000000000049d95cd708e7241a86bff0b6784981aa0ce9be800c62603d611");
+        let txid = Txid::from_raw_hash(hash.to_raw_hash());
+        let wtxid = Wtxid::from_raw_hash(hash.to_raw_hash());
+
+        let inv = utreexo_inventory(&Inventory::Transaction(txid)).unwrap();
+        assert!(matches!(
+            inv,
+            Inventory::Unknown { inv_type, .. } if inv_type == 0x40000001 | MSG_UTREEXO_FLAG
+        ));
+        assert!(is_utreexo_tx_inventory(&inv));
+
+        let inv = utreexo_inventory(&Inventory::WTx(wtxid)).unwrap();
+        assert!(matches!(
+            inv,
+            Inventory::Unknown { inv_type, .. } if inv_type == 5 | MSG_UTREEXO_FLAG
+        ));
+        assert!(is_utreexo_tx_inventory(&inv));
+
+        assert!(utreexo_inventory(&Inventory::Block(hash)).is_none());
+        assert!(!is_utreexo_tx_inventory(&Inventory::Transaction(txid)));
+        assert!(!is_utreexo_tx_inventory(&Inventory::Unknown {
+            inv_type: 2 | MSG_UTREEXO_FLAG,
+            hash: [0; 32],
+        }));
+    }
+
+    #[test]
+    fn test_utreexo_tx_roundtrip() {
+        // The first non-coinbase transaction, from block 170
+        let transaction: Transaction = bitcoin::consensus::encode::deserialize_hex("0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000").unwrap();
+
+        let utreexo_tx = UtreexoTx {
+            transaction,
+            proof_hashes: vec![
+                BitcoinNodeHash::Some([1; 32]),
+                BitcoinNodeHash::Some([2; 32]),
+            ],
+            targets: vec![42],
+            leaf_data: vec![CompactLeafData {
+                header_code: 9 << 1 | 1,
+                amount: 5_000_000_000,
+                spk_ty: ScriptPubKeyKind::PubKeyHash,
+            }],
+        };
+
+        let decoded: UtreexoTx = deserialize(&serialize(&utreexo_tx)).unwrap();
+        assert_eq!(decoded, utreexo_tx);
+
+        // A truncated message must not decode
+        let serialized = serialize(&utreexo_tx);
+        assert!(deserialize::<UtreexoTx>(&serialized[..serialized.len() - 1]).is_err());
+    }
+}
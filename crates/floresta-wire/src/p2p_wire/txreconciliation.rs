@@ -0,0 +1,516 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Transaction relay through set reconciliation, also known as Erlay (BIP 330).
+//!
+//! Instead of announcing every transaction to every peer, which makes `inv` messages the bulk
+//! of the bandwidth used for transaction relay, each side keeps a set of transactions it would
+//! announce to the other. Every once in a while, the node that opened the connection asks its
+//! peer for a [Minisketch] of its set, with a `reqrecon` message. From that sketch and our own,
+//! we can find which transactions only one of us has, and announce or ask for just those, in a
+//! `reconcildiff` message. We only implement that side, so we don't offer reconciliation to
+//! peers that connected to us.
+//!
+//! Both sides must signal support with a `sendtxrcncl` message, between `version` and
+//! `verack`, and negotiate wtxid relay (BIP 339). Peers that don't support it get our
+//! transactions by flooding, as usual. If a reconciliation round fails, because the sets were
+//! too different for the sketch, both sides announce their entire sets instead.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::siphash24;
+use bitcoin::hashes::Hash;
+use bitcoin::VarInt;
+use bitcoin::Wtxid;
+use floresta_common::read_bounded_len;
+
+use crate::p2p_wire::minisketch::Minisketch;
+
+/// The command string for the message signaling support for reconciliation
+pub const SENDTXRCNCL_CMD_STRING: &str = "sendtxrcncl";
+
+/// The command string for the message starting a reconciliation round
+pub const REQRECON_CMD_STRING: &str = "reqrecon";
+
+/// The command string for the message with the responder's sketch
+pub const SKETCH_CMD_STRING: &str = "sketch";
+
+/// The command string for the message ending a reconciliation round
+pub const RECONCILDIFF_CMD_STRING: &str = "reconcildiff";
+
+/// The only version of the reconciliation protocol we support
+pub const TXRECONCILIATION_VERSION: u32 = 1;
+
+/// How often we start a reconciliation round with each peer
+pub const RECON_REQUEST_INTERVAL: Duration = Duration::from_secs(8);
+
+/// If our peer doesn't send its sketch within this time, we give up on this round and announce
+/// our set by flooding
+pub const RECON_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many transactions we keep in the set for one peer. Transactions that don't fit are
+/// announced by flooding.
+pub const MAX_RECON_SET_SIZE: usize = 3_000;
+
+/// The largest sketch we accept from a peer, in elements
+pub const MAX_SKETCH_CAPACITY: usize = 2 << 12;
+
+/// The largest difference we try to decode. Larger sketches are truncated, which is the same as
+/// if our peer had sent a smaller one.
+pub const MAX_DECODE_CAPACITY: usize = 256;
+
+/// The tag used to combine both salts into our SipHash keys
+const SALT_TAG: &[u8] = b"Tx Relay Salting";
+
+/// The coefficient `q` is sent as a fixed-point number, multiplied by this
+const Q_PRECISION: f64 = ((2 << 14) - 1) as f64;
+
+/// Our initial guess for `q`, same as Bitcoin Core
+const DEFAULT_Q: f64 = 0.25;
+
+/// The largest value `q` can take
+const MAX_Q: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Signals that a node supports transaction reconciliation
+pub struct SendTxRcncl {
+    /// The protocol version the sender supports
+    pub version: u32,
+
+    /// The sender's half of the salt used for short ids
+    pub salt: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Asks our peer for a sketch of its reconciliation set
+pub struct ReqRecon {
+    /// How many transactions we have in our set
+    pub set_size: u16,
+
+    /// Our estimate of how different our sets are, used to compute the sketch's capacity
+    pub q: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A responder's sketch, answering a [ReqRecon]
+pub struct Sketch {
+    /// The serialized [Minisketch]
+    pub skdata: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Ends a reconciliation round
+pub struct ReconcilDiff {
+    /// Whether we could decode the difference between our sets. If not, both sides should
+    /// announce their entire set.
+    pub success: bool,
+
+    /// The short ids of the transactions we are missing
+    pub ask_shortids: Vec<u32>,
+}
+
+impl Encodable for SendTxRcncl {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        Ok(self.version.consensus_encode(writer)? + self.salt.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for SendTxRcncl {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(SendTxRcncl {
+            version: u32::consensus_decode(reader)?,
+            salt: u64::consensus_decode(reader)?,
+        })
+    }
+}
+
+impl Encodable for ReqRecon {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        Ok(self.set_size.consensus_encode(writer)? + self.q.consensus_encode(writer)?)
+    }
+}
+
+impl Decodable for ReqRecon {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(ReqRecon {
+            set_size: u16::consensus_decode(reader)?,
+            q: u16::consensus_decode(reader)?,
+        })
+    }
+}
+
+impl Encodable for Sketch {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        self.skdata.consensus_encode(writer)
+    }
+}
+
+impl Decodable for Sketch {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let len = read_bounded_len(reader, MAX_SKETCH_CAPACITY * 4)?;
+        let mut skdata = vec![0; len];
+        reader.read_exact(&mut skdata)?;
+
+        Ok(Sketch { skdata })
+    }
+}
+
+impl Encodable for ReconcilDiff {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = (self.success as u8).consensus_encode(writer)?;
+
+        len += VarInt(self.ask_shortids.len() as u64).consensus_encode(writer)?;
+        for short_id in self.ask_shortids.iter() {
+            len += short_id.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for ReconcilDiff {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let success = u8::consensus_decode(reader)? != 0;
+
+        let n_ids = read_bounded_len(reader, MAX_SKETCH_CAPACITY)?;
+        let mut ask_shortids = Vec::with_capacity(n_ids);
+        for _ in 0..n_ids {
+            ask_shortids.push(u32::consensus_decode(reader)?);
+        }
+
+        Ok(ReconcilDiff {
+            success,
+            ask_shortids,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What we should do after getting our peer's sketch
+pub struct ReconciliationOutcome {
+    /// The message we should answer our peer with
+    pub diff: ReconcilDiff,
+
+    /// The transactions our peer is missing, that we should announce to it
+    pub announce: Vec<Wtxid>,
+}
+
+#[derive(Debug)]
+/// Our reconciliation state with one peer, where we are the initiator
+pub struct TxReconciliationState {
+    /// The SipHash keys for our short ids, derived from both salts
+    k0: u64,
+    k1: u64,
+
+    /// Transactions we should reconcile in the next round, by short id
+    local_set: HashMap<u32, Wtxid>,
+
+    /// The set we are reconciling right now, and when we asked for our peer's sketch
+    in_flight: Option<(HashMap<u32, Wtxid>, Instant)>,
+
+    /// Our estimate of how different our sets are
+    q: f64,
+
+    /// When we should start the next round
+    next_request: Instant,
+}
+
+impl TxReconciliationState {
+    /// Creates the state for a peer, after both sides sent their `sendtxrcncl`
+    pub fn new(our_salt: u64, their_salt: u64) -> Self {
+        let (k0, k1) = Self::compute_keys(our_salt, their_salt);
+
+        TxReconciliationState {
+            k0,
+            k1,
+            local_set: HashMap::new(),
+            in_flight: None,
+            q: DEFAULT_Q,
+            next_request: Instant::now() + RECON_REQUEST_INTERVAL,
+        }
+    }
+
+    /// Combines both salts into the SipHash keys used for short ids, as a tagged hash of
+    /// both of them, in ascending order
+    fn compute_keys(our_salt: u64, their_salt: u64) -> (u64, u64) {
+        let tag = sha256::Hash::hash(SALT_TAG);
+
+        let mut data = Vec::with_capacity(80);
+        data.extend_from_slice(tag.as_byte_array());
+        data.extend_from_slice(tag.as_byte_array());
+        data.extend_from_slice(&our_salt.min(their_salt).to_le_bytes());
+        data.extend_from_slice(&our_salt.max(their_salt).to_le_bytes());
+
+        let hash = sha256::Hash::hash(&data).to_byte_array();
+        let k0 = u64::from_le_bytes(hash[0..8].try_into().expect("slice has 8 bytes"));
+        let k1 = u64::from_le_bytes(hash[8..16].try_into().expect("slice has 8 bytes"));
+
+        (k0, k1)
+    }
+
+    /// The 32-bit short id of a transaction, that's never zero
+    pub fn short_id(&self, wtxid: &Wtxid) -> u32 {
+        let hash = siphash24::Hash::hash_to_u64_with_keys(self.k0, self.k1, &wtxid.to_byte_array());
+        1 + (hash % 0xFFFF_FFFF) as u32
+    }
+
+    /// Adds a transaction to our set. Returns false if the set is full, and this transaction
+    /// should be announced by flooding.
+    pub fn add_transaction(&mut self, wtxid: Wtxid) -> bool {
+        if self.local_set.len() >= MAX_RECON_SET_SIZE {
+            return false;
+        }
+
+        let short_id = self.short_id(&wtxid);
+        self.local_set.insert(short_id, wtxid);
+        true
+    }
+
+    /// Removes a transaction from our set, if our peer already told us about it
+    pub fn remove_transaction(&mut self, wtxid: &Wtxid) {
+        let short_id = self.short_id(wtxid);
+        self.local_set.remove(&short_id);
+    }
+
+    /// Starts a new round, if it's time to. The current set is frozen until we get our peer's
+    /// sketch, and new transactions go into the next round.
+    pub fn request(&mut self) -> Option<ReqRecon> {
+        if self.in_flight.is_some() || Instant::now() < self.next_request {
+            return None;
+        }
+
+        self.next_request = Instant::now() + RECON_REQUEST_INTERVAL;
+
+        let set = std::mem::take(&mut self.local_set);
+        let request = ReqRecon {
+            set_size: set.len().min(u16::MAX as usize) as u16,
+            q: (self.q * Q_PRECISION) as u16,
+        };
+
+        self.in_flight = Some((set, Instant::now()));
+        Some(request)
+    }
+
+    /// Abandons the current round if our peer took too long to answer, returning the
+    /// transactions we should announce by flooding
+    pub fn expire(&mut self) -> Vec<Wtxid> {
+        let timed_out = self
+            .in_flight
+            .as_ref()
+            .is_some_and(|(_, when)| when.elapsed() > RECON_RESPONSE_TIMEOUT);
+
+        if !timed_out {
+            return Vec::new();
+        }
+
+        self.in_flight
+            .take()
+            .map(|(set, _)| set.into_values().collect())
+            .unwrap_or_default()
+    }
+
+    /// Reconciles our set with our peer's sketch. Returns `None` if we didn't ask for one.
+    pub fn handle_sketch(&mut self, sketch: &Sketch) -> Option<ReconciliationOutcome> {
+        let (set, _) = self.in_flight.take()?;
+
+        let remote = Minisketch::deserialize(&sketch.skdata)
+            .filter(|remote| remote.capacity() > 0)
+            .map(|mut remote| {
+                remote.truncate(MAX_DECODE_CAPACITY);
+                remote
+            });
+
+        let difference = remote.and_then(|remote| {
+            let mut local = Minisketch::new(remote.capacity());
+            for short_id in set.keys() {
+                local.add(*short_id);
+            }
+
+            local.merge(&remote);
+            local.decode(local.capacity())
+        });
+
+        // We can't tell which transactions our peer is missing, so both of us should announce
+        // our entire sets
+        let Some(difference) = difference else {
+            return Some(ReconciliationOutcome {
+                diff: ReconcilDiff {
+                    success: false,
+                    ask_shortids: Vec::new(),
+                },
+                announce: set.into_values().collect(),
+            });
+        };
+
+        let (ours, theirs): (Vec<u32>, Vec<u32>) = difference
+            .into_iter()
+            .partition(|short_id| set.contains_key(short_id));
+
+        self.update_q(set.len(), ours.len(), theirs.len());
+
+        Some(ReconciliationOutcome {
+            diff: ReconcilDiff {
+                success: true,
+                ask_shortids: theirs,
+            },
+            announce: ours.iter().map(|short_id| set[short_id]).collect(),
+        })
+    }
+
+    /// Updates our estimate of how different our sets are, from the last round's result
+    fn update_q(&mut self, local_size: usize, local_only: usize, remote_only: usize) {
+        let remote_size = local_size - local_only + remote_only;
+        let min_size = local_size.min(remote_size);
+        if min_size == 0 {
+            return;
+        }
+
+        let difference = local_only + remote_only;
+        let expected = local_size.abs_diff(remote_size);
+        self.q = ((difference - expected) as f64 / min_size as f64).clamp(0.0, MAX_Q);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Wtxid;
+
+    use super::ReconcilDiff;
+    use super::Sketch;
+    use super::TxReconciliationState;
+    use super::RECON_REQUEST_INTERVAL;
+    use crate::p2p_wire::minisketch::Minisketch;
+
+    fn wtxid(n: u8) -> Wtxid {
+        Wtxid::from_byte_array([n; 32])
+    }
+
+    /// Lets our state start a round right away
+    fn ready(state: &mut TxReconciliationState) {
+        state.next_request -= RECON_REQUEST_INTERVAL;
+    }
+
+    #[test]
+    fn test_short_ids() {
+        // Both sides must derive the same keys, no matter whose salt is whose
+        let ours = TxReconciliationState::new(1, 2);
+        let theirs = TxReconciliationState::new(2, 1);
+        assert_eq!((ours.k0, ours.k1), (theirs.k0, theirs.k1));
+        assert_eq!(ours.short_id(&wtxid(1)), theirs.short_id(&wtxid(1)));
+
+        let other = TxReconciliationState::new(1, 3);
+        assert_ne!(ours.short_id(&wtxid(1)), other.short_id(&wtxid(1)));
+        assert_ne!(ours.short_id(&wtxid(1)), 0);
+    }
+
+    #[test]
+    fn test_messages() {
+        let diff = ReconcilDiff {
+            success: true,
+            ask_shortids: vec![1, 0xdeadbeef],
+        };
+
+        let serialized = serialize(&diff);
+        assert_eq!(serialized, vec![1, 2, 1, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]);
+        assert_eq!(deserialize::<ReconcilDiff>(&serialized).unwrap(), diff);
+
+        let sketch = Sketch {
+            skdata: vec![1, 2, 3, 4],
+        };
+        assert_eq!(deserialize::<Sketch>(&serialize(&sketch)).unwrap(), sketch);
+    }
+
+    #[test]
+    fn test_reconciliation() {
+        let mut state = TxReconciliationState::new(1, 2);
+        ready(&mut state);
+
+        // We have 1..=10, our peer has 5..=12
+        for n in 1..=10 {
+            assert!(state.add_transaction(wtxid(n)));
+        }
+
+        let mut remote = Minisketch::new(20);
+        for n in 5..=12 {
+            remote.add(state.short_id(&wtxid(n)));
+        }
+
+        let request = state.request().unwrap();
+        assert_eq!(request.set_size, 10);
+
+        // Only one round at a time
+        assert!(state.request().is_none());
+
+        let sketch = Sketch {
+            skdata: remote.serialize(),
+        };
+        let outcome = state.handle_sketch(&sketch).unwrap();
+
+        assert!(outcome.diff.success);
+
+        let mut announce = outcome.announce.clone();
+        announce.sort();
+        assert_eq!(announce, (1..=4).map(wtxid).collect::<Vec<_>>());
+
+        let mut ask = outcome.diff.ask_shortids.clone();
+        let mut expected: Vec<_> = [11, 12].map(|n| state.short_id(&wtxid(n))).to_vec();
+        ask.sort();
+        expected.sort();
+        assert_eq!(ask, expected);
+
+        // 6 differences, 2 of them expected from the set sizes, over a min size of 8
+        assert_eq!(state.q, 0.5);
+
+        // We didn't ask for another sketch
+        assert!(state.handle_sketch(&sketch).is_none());
+    }
+
+    #[test]
+    fn test_failed_reconciliation() {
+        let mut state = TxReconciliationState::new(1, 2);
+        ready(&mut state);
+
+        for n in 1..=20 {
+            state.add_transaction(wtxid(n));
+        }
+
+        // Our peer has nothing, and its sketch is too small for our set. Small sketches may
+        // decode to a wrong set with a probability of about 1/capacity!, so don't go too small.
+        state.request().unwrap();
+        let sketch = Sketch {
+            skdata: Minisketch::new(8).serialize(),
+        };
+        let outcome = state.handle_sketch(&sketch).unwrap();
+
+        assert!(!outcome.diff.success);
+        assert!(outcome.diff.ask_shortids.is_empty());
+        assert_eq!(outcome.announce.len(), 20);
+    }
+}
@@ -138,6 +138,7 @@ fuzz_target!(|input: FuzzInput| {
         fork_file_size: Some(64),
         cache_size: Some(15),
         file_permission: Some(0o666),
+        roots_retention: None,
         path: temp_dir.path().into(),
     };
 
@@ -0,0 +1,21 @@
+# SPDX-License-Identifier: MIT OR Apache-2.0
+
+"""
+floresta_cli_abortrescan.py
+
+This functional test cli utility to interact with a Floresta node with
+`getrescaninfo` and `abortrescan`
+"""
+
+import pytest
+
+
+@pytest.mark.rpc
+def test_abortrescan(florestad_node):
+    """Test that a node without rescans reports none, and has nothing to abort."""
+
+    result = florestad_node.rpc.get_rescaninfo()
+    assert result == []
+
+    result = florestad_node.rpc.abort_rescan()
+    assert result is False
@@ -0,0 +1,48 @@
+# SPDX-License-Identifier: MIT OR Apache-2.0
+
+"""
+dumputreexostate.py
+
+Functional test for the `dumputreexostate` and `loadutreexostate` RPCs.
+"""
+
+import os
+
+import pytest
+from requests.exceptions import HTTPError
+
+
+@pytest.mark.rpc
+def test_dumputreexostate(florestad_node, tmp_path):
+    """
+    A fresh node dumps the empty accumulator at genesis, and can't load it back, since it
+    already validated that block.
+    """
+    path = os.path.join(tmp_path, "utreexo.snapshot")
+    result = florestad_node.rpc.dumputreexostate(path)
+
+    assert result["height"] == 0
+    assert result["leaves"] == 0
+    assert result["roots"] == []
+    assert result["path"] == path
+    assert result["block_hash"] == florestad_node.rpc.get_bestblockhash()
+    assert os.path.getsize(path) == 59 + 32
+
+    with pytest.raises(HTTPError):
+        florestad_node.rpc.loadutreexostate(path)
+
+
+@pytest.mark.rpc
+def test_loadutreexostate_corrupted(florestad_node, tmp_path):
+    """
+    Corrupted snapshots are rejected.
+    """
+    path = os.path.join(tmp_path, "utreexo.snapshot")
+    florestad_node.rpc.dumputreexostate(path)
+
+    with open(path, "r+b") as snapshot:
+        snapshot.seek(50)
+        snapshot.write(b"\x01")
+
+    with pytest.raises(HTTPError):
+        florestad_node.rpc.loadutreexostate(path)
@@ -35,3 +35,4 @@ def test_get_blockchain_info(florestad_node):
     assert response["root_count"] == 0
     assert response["root_hashes"] == []
     assert response["validated"] == 0
+    assert response["pow_fraud_proofs"] == {"enabled": False, "checks": []}
@@ -0,0 +1,45 @@
+# SPDX-License-Identifier: MIT OR Apache-2.0
+
+"""
+getnettotals.py
+
+Functional test for the `getnettotals` RPC.
+
+See the RPC documentation at https://bitcoincore.org/en/doc/29.0.0/rpc/network/getnettotals/
+"""
+
+import pytest
+
+from test_framework.node import NodeType
+
+
+@pytest.mark.rpc
+def test_getnettotals(florestad_node):
+    """
+    A fresh node without peers hasn't sent anything, and has no upload target.
+    """
+    result = florestad_node.rpc.getnettotals()
+
+    assert result["totalbytesrecv"] == 0
+    assert result["totalbytessent"] == 0
+    assert result["timemillis"] > 0
+
+    target = result["uploadtarget"]
+    assert target["timeframe"] == 86400
+    assert target["target"] == 0
+    assert not target["target_reached"]
+    assert target["serve_historical_blocks"]
+
+
+@pytest.mark.rpc
+def test_getnettotals_upload_target(add_node_with_extra_args):
+    """
+    With `--maxuploadtarget`, the target is reported in bytes.
+    """
+    node = add_node_with_extra_args(NodeType.FLORESTAD, ["--maxuploadtarget=10"])
+    target = node.rpc.getnettotals()["uploadtarget"]
+
+    assert target["target"] == 10 * 1024 * 1024
+    assert target["bytes_left_in_cycle"] == 10 * 1024 * 1024
+    assert 0 < target["time_left_in_cycle"] <= 86400
+    assert not target["target_reached"]
@@ -0,0 +1,58 @@
+# SPDX-License-Identifier: MIT OR Apache-2.0
+
+"""
+setban.py
+
+Functional test for the `setban`, `listbanned` and `clearbanned` RPCs.
+
+See the RPC documentation at https://bitcoincore.org/en/doc/29.0.0/rpc/network/setban/
+"""
+
+import time
+
+import pytest
+from requests.exceptions import HTTPError
+
+
+@pytest.mark.rpc
+def test_setban(florestad_node):
+    """
+    Ban and unban a few subnets, checking `listbanned` along the way.
+    """
+    rpc = florestad_node.rpc
+    assert rpc.listbanned() == []
+
+    now = int(time.time())
+    rpc.setban("192.168.0.6", "add", 3600)
+    rpc.setban("10.0.0.1/255.255.0.0", "add")
+
+    bans = {ban["address"]: ban for ban in rpc.listbanned()}
+    assert set(bans) == {"192.168.0.6/32", "10.0.0.0/16"}
+
+    ban = bans["192.168.0.6/32"]
+    assert ban["ban_duration"] == 3600
+    assert ban["ban_reason"] == "manually added"
+    assert 0 < ban["time_remaining"] <= 3600
+    assert ban["banned_until"] >= now + 3600
+
+    # A ban without a duration lasts 24 hours
+    assert bans["10.0.0.0/16"]["ban_duration"] == 86400
+
+    # Banning twice, or unbanning something that isn't banned, is an error
+    with pytest.raises(HTTPError):
+        rpc.setban("192.168.0.6", "add")
+    with pytest.raises(HTTPError):
+        rpc.setban("172.16.0.0/12", "remove")
+    with pytest.raises(HTTPError):
+        rpc.setban("not an ip", "add")
+    with pytest.raises(HTTPError):
+        rpc.setban("172.16.0.0/12", "add", 1, True)
+
+    rpc.setban("192.168.0.6", "remove")
+    assert [ban["address"] for ban in rpc.listbanned()] == ["10.0.0.0/16"]
+
+    rpc.setban("2001:db8::/32", "add", now + 600, True)
+    assert len(rpc.listbanned()) == 2
+
+    rpc.clearbanned()
+    assert rpc.listbanned() == []
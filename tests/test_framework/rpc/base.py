@@ -335,6 +335,59 @@ class BaseRPC(ABC):
             )
         return self.perform_request("disconnectnode", params=[node_address])
 
+    def setban(
+        self,
+        subnet: str,
+        command: str,
+        bantime: int = 0,
+        absolute: bool = False,
+    ):
+        """
+        Adds or removes an IP/Subnet from the banned list
+        """
+        if command not in ("add", "remove"):
+            raise ValueError(f"Invalid command '{command}'")
+
+        return self.perform_request(
+            "setban", params=[subnet, command, bantime, absolute]
+        )
+
+    def listbanned(self):
+        """
+        Lists all banned IPs/Subnets
+        """
+        return self.perform_request("listbanned")
+
+    def clearbanned(self):
+        """
+        Lifts all bans
+        """
+        return self.perform_request("clearbanned")
+
+    def dumputreexostate(self, path: str):
+        """
+        Writes the node's Utreexo accumulator to a snapshot file
+        """
+        return self.perform_request("dumputreexostate", params=[path])
+
+    def loadutreexostate(self, path: str):
+        """
+        Starts from the Utreexo accumulator in a snapshot file
+        """
+        return self.perform_request("loadutreexostate", params=[path])
+
+    def getnettotals(self):
+        """
+        Returns how many bytes we exchanged with our peers
+        """
+        return self.perform_request("getnettotals")
+
+    def submitpackage(self, package: list):
+        """
+        Submits a child with its unconfirmed parents to the mempool
+        """
+        return self.perform_request("submitpackage", params=[package])
+
     def list_descriptors(self):
         """
         List all loaded descriptors
@@ -40,3 +40,15 @@ class FlorestaRPC(BaseRPC):
         Load a script descriptor into the wallet.
         """
         return self.perform_request("loaddescriptor", params=[descriptor])
+
+    def get_rescaninfo(self):
+        """
+        Returns the progress of every rescan, running or interrupted
+        """
+        return self.perform_request("getrescaninfo")
+
+    def abort_rescan(self):
+        """
+        Stops every rescan, returning whether there was any
+        """
+        return self.perform_request("abortrescan")